@@ -0,0 +1,53 @@
+#![allow(clippy::unwrap_used)]
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use natgeo_wallpapers::{
+    build_assignments, find_photos_in_path, find_photos_in_path_sorted, SortOrder, TargetFilter,
+    WallpaperMode,
+};
+use std::fs;
+use tempfile::TempDir;
+
+/// Build a library of 5,000 photos spread across date folders, plus 500
+/// photos in undated folders that the default sort has to stat
+fn build_library() -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    let first = chrono::NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+    for day in first.iter_days().take(500) {
+        let dir = temp_dir.path().join(day.format("%Y-%m-%d").to_string());
+        fs::create_dir_all(&dir).unwrap();
+        for photo in 0..10 {
+            fs::write(dir.join(format!("photo_{}.jpg", photo)), b"x").unwrap();
+        }
+        fs::write(dir.join("photo.log"), b"x").unwrap();
+    }
+    for folder in 0..50 {
+        let dir = temp_dir.path().join(format!("imported-{}", folder));
+        fs::create_dir_all(&dir).unwrap();
+        for photo in 0..10 {
+            fs::write(dir.join(format!("photo_{}.jpg", photo)), b"x").unwrap();
+        }
+    }
+    temp_dir
+}
+
+fn bench_discovery(c: &mut Criterion) {
+    let library = build_library();
+    let path = library.path().to_str().unwrap().to_string();
+
+    c.bench_function("find_photos_in_path 5500 files", |b| {
+        b.iter(|| find_photos_in_path(Some(&path)).unwrap());
+    });
+    c.bench_function("find_photos_in_path_sorted mtime 5500 files", |b| {
+        b.iter(|| find_photos_in_path_sorted(Some(&path), SortOrder::Mtime).unwrap());
+    });
+    c.bench_function("find and assign 3 monitors x 4 desktops", |b| {
+        b.iter(|| {
+            let photos = find_photos_in_path(Some(&path)).unwrap();
+            build_assignments(WallpaperMode::Both, &photos, 3, 4, &TargetFilter::default())
+        });
+    });
+}
+
+criterion_group!(benches, bench_discovery);
+criterion_main!(benches);
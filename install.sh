@@ -45,7 +45,11 @@ echo -e "\n${YELLOW}Creating directories...${NC}"
 
 # Create photo storage directory
 PHOTO_DIR="$HOME/Pictures/NationalGeographic"
-mkdir -p "$PHOTO_DIR"
+if [ ! -d "$PHOTO_DIR" ]; then
+    mkdir -p "$PHOTO_DIR"
+    # Mark the directory as ours so `natgeo-wallpapers uninstall --photos` may remove it
+    touch "$PHOTO_DIR/.natgeo-wallpapers"
+fi
 echo -e "${GREEN}✓${NC} Created $PHOTO_DIR"
 
 # Create KDE wallpaper picker directory
@@ -1,30 +1,55 @@
 use chrono::Local;
+use img_parts::jpeg::{markers, Jpeg, JpegSegment};
+use img_parts::ImageEXIF;
 use owo_colors::OwoColorize;
-use rand::seq::SliceRandom;
-use reqwest::blocking::Client;
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, ACCEPT_LANGUAGE, USER_AGENT};
+use rand::{seq::SliceRandom, Rng};
+use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT, ACCEPT_LANGUAGE, CONTENT_LENGTH, RETRY_AFTER, USER_AGENT,
+};
+use reqwest::{NoProxy, Proxy};
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fs::{File, OpenOptions},
     io::{self, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 use thiserror::Error;
+use tracing::{debug, info, warn};
+use xmp_writer::XmpWriter;
 
 // Constants for the URL and photo storage
 // Note: National Geographic has changed their API structure. This is an alternative approach
 // that scrapes the photo of the day page directly
 pub const NATGEO_POD_URL: &str = "https://www.nationalgeographic.com/photo-of-the-day";
-pub const PHOTO_SAVE_PATH: &str = "~/Pictures/NationalGeographic/"; // Photos saved here
-pub const COLLECTION_SAVE_PATH: &str = "~/Pictures/NationalGeographic/collections/"; // Collections saved here
-pub const LOG_DIR: &str = "~/.local/share/natgeo-wallpapers/";
+/// Web archive holding snapshots of the photo of the day page for past dates
+pub const NATGEO_POD_ARCHIVE_URL: &str = "https://web.archive.org/web/";
+pub const CONFIG_PATH: &str = "~/.config/natgeo-wallpapers/config.toml";
+pub const SYSTEMD_USER_DIR: &str = "~/.config/systemd/user/";
+
+/// Marker file written into photo directories this tool created itself.
+/// Directories without it are never deleted by `uninstall --photos`.
+pub const MANAGED_DIR_MARKER: &str = ".natgeo-wallpapers";
 
 // Since the JSON API is now protected, we'll need to scrape the HTML page
 // For now, let's create a simple structure to hold photo information
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PhotoInfo {
     pub image_url: String,
     pub title: String,
+    /// Caption from the page's `og:description`, if any
+    pub caption: Option<String>,
+    /// Photographer credit from the page's embedded JSON, if any
+    pub photographer: Option<String>,
 }
 
 /// A collection of photos from a "Best of Photo of the Day" page
@@ -38,7 +63,10 @@ pub struct PhotoCollection {
 #[derive(Error, Debug)]
 pub enum PhotoError {
     #[error("Network error: {0}")]
-    Network(#[from] reqwest::Error),
+    Network(#[source] reqwest::Error),
+
+    #[error("Request timed out: {0}")]
+    Timeout(#[source] reqwest::Error),
 
     #[error("JSON parsing error: {0}")]
     Json(#[from] serde_json::Error),
@@ -49,6 +77,12 @@ pub enum PhotoError {
     #[error("Invalid content type: {0}")]
     InvalidContentType(String),
 
+    #[error("HTTP {status} from {url}")]
+    HttpStatus { status: u16, url: String },
+
+    #[error("Parse error: {0}")]
+    Parse(String),
+
     #[error("Wallpaper error: {0}")]
     Wallpaper(String),
 
@@ -57,15 +91,75 @@ pub enum PhotoError {
 
     #[error("No photos found: {0}")]
     NoPhotos(String),
+
+    #[error("Image error: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("Interrupted")]
+    Interrupted,
+
+    #[error("Corrupt image: {0}")]
+    CorruptImage(String),
+
+    #[error("Metadata error: {0}")]
+    Metadata(String),
+
+    #[error("Invalid proxy: {0}")]
+    InvalidProxy(String),
+
+    #[error("Invalid date: {0}")]
+    InvalidDate(String),
+
+    #[error("Config error: {0}")]
+    Config(String),
+
+    #[error("Log error: {0}")]
+    Log(String),
+
+    #[error("Hook error: {0}")]
+    Hook(String),
+
+    #[error("{0} check(s) failed")]
+    ChecksFailed(usize),
+}
+
+impl PhotoError {
+    /// Whether the error means the server couldn't be reached at all, as when offline
+    pub fn is_offline(&self) -> bool {
+        match self {
+            Self::Timeout(_) => true,
+            Self::Network(e) => !e.is_status(),
+            _ => false,
+        }
+    }
+
+    /// Whether trying again can't help: a 4xx answer other than 408 or 429
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, Self::HttpStatus { status, .. }
+            if (400..500).contains(status) && !matches!(status, 408 | 429))
+    }
+}
+
+impl From<reqwest::Error> for PhotoError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(e)
+        } else {
+            Self::Network(e)
+        }
+    }
 }
 
 // Wallpaper mode for multi-monitor/virtual desktop support
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum WallpaperMode {
     #[default]
     Monitors,
     VirtualDesktops,
     Both,
+    /// One wallpaper per KDE Plasma activity
+    Activities,
 }
 
 impl std::fmt::Display for WallpaperMode {
@@ -74,21 +168,59 @@ impl std::fmt::Display for WallpaperMode {
             Self::Monitors => write!(f, "monitors"),
             Self::VirtualDesktops => write!(f, "virtual-desktops"),
             Self::Both => write!(f, "both"),
+            Self::Activities => write!(f, "activities"),
         }
     }
 }
 
 // Detected desktop environment
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum DesktopEnvironment {
     KdePlasma6,
     KdePlasma5,
     PlasmaFallback,
     Gnome,
+    Cinnamon,
+    Mate,
     Feh,
+    Hyprland,
+    Swww,
+    Xfce,
+    Lxqt,
+    Windows,
     Unknown,
 }
 
+/// Extensions a downloaded photo may be saved with
+const DOWNLOAD_EXTENSIONS: &[&str] = &["jpg", "png", "gif", "tiff", "jxl", "webp", "avif"];
+
+impl DesktopEnvironment {
+    /// Whether the wallpaper tool can display files with this extension directly.
+    /// Anything else is transcoded to JPEG before being applied.
+    pub fn displays_extension(self, ext: &str) -> bool {
+        match self {
+            // hyprpaper only decodes these
+            Self::Hyprland => {
+                return matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg" | "png" | "webp");
+            }
+            Self::Swww => {
+                return matches!(
+                    ext.to_lowercase().as_str(),
+                    "jpg" | "jpeg" | "png" | "gif" | "webp" | "tif" | "tiff"
+                );
+            }
+            _ => {}
+        }
+        match ext.to_lowercase().as_str() {
+            "jpg" | "jpeg" | "png" | "gif" => true,
+            // Qt and gdk-pixbuf ship TIFF loaders; imlib2's is optional
+            "tif" | "tiff" => !matches!(self, Self::Feh),
+            _ => false,
+        }
+    }
+}
+
 // Function to get the file extension based on the MIME type
 pub fn get_extension_from_content_type(content_type: &str) -> Result<String, PhotoError> {
     if content_type.contains("jpeg") {
@@ -97,1473 +229,21769 @@ pub fn get_extension_from_content_type(content_type: &str) -> Result<String, Pho
         Ok("png".to_string())
     } else if content_type.contains("gif") {
         Ok("gif".to_string())
+    } else if content_type.contains("tiff") {
+        Ok("tiff".to_string())
+    } else if content_type.contains("jxl") {
+        Ok("jxl".to_string())
+    } else if content_type.contains("webp") {
+        Ok("webp".to_string())
+    } else if content_type.contains("avif") {
+        Ok("avif".to_string())
     } else {
         Err(PhotoError::InvalidContentType(content_type.to_string()))
     }
 }
 
-// Helper function to write log entries
-pub fn write_log(log_path: &str, message: &str) {
-    let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
-    let log_message = format!("[{}] {}\n", timestamp, message);
+// ============================================================================
+// Output
+// ============================================================================
 
-    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(log_path) {
-        let _ = file.write_all(log_message.as_bytes());
-    }
+/// Whether stdout is reserved for a single JSON document
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Reserve stdout for JSON, sending progress messages to stderr instead
+pub fn set_json_output(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::Relaxed);
 }
 
-// Fetch the current "photo of the day" data from the HTML page
-// Note: This is a workaround since the JSON API is now protected
-pub fn get_current_web_natgeo_gallery() -> Result<PhotoInfo, PhotoError> {
-    // Create headers to mimic a real browser request
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/999.0.0.0 Safari/537.36"));
-    headers.insert(
-        ACCEPT,
-        HeaderValue::from_static(
-            "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8",
-        ),
-    );
-    headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("en-US,en;q=0.9"));
-    headers.insert(
-        "Referer",
-        HeaderValue::from_static("https://www.nationalgeographic.com/"),
-    );
+/// Whether `--json` was given
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
 
-    // Create a client with headers
-    let client = Client::builder().default_headers(headers).build()?;
+/// Whether terminal output is colored
+static COLOR_OUTPUT: AtomicBool = AtomicBool::new(false);
 
-    // Fetch the raw response
-    let response = client.get(NATGEO_POD_URL).send()?;
+/// Turn terminal colors on or off
+pub fn set_color_output(enabled: bool) {
+    COLOR_OUTPUT.store(enabled, Ordering::Relaxed);
+}
 
-    // Check the status code (capture it first since we'll consume response later)
-    let status = response.status();
-    if !status.is_success() {
-        return Err(PhotoError::InvalidContentType(format!(
-            "HTTP {}: Failed to fetch photo of the day page",
-            status
-        )));
+/// Whether terminal output is colored
+pub fn color_output() -> bool {
+    COLOR_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Whether `--color auto` colors output: only on a terminal, and never when
+/// `NO_COLOR` is set to anything but an empty string
+pub fn auto_color(no_color: Option<&std::ffi::OsStr>, is_terminal: bool) -> bool {
+    no_color.is_none_or(std::ffi::OsStr::is_empty) && is_terminal
+}
+
+/// `text` styled by `style` if `enabled`, plain otherwise
+fn paint<T: std::fmt::Display + ?Sized>(
+    text: &T,
+    enabled: bool,
+    style: impl FnOnce(&T) -> String,
+) -> String {
+    if enabled {
+        style(text)
+    } else {
+        text.to_string()
     }
+}
 
-    let body = response.text()?;
+/// Terminal colors that are left out when color is off
+pub trait Paint: std::fmt::Display {
+    fn green(&self) -> String {
+        paint(self, color_output(), |text| {
+            OwoColorize::green(&text).to_string()
+        })
+    }
 
-    // Extract image URL from the HTML - look for og:image meta tag
-    // The meta tags are all on one line, so we need to find the specific property
-    let image_url = body
-        .split("property=\"og:image\"")
-        .nth(1)
-        .and_then(|s| s.split("content=\"").nth(1))
-        .and_then(|s| s.split('"').next())
-        .unwrap_or("")
-        .to_string();
+    fn red(&self) -> String {
+        paint(self, color_output(), |text| {
+            OwoColorize::red(&text).to_string()
+        })
+    }
 
-    if image_url.is_empty() {
-        return Err(PhotoError::InvalidContentType(
-            "Could not extract image URL from page".to_string(),
-        ));
+    fn yellow(&self) -> String {
+        paint(self, color_output(), |text| {
+            OwoColorize::yellow(&text).to_string()
+        })
     }
 
-    // Extract title from og:title
-    let og_title = body
-        .split("property=\"og:title\"")
-        .nth(1)
-        .and_then(|s| s.split("content=\"").nth(1))
-        .and_then(|s| s.split('"').next())
-        .unwrap_or("")
-        .to_string();
+    fn dimmed(&self) -> String {
+        paint(self, color_output(), |text| {
+            OwoColorize::dimmed(&text).to_string()
+        })
+    }
+}
 
-    // Check if title is meaningful (not just "Test" or empty or too short)
-    let title = if og_title.is_empty() || og_title.len() < 5 || og_title.to_lowercase() == "test" {
-        // Fall back to extracting filename from image URL
-        image_url
-            .split('/')
-            .next_back()
-            .and_then(|filename| filename.split('.').next())
-            .unwrap_or("photo-of-the-day")
-            .to_string()
-    } else {
-        og_title
+impl<T: std::fmt::Display + ?Sized> Paint for T {}
+
+/// Whether progress messages are suppressed
+static QUIET_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Suppress progress messages, leaving only results and errors
+pub fn set_quiet_output(enabled: bool) {
+    QUIET_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether `--quiet` was given
+pub fn quiet_output() -> bool {
+    QUIET_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// `println!` for progress messages, which go to stderr when printing JSON
+/// and nowhere with `--quiet`
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::quiet_output() {
+        } else if $crate::json_output() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
     };
+}
+
+// ============================================================================
+// Notifications
+// ============================================================================
 
-    Ok(PhotoInfo { image_url, title })
+/// How urgent a notification is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    Normal,
+    /// Something is broken and needs attention
+    Critical,
 }
 
-// Download the photo of the day and save it to the specified destination
-pub fn download_natgeo_photo_of_the_day(
-    photo_url: &str,       // URL of the photo to download
-    save_dir: &str,        // Directory where the photo will be saved
-    sanitized_title: &str, // Sanitized photo title for the filename
-    log_path: &str,        // Path to log file for this download
-) -> Result<(), PhotoError> {
-    // Check if photo already exists (jpg, png, or gif)
-    if let Ok(entries) = std::fs::read_dir(save_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
-                if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
-                    if stem == sanitized_title && matches!(ext, "jpg" | "png" | "gif") {
-                        write_log(
-                            log_path,
-                            &format!("Photo already exists: {}", path.display()),
-                        );
-                        return Ok(());
-                    }
-                }
-            }
+impl std::fmt::Display for Urgency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Normal => write!(f, "normal"),
+            Self::Critical => write!(f, "critical"),
         }
     }
+}
 
-    // Create headers to mimic a real browser request
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/999.0.0.0 Safari/537.36"));
-    headers.insert(
-        ACCEPT,
-        HeaderValue::from_static(
-            "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8",
-        ),
-    );
-
-    // Create a client with headers
-    let client = Client::builder().default_headers(headers).build()?;
+/// A desktop notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+    /// Image shown with the notification, where the desktop supports it
+    pub icon: Option<PathBuf>,
+    pub urgency: Urgency,
+}
 
-    // Make the full URL request to download the image
-    let response = client.get(photo_url).send()?;
+impl Notification {
+    /// A new photo titled `title` was saved at `path`
+    pub fn downloaded(title: &str, path: &Path) -> Self {
+        Self {
+            summary: format!("New NatGeo photo: {}", title),
+            body: path.display().to_string(),
+            icon: Some(path.to_path_buf()),
+            urgency: Urgency::Normal,
+        }
+    }
 
-    // Ensure the response is successful
-    if !response.status().is_success() {
-        return Err(PhotoError::InvalidContentType(format!(
-            "Failed to download photo: HTTP {}",
-            response.status()
-        )));
+    /// The wallpaper was changed to `photo`, plus `others` more on other screens
+    pub fn wallpaper_set(photo: &Path, others: usize) -> Self {
+        let name = photo
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().replace('_', " "))
+            .unwrap_or_default();
+        let body = match others {
+            0 => name,
+            1 => format!("{} and 1 other photo", name),
+            n => format!("{} and {} other photos", name, n),
+        };
+        Self {
+            summary: "Wallpaper changed".to_string(),
+            body,
+            icon: Some(photo.to_path_buf()),
+            urgency: Urgency::Normal,
+        }
     }
 
-    // Get the content type to determine the file extension (jpg or png)
-    let content_type = response
-        .headers()
-        .get("Content-Type")
-        .and_then(|val| val.to_str().ok())
-        .unwrap_or_default();
+    /// `what` failed with `error`
+    pub fn failed(what: &str, error: &PhotoError) -> Self {
+        Self {
+            summary: format!("NatGeo wallpaper: {} failed", what),
+            body: error.to_string(),
+            icon: None,
+            urgency: Urgency::Critical,
+        }
+    }
+}
 
-    // Get the file extension based on the content type
-    let file_extension = match get_extension_from_content_type(content_type) {
-        Ok(ext) => ext,
-        Err(_) => "jpg".to_string(), // Default to .jpg if content type isn't recognized
-    };
+/// Shows desktop notifications.
+/// Abstracted so callers can be tested without a notification daemon.
+pub trait Notifier {
+    fn notify(&self, notification: &Notification) -> Result<(), PhotoError>;
+}
 
-    // Create the filename using the sanitized title
-    let photo_filename = format!("{}/{}.{}", save_dir, sanitized_title, file_extension);
+/// Arguments for `notify-send` showing `notification`
+pub fn notify_send_args(notification: &Notification) -> Vec<String> {
+    let mut args = vec![
+        "--app-name=natgeo-wallpapers".to_string(),
+        format!("--urgency={}", notification.urgency),
+    ];
+    if let Some(icon) = &notification.icon {
+        args.push(format!("--icon={}", icon.display()));
+    }
+    args.push(notification.summary.clone());
+    args.push(notification.body.clone());
+    args
+}
 
-    // Open the file to write the downloaded photo
-    let mut file = File::create(&photo_filename)?;
+/// `osascript` script showing `notification`; macOS has no icon or urgency for these
+pub fn osascript_notification(notification: &Notification) -> String {
+    let quote = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+    format!(
+        "display notification \"{}\" with title \"{}\"",
+        quote(&notification.body),
+        quote(&notification.summary)
+    )
+}
 
-    // Download and save the image
-    let response_bytes = response.bytes()?;
-    io::copy(&mut response_bytes.as_ref(), &mut file)?;
+/// Notifier using `notify-send`, or `osascript` on macOS
+pub struct DesktopNotifier {
+    runner: Box<dyn CommandRunner>,
+}
 
-    write_log(log_path, &format!("Downloaded photo: {}", photo_filename));
+impl DesktopNotifier {
+    pub fn new(runner: Box<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
+}
 
-    Ok(())
+impl Notifier for DesktopNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), PhotoError> {
+        if cfg!(target_os = "macos") {
+            let script = osascript_notification(notification);
+            self.runner.run("osascript", &["-e", &script])?;
+        } else {
+            let args = notify_send_args(notification);
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            self.runner.run("notify-send", &args)?;
+        }
+        Ok(())
+    }
 }
 
 // ============================================================================
-// Collection Scraping Functions
+// Hooks
 // ============================================================================
 
-/// Extract the collection name from a URL like "best-photos-october-2018"
-pub fn extract_collection_name_from_url(url: &str) -> String {
-    url.split('/')
-        .next_back()
-        .unwrap_or("collection")
-        .to_string()
+/// When a user hook runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// After a new photo is saved
+    PostDownload,
+    /// After a wallpaper is applied, once per monitor or virtual desktop
+    PostSet,
 }
 
-/// Create the HTTP client with browser-like headers
-fn create_http_client() -> Result<Client, PhotoError> {
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/999.0.0.0 Safari/537.36"));
-    headers.insert(
-        ACCEPT,
-        HeaderValue::from_static(
-            "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8",
-        ),
-    );
-    headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("en-US,en;q=0.9"));
-    headers.insert(
-        "Referer",
-        HeaderValue::from_static("https://www.nationalgeographic.com/"),
-    );
-
-    Client::builder()
-        .default_headers(headers)
-        .build()
-        .map_err(PhotoError::from)
+impl std::fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PostDownload => write!(f, "post-download"),
+            Self::PostSet => write!(f, "post-set"),
+        }
+    }
 }
 
-/// Minimum file size in bytes to keep (skip small thumbnails/icons)
-const MIN_PHOTO_SIZE_BYTES: u64 = 50_000; // 50KB
-
-/// Check if a filename looks like a "Best of Photo of the Day" collection photo
-/// Matches patterns like: `01-best-pod-october-18`, `02_best-pod-july-18`, `best_pod_landscapes`
-fn is_collection_photo_filename(filename: &str) -> bool {
-    let lower = filename.to_lowercase();
-    lower.contains("best-pod") || lower.contains("best_pod")
+/// What a hook is told about the photo, as `NATGEO_*` environment variables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookContext {
+    pub photo_path: PathBuf,
+    pub title: String,
+    /// Monitor or virtual desktop the photo was applied to
+    pub assignment: Option<String>,
 }
 
-/// Extract all unique image URLs from i.natgeofe.com in the HTML body
-fn extract_natgeo_image_urls(body: &str) -> Vec<String> {
-    let mut urls: Vec<String> = Vec::new();
-    let mut seen: std::collections::HashSet<String> = std::collections::HashSet::new();
+impl HookContext {
+    /// `info` was just saved at `path`
+    pub fn downloaded(info: &PhotoInfo, path: &Path) -> Self {
+        Self {
+            photo_path: path.to_path_buf(),
+            title: info.title.clone(),
+            assignment: None,
+        }
+    }
 
-    // Look for patterns like "https://i.natgeofe.com/n/UUID/filename.jpg"
-    // These appear in various contexts: img src, JSON data, meta tags
-    for part in body.split("https://i.natgeofe.com/n/") {
-        // Skip the first split (before first match)
-        if part.starts_with("i.natgeofe.com") {
-            continue;
+    /// `assignment` was applied; the title comes from the metadata sidecar,
+    /// or the file name for photos without one
+    pub fn applied(assignment: &WallpaperAssignment) -> Self {
+        let path = &assignment.photo_path;
+        let title = read_metadata(&metadata_path_for(path)).map_or_else(
+            |_| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().replace('_', " "))
+                    .unwrap_or_default()
+            },
+            |metadata| metadata.title,
+        );
+        Self {
+            photo_path: path.clone(),
+            title,
+            assignment: Some(assignment.location.clone()),
         }
+    }
 
-        // Extract the path until we hit a quote, space, or other delimiter
-        let path_end = part.find(['"', '\'', ' ', '?', '\\']).unwrap_or(part.len());
+    /// Environment variables for a hook running after `event`
+    pub fn env(&self, event: HookEvent) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("NATGEO_EVENT", event.to_string()),
+            ("NATGEO_PHOTO_PATH", self.photo_path.display().to_string()),
+            ("NATGEO_PHOTO_TITLE", self.title.clone()),
+        ];
+        if let Some(assignment) = &self.assignment {
+            env.push(("NATGEO_ASSIGNMENT", assignment.clone()));
+        }
+        env
+    }
+}
 
-        let path = &part[..path_end];
+/// Run the command `hooks` configures for `event` with the config of this
+/// process. See [`run_hooks_with`].
+pub fn run_hooks(
+    event: HookEvent,
+    context: &HookContext,
+    log_path: &str,
+) -> Result<(), PhotoError> {
+    run_hooks_with(&config().hooks, event, context, log_path)
+}
 
-        // Only include if it looks like a valid image path (has UUID and extension)
-        // We use to_lowercase() so the ends_with checks are already case-insensitive
-        let path_lower = path.to_lowercase();
-        #[allow(clippy::case_sensitive_file_extension_comparisons)]
-        let has_image_ext = path_lower.ends_with(".jpg")
-            || path_lower.ends_with(".png")
-            || path_lower.ends_with(".gif");
-        if path.contains('/') && has_image_ext {
-            // Skip crop variants (e.g., _16x9.jpg, _3x2.jpg) - we want the raw images
-            let is_crop_variant = path.contains("_16x9")
-                || path.contains("_3x2")
-                || path.contains("_4x3")
-                || path.contains("_2x1")
-                || path.contains("_2x3")
-                || path.contains("_3x4")
-                || path.contains("_square");
-
-            if !is_crop_variant {
-                let full_url = format!("https://i.natgeofe.com/n/{}", path);
-                if seen.insert(full_url.clone()) {
-                    urls.push(full_url);
-                }
+/// Run the command `hooks` configures for `event` through the shell, with
+/// `context` in its environment
+///
+/// A hook that can't start or exits non-zero is logged and warned about, and
+/// only fails the caller when `hooks.strict` is set.
+pub fn run_hooks_with(
+    hooks: &HooksConfig,
+    event: HookEvent,
+    context: &HookContext,
+    log_path: &str,
+) -> Result<(), PhotoError> {
+    let Some(command) = hooks.command(event) else {
+        return Ok(());
+    };
+    debug!(%event, command, "Running hook");
+    let result = shell_command(command)
+        .envs(context.env(event))
+        .output()
+        .map_err(|e| format!("{}: {}", command, e))
+        .and_then(|output| {
+            if output.status.success() {
+                Ok(())
+            } else {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                Err(format!(
+                    "{} ({}): {}",
+                    command,
+                    output.status,
+                    stderr.trim()
+                ))
+            }
+        });
+    match result {
+        Ok(()) => {
+            write_log(log_path, &format!("Ran {} hook: {}", event, command));
+            Ok(())
+        }
+        Err(message) => {
+            write_log(log_path, &format!("{} hook failed: {}", event, message));
+            warn!(%event, "Hook failed: {}", message);
+            if hooks.strict == Some(true) {
+                return Err(PhotoError::Hook(format!("{}: {}", event, message)));
             }
+            if !quiet_output() {
+                eprintln!("Warning: {} hook failed: {}", event, message);
+            }
+            Ok(())
         }
     }
+}
 
-    urls
+/// `command` run by the platform shell
+fn shell_command(command: &str) -> Command {
+    let (shell, flag) = if cfg!(target_os = "windows") {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut shell = Command::new(shell);
+    shell.arg(flag).arg(command);
+    shell
 }
 
-/// Fetch photos from a "Best of Photo of the Day" collection page
-pub fn get_collection_photos(url: &str) -> Result<PhotoCollection, PhotoError> {
-    let client = create_http_client()?;
+// ============================================================================
+// Configuration
+// ============================================================================
+
+/// Directories from the `[paths]` table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathsConfig {
+    /// Where daily photos are saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub photos: Option<String>,
+    /// Where collections are saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collections: Option<String>,
+    /// Where logs and the manifest are kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logs: Option<String>,
+    /// Where resized and converted copies are cached
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<String>,
+}
 
-    let response = client.get(url).send()?;
+/// Wallpaper defaults from the `[wallpaper]` table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WallpaperConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<WallpaperMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub random: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_screen: Option<bool>,
+    /// Whether to keep a `current` link to the wallpaper in the photo directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_link: Option<bool>,
+    /// Whether to theme the terminal from the wallpaper with pywal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pywal: Option<bool>,
+    /// How many times likelier favorites are to be picked at random
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub favorite_weight: Option<u32>,
+}
 
-    let status = response.status();
-    if !status.is_success() {
-        return Err(PhotoError::InvalidContentType(format!(
-            "HTTP {}: Failed to fetch collection page",
-            status
-        )));
+/// The `clean` policy from the `[retention]` table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_days: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_count: Option<usize>,
+    /// A size such as `500M` or `2G`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<String>,
+}
+
+impl RetentionConfig {
+    /// Whether no policy is set
+    pub const fn is_empty(&self) -> bool {
+        self.keep_days.is_none() && self.keep_count.is_none() && self.max_size.is_none()
     }
+}
 
-    let body = response.text()?;
+/// Network settings from the `[http]` table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
 
-    // Extract collection name from og:title or URL
-    let name = body
-        .split("property=\"og:title\"")
-        .nth(1)
-        .and_then(|s| s.split("content=\"").nth(1))
-        .and_then(|s| s.split('"').next())
-        .filter(|s| !s.is_empty() && s.len() >= 5)
-        .map_or_else(|| extract_collection_name_from_url(url), String::from);
+/// Log rotation from the `[logging]` table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Size at which a log is rotated, such as `1M`; `0` turns rotation off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<String>,
+    /// Number of rotated copies to keep
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+}
 
-    // Extract all image URLs
-    let image_urls = extract_natgeo_image_urls(&body);
+/// Desktop notifications from the `[notifications]` table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationsConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+}
 
-    if image_urls.is_empty() {
-        return Err(PhotoError::NoPhotos(format!(
-            "No photos found in collection: {}",
-            url
-        )));
+/// User commands from the `[hooks]` table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Shell command run after each new photo is saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_download: Option<String>,
+    /// Shell command run after each wallpaper is applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_set: Option<String>,
+    /// Whether a failing hook fails the command that ran it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl HooksConfig {
+    /// The command to run after `event`, if any
+    pub fn command(&self, event: HookEvent) -> Option<&str> {
+        match event {
+            HookEvent::PostDownload => self.post_download.as_deref(),
+            HookEvent::PostSet => self.post_set.as_deref(),
+        }
+        .filter(|command| !command.trim().is_empty())
     }
+}
 
-    // Create PhotoInfo for each URL, using filename as title
-    // Filter to only include photos that match the "best-pod" naming pattern
-    let photos: Vec<PhotoInfo> = image_urls
-        .into_iter()
-        .filter_map(|image_url| {
-            let title = image_url
-                .split('/')
-                .next_back()
-                .and_then(|filename| filename.split('.').next())
-                .unwrap_or("photo")
-                .to_string();
-
-            // Only include photos matching the collection naming pattern
-            if is_collection_photo_filename(&title) {
-                Some(PhotoInfo { image_url, title })
+/// Settings from the config file or the command line, with every key optional
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub paths: PathsConfig,
+    pub wallpaper: WallpaperConfig,
+    pub retention: RetentionConfig,
+    pub http: HttpConfig,
+    pub logging: LoggingConfig,
+    pub notifications: NotificationsConfig,
+    pub hooks: HooksConfig,
+}
+
+/// `over` if it is set, otherwise `base`
+fn layer<T: Clone>(base: Option<&T>, over: Option<&T>) -> Option<T> {
+    over.or(base).cloned()
+}
+
+impl Config {
+    /// `self` with every key that `overrides` sets replaced
+    ///
+    /// The retention keys form one policy, so setting any of them in
+    /// `overrides` replaces all of them.
+    #[must_use]
+    pub fn merged(&self, overrides: &Self) -> Self {
+        Self {
+            paths: PathsConfig {
+                photos: layer(self.paths.photos.as_ref(), overrides.paths.photos.as_ref()),
+                collections: layer(
+                    self.paths.collections.as_ref(),
+                    overrides.paths.collections.as_ref(),
+                ),
+                logs: layer(self.paths.logs.as_ref(), overrides.paths.logs.as_ref()),
+                cache: layer(self.paths.cache.as_ref(), overrides.paths.cache.as_ref()),
+            },
+            wallpaper: WallpaperConfig {
+                mode: layer(
+                    self.wallpaper.mode.as_ref(),
+                    overrides.wallpaper.mode.as_ref(),
+                ),
+                random: layer(
+                    self.wallpaper.random.as_ref(),
+                    overrides.wallpaper.random.as_ref(),
+                ),
+                lock_screen: layer(
+                    self.wallpaper.lock_screen.as_ref(),
+                    overrides.wallpaper.lock_screen.as_ref(),
+                ),
+                current_link: layer(
+                    self.wallpaper.current_link.as_ref(),
+                    overrides.wallpaper.current_link.as_ref(),
+                ),
+                pywal: layer(
+                    self.wallpaper.pywal.as_ref(),
+                    overrides.wallpaper.pywal.as_ref(),
+                ),
+                favorite_weight: layer(
+                    self.wallpaper.favorite_weight.as_ref(),
+                    overrides.wallpaper.favorite_weight.as_ref(),
+                ),
+            },
+            retention: if overrides.retention.is_empty() {
+                self.retention.clone()
             } else {
-                None
-            }
-        })
-        .collect();
+                overrides.retention.clone()
+            },
+            http: HttpConfig {
+                retries: layer(self.http.retries.as_ref(), overrides.http.retries.as_ref()),
+                timeout: layer(self.http.timeout.as_ref(), overrides.http.timeout.as_ref()),
+                proxy: layer(self.http.proxy.as_ref(), overrides.http.proxy.as_ref()),
+            },
+            logging: LoggingConfig {
+                max_size: layer(
+                    self.logging.max_size.as_ref(),
+                    overrides.logging.max_size.as_ref(),
+                ),
+                keep: layer(self.logging.keep.as_ref(), overrides.logging.keep.as_ref()),
+            },
+            notifications: NotificationsConfig {
+                enabled: layer(
+                    self.notifications.enabled.as_ref(),
+                    overrides.notifications.enabled.as_ref(),
+                ),
+            },
+            hooks: HooksConfig {
+                post_download: layer(
+                    self.hooks.post_download.as_ref(),
+                    overrides.hooks.post_download.as_ref(),
+                ),
+                post_set: layer(
+                    self.hooks.post_set.as_ref(),
+                    overrides.hooks.post_set.as_ref(),
+                ),
+                strict: layer(self.hooks.strict.as_ref(), overrides.hooks.strict.as_ref()),
+            },
+        }
+    }
 
-    if photos.is_empty() {
-        return Err(PhotoError::NoPhotos(format!(
-            "No collection photos found (matching 'best-pod' pattern) in: {}",
-            url
-        )));
+    /// `self` with unset keys filled in from the built-in defaults, and the
+    /// paths replaced by the directories actually in use
+    ///
+    /// Retention, the proxy, and hook commands have no default and stay unset.
+    #[must_use]
+    pub fn with_defaults(&self, dirs: &DataDirs) -> Self {
+        let defaults = Self {
+            paths: PathsConfig::default(),
+            wallpaper: WallpaperConfig {
+                mode: Some(WallpaperMode::default()),
+                random: Some(false),
+                lock_screen: Some(false),
+                current_link: Some(true),
+                pywal: Some(false),
+                favorite_weight: Some(DEFAULT_FAVORITE_WEIGHT),
+            },
+            retention: RetentionConfig::default(),
+            http: HttpConfig {
+                retries: Some(DEFAULT_HTTP_RETRIES),
+                timeout: Some(DEFAULT_HTTP_TIMEOUT.as_secs()),
+                proxy: None,
+            },
+            logging: LoggingConfig {
+                max_size: Some(DEFAULT_LOG_MAX_SIZE.to_string()),
+                keep: Some(DEFAULT_LOG_KEEP),
+            },
+            notifications: NotificationsConfig {
+                enabled: Some(false),
+            },
+            hooks: HooksConfig {
+                post_download: None,
+                post_set: None,
+                strict: Some(false),
+            },
+        };
+        let mut merged = defaults.merged(self);
+        merged.paths = PathsConfig {
+            photos: Some(dirs.photos.clone()),
+            collections: Some(dirs.collections.clone()),
+            logs: Some(dirs.logs.clone()),
+            cache: Some(dirs.cache.clone()),
+        };
+        merged
     }
 
-    Ok(PhotoCollection { name, photos })
-}
+    /// Request options from the `[http]` table, defaulting the rest
+    pub fn http_options(&self) -> HttpOptions {
+        let defaults = HttpOptions::default();
+        HttpOptions {
+            retries: self.http.retries.unwrap_or(defaults.retries),
+            timeout: self
+                .http
+                .timeout
+                .map_or(defaults.timeout, Duration::from_secs),
+            proxy: self.http.proxy.clone(),
+            ..defaults
+        }
+    }
 
-/// Download result for a collection
-#[derive(Debug)]
-pub struct CollectionDownloadResult {
-    pub downloaded: usize,
-    pub skipped: usize,
-    pub failed: usize,
+    /// Log rotation from the `[logging]` table, defaulting the rest
+    pub fn log_rotation(&self) -> LogRotation {
+        let defaults = LogRotation::default();
+        LogRotation {
+            max_bytes: self
+                .logging
+                .max_size
+                .as_deref()
+                .and_then(parse_size)
+                .unwrap_or(defaults.max_bytes),
+            keep: self.logging.keep.unwrap_or(defaults.keep),
+        }
+    }
 }
 
-/// Find a downloaded file by its sanitized title (checks jpg, png, gif extensions)
-fn find_downloaded_file(dir: &str, sanitized_title: &str) -> Option<std::path::PathBuf> {
-    for ext in ["jpg", "png", "gif"] {
-        let path = std::path::PathBuf::from(format!("{}/{}.{}", dir, sanitized_title, ext));
-        if path.exists() {
-            return Some(path);
+/// Parse a config file, returning the settings and the keys it didn't recognize
+pub fn parse_config(text: &str) -> Result<(Config, Vec<String>), PhotoError> {
+    let deserializer =
+        toml::Deserializer::parse(text).map_err(|e| PhotoError::Config(e.to_string()))?;
+    let mut unknown = Vec::new();
+    let config: Config = serde_ignored::deserialize(deserializer, |path| {
+        unknown.push(path.to_string());
+    })
+    .map_err(|e| PhotoError::Config(e.to_string()))?;
+
+    if let Some(size) = &config.retention.max_size {
+        if parse_size(size).is_none() {
+            return Err(PhotoError::Config(format!(
+                "retention.max_size: expected a size like 500M or 2G, got {:?}",
+                size
+            )));
         }
     }
-    None
+    if let Some(size) = &config.logging.max_size {
+        if parse_size(size).is_none() {
+            return Err(PhotoError::Config(format!(
+                "logging.max_size: expected a size like 1M or 500K, got {:?}",
+                size
+            )));
+        }
+    }
+    if config.http.timeout == Some(0) {
+        return Err(PhotoError::Config(
+            "http.timeout must be at least 1 second".to_string(),
+        ));
+    }
+    Ok((config, unknown))
 }
 
-/// Download all photos from a collection
-pub fn download_collection(
-    collection: &PhotoCollection,
-    collection_name: &str,
-) -> Result<CollectionDownloadResult, PhotoError> {
-    let base_dir = expand_tilde(COLLECTION_SAVE_PATH);
-    let save_dir = format!("{}{}", base_dir, collection_name);
+/// Location of the config file, under `XDG_CONFIG_HOME` when that is set
+pub fn config_path() -> PathBuf {
+    config_path_in(std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from))
+}
 
-    // Create the collection directory
-    std::fs::create_dir_all(&save_dir)?;
+/// Location of the config file given the value of `XDG_CONFIG_HOME`
+fn config_path_in(xdg_config_home: Option<PathBuf>) -> PathBuf {
+    // The spec says to ignore a relative XDG_CONFIG_HOME
+    match xdg_config_home.filter(|dir| dir.is_absolute()) {
+        Some(dir) => dir.join("natgeo-wallpapers").join("config.toml"),
+        None => PathBuf::from(expand_tilde(CONFIG_PATH)),
+    }
+}
 
-    let log_path = format!("{}/collection.log", save_dir);
-    write_log(
-        &log_path,
-        &format!("Starting download of collection: {}", collection.name),
-    );
-    write_log(
-        &log_path,
-        &format!("Total photos: {}", collection.photos.len()),
-    );
+/// Read the config file at `path`, treating a missing file as an empty config
+pub fn load_config(path: &Path) -> Result<(Config, Vec<String>), PhotoError> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => parse_config(&text)
+            .map_err(|e| PhotoError::Config(format!("{}: {}", path.display(), e))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok((Config::default(), Vec::new())),
+        Err(e) => Err(PhotoError::File(e)),
+    }
+}
 
-    let mut downloaded = 0;
-    let mut skipped = 0;
-    let mut failed = 0;
+/// A commented config file showing every key with its default
+pub fn default_config_file() -> String {
+    format!(
+        r#"# natgeo-wallpapers configuration
+#
+# Every key is optional. Command-line flags override these values.
 
-    for photo in &collection.photos {
-        let sanitized_title = sanitize_title(&photo.title);
+[paths]
+# Where daily photos are saved (default: the Pictures folder)
+# photos = "~/Pictures/NationalGeographic/"
+# Where collections are saved (default: inside the photo directory)
+# collections = "~/Pictures/NationalGeographic/collections/"
+# Where logs and the download manifest are kept (default: XDG_DATA_HOME)
+# logs = "~/.local/share/natgeo-wallpapers/"
+# Where resized and converted copies are kept (default: XDG_CACHE_HOME)
+# cache = "~/.cache/natgeo-wallpapers/"
 
-        // Check if already exists
-        let already_exists = std::fs::read_dir(&save_dir).ok().is_some_and(|entries| {
-            entries.flatten().any(|entry| {
-                let path = entry.path();
-                path.file_stem()
-                    .and_then(|s| s.to_str())
-                    .is_some_and(|stem| stem == sanitized_title)
-                    && path
-                        .extension()
-                        .and_then(|e| e.to_str())
-                        .is_some_and(|ext| matches!(ext, "jpg" | "png" | "gif"))
-            })
-        });
+[wallpaper]
+# monitors, virtual-desktops, both or activities
+# mode = "monitors"
+# random = false
+# lock_screen = false
+# Keep "current" (and "current-monitor-N") in the photo directory linked to the wallpaper
+# current_link = true
+# Theme the terminal from the primary wallpaper with pywal (`wal -n -i PHOTO`)
+# pywal = false
+# How many times likelier photos in favorites.txt are to be picked at random
+# favorite_weight = {favorite_weight}
 
-        if already_exists {
-            skipped += 1;
-            continue;
-        }
+[retention]
+# Policy used by `clean` when no policy flag is given
+# keep_days = 90
+# keep_count = 30
+# max_size = "2G"
 
-        match download_natgeo_photo_of_the_day(
-            &photo.image_url,
-            &save_dir,
-            &sanitized_title,
-            &log_path,
-        ) {
-            Ok(()) => {
-                // Check file size and remove if too small (likely a thumbnail)
-                let downloaded_file = find_downloaded_file(&save_dir, &sanitized_title);
-                if let Some(file_path) = downloaded_file {
-                    if let Ok(metadata) = std::fs::metadata(&file_path) {
-                        if metadata.len() < MIN_PHOTO_SIZE_BYTES {
-                            // Remove small file (thumbnail/icon)
-                            let _ = std::fs::remove_file(&file_path);
-                            write_log(
-                                &log_path,
-                                &format!(
-                                    "Removed {} (too small: {} bytes, min: {} bytes)",
-                                    sanitized_title,
-                                    metadata.len(),
-                                    MIN_PHOTO_SIZE_BYTES
-                                ),
-                            );
-                            skipped += 1;
-                            continue;
-                        }
-                    }
-                }
-                downloaded += 1;
-            }
-            Err(e) => {
-                write_log(
-                    &log_path,
-                    &format!("Failed to download {}: {}", photo.title, e),
-                );
-                failed += 1;
-            }
-        }
-    }
+[http]
+# retries = {retries}
+# Seconds to wait for a response
+# timeout = {timeout}
+# proxy = "http://proxy.example.com:8080"
 
-    write_log(
-        &log_path,
-        &format!(
-            "Collection download complete: {} downloaded, {} skipped, {} failed",
-            downloaded, skipped, failed
-        ),
-    );
+[logging]
+# Rotate a log once it reaches this size; "0" keeps logs growing
+# max_size = "{log_max_size}"
+# Rotated copies to keep (log.1 is the newest)
+# keep = {log_keep}
 
-    Ok(CollectionDownloadResult {
-        downloaded,
-        skipped,
-        failed,
-    })
+[notifications]
+# Show a desktop notification for new photos, wallpaper changes, and failures
+# enabled = false
+
+[hooks]
+# Shell commands run with NATGEO_EVENT, NATGEO_PHOTO_PATH, NATGEO_PHOTO_TITLE,
+# and (after set) NATGEO_ASSIGNMENT in their environment
+# post_download = "notify-photo.sh"
+# post_set = "echo \"$NATGEO_PHOTO_TITLE\" > ~/.cache/wallpaper-title"
+# Fail the command when a hook fails, instead of only logging it
+# strict = false
+"#,
+        retries = DEFAULT_HTTP_RETRIES,
+        timeout = DEFAULT_HTTP_TIMEOUT.as_secs(),
+        log_max_size = DEFAULT_LOG_MAX_SIZE,
+        log_keep = DEFAULT_LOG_KEEP,
+        favorite_weight = DEFAULT_FAVORITE_WEIGHT
+    )
 }
 
-// Helper function to sanitize title for filename
-pub fn sanitize_title(title: &str) -> String {
-    title
-        .replace("/", "_")
-        .replace(" ", "_")
-        .replace(":", "")
-        .replace("|", "-")
-        .chars()
-        .take(100)
-        .collect()
+/// Config for this process, set once at startup
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Use `config` for the directories and defaults of this process
+///
+/// Only the first call has an effect.
+pub fn set_config(config: Config) {
+    let _ = CONFIG.set(config);
 }
 
-// Helper function to expand tilde in path
-pub fn expand_tilde(path: &str) -> String {
-    if path.starts_with("~/") {
-        if let Some(home) = std::env::var_os("HOME") {
-            return path.replacen("~", &home.to_string_lossy(), 1);
-        }
-    }
-    path.to_string()
+/// The config passed to `set_config`, or an empty one
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
 }
 
 // ============================================================================
-// Wallpaper Setting Functions
+// Data Directories
 // ============================================================================
 
-/// Check if a command exists in PATH
-fn command_exists(cmd: &str) -> bool {
-    Command::new("which")
-        .arg(cmd)
-        .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
+/// Environment variable overriding the photo directory
+pub const PHOTO_DIR_ENV: &str = "NATGEO_WALLPAPERS_PHOTO_DIR";
+
+/// Environment variable overriding the collection directory
+pub const COLLECTION_DIR_ENV: &str = "NATGEO_WALLPAPERS_COLLECTION_DIR";
+
+/// Environment variable overriding the directory for logs, state and the manifest
+pub const DATA_DIR_ENV: &str = "NATGEO_WALLPAPERS_DATA_DIR";
+
+/// Environment variable overriding the directory for resized and converted copies
+pub const CACHE_DIR_ENV: &str = "NATGEO_WALLPAPERS_CACHE_DIR";
+
+/// Directory under the Pictures folder that photos are saved to by default
+const PHOTO_SUBDIR: &str = "NationalGeographic";
+
+/// Directory under the data and cache folders for this tool's files
+const DATA_SUBDIR: &str = "natgeo-wallpapers";
+
+/// The directories this process reads and writes, each expanded and ending
+/// in a separator so file names can be appended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataDirs {
+    pub photos: String,
+    pub collections: String,
+    pub logs: String,
+    pub cache: String,
 }
 
-/// Check if a process is running
-fn process_running(name: &str) -> bool {
-    Command::new("pgrep")
-        .args(["-x", name])
-        .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
+/// Platform folders the default directories are built from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BaseDirs {
+    pub home: Option<PathBuf>,
+    /// The user's Pictures folder (`XDG_PICTURES_DIR` from `user-dirs.dirs` on Linux)
+    pub pictures: Option<PathBuf>,
+    /// Per-user data folder (`XDG_DATA_HOME` on Linux)
+    pub data: Option<PathBuf>,
+    /// Per-user cache folder (`XDG_CACHE_HOME` on Linux)
+    pub cache: Option<PathBuf>,
 }
 
-/// Detect the current desktop environment
-pub fn detect_desktop_environment() -> DesktopEnvironment {
-    let plasmashell_running = process_running("plasmashell");
+impl BaseDirs {
+    /// The folders of the current user
+    pub fn from_system() -> Self {
+        let home = std::env::var_os("HOME")
+            .filter(|h| !h.is_empty())
+            .map(PathBuf::from)
+            .or_else(dirs::home_dir);
+        // Logs and caches have always lived in ~/.local/share and ~/.cache on
+        // macOS, not Application Support and Library/Caches
+        let (data, cache) = if cfg!(target_os = "macos") {
+            (
+                home.as_ref().map(|h| h.join(".local").join("share")),
+                home.as_ref().map(|h| h.join(".cache")),
+            )
+        } else {
+            (dirs::data_local_dir(), dirs::cache_dir())
+        };
+        Self {
+            home,
+            pictures: dirs::picture_dir(),
+            data,
+            cache,
+        }
+    }
+}
 
-    if command_exists("qdbus6") && plasmashell_running {
-        DesktopEnvironment::KdePlasma6
-    } else if command_exists("qdbus") && plasmashell_running {
-        DesktopEnvironment::KdePlasma5
-    } else if command_exists("plasma-apply-wallpaperimage") {
-        DesktopEnvironment::PlasmaFallback
-    } else if command_exists("gsettings") {
-        DesktopEnvironment::Gnome
-    } else if command_exists("feh") {
-        DesktopEnvironment::Feh
-    } else {
-        DesktopEnvironment::Unknown
+/// `dir` as a string ending in a separator
+fn with_trailing_separator(dir: &Path) -> String {
+    let mut dir = dir.display().to_string();
+    if !dir.ends_with('/') && !dir.ends_with(std::path::MAIN_SEPARATOR) {
+        dir.push('/');
+    }
+    dir
+}
+
+/// An override from the environment or config file, with `~` expanded
+fn expand_override(value: &str, home: Option<&Path>) -> Result<String, PhotoError> {
+    let expanded = resolve_home_path(value, home, &[]);
+    if expanded.starts_with('~')
+        || expanded
+            .get(..13)
+            .is_some_and(|p| p.eq_ignore_ascii_case("%USERPROFILE%"))
+    {
+        return Err(PhotoError::Config(format!(
+            "can't expand {}: HOME is not set",
+            value
+        )));
     }
+    Ok(with_trailing_separator(Path::new(&expanded)))
 }
 
-/// Get monitor count via qdbus
-fn get_monitor_count(de: DesktopEnvironment) -> usize {
-    let qdbus_cmd = match de {
-        DesktopEnvironment::KdePlasma6 => "qdbus6",
-        DesktopEnvironment::KdePlasma5 => "qdbus",
-        _ => return 1,
+/// Resolve the data directories
+///
+/// Each directory comes from its environment variable, then the `[paths]`
+/// table of `config`, then the platform folders in `base`. Collections
+/// default to a `collections` directory inside the photo directory.
+pub fn resolve_data_dirs(
+    env: &dyn Fn(&str) -> Option<String>,
+    config: &Config,
+    base: &BaseDirs,
+) -> Result<DataDirs, PhotoError> {
+    let home = base.home.as_deref();
+    let no_home =
+        || PhotoError::Config("HOME is not set, so there is nowhere to keep photos".to_string());
+    let pick = |var: &str, configured: Option<&String>| {
+        env(var)
+            .filter(|value| !value.is_empty())
+            .or_else(|| configured.cloned())
+            .map(|value| expand_override(&value, home))
+            .transpose()
+    };
+
+    let photos = if let Some(dir) = pick(PHOTO_DIR_ENV, config.paths.photos.as_ref())? {
+        dir
+    } else {
+        let pictures = base
+            .pictures
+            .clone()
+            .or_else(|| home.map(|h| h.join("Pictures")))
+            .ok_or_else(no_home)?;
+        with_trailing_separator(&pictures.join(PHOTO_SUBDIR))
+    };
+    let collections = match pick(COLLECTION_DIR_ENV, config.paths.collections.as_ref())? {
+        Some(dir) => dir,
+        None => with_trailing_separator(&Path::new(&photos).join("collections")),
+    };
+    let logs = if let Some(dir) = pick(DATA_DIR_ENV, config.paths.logs.as_ref())? {
+        dir
+    } else {
+        let data = base
+            .data
+            .clone()
+            .or_else(|| home.map(|h| h.join(".local").join("share")))
+            .ok_or_else(no_home)?;
+        with_trailing_separator(&data.join(DATA_SUBDIR))
+    };
+    let cache = if let Some(dir) = pick(CACHE_DIR_ENV, config.paths.cache.as_ref())? {
+        dir
+    } else {
+        let cache = base
+            .cache
+            .clone()
+            .or_else(|| home.map(|h| h.join(".cache")))
+            .ok_or_else(no_home)?;
+        with_trailing_separator(&cache.join(DATA_SUBDIR))
     };
+    Ok(DataDirs {
+        photos,
+        collections,
+        logs,
+        cache,
+    })
+}
 
-    let script = "var allDesktops = desktops(); print(allDesktops.length);";
-    let output = Command::new(qdbus_cmd)
-        .args([
-            "org.kde.plasmashell",
-            "/PlasmaShell",
-            "org.kde.PlasmaShell.evaluateScript",
-            script,
-        ])
-        .output();
+/// Directories for this process, resolved once
+static DATA_DIRS: OnceLock<DataDirs> = OnceLock::new();
 
-    output
-        .ok()
-        .and_then(|o| String::from_utf8(o.stdout).ok())
-        .and_then(|s| s.trim().parse().ok())
-        .unwrap_or(1)
+/// Resolve the data directories from the environment, the config set with
+/// `set_config`, and the platform folders
+///
+/// Call this at startup to report a missing home directory before any work
+/// is done. Resolving fails rather than falling back to the working directory.
+/// The data and cache directories get the managed marker when this call
+/// creates them, so `uninstall` can tell them from folders the user chose.
+pub fn init_data_dirs() -> Result<&'static DataDirs, PhotoError> {
+    let dirs = data_dirs()?;
+    ensure_managed_dir(&dirs.logs)?;
+    ensure_managed_dir(&dirs.cache)?;
+    Ok(dirs)
 }
 
-/// Get virtual desktop count via qdbus
-fn get_virtual_desktop_count(de: DesktopEnvironment) -> usize {
-    let qdbus_cmd = match de {
-        DesktopEnvironment::KdePlasma6 => "qdbus6",
-        _ => return 1, // Only Plasma 6 supports VD wallpapers reliably
-    };
+/// The resolved data directories
+pub fn data_dirs() -> Result<&'static DataDirs, PhotoError> {
+    if let Some(dirs) = DATA_DIRS.get() {
+        return Ok(dirs);
+    }
+    let dirs = resolve_data_dirs(
+        &|var| std::env::var(var).ok(),
+        config(),
+        &BaseDirs::from_system(),
+    )?;
+    Ok(DATA_DIRS.get_or_init(|| dirs))
+}
 
-    let output = Command::new(qdbus_cmd)
-        .args([
-            "org.kde.KWin",
-            "/VirtualDesktopManager",
-            "org.kde.KWin.VirtualDesktopManager.count",
-        ])
-        .output();
+/// Directory for daily photos, ending in a separator
+pub fn photo_dir() -> Result<String, PhotoError> {
+    Ok(data_dirs()?.photos.clone())
+}
 
-    output
-        .ok()
-        .and_then(|o| String::from_utf8(o.stdout).ok())
-        .and_then(|s| s.trim().parse().ok())
-        .unwrap_or(1)
+/// Directory for collections, ending in a separator
+pub fn collection_dir() -> Result<String, PhotoError> {
+    Ok(data_dirs()?.collections.clone())
 }
 
-/// Recursively collect photos from a directory
-fn collect_photos(dir: &std::path::Path, photos: &mut Vec<PathBuf>) -> io::Result<()> {
-    if dir.is_dir() {
-        for entry in std::fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                collect_photos(&path, photos)?;
-            } else if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-                if matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg" | "png" | "gif") {
-                    photos.push(path);
-                }
-            }
+/// Directory for logs, state and the manifest, ending in a separator
+pub fn log_dir() -> Result<String, PhotoError> {
+    Ok(data_dirs()?.logs.clone())
+}
+
+/// Directory for resized and converted copies, ending in a separator
+pub fn cache_dir() -> Result<String, PhotoError> {
+    Ok(data_dirs()?.cache.clone())
+}
+
+/// `path` with `~` expanded, or the photo directory when there is none
+pub fn photo_dir_or(path: Option<&str>) -> Result<String, PhotoError> {
+    path.map_or_else(photo_dir, |path| Ok(expand_tilde(path)))
+}
+
+/// Whether log files that can't be written are errors rather than warnings
+static STRICT_LOGGING: AtomicBool = AtomicBool::new(false);
+
+/// Set once a failed log write has been reported, so it is only reported once
+static LOG_FAILURE_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Make [`write_log_checked`] fail when the log can't be written
+pub fn set_strict_logging(enabled: bool) {
+    STRICT_LOGGING.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether `--strict-logs` was given
+pub fn strict_logging() -> bool {
+    STRICT_LOGGING.load(Ordering::Relaxed)
+}
+
+/// Size at which logs are rotated unless the config says otherwise
+pub const DEFAULT_LOG_MAX_SIZE: &str = "1M";
+
+/// Rotated copies of each log kept unless the config says otherwise
+pub const DEFAULT_LOG_KEEP: usize = 3;
+
+/// When logs are rotated and how many old copies are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    /// Size at which a log is rotated; `0` turns rotation off
+    pub max_bytes: u64,
+    /// Rotated copies to keep, `log.1` being the newest
+    pub keep: usize,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self {
+            max_bytes: parse_size(DEFAULT_LOG_MAX_SIZE).unwrap_or(1 << 20),
+            keep: DEFAULT_LOG_KEEP,
         }
     }
-    Ok(())
 }
 
-/// Find all photos in the photo directory, sorted newest first
-pub fn find_all_photos() -> Result<Vec<PathBuf>, PhotoError> {
-    find_photos_in_path(None)
+/// Path of the `n`th rotated copy of `log_path` (`wallpaper.log.1`)
+pub fn rotated_log_path(log_path: &Path, n: usize) -> PathBuf {
+    let mut name = log_path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
 }
 
-/// Find photos in a specific path (file or directory), or default location if None
-pub fn find_photos_in_path(path: Option<&str>) -> Result<Vec<PathBuf>, PhotoError> {
-    let search_path = match path {
-        Some(p) => expand_tilde(p),
-        None => expand_tilde(PHOTO_SAVE_PATH),
-    };
-
-    let search_path_obj = std::path::Path::new(&search_path);
+/// Rotate `log_path` if it has reached `rotation.max_bytes`: `log.1` becomes
+/// `log.2` and so on, the oldest copy is dropped, and the log becomes `log.1`.
+/// Returns whether the log was rotated.
+pub fn rotate_log(log_path: &Path, rotation: LogRotation) -> std::io::Result<bool> {
+    if rotation.max_bytes == 0 {
+        return Ok(false);
+    }
+    match std::fs::metadata(log_path) {
+        Ok(metadata) if metadata.len() >= rotation.max_bytes => {}
+        _ => return Ok(false),
+    }
 
-    if !search_path_obj.exists() {
-        return Err(PhotoError::NoPhotos(format!(
-            "Path not found: {}",
-            search_path
-        )));
+    if rotation.keep == 0 {
+        std::fs::remove_file(log_path)?;
+        return Ok(true);
+    }
+    for n in (1..rotation.keep).rev() {
+        let older = rotated_log_path(log_path, n);
+        if older.exists() {
+            std::fs::rename(&older, rotated_log_path(log_path, n + 1))?;
+        }
     }
+    std::fs::rename(log_path, rotated_log_path(log_path, 1))?;
+    Ok(true)
+}
 
-    let mut photos: Vec<PathBuf> = Vec::new();
+/// Append a timestamped entry to the log at `log_path`, creating its directory
+/// if needed and rotating it as the `[logging]` config says
+pub fn try_write_log(log_path: &str, message: &str) -> std::io::Result<()> {
+    append_log(log_path, message, config().log_rotation())
+}
 
-    // If it's a single file, just use that
-    if search_path_obj.is_file() {
-        if let Some(ext) = search_path_obj.extension().and_then(|e| e.to_str()) {
-            if matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg" | "png" | "gif") {
-                photos.push(search_path_obj.to_path_buf());
-            } else {
-                return Err(PhotoError::NoPhotos(format!(
-                    "Not a supported image file: {}",
-                    search_path
-                )));
-            }
+/// [`try_write_log`] with explicit rotation settings
+fn append_log(log_path: &str, message: &str, rotation: LogRotation) -> std::io::Result<()> {
+    let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
+    let log_message = format!("[{}] {}\n", timestamp, message);
+
+    if let Some(parent) = Path::new(log_path)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    rotate_log(Path::new(log_path), rotation)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+    file.write_all(log_message.as_bytes())
+}
+
+/// Append a timestamped entry to a log. Logging is best effort: the first
+/// failure is reported on stderr and later ones are ignored.
+pub fn write_log(log_path: &str, message: &str) {
+    debug!(log = log_path, "{}", message);
+    if let Err(e) = try_write_log(log_path, message) {
+        if !LOG_FAILURE_REPORTED.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "Warning: can't write log {}: {} (further log failures are not reported)",
+                log_path, e
+            );
         }
-    } else {
-        // It's a directory, collect all photos recursively
-        collect_photos(search_path_obj, &mut photos)?;
     }
+}
 
-    if photos.is_empty() {
-        return Err(PhotoError::NoPhotos(format!(
-            "No photos found in {}",
-            search_path
-        )));
+/// Append to a log like [`write_log`], but fail under `--strict-logs`
+pub fn write_log_checked(log_path: &str, message: &str) -> Result<(), PhotoError> {
+    if !strict_logging() {
+        write_log(log_path, message);
+        return Ok(());
     }
+    try_write_log(log_path, message).map_err(|e| PhotoError::Log(format!("{}: {}", log_path, e)))
+}
 
-    // Sort by path in reverse order (newest first)
-    photos.sort();
-    photos.reverse();
+// ============================================================================
+// HTTP Options and Retries
+// ============================================================================
 
-    Ok(photos)
+/// Default number of retries after the first attempt of a request
+pub const DEFAULT_HTTP_RETRIES: u32 = 2;
+
+/// Default limit on a request, and on each read of a response body
+pub const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default limit on establishing a connection
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before the first retry; doubled for each retry after it
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest wait between attempts, including one asked for by `Retry-After`
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Network behavior shared by every request the library makes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpOptions {
+    /// Extra attempts after a network error, 429, or 5xx response
+    pub retries: u32,
+    /// Delay before the first retry, doubled (plus jitter) for each one after
+    pub retry_base_delay: Duration,
+    /// Limit on a request, and on each read of a response body
+    pub timeout: Duration,
+    /// Limit on establishing a connection
+    pub connect_timeout: Duration,
+    /// Proxy for all requests, overriding `HTTP_PROXY`/`HTTPS_PROXY`
+    pub proxy: Option<String>,
 }
 
-/// Wallpaper assignment for display
-#[derive(Debug)]
-pub struct WallpaperAssignment {
-    pub location: String,
-    pub photo_path: PathBuf,
-    pub is_newest: bool,
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_HTTP_RETRIES,
+            retry_base_delay: RETRY_BASE_DELAY,
+            timeout: DEFAULT_HTTP_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            proxy: None,
+        }
+    }
 }
 
-/// Build wallpaper assignments based on mode
-pub fn build_assignments(
-    mode: WallpaperMode,
-    photos: &[PathBuf],
-    monitor_count: usize,
-    vd_count: usize,
-) -> Vec<WallpaperAssignment> {
-    let mut assignments = Vec::new();
+/// Proxies to use, resolved from `--proxy` and the environment
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// Proxy for `http://` URLs
+    pub http: Option<String>,
+    /// Proxy for `https://` URLs
+    pub https: Option<String>,
+    /// Comma-separated hosts that bypass the proxy, as in `NO_PROXY`
+    pub no_proxy: Option<String>,
+}
 
-    match mode {
-        WallpaperMode::Monitors => {
-            for i in 0..monitor_count {
-                let photo_idx = i % photos.len();
-                assignments.push(WallpaperAssignment {
-                    location: format!("Monitor {}", i + 1),
-                    photo_path: photos[photo_idx].clone(),
-                    is_newest: i == 0,
-                });
-            }
+impl ProxyConfig {
+    /// Resolve proxies: `explicit` (from `--proxy`) wins for both schemes,
+    /// otherwise `HTTP_PROXY`/`HTTPS_PROXY` (or their lowercase forms) are used.
+    /// `NO_PROXY` applies either way.
+    pub fn resolve(explicit: Option<&str>, probe: &dyn SystemProbe) -> Self {
+        let env = |name: &str| {
+            probe
+                .env_var(name)
+                .or_else(|| probe.env_var(&name.to_lowercase()))
+                .and_then(|value| non_empty(&value))
+        };
+        let explicit = explicit.and_then(non_empty);
+        Self {
+            http: explicit.clone().or_else(|| env("HTTP_PROXY")),
+            https: explicit.or_else(|| env("HTTPS_PROXY")),
+            no_proxy: env("NO_PROXY"),
         }
-        WallpaperMode::VirtualDesktops => {
-            for i in 0..vd_count {
-                let photo_idx = i % photos.len();
-                assignments.push(WallpaperAssignment {
-                    location: format!("Virtual Desktop {}", i + 1),
-                    photo_path: photos[photo_idx].clone(),
-                    is_newest: i == 0,
-                });
-            }
+    }
+
+    /// Configure `builder` to use exactly these proxies
+    pub fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder, PhotoError> {
+        // Turn off reqwest's own environment lookup so only this config applies
+        let mut builder = builder.no_proxy();
+        let no_proxy = || self.no_proxy.as_deref().and_then(NoProxy::from_string);
+        let invalid =
+            |url: &str, e: reqwest::Error| PhotoError::InvalidProxy(format!("{}: {}", url, e));
+        if let Some(url) = &self.http {
+            let proxy = Proxy::http(url).map_err(|e| invalid(url, e))?;
+            builder = builder.proxy(proxy.no_proxy(no_proxy()));
         }
-        WallpaperMode::Both => {
-            let mut idx = 0;
-            for vd in 0..vd_count {
-                for mon in 0..monitor_count {
-                    let photo_idx = idx % photos.len();
-                    assignments.push(WallpaperAssignment {
-                        location: format!("Monitor {}, VD {}", mon + 1, vd + 1),
-                        photo_path: photos[photo_idx].clone(),
-                        is_newest: idx == 0,
-                    });
-                    idx += 1;
-                }
-            }
+        if let Some(url) = &self.https {
+            let proxy = Proxy::https(url).map_err(|e| invalid(url, e))?;
+            builder = builder.proxy(proxy.no_proxy(no_proxy()));
         }
+        Ok(builder)
     }
-
-    assignments
 }
 
-/// Set wallpaper for a specific monitor using qdbus6
-fn set_wallpaper_qdbus6(
-    monitor_idx: usize,
-    photo_path: &std::path::Path,
-) -> Result<(), PhotoError> {
-    let path_str = photo_path.to_string_lossy();
-    let script = format!(
-        r"var allDesktops = desktops();
-if ({idx} < allDesktops.length) {{
-    d = allDesktops[{idx}];
-    d.wallpaperPlugin = 'org.kde.image';
-    d.currentConfigGroup = Array('Wallpaper', 'org.kde.image', 'General');
-    d.writeConfig('Image', 'file://{path}');
-}}",
-        idx = monitor_idx,
-        path = path_str
-    );
+/// Build a client sending `headers`, with the timeouts and proxy from `http`
+fn build_client(headers: HeaderMap, http: &HttpOptions) -> Result<Client, PhotoError> {
+    ProxyConfig::resolve(http.proxy.as_deref(), &LiveSystem)
+        .apply(Client::builder())?
+        .default_headers(headers)
+        .timeout(http.timeout)
+        .connect_timeout(http.connect_timeout)
+        .build()
+        .map_err(PhotoError::from)
+}
 
-    let output = Command::new("qdbus6")
-        .args([
-            "org.kde.plasmashell",
-            "/PlasmaShell",
-            "org.kde.PlasmaShell.evaluateScript",
-            &script,
-        ])
-        .output()
-        .map_err(|e| PhotoError::Command(e.to_string()))?;
+/// Whether a response status is worth retrying: 429 and any 5xx
+pub fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
 
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(PhotoError::Wallpaper(
-            String::from_utf8_lossy(&output.stderr).to_string(),
-        ))
+/// Delay requested by a `Retry-After` header, given as seconds or an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
     }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.signed_duration_since(chrono::Utc::now());
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
 }
 
-/// Set wallpaper for a specific monitor using qdbus (Plasma 5)
-fn set_wallpaper_qdbus(monitor_idx: usize, photo_path: &std::path::Path) -> Result<(), PhotoError> {
-    let path_str = photo_path.to_string_lossy();
-    let script = format!(
-        r"var allDesktops = desktops();
-if ({idx} < allDesktops.length) {{
-    d = allDesktops[{idx}];
-    d.wallpaperPlugin = 'org.kde.image';
-    d.currentConfigGroup = Array('Wallpaper', 'org.kde.image', 'General');
-    d.writeConfig('Image', 'file://{path}');
-}}",
-        idx = monitor_idx,
-        path = path_str
+/// Exponential backoff for the given 1-based retry, with up to 50% random jitter
+fn backoff_delay(base: Duration, retry: u32) -> Duration {
+    let backoff = base.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+    let jitter_ms = u64::try_from(backoff.as_millis() / 2).unwrap_or(u64::MAX);
+    let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms));
+    backoff.saturating_add(jitter).min(RETRY_MAX_DELAY)
+}
+
+/// GET `url` with `headers`, retrying network errors, 429, and 5xx responses with backoff.
+///
+/// Other 4xx responses are returned straight away, as is the last response once
+/// retries run out, so callers report HTTP errors as before. Each retry is
+/// logged to `log_path` if given.
+fn get_with_retry(
+    client: &Client,
+    url: &str,
+    headers: &HeaderMap,
+    http: &HttpOptions,
+    log_path: Option<&str>,
+) -> Result<reqwest::blocking::Response, PhotoError> {
+    let mut retry = 0;
+    loop {
+        let (reason, retry_after) = match client.get(url).headers(headers.clone()).send() {
+            Ok(response) if retry < http.retries && is_retryable_status(response.status()) => {
+                let retry_after = response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_retry_after);
+                (format!("HTTP {}", response.status()), retry_after)
+            }
+            Ok(response) => return Ok(response),
+            Err(e) if retry < http.retries && !e.is_builder() => {
+                (PhotoError::from(e).to_string(), None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        retry += 1;
+        let delay = retry_after.map_or_else(
+            || backoff_delay(http.retry_base_delay, retry),
+            |delay| delay.min(RETRY_MAX_DELAY),
+        );
+        warn!(url, retry, %reason, "Retrying request");
+        if let Some(log_path) = log_path {
+            write_log(
+                log_path,
+                &format!(
+                    "Retrying {} in {:.1}s after {} (retry {} of {})",
+                    url,
+                    delay.as_secs_f64(),
+                    reason,
+                    retry,
+                    http.retries
+                ),
+            );
+        }
+        shutdown_flag().check()?;
+        std::thread::sleep(delay);
+    }
+}
+
+// ============================================================================
+// NatGeo Client
+// ============================================================================
+
+/// Browser user agent sent with every request, so National Geographic serves the regular site
+const BROWSER_USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/999.0.0.0 Safari/537.36";
+
+/// Headers a browser sends when loading an HTML page
+fn page_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8",
+        ),
+    );
+    headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("en-US,en;q=0.9"));
+    headers.insert(
+        "Referer",
+        HeaderValue::from_static("https://www.nationalgeographic.com/"),
     );
+    headers
+}
 
-    let output = Command::new("qdbus")
-        .args([
-            "org.kde.plasmashell",
-            "/PlasmaShell",
-            "org.kde.PlasmaShell.evaluateScript",
-            &script,
-        ])
-        .output()
-        .map_err(|e| PhotoError::Command(e.to_string()))?;
+/// Headers a browser sends when loading an image
+fn image_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static(
+            "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8",
+        ),
+    );
+    headers
+}
 
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(PhotoError::Wallpaper(
-            String::from_utf8_lossy(&output.stderr).to_string(),
-        ))
+/// HTTP client with browser-like headers for fetching National Geographic pages and photos.
+///
+/// Build one per run and reuse it: every request shares its connection pool,
+/// so a whole collection downloads over the same few connections.
+#[derive(Debug, Clone)]
+pub struct NatGeoClient {
+    client: Client,
+    http: HttpOptions,
+    log_path: Option<String>,
+}
+
+impl NatGeoClient {
+    /// Create a client using the timeouts, retries, and proxy in `http`
+    pub fn new(http: HttpOptions) -> Result<Self, PhotoError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static(BROWSER_USER_AGENT));
+        let client = build_client(headers, &http)?;
+        Ok(Self {
+            client,
+            http,
+            log_path: None,
+        })
+    }
+
+    /// Log retried page requests and page parsing problems to `log_path`
+    #[must_use]
+    pub fn with_log(mut self, log_path: &str) -> Self {
+        self.log_path = Some(log_path.to_string());
+        self
+    }
+
+    /// The timeouts, retries, and proxy this client was built with
+    pub const fn http(&self) -> &HttpOptions {
+        &self.http
+    }
+
+    /// The HTTP status code of a GET for `url`, without reading the body
+    pub fn status(&self, url: &str) -> Result<u16, PhotoError> {
+        let response = self.client.get(url).headers(page_headers()).send()?;
+        Ok(response.status().as_u16())
+    }
+
+    /// Fetch an HTML page, describing it as `what` if the server refuses
+    fn fetch_page(&self, url: &str, what: &str) -> Result<String, PhotoError> {
+        let response = get_with_retry(
+            &self.client,
+            url,
+            &page_headers(),
+            &self.http,
+            self.log_path.as_deref(),
+        )?;
+
+        // Check the status code (capture it first since we'll consume response later)
+        let status = response.status();
+        if !status.is_success() {
+            debug!(url, %status, "Failed to fetch {}", what);
+            return Err(PhotoError::HttpStatus {
+                status: status.as_u16(),
+                url: url.to_string(),
+            });
+        }
+
+        let body = response.text()?;
+        debug!(url, bytes = body.len(), "Fetched {}", what);
+        Ok(body)
+    }
+
+    /// Fetch the current photo of the day
+    pub fn photo_of_the_day(&self) -> Result<PhotoInfo, PhotoError> {
+        self.photo_of_the_day_from(NATGEO_POD_URL)
+    }
+
+    /// Fetch the photo of the day from a page at `url`, such as a mirror or test server
+    pub fn photo_of_the_day_from(&self, url: &str) -> Result<PhotoInfo, PhotoError> {
+        let body = self.fetch_page(url, "photo of the day page")?;
+        parse_photo_page(&body, self.log_path.as_deref())
+    }
+
+    /// Fetch the photo of the day for `date`. Past dates come from the web archive.
+    pub fn photo_for_date(&self, date: chrono::NaiveDate) -> Result<PhotoInfo, PhotoError> {
+        validate_photo_date(date)?;
+        if date == Local::now().date_naive() {
+            return self.photo_of_the_day();
+        }
+        self.photo_for_date_from(NATGEO_POD_ARCHIVE_URL, date)
+    }
+
+    /// Fetch the photo of the day for `date` from the archive at `archive_url`
+    pub fn photo_for_date_from(
+        &self,
+        archive_url: &str,
+        date: chrono::NaiveDate,
+    ) -> Result<PhotoInfo, PhotoError> {
+        let no_photo =
+            || PhotoError::NoPhotos(format!("No photo of the day archived for {}", date));
+
+        let url = archive_url_for_date(archive_url, date);
+        let response = get_with_retry(
+            &self.client,
+            &url,
+            &page_headers(),
+            &self.http,
+            self.log_path.as_deref(),
+        )?;
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Err(no_photo());
+        }
+        if !status.is_success() {
+            return Err(PhotoError::HttpStatus {
+                status: status.as_u16(),
+                url,
+            });
+        }
+        // The archive redirects to its nearest snapshot, which may be another day's photo
+        if archive_snapshot_date(response.url().as_str()) != Some(date) {
+            return Err(no_photo());
+        }
+        let body = response.text()?;
+        debug!(
+            url,
+            bytes = body.len(),
+            "Fetched archived photo of the day page"
+        );
+        parse_photo_page(&body, self.log_path.as_deref())
+    }
+
+    /// Fetch the photos of a "Best of Photo of the Day" collection page
+    pub fn collection(&self, url: &str) -> Result<PhotoCollection, PhotoError> {
+        self.collection_with_filter(url, &CollectionFilter::default())
+    }
+
+    /// Fetch the images on a collection page that pass `filter`
+    pub fn collection_with_filter(
+        &self,
+        url: &str,
+        filter: &CollectionFilter,
+    ) -> Result<PhotoCollection, PhotoError> {
+        let body = self.fetch_page(url, "collection page")?;
+        parse_collection_page(url, &body, filter, &|image_url| {
+            self.content_length(image_url)
+        })
+    }
+
+    /// Download the photo at `url` to `dest`, a path without an extension.
+    ///
+    /// The extension comes from the response's `Content-Type`, and the download
+    /// is logged next to the photo in `dest` with a `.log` extension.
+    pub fn download(&self, url: &str, dest: &Path) -> Result<DownloadOutcome, PhotoError> {
+        let save_dir = dest.parent().unwrap_or_else(|| Path::new("."));
+        let sanitized_title = dest
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let log_path = save_dir.join(format!("{}.log", sanitized_title));
+        self.download_with_options(
+            url,
+            &save_dir.to_string_lossy(),
+            &sanitized_title,
+            &log_path.to_string_lossy(),
+            &DownloadOptions::default(),
+        )
+    }
+
+    /// Download a photo, applying the post-processing in `options`.
+    /// `options.http` is ignored; the client's own settings apply.
+    pub fn download_with_options(
+        &self,
+        photo_url: &str,
+        save_dir: &str,
+        sanitized_title: &str,
+        log_path: &str,
+        options: &DownloadOptions,
+    ) -> Result<DownloadOutcome, PhotoError> {
+        self.download_photo(
+            photo_url,
+            save_dir,
+            sanitized_title,
+            log_path,
+            options,
+            None,
+        )
+    }
+
+    /// Size the server reports for `url` in response to a HEAD request.
+    /// `None` if the request fails or the response has no `Content-Length`.
+    fn content_length(&self, url: &str) -> Option<u64> {
+        let response = self.client.head(url).headers(page_headers()).send().ok()?;
+        if !response.status().is_success() {
+            return None;
+        }
+        // Read the header directly: reqwest reports a zero-length body for HEAD
+        response
+            .headers()
+            .get(CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
     }
 }
 
-/// Set wallpaper using plasma-apply-wallpaperimage
-fn set_wallpaper_plasma_apply(photo_path: &std::path::Path) -> Result<(), PhotoError> {
-    let output = Command::new("plasma-apply-wallpaperimage")
-        .arg(photo_path)
-        .output()
-        .map_err(|e| PhotoError::Command(e.to_string()))?;
+// Fetch the current "photo of the day" data from the HTML page
+// Note: This is a workaround since the JSON API is now protected
+pub fn get_current_web_natgeo_gallery() -> Result<PhotoInfo, PhotoError> {
+    get_photo_of_the_day_from(NATGEO_POD_URL)
+}
 
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(PhotoError::Wallpaper(
-            String::from_utf8_lossy(&output.stderr).to_string(),
-        ))
+/// Fetch the photo of the day from a page at `url` instead of [`NATGEO_POD_URL`]
+pub fn get_photo_of_the_day_from(url: &str) -> Result<PhotoInfo, PhotoError> {
+    NatGeoClient::new(HttpOptions::default())?.photo_of_the_day_from(url)
+}
+
+/// Fetch the photo of the day for `date`, today's from the live page and earlier ones
+/// from the web archive
+pub fn get_photo_for_date(date: chrono::NaiveDate) -> Result<PhotoInfo, PhotoError> {
+    NatGeoClient::new(HttpOptions::default())?.photo_for_date(date)
+}
+
+/// Reject dates that can't have a photo of the day yet
+pub fn validate_photo_date(date: chrono::NaiveDate) -> Result<(), PhotoError> {
+    if date > Local::now().date_naive() {
+        return Err(PhotoError::InvalidDate(format!(
+            "{} is in the future",
+            date
+        )));
     }
+    Ok(())
 }
 
-/// Set wallpaper using gsettings (GNOME)
-fn set_wallpaper_gnome(photo_path: &std::path::Path) -> Result<(), PhotoError> {
-    let uri = format!("file://{}", photo_path.to_string_lossy());
+/// Archive URL of the photo of the day page as it was on `date`
+pub fn archive_url_for_date(archive_url: &str, date: chrono::NaiveDate) -> String {
+    format!(
+        "{}{}/{}",
+        archive_url,
+        date.format("%Y%m%d"),
+        NATGEO_POD_URL
+    )
+}
 
-    // Set both light and dark mode wallpapers
-    for key in ["picture-uri", "picture-uri-dark"] {
-        let output = Command::new("gsettings")
-            .args(["set", "org.gnome.desktop.background", key, &uri])
-            .output()
-            .map_err(|e| PhotoError::Command(e.to_string()))?;
+/// Day of the snapshot an archive URL points at, from its `/web/YYYYMMDD...` timestamp
+pub fn archive_snapshot_date(url: &str) -> Option<chrono::NaiveDate> {
+    let (_, rest) = url.split_once("/web/")?;
+    let timestamp = rest.get(..8)?;
+    chrono::NaiveDate::parse_from_str(timestamp, "%Y%m%d").ok()
+}
 
-        if !output.status.success() {
-            return Err(PhotoError::Wallpaper(
-                String::from_utf8_lossy(&output.stderr).to_string(),
-            ));
+/// Fetch the photo of the day using `http`, logging retries to `log_path` if given
+pub fn get_current_web_natgeo_gallery_with(
+    http: &HttpOptions,
+    log_path: Option<&str>,
+) -> Result<PhotoInfo, PhotoError> {
+    let mut client = NatGeoClient::new(http.clone())?;
+    if let Some(log_path) = log_path {
+        client = client.with_log(log_path);
+    }
+    client.photo_of_the_day()
+}
+
+/// Trimmed copy of `text`, or `None` if nothing is left
+fn non_empty(text: &str) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Content of the first `<meta property="...">` tag with the given property
+fn meta_property_content(document: &Html, property: &str) -> Option<String> {
+    let selector = Selector::parse(&format!("meta[property=\"{property}\"]")).ok()?;
+    document
+        .select(&selector)
+        .find_map(|element| element.value().attr("content"))
+        .and_then(non_empty)
+}
+
+/// First string value stored under `"key"` in the JSON embedded in `<script>` tags.
+///
+/// Non-string values (objects, `null`) under the same key are skipped.
+fn embedded_json_string(document: &Html, key: &str) -> Option<String> {
+    let selector = Selector::parse("script").ok()?;
+    let needle = format!("\"{key}\"");
+    document.select(&selector).find_map(|script| {
+        let text: String = script.text().collect();
+        text.match_indices(&needle).find_map(|(start, _)| {
+            let value = text[start + needle.len()..]
+                .trim_start()
+                .strip_prefix(':')?
+                .trim_start();
+            serde_json::Deserializer::from_str(value)
+                .into_iter::<String>()
+                .next()?
+                .ok()
+                .as_deref()
+                .and_then(non_empty)
+        })
+    })
+}
+
+/// Suffixes the image CDN adds to filenames for fixed aspect ratio crops
+const CROP_SUFFIXES: &[&str] = &["_16x9", "_3x2", "_4x3", "_2x1", "_2x3", "_3x4", "_square"];
+
+/// One size of an image, with the width the page declares for it, if any
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageVariant {
+    pub url: String,
+    pub width: Option<u32>,
+}
+
+impl ImageVariant {
+    /// A variant whose width comes from the URL's `w` query parameter, if any
+    pub fn from_url(url: &str) -> Self {
+        let width = url.split_once('?').and_then(|(_, query)| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("w=")?.parse().ok())
+        });
+        Self {
+            url: url.to_string(),
+            width,
         }
     }
 
-    Ok(())
+    /// Whether the URL is a fixed aspect ratio crop rather than the full photo
+    fn is_crop(&self) -> bool {
+        let path = self.url.split('?').next().unwrap_or_default();
+        CROP_SUFFIXES.iter().any(|suffix| path.contains(suffix))
+    }
+
+    /// Whether this is the unsized original that `other` is a resized copy of
+    fn is_original_of(&self, other: &Self) -> bool {
+        self.width.is_none()
+            && other.width.is_some()
+            && other.url.split_once('?').map(|(path, _)| path) == Some(self.url.as_str())
+    }
 }
 
-/// Set wallpaper using feh (X11)
-fn set_wallpaper_feh(photo_path: &std::path::Path) -> Result<(), PhotoError> {
-    let output = Command::new("feh")
-        .args(["--bg-scale", &photo_path.to_string_lossy()])
-        .output()
-        .map_err(|e| PhotoError::Command(e.to_string()))?;
+/// The photo a URL shows, ignoring query parameters and crop suffixes,
+/// so every size and crop of one photo share a key
+fn image_photo_key(url: &str) -> String {
+    let path = url.split('?').next().unwrap_or_default();
+    if let Some((stem, ext)) = path.rsplit_once('.') {
+        for suffix in CROP_SUFFIXES {
+            if let Some(base) = stem.strip_suffix(suffix) {
+                return format!("{}.{}", base, ext);
+            }
+        }
+    }
+    path.to_string()
+}
 
-    if output.status.success() {
-        Ok(())
+/// Parse a `srcset` attribute such as `"a.jpg?w=640 640w, a.jpg?w=1280 1280w"`.
+/// Candidates without a width descriptor fall back to their `w` query parameter.
+pub fn parse_srcset(srcset: &str) -> Vec<ImageVariant> {
+    srcset
+        .split(',')
+        .filter_map(|candidate| {
+            let mut parts = candidate.split_whitespace();
+            let mut variant = ImageVariant::from_url(parts.next()?);
+            if let Some(width) = parts
+                .next()
+                .and_then(|descriptor| descriptor.strip_suffix('w'))
+                .and_then(|width| width.parse().ok())
+            {
+                variant.width = Some(width);
+            }
+            Some(variant)
+        })
+        .collect()
+}
+
+/// The variant with the largest declared width, preferring uncropped photos on ties.
+///
+/// Variants without a width only win when none declares one; the first is kept then.
+/// If the unsized URL the winner was resized from is listed, that original is returned
+/// instead, keeping the winner's width as a lower bound.
+pub fn widest_variant(variants: &[ImageVariant]) -> Option<ImageVariant> {
+    let widest = variants
+        .iter()
+        .rev()
+        .max_by_key(|variant| (variant.width, !variant.is_crop()))?;
+    Some(
+        match variants
+            .iter()
+            .find(|variant| variant.is_original_of(widest))
+        {
+            Some(original) => ImageVariant {
+                url: original.url.clone(),
+                width: widest.width,
+            },
+            None => widest.clone(),
+        },
+    )
+}
+
+/// The widest version of `image_url` offered by the page's `img` and `source` elements
+fn widest_page_variant(document: &Html, image_url: &str) -> String {
+    let key = image_photo_key(image_url);
+    let mut variants = vec![ImageVariant::from_url(image_url)];
+    if let Ok(selector) = Selector::parse("img, source") {
+        for element in document.select(&selector) {
+            let element = element.value();
+            if let Some(srcset) = element.attr("srcset") {
+                variants.extend(parse_srcset(srcset));
+            }
+            if let Some(src) = element.attr("src") {
+                variants.push(ImageVariant::from_url(src));
+            }
+        }
+    }
+    variants.retain(|variant| image_photo_key(&variant.url) == key);
+    widest_variant(&variants).map_or_else(|| image_url.to_string(), |variant| variant.url)
+}
+
+/// Photo fields from a page's structured data, ignoring anything unexpected
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct StructuredPhoto {
+    #[serde(deserialize_with = "lenient_string")]
+    content_url: Option<String>,
+    #[serde(deserialize_with = "lenient_string")]
+    caption: Option<String>,
+    #[serde(deserialize_with = "lenient_string")]
+    credit_text: Option<String>,
+}
+
+/// A trimmed, non-empty string, or `None` for any other JSON value
+fn lenient_string<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(text) => non_empty(&text),
+        _ => None,
+    })
+}
+
+/// The first object under `value`, depth first, with a string `contentUrl`
+fn find_content_url_object(value: &serde_json::Value) -> Option<&serde_json::Value> {
+    match value {
+        serde_json::Value::Object(map) => {
+            if map
+                .get("contentUrl")
+                .is_some_and(serde_json::Value::is_string)
+            {
+                return Some(value);
+            }
+            map.values().find_map(find_content_url_object)
+        }
+        serde_json::Value::Array(items) => items.iter().find_map(find_content_url_object),
+        _ => None,
+    }
+}
+
+/// Photo details from the page's JSON-LD or `__NEXT_DATA__` script.
+///
+/// `Ok(None)` if no script describes an image. A script that isn't valid JSON
+/// is skipped, and its error returned if no other script has the photo.
+fn structured_photo(document: &Html) -> Result<Option<StructuredPhoto>, PhotoError> {
+    let Ok(selector) =
+        Selector::parse(r#"script[type="application/ld+json"], script#__NEXT_DATA__"#)
+    else {
+        return Ok(None);
+    };
+
+    let mut first_error = None;
+    for script in document.select(&selector) {
+        let text: String = script.text().collect();
+        let value: serde_json::Value = match serde_json::from_str(&text) {
+            Ok(value) => value,
+            Err(e) => {
+                first_error.get_or_insert(e);
+                continue;
+            }
+        };
+        if let Some(object) = find_content_url_object(&value) {
+            let photo: StructuredPhoto = serde_json::from_value(object.clone())?;
+            if photo.content_url.is_some() {
+                return Ok(Some(photo));
+            }
+        }
+    }
+
+    first_error.map_or(Ok(None), |e| Err(e.into()))
+}
+
+/// Extract the photo URL, title, caption and credit from the photo of the day page.
+///
+/// Structured data comes first: the `contentUrl`, `caption` and `creditText`
+/// of an image in the page's JSON-LD or `__NEXT_DATA__` script. Anything it
+/// lacks comes from the `og:image`, `og:title` and `og:description` meta tags,
+/// read regardless of attribute order or quoting, where the photo URL is the
+/// widest version of `og:image` that the page's images declare. Falls back to
+/// the image filename when the title is missing or meaningless, and to a
+/// `"photographer"` or `"credit"` field in the page's embedded JSON for the
+/// photographer.
+pub fn parse_photo_info_from_html(html: &str) -> Result<PhotoInfo, PhotoError> {
+    parse_photo_page(html, None)
+}
+
+/// Parse the photo of the day page, logging structured data that had to be
+/// skipped to `log_path` if given
+fn parse_photo_page(html: &str, log_path: Option<&str>) -> Result<PhotoInfo, PhotoError> {
+    let document = Html::parse_document(html);
+
+    let structured = structured_photo(&document).unwrap_or_else(|e| {
+        if let Some(log_path) = log_path {
+            write_log(
+                log_path,
+                &format!("Ignoring structured data on photo page: {}", e),
+            );
+        }
+        None
+    });
+    let StructuredPhoto {
+        content_url,
+        caption,
+        credit_text,
+    } = structured.unwrap_or_default();
+
+    let image_url = if let Some(url) = content_url {
+        url
     } else {
-        Err(PhotoError::Wallpaper(
-            String::from_utf8_lossy(&output.stderr).to_string(),
-        ))
+        let og_image = meta_property_content(&document, "og:image").ok_or_else(|| {
+            PhotoError::Parse("Could not extract image URL from page".to_string())
+        })?;
+        widest_page_variant(&document, &og_image)
+    };
+
+    let og_title = meta_property_content(&document, "og:title").unwrap_or_default();
+
+    // Check if title is meaningful (not just "Test" or empty or too short)
+    let title = if og_title.len() < 5 || og_title.to_lowercase() == "test" {
+        // Fall back to extracting filename from image URL
+        image_url
+            .split('?')
+            .next()
+            .and_then(|path| path.split('/').next_back())
+            .and_then(|filename| filename.split('.').next())
+            .unwrap_or("photo-of-the-day")
+            .to_string()
+    } else {
+        og_title
+    };
+
+    let caption = caption.or_else(|| meta_property_content(&document, "og:description"));
+    let photographer = credit_text
+        .or_else(|| embedded_json_string(&document, "photographer"))
+        .or_else(|| embedded_json_string(&document, "credit"));
+
+    debug!(url = image_url, title, "Chose photo of the day image");
+    Ok(PhotoInfo {
+        image_url,
+        title,
+        caption,
+        photographer,
+    })
+}
+
+/// Suffix for a download that hasn't finished yet
+pub const PARTIAL_SUFFIX: &str = ".part";
+
+/// Remove `.part` files left behind for `sanitized_title` by an earlier crash
+fn remove_stale_partials(save_dir: &str, sanitized_title: &str) -> usize {
+    DOWNLOAD_EXTENSIONS
+        .iter()
+        .map(|ext| format!("{}/{}.{}{}", save_dir, sanitized_title, ext, PARTIAL_SUFFIX))
+        .filter(|partial| std::fs::remove_file(partial).is_ok())
+        .count()
+}
+
+/// Format downloads can be converted into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertFormat {
+    Jpeg,
+}
+
+impl ConvertFormat {
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+        }
     }
 }
 
-/// Main wallpaper setting function (uses default photo directory)
-pub fn set_wallpapers(mode: WallpaperMode) -> Result<(), PhotoError> {
-    set_wallpapers_with_options(mode, None, false)
+/// Extensions that `DownloadOptions::convert_to` applies to
+const CONVERTIBLE_EXTENSIONS: &[&str] = &["webp", "avif"];
+
+/// Post-processing applied to downloaded photos
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Re-encode WebP and AVIF downloads into this format
+    pub convert_to: Option<ConvertFormat>,
+    /// Encoder quality (1-100) used when converting
+    pub quality: u8,
+    /// Write the title and credit into the EXIF/XMP of JPEG downloads
+    pub embed_metadata: bool,
+    /// Retry behavior for the download request
+    pub http: HttpOptions,
+    /// Skip photos whose content hash is already recorded in this manifest
+    pub manifest: Option<ManifestConfig>,
 }
 
-/// Main wallpaper setting function with optional custom path (for backwards compatibility)
-pub fn set_wallpapers_with_path(
-    mode: WallpaperMode,
-    path: Option<String>,
-) -> Result<(), PhotoError> {
-    set_wallpapers_with_options(mode, path, false)
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            convert_to: None,
+            quality: TRANSCODE_JPEG_QUALITY,
+            embed_metadata: false,
+            http: HttpOptions::default(),
+            manifest: None,
+        }
+    }
 }
 
-/// Main wallpaper setting function with all options
-#[allow(clippy::too_many_lines, clippy::needless_pass_by_value)]
-pub fn set_wallpapers_with_options(
-    mode: WallpaperMode,
-    path: Option<String>,
-    random: bool,
-) -> Result<(), PhotoError> {
-    let log_path = format!("{}wallpaper.log", expand_tilde(LOG_DIR));
+/// What a download call did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadOutcome {
+    /// The photo was fetched and saved at this path
+    Downloaded(PathBuf),
+    /// A photo with the same title was already on disk at this path
+    AlreadyExists(PathBuf),
+    /// The downloaded bytes matched this photo from the manifest, so nothing was written
+    Duplicate(PathBuf),
+}
 
-    // Ensure log directory exists
-    if let Some(parent) = std::path::Path::new(&log_path).parent() {
-        std::fs::create_dir_all(parent)?;
+/// What a daily download did, as printed by `download --json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadReport {
+    pub path: PathBuf,
+    pub title: String,
+    pub url: String,
+    /// The photo was already on disk, so nothing was written
+    pub skipped: bool,
+}
+
+impl DownloadReport {
+    pub fn new(photo: &PhotoInfo, outcome: &DownloadOutcome) -> Self {
+        Self {
+            path: outcome.path().to_path_buf(),
+            title: photo.title.clone(),
+            url: photo.image_url.clone(),
+            skipped: !matches!(outcome, DownloadOutcome::Downloaded(_)),
+        }
+    }
+}
+
+impl DownloadOutcome {
+    /// Where the photo lives on disk
+    pub fn path(&self) -> &std::path::Path {
+        match self {
+            Self::Downloaded(path) | Self::AlreadyExists(path) | Self::Duplicate(path) => path,
+        }
+    }
+}
+
+// Download the photo of the day and save it to the specified destination
+pub fn download_natgeo_photo_of_the_day(
+    photo_url: &str,       // URL of the photo to download
+    save_dir: &str,        // Directory where the photo will be saved
+    sanitized_title: &str, // Sanitized photo title for the filename
+    log_path: &str,        // Path to log file for this download
+) -> Result<DownloadOutcome, PhotoError> {
+    download_natgeo_photo_of_the_day_with_options(
+        photo_url,
+        save_dir,
+        sanitized_title,
+        log_path,
+        &DownloadOptions::default(),
+    )
+}
+
+/// Download a photo, applying the post-processing in `options`
+pub fn download_natgeo_photo_of_the_day_with_options(
+    photo_url: &str,
+    save_dir: &str,
+    sanitized_title: &str,
+    log_path: &str,
+    options: &DownloadOptions,
+) -> Result<DownloadOutcome, PhotoError> {
+    NatGeoClient::new(options.http.clone())?.download_with_options(
+        photo_url,
+        save_dir,
+        sanitized_title,
+        log_path,
+        options,
+    )
+}
+
+/// Called with the bytes received so far and the expected total, if known
+type ByteProgress<'a> = &'a dyn Fn(u64, Option<u64>);
+
+/// Writer that reports how many bytes have passed through it
+struct ProgressWriter<'a, W> {
+    inner: W,
+    written: u64,
+    total: Option<u64>,
+    on_bytes: ByteProgress<'a>,
+}
+
+impl<W: Write> Write for ProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        (self.on_bytes)(self.written, self.total);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl NatGeoClient {
+    /// Download a photo, reporting bytes received to `on_bytes` if given
+    fn download_photo(
+        &self,
+        photo_url: &str,
+        save_dir: &str,
+        sanitized_title: &str,
+        log_path: &str,
+        options: &DownloadOptions,
+        on_bytes: Option<ByteProgress>,
+    ) -> Result<DownloadOutcome, PhotoError> {
+        info!(
+            url = photo_url,
+            title = sanitized_title,
+            "Starting download"
+        );
+        let result = self.save_photo(
+            photo_url,
+            save_dir,
+            sanitized_title,
+            log_path,
+            options,
+            on_bytes,
+        );
+        match &result {
+            Ok(DownloadOutcome::Downloaded(path)) => {
+                info!(path = %path.display(), "Photo saved");
+            }
+            Ok(DownloadOutcome::AlreadyExists(path) | DownloadOutcome::Duplicate(path)) => {
+                info!(path = %path.display(), "Photo already saved, skipping");
+            }
+            Err(e) => warn!(url = photo_url, error = %e, "Download failed"),
+        }
+        result
+    }
+
+    /// The work of [`Self::download_photo`]
+    #[allow(clippy::too_many_lines)]
+    fn save_photo(
+        &self,
+        photo_url: &str,
+        save_dir: &str,
+        sanitized_title: &str,
+        log_path: &str,
+        options: &DownloadOptions,
+        on_bytes: Option<ByteProgress>,
+    ) -> Result<DownloadOutcome, PhotoError> {
+        // A different photo may already be saved under this title
+        let unique = unique_title(save_dir, sanitized_title, photo_url);
+        if unique != sanitized_title {
+            write_log(
+                log_path,
+                &format!(
+                    "A different photo is already saved as {}, saving as {}",
+                    sanitized_title, unique
+                ),
+            );
+        }
+        let sanitized_title = unique.as_str();
+
+        // Check if photo already exists under any supported extension
+        if let Some(path) = find_downloaded_file(save_dir, sanitized_title) {
+            write_log(
+                log_path,
+                &format!("Photo already exists: {}", path.display()),
+            );
+            return Ok(DownloadOutcome::AlreadyExists(path));
+        }
+
+        // Make the full URL request to download the image
+        let mut response = get_with_retry(
+            &self.client,
+            photo_url,
+            &image_headers(),
+            &self.http,
+            Some(log_path),
+        )?;
+
+        // Ensure the response is successful
+        if !response.status().is_success() {
+            return Err(PhotoError::HttpStatus {
+                status: response.status().as_u16(),
+                url: photo_url.to_string(),
+            });
+        }
+
+        // Get the content type to determine the file extension (jpg or png)
+        let content_type = response
+            .headers()
+            .get("Content-Type")
+            .and_then(|val| val.to_str().ok())
+            .unwrap_or_default();
+
+        // Get the file extension based on the content type
+        let file_extension = match get_extension_from_content_type(content_type) {
+            Ok(ext) => ext,
+            Err(_) => "jpg".to_string(), // Default to .jpg if content type isn't recognized
+        };
+
+        // Create the filename using the sanitized title
+        let photo_filename = format!("{}/{}.{}", save_dir, sanitized_title, file_extension);
+
+        // Write to a .part file and rename once complete, so a failed or interrupted
+        // download never leaves a truncated photo under the final name
+        let removed = remove_stale_partials(save_dir, sanitized_title);
+        if removed > 0 {
+            write_log(
+                log_path,
+                &format!("Removed {} leftover partial download(s)", removed),
+            );
+        }
+        let partial_filename = format!("{}{}", photo_filename, PARTIAL_SUFFIX);
+        let in_progress = InProgressFile::track(&partial_filename);
+        let mut file = File::create(&partial_filename)?;
+
+        // Stream the image to disk in chunks rather than buffering it in memory
+        match on_bytes {
+            Some(on_bytes) => {
+                let total = response.content_length();
+                let mut writer = ProgressWriter {
+                    inner: &mut file,
+                    written: 0,
+                    total,
+                    on_bytes,
+                };
+                response.copy_to(&mut writer)?;
+            }
+            None => {
+                response.copy_to(&mut file)?;
+            }
+        }
+        file.sync_all()?;
+        drop(file);
+
+        // Reject error pages and truncated files before they get a photo's name
+        if let Err(e) = validate_image(std::path::Path::new(&partial_filename)) {
+            write_log(
+                log_path,
+                &format!("Rejected download from {}: {}", photo_url, e),
+            );
+            return Err(e);
+        }
+
+        // Reruns land in a new date directory under the same title, so compare content
+        let content_hash = match &options.manifest {
+            Some(manifest) => Some((manifest, hash_file(Path::new(&partial_filename))?)),
+            None => None,
+        };
+        if let Some((manifest, hash)) = &content_hash {
+            if let Some(existing) = manifest.find(hash, log_path) {
+                drop(in_progress);
+                write_log(
+                    log_path,
+                    &format!(
+                        "Skipped {}: same photo as {}",
+                        photo_url,
+                        existing.display()
+                    ),
+                );
+                return Ok(DownloadOutcome::Duplicate(existing));
+            }
+        }
+
+        std::fs::rename(&partial_filename, &photo_filename)?;
+        in_progress.finish();
+
+        write_log(
+            log_path,
+            &format!("Downloaded photo: {} from {}", photo_filename, photo_url),
+        );
+
+        let mut photo_path = PathBuf::from(photo_filename);
+        if let Some(format) = options
+            .convert_to
+            .filter(|_| CONVERTIBLE_EXTENSIONS.contains(&file_extension.as_str()))
+        {
+            let converted = convert_image(&photo_path, format, options.quality)?;
+            write_log(
+                log_path,
+                &format!(
+                    "Converted {} to {}",
+                    photo_path.display(),
+                    converted.display()
+                ),
+            );
+            photo_path = converted;
+        }
+
+        if let Some((manifest, hash)) = content_hash {
+            manifest.record(hash, &photo_path, log_path);
+        }
+        Ok(DownloadOutcome::Downloaded(photo_path))
+    }
+}
+
+// ============================================================================
+// Photo Metadata
+// ============================================================================
+
+/// What a downloaded photo was, saved as `{title}.json` next to the image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhotoMetadata {
+    pub url: String,
+    pub title: String,
+    /// RFC 3339 timestamp of when the download finished
+    pub downloaded_at: String,
+    /// MIME type of the saved file
+    pub content_type: String,
+    pub file_size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub photographer: Option<String>,
+    /// Average luminance from 0 (black) to 255 (white), cached by [`photo_brightness`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<u8>,
+    /// Pixel dimensions of the saved file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+impl PhotoMetadata {
+    /// Describe the photo `info` that was just saved at `photo_path`
+    pub fn for_download(info: &PhotoInfo, photo_path: &Path) -> Result<Self, PhotoError> {
+        let dimensions = probe_dimensions(photo_path).ok();
+        Ok(Self {
+            url: info.image_url.clone(),
+            title: info.title.clone(),
+            downloaded_at: Local::now().to_rfc3339(),
+            content_type: content_type_for_path(photo_path).to_string(),
+            file_size: std::fs::metadata(photo_path)?.len(),
+            caption: info.caption.clone(),
+            photographer: info.photographer.clone(),
+            brightness: None,
+            width: dimensions.map(|(w, _)| w),
+            height: dimensions.map(|(_, h)| h),
+        })
+    }
+}
+
+/// MIME type for a photo file, based on its extension
+pub fn content_type_for_path(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    match ext.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "tiff" => "image/tiff",
+        "jxl" => "image/jxl",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Sidecar metadata path for a photo (`title.jpg` -> `title.json`)
+pub fn metadata_path_for(photo_path: &Path) -> PathBuf {
+    photo_path.with_extension("json")
+}
+
+/// Write photo metadata as pretty-printed JSON
+pub fn write_metadata(path: &Path, metadata: &PhotoMetadata) -> Result<(), PhotoError> {
+    let json = serde_json::to_string_pretty(metadata)?;
+    std::fs::write(path, json + "\n")?;
+    Ok(())
+}
+
+/// Read a metadata sidecar written by [`write_metadata`]
+pub fn read_metadata(path: &Path) -> Result<PhotoMetadata, PhotoError> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+/// Record what a fresh download was: embed it into the JPEG if requested, then
+/// write the metadata sidecar.
+///
+/// Failures are logged rather than returned, since the photo itself is fine.
+pub fn record_photo_metadata(
+    info: &PhotoInfo,
+    photo_path: &Path,
+    options: &DownloadOptions,
+    log_path: &str,
+) {
+    if options.embed_metadata && is_jpeg(photo_path) {
+        match embed_metadata(photo_path, info) {
+            Ok(()) => write_log(
+                log_path,
+                &format!("Embedded metadata into {}", photo_path.display()),
+            ),
+            Err(e) => write_log(
+                log_path,
+                &format!(
+                    "Failed to embed metadata into {}: {}",
+                    photo_path.display(),
+                    e
+                ),
+            ),
+        }
+    }
+
+    let sidecar = metadata_path_for(photo_path);
+    let result = PhotoMetadata::for_download(info, photo_path)
+        .and_then(|metadata| write_metadata(&sidecar, &metadata));
+    if let Err(e) = result {
+        write_log(
+            log_path,
+            &format!("Failed to write metadata {}: {}", sidecar.display(), e),
+        );
+    }
+}
+
+/// Signature that starts an EXIF APP1 segment in a JPEG
+const EXIF_APP1_PREFIX: &[u8] = b"Exif\0\0";
+
+/// Signature that starts an XMP APP1 segment in a JPEG
+const XMP_APP1_PREFIX: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+fn is_jpeg(path: &Path) -> bool {
+    has_extension(path, "jpg") || has_extension(path, "jpeg")
+}
+
+/// Write the title, caption and photographer into a JPEG's EXIF and XMP.
+///
+/// EXIF gets `ImageDescription` (the caption, or the title without one) and
+/// `Artist`; other EXIF fields are kept. XMP gets `dc:title`, `dc:description`
+/// and `dc:creator`, replacing any existing XMP packet. The image data is not
+/// re-encoded. The result is written to a `.part` copy and validated before
+/// it replaces the original, so a failure leaves the photo untouched.
+pub fn embed_metadata(path: &Path, info: &PhotoInfo) -> Result<(), PhotoError> {
+    let original = std::fs::read(path)?;
+    let mut jpeg = Jpeg::from_bytes(original.into())
+        .map_err(|e| PhotoError::CorruptImage(format!("{}: {}", path.display(), e)))?;
+
+    let exif = build_exif(jpeg.exif().as_deref(), info)?;
+    let xmp = build_xmp(info);
+
+    let segments = jpeg.segments_mut();
+    segments.retain(|segment| {
+        segment.marker() != markers::APP1
+            || !(segment.contents().starts_with(EXIF_APP1_PREFIX)
+                || segment.contents().starts_with(XMP_APP1_PREFIX))
+    });
+    // EXIF must come right after SOI, or after a JFIF APP0 segment
+    let at = segments
+        .iter()
+        .take_while(|segment| segment.marker() == markers::APP0)
+        .count();
+    for payload in [
+        [XMP_APP1_PREFIX, xmp.as_bytes()].concat(),
+        [EXIF_APP1_PREFIX, &exif].concat(),
+    ] {
+        if payload.len() > usize::from(u16::MAX) - 2 {
+            return Err(PhotoError::Metadata(
+                "Metadata too large for a JPEG segment".to_string(),
+            ));
+        }
+        segments.insert(
+            at,
+            JpegSegment::new_with_contents(markers::APP1, payload.into()),
+        );
+    }
+
+    let partial = PathBuf::from(format!("{}{}", path.display(), PARTIAL_SUFFIX));
+    let in_progress = InProgressFile::track(&partial);
+    let mut file = io::BufWriter::new(File::create(&partial)?);
+    jpeg.encoder().write_to(&mut file)?;
+    file.into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .sync_all()?;
+    validate_image(&partial)?;
+    std::fs::rename(&partial, path)?;
+    in_progress.finish();
+    Ok(())
+}
+
+/// EXIF (TIFF) block with the photo's description and artist, keeping any
+/// other fields from `existing`
+fn build_exif(existing: Option<&[u8]>, info: &PhotoInfo) -> Result<Vec<u8>, PhotoError> {
+    let mut fields: Vec<exif::Field> = existing
+        .and_then(|raw| exif::Reader::new().read_raw(raw.to_vec()).ok())
+        .map(|existing| {
+            existing
+                .fields()
+                .filter(|field| field.ifd_num == exif::In::PRIMARY)
+                .filter(|field| {
+                    !matches!(field.tag, exif::Tag::ImageDescription | exif::Tag::Artist)
+                })
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let ascii = |tag, text: &str| exif::Field {
+        tag,
+        ifd_num: exif::In::PRIMARY,
+        value: exif::Value::Ascii(vec![text.as_bytes().to_vec()]),
+    };
+    let description = info.caption.as_deref().unwrap_or(&info.title);
+    fields.push(ascii(exif::Tag::ImageDescription, description));
+    if let Some(photographer) = &info.photographer {
+        fields.push(ascii(exif::Tag::Artist, photographer));
+    }
+
+    let mut writer = exif::experimental::Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut buf = io::Cursor::new(Vec::new());
+    writer
+        .write(&mut buf, false)
+        .map_err(|e| PhotoError::Metadata(e.to_string()))?;
+    Ok(buf.into_inner())
+}
+
+/// XMP packet with the photo's title, caption and photographer
+fn build_xmp(info: &PhotoInfo) -> String {
+    let mut writer = XmpWriter::new();
+    writer.title([(None, info.title.as_str())]);
+    if let Some(caption) = &info.caption {
+        writer.description([(None, caption.as_str())]);
+    }
+    if let Some(photographer) = &info.photographer {
+        writer.creator([photographer.as_str()]);
+    }
+    writer.finish(None)
+}
+
+// ============================================================================
+// Cancellation
+// ============================================================================
+
+/// Exit code for a run stopped by Ctrl-C or SIGTERM (128 + SIGINT)
+pub const INTERRUPTED_EXIT_CODE: u8 = 130;
+
+/// Shared flag raised when the user asks the program to stop.
+/// Long-running loops check it at safe points and bail out with `PhotoError::Interrupted`.
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Safe point: return `PhotoError::Interrupted` if cancellation was requested
+    pub fn check(&self) -> Result<(), PhotoError> {
+        if self.is_cancelled() {
+            Err(PhotoError::Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Process-wide flag raised by the Ctrl-C/SIGTERM handler
+pub fn shutdown_flag() -> &'static CancelFlag {
+    static FLAG: OnceLock<CancelFlag> = OnceLock::new();
+    FLAG.get_or_init(CancelFlag::new)
+}
+
+/// Install the Ctrl-C/SIGTERM handler.
+/// The first signal raises [`shutdown_flag`] so the current step can finish cleanly;
+/// a second one removes files still being written and exits immediately.
+pub fn install_interrupt_handler() -> Result<(), PhotoError> {
+    let flag = shutdown_flag().clone();
+    ctrlc::set_handler(move || {
+        if flag.is_cancelled() {
+            remove_in_progress_files();
+            std::process::exit(i32::from(INTERRUPTED_EXIT_CODE));
+        }
+        eprintln!("\nInterrupted, stopping after the current step (press Ctrl-C again to force)");
+        flag.cancel();
+    })
+    .map_err(|e| PhotoError::Command(format!("Failed to install interrupt handler: {}", e)))
+}
+
+/// Files currently being written
+fn in_progress_files() -> &'static Mutex<Vec<PathBuf>> {
+    static FILES: OnceLock<Mutex<Vec<PathBuf>>> = OnceLock::new();
+    FILES.get_or_init(Mutex::default)
+}
+
+/// Guard for a file being written: it is deleted on drop unless `finish` is called,
+/// so an error or interruption mid-write never leaves a truncated photo behind
+#[derive(Debug)]
+pub struct InProgressFile {
+    path: PathBuf,
+    finished: bool,
+}
+
+impl InProgressFile {
+    pub fn track(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        if let Ok(mut files) = in_progress_files().lock() {
+            files.push(path.clone());
+        }
+        Self {
+            path,
+            finished: false,
+        }
+    }
+
+    /// Mark the file as complete so it is kept
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for InProgressFile {
+    fn drop(&mut self) {
+        if let Ok(mut files) = in_progress_files().lock() {
+            files.retain(|p| p != &self.path);
+        }
+        if !self.finished {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Delete every file still being written, returning how many were removed
+pub fn remove_in_progress_files() -> usize {
+    in_progress_files().lock().map_or(0, |files| {
+        files
+            .iter()
+            .filter(|p| std::fs::remove_file(p).is_ok())
+            .count()
+    })
+}
+
+// ============================================================================
+// Collection Scraping Functions
+// ============================================================================
+
+/// Extract the collection name from a URL like "best-photos-october-2018"
+pub fn extract_collection_name_from_url(url: &str) -> String {
+    url.split('/')
+        .next_back()
+        .unwrap_or("collection")
+        .to_string()
+}
+
+/// Minimum file size in bytes to keep (skip small thumbnails/icons)
+const MIN_PHOTO_SIZE_BYTES: u64 = 50_000; // 50KB
+
+/// Options for downloading a whole collection
+#[derive(Debug, Clone)]
+pub struct CollectionDownloadOptions {
+    /// Post-processing applied to each photo
+    pub download: DownloadOptions,
+    /// Photos smaller than this many bytes are thumbnails and are skipped
+    pub min_photo_size: u64,
+}
+
+impl Default for CollectionDownloadOptions {
+    fn default() -> Self {
+        Self {
+            download: DownloadOptions::default(),
+            min_photo_size: MIN_PHOTO_SIZE_BYTES,
+        }
+    }
+}
+
+/// Filename pattern of "Best of Photo of the Day" collection photos.
+/// Matches names like `01-best-pod-october-18`, `02_best-pod-july-18`, `best_pod_landscapes`
+pub const DEFAULT_COLLECTION_PATTERN: &str = "best-pod";
+
+/// Which images on a collection page are the collection's photos
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollectionFilter {
+    /// Filenames containing this text, ignoring case and treating `-` and `_` alike
+    Pattern(String),
+    /// Every image on the page
+    None,
+}
+
+impl Default for CollectionFilter {
+    fn default() -> Self {
+        Self::Pattern(DEFAULT_COLLECTION_PATTERN.to_string())
+    }
+}
+
+impl CollectionFilter {
+    /// Whether a photo with this filename (without extension) belongs to the collection
+    pub fn matches(&self, filename: &str) -> bool {
+        let normalize = |text: &str| text.to_lowercase().replace('_', "-");
+        match self {
+            Self::Pattern(pattern) => normalize(filename).contains(&normalize(pattern)),
+            Self::None => true,
+        }
+    }
+}
+
+/// Extract every image from i.natgeofe.com in the HTML body.
+///
+/// Sizes of the same file, from `srcset` width descriptors or `w` query
+/// parameters, are grouped and the widest is kept, in order of first appearance.
+fn extract_natgeo_image_variants(body: &str) -> Vec<ImageVariant> {
+    let mut groups: Vec<Vec<ImageVariant>> = Vec::new();
+    let mut group_index: std::collections::HashMap<String, usize> =
+        std::collections::HashMap::new();
+
+    // Look for patterns like "https://i.natgeofe.com/n/UUID/filename.jpg"
+    // These appear in various contexts: img src, srcset, JSON data, meta tags
+    for part in body.split("https://i.natgeofe.com/n/").skip(1) {
+        // Extract the URL until we hit a quote, space, or other delimiter
+        let url_end = part
+            .find(['"', '\'', ' ', ',', '\\', '<', '>'])
+            .unwrap_or(part.len());
+        let tail = part[..url_end].replace("&amp;", "&");
+        let path = tail.split('?').next().unwrap_or_default();
+
+        // Only include if it looks like a valid image path (has UUID and extension)
+        // We use to_lowercase() so the ends_with checks are already case-insensitive
+        let path_lower = path.to_lowercase();
+        #[allow(clippy::case_sensitive_file_extension_comparisons)]
+        let has_image_ext = path_lower.ends_with(".jpg")
+            || path_lower.ends_with(".png")
+            || path_lower.ends_with(".gif");
+        // Skip crop variants (e.g., _16x9.jpg, _3x2.jpg) - we want the raw images
+        let is_crop_variant = CROP_SUFFIXES.iter().any(|suffix| path.contains(suffix));
+        if !path.contains('/') || !has_image_ext || is_crop_variant {
+            continue;
+        }
+
+        // A srcset entry is followed by its width, e.g. "... 1280w"
+        let mut variant = ImageVariant::from_url(&format!("https://i.natgeofe.com/n/{}", tail));
+        if let Some(width) = part[url_end..]
+            .strip_prefix(' ')
+            .and_then(|rest| rest.split([',', ' ', '"', '\'']).next())
+            .and_then(|descriptor| descriptor.strip_suffix('w'))
+            .and_then(|width| width.parse().ok())
+        {
+            variant.width = Some(width);
+        }
+
+        let key = format!("https://i.natgeofe.com/n/{}", path);
+        let index = *group_index.entry(key).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[index].push(variant);
+    }
+
+    groups
+        .iter()
+        .filter_map(|variants| widest_variant(variants))
+        .collect()
+}
+
+/// The UUID directory of an image CDN URL such as `https://i.natgeofe.com/n/{uuid}/photo.jpg`
+fn natgeo_image_uuid(url: &str) -> Option<&str> {
+    url.split_once("/n/")?
+        .1
+        .split('/')
+        .next()
+        .filter(|uuid| !uuid.is_empty())
+}
+
+/// Group `urls` by the CDN UUID in their path, in order of first appearance.
+/// The CDN serves one photo under several filenames within the same UUID.
+/// URLs without a UUID each form their own group.
+pub fn group_urls_by_uuid(urls: &[String]) -> Vec<Vec<String>> {
+    group_by_uuid(urls.to_vec(), String::as_str)
+}
+
+fn group_by_uuid<T>(items: Vec<T>, url_of: fn(&T) -> &str) -> Vec<Vec<T>> {
+    let mut groups: Vec<Vec<T>> = Vec::new();
+    let mut group_index: std::collections::HashMap<String, usize> =
+        std::collections::HashMap::new();
+
+    for item in items {
+        let url = url_of(&item);
+        let key = natgeo_image_uuid(url).unwrap_or(url).to_string();
+        if let Some(&index) = group_index.get(&key) {
+            groups[index].push(item);
+        } else {
+            group_index.insert(key, groups.len());
+            groups.push(vec![item]);
+        }
+    }
+    groups
+}
+
+/// Keep the largest of several images of one photo: the widest declared, or
+/// failing that the one whose `Content-Length` is biggest. The first caption
+/// in the group is kept.
+fn largest_of_group(
+    group: Vec<(ImageVariant, Option<String>)>,
+    content_length: &dyn Fn(&str) -> Option<u64>,
+) -> Option<(ImageVariant, Option<String>)> {
+    let caption = group.iter().find_map(|(_, caption)| caption.clone());
+    let variants: Vec<ImageVariant> = group.into_iter().map(|(variant, _)| variant).collect();
+
+    let chosen = if variants.len() > 1 && variants.iter().all(|variant| variant.width.is_none()) {
+        // Iterate in reverse so the first variant wins ties
+        variants
+            .iter()
+            .rev()
+            .max_by_key(|variant| content_length(&variant.url))
+            .cloned()
+    } else {
+        widest_variant(&variants)
+    };
+    chosen.map(|variant| (variant, caption))
+}
+
+/// The photos in a collection article's `<figure>` elements, in page order:
+/// each image's URL without its query string, and the figure's caption
+fn collection_figures(body: &str) -> Vec<(String, Option<String>)> {
+    let document = Html::parse_document(body);
+    let (Ok(figures), Ok(images), Ok(captions)) = (
+        Selector::parse("figure"),
+        Selector::parse("img, source"),
+        Selector::parse("figcaption"),
+    ) else {
+        return Vec::new();
+    };
+
+    document
+        .select(&figures)
+        .filter_map(|figure| {
+            let image_url = figure.select(&images).find_map(|image| {
+                let element = image.value();
+                element
+                    .attr("src")
+                    .into_iter()
+                    .chain(
+                        element
+                            .attr("srcset")
+                            .into_iter()
+                            .flat_map(|srcset| srcset.split(',')),
+                    )
+                    .filter_map(|candidate| candidate.split_whitespace().next())
+                    .find(|url| url.starts_with("https://i.natgeofe.com/n/"))
+            })?;
+            let caption = figure.select(&captions).next().and_then(|caption| {
+                let text: Vec<&str> = caption.text().flat_map(str::split_whitespace).collect();
+                non_empty(&text.join(" "))
+            });
+            Some((
+                image_url.split('?').next().unwrap_or_default().to_string(),
+                caption,
+            ))
+        })
+        .collect()
+}
+
+/// Put `images` in the order of `figures`, pairing each with its caption.
+/// Images outside any figure follow in their original order, without a caption.
+fn order_by_figures(
+    images: Vec<ImageVariant>,
+    figures: &[(String, Option<String>)],
+) -> Vec<(ImageVariant, Option<String>)> {
+    let mut remaining: Vec<Option<ImageVariant>> = images.into_iter().map(Some).collect();
+    let mut ordered = Vec::with_capacity(remaining.len());
+
+    for (figure_path, caption) in figures {
+        let found = remaining.iter_mut().find(|image| {
+            image
+                .as_ref()
+                .is_some_and(|image| image.url.split('?').next() == Some(figure_path.as_str()))
+        });
+        if let Some(image) = found.and_then(Option::take) {
+            ordered.push((image, caption.clone()));
+        }
+    }
+    ordered.extend(remaining.into_iter().flatten().map(|image| (image, None)));
+    ordered
+}
+
+/// Filename of an image URL without its extension or query string
+fn image_title(image_url: &str) -> String {
+    image_url
+        .split('?')
+        .next()
+        .and_then(|path| path.split('/').next_back())
+        .and_then(|filename| filename.split('.').next())
+        .unwrap_or("photo")
+        .to_string()
+}
+
+/// Fetch photos from a "Best of Photo of the Day" collection page.
+/// `url` can point anywhere serving the same markup, such as a mirror.
+pub fn get_collection_photos(url: &str) -> Result<PhotoCollection, PhotoError> {
+    get_collection_photos_with(url, &HttpOptions::default(), &CollectionFilter::default())
+}
+
+/// Fetch a collection page using the timeouts and retries in `http`,
+/// keeping the images that pass `filter`
+pub fn get_collection_photos_with(
+    url: &str,
+    http: &HttpOptions,
+    filter: &CollectionFilter,
+) -> Result<PhotoCollection, PhotoError> {
+    NatGeoClient::new(http.clone())?.collection_with_filter(url, filter)
+}
+
+/// Extract a collection's name and the photos passing `filter` from its page, fetched from `url`.
+/// Copies of one photo are reduced to the largest, asking `content_length`
+/// for sizes when the page declares no widths.
+fn parse_collection_page(
+    url: &str,
+    body: &str,
+    filter: &CollectionFilter,
+    content_length: &dyn Fn(&str) -> Option<u64>,
+) -> Result<PhotoCollection, PhotoError> {
+    // Extract collection name from og:title or URL
+    let name = body
+        .split("property=\"og:title\"")
+        .nth(1)
+        .and_then(|s| s.split("content=\"").nth(1))
+        .and_then(|s| s.split('"').next())
+        .filter(|s| !s.is_empty() && s.len() >= 5)
+        .map_or_else(|| extract_collection_name_from_url(url), String::from);
+
+    // Extract all images, in the order the article shows them
+    let images = order_by_figures(
+        extract_natgeo_image_variants(body),
+        &collection_figures(body),
+    );
+
+    if images.is_empty() {
+        return Err(PhotoError::NoPhotos(format!(
+            "No photos found in collection: {}",
+            url
+        )));
+    }
+
+    // Filter to only include photos that match the collection's naming pattern
+    let found = images.len();
+    let matching: Vec<(ImageVariant, Option<String>)> = images
+        .into_iter()
+        .filter(|(image, _)| filter.matches(&image_title(&image.url)))
+        .collect();
+
+    // Keep one copy of each photo, and create PhotoInfo using its filename as title
+    let photos: Vec<PhotoInfo> = group_by_uuid(matching, |(image, _)| image.url.as_str())
+        .into_iter()
+        .filter_map(|group| largest_of_group(group, content_length))
+        .map(|(image, caption)| PhotoInfo {
+            title: image_title(&image.url),
+            image_url: image.url,
+            caption,
+            photographer: None,
+        })
+        .collect();
+
+    if photos.is_empty() {
+        let pattern = match filter {
+            CollectionFilter::Pattern(pattern) => pattern.as_str(),
+            CollectionFilter::None => "",
+        };
+        return Err(PhotoError::NoPhotos(format!(
+            "No collection photos found (matching '{}' pattern) in: {}. \
+             The page has {} other image(s); use --no-filter to download them all",
+            pattern, url, found
+        )));
+    }
+
+    Ok(PhotoCollection { name, photos })
+}
+
+/// Progress of a collection download, passed to the `progress` callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionProgress<'a> {
+    /// Work on a photo is starting
+    Started {
+        title: &'a str,
+        /// 0-based position in the collection
+        index: usize,
+        total: usize,
+    },
+    /// Bytes received so far for the photo being downloaded
+    Transfer {
+        title: &'a str,
+        bytes: u64,
+        total_bytes: Option<u64>,
+    },
+    /// A photo was downloaded, skipped, or failed; sent once per photo
+    Finished {
+        title: &'a str,
+        completed: usize,
+        total: usize,
+        downloaded: usize,
+        skipped: usize,
+        failed: usize,
+    },
+}
+
+/// How a single collection photo ended up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PhotoStatus {
+    Downloaded,
+    Skipped,
+    Failed,
+}
+
+/// A collection photo and how it ended up
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionPhotoResult {
+    pub title: String,
+    pub status: PhotoStatus,
+}
+
+/// Download result for a collection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollectionDownloadResult {
+    pub downloaded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Each photo in collection order
+    pub photos: Vec<CollectionPhotoResult>,
+}
+
+/// Filename stem for the `index`th (0-based) of `total` collection photos.
+///
+/// Captioned photos are named `NN-{caption}`, zero-padded so listings follow the
+/// article's order. Photos without a caption keep their title as the name.
+pub fn collection_photo_stem(index: usize, total: usize, photo: &PhotoInfo) -> String {
+    sanitize_title(&collection_photo_name(index, total, photo))
+}
+
+/// Unsanitized name for a collection photo, see `collection_photo_stem`
+fn collection_photo_name(index: usize, total: usize, photo: &PhotoInfo) -> String {
+    match &photo.caption {
+        Some(caption) => {
+            let width = total.to_string().len().max(2);
+            let caption = caption.trim().trim_end_matches('.');
+            format!("{:0width$}-{}", index + 1, caption)
+        }
+        None => photo.title.clone(),
+    }
+}
+
+/// Find a downloaded file by its sanitized title under any supported extension
+fn find_downloaded_file(dir: &str, sanitized_title: &str) -> Option<std::path::PathBuf> {
+    for ext in DOWNLOAD_EXTENSIONS {
+        let path = std::path::PathBuf::from(format!("{}/{}.{}", dir, sanitized_title, ext));
+        if path.exists() {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Whether the photo saved at `path` was downloaded from `url`.
+///
+/// Photos without a readable sidecar, such as those saved by earlier versions,
+/// are assumed to match so they are never fetched again.
+fn is_same_photo(path: &Path, url: &str) -> bool {
+    read_metadata(&metadata_path_for(path)).map_or(true, |metadata| {
+        image_photo_key(&metadata.url) == image_photo_key(url)
+    })
+}
+
+/// Stem to save the photo at `url` under in `dir`, starting from `sanitized_title`.
+///
+/// A file already saved under a name is only the same photo if its sidecar
+/// records the same URL. Different photos that sanitize to the same title get
+/// `_2`, `_3`, ... until a free name or the same photo turns up.
+pub fn unique_title(dir: &str, sanitized_title: &str, url: &str) -> String {
+    let mut candidate = sanitized_title.to_string();
+    for n in 2.. {
+        match find_downloaded_file(dir, &candidate) {
+            Some(path) if !is_same_photo(&path, url) => {
+                candidate = format!("{}_{}", sanitized_title, n);
+            }
+            _ => break,
+        }
+    }
+    candidate
+}
+
+/// Download all photos from a collection
+pub fn download_collection(
+    collection: &PhotoCollection,
+    collection_name: &str,
+    options: &CollectionDownloadOptions,
+    progress: Option<&dyn Fn(CollectionProgress)>,
+) -> Result<CollectionDownloadResult, PhotoError> {
+    let base_dir = collection_dir()?;
+    let save_dir = format!("{}{}", base_dir, collection_name);
+
+    // Create the collection directory
+    ensure_managed_dir(&photo_dir()?)?;
+    ensure_managed_dir(&base_dir)?;
+
+    download_collection_into(collection, &save_dir, options, shutdown_flag(), progress)
+}
+
+/// Download all photos from a collection into `save_dir`.
+///
+/// Photos whose `Content-Length` is below `options.min_photo_size` are skipped
+/// without being fetched; when the server doesn't report a size, the file is
+/// checked after downloading instead. Stops between photos with
+/// `PhotoError::Interrupted` once `cancel` is raised. `progress`, if given, is
+/// told about each photo as it starts, transfers, and finishes.
+pub fn download_collection_into(
+    collection: &PhotoCollection,
+    save_dir: &str,
+    options: &CollectionDownloadOptions,
+    cancel: &CancelFlag,
+    progress: Option<&dyn Fn(CollectionProgress)>,
+) -> Result<CollectionDownloadResult, PhotoError> {
+    std::fs::create_dir_all(save_dir)?;
+
+    let log_path = format!("{}/collection.log", save_dir);
+    write_log(
+        &log_path,
+        &format!("Starting download of collection: {}", collection.name),
+    );
+    write_log(
+        &log_path,
+        &format!("Total photos: {}", collection.photos.len()),
+    );
+
+    // One client for the whole collection, so photos share its connections
+    let client = NatGeoClient::new(options.download.http.clone())?;
+
+    let mut downloaded = 0;
+    let mut skipped = 0;
+    let mut failed = 0;
+    let mut photos = Vec::with_capacity(collection.photos.len());
+
+    let total = collection.photos.len();
+    let report = |event: CollectionProgress| {
+        if let Some(progress) = progress {
+            progress(event);
+        }
+    };
+
+    for (index, photo) in collection.photos.iter().enumerate() {
+        if cancel.is_cancelled() {
+            write_log(
+                &log_path,
+                &format!(
+                    "Interrupted: {} downloaded, {} skipped, {} failed",
+                    downloaded, skipped, failed
+                ),
+            );
+            return Err(PhotoError::Interrupted);
+        }
+
+        let title = photo.title.as_str();
+        report(CollectionProgress::Started {
+            title,
+            index,
+            total,
+        });
+        let on_bytes = |bytes, total_bytes| {
+            report(CollectionProgress::Transfer {
+                title,
+                bytes,
+                total_bytes,
+            });
+        };
+
+        let stem = downloaded_title(save_dir, &collection_photo_name(index, total, photo));
+        let status = download_collection_photo(
+            &client, photo, &stem, save_dir, &log_path, options, &on_bytes,
+        );
+        match status {
+            PhotoStatus::Downloaded => downloaded += 1,
+            PhotoStatus::Skipped => skipped += 1,
+            PhotoStatus::Failed => failed += 1,
+        }
+        photos.push(CollectionPhotoResult {
+            title: photo.title.clone(),
+            status,
+        });
+        report(CollectionProgress::Finished {
+            title,
+            completed: index + 1,
+            total,
+            downloaded,
+            skipped,
+            failed,
+        });
+    }
+
+    write_log(
+        &log_path,
+        &format!(
+            "Collection download complete: {} downloaded, {} skipped, {} failed",
+            downloaded, skipped, failed
+        ),
+    );
+
+    Ok(CollectionDownloadResult {
+        downloaded,
+        skipped,
+        failed,
+        photos,
+    })
+}
+
+/// Download one collection photo as `sanitized_title`, skipping thumbnails and logging failures
+fn download_collection_photo(
+    client: &NatGeoClient,
+    photo: &PhotoInfo,
+    sanitized_title: &str,
+    save_dir: &str,
+    log_path: &str,
+    options: &CollectionDownloadOptions,
+    on_bytes: ByteProgress,
+) -> PhotoStatus {
+    // Photos downloaded before captions were used are named after their title
+    let legacy_title = downloaded_title(save_dir, &photo.title);
+    if legacy_title != sanitized_title {
+        if let Some(path) = find_downloaded_file(save_dir, &legacy_title)
+            .filter(|path| is_same_photo(path, &photo.image_url))
+        {
+            write_log(
+                log_path,
+                &format!("Photo already exists: {}", path.display()),
+            );
+            return PhotoStatus::Skipped;
+        }
+    }
+
+    // Skip thumbnails before downloading them, if the server says how big they are
+    let unique = unique_title(save_dir, sanitized_title, &photo.image_url);
+    if find_downloaded_file(save_dir, &unique).is_none() {
+        if let Some(size) = client
+            .content_length(&photo.image_url)
+            .filter(|&size| size < options.min_photo_size)
+        {
+            info!(url = photo.image_url, size, "Skipping thumbnail");
+            write_log(
+                log_path,
+                &format!(
+                    "Skipped {} (too small: {} bytes, min: {} bytes)",
+                    sanitized_title, size, options.min_photo_size
+                ),
+            );
+            return PhotoStatus::Skipped;
+        }
+    }
+
+    match client.download_photo(
+        &photo.image_url,
+        save_dir,
+        sanitized_title,
+        log_path,
+        &options.download,
+        Some(on_bytes),
+    ) {
+        Ok(DownloadOutcome::AlreadyExists(_) | DownloadOutcome::Duplicate(_)) => {
+            PhotoStatus::Skipped
+        }
+        Ok(DownloadOutcome::Downloaded(file_path)) => {
+            // Fallback for servers that don't report a size up front
+            if let Ok(metadata) = std::fs::metadata(&file_path) {
+                if metadata.len() < options.min_photo_size {
+                    // Remove small file (thumbnail/icon)
+                    let _ = std::fs::remove_file(&file_path);
+                    write_log(
+                        log_path,
+                        &format!(
+                            "Removed {} (too small: {} bytes, min: {} bytes)",
+                            sanitized_title,
+                            metadata.len(),
+                            options.min_photo_size
+                        ),
+                    );
+                    return PhotoStatus::Skipped;
+                }
+            }
+            record_photo_metadata(photo, &file_path, &options.download, log_path);
+            let context = HookContext::downloaded(photo, &file_path);
+            if run_hooks(HookEvent::PostDownload, &context, log_path).is_err() {
+                return PhotoStatus::Failed;
+            }
+            PhotoStatus::Downloaded
+        }
+        Err(e) => {
+            write_log(
+                log_path,
+                &format!("Failed to download {}: {}", photo.title, e),
+            );
+            PhotoStatus::Failed
+        }
+    }
+}
+
+/// Longest file stem `sanitize_title` produces, in bytes
+const MAX_TITLE_BYTES: usize = 100;
+
+/// ASCII punctuation that is valid in file names on every platform
+const SAFE_TITLE_PUNCTUATION: &str = "-_.,'()&!+=@#$%;[]{}~";
+
+/// Device names Windows reserves in every directory, with or without an extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7",
+    "COM8", "COM9", "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Whether `c` can appear in a file name as-is
+fn is_safe_title_char(c: char) -> bool {
+    if c.is_ascii() {
+        return c.is_ascii_alphanumeric() || SAFE_TITLE_PUNCTUATION.contains(c);
+    }
+    // Non-ASCII text is kept, minus invisible and direction-changing characters
+    !c.is_control()
+        && !c.is_whitespace()
+        && !matches!(c, '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}' | '\u{FEFF}')
+}
+
+/// Whether `name` is one of Windows' reserved device names, like `CON` or `LPT1.jpg`
+fn is_windows_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+/// Turn a photo title into a file stem that is valid on every platform.
+///
+/// Spaces and slashes become `_`, `:` is dropped and `|` becomes `-`, as they
+/// always have. Any other unsafe or control character becomes `_`, runs of `_`
+/// are collapsed, separators and dots are trimmed from the ends, and Windows
+/// device names get a trailing `_`. The result is at most 100 bytes.
+pub fn sanitize_title(title: &str) -> String {
+    let mut sanitized = String::with_capacity(title.len());
+    for c in title.chars() {
+        let mapped = match c {
+            ':' => continue,
+            '|' => '-',
+            c if is_safe_title_char(c) => c,
+            _ => '_',
+        };
+        if mapped == '_' && sanitized.ends_with('_') {
+            continue;
+        }
+        sanitized.push(mapped);
+    }
+
+    let trim = |s: &str| s.trim_matches(|c| matches!(c, '_' | '-' | '.')).to_string();
+    let mut sanitized = trim(&sanitized);
+    if sanitized.len() > MAX_TITLE_BYTES {
+        let mut end = MAX_TITLE_BYTES;
+        while !sanitized.is_char_boundary(end) {
+            end -= 1;
+        }
+        sanitized = trim(&sanitized[..end]);
+    }
+
+    if sanitized.is_empty() {
+        return "untitled".to_string();
+    }
+    if is_windows_reserved_name(&sanitized) {
+        sanitized.push('_');
+    }
+    sanitized
+}
+
+/// The file stem earlier releases gave `title`, before unsafe characters were filtered.
+///
+/// Kept so photos saved under those names are still recognised as downloaded.
+pub fn legacy_sanitize_title(title: &str) -> String {
+    title
+        .replace(['/', ' '], "_")
+        .replace(':', "")
+        .replace('|', "-")
+        .chars()
+        .take(100)
+        .collect()
+}
+
+/// File stem to save `title` under in `dir`.
+///
+/// This is `sanitize_title(title)`, unless the photo was already saved there under
+/// its legacy name, in which case that name is reused so it is not fetched again.
+pub fn downloaded_title(dir: &str, title: &str) -> String {
+    let sanitized = sanitize_title(title);
+    let legacy = legacy_sanitize_title(title);
+    if legacy != sanitized && !legacy.is_empty() && find_downloaded_file(dir, &legacy).is_some() {
+        return legacy;
+    }
+    sanitized
+}
+
+// Helper function to expand tilde in path
+pub fn expand_tilde(path: &str) -> String {
+    let home = std::env::var_os("HOME")
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from)
+        .or_else(dirs::home_dir);
+    resolve_home_path(path, home.as_deref(), &known_folders())
+}
+
+/// Folders the `~/...` defaults map to where the platform has its own layout
+#[cfg(target_os = "windows")]
+fn known_folders() -> Vec<(&'static str, Option<PathBuf>)> {
+    vec![
+        ("~/Pictures/", dirs::picture_dir()),
+        ("~/.local/share/", dirs::data_local_dir()),
+        ("~/.cache/", dirs::cache_dir()),
+        ("~/.config/", dirs::config_dir()),
+    ]
+}
+
+#[cfg(not(target_os = "windows"))]
+const fn known_folders() -> Vec<(&'static str, Option<PathBuf>)> {
+    Vec::new()
+}
+
+/// Expand a leading `~/` or `%USERPROFILE%` to `home`, preferring a known
+/// folder whose prefix matches. Anything else is returned unchanged.
+pub fn resolve_home_path(
+    path: &str,
+    home: Option<&std::path::Path>,
+    known: &[(&str, Option<PathBuf>)],
+) -> String {
+    for (prefix, folder) in known {
+        if let (Some(rest), Some(folder)) = (path.strip_prefix(prefix), folder) {
+            return format!("{}{}{}", folder.display(), std::path::MAIN_SEPARATOR, rest);
+        }
+    }
+
+    let rest = path
+        .strip_prefix('~')
+        .filter(|r| r.starts_with(['/', '\\']))
+        .or_else(|| {
+            path.get(..13)
+                .filter(|p| p.eq_ignore_ascii_case("%USERPROFILE%"))
+                .map(|_| &path[13..])
+        });
+    match (rest, home) {
+        (Some(rest), Some(home)) => format!("{}{}", home.display(), rest),
+        _ => path.to_string(),
+    }
+}
+
+/// Create a photo directory, writing the managed marker only if this call created it.
+/// A directory that already existed (e.g. a user-chosen folder) is left unmarked.
+pub fn ensure_managed_dir(path: &str) -> io::Result<()> {
+    let dir = std::path::Path::new(path);
+    if dir.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir)?;
+    File::create(dir.join(MANAGED_DIR_MARKER))?;
+    Ok(())
+}
+
+/// Check whether a directory was created by this tool (has the managed marker)
+pub fn is_managed_dir(path: &std::path::Path) -> bool {
+    path.join(MANAGED_DIR_MARKER).is_file()
+}
+
+// ============================================================================
+// Wallpaper Setting Functions
+// ============================================================================
+
+/// Facts about the running system that desktop detection depends on.
+/// Abstracted so the detection decision table can be tested without a real desktop.
+pub trait SystemProbe {
+    fn command_exists(&self, cmd: &str) -> bool;
+    fn process_running(&self, name: &str) -> bool;
+    fn env_var(&self, key: &str) -> Option<String>;
+    /// Whether a gsettings schema is installed
+    fn gsettings_schema_exists(&self, schema: &str) -> bool;
+    /// Whether something owns `name` on the session D-Bus
+    fn dbus_name_has_owner(&self, name: &str) -> bool;
+}
+
+/// Probe that inspects the live system
+pub struct LiveSystem;
+
+impl SystemProbe for LiveSystem {
+    fn command_exists(&self, cmd: &str) -> bool {
+        command_exists(cmd)
+    }
+
+    fn process_running(&self, name: &str) -> bool {
+        process_running(name)
+    }
+
+    fn env_var(&self, key: &str) -> Option<String> {
+        std::env::var(key).ok()
+    }
+
+    fn gsettings_schema_exists(&self, schema: &str) -> bool {
+        command_stdout("gsettings", &["list-schemas"])
+            .is_some_and(|schemas| schemas.lines().any(|s| s.trim() == schema))
+    }
+
+    fn dbus_name_has_owner(&self, name: &str) -> bool {
+        plasma_dbus::name_has_owner(name)
+    }
+}
+
+/// Where `command_exists` and `process_running` look.
+/// Abstracted so the lookups can be tested against a fake `PATH` and `/proc`.
+pub trait HostPaths {
+    /// The `PATH` to search for commands
+    fn search_path(&self) -> Option<std::ffi::OsString>;
+    /// Root of the proc filesystem, which lists running processes on Linux
+    fn proc_root(&self) -> PathBuf;
+}
+
+/// The live system's `PATH` and `/proc`
+pub struct LiveHost;
+
+impl HostPaths for LiveHost {
+    fn search_path(&self) -> Option<std::ffi::OsString> {
+        std::env::var_os("PATH")
+    }
+
+    fn proc_root(&self) -> PathBuf {
+        PathBuf::from("/proc")
+    }
+}
+
+/// Check if a command exists in PATH
+pub fn command_exists(cmd: &str) -> bool {
+    command_exists_in(&LiveHost, cmd)
+}
+
+/// Check if a process is running
+fn process_running(name: &str) -> bool {
+    process_running_in(&LiveHost, name)
+}
+
+/// Whether `cmd` is an executable file in one of the host's `PATH` entries,
+/// found without running `which`. A `cmd` containing a slash is checked as given.
+pub fn command_exists_in(host: &dyn HostPaths, cmd: &str) -> bool {
+    find_command_in(host, cmd).is_some()
+}
+
+/// Where `cmd` would be run from: the first executable match in the host's
+/// `PATH`, or `cmd` itself when it contains a slash
+pub fn find_command_in(host: &dyn HostPaths, cmd: &str) -> Option<PathBuf> {
+    if cmd.is_empty() {
+        return None;
+    }
+    if cmd.contains('/') {
+        let path = PathBuf::from(cmd);
+        return is_executable(&path).then_some(path);
+    }
+    let search_path = host.search_path()?;
+    std::env::split_paths(&search_path).find_map(|entry| {
+        let dir = search_dir(&entry);
+        executable_names(cmd)
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| is_executable(path))
+    })
+}
+
+/// The directory a `PATH` entry names: `~` is the home directory and an empty
+/// entry is the current directory, as shells treat them
+pub fn search_dir(entry: &Path) -> PathBuf {
+    if entry.as_os_str().is_empty() {
+        return PathBuf::from(".");
+    }
+    match entry.to_str() {
+        Some(entry) if entry.starts_with('~') => PathBuf::from(expand_tilde(entry)),
+        _ => entry.to_path_buf(),
+    }
+}
+
+/// File names `cmd` may have on disk; Windows adds an executable extension
+fn executable_names(cmd: &str) -> Vec<String> {
+    if cfg!(target_os = "windows") {
+        ["", ".exe", ".cmd", ".bat"]
+            .iter()
+            .map(|ext| format!("{cmd}{ext}"))
+            .collect()
+    } else {
+        vec![cmd.to_string()]
+    }
+}
+
+/// Whether `path` is a file that may be executed
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+/// Longest process name the kernel keeps in `/proc/<pid>/comm`
+const COMM_MAX_LEN: usize = 15;
+
+/// Whether a process called exactly `name` is running, as `pgrep -x` tells.
+/// Reads each `/proc/<pid>/comm` where there is a proc filesystem, and falls
+/// back to `pgrep` elsewhere.
+pub fn process_running_in(host: &dyn HostPaths, name: &str) -> bool {
+    let Some(names) = process_names(&host.proc_root()) else {
+        return Command::new("pgrep")
+            .args(["-x", name])
+            .output()
+            .is_ok_and(|o| o.status.success());
+    };
+    // The kernel cuts longer names short
+    let mut end = name.len().min(COMM_MAX_LEN);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    names.iter().any(|running| running == &name[..end])
+}
+
+/// Names of the processes listed under `proc_root`, or `None` if it can't be read
+fn process_names(proc_root: &Path) -> Option<Vec<String>> {
+    let entries = std::fs::read_dir(proc_root).ok()?;
+    let names = entries
+        .flatten()
+        .filter(|entry| {
+            let pid = entry.file_name();
+            let pid = pid.to_string_lossy();
+            !pid.is_empty() && pid.bytes().all(|b| b.is_ascii_digit())
+        })
+        // Processes that exit during the scan are skipped
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("comm")).ok())
+        .map(|comm| comm.trim_end_matches('\n').to_string())
+        .collect();
+    Some(names)
+}
+
+/// Runs external commands.
+/// Abstracted so backends can be tested without the tools installed.
+pub trait CommandRunner {
+    /// Run `program`, returning its stdout, or its stderr as an error if it fails
+    fn run(&self, program: &str, args: &[&str]) -> Result<String, PhotoError>;
+}
+
+/// Runner that executes commands on the live system
+pub struct LiveCommands;
+
+impl CommandRunner for LiveCommands {
+    fn run(&self, program: &str, args: &[&str]) -> Result<String, PhotoError> {
+        let output = Command::new(program)
+            .args(args)
+            .output()
+            .map_err(|e| PhotoError::Command(format!("{}: {}", program, e)))?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(PhotoError::Wallpaper(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ))
+        }
+    }
+}
+
+/// Detect the current desktop environment
+pub fn detect_desktop_environment() -> DesktopEnvironment {
+    if cfg!(target_os = "windows") {
+        return DesktopEnvironment::Windows;
+    }
+    detect_desktop_environment_with(&LiveSystem)
+}
+
+/// Whether the session is GNOME or a GNOME-based desktop that honors
+/// `org.gnome.desktop.background`. The `gsettings` binary alone proves nothing:
+/// it is installed on almost every system with GTK apps.
+fn is_gnome_session(probe: &dyn SystemProbe) -> bool {
+    let desktop_matches = probe
+        .env_var("XDG_CURRENT_DESKTOP")
+        .is_some_and(|desktops| {
+            desktops.split(':').any(|d| {
+                matches!(
+                    d.trim().to_lowercase().as_str(),
+                    "gnome" | "unity" | "budgie" | "budgie-desktop"
+                )
+            })
+        });
+
+    desktop_matches || probe.process_running("gnome-shell")
+}
+
+/// Whether `XDG_CURRENT_DESKTOP` names any of `names`, ignoring case
+fn current_desktop_is(probe: &dyn SystemProbe, names: &[&str]) -> bool {
+    probe
+        .env_var("XDG_CURRENT_DESKTOP")
+        .is_some_and(|desktops| {
+            desktops
+                .split(':')
+                .any(|d| names.iter().any(|n| d.trim().eq_ignore_ascii_case(n)))
+        })
+}
+
+/// Whether the session is Cinnamon, whose background lives in its own schema
+fn is_cinnamon_session(probe: &dyn SystemProbe) -> bool {
+    probe.gsettings_schema_exists("org.cinnamon.desktop.background")
+        && (probe.process_running("cinnamon")
+            || current_desktop_is(probe, &["X-Cinnamon", "Cinnamon"]))
+}
+
+/// Whether the session is MATE, whose background lives in its own schema
+fn is_mate_session(probe: &dyn SystemProbe) -> bool {
+    probe.gsettings_schema_exists("org.mate.background")
+        && (probe.process_running("mate-session") || current_desktop_is(probe, &["MATE"]))
+}
+
+/// Wallpaper tools that only work against an X server
+const X11_ONLY_TOOLS: &[&str] = &["feh"];
+
+/// The display server the session runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionType {
+    X11,
+    Wayland,
+    /// Neither an X11 nor a Wayland display is reachable, as under SSH or cron
+    Headless,
+}
+
+impl SessionType {
+    /// Read the session type from `XDG_SESSION_TYPE`, `WAYLAND_DISPLAY`, and `DISPLAY`.
+    /// X11 needs `DISPLAY` set even when `XDG_SESSION_TYPE` says x11, since
+    /// X11 tools have nothing to draw on without it. Under Wayland, `DISPLAY`
+    /// usually points at Xwayland, where a root-window wallpaper is never shown.
+    pub fn detect(probe: &dyn SystemProbe) -> Self {
+        let set = |key: &str| probe.env_var(key).is_some_and(|v| !v.trim().is_empty());
+        let wayland = probe
+            .env_var("XDG_SESSION_TYPE")
+            .is_some_and(|t| t.trim().eq_ignore_ascii_case("wayland"));
+        if wayland || set("WAYLAND_DISPLAY") {
+            Self::Wayland
+        } else if set("DISPLAY") {
+            Self::X11
+        } else {
+            Self::Headless
+        }
+    }
+}
+
+impl std::fmt::Display for SessionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::X11 => "X11",
+            Self::Wayland => "Wayland",
+            Self::Headless => "headless",
+        })
+    }
+}
+
+/// The detected desktop together with the session it runs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetectedEnvironment {
+    pub de: DesktopEnvironment,
+    pub session: SessionType,
+}
+
+/// Explain why no wallpaper backend was detected.
+/// Calls out installed X11-only tools that were skipped because there is no X11 display.
+pub fn unsupported_desktop_reason(probe: &dyn SystemProbe) -> String {
+    let skipped: Vec<&str> = X11_ONLY_TOOLS
+        .iter()
+        .copied()
+        .filter(|tool| probe.command_exists(tool))
+        .collect();
+
+    let session = SessionType::detect(probe);
+    if skipped.is_empty() || session == SessionType::X11 {
+        return "No supported wallpaper tool found".to_string();
+    }
+
+    match session {
+        SessionType::Wayland => {
+            let session = probe
+                .env_var("XDG_SESSION_TYPE")
+                .filter(|t| !t.trim().is_empty())
+                .unwrap_or_else(|| "wayland".to_string());
+            format!(
+                "Found {} but it only works on X11, and this is a {} session. \
+                 Install a Wayland wallpaper tool such as swaybg, swww, or hyprpaper",
+                skipped.join(", "),
+                session
+            )
+        }
+        SessionType::X11 | SessionType::Headless => format!(
+            "Found {} but it only works on X11, and neither DISPLAY nor WAYLAND_DISPLAY is set. \
+             Run it from within the desktop session",
+            skipped.join(", ")
+        ),
+    }
+}
+
+/// Which sessions a detection rule's wallpaper tool works in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SessionSupport {
+    /// Works wherever the desktop itself runs
+    Any,
+    /// Needs an X server, so is skipped unless the session is X11
+    X11Only,
+    /// Talks to the compositor directly; preferred on Wayland
+    WaylandNative,
+}
+
+/// One row of the detection table: what sessions its tool works in,
+/// and the desktop it reports when the evidence matches
+struct DetectionRule {
+    support: SessionSupport,
+    detect: fn(&dyn SystemProbe) -> Option<DesktopEnvironment>,
+}
+
+/// Whether Hyprland is running, by its instance signature or process
+fn is_hyprland_running(probe: &dyn SystemProbe) -> bool {
+    probe
+        .env_var("HYPRLAND_INSTANCE_SIGNATURE")
+        .is_some_and(|s| !s.trim().is_empty())
+        || probe.process_running("Hyprland")
+}
+
+/// Desktops in the order they are tried
+const DETECTION_RULES: &[DetectionRule] = &[
+    DetectionRule {
+        support: SessionSupport::WaylandNative,
+        detect: |p| {
+            (p.command_exists("swww") && p.process_running("swww-daemon"))
+                .then_some(DesktopEnvironment::Swww)
+        },
+    },
+    DetectionRule {
+        support: SessionSupport::WaylandNative,
+        detect: |p| {
+            (is_hyprland_running(p) && p.command_exists("hyprctl"))
+                .then_some(DesktopEnvironment::Hyprland)
+        },
+    },
+    DetectionRule {
+        support: SessionSupport::Any,
+        detect: detect_plasma,
+    },
+    DetectionRule {
+        support: SessionSupport::Any,
+        detect: |p| {
+            (p.command_exists("xfconf-query") && p.process_running("xfce4-session"))
+                .then_some(DesktopEnvironment::Xfce)
+        },
+    },
+    DetectionRule {
+        support: SessionSupport::Any,
+        detect: |p| {
+            (p.process_running("pcmanfm-qt") && current_desktop_is(p, &["LXQt"]))
+                .then_some(DesktopEnvironment::Lxqt)
+        },
+    },
+    DetectionRule {
+        support: SessionSupport::Any,
+        detect: |p| {
+            p.command_exists("plasma-apply-wallpaperimage")
+                .then_some(DesktopEnvironment::PlasmaFallback)
+        },
+    },
+    // Before GNOME: Cinnamon ships the GNOME schemas too, but doesn't read them
+    DetectionRule {
+        support: SessionSupport::Any,
+        detect: |p| {
+            (p.command_exists("gsettings") && is_cinnamon_session(p))
+                .then_some(DesktopEnvironment::Cinnamon)
+        },
+    },
+    DetectionRule {
+        support: SessionSupport::Any,
+        detect: |p| {
+            (p.command_exists("gsettings") && is_mate_session(p))
+                .then_some(DesktopEnvironment::Mate)
+        },
+    },
+    DetectionRule {
+        support: SessionSupport::Any,
+        detect: |p| {
+            (p.command_exists("gsettings") && is_gnome_session(p))
+                .then_some(DesktopEnvironment::Gnome)
+        },
+    },
+    DetectionRule {
+        support: SessionSupport::X11Only,
+        detect: |p| p.command_exists("feh").then_some(DesktopEnvironment::Feh),
+    },
+];
+
+/// Desktop names as `XDG_CURRENT_DESKTOP` and `DESKTOP_SESSION` give them, and the
+/// backend each uses. KDE maps to `PlasmaFallback`; which Plasma it is gets probed.
+const SESSION_DESKTOPS: &[(&str, DesktopEnvironment)] = &[
+    ("KDE", DesktopEnvironment::PlasmaFallback),
+    ("plasma", DesktopEnvironment::PlasmaFallback),
+    ("plasmawayland", DesktopEnvironment::PlasmaFallback),
+    ("plasmax11", DesktopEnvironment::PlasmaFallback),
+    ("GNOME", DesktopEnvironment::Gnome),
+    ("GNOME-Classic", DesktopEnvironment::Gnome),
+    ("gnome-xorg", DesktopEnvironment::Gnome),
+    ("ubuntu", DesktopEnvironment::Gnome),
+    ("ubuntu-wayland", DesktopEnvironment::Gnome),
+    ("Unity", DesktopEnvironment::Gnome),
+    ("Budgie", DesktopEnvironment::Gnome),
+    ("budgie-desktop", DesktopEnvironment::Gnome),
+    ("X-Cinnamon", DesktopEnvironment::Cinnamon),
+    ("cinnamon", DesktopEnvironment::Cinnamon),
+    ("MATE", DesktopEnvironment::Mate),
+    ("XFCE", DesktopEnvironment::Xfce),
+    ("LXQt", DesktopEnvironment::Lxqt),
+    ("Hyprland", DesktopEnvironment::Hyprland),
+    ("sway", DesktopEnvironment::Swww),
+];
+
+/// The backend named by `XDG_CURRENT_DESKTOP`, or failing that `DESKTOP_SESSION`
+///
+/// `XDG_CURRENT_DESKTOP` is a colon-separated list whose first recognized entry wins.
+/// `DESKTOP_SESSION` may be a path to the session file, so only its name is used.
+pub fn desktop_from_session_vars(
+    current_desktop: Option<&str>,
+    desktop_session: Option<&str>,
+) -> Option<DesktopEnvironment> {
+    let lookup = |name: &str| {
+        SESSION_DESKTOPS
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name.trim()))
+            .map(|&(_, de)| de)
+    };
+    current_desktop
+        .into_iter()
+        .flat_map(|desktops| desktops.split(':'))
+        .find_map(lookup)
+        .or_else(|| {
+            desktop_session
+                .and_then(|session| session.trim().rsplit('/').next())
+                .map(|name| name.trim_end_matches(".desktop"))
+                .and_then(lookup)
+        })
+}
+
+/// Detect the desktop environment using the given probe
+pub fn detect_desktop_environment_with(probe: &dyn SystemProbe) -> DesktopEnvironment {
+    detect_environment_with(probe).de
+}
+
+/// Detect the desktop environment and session type using the given probe.
+///
+/// A running Plasma shell wins, then the desktop the session variables name if
+/// its tool is there; the detection table decides everything else.
+pub fn detect_environment_with(probe: &dyn SystemProbe) -> DetectedEnvironment {
+    let named = desktop_from_session_vars(
+        probe.env_var("XDG_CURRENT_DESKTOP").as_deref(),
+        probe.env_var("DESKTOP_SESSION").as_deref(),
+    )
+    .and_then(|de| detect_plasma(probe).or_else(|| confirm_session_desktop(de, probe)));
+    match named {
+        Some(de) => DetectedEnvironment {
+            de,
+            session: SessionType::detect(probe),
+        },
+        None => detect_with_rules(DETECTION_RULES, probe),
+    }
+}
+
+/// `de`, as named by the session variables, if its wallpaper tool is installed
+/// or, for desktops drawn by a separate process, that process is running
+fn confirm_session_desktop(
+    de: DesktopEnvironment,
+    probe: &dyn SystemProbe,
+) -> Option<DesktopEnvironment> {
+    let available = match de {
+        DesktopEnvironment::PlasmaFallback => probe.command_exists("plasma-apply-wallpaperimage"),
+        DesktopEnvironment::Gnome => probe.command_exists("gsettings"),
+        DesktopEnvironment::Cinnamon => {
+            probe.command_exists("gsettings")
+                && probe.gsettings_schema_exists("org.cinnamon.desktop.background")
+        }
+        DesktopEnvironment::Mate => {
+            probe.command_exists("gsettings")
+                && probe.gsettings_schema_exists("org.mate.background")
+        }
+        DesktopEnvironment::Xfce => probe.command_exists("xfconf-query"),
+        DesktopEnvironment::Lxqt => probe.process_running("pcmanfm-qt"),
+        DesktopEnvironment::Hyprland => probe.command_exists("hyprctl"),
+        DesktopEnvironment::Swww => probe.command_exists("swww"),
+        _ => false,
+    };
+    available.then_some(de)
+}
+
+/// Try `rules` in order, skipping X11-only tools outside X11.
+/// On Wayland, a matching Wayland-native rule wins over any other match.
+fn detect_with_rules(rules: &[DetectionRule], probe: &dyn SystemProbe) -> DetectedEnvironment {
+    let session = SessionType::detect(probe);
+    let usable = |rule: &&DetectionRule| {
+        rule.support != SessionSupport::X11Only || session == SessionType::X11
+    };
+    let preferred = |rule: &&DetectionRule| {
+        session == SessionType::Wayland && rule.support == SessionSupport::WaylandNative
+    };
+
+    let de = rules
+        .iter()
+        .filter(usable)
+        .filter(preferred)
+        .chain(rules.iter().filter(usable).filter(|rule| !preferred(rule)))
+        .find_map(|rule| (rule.detect)(probe))
+        .or_else(|| {
+            // Picked so the user is told to start the daemon
+            (session == SessionType::Wayland && probe.command_exists("swww"))
+                .then_some(DesktopEnvironment::Swww)
+        })
+        .unwrap_or(DesktopEnvironment::Unknown);
+    DetectedEnvironment { de, session }
+}
+
+/// Bus name plasmashell owns while it's running
+const PLASMA_SHELL_SERVICE: &str = "org.kde.plasmashell";
+
+/// Which KDE Plasma is running and scriptable, if any.
+/// Reachable on the session bus is enough; otherwise plasmashell must be
+/// running with `qdbus6` or `qdbus` installed to talk to it.
+fn detect_plasma(probe: &dyn SystemProbe) -> Option<DesktopEnvironment> {
+    if probe.dbus_name_has_owner(PLASMA_SHELL_SERVICE) {
+        let plasma5 = probe
+            .env_var("KDE_SESSION_VERSION")
+            .is_some_and(|v| v.trim() == "5");
+        return Some(if plasma5 {
+            DesktopEnvironment::KdePlasma5
+        } else {
+            DesktopEnvironment::KdePlasma6
+        });
+    }
+    if !probe.process_running("plasmashell") {
+        None
+    } else if probe.command_exists("qdbus6") {
+        Some(DesktopEnvironment::KdePlasma6)
+    } else if probe.command_exists("qdbus") {
+        Some(DesktopEnvironment::KdePlasma5)
+    } else {
+        None
+    }
+}
+
+/// A KDE Plasma activity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Activity {
+    pub id: String,
+    pub name: String,
+}
+
+/// Bus name and object path of the activity manager
+const ACTIVITY_MANAGER: (&str, &str) = ("org.kde.ActivityManager", "/ActivityManager/Activities");
+
+/// Interface of the activity manager's methods
+const ACTIVITIES_INTERFACE: &str = "org.kde.ActivityManager.Activities";
+
+/// Activity ids from `ListActivities` as printed by `qdbus`, one per line
+pub fn parse_activity_list(output: &str) -> Vec<String> {
+    let mut ids: Vec<String> = Vec::new();
+    for id in output.lines().map(str::trim).filter(|id| !id.is_empty()) {
+        if !ids.iter().any(|known| known == id) {
+            ids.push(id.to_string());
+        }
+    }
+    ids
+}
+
+/// How scripts and queries reach KDE Plasma
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlasmaTransport {
+    /// Method calls on the session D-Bus
+    DBus,
+    /// Shelling out to `qdbus6` or `qdbus`
+    Qdbus(&'static str),
+}
+
+impl PlasmaTransport {
+    /// Direct D-Bus when plasmashell owns its bus name, else the `qdbus` tool
+    /// for `de` if it's installed
+    pub fn select(de: DesktopEnvironment, probe: &dyn SystemProbe) -> Option<Self> {
+        let qdbus = match de {
+            DesktopEnvironment::KdePlasma6 => "qdbus6",
+            DesktopEnvironment::KdePlasma5 => "qdbus",
+            _ => return None,
+        };
+        if probe.dbus_name_has_owner(PLASMA_SHELL_SERVICE) {
+            Some(Self::DBus)
+        } else {
+            probe.command_exists(qdbus).then_some(Self::Qdbus(qdbus))
+        }
+    }
+
+    /// Run `script` in plasmashell, returning what it printed.
+    /// `runner` runs the `qdbus` tool when that's the transport.
+    pub fn evaluate_script(
+        self,
+        script: &str,
+        runner: &dyn CommandRunner,
+    ) -> Result<String, PhotoError> {
+        match self {
+            Self::DBus => {
+                debug!(%script, "Running Plasma script over D-Bus");
+                plasma_dbus::evaluate_script(script)
+            }
+            Self::Qdbus(qdbus) => {
+                debug!(command = qdbus, %script, "Running Plasma script");
+                runner.run(
+                    qdbus,
+                    &[
+                        PLASMA_SHELL_SERVICE,
+                        "/PlasmaShell",
+                        "org.kde.PlasmaShell.evaluateScript",
+                        script,
+                    ],
+                )
+            }
+        }
+    }
+
+    /// Plasma's activities with the current one first, if the activity manager can be asked
+    fn activities(self, runner: &dyn CommandRunner) -> Option<Vec<Activity>> {
+        let ids = match self {
+            Self::DBus => plasma_dbus::activity_ids()?,
+            Self::Qdbus(_) => {
+                parse_activity_list(&self.activity_query(runner, "ListActivities", None)?)
+            }
+        };
+        let current = self.activity_query(runner, "CurrentActivity", None);
+        let mut activities: Vec<Activity> = ids
+            .into_iter()
+            .map(|id| Activity {
+                name: self
+                    .activity_query(runner, "ActivityName", Some(&id))
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| id.clone()),
+                id,
+            })
+            .collect();
+        // The current activity is the one on screen
+        activities.sort_by_key(|a| current.as_deref().map(str::trim) != Some(a.id.as_str()));
+        Some(activities)
+    }
+
+    /// Call an activity manager `method` that returns a string, with an activity `id` if given
+    fn activity_query(
+        self,
+        runner: &dyn CommandRunner,
+        method: &str,
+        id: Option<&str>,
+    ) -> Option<String> {
+        match self {
+            Self::DBus => plasma_dbus::activity_string(method, id),
+            Self::Qdbus(qdbus) => {
+                let method = format!("{ACTIVITIES_INTERFACE}.{method}");
+                let mut args = vec![ACTIVITY_MANAGER.0, ACTIVITY_MANAGER.1, &method];
+                args.extend(id);
+                runner.run(qdbus, &args).ok()
+            }
+        }
+    }
+
+    /// How many virtual desktops `KWin` has, if it can be asked
+    fn virtual_desktop_count(self, runner: &dyn CommandRunner) -> Option<usize> {
+        match self {
+            Self::DBus => plasma_dbus::virtual_desktop_count(),
+            Self::Qdbus(qdbus) => runner
+                .run(
+                    qdbus,
+                    &[
+                        "org.kde.KWin",
+                        "/VirtualDesktopManager",
+                        "org.kde.KWin.VirtualDesktopManager.count",
+                    ],
+                )
+                .ok()
+                .and_then(|s| s.trim().parse().ok()),
+        }
+    }
+}
+
+/// Get the number of screens Plasma has desktops on
+fn get_monitor_count(transport: PlasmaTransport, runner: &dyn CommandRunner) -> usize {
+    transport
+        .evaluate_script(PLASMA_SCREEN_COUNT_SCRIPT, runner)
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .filter(|&count| count > 0)
+        .unwrap_or(1)
+}
+
+/// Get the number of `KWin` virtual desktops
+fn get_virtual_desktop_count(transport: PlasmaTransport, runner: &dyn CommandRunner) -> usize {
+    transport
+        .virtual_desktop_count(runner)
+        .filter(|&count| count > 0)
+        .unwrap_or(1)
+}
+
+/// Calls to plasmashell and `KWin` on the session D-Bus
+#[cfg(target_os = "linux")]
+mod plasma_dbus {
+    use super::PhotoError;
+    use zbus::blocking::{fdo::DBusProxy, Connection, MessageIterator, Proxy};
+    use zbus::names::BusName;
+    use zbus::MatchRule;
+
+    fn dbus_error(e: &zbus::Error) -> PhotoError {
+        PhotoError::Wallpaper(format!("D-Bus: {e}"))
+    }
+
+    pub fn name_has_owner(name: &str) -> bool {
+        let Ok(name) = BusName::try_from(name) else {
+            return false;
+        };
+        Connection::session()
+            .and_then(|conn| {
+                DBusProxy::new(&conn)?
+                    .name_has_owner(name)
+                    .map_err(Into::into)
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn evaluate_script(script: &str) -> Result<String, PhotoError> {
+        let conn = Connection::session().map_err(|e| dbus_error(&e))?;
+        let reply = conn
+            .call_method(
+                Some(super::PLASMA_SHELL_SERVICE),
+                "/PlasmaShell",
+                Some("org.kde.PlasmaShell"),
+                "evaluateScript",
+                &(script,),
+            )
+            .map_err(|e| dbus_error(&e))?;
+        reply.body().deserialize().map_err(|e| dbus_error(&e))
+    }
+
+    pub fn virtual_desktop_count() -> Option<usize> {
+        let conn = Connection::session().ok()?;
+        let proxy = Proxy::new(
+            &conn,
+            "org.kde.KWin",
+            "/VirtualDesktopManager",
+            "org.kde.KWin.VirtualDesktopManager",
+        )
+        .ok()?;
+        let count: u32 = proxy.get_property("count").ok()?;
+        usize::try_from(count).ok()
+    }
+
+    fn call_activity_manager<B>(method: &str, body: &B) -> Option<zbus::Message>
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        let (service, path) = super::ACTIVITY_MANAGER;
+        Connection::session()
+            .ok()?
+            .call_method(
+                Some(service),
+                path,
+                Some(super::ACTIVITIES_INTERFACE),
+                method,
+                body,
+            )
+            .ok()
+    }
+
+    pub fn activity_ids() -> Option<Vec<String>> {
+        call_activity_manager("ListActivities", &())?
+            .body()
+            .deserialize()
+            .ok()
+    }
+
+    pub fn activity_string(method: &str, id: Option<&str>) -> Option<String> {
+        let reply = match id {
+            Some(id) => call_activity_manager(method, &(id,)),
+            None => call_activity_manager(method, &()),
+        }?;
+        reply.body().deserialize().ok()
+    }
+
+    /// Forward `KScreen` output change signals to `events` from a background thread
+    pub fn watch_kscreen(events: std::sync::mpsc::Sender<()>) -> Result<(), PhotoError> {
+        let conn = Connection::session().map_err(|e| dbus_error(&e))?;
+        let bus = DBusProxy::new(&conn).map_err(|e| dbus_error(&e))?;
+        for interface in [
+            super::KSCREEN_BACKEND_INTERFACE,
+            super::KSCREEN_MODULE_INTERFACE,
+        ] {
+            let rule = MatchRule::builder()
+                .msg_type(zbus::message::Type::Signal)
+                .interface(interface)
+                .map_err(|e| dbus_error(&e))?
+                .build();
+            bus.add_match_rule(rule)
+                .map_err(|e| dbus_error(&e.into()))?;
+        }
+        std::thread::spawn(move || {
+            for message in MessageIterator::from(&conn).map_while(Result::ok) {
+                let header = message.header();
+                let (Some(interface), Some(member)) = (header.interface(), header.member()) else {
+                    continue;
+                };
+                if super::is_kscreen_output_signal(interface, member) && events.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// No session D-Bus off Linux, so Plasma is always reached through `qdbus`
+#[cfg(not(target_os = "linux"))]
+mod plasma_dbus {
+    use super::PhotoError;
+
+    pub const fn name_has_owner(_name: &str) -> bool {
+        false
+    }
+
+    pub fn evaluate_script(_script: &str) -> Result<String, PhotoError> {
+        Err(PhotoError::Wallpaper(
+            "D-Bus is only available on Linux".to_string(),
+        ))
+    }
+
+    pub const fn virtual_desktop_count() -> Option<usize> {
+        None
+    }
+
+    pub const fn activity_ids() -> Option<Vec<String>> {
+        None
+    }
+
+    pub const fn activity_string(_method: &str, _id: Option<&str>) -> Option<String> {
+        None
+    }
+
+    pub fn watch_kscreen(_events: std::sync::mpsc::Sender<()>) -> Result<(), PhotoError> {
+        Err(PhotoError::Command(
+            "KScreen is only watched on Linux".to_string(),
+        ))
+    }
+}
+
+// ============================================================================
+// Image Probing and Conversion
+// ============================================================================
+
+/// JPEG quality used when transcoding photos for display
+const TRANSCODE_JPEG_QUALITY: u8 = 90;
+
+fn has_extension(path: &std::path::Path, wanted: &str) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(wanted))
+}
+
+fn is_jxl(path: &std::path::Path) -> bool {
+    has_extension(path, "jxl")
+}
+
+fn is_avif(path: &std::path::Path) -> bool {
+    has_extension(path, "avif")
+}
+
+/// Read the first few KB of a file, enough for any header we parse
+fn read_header(path: &std::path::Path) -> io::Result<Vec<u8>> {
+    let mut header = Vec::new();
+    io::Read::read_to_end(&mut io::Read::take(File::open(path)?, 4096), &mut header)?;
+    Ok(header)
+}
+
+/// Read a photo's pixel dimensions without decoding the whole image
+pub fn probe_dimensions(path: &std::path::Path) -> Result<(u32, u32), PhotoError> {
+    if is_jxl(path) {
+        return parse_jxl_dimensions(&read_header(path)?).ok_or_else(|| {
+            PhotoError::InvalidContentType(format!("Invalid JPEG XL header: {}", path.display()))
+        });
+    }
+    if is_avif(path) {
+        return parse_avif_dimensions(&read_header(path)?).ok_or_else(|| {
+            PhotoError::InvalidContentType(format!("Invalid AVIF header: {}", path.display()))
+        });
+    }
+    Ok(image::image_dimensions(path)?)
+}
+
+/// How many trailing bytes to inspect for a format's end marker,
+/// allowing for trailers some cameras and editors append after it
+const IMAGE_TRAILER_WINDOW: u64 = 64 * 1024;
+
+/// Check that a downloaded file is a complete image.
+///
+/// The format is detected from content, so an HTML error page served as
+/// `image/jpeg` is rejected whatever the file is named. Rather than decoding
+/// every pixel (costly for 40 MB originals), the header must parse and the
+/// file must end with its format's end marker, which catches truncation.
+pub fn validate_image(path: &std::path::Path) -> Result<(), PhotoError> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let corrupt =
+        |reason: &str| PhotoError::CorruptImage(format!("{}: {}", path.display(), reason));
+
+    let mut file = File::open(path)?;
+    let head = read_header(path)?;
+
+    // No JPEG XL or AVIF decoder is bundled, so a well-formed header has to do
+    if parse_jxl_dimensions(&head).is_some() || parse_avif_dimensions(&head).is_some() {
+        return Ok(());
+    }
+
+    let format = image::guess_format(&head).map_err(|_| corrupt("not an image"))?;
+    let reader = image::ImageReader::with_format(io::BufReader::new(File::open(path)?), format);
+    let (width, height) = reader
+        .into_dimensions()
+        .map_err(|e| corrupt(&e.to_string()))?;
+    if width == 0 || height == 0 {
+        return Err(corrupt("image has no pixels"));
+    }
+
+    let len = file.metadata()?.len();
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(len.saturating_sub(IMAGE_TRAILER_WINDOW)))?;
+    file.read_to_end(&mut tail)?;
+    let complete = match format {
+        image::ImageFormat::Jpeg => tail.windows(2).any(|w| w == [0xff, 0xd9]),
+        image::ImageFormat::Png => tail.windows(4).any(|w| w == b"IEND"),
+        image::ImageFormat::Gif => tail.contains(&0x3b),
+        // The RIFF header records the size of everything after its first 8 bytes
+        image::ImageFormat::WebP => head
+            .get(4..8)
+            .and_then(|size| size.try_into().ok())
+            .is_some_and(|size| u64::from(u32::from_le_bytes(size)) + 8 <= len),
+        _ => true,
+    };
+    if complete {
+        Ok(())
+    } else {
+        Err(corrupt("truncated (missing end-of-image marker)"))
+    }
+}
+
+/// Parse the image size from an AVIF header: an ISOBMFF `ftyp` box with an AVIF
+/// brand, and the `ispe` (image spatial extents) property of the primary image
+pub fn parse_avif_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if data.get(4..8)? != b"ftyp" {
+        return None;
+    }
+    let ftyp_size = u32::from_be_bytes(data.get(0..4)?.try_into().ok()?) as usize;
+    let brands = data.get(8..ftyp_size.min(data.len()))?;
+    if !brands
+        .chunks_exact(4)
+        .any(|brand| brand == b"avif" || brand == b"avis")
+    {
+        return None;
+    }
+
+    // ispe: 4-byte type, 4 bytes of version/flags, then width and height
+    let at = data.windows(4).position(|w| w == b"ispe")? + 8;
+    let width = u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?);
+    let height = u32::from_be_bytes(data.get(at + 4..at + 8)?.try_into().ok()?);
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+/// Parse the image size from a JPEG XL codestream or container header
+pub fn parse_jxl_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    const CONTAINER_SIGNATURE: &[u8] = b"\0\0\0\x0cJXL \r\n\x87\n";
+
+    if data.starts_with(CONTAINER_SIGNATURE) {
+        // Find the first codestream box (jxlc, or a jxlp part with a 4-byte index)
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let size = u32::from_be_bytes(data[offset..offset + 4].try_into().ok()?) as usize;
+            let box_type = &data[offset + 4..offset + 8];
+            match box_type {
+                b"jxlc" => return parse_jxl_dimensions(data.get(offset + 8..)?),
+                b"jxlp" => return parse_jxl_dimensions(data.get(offset + 12..)?),
+                _ if size < 8 => return None,
+                _ => offset += size,
+            }
+        }
+        return None;
+    }
+
+    let mut bits = JxlBitReader::new(data.strip_prefix(&[0xff, 0x0a])?);
+    let small = bits.read(1)? == 1;
+    let height = if small {
+        (bits.read(5)? + 1) * 8
+    } else {
+        bits.read_size()?
+    };
+    let ratio = bits.read(3)?;
+    let width = match ratio {
+        0 if small => (bits.read(5)? + 1) * 8,
+        0 => bits.read_size()?,
+        _ => {
+            let (num, den) =
+                [(1, 1), (12, 10), (4, 3), (3, 2), (16, 9), (5, 4), (2, 1)][ratio as usize - 1];
+            u32::try_from(u64::from(height) * num / den).ok()?
+        }
+    };
+    Some((width, height))
+}
+
+/// Minimal LSB-first bit reader for the JPEG XL size header
+struct JxlBitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> JxlBitReader<'a> {
+    const fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn read(&mut self, count: usize) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self.data.get(self.pos / 8)?;
+            value |= u32::from((byte >> (self.pos % 8)) & 1) << i;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+
+    /// `SizeHeader` dimension: `U32(1 + u(9), 1 + u(13), 1 + u(18), 1 + u(30))`
+    fn read_size(&mut self) -> Option<u32> {
+        let bits = [9, 13, 18, 30][self.read(2)? as usize];
+        Some(self.read(bits)? + 1)
+    }
+}
+
+/// Convert a photo to JPEG in `dest_dir`, reusing a previous conversion if it is newer
+/// than the source. JPEG XL and AVIF are decoded with `djxl` and `avifdec` since no
+/// decoders for them are linked in.
+pub fn transcode_to_jpeg(
+    src: &std::path::Path,
+    dest_dir: &std::path::Path,
+) -> Result<PathBuf, PhotoError> {
+    transcode_to_jpeg_in(&LiveHost, src, dest_dir)
+}
+
+/// [`transcode_to_jpeg`], finding the decoding tools on `host`'s `PATH`
+fn transcode_to_jpeg_in(
+    host: &dyn HostPaths,
+    src: &std::path::Path,
+    dest_dir: &std::path::Path,
+) -> Result<PathBuf, PhotoError> {
+    let dest = dest_dir.join(transcoded_name(src));
+
+    let is_fresh = |dest: &std::path::Path| -> Option<bool> {
+        let src_time = std::fs::metadata(src).ok()?.modified().ok()?;
+        let dest_time = std::fs::metadata(dest).ok()?.modified().ok()?;
+        Some(dest_time >= src_time)
+    };
+    if is_fresh(&dest) == Some(true) {
+        return Ok(dest);
+    }
+
+    std::fs::create_dir_all(dest_dir)?;
+
+    let image = if is_jxl(src) {
+        decode_with_tool(host, "djxl", "JPEG XL", src, dest_dir)?
+    } else if is_avif(src) {
+        decode_with_tool(host, "avifdec", "AVIF", src, dest_dir)?
+    } else {
+        image::open(src)?
+    };
+
+    let mut file = File::create(&dest)?;
+    let encoder =
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut file, TRANSCODE_JPEG_QUALITY);
+    image.to_rgb8().write_with_encoder(encoder)?;
+
+    Ok(dest)
+}
+
+/// File name of a photo's JPEG conversion: its stem plus a hash of its full
+/// path, so same-named photos from different directories don't share one
+fn transcoded_name(src: &std::path::Path) -> String {
+    use sha2::{Digest, Sha256};
+
+    let stem = src.file_stem().and_then(|s| s.to_str()).unwrap_or("photo");
+    let full = src
+        .canonicalize()
+        .or_else(|_| std::path::absolute(src))
+        .unwrap_or_else(|_| src.to_path_buf());
+    let digest = Sha256::digest(full.as_os_str().as_encoded_bytes());
+    format!("{}-{:.16x}.jpg", stem, digest)
+}
+
+/// Re-encode a photo into `format` next to the original, then remove the original.
+/// Returns the path of the converted file, whose extension reflects the new format.
+pub fn convert_image(
+    path: &std::path::Path,
+    format: ConvertFormat,
+    quality: u8,
+) -> Result<PathBuf, PhotoError> {
+    convert_image_in(&LiveHost, path, format, quality, &converted_dir()?)
+}
+
+/// [`convert_image`], finding `avifdec` on `host`'s `PATH` and decoding into
+/// `scratch_dir`, so an interrupted decode can't leave a stray image among the photos
+fn convert_image_in(
+    host: &dyn HostPaths,
+    path: &std::path::Path,
+    format: ConvertFormat,
+    quality: u8,
+    scratch_dir: &std::path::Path,
+) -> Result<PathBuf, PhotoError> {
+    let dest = path.with_extension(format.extension());
+    let image = if is_avif(path) {
+        std::fs::create_dir_all(scratch_dir)?;
+        decode_with_tool(host, "avifdec", "AVIF", path, scratch_dir)?
+    } else {
+        image::open(path)?
+    };
+
+    let partial = PathBuf::from(format!("{}{}", dest.display(), PARTIAL_SUFFIX));
+    let in_progress = InProgressFile::track(&partial);
+    let mut file = io::BufWriter::new(File::create(&partial)?);
+    match format {
+        ConvertFormat::Jpeg => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(
+                &mut file,
+                quality.clamp(1, 100),
+            );
+            image.to_rgb8().write_with_encoder(encoder)?;
+        }
+    }
+    file.into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .sync_all()?;
+    std::fs::rename(&partial, &dest)?;
+    in_progress.finish();
+
+    if dest != path {
+        std::fs::remove_file(path)?;
+    }
+    Ok(dest)
+}
+
+/// Decode a photo with an external `<tool> <src> <out.png>` decoder
+fn decode_with_tool(
+    host: &dyn HostPaths,
+    tool: &str,
+    format_name: &str,
+    src: &std::path::Path,
+    dest_dir: &std::path::Path,
+) -> Result<image::DynamicImage, PhotoError> {
+    let program = find_command_in(host, tool).ok_or_else(|| {
+        PhotoError::Command(format!(
+            "{} is required to convert {} photos, but it isn't installed or on PATH",
+            tool, format_name
+        ))
+    })?;
+    let stem = src.file_stem().and_then(|s| s.to_str()).unwrap_or("photo");
+    let decoded = dest_dir.join(format!("{}.{}.png", stem, tool));
+    let output = Command::new(program)
+        .arg(src)
+        .arg(&decoded)
+        .output()
+        .map_err(|e| {
+            PhotoError::Command(format!("{} is required for {}: {}", tool, format_name, e))
+        })?;
+    if !output.status.success() {
+        return Err(PhotoError::Command(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    let image = image::open(&decoded);
+    let _ = std::fs::remove_file(&decoded);
+    Ok(image?)
+}
+
+// ============================================================================
+// Resized Wallpapers
+// ============================================================================
+
+/// How a photo is resized to a monitor's resolution before it is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeMode {
+    /// Scale to cover the screen and cut off the edges that don't fit
+    Crop,
+    /// Scale to fit inside the screen, with black bars on the sides left over
+    Fit,
+    /// Scale to the screen exactly, ignoring the aspect ratio
+    Stretch,
+}
+
+impl std::fmt::Display for ResizeMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Crop => write!(f, "crop"),
+            Self::Fit => write!(f, "fit"),
+            Self::Stretch => write!(f, "stretch"),
+        }
+    }
+}
+
+/// Where resized wallpapers are cached
+pub fn derived_dir() -> Result<PathBuf, PhotoError> {
+    Ok(PathBuf::from(cache_dir()?).join("derived"))
+}
+
+/// Where JPEG conversions of photos the desktop can't show are cached
+pub fn converted_dir() -> Result<PathBuf, PhotoError> {
+    Ok(PathBuf::from(cache_dir()?).join("converted"))
+}
+
+/// A copy of `src` resized to `(width, height)` with `mode`, cached in `cache_dir`
+///
+/// Cache entries are named after the source's content hash and the target, so a
+/// photo that has been resized for a monitor before is reused without decoding it.
+pub fn resize_for_monitor(
+    src: &Path,
+    (width, height): (u32, u32),
+    mode: ResizeMode,
+    cache_dir: &Path,
+) -> Result<PathBuf, PhotoError> {
+    let hash = hash_file(src)?;
+    let dest = cache_dir.join(format!("{}_{}x{}_{}.jpg", &hash[..16], width, height, mode));
+    if dest.exists() {
+        return Ok(dest);
+    }
+
+    let image = image::open(src)?;
+    let (width, height) = (width.max(1), height.max(1));
+    let filter = image::imageops::FilterType::Lanczos3;
+    let resized = match mode {
+        ResizeMode::Crop => image.resize_to_fill(width, height, filter).to_rgb8(),
+        ResizeMode::Stretch => image.resize_exact(width, height, filter).to_rgb8(),
+        ResizeMode::Fit => {
+            let scaled = image.resize(width, height, filter).to_rgb8();
+            let mut canvas = image::RgbImage::new(width, height);
+            image::imageops::overlay(
+                &mut canvas,
+                &scaled,
+                i64::from((width - scaled.width()) / 2),
+                i64::from((height - scaled.height()) / 2),
+            );
+            canvas
+        }
+    };
+
+    save_cached_jpeg(&resized, &dest)?;
+    Ok(dest)
+}
+
+/// Save `image` as a JPEG at `dest` under a temporary name first, so a cache
+/// entry is never half written
+fn save_cached_jpeg(image: &image::RgbImage, dest: &Path) -> Result<(), PhotoError> {
+    if let Some(dir) = dest.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let partial = dest.with_extension("jpg.partial");
+    let mut file = io::BufWriter::new(File::create(&partial)?);
+    let encoder =
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut file, TRANSCODE_JPEG_QUALITY);
+    image.write_with_encoder(encoder)?;
+    file.flush()?;
+    drop(file);
+    std::fs::rename(&partial, dest)?;
+    Ok(())
+}
+
+/// Files removed, or that would be removed, by [`purge_image_cache`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CachePurge {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Delete the resized and converted wallpapers cached in `cache_dir`, or only
+/// count them when `dry_run` is set
+pub fn purge_image_cache(cache_dir: &Path, dry_run: bool) -> Result<CachePurge, PhotoError> {
+    let mut purge = CachePurge::default();
+    for dir in [cache_dir.join("derived"), cache_dir.join("converted")] {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            if !dry_run {
+                std::fs::remove_file(entry.path())?;
+            }
+            purge.files += 1;
+            purge.bytes += metadata.len();
+        }
+    }
+    Ok(purge)
+}
+
+/// A backend that hands `inner` copies of each photo resized to the monitor
+/// they are set on
+///
+/// Wallpapers spanning every monitor are sized for the first one. A photo that
+/// can't be resized is applied as it is.
+pub struct ResizingBackend<'a> {
+    inner: &'a dyn WallpaperBackend,
+    monitors: &'a [MonitorInfo],
+    mode: ResizeMode,
+    cache_dir: PathBuf,
+}
+
+impl<'a> ResizingBackend<'a> {
+    pub fn new(
+        inner: &'a dyn WallpaperBackend,
+        monitors: &'a [MonitorInfo],
+        mode: ResizeMode,
+        cache_dir: PathBuf,
+    ) -> Self {
+        Self {
+            inner,
+            monitors,
+            mode,
+            cache_dir,
+        }
+    }
+
+    /// `photo` resized for monitor `index`, or `photo` itself if that fails
+    fn resized(&self, index: usize, photo: &Path) -> PathBuf {
+        let Some(monitor) = self.monitors.get(index).or_else(|| self.monitors.first()) else {
+            return photo.to_path_buf();
+        };
+        let size = monitor.size(PixelSpace::Physical);
+        match resize_for_monitor(photo, size, self.mode, &self.cache_dir) {
+            Ok(resized) => {
+                debug!(photo = %photo.display(), resized = %resized.display(), "Resized wallpaper");
+                resized
+            }
+            Err(e) => {
+                warn!("Could not resize {}: {}", photo.display(), e);
+                photo.to_path_buf()
+            }
+        }
+    }
+}
+
+impl WallpaperBackend for ResizingBackend<'_> {
+    fn desktop(&self) -> DesktopEnvironment {
+        self.inner.desktop()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn set(&self, target: Target, photo: &Path) -> Result<(), PhotoError> {
+        let index = match target {
+            Target::Monitor(index) => index,
+            Target::AllMonitors => 0,
+        };
+        self.inner.set(target, &self.resized(index, photo))
+    }
+
+    fn set_on_virtual_desktop(
+        &self,
+        desktop: usize,
+        monitor: Option<usize>,
+        photo: &Path,
+    ) -> Result<(), PhotoError> {
+        let resized = self.resized(monitor.unwrap_or(0), photo);
+        self.inner
+            .set_on_virtual_desktop(desktop, monitor, &resized)
+    }
+
+    fn activities(&self) -> Option<Vec<Activity>> {
+        self.inner.activities()
+    }
+
+    fn set_on_activity(&self, activity: &Activity, photo: &Path) -> Result<(), PhotoError> {
+        self.inner
+            .set_on_activity(activity, &self.resized(0, photo))
+    }
+}
+
+// ============================================================================
+// Spanned Wallpapers
+// ============================================================================
+
+/// The part of a spanned photo shown on one monitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanTile {
+    /// Left edge of the part, in photo pixels
+    pub x: u32,
+    /// Top edge of the part, in photo pixels
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// The monitor's physical resolution, which the part is scaled to
+    pub output: (u32, u32),
+}
+
+/// Cut a `photo`-sized image into one tile per monitor, so that together they
+/// cover the whole desktop
+///
+/// The photo is scaled to cover the bounding box of every monitor's layout and
+/// cropped evenly on the sides that don't fit. Tiles of neighbouring monitors
+/// share their edges exactly. `None` if any monitor's position is unknown.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+pub fn span_tiles(photo: (u32, u32), monitors: &[MonitorInfo]) -> Option<Vec<SpanTile>> {
+    let rects: Vec<LayoutRect> = monitors.iter().map(|m| m.layout).collect::<Option<_>>()?;
+    let left = rects.iter().map(|r| i64::from(r.x)).min()?;
+    let top = rects.iter().map(|r| i64::from(r.y)).min()?;
+    let right = rects
+        .iter()
+        .map(|r| i64::from(r.x) + i64::from(r.width))
+        .max()?;
+    let bottom = rects
+        .iter()
+        .map(|r| i64::from(r.y) + i64::from(r.height))
+        .max()?;
+    if right <= left || bottom <= top || photo.0 == 0 || photo.1 == 0 {
+        return None;
+    }
+
+    // Photo pixels per layout unit when the photo just covers the bounding box
+    let (pw, ph) = (f64::from(photo.0), f64::from(photo.1));
+    let (bw, bh) = ((right - left) as f64, (bottom - top) as f64);
+    let k = f64::min(pw / bw, ph / bh);
+    let (ox, oy) = (bw.mul_add(-k, pw) / 2.0, bh.mul_add(-k, ph) / 2.0);
+    let edge = |origin: f64, offset: i64, limit: u32| {
+        ((offset as f64).mul_add(k, origin).round().max(0.0) as u32).min(limit)
+    };
+
+    Some(
+        rects
+            .iter()
+            .zip(monitors)
+            .map(|(r, monitor)| {
+                let x0 = edge(ox, i64::from(r.x) - left, photo.0);
+                let x1 = edge(ox, i64::from(r.x) + i64::from(r.width) - left, photo.0);
+                let y0 = edge(oy, i64::from(r.y) - top, photo.1);
+                let y1 = edge(oy, i64::from(r.y) + i64::from(r.height) - top, photo.1);
+                SpanTile {
+                    x: x0,
+                    y: y0,
+                    width: (x1 - x0).max(1),
+                    height: (y1 - y0).max(1),
+                    output: monitor.size(PixelSpace::Physical),
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Cut `photo` into one image per monitor with [`span_tiles`], cached in
+/// `cache_dir` by the photo's content hash and each tile's geometry
+pub fn span_photo(
+    photo: &Path,
+    monitors: &[MonitorInfo],
+    cache_dir: &Path,
+) -> Result<Vec<PathBuf>, PhotoError> {
+    let tiles = span_tiles(image::image_dimensions(photo)?, monitors).ok_or_else(|| {
+        PhotoError::Wallpaper("monitor positions are unknown, so photos can't be spanned".into())
+    })?;
+    let hash = hash_file(photo)?;
+    let paths: Vec<PathBuf> = tiles
+        .iter()
+        .map(|t| {
+            cache_dir.join(format!(
+                "{}_span_{}_{}_{}x{}_{}x{}.jpg",
+                &hash[..16],
+                t.x,
+                t.y,
+                t.width,
+                t.height,
+                t.output.0,
+                t.output.1
+            ))
+        })
+        .collect();
+    if paths.iter().all(|p| p.exists()) {
+        return Ok(paths);
+    }
+
+    let image = image::open(photo)?;
+    for (tile, path) in tiles.iter().zip(&paths) {
+        if path.exists() {
+            continue;
+        }
+        let (width, height) = (tile.output.0.max(1), tile.output.1.max(1));
+        let part = image
+            .crop_imm(tile.x, tile.y, tile.width, tile.height)
+            .resize_exact(width, height, image::imageops::FilterType::Lanczos3)
+            .to_rgb8();
+        save_cached_jpeg(&part, path)?;
+    }
+    Ok(paths)
+}
+
+/// A backend that stretches each photo across every monitor
+///
+/// Desktops that can span a wallpaper themselves are asked to. Otherwise the
+/// photo is cut with [`span_photo`] and each monitor gets its own tile; a photo
+/// that can't be cut is applied as it is.
+pub struct SpanningBackend<'a> {
+    inner: &'a dyn WallpaperBackend,
+    monitors: &'a [MonitorInfo],
+    cache_dir: PathBuf,
+    /// The last photo cut and its tiles, so each monitor doesn't cut it again
+    tiles: std::cell::RefCell<Option<(PathBuf, Vec<PathBuf>)>>,
+}
+
+impl<'a> SpanningBackend<'a> {
+    pub fn new(
+        inner: &'a dyn WallpaperBackend,
+        monitors: &'a [MonitorInfo],
+        cache_dir: PathBuf,
+    ) -> Self {
+        Self {
+            inner,
+            monitors,
+            cache_dir,
+            tiles: std::cell::RefCell::new(None),
+        }
+    }
+
+    /// The tile of `photo` for monitor `index`, or `photo` itself if it can't be cut
+    fn tile(&self, index: usize, photo: &Path) -> PathBuf {
+        let mut tiles = self.tiles.borrow_mut();
+        if tiles.as_ref().is_none_or(|(cut, _)| cut != photo) {
+            match span_photo(photo, self.monitors, &self.cache_dir) {
+                Ok(paths) => *tiles = Some((photo.to_path_buf(), paths)),
+                Err(e) => {
+                    warn!("Could not span {}: {}", photo.display(), e);
+                    return photo.to_path_buf();
+                }
+            }
+        }
+        tiles
+            .as_ref()
+            .and_then(|(_, paths)| paths.get(index).cloned())
+            .unwrap_or_else(|| photo.to_path_buf())
+    }
+}
+
+impl WallpaperBackend for SpanningBackend<'_> {
+    fn desktop(&self) -> DesktopEnvironment {
+        self.inner.desktop()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn set(&self, target: Target, photo: &Path) -> Result<(), PhotoError> {
+        if self.inner.spans_natively() {
+            return self.inner.set_spanned(photo);
+        }
+        match target {
+            Target::Monitor(index) => self.inner.set(target, &self.tile(index, photo)),
+            Target::AllMonitors => self.inner.set(target, photo),
+        }
+    }
+}
+
+// ============================================================================
+// Monitor Enumeration
+// ============================================================================
+
+/// Which pixel space a dimension is measured in.
+/// Photo fitting always uses physical pixels; logical pixels are only meaningful
+/// for compositor layout (positions) and for display to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelSpace {
+    Physical,
+    Logical,
+}
+
+/// Where an output sits on the desktop, in the units the desktop lays outputs
+/// out in: logical pixels on Wayland, physical pixels on X11
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A connected output with its native resolution and scale factor
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorInfo {
+    pub name: String,
+    /// Width in physical pixels, as shown after any rotation
+    pub width: u32,
+    /// Height in physical pixels, as shown after any rotation
+    pub height: u32,
+    /// Scale factor (1.0 when unknown or unscaled)
+    pub scale: f64,
+    /// Position and size on the desktop, when the desktop reports it
+    pub layout: Option<LayoutRect>,
+}
+
+impl MonitorInfo {
+    /// The connector name, such as `DP-1`, if the desktop reported one
+    pub fn connector(&self) -> Option<&str> {
+        Some(self.name.as_str()).filter(|name| !name.is_empty() && *name != "unknown")
+    }
+
+    /// Dimensions of the monitor in the requested pixel space
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn size(&self, space: PixelSpace) -> (u32, u32) {
+        match space {
+            PixelSpace::Physical => (self.width, self.height),
+            PixelSpace::Logical => {
+                let scale = if self.scale > 0.0 { self.scale } else { 1.0 };
+                (
+                    (f64::from(self.width) / scale).round() as u32,
+                    (f64::from(self.height) / scale).round() as u32,
+                )
+            }
+        }
+    }
+
+    /// Whether the monitor is wider or taller; `None` for a square one
+    pub const fn orientation(&self) -> Option<Orientation> {
+        orientation_of((self.width, self.height))
+    }
+
+    /// This monitor placed at `position` on a desktop laid out in logical pixels
+    fn placed_logically(mut self, position: Option<(i32, i32)>) -> Self {
+        let (width, height) = self.size(PixelSpace::Logical);
+        self.layout = position.map(|(x, y)| LayoutRect {
+            x,
+            y,
+            width,
+            height,
+        });
+        self
+    }
+}
+
+/// `(width, height)` as shown on an output turned a quarter turn if `quarter_turn`
+const fn rotated(width: u32, height: u32, quarter_turn: bool) -> (u32, u32) {
+    if quarter_turn {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+impl std::fmt::Display for MonitorInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}x{}", self.name, self.width, self.height)?;
+        if (self.scale - 1.0).abs() > f64::EPSILON {
+            let (lw, lh) = self.size(PixelSpace::Logical);
+            write!(f, " @ {}x (logical {}x{})", self.scale, lw, lh)?;
+        }
+        Ok(())
+    }
+}
+
+/// How a photo maps onto a monitor when scaled to cover it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitQuality {
+    /// Photo matches the physical resolution exactly
+    Exact,
+    /// Photo has more pixels than needed and is scaled down
+    Downscaled,
+    /// Photo has fewer pixels than the panel and will look soft
+    Upscaled,
+}
+
+/// Decide how a photo fits a monitor, always comparing against physical pixels
+/// so a 200% scaled 4K panel still demands a 4K photo
+pub fn fit_quality(photo_width: u32, photo_height: u32, monitor: &MonitorInfo) -> FitQuality {
+    let (mw, mh) = monitor.size(PixelSpace::Physical);
+    // Cover scaling: the photo must fill both dimensions
+    let cover = f64::max(
+        f64::from(mw) / f64::from(photo_width.max(1)),
+        f64::from(mh) / f64::from(photo_height.max(1)),
+    );
+
+    if (cover - 1.0).abs() < 1e-9 {
+        FitQuality::Exact
+    } else if cover < 1.0 {
+        FitQuality::Downscaled
+    } else {
+        FitQuality::Upscaled
+    }
+}
+
+/// Parse `kscreen-doctor -j` output (KDE Plasma)
+pub fn parse_kscreen_outputs(json: &str) -> Vec<MonitorInfo> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(json) else {
+        return Vec::new();
+    };
+
+    let mut monitors = Vec::new();
+    for output in value["outputs"].as_array().into_iter().flatten() {
+        if output["enabled"] == false || output["connected"] == false {
+            continue;
+        }
+        let current_mode = output["currentModeId"].clone();
+        let Some(mode) = output["modes"]
+            .as_array()
+            .and_then(|modes| modes.iter().find(|m| m["id"] == current_mode))
+        else {
+            continue;
+        };
+        let (Some(width), Some(height)) = (
+            json_u32(&mode["size"]["width"]),
+            json_u32(&mode["size"]["height"]),
+        ) else {
+            continue;
+        };
+        // Modes are listed unrotated; rotation 2 is left and 8 is right
+        let (width, height) = rotated(
+            width,
+            height,
+            matches!(json_u32(&output["rotation"]), Some(2 | 8)),
+        );
+        let position = json_i32(&output["pos"]["x"]).zip(json_i32(&output["pos"]["y"]));
+        monitors.push(
+            MonitorInfo {
+                name: output["name"].as_str().unwrap_or("unknown").to_string(),
+                width,
+                height,
+                scale: output["scale"].as_f64().unwrap_or(1.0),
+                layout: None,
+            }
+            .placed_logically(position),
+        );
+    }
+    // Number monitors left to right, then top to bottom, as the Plasma scripts do
+    if monitors.iter().all(|m| m.layout.is_some()) {
+        monitors.sort_by_key(|m| m.layout.map(|l| (l.x, l.y)));
+    }
+    monitors
+}
+
+/// Parse `swaymsg -t get_outputs -r` output
+pub fn parse_sway_outputs(json: &str) -> Vec<MonitorInfo> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(json) else {
+        return Vec::new();
+    };
+
+    let mut monitors = Vec::new();
+    for output in value.as_array().into_iter().flatten() {
+        if output["active"] == false {
+            continue;
+        }
+        let (Some(width), Some(height)) = (
+            json_u32(&output["current_mode"]["width"]),
+            json_u32(&output["current_mode"]["height"]),
+        ) else {
+            continue;
+        };
+        // "90", "270", "flipped-90" and "flipped-270" turn the output on its side
+        let transform = output["transform"].as_str().unwrap_or("normal");
+        let (width, height) = rotated(
+            width,
+            height,
+            transform.ends_with("90") || transform.ends_with("270"),
+        );
+        let rect = &output["rect"];
+        let layout = (|| {
+            Some(LayoutRect {
+                x: json_i32(&rect["x"])?,
+                y: json_i32(&rect["y"])?,
+                width: json_u32(&rect["width"])?,
+                height: json_u32(&rect["height"])?,
+            })
+        })();
+        monitors.push(MonitorInfo {
+            name: output["name"].as_str().unwrap_or("unknown").to_string(),
+            width,
+            height,
+            scale: output["scale"].as_f64().unwrap_or(1.0),
+            layout,
+        });
+    }
+    monitors
+}
+
+/// Parse `hyprctl monitors -j` output (Hyprland)
+pub fn parse_hyprland_monitors(json: &str) -> Vec<MonitorInfo> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(json) else {
+        return Vec::new();
+    };
+
+    let mut monitors = Vec::new();
+    for output in value.as_array().into_iter().flatten() {
+        if output["disabled"] == true {
+            continue;
+        }
+        let (Some(name), Some(width), Some(height)) = (
+            output["name"].as_str(),
+            json_u32(&output["width"]),
+            json_u32(&output["height"]),
+        ) else {
+            continue;
+        };
+        // Odd transforms are a quarter turn, flipped or not
+        let transform = json_u32(&output["transform"]).unwrap_or(0);
+        let (width, height) = rotated(width, height, transform % 2 == 1);
+        let position = json_i32(&output["x"]).zip(json_i32(&output["y"]));
+        monitors.push(
+            MonitorInfo {
+                name: name.to_string(),
+                width,
+                height,
+                scale: output["scale"].as_f64().unwrap_or(1.0),
+                layout: None,
+            }
+            .placed_logically(position),
+        );
+    }
+    monitors
+}
+
+/// Parse `swww query` output, one output per line such as
+/// `DP-1: 2560x1440, scale: 1, currently displaying: ...`.
+/// Newer versions start each line with `: `.
+pub fn parse_swww_query(text: &str) -> Vec<MonitorInfo> {
+    let mut monitors = Vec::new();
+    for line in text.lines() {
+        let line = line.trim().trim_start_matches(':').trim_start();
+        let Some((name, rest)) = line.split_once(": ") else {
+            continue;
+        };
+        let mut fields = rest.split(',').map(str::trim);
+        let Some((width, height)) = fields
+            .next()
+            .and_then(|size| size.split_once('x'))
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+        else {
+            continue;
+        };
+        let scale = fields
+            .find_map(|f| f.strip_prefix("scale:"))
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(1.0);
+        monitors.push(MonitorInfo {
+            name: name.trim().to_string(),
+            width,
+            height,
+            scale,
+            layout: None,
+        });
+    }
+    monitors
+}
+
+/// Parse `xrandr --current` output. X11 has no per-output scale, so it is
+/// estimated from the physical size reported by the monitor.
+pub fn parse_xrandr_outputs(text: &str) -> Vec<MonitorInfo> {
+    let mut monitors = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some("connected")) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let rest: Vec<&str> = fields.collect();
+        // Geometry looks like 3840x2160+0+0; absent when the output is disabled
+        let Some(layout) = rest.iter().find_map(|f| parse_xrandr_geometry(f)) else {
+            continue;
+        };
+        let (width, height) = (layout.width, layout.height);
+        let width_mm = rest
+            .iter()
+            .filter_map(|f| f.strip_suffix("mm"))
+            .find_map(|mm| mm.parse::<u32>().ok());
+
+        monitors.push(MonitorInfo {
+            name: name.to_string(),
+            width,
+            height,
+            scale: width_mm.map_or(1.0, |mm| scale_from_dpi(width, mm)),
+            layout: Some(layout),
+        });
+    }
+    monitors
+}
+
+fn parse_xrandr_geometry(field: &str) -> Option<LayoutRect> {
+    let mut parts = field.split('+');
+    let (w, h) = parts.next()?.split_once('x')?;
+    let (x, y) = (parts.next()?, parts.next()?);
+    Some(LayoutRect {
+        x: x.parse().ok()?,
+        y: y.parse().ok()?,
+        width: w.parse().ok()?,
+        height: h.parse().ok()?,
+    })
+}
+
+/// Estimate a scale factor from horizontal DPI (96 DPI = 1.0), snapped to quarter steps
+pub fn scale_from_dpi(width_px: u32, width_mm: u32) -> f64 {
+    if width_mm == 0 {
+        return 1.0;
+    }
+    let dpi = f64::from(width_px) / (f64::from(width_mm) / 25.4);
+    let scale = (dpi / 96.0 * 4.0).round() / 4.0;
+    scale.clamp(1.0, 3.0)
+}
+
+fn json_u32(value: &serde_json::Value) -> Option<u32> {
+    value.as_u64().and_then(|v| u32::try_from(v).ok())
+}
+
+fn json_i32(value: &serde_json::Value) -> Option<i32> {
+    value.as_i64().and_then(|v| i32::try_from(v).ok())
+}
+
+fn command_stdout(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        None
+    }
+}
+
+/// Enumerate connected monitors with resolution and scale, trying the
+/// most precise source available for the desktop environment first
+pub fn enumerate_monitors(de: DesktopEnvironment) -> Vec<MonitorInfo> {
+    enumerate_monitors_with(de, &LiveCommands, &LiveSystem)
+}
+
+/// Enumerate monitors as `enumerate_monitors` does, running tools through `runner`
+pub fn enumerate_monitors_with(
+    de: DesktopEnvironment,
+    runner: &dyn CommandRunner,
+    probe: &dyn SystemProbe,
+) -> Vec<MonitorInfo> {
+    let command_stdout = |program: &str, args: &[&str]| runner.run(program, args).ok();
+    if matches!(
+        de,
+        DesktopEnvironment::KdePlasma6
+            | DesktopEnvironment::KdePlasma5
+            | DesktopEnvironment::PlasmaFallback
+    ) {
+        if let Some(json) = command_stdout("kscreen-doctor", &["-j"]) {
+            let monitors = parse_kscreen_outputs(&json);
+            if !monitors.is_empty() {
+                return monitors;
+            }
+        }
+    }
+
+    if de == DesktopEnvironment::Swww {
+        if let Some(text) = command_stdout("swww", &["query"]) {
+            let monitors = parse_swww_query(&text);
+            if !monitors.is_empty() {
+                return monitors;
+            }
+        }
+    }
+
+    if de == DesktopEnvironment::Hyprland {
+        if let Some(json) = command_stdout("hyprctl", &["monitors", "-j"]) {
+            let monitors = parse_hyprland_monitors(&json);
+            if !monitors.is_empty() {
+                return monitors;
+            }
+        }
+    }
+
+    if probe.env_var("SWAYSOCK").is_some() {
+        if let Some(json) = command_stdout("swaymsg", &["-t", "get_outputs", "-r"]) {
+            let monitors = parse_sway_outputs(&json);
+            if !monitors.is_empty() {
+                return monitors;
+            }
+        }
+    }
+
+    command_stdout("xrandr", &["--current"])
+        .map(|text| parse_xrandr_outputs(&text))
+        .unwrap_or_default()
+}
+
+/// Check whether a path has one of the supported photo extensions
+fn has_photo_extension(path: &std::path::Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| {
+            matches!(
+                ext.to_lowercase().as_str(),
+                "jpg" | "jpeg" | "png" | "gif" | "tif" | "tiff" | "jxl" | "webp" | "avif"
+            )
+        })
+}
+
+/// Parallel walk of a photo tree that follows symlinks.
+///
+/// A symlinked directory is not descended into when it resolves back into
+/// `dir` or into a directory already being walked, so a link such as
+/// `library/day/back -> library` neither loops nor lists photos twice.
+fn photo_walker(dir: &std::path::Path) -> jwalk::WalkDirGeneric<(Vec<PathBuf>, ())> {
+    let root = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    jwalk::WalkDirGeneric::<(Vec<PathBuf>, ())>::new(dir)
+        .skip_hidden(false)
+        .follow_links(true)
+        .process_read_dir(move |_, path, ancestors, children| {
+            if let Ok(real) = path.canonicalize() {
+                ancestors.push(real);
+            }
+            for entry in children.iter_mut().flatten() {
+                if !entry.path_is_symlink() || !entry.file_type().is_dir() {
+                    continue;
+                }
+                let loops = entry.path().canonicalize().map_or(true, |target| {
+                    target.starts_with(&root) || ancestors.contains(&target)
+                });
+                if loops {
+                    debug!("Not following {}", entry.path().display());
+                    entry.read_children_path = None;
+                }
+            }
+        })
+}
+
+/// Recursively collect photos from a directory.
+/// Directories are read in parallel and entries are classified using the file type
+/// returned by `readdir`, so the walk itself stats nothing but symlinks. Sorting
+/// the result may still stat photos; see [`sort_photos`].
+fn collect_photos(dir: &std::path::Path, photos: &mut Vec<PathBuf>) {
+    if !dir.is_dir() {
+        return;
+    }
+
+    let walker = photo_walker(dir).min_depth(1);
+
+    for entry in walker {
+        // A dangling symlink (such as a stale `current` link) shouldn't hide every other photo
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Skipping unreadable entry under {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        if has_photo_extension(&path) {
+            photos.push(path);
+        }
+    }
+}
+
+/// Find all photos in the photo directory, sorted newest first
+pub fn find_all_photos() -> Result<Vec<PathBuf>, PhotoError> {
+    find_photos_in_path(None)
+}
+
+/// Find photos in a specific path (file or directory), or default location if None
+pub fn find_photos_in_path(path: Option<&str>) -> Result<Vec<PathBuf>, PhotoError> {
+    find_photos_in_path_sorted(path, SortOrder::default())
+}
+
+/// Find photos in a path, ordered by `order`
+pub fn find_photos_in_path_sorted(
+    path: Option<&str>,
+    order: SortOrder,
+) -> Result<Vec<PathBuf>, PhotoError> {
+    let search_path = photo_dir_or(path)?;
+
+    let search_path_obj = std::path::Path::new(&search_path);
+
+    if !search_path_obj.exists() {
+        return Err(PhotoError::NoPhotos(format!(
+            "Path not found: {}",
+            search_path
+        )));
+    }
+
+    let mut photos: Vec<PathBuf> = Vec::new();
+
+    // If it's a single file, just use that
+    if search_path_obj.is_file() {
+        if has_photo_extension(search_path_obj) {
+            photos.push(search_path_obj.to_path_buf());
+        } else {
+            return Err(PhotoError::NoPhotos(format!(
+                "Not a supported image file: {}",
+                search_path
+            )));
+        }
+    } else {
+        // It's a directory, collect all photos recursively
+        collect_photos(search_path_obj, &mut photos);
+    }
+
+    if photos.is_empty() {
+        return Err(PhotoError::NoPhotos(format!(
+            "No photos found in {}",
+            search_path
+        )));
+    }
+
+    sort_photos(&mut photos, order);
+
+    Ok(photos)
+}
+
+/// Find photos in a path like [`find_photos_in_path_sorted`], keeping only
+/// those that pass `brightness`
+pub fn find_photos_in_path_filtered(
+    path: Option<&str>,
+    order: SortOrder,
+    brightness: Option<BrightnessFilter>,
+) -> Result<Vec<PathBuf>, PhotoError> {
+    let photos = find_photos_in_path_sorted(path, order)?;
+    filter_brightness(photos, path, brightness)
+}
+
+/// Keep the `photos` from `path` that pass `brightness`, failing if none do
+fn filter_brightness(
+    mut photos: Vec<PathBuf>,
+    path: Option<&str>,
+    brightness: Option<BrightnessFilter>,
+) -> Result<Vec<PathBuf>, PhotoError> {
+    let Some(filter) = brightness else {
+        return Ok(photos);
+    };
+    // Photos that can't be decoded can't be judged, so they are left out
+    photos.retain(|photo| photo_brightness(photo).is_ok_and(|luma| filter.matches(luma)));
+    if photos.is_empty() {
+        return Err(PhotoError::NoPhotos(format!(
+            "No {} photos found in {}",
+            filter,
+            photo_dir_or(path)?
+        )));
+    }
+    Ok(photos)
+}
+
+/// The photo in `path` to show while the desktop uses a dark color scheme
+///
+/// That is the first dark photo in `order`, or the darkest one if none is dark,
+/// or just the first one if no photo's brightness can be measured.
+pub fn pick_dark_photo(path: &str, order: SortOrder) -> Result<PathBuf, PhotoError> {
+    let photos = find_photos_in_path_sorted(Some(path), order)?;
+    let mut darkest: Option<(u8, &PathBuf)> = None;
+    for photo in &photos {
+        let Ok(luma) = photo_brightness(photo) else {
+            continue;
+        };
+        if BrightnessFilter::Dark.matches(luma) {
+            return Ok(photo.clone());
+        }
+        if darkest.is_none_or(|(darkest, _)| luma < darkest) {
+            darkest = Some((luma, photo));
+        }
+    }
+    darkest
+        .map(|(_, photo)| photo)
+        .or_else(|| photos.first())
+        .cloned()
+        .ok_or_else(|| PhotoError::NoPhotos(format!("No photos found in {}", expand_tilde(path))))
+}
+
+// ============================================================================
+// Photo Brightness
+// ============================================================================
+
+/// Average luminance below which a photo counts as dark
+pub const DARK_PHOTO_THRESHOLD: u8 = 100;
+
+/// Which photos to use by how bright they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrightnessFilter {
+    Dark,
+    Light,
+    /// Follow the desktop's light or dark color scheme
+    Auto,
+}
+
+impl std::fmt::Display for BrightnessFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dark => write!(f, "dark"),
+            Self::Light => write!(f, "light"),
+            Self::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl BrightnessFilter {
+    /// `Dark` or `Light`, asking the desktop `de` through `runner` for `Auto`.
+    /// `None` if the desktop's color scheme can't be read.
+    pub fn resolve(self, de: DesktopEnvironment, runner: &dyn CommandRunner) -> Option<Self> {
+        match self {
+            Self::Auto => {
+                system_prefers_dark(de, runner)
+                    .map(|dark| if dark { Self::Dark } else { Self::Light })
+            }
+            filter => Some(filter),
+        }
+    }
+
+    /// Whether a photo with average luminance `luma` passes; `Auto` passes everything
+    pub const fn matches(self, luma: u8) -> bool {
+        match self {
+            Self::Dark => luma < DARK_PHOTO_THRESHOLD,
+            Self::Light => luma >= DARK_PHOTO_THRESHOLD,
+            Self::Auto => true,
+        }
+    }
+}
+
+/// Whether the desktop `de` uses a dark color scheme: KDE's `ColorScheme` from
+/// `kdeglobals`, or GNOME's `color-scheme` everywhere else
+pub fn system_prefers_dark(de: DesktopEnvironment, runner: &dyn CommandRunner) -> Option<bool> {
+    match de {
+        DesktopEnvironment::KdePlasma6
+        | DesktopEnvironment::KdePlasma5
+        | DesktopEnvironment::PlasmaFallback => {
+            let tool = if de == DesktopEnvironment::KdePlasma5 {
+                "kreadconfig5"
+            } else {
+                "kreadconfig6"
+            };
+            let scheme = runner
+                .run(tool, &["--group", "General", "--key", "ColorScheme"])
+                .ok()?;
+            let scheme = scheme.trim();
+            (!scheme.is_empty()).then(|| scheme.to_lowercase().contains("dark"))
+        }
+        _ => {
+            let scheme = runner
+                .run(
+                    "gsettings",
+                    &["get", "org.gnome.desktop.interface", "color-scheme"],
+                )
+                .ok()?;
+            match scheme.trim().trim_matches('\'') {
+                "" => None,
+                scheme => Some(scheme == "prefer-dark"),
+            }
+        }
+    }
+}
+
+/// Average luminance of the photo at `path`, from 0 (black) to 255 (white),
+/// measured on a small thumbnail
+pub fn average_luminance(path: &Path) -> Result<u8, PhotoError> {
+    let thumbnail = image::open(path)?.thumbnail(64, 64).to_luma8();
+    let pixels = thumbnail.as_raw();
+    if pixels.is_empty() {
+        return Ok(0);
+    }
+    let total: u64 = pixels.iter().map(|&p| u64::from(p)).sum();
+    Ok(u8::try_from(total / pixels.len() as u64).unwrap_or(u8::MAX))
+}
+
+/// Average luminance of the photo at `path`, read from its metadata sidecar
+/// when it has been measured before
+///
+/// A fresh measurement is saved to the sidecar, if the photo has one.
+pub fn photo_brightness(path: &Path) -> Result<u8, PhotoError> {
+    let sidecar = metadata_path_for(path);
+    let mut metadata = read_metadata(&sidecar).ok();
+    if let Some(brightness) = metadata.as_ref().and_then(|m| m.brightness) {
+        return Ok(brightness);
+    }
+    let brightness = average_luminance(path)?;
+    if let Some(metadata) = &mut metadata {
+        metadata.brightness = Some(brightness);
+        if let Err(e) = write_metadata(&sidecar, metadata) {
+            debug!("Couldn't cache brightness in {}: {}", sidecar.display(), e);
+        }
+    }
+    Ok(brightness)
+}
+
+// ============================================================================
+// Photo Dimensions
+// ============================================================================
+
+/// Which way round a photo is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    /// Wider than tall
+    Landscape,
+    /// Taller than wide
+    Portrait,
+}
+
+impl std::fmt::Display for Orientation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Landscape => write!(f, "landscape"),
+            Self::Portrait => write!(f, "portrait"),
+        }
+    }
+}
+
+/// The orientation of a `(width, height)` photo; square photos have none
+pub const fn orientation_of((width, height): (u32, u32)) -> Option<Orientation> {
+    if width > height {
+        Some(Orientation::Landscape)
+    } else if height > width {
+        Some(Orientation::Portrait)
+    } else {
+        None
+    }
+}
+
+/// Which photos are big enough, and the right way round, to use as wallpapers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DimensionFilter {
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub orientation: Option<Orientation>,
+}
+
+impl std::fmt::Display for DimensionFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(orientation) = self.orientation {
+            parts.push(orientation.to_string());
+        }
+        if let Some(width) = self.min_width {
+            parts.push(format!("at least {}px wide", width));
+        }
+        if let Some(height) = self.min_height {
+            parts.push(format!("at least {}px tall", height));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+impl DimensionFilter {
+    /// Whether the filter lets every photo through
+    pub const fn is_empty(&self) -> bool {
+        self.min_width.is_none() && self.min_height.is_none() && self.orientation.is_none()
+    }
+
+    /// Whether a `(width, height)` photo passes
+    pub fn matches(&self, (width, height): (u32, u32)) -> bool {
+        self.min_width.is_none_or(|min| width >= min)
+            && self.min_height.is_none_or(|min| height >= min)
+            && self
+                .orientation
+                .is_none_or(|o| orientation_of((width, height)) == Some(o))
+    }
+}
+
+/// Pixel dimensions of the photo at `path`, from its metadata sidecar when that
+/// records them, otherwise read from the image header
+pub fn photo_dimensions(path: &Path) -> Result<(u32, u32), PhotoError> {
+    if let Ok(PhotoMetadata {
+        width: Some(width),
+        height: Some(height),
+        ..
+    }) = read_metadata(&metadata_path_for(path))
+    {
+        return Ok((width, height));
+    }
+    probe_dimensions(path)
+}
+
+/// The photos that pass `filter`, or all of them with a warning if none do
+fn keep_fitting_photos(
+    photos: Vec<PathBuf>,
+    filter: &DimensionFilter,
+) -> (Vec<PathBuf>, Option<String>) {
+    if filter.is_empty() {
+        return (photos, None);
+    }
+    let fitting = filter_by_dimensions(&photos, filter);
+    if fitting.is_empty() {
+        let warning = format!(
+            "No photos are {}; using all {} photos",
+            filter,
+            photos.len()
+        );
+        (photos, Some(warning))
+    } else {
+        (fitting, None)
+    }
+}
+
+/// The photos that pass `filter`, in order; photos whose size can't be read are dropped
+pub fn filter_by_dimensions(photos: &[PathBuf], filter: &DimensionFilter) -> Vec<PathBuf> {
+    photos
+        .iter()
+        .filter(|photo| photo_dimensions(photo).is_ok_and(|size| filter.matches(size)))
+        .cloned()
+        .collect()
+}
+
+// ============================================================================
+// Photo Listing Cache
+// ============================================================================
+
+/// Directories modified this close to the scan time are re-read on the next
+/// refresh, since coarse filesystem timestamps can hide a change made in the same tick
+const RACY_MTIME_WINDOW: Duration = Duration::from_secs(2);
+
+/// What a cache refresh had to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshOutcome {
+    /// No directory changed; the cached listing was reused
+    Unchanged,
+    /// Only the given number of changed directories were re-read
+    Partial(usize),
+    /// A directory vanished or could not be read, so the whole tree was rescanned
+    Full,
+}
+
+/// In-memory photo listing for long-running modes (daemon, watch).
+/// Freshness is checked by comparing directory mtimes, so a refresh costs one
+/// stat per directory instead of a full tree walk.
+#[derive(Debug)]
+pub struct PhotoListingCache {
+    root: PathBuf,
+    photos: Vec<PathBuf>,
+    dir_mtimes: HashMap<PathBuf, SystemTime>,
+    scanned_at: SystemTime,
+}
+
+impl PhotoListingCache {
+    /// Build the cache with a full scan of `root`
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, PhotoError> {
+        let mut cache = Self {
+            root: root.into(),
+            photos: Vec::new(),
+            dir_mtimes: HashMap::new(),
+            scanned_at: SystemTime::now(),
+        };
+        cache.full_rescan()?;
+        Ok(cache)
+    }
+
+    /// Cached photos, sorted newest first like `find_photos_in_path`
+    pub fn photos(&self) -> &[PathBuf] {
+        &self.photos
+    }
+
+    /// Bring the listing up to date, re-reading only directories whose mtime changed
+    pub fn refresh(&mut self) -> Result<RefreshOutcome, PhotoError> {
+        let mut changed = Vec::new();
+        for (dir, cached_mtime) in &self.dir_mtimes {
+            let Ok(mtime) = std::fs::metadata(dir).and_then(|m| m.modified()) else {
+                // A known directory disappeared; the listing can't be patched safely
+                self.full_rescan()?;
+                return Ok(RefreshOutcome::Full);
+            };
+            let racy = *cached_mtime + RACY_MTIME_WINDOW > self.scanned_at;
+            if mtime != *cached_mtime || racy {
+                changed.push(dir.clone());
+            }
+        }
+
+        self.scanned_at = SystemTime::now();
+        if changed.is_empty() {
+            return Ok(RefreshOutcome::Unchanged);
+        }
+
+        for dir in &changed {
+            if self.rescan_dir(dir).is_err() {
+                self.full_rescan()?;
+                return Ok(RefreshOutcome::Full);
+            }
+        }
+        sort_newest_first(&mut self.photos);
+
+        Ok(RefreshOutcome::Partial(changed.len()))
+    }
+
+    fn full_rescan(&mut self) -> Result<(), PhotoError> {
+        self.scanned_at = SystemTime::now();
+        self.photos.clear();
+        self.dir_mtimes.clear();
+        self.track_tree(&self.root.clone())?;
+        sort_newest_first(&mut self.photos);
+        Ok(())
+    }
+
+    /// Re-read the direct entries of one directory, walking any new subdirectories
+    fn rescan_dir(&mut self, dir: &std::path::Path) -> io::Result<()> {
+        let mtime = std::fs::metadata(dir)?.modified()?;
+        self.photos.retain(|p| p.parent() != Some(dir));
+
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() || path.is_dir() {
+                if entry.file_type()?.is_symlink() && self.links_back(&path) {
+                    continue;
+                }
+                if !self.dir_mtimes.contains_key(&path) {
+                    self.track_tree(&path)?;
+                }
+            } else if has_photo_extension(&path) {
+                self.photos.push(path);
+            }
+        }
+
+        self.dir_mtimes.insert(dir.to_path_buf(), mtime);
+        Ok(())
+    }
+
+    /// Whether the symlinked directory `link` leads back into the cached tree
+    fn links_back(&self, link: &std::path::Path) -> bool {
+        let root = self
+            .root
+            .canonicalize()
+            .unwrap_or_else(|_| self.root.clone());
+        link.canonicalize()
+            .map_or(true, |target| target.starts_with(root))
+    }
+
+    /// Walk a directory tree, recording every photo and every directory's mtime
+    fn track_tree(&mut self, dir: &std::path::Path) -> io::Result<()> {
+        if !dir.is_dir() {
+            return Ok(());
+        }
+        for entry in photo_walker(dir) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Skipping unreadable entry under {}: {}", dir.display(), e);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            if entry.file_type().is_dir() {
+                // A symlinked directory the walker wouldn't follow
+                if entry.read_children_path.is_none() {
+                    continue;
+                }
+                let mtime = entry.metadata()?.modified()?;
+                self.dir_mtimes.insert(path, mtime);
+            } else if has_photo_extension(&path) {
+                self.photos.push(path);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Format of the daily photo directories, chosen so names sort chronologically
+pub const DATE_DIR_FORMAT: &str = "%Y-%m-%d";
+
+/// Format used for daily photo directories before `DATE_DIR_FORMAT`
+const LEGACY_DATE_DIR_FORMAT: &str = "%d-%m-%Y";
+
+/// Parse a daily photo directory name in either the current or legacy format
+pub fn parse_date_dir(name: &str) -> Option<chrono::NaiveDate> {
+    [DATE_DIR_FORMAT, LEGACY_DATE_DIR_FORMAT]
+        .iter()
+        .find_map(|format| chrono::NaiveDate::parse_from_str(name, format).ok())
+}
+
+/// Directory for a day's photo under `base_dir`.
+/// Reuses an existing legacy-format directory so a photo isn't downloaded twice.
+pub fn date_dir_for(base_dir: &str, date: chrono::NaiveDate) -> String {
+    let legacy = format!("{}{}", base_dir, date.format(LEGACY_DATE_DIR_FORMAT));
+    if std::path::Path::new(&legacy).is_dir() {
+        legacy
+    } else {
+        format!("{}{}", base_dir, date.format(DATE_DIR_FORMAT))
+    }
+}
+
+/// Date of the directory a photo lives in, if it is a daily photo directory
+fn photo_dir_date(photo: &std::path::Path) -> Option<chrono::NaiveDate> {
+    photo
+        .parent()
+        .and_then(|dir| dir.file_name())
+        .and_then(|name| name.to_str())
+        .and_then(parse_date_dir)
+}
+
+/// How discovered photos are ordered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Newest date directory first; undated photos follow, newest mtime first
+    #[default]
+    Date,
+    /// Alphabetical by path
+    Name,
+    /// Most recently modified first
+    Mtime,
+    /// Shuffled
+    Random,
+}
+
+impl std::fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Date => write!(f, "date"),
+            Self::Name => write!(f, "name"),
+            Self::Mtime => write!(f, "mtime"),
+            Self::Random => write!(f, "random"),
+        }
+    }
+}
+
+fn modified_time(photo: &std::path::Path) -> Option<SystemTime> {
+    std::fs::metadata(photo).and_then(|m| m.modified()).ok()
+}
+
+/// Sort photos in place.
+/// `Date` stats only photos outside date directories; `Mtime` stats every photo.
+pub fn sort_photos(photos: &mut [PathBuf], order: SortOrder) {
+    match order {
+        SortOrder::Date => sort_newest_first(photos),
+        SortOrder::Name => photos.sort(),
+        SortOrder::Mtime => photos
+            .sort_by_cached_key(|photo| std::cmp::Reverse((modified_time(photo), photo.clone()))),
+        SortOrder::Random => photos.shuffle(&mut rand::thread_rng()),
+    }
+}
+
+/// Sort photos newest first: by date directory, then by path.
+/// Photos outside date directories come after dated ones, newest mtime first.
+fn sort_newest_first(photos: &mut [PathBuf]) {
+    photos.sort_by_cached_key(|photo| {
+        let date = photo_dir_date(photo);
+        let mtime = if date.is_none() {
+            modified_time(photo)
+        } else {
+            None
+        };
+        std::cmp::Reverse((date, mtime, photo.clone()))
+    });
+}
+
+// ============================================================================
+// Favorites and Exclusions
+// ============================================================================
+
+/// How many times likelier a favorite is picked at random unless the config says otherwise
+pub const DEFAULT_FAVORITE_WEIGHT: u32 = 3;
+
+/// A list of photos kept in the data directory, one path per line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhotoList {
+    Favorites,
+    Excluded,
+}
+
+impl PhotoList {
+    pub const fn file_name(self) -> &'static str {
+        match self {
+            Self::Favorites => "favorites.txt",
+            Self::Excluded => "excluded.txt",
+        }
+    }
+
+    /// Path of the list in `data_dir`
+    pub fn path_in(self, data_dir: &Path) -> PathBuf {
+        data_dir.join(self.file_name())
+    }
+
+    /// The photos on the list, skipping blank lines and `#` comments
+    pub fn read(self, data_dir: &Path) -> Vec<PathBuf> {
+        std::fs::read_to_string(self.path_in(data_dir))
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    /// Add `photo` to the list, returning whether it wasn't on it already
+    pub fn add(self, data_dir: &Path, photo: &Path) -> Result<bool, PhotoError> {
+        let mut photos = self.read(data_dir);
+        if photos.iter().any(|p| p == photo) {
+            return Ok(false);
+        }
+        photos.push(photo.to_path_buf());
+        self.write(data_dir, &photos)?;
+        Ok(true)
+    }
+
+    /// Take `photo` off the list, returning whether it was on it
+    pub fn remove(self, data_dir: &Path, photo: &Path) -> Result<bool, PhotoError> {
+        let mut photos = self.read(data_dir);
+        let before = photos.len();
+        photos.retain(|p| p != photo);
+        if photos.len() == before {
+            return Ok(false);
+        }
+        self.write(data_dir, &photos)?;
+        Ok(true)
+    }
+
+    fn write(self, data_dir: &Path, photos: &[PathBuf]) -> Result<(), PhotoError> {
+        std::fs::create_dir_all(data_dir)?;
+        let mut contents = String::new();
+        for photo in photos {
+            contents.push_str(&photo.to_string_lossy());
+            contents.push('\n');
+        }
+        std::fs::write(self.path_in(data_dir), contents)?;
+        Ok(())
+    }
+}
+
+/// The favorite and excluded photos, for picking wallpapers
+#[derive(Debug, Clone, Default)]
+pub struct PhotoLists {
+    favorites: std::collections::HashSet<PathBuf>,
+    excluded: std::collections::HashSet<PathBuf>,
+}
+
+impl PhotoLists {
+    /// Read both lists from `data_dir`
+    pub fn load(data_dir: &Path) -> Self {
+        Self {
+            favorites: PhotoList::Favorites.read(data_dir).into_iter().collect(),
+            excluded: PhotoList::Excluded.read(data_dir).into_iter().collect(),
+        }
+    }
+
+    /// Whether `photo` is on `set`, also trying its canonical path since the
+    /// lists hold canonical paths and photos may be found through symlinks
+    fn contains(set: &std::collections::HashSet<PathBuf>, photo: &Path) -> bool {
+        !set.is_empty()
+            && (set.contains(photo)
+                || photo
+                    .canonicalize()
+                    .is_ok_and(|canonical| set.contains(&canonical)))
+    }
+
+    pub fn is_favorite(&self, photo: &Path) -> bool {
+        Self::contains(&self.favorites, photo)
+    }
+
+    pub fn is_excluded(&self, photo: &Path) -> bool {
+        Self::contains(&self.excluded, photo)
+    }
+
+    /// Drop excluded photos, and with `favorites_only` everything but favorites
+    pub fn select(
+        &self,
+        mut photos: Vec<PathBuf>,
+        favorites_only: bool,
+    ) -> Result<Vec<PathBuf>, PhotoError> {
+        photos.retain(|photo| !self.is_excluded(photo));
+        if favorites_only {
+            photos.retain(|photo| self.is_favorite(photo));
+            if photos.is_empty() {
+                return Err(PhotoError::NoPhotos(
+                    "None of the photos are favorites; add some with `favorite`".to_string(),
+                ));
+            }
+        }
+        Ok(photos)
+    }
+
+    /// Shuffle `photos` so each favorite is `weight` times as likely as any
+    /// other photo to come before a given one
+    pub fn shuffle_weighted(
+        &self,
+        photos: Vec<PathBuf>,
+        weight: u32,
+        rng: &mut impl Rng,
+    ) -> Vec<PathBuf> {
+        // Weighted random sampling (Efraimidis-Spirakis): sort by u^(1/w)
+        let weight = f64::from(weight.max(1));
+        let mut keyed: Vec<(f64, PathBuf)> = photos
+            .into_iter()
+            .map(|photo| {
+                let w = if self.is_favorite(&photo) {
+                    weight
+                } else {
+                    1.0
+                };
+                (rng.gen::<f64>().powf(w.recip()), photo)
+            })
+            .collect();
+        keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+        keyed.into_iter().map(|(_, photo)| photo).collect()
+    }
+}
+
+/// Resolve a photo given on the command line: `current` is the wallpaper on the
+/// primary monitor, found through the `current` link or else the history
+pub fn resolve_photo_arg(
+    arg: &str,
+    current_link: &Path,
+    history: &RotationHistory,
+) -> Result<PathBuf, PhotoError> {
+    if arg == "current" {
+        return current_link
+            .canonicalize()
+            .ok()
+            .or_else(|| history.current().first().map(|photo| (*photo).clone()))
+            .ok_or_else(|| {
+                PhotoError::NoPhotos("No current wallpaper to resolve `current` to".to_string())
+            });
+    }
+    let path = PathBuf::from(expand_tilde(arg));
+    if !path.is_file() {
+        return Err(PhotoError::NoPhotos(format!("{} is not a photo", arg)));
+    }
+    Ok(path.canonicalize()?)
+}
+
+/// Find photos for the wallpaper like [`find_photos_in_path_filtered`], leaving
+/// out excluded photos and, with `favorites_only`, all but favorites
+///
+/// Random order favors favorites as much as the `favorite_weight` setting says.
+pub fn find_wallpaper_photos(
+    path: Option<&str>,
+    order: SortOrder,
+    brightness: Option<BrightnessFilter>,
+    favorites_only: bool,
+) -> Result<Vec<PathBuf>, PhotoError> {
+    let photos = find_photos_in_path_filtered(path, order, brightness)?;
+    select_wallpaper_photos(photos, order, favorites_only)
+}
+
+/// The part of [`find_wallpaper_photos`] that applies the favorites and exclusions
+fn select_wallpaper_photos(
+    photos: Vec<PathBuf>,
+    order: SortOrder,
+    favorites_only: bool,
+) -> Result<Vec<PathBuf>, PhotoError> {
+    let lists = PhotoLists::load(Path::new(&log_dir()?));
+    let photos = lists.select(photos, favorites_only)?;
+    if photos.is_empty() {
+        return Err(PhotoError::NoPhotos(
+            "Every photo found is excluded".to_string(),
+        ));
+    }
+    if order == SortOrder::Random && !favorites_only {
+        let weight = config()
+            .wallpaper
+            .favorite_weight
+            .unwrap_or(DEFAULT_FAVORITE_WEIGHT);
+        return Ok(lists.shuffle_weighted(photos, weight, &mut rand::thread_rng()));
+    }
+    Ok(photos)
+}
+
+// ============================================================================
+// Photo Selection
+// ============================================================================
+
+/// How photos are spread across assignments
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpreadStrategy {
+    /// Use photos in the order they were selected
+    #[default]
+    None,
+    /// At most one photo per calendar month until every month has been used
+    Monthly,
+}
+
+impl std::fmt::Display for SpreadStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Monthly => write!(f, "monthly"),
+        }
+    }
+}
+
+/// A calendar month as (year, month)
+pub type PhotoMonth = (i32, u32);
+
+/// Parse a month from a number token, accepting 1-12
+fn parse_month_number(token: &str) -> Option<u32> {
+    token.parse().ok().filter(|m| (1..=12).contains(m))
+}
+
+/// Parse a month from an English month name or its three-letter abbreviation
+fn parse_month_name(token: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = [
+        "january",
+        "february",
+        "march",
+        "april",
+        "may",
+        "june",
+        "july",
+        "august",
+        "september",
+        "october",
+        "november",
+        "december",
+    ];
+    let token = token.to_lowercase();
+    if token.len() < 3 {
+        return None;
+    }
+    MONTHS
+        .iter()
+        .position(|m| m.starts_with(&token) && (token.len() == 3 || *m == token))
+        .and_then(|i| u32::try_from(i + 1).ok())
+}
+
+/// Extract a month from a directory name such as `18-10-2018`, `2018-10-18`,
+/// or `best-photos-october-2018`
+pub fn parse_month_from_dir_name(name: &str) -> Option<PhotoMonth> {
+    let tokens: Vec<&str> = name
+        .split(['-', '_', ' '])
+        .filter(|t| !t.is_empty())
+        .collect();
+    let is_year = |t: &str| t.len() == 4 && t.chars().all(|c| c.is_ascii_digit());
+
+    // yyyy-mm[-dd]
+    if let [year, month, ..] = tokens.as_slice() {
+        if is_year(year) {
+            if let (Ok(y), Some(m)) = (year.parse(), parse_month_number(month)) {
+                return Some((y, m));
+            }
+        }
+    }
+
+    // dd-mm-yyyy
+    if let [day, month, year] = tokens.as_slice() {
+        if day.len() <= 2 && is_year(year) {
+            if let (Ok(y), Some(m)) = (year.parse(), parse_month_number(month)) {
+                return Some((y, m));
+            }
+        }
+    }
+
+    // <month name> ... <yyyy>
+    let month = tokens.iter().find_map(|t| parse_month_name(t))?;
+    let year = tokens.iter().find(|t| is_year(t))?.parse().ok()?;
+    Some((year, month))
+}
+
+/// The month a photo belongs to: from its nearest dated directory, else its mtime
+pub fn photo_month(path: &std::path::Path) -> Option<PhotoMonth> {
+    use chrono::Datelike;
+
+    let from_dirs = path
+        .ancestors()
+        .skip(1)
+        .filter_map(|dir| dir.file_name().and_then(|n| n.to_str()))
+        .find_map(parse_month_from_dir_name);
+    if from_dirs.is_some() {
+        return from_dirs;
+    }
+
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    let local: chrono::DateTime<Local> = modified.into();
+    Some((local.year(), local.month()))
+}
+
+/// Reorder photos so assignments use one photo per month, most recent month first.
+///
+/// Within a month the existing order is kept, so this composes with newest-first,
+/// random, and any filtering done beforehand. When there are more slots than months,
+/// later rounds take the next photo from each month, again most recent first.
+pub fn spread_by_month(photos: &[PathBuf]) -> Vec<PathBuf> {
+    let mut months: Vec<(Option<PhotoMonth>, Vec<&PathBuf>)> = Vec::new();
+    for photo in photos {
+        let month = photo_month(photo);
+        match months.iter_mut().find(|(m, _)| *m == month) {
+            Some((_, group)) => group.push(photo),
+            None => months.push((month, vec![photo])),
+        }
+    }
+    // Undated photos sort last since None < Some
+    months.sort_by_key(|(month, _)| std::cmp::Reverse(*month));
+
+    let rounds = months.iter().map(|(_, g)| g.len()).max().unwrap_or(0);
+    let mut spread = Vec::with_capacity(photos.len());
+    for round in 0..rounds {
+        for (_, group) in &months {
+            if let Some(photo) = group.get(round) {
+                spread.push((*photo).clone());
+            }
+        }
+    }
+    spread
+}
+
+/// Apply a spread strategy to an already selected photo list
+pub fn apply_spread(photos: Vec<PathBuf>, spread: SpreadStrategy) -> Vec<PathBuf> {
+    match spread {
+        SpreadStrategy::None => photos,
+        SpreadStrategy::Monthly => spread_by_month(&photos),
+    }
+}
+
+/// Wallpaper assignment for display
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WallpaperAssignment {
+    pub location: String,
+    pub photo_path: PathBuf,
+    pub is_newest: bool,
+    /// Position among every assignment of the mode, which tells the backend where to set it
+    #[serde(skip)]
+    pub slot: usize,
+}
+
+/// A wallpaper assignment and whether it was applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppliedWallpaper {
+    #[serde(flatten)]
+    pub assignment: WallpaperAssignment,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl From<&AssignmentResult> for AppliedWallpaper {
+    fn from(applied: &AssignmentResult) -> Self {
+        Self {
+            assignment: applied.assignment.clone(),
+            success: applied.result.is_ok(),
+            error: applied.result.as_ref().err().map(ToString::to_string),
+        }
+    }
+}
+
+/// Which monitors and virtual desktops to set, leaving the others as they are
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TargetFilter {
+    /// Monitor indices to set, or every monitor if empty
+    pub monitors: Vec<usize>,
+    /// Connector names of more monitors to set, such as `DP-1`
+    pub monitor_names: Vec<String>,
+    /// Virtual desktop indices to set, or every virtual desktop if empty
+    pub desktops: Vec<usize>,
+}
+
+impl TargetFilter {
+    /// Whether an assignment covering `monitor` of `desktop` is set, where
+    /// `None` means every monitor or virtual desktop
+    pub fn keeps(&self, monitor: Option<usize>, desktop: Option<usize>) -> bool {
+        let wanted = |targets: &[usize], index: Option<usize>| {
+            targets.is_empty() || index.is_some_and(|i| targets.contains(&i))
+        };
+        wanted(&self.monitors, monitor) && wanted(&self.desktops, desktop)
+    }
+
+    /// These targets with each monitor name turned into its index in
+    /// `monitors`, the desktop's own list of its monitors.
+    /// Fails if a name isn't there.
+    pub fn resolve_names(&self, monitors: &[MonitorInfo]) -> Result<Self, PhotoError> {
+        let mut resolved = Self {
+            monitors: self.monitors.clone(),
+            monitor_names: Vec::new(),
+            desktops: self.desktops.clone(),
+        };
+        for name in &self.monitor_names {
+            let Some(index) = monitors
+                .iter()
+                .position(|m| m.connector().is_some_and(|c| c.eq_ignore_ascii_case(name)))
+            else {
+                let known: Vec<&str> = monitors.iter().filter_map(MonitorInfo::connector).collect();
+                return Err(PhotoError::Config(if known.is_empty() {
+                    format!("There is no monitor named {name}: monitor names couldn't be read")
+                } else {
+                    format!(
+                        "There is no monitor named {name}: detected {}",
+                        known.join(", ")
+                    )
+                }));
+            };
+            if !resolved.monitors.contains(&index) {
+                resolved.monitors.push(index);
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Check the targets make sense in `mode`, with the detected monitor and virtual desktop counts
+    pub fn validate(
+        &self,
+        mode: WallpaperMode,
+        monitor_count: usize,
+        vd_count: usize,
+    ) -> Result<(), PhotoError> {
+        let (by_monitor, by_desktop) = match mode {
+            WallpaperMode::Monitors => (true, false),
+            WallpaperMode::VirtualDesktops => (false, true),
+            WallpaperMode::Both => (true, true),
+            WallpaperMode::Activities => (false, false),
+        };
+        if !by_monitor && !self.monitors.is_empty() {
+            return Err(PhotoError::Config(format!(
+                "--monitor can't be used with {mode} mode"
+            )));
+        }
+        if !by_desktop && !self.desktops.is_empty() {
+            return Err(PhotoError::Config(format!(
+                "--desktop needs virtual-desktops or both mode on a desktop that supports it, \
+                 but wallpapers are being set in {mode} mode"
+            )));
+        }
+        if let Some(monitor) = self.monitors.iter().find(|&&m| m >= monitor_count) {
+            return Err(PhotoError::Config(format!(
+                "There is no monitor {}: {} monitor(s) detected",
+                monitor + 1,
+                monitor_count
+            )));
+        }
+        if let Some(desktop) = self.desktops.iter().find(|&&d| d >= vd_count) {
+            return Err(PhotoError::Config(format!(
+                "There is no virtual desktop {}: {} virtual desktop(s) detected",
+                desktop + 1,
+                vd_count
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Where one assignment goes: its position among all of a mode's
+/// assignments, and the monitor and virtual desktop it covers
+struct AssignmentSlot {
+    location: String,
+    index: usize,
+    monitor: Option<usize>,
+    desktop: Option<usize>,
+}
+
+/// How monitor `index` is shown, with its connector name from `monitors` when known
+fn monitor_label(index: usize, monitors: &[MonitorInfo]) -> String {
+    match monitors.get(index).and_then(MonitorInfo::connector) {
+        Some(name) => format!("Monitor {} ({})", index + 1, name),
+        None => format!("Monitor {}", index + 1),
+    }
+}
+
+/// Every assignment `mode` makes, in order, with `vd_count` counting activities
+/// in activity mode. Monitors are labelled with their names in `monitors`, if any.
+fn assignment_slots(
+    mode: WallpaperMode,
+    monitor_count: usize,
+    vd_count: usize,
+    monitors: &[MonitorInfo],
+) -> Vec<AssignmentSlot> {
+    match mode {
+        WallpaperMode::Monitors => (0..monitor_count)
+            .map(|mon| AssignmentSlot {
+                location: monitor_label(mon, monitors),
+                index: mon,
+                monitor: Some(mon),
+                desktop: None,
+            })
+            .collect(),
+        WallpaperMode::VirtualDesktops => (0..vd_count)
+            .map(|vd| AssignmentSlot {
+                location: format!("Virtual Desktop {}", vd + 1),
+                index: vd,
+                monitor: None,
+                desktop: Some(vd),
+            })
+            .collect(),
+        WallpaperMode::Both => (0..vd_count)
+            .flat_map(|vd| {
+                (0..monitor_count).map(move |mon| AssignmentSlot {
+                    location: format!("{}, VD {}", monitor_label(mon, monitors), vd + 1),
+                    index: vd * monitor_count + mon,
+                    monitor: Some(mon),
+                    desktop: Some(vd),
+                })
+            })
+            .collect(),
+        WallpaperMode::Activities => (0..vd_count)
+            .map(|activity| AssignmentSlot {
+                location: format!("Activity {}", activity + 1),
+                index: activity,
+                monitor: None,
+                desktop: None,
+            })
+            .collect(),
+    }
+}
+
+/// Build wallpaper assignments based on mode, for the `targets` alone.
+/// In activity mode, `vd_count` is the number of activities.
+pub fn build_assignments(
+    mode: WallpaperMode,
+    photos: &[PathBuf],
+    monitor_count: usize,
+    vd_count: usize,
+    targets: &TargetFilter,
+) -> Vec<WallpaperAssignment> {
+    assignment_slots(mode, monitor_count, vd_count, &[])
+        .into_iter()
+        .filter(|slot| targets.keeps(slot.monitor, slot.desktop))
+        .enumerate()
+        .map(|(i, slot)| WallpaperAssignment {
+            location: slot.location,
+            photo_path: photos[i % photos.len()].clone(),
+            is_newest: i == 0,
+            slot: slot.index,
+        })
+        .collect()
+}
+
+/// How many assignments `build_assignments` makes for these targets
+pub fn assignment_count(
+    mode: WallpaperMode,
+    monitor_count: usize,
+    vd_count: usize,
+    targets: &TargetFilter,
+) -> usize {
+    assignment_slots(mode, monitor_count, vd_count, &[])
+        .into_iter()
+        .filter(|slot| targets.keeps(slot.monitor, slot.desktop))
+        .count()
+}
+
+/// Score of a photo whose size can't be read, worse than any real mismatch
+const UNKNOWN_ASPECT_SCORE: f64 = 10.0;
+
+/// Photos scoring within this much of the best fit count as equally good, so the
+/// selection order decides between them. A 3:2 photo on a 16:9 monitor scores 0.17.
+const ASPECT_TOLERANCE: f64 = 0.2;
+
+/// How many photos, in selection order, `build_assignments_smart` chooses from
+const SMART_CANDIDATES: usize = 50;
+
+/// How far apart the shapes of a `photo` and a `monitor` are: 0 when they have the
+/// same aspect ratio, growing as they differ
+///
+/// Symmetric, so a photo twice as wide as the monitor scores the same as one half as wide.
+pub fn aspect_score(photo: (u32, u32), monitor: (u32, u32)) -> f64 {
+    let ratio = |(width, height): (u32, u32)| f64::from(width.max(1)) / f64::from(height.max(1));
+    (ratio(photo) / ratio(monitor)).ln().abs()
+}
+
+/// Index of the photo to show on `monitor`, given each photo's size if known
+///
+/// Picks the first photo not `used` whose shape is within [`ASPECT_TOLERANCE`]
+/// of the best fit, preferring one that [`fit_quality`] says isn't upscaled on
+/// the monitor's physical pixels. `None` when every photo is used.
+pub fn pick_photo(
+    monitor: &MonitorInfo,
+    photos: &[Option<(u32, u32)>],
+    used: &[bool],
+) -> Option<usize> {
+    let physical = monitor.size(PixelSpace::Physical);
+    let score =
+        |i: usize| photos[i].map_or(UNKNOWN_ASPECT_SCORE, |size| aspect_score(size, physical));
+    let available: Vec<usize> = (0..photos.len())
+        .filter(|&i| !used.get(i).copied().unwrap_or(false))
+        .collect();
+    let best = available
+        .iter()
+        .map(|&i| score(i))
+        .fold(f64::INFINITY, f64::min);
+    let well_shaped: Vec<usize> = available
+        .into_iter()
+        .filter(|&i| score(i) <= best + ASPECT_TOLERANCE)
+        .collect();
+    let sharp = well_shaped.iter().copied().find(|&i| {
+        photos[i].is_none_or(|(w, h)| fit_quality(w, h, monitor) != FitQuality::Upscaled)
+    });
+    sharp.or_else(|| well_shaped.first().copied())
+}
+
+/// Build wallpaper assignments like [`build_assignments`], but give each monitor
+/// the photo whose shape suits it best, such as a portrait photo for a rotated
+/// monitor
+///
+/// Falls back to [`build_assignments`] in virtual desktop and activity modes,
+/// where one photo spans every monitor, and when `monitors` doesn't describe each of the
+/// `monitor_count` monitors.
+pub fn build_assignments_smart(
+    mode: WallpaperMode,
+    photos: &[PathBuf],
+    monitors: &[MonitorInfo],
+    monitor_count: usize,
+    vd_count: usize,
+    targets: &TargetFilter,
+) -> Vec<WallpaperAssignment> {
+    if photos.is_empty()
+        || monitors.is_empty()
+        || monitors.len() != monitor_count
+        || matches!(
+            mode,
+            WallpaperMode::VirtualDesktops | WallpaperMode::Activities
+        )
+    {
+        return build_assignments(mode, photos, monitor_count, vd_count, targets);
+    }
+    let slots: Vec<AssignmentSlot> = assignment_slots(mode, monitor_count, vd_count, monitors)
+        .into_iter()
+        .filter(|slot| targets.keeps(slot.monitor, slot.desktop))
+        .collect();
+
+    let candidates = &photos[..photos.len().min(SMART_CANDIDATES)];
+    let sizes: Vec<_> = candidates
+        .iter()
+        .map(|photo| photo_dimensions(photo).ok())
+        .collect();
+    let mut used = vec![false; candidates.len()];
+    let mut assignments = Vec::with_capacity(slots.len());
+    for slot in slots {
+        // Reuse photos once each has been shown
+        if used.iter().all(|&u| u) {
+            used.fill(false);
+        }
+        let monitor = &monitors[slot.monitor.unwrap_or(0)];
+        let Some(i) = pick_photo(monitor, &sizes, &used) else {
+            continue;
+        };
+        used[i] = true;
+        assignments.push(WallpaperAssignment {
+            location: slot.location,
+            photo_path: candidates[i].clone(),
+            is_newest: i == 0,
+            slot: slot.index,
+        });
+    }
+    assignments
+}
+
+/// Plasma script fragment that sets `screens` to Plasma's screen ids, ordered
+/// left to right and then top to bottom like [`parse_kscreen_outputs`]
+const PLASMA_SORTED_SCREENS: &str = r"var screens = [];
+for (var s = 0; s < screenCount; s++) {
+    screens.push(s);
+}
+screens.sort(function (a, b) {
+    var ga = screenGeometry(a);
+    var gb = screenGeometry(b);
+    return ga.x - gb.x || ga.y - gb.y;
+});";
+
+/// Plasma script that shows `photo_path` on monitor `monitor_idx`
+///
+/// `desktops()` lists containments in no particular order, and some setups have
+/// one per virtual desktop, so every containment on the monitor's screen is set.
+/// The script fails if there is no such monitor.
+pub fn plasma_wallpaper_script(monitor_idx: usize, photo_path: &std::path::Path) -> String {
+    format!(
+        r"{PLASMA_SORTED_SCREENS}
+var screen = screens[{idx}];
+if (screen === undefined) {{
+    throw new Error('No monitor {monitor}');
+}}
+var allDesktops = desktops();
+for (var i = 0; i < allDesktops.length; i++) {{
+    var d = allDesktops[i];
+    if (d.screen != screen) {{
+        continue;
+    }}
+    d.wallpaperPlugin = 'org.kde.image';
+    d.currentConfigGroup = Array('Wallpaper', 'org.kde.image', 'General');
+    d.writeConfig('Image', '{uri}');
+}}",
+        idx = monitor_idx,
+        monitor = monitor_idx + 1,
+        uri = js_string(&format!("file://{}", photo_path.to_string_lossy()))
+    )
+}
+
+/// Plasma script that prints each desktop containment's monitor index and virtual desktop
+///
+/// One containment per line. The desktop is -1 when a containment is shared by
+/// every virtual desktop, as it is unless Plasma is set up to give each virtual
+/// desktop its own wallpaper.
+pub fn plasma_containments_script() -> String {
+    format!(
+        r"{PLASMA_SORTED_SCREENS}
+var allDesktops = desktops();
+for (var i = 0; i < allDesktops.length; i++) {{
+    var d = allDesktops[i];
+    var desktop = typeof d.desktop === 'number' ? d.desktop : -1;
+    print(screens.indexOf(d.screen) + ' ' + desktop);
+}}"
+    )
+}
+
+/// A Plasma desktop containment: the wallpaper of one screen, and of one
+/// virtual desktop when Plasma keeps them apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlasmaContainment {
+    /// Index of the monitor, ordered by position, or `None` if it isn't on one
+    pub monitor: Option<usize>,
+    /// The virtual desktop, or `None` when shared by all of them
+    pub virtual_desktop: Option<usize>,
+}
+
+/// Parse the output of [`plasma_containments_script`], skipping lines it didn't write
+pub fn parse_plasma_containments(output: &str) -> Vec<PlasmaContainment> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let monitor: i64 = fields.next()?.parse().ok()?;
+            let desktop: i64 = fields.next()?.parse().ok()?;
+            Some(PlasmaContainment {
+                monitor: usize::try_from(monitor).ok(),
+                virtual_desktop: usize::try_from(desktop).ok(),
+            })
+        })
+        .collect()
+}
+
+/// Whether Plasma has a containment per virtual desktop, so each can show its own wallpaper
+pub fn has_per_desktop_containments(containments: &[PlasmaContainment]) -> bool {
+    containments.iter().any(|c| c.virtual_desktop.is_some())
+}
+
+/// Why virtual desktop wallpapers fell back to monitors on Plasma, and how to fix it
+pub const PLASMA_SHARED_DESKTOPS_WARNING: &str = "Plasma shows the same wallpaper on every \
+virtual desktop, so photos were set per monitor instead. To give each virtual desktop its own \
+wallpaper (Plasma 6.1+), right-click the desktop, open Desktop and Wallpaper, turn on a \
+different wallpaper for each virtual desktop, and run this again";
+
+/// Plasma script that shows `photo_path` on virtual desktop `desktop_idx`, on
+/// monitor `monitor_idx` or on all of them.
+/// The script fails if Plasma has no containment for that desktop.
+pub fn plasma_virtual_desktop_script(
+    desktop_idx: usize,
+    monitor_idx: Option<usize>,
+    photo_path: &std::path::Path,
+) -> String {
+    let (screen, on_screen) = monitor_idx.map_or_else(Default::default, |idx| {
+        (
+            format!(
+                r"var screen = screens[{idx}];
+if (screen === undefined) {{
+    throw new Error('No monitor {monitor}');
+}}
+",
+                monitor = idx + 1
+            ),
+            " || d.screen != screen",
+        )
+    });
+    format!(
+        r"{PLASMA_SORTED_SCREENS}
+{screen}var set = 0;
+var allDesktops = desktops();
+for (var i = 0; i < allDesktops.length; i++) {{
+    var d = allDesktops[i];
+    if (d.desktop !== {desktop_idx}{on_screen}) {{
+        continue;
+    }}
+    d.wallpaperPlugin = 'org.kde.image';
+    d.currentConfigGroup = Array('Wallpaper', 'org.kde.image', 'General');
+    d.writeConfig('Image', '{uri}');
+    set++;
+}}
+if (set == 0) {{
+    throw new Error('No containment for virtual desktop {desktop}');
+}}",
+        desktop = desktop_idx + 1,
+        uri = js_string(&format!("file://{}", photo_path.to_string_lossy()))
+    )
+}
+
+/// Plasma script that shows `photo_path` on every monitor of activity `activity_id`.
+/// The script fails if the activity has no desktops.
+pub fn plasma_activity_script(activity_id: &str, photo_path: &std::path::Path) -> String {
+    format!(
+        r"var set = 0;
+var allDesktops = desktops();
+for (var i = 0; i < allDesktops.length; i++) {{
+    var d = allDesktops[i];
+    if (d.activity != '{id}') {{
+        continue;
+    }}
+    d.wallpaperPlugin = 'org.kde.image';
+    d.currentConfigGroup = Array('Wallpaper', 'org.kde.image', 'General');
+    d.writeConfig('Image', '{uri}');
+    set++;
+}}
+if (set == 0) {{
+    throw new Error('No desktops in activity {id}');
+}}",
+        id = js_string(activity_id),
+        uri = js_string(&format!("file://{}", photo_path.to_string_lossy()))
+    )
+}
+
+/// Plasma script that prints how many screens have a desktop containment.
+/// Containments are counted by distinct screen, since there may be several per screen.
+pub const PLASMA_SCREEN_COUNT_SCRIPT: &str = r"var seen = {};
+var count = 0;
+var allDesktops = desktops();
+for (var i = 0; i < allDesktops.length; i++) {
+    var screen = allDesktops[i].screen;
+    if (screen >= 0 && !seen[screen]) {
+        seen[screen] = true;
+        count++;
+    }
+}
+print(count);";
+
+/// Set wallpaper for a specific monitor in KDE Plasma
+fn set_wallpaper_plasma(
+    transport: PlasmaTransport,
+    monitor_idx: usize,
+    photo_path: &std::path::Path,
+) -> Result<(), PhotoError> {
+    transport
+        .evaluate_script(
+            &plasma_wallpaper_script(monitor_idx, photo_path),
+            &LiveCommands,
+        )
+        .map(drop)
+}
+
+/// `text` escaped for a single-quoted string in a Plasma script
+pub fn js_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\'' => escaped.push_str("\\'"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\u{2028}' => escaped.push_str("\\u2028"),
+            '\u{2029}' => escaped.push_str("\\u2029"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Plasma script that turns every desktop into a slideshow of the photos in
+/// `dir` and its subdirectories, changing every `interval_secs` seconds
+pub fn plasma_slideshow_script(dir: &std::path::Path, interval_secs: u64) -> String {
+    format!(
+        r"var allDesktops = desktops();
+for (var i = 0; i < allDesktops.length; i++) {{
+    var d = allDesktops[i];
+    d.wallpaperPlugin = 'org.kde.slideshow';
+    d.currentConfigGroup = Array('Wallpaper', 'org.kde.slideshow', 'General');
+    d.writeConfig('SlidePaths', '{path}');
+    d.writeConfig('SlideInterval', {interval_secs});
+}}",
+        path = js_string(&dir.to_string_lossy()),
+    )
+}
+
+/// Have Plasma rotate the photos in `dir` itself, every `interval_secs` seconds,
+/// on each of its desktops
+pub fn set_plasma_slideshow(
+    transport: PlasmaTransport,
+    dir: &std::path::Path,
+    interval_secs: u64,
+    runner: &dyn CommandRunner,
+) -> Result<(), PhotoError> {
+    if !dir.is_dir() {
+        return Err(PhotoError::NoPhotos(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+    transport
+        .evaluate_script(&plasma_slideshow_script(dir, interval_secs), runner)
+        .map(drop)
+}
+
+/// `gsettings` argument lists that show `photo_path` on a gsettings-based desktop,
+/// scaled as `picture_options` says (`zoom`, `spanned`, ...)
+///
+/// GNOME and Cinnamon take a `file://` URI, MATE a plain path. GNOME shows
+/// `dark_photo`, if given, while its dark style is on.
+pub fn gsettings_wallpaper_args(
+    de: DesktopEnvironment,
+    photo_path: &std::path::Path,
+    dark_photo: Option<&std::path::Path>,
+    picture_options: &str,
+) -> Vec<[String; 4]> {
+    let path = photo_path.to_string_lossy();
+    let uri = format!("file://{path}");
+    let dark_uri = dark_photo.map_or_else(
+        || uri.clone(),
+        |dark| format!("file://{}", dark.to_string_lossy()),
+    );
+    let settings: &[(&str, &str, &str)] = match de {
+        // Set both light and dark mode wallpapers
+        DesktopEnvironment::Gnome => &[
+            (
+                "org.gnome.desktop.background",
+                "picture-options",
+                picture_options,
+            ),
+            ("org.gnome.desktop.background", "picture-uri", &uri),
+            (
+                "org.gnome.desktop.background",
+                "picture-uri-dark",
+                &dark_uri,
+            ),
+        ],
+        DesktopEnvironment::Cinnamon => &[
+            (
+                "org.cinnamon.desktop.background",
+                "picture-options",
+                picture_options,
+            ),
+            ("org.cinnamon.desktop.background", "picture-uri", &uri),
+        ],
+        DesktopEnvironment::Mate => &[
+            ("org.mate.background", "picture-options", picture_options),
+            ("org.mate.background", "picture-filename", &path),
+        ],
+        _ => &[],
+    };
+    settings
+        .iter()
+        .map(|(schema, key, value)| ["set", schema, key, value].map(String::from))
+        .collect()
+}
+
+// ============================================================================
+// Wallpaper Backends
+// ============================================================================
+
+/// What a wallpaper backend can do on this desktop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Each monitor can show a different wallpaper
+    pub per_monitor: bool,
+    /// Each virtual desktop can show a different wallpaper
+    pub per_virtual_desktop: bool,
+    pub monitors: usize,
+    pub virtual_desktops: usize,
+}
+
+/// Where a wallpaper is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// One monitor, by index
+    Monitor(usize),
+    /// Every monitor, or the whole desktop when monitors can't be told apart
+    AllMonitors,
+}
+
+/// A way of setting wallpapers on a particular desktop
+pub trait WallpaperBackend {
+    /// The desktop this backend drives
+    fn desktop(&self) -> DesktopEnvironment;
+
+    fn capabilities(&self) -> Capabilities;
+
+    fn set(&self, target: Target, photo: &std::path::Path) -> Result<(), PhotoError>;
+
+    /// Set the wallpaper of one virtual desktop, on one monitor or all of them.
+    /// Backends that can't tell desktops apart set the monitor instead.
+    fn set_on_virtual_desktop(
+        &self,
+        desktop: usize,
+        monitor: Option<usize>,
+        photo: &std::path::Path,
+    ) -> Result<(), PhotoError> {
+        let _ = desktop;
+        self.set(monitor.map_or(Target::AllMonitors, Target::Monitor), photo)
+    }
+
+    /// The desktop's activities, the current one first, or `None` if it has none
+    fn activities(&self) -> Option<Vec<Activity>> {
+        None
+    }
+
+    /// Set the wallpaper of every monitor in `activity`
+    fn set_on_activity(
+        &self,
+        activity: &Activity,
+        photo: &std::path::Path,
+    ) -> Result<(), PhotoError> {
+        let _ = photo;
+        Err(PhotoError::Wallpaper(format!(
+            "{:?} has no activity {}",
+            self.desktop(),
+            activity.name
+        )))
+    }
+
+    /// Whether the desktop can stretch one wallpaper across every monitor itself
+    fn spans_natively(&self) -> bool {
+        false
+    }
+
+    /// Stretch `photo` across every monitor. Only called when `spans_natively` is true.
+    fn set_spanned(&self, photo: &std::path::Path) -> Result<(), PhotoError> {
+        self.set(Target::AllMonitors, photo)
+    }
+}
+
+/// KDE Plasma 6, over D-Bus or `qdbus6`
+pub struct KdePlasma6Backend {
+    transport: PlasmaTransport,
+    monitors: usize,
+    virtual_desktops: usize,
+    /// Each virtual desktop has its own containments
+    per_desktop_containments: bool,
+}
+
+impl WallpaperBackend for KdePlasma6Backend {
+    fn desktop(&self) -> DesktopEnvironment {
+        DesktopEnvironment::KdePlasma6
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            per_monitor: true,
+            per_virtual_desktop: self.per_desktop_containments,
+            monitors: self.monitors,
+            virtual_desktops: self.virtual_desktops,
+        }
+    }
+
+    fn set(&self, target: Target, photo: &std::path::Path) -> Result<(), PhotoError> {
+        match target {
+            Target::Monitor(idx) => set_wallpaper_plasma(self.transport, idx, photo),
+            Target::AllMonitors => {
+                // Try every monitor, reporting the first failure
+                let mut result = Ok(());
+                for idx in 0..self.monitors {
+                    let set = set_wallpaper_plasma(self.transport, idx, photo);
+                    if result.is_ok() {
+                        result = set;
+                    }
+                }
+                result
+            }
+        }
+    }
+
+    fn set_on_virtual_desktop(
+        &self,
+        desktop: usize,
+        monitor: Option<usize>,
+        photo: &std::path::Path,
+    ) -> Result<(), PhotoError> {
+        if !self.per_desktop_containments {
+            return self.set(monitor.map_or(Target::AllMonitors, Target::Monitor), photo);
+        }
+        self.transport
+            .evaluate_script(
+                &plasma_virtual_desktop_script(desktop, monitor, photo),
+                &LiveCommands,
+            )
+            .map(drop)
+    }
+
+    fn activities(&self) -> Option<Vec<Activity>> {
+        self.transport.activities(&LiveCommands)
+    }
+
+    fn set_on_activity(
+        &self,
+        activity: &Activity,
+        photo: &std::path::Path,
+    ) -> Result<(), PhotoError> {
+        self.transport
+            .evaluate_script(&plasma_activity_script(&activity.id, photo), &LiveCommands)
+            .map(drop)
+    }
+}
+
+/// KDE Plasma 5, over D-Bus or `qdbus`
+pub struct KdePlasma5Backend {
+    transport: PlasmaTransport,
+    monitors: usize,
+}
+
+impl WallpaperBackend for KdePlasma5Backend {
+    fn desktop(&self) -> DesktopEnvironment {
+        DesktopEnvironment::KdePlasma5
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            per_monitor: true,
+            per_virtual_desktop: false,
+            monitors: self.monitors,
+            virtual_desktops: 1,
+        }
+    }
+
+    fn set(&self, target: Target, photo: &std::path::Path) -> Result<(), PhotoError> {
+        match target {
+            Target::Monitor(idx) => set_wallpaper_plasma(self.transport, idx, photo),
+            Target::AllMonitors => {
+                let mut result = Ok(());
+                for idx in 0..self.monitors {
+                    let set = set_wallpaper_plasma(self.transport, idx, photo);
+                    if result.is_ok() {
+                        result = set;
+                    }
+                }
+                result
+            }
+        }
+    }
+
+    fn activities(&self) -> Option<Vec<Activity>> {
+        self.transport.activities(&LiveCommands)
+    }
+
+    fn set_on_activity(
+        &self,
+        activity: &Activity,
+        photo: &std::path::Path,
+    ) -> Result<(), PhotoError> {
+        self.transport
+            .evaluate_script(&plasma_activity_script(&activity.id, photo), &LiveCommands)
+            .map(drop)
+    }
+}
+
+/// KDE Plasma through `plasma-apply-wallpaperimage`, which sets one wallpaper everywhere
+pub struct PlasmaApplyBackend {
+    runner: Box<dyn CommandRunner>,
+}
+
+impl PlasmaApplyBackend {
+    pub fn new(runner: Box<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
+}
+
+impl WallpaperBackend for PlasmaApplyBackend {
+    fn desktop(&self) -> DesktopEnvironment {
+        DesktopEnvironment::PlasmaFallback
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            per_monitor: false,
+            per_virtual_desktop: false,
+            monitors: 1,
+            virtual_desktops: 1,
+        }
+    }
+
+    fn set(&self, _target: Target, photo: &std::path::Path) -> Result<(), PhotoError> {
+        self.runner
+            .run("plasma-apply-wallpaperimage", &[&photo.to_string_lossy()])
+            .map(drop)
+    }
+}
+
+/// GNOME, Cinnamon and MATE through `gsettings`
+pub struct GsettingsBackend {
+    desktop: DesktopEnvironment,
+    runner: Box<dyn CommandRunner>,
+    fit: FitMode,
+    /// Shown instead while GNOME's dark style is on
+    dark_photo: Option<PathBuf>,
+}
+
+impl GsettingsBackend {
+    pub fn new(
+        desktop: DesktopEnvironment,
+        runner: Box<dyn CommandRunner>,
+        fit: FitMode,
+        dark_photo: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            desktop,
+            runner,
+            fit,
+            dark_photo,
+        }
+    }
+
+    fn apply(&self, photo: &std::path::Path, picture_options: &str) -> Result<(), PhotoError> {
+        let dark_photo = self.dark_photo.as_deref();
+        for args in gsettings_wallpaper_args(self.desktop, photo, dark_photo, picture_options) {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            self.runner.run("gsettings", &args)?;
+        }
+        Ok(())
+    }
+}
+
+impl WallpaperBackend for GsettingsBackend {
+    fn desktop(&self) -> DesktopEnvironment {
+        self.desktop
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            per_monitor: false,
+            per_virtual_desktop: false,
+            monitors: 1,
+            virtual_desktops: 1,
+        }
+    }
+
+    fn set(&self, _target: Target, photo: &std::path::Path) -> Result<(), PhotoError> {
+        self.apply(photo, self.fit.gsettings_option())
+    }
+
+    fn spans_natively(&self) -> bool {
+        true
+    }
+
+    fn set_spanned(&self, photo: &std::path::Path) -> Result<(), PhotoError> {
+        self.apply(photo, "spanned")
+    }
+}
+
+/// Hyprland via hyprpaper's `hyprctl hyprpaper` IPC
+pub struct HyprlandBackend {
+    runner: Box<dyn CommandRunner>,
+    /// Monitor names, in the order `hyprctl monitors` lists them
+    monitors: Vec<String>,
+}
+
+impl HyprlandBackend {
+    /// Create a backend, asking Hyprland for its monitors
+    pub fn new(runner: Box<dyn CommandRunner>) -> Self {
+        let monitors = runner
+            .run("hyprctl", &["monitors", "-j"])
+            .map(|json| {
+                parse_hyprland_monitors(&json)
+                    .into_iter()
+                    .map(|m| m.name)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { runner, monitors }
+    }
+
+    /// `hyprctl` arguments that show `photo` on `monitor` (empty for all monitors).
+    ///
+    /// The photo is preloaded first, and images no longer shown are unloaded
+    /// afterwards so hyprpaper's memory doesn't grow with every change.
+    pub fn commands(monitor: &str, photo: &std::path::Path) -> [Vec<String>; 3] {
+        let photo = photo.to_string_lossy();
+        [
+            vec!["hyprpaper".into(), "preload".into(), photo.to_string()],
+            vec![
+                "hyprpaper".into(),
+                "wallpaper".into(),
+                format!("{},{}", monitor, photo),
+            ],
+            vec!["hyprpaper".into(), "unload".into(), "all".into()],
+        ]
+    }
+}
+
+impl WallpaperBackend for HyprlandBackend {
+    fn desktop(&self) -> DesktopEnvironment {
+        DesktopEnvironment::Hyprland
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            per_monitor: !self.monitors.is_empty(),
+            per_virtual_desktop: false,
+            monitors: self.monitors.len().max(1),
+            virtual_desktops: 1,
+        }
+    }
+
+    fn set(&self, target: Target, photo: &std::path::Path) -> Result<(), PhotoError> {
+        let monitor = match target {
+            Target::Monitor(idx) => self.monitors.get(idx).ok_or_else(|| {
+                PhotoError::Wallpaper(format!("Hyprland has no monitor {}", idx + 1))
+            })?,
+            Target::AllMonitors => "",
+        };
+        for args in Self::commands(monitor, photo) {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            self.runner.run("hyprctl", &args)?;
+        }
+        Ok(())
+    }
+}
+
+/// Transition types `swww img` accepts
+pub const SWWW_TRANSITIONS: &[&str] = &[
+    "none", "simple", "fade", "left", "right", "top", "bottom", "wipe", "wave", "grow", "center",
+    "any", "outer", "random",
+];
+
+/// How backends that animate wallpaper changes should do it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransitionOptions {
+    /// One of `SWWW_TRANSITIONS`; the backend's default when `None`
+    pub kind: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+/// swww, for Wayland compositors
+pub struct SwwwBackend {
+    runner: Box<dyn CommandRunner>,
+    /// Output names from `swww query`, or `None` if the daemon didn't answer
+    outputs: Option<Vec<String>>,
+    transition: TransitionOptions,
+}
+
+impl SwwwBackend {
+    /// Create a backend, asking the swww daemon for its outputs
+    pub fn new(runner: Box<dyn CommandRunner>, transition: TransitionOptions) -> Self {
+        let outputs = runner.run("swww", &["query"]).ok().map(|text| {
+            parse_swww_query(&text)
+                .into_iter()
+                .map(|m| m.name)
+                .collect()
+        });
+        Self {
+            runner,
+            outputs,
+            transition,
+        }
+    }
+
+    /// `swww` arguments that show `photo` on `output`, or on every output if `None`
+    pub fn img_args(
+        photo: &std::path::Path,
+        output: Option<&str>,
+        transition: &TransitionOptions,
+    ) -> Vec<String> {
+        let mut args = vec!["img".to_string(), photo.to_string_lossy().into_owned()];
+        if let Some(output) = output {
+            args.extend(["--outputs".to_string(), output.to_string()]);
+        }
+        if let Some(kind) = &transition.kind {
+            args.extend(["--transition-type".to_string(), kind.clone()]);
+        }
+        if let Some(duration) = transition.duration {
+            args.extend([
+                "--transition-duration".to_string(),
+                duration.as_secs_f64().to_string(),
+            ]);
+        }
+        args
+    }
+}
+
+impl WallpaperBackend for SwwwBackend {
+    fn desktop(&self) -> DesktopEnvironment {
+        DesktopEnvironment::Swww
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let outputs = self.outputs.as_ref().map_or(0, Vec::len);
+        Capabilities {
+            per_monitor: outputs > 0,
+            per_virtual_desktop: false,
+            monitors: outputs.max(1),
+            virtual_desktops: 1,
+        }
+    }
+
+    fn set(&self, target: Target, photo: &std::path::Path) -> Result<(), PhotoError> {
+        let Some(outputs) = &self.outputs else {
+            return Err(PhotoError::Wallpaper(
+                "swww-daemon isn't running. Start it (for example with `swww-daemon &` \
+                 in your compositor's autostart) and try again"
+                    .to_string(),
+            ));
+        };
+        let output =
+            match target {
+                Target::Monitor(idx) => Some(outputs.get(idx).ok_or_else(|| {
+                    PhotoError::Wallpaper(format!("swww has no output {}", idx + 1))
+                })?),
+                Target::AllMonitors => None,
+            };
+        let args = Self::img_args(photo, output.map(String::as_str), &self.transition);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.runner.run("swww", &args).map(drop)
+    }
+}
+
+/// xfdesktop image style used when a backdrop has none: zoomed, scaled to fill the monitor
+const XFCE_IMAGE_STYLE_ZOOMED: &str = "5";
+
+/// One xfdesktop backdrop, a monitor's wallpaper on one workspace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XfceBackdrop {
+    /// Property prefix, like `/backdrop/screen0/monitoreDP-1/workspace0`
+    pub property: String,
+    pub monitor: String,
+    pub workspace: usize,
+    /// Whether `image-style` is already set
+    pub has_image_style: bool,
+}
+
+impl XfceBackdrop {
+    /// `xfconf-query` argument lists that show `photo` on this backdrop
+    pub fn commands(&self, photo: &std::path::Path) -> Vec<Vec<String>> {
+        let set = |name: &str, kind: &str, value: &str| {
+            [
+                "-c",
+                "xfce4-desktop",
+                "-p",
+                &format!("{}/{}", self.property, name),
+                "-n",
+                "-t",
+                kind,
+                "-s",
+                value,
+            ]
+            .map(String::from)
+            .to_vec()
+        };
+        let mut commands = vec![set("last-image", "string", &photo.to_string_lossy())];
+        if !self.has_image_style {
+            commands.push(set("image-style", "int", XFCE_IMAGE_STYLE_ZOOMED));
+        }
+        commands
+    }
+}
+
+/// Parse `xfconf-query -c xfce4-desktop -l` into backdrops, in the order their
+/// monitors first appear, then by workspace. Legacy properties without a
+/// workspace are ignored.
+pub fn parse_xfce_backdrops(list: &str) -> Vec<XfceBackdrop> {
+    let mut backdrops: Vec<XfceBackdrop> = Vec::new();
+    for line in list.lines() {
+        let Some(rest) = line.trim().strip_prefix("/backdrop/") else {
+            continue;
+        };
+        let parts: Vec<&str> = rest.split('/').collect();
+        let [screen, monitor, workspace, name] = parts.as_slice() else {
+            continue;
+        };
+        let (Some(monitor), Some(workspace)) = (
+            monitor.strip_prefix("monitor"),
+            workspace
+                .strip_prefix("workspace")
+                .and_then(|n| n.parse::<usize>().ok()),
+        ) else {
+            continue;
+        };
+        let property = format!("/backdrop/{screen}/monitor{monitor}/workspace{workspace}");
+        let idx = backdrops
+            .iter()
+            .position(|b| b.property == property)
+            .unwrap_or_else(|| {
+                backdrops.push(XfceBackdrop {
+                    property,
+                    monitor: monitor.to_string(),
+                    workspace,
+                    has_image_style: false,
+                });
+                backdrops.len() - 1
+            });
+        backdrops[idx].has_image_style |= *name == "image-style";
+    }
+
+    let monitors = xfce_monitors(&backdrops);
+    backdrops.sort_by_key(|b| (monitors.iter().position(|m| *m == b.monitor), b.workspace));
+    backdrops
+}
+
+/// Monitor names in the order they first appear
+fn xfce_monitors(backdrops: &[XfceBackdrop]) -> Vec<String> {
+    let mut monitors: Vec<String> = Vec::new();
+    for b in backdrops {
+        if !monitors.contains(&b.monitor) {
+            monitors.push(b.monitor.clone());
+        }
+    }
+    monitors
+}
+
+/// The backdrops to change for a monitor (by index) and workspace, either of
+/// which may be `None` for all of them
+pub fn select_xfce_backdrops(
+    backdrops: &[XfceBackdrop],
+    monitor: Option<usize>,
+    workspace: Option<usize>,
+) -> Vec<&XfceBackdrop> {
+    let monitors = xfce_monitors(backdrops);
+    let monitor = monitor.map(|idx| monitors.get(idx));
+    backdrops
+        .iter()
+        .filter(|b| monitor.is_none_or(|m| m == Some(&b.monitor)))
+        .filter(|b| workspace.is_none_or(|w| w == b.workspace))
+        .collect()
+}
+
+/// XFCE, through xfdesktop's xfconf properties
+pub struct XfceBackend {
+    runner: Box<dyn CommandRunner>,
+    backdrops: Vec<XfceBackdrop>,
+}
+
+impl XfceBackend {
+    /// Create a backend, listing the existing backdrop properties
+    pub fn new(runner: Box<dyn CommandRunner>) -> Self {
+        let backdrops = runner
+            .run("xfconf-query", &["-c", "xfce4-desktop", "-l"])
+            .map(|list| parse_xfce_backdrops(&list))
+            .unwrap_or_default();
+        Self { runner, backdrops }
+    }
+
+    fn apply(
+        &self,
+        monitor: Option<usize>,
+        workspace: Option<usize>,
+        photo: &std::path::Path,
+    ) -> Result<(), PhotoError> {
+        if self.backdrops.is_empty() {
+            return Err(PhotoError::Wallpaper(
+                "No xfdesktop backdrops found. Open Desktop Settings once so XFCE creates them"
+                    .to_string(),
+            ));
+        }
+        let selected = select_xfce_backdrops(&self.backdrops, monitor, workspace);
+        if selected.is_empty() {
+            return Err(PhotoError::Wallpaper(format!(
+                "XFCE has no backdrop for monitor {} on workspace {}",
+                monitor.map_or_else(|| "any".to_string(), |m| (m + 1).to_string()),
+                workspace.map_or_else(|| "any".to_string(), |w| (w + 1).to_string()),
+            )));
+        }
+        for backdrop in selected {
+            for args in backdrop.commands(photo) {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                self.runner.run("xfconf-query", &args)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl WallpaperBackend for XfceBackend {
+    fn desktop(&self) -> DesktopEnvironment {
+        DesktopEnvironment::Xfce
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut workspaces: Vec<usize> = self.backdrops.iter().map(|b| b.workspace).collect();
+        workspaces.sort_unstable();
+        workspaces.dedup();
+        Capabilities {
+            per_monitor: true,
+            per_virtual_desktop: true,
+            monitors: xfce_monitors(&self.backdrops).len().max(1),
+            virtual_desktops: workspaces.len().max(1),
+        }
+    }
+
+    fn set(&self, target: Target, photo: &std::path::Path) -> Result<(), PhotoError> {
+        match target {
+            Target::Monitor(idx) => self.apply(Some(idx), None, photo),
+            Target::AllMonitors => self.apply(None, None, photo),
+        }
+    }
+
+    fn set_on_virtual_desktop(
+        &self,
+        desktop: usize,
+        monitor: Option<usize>,
+        photo: &std::path::Path,
+    ) -> Result<(), PhotoError> {
+        self.apply(monitor, Some(desktop), photo)
+    }
+}
+
+/// How a photo is fitted to the screen, for backends that ask
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FitMode {
+    /// Scale to fit inside the screen, keeping the aspect ratio
+    #[default]
+    Fit,
+    /// Scale to fill the screen, cropping the edges
+    Zoom,
+    /// Stretch to the screen, ignoring the aspect ratio
+    Stretch,
+    Center,
+    Tile,
+}
+
+impl FitMode {
+    /// The `picture-options` value on gsettings-based desktops
+    pub const fn gsettings_option(self) -> &'static str {
+        match self {
+            Self::Fit => "scaled",
+            Self::Zoom => "zoom",
+            Self::Stretch => "stretched",
+            Self::Center => "centered",
+            Self::Tile => "wallpaper",
+        }
+    }
+
+    /// The `feh` option that scales a background this way
+    pub const fn feh_option(self) -> &'static str {
+        match self {
+            Self::Fit => "--bg-max",
+            Self::Zoom => "--bg-fill",
+            Self::Stretch => "--bg-scale",
+            Self::Center => "--bg-center",
+            Self::Tile => "--bg-tile",
+        }
+    }
+
+    /// The `pcmanfm-qt --wallpaper-mode` value
+    pub const fn pcmanfm_mode(self) -> &'static str {
+        match self {
+            Self::Fit => "fit",
+            Self::Zoom => "zoom",
+            Self::Stretch => "stretch",
+            Self::Center => "center",
+            Self::Tile => "tile",
+        }
+    }
+}
+
+/// `pcmanfm-qt --desktop`, which draws the desktop on `LXQt`
+pub struct LxqtBackend {
+    runner: Box<dyn CommandRunner>,
+    fit: FitMode,
+}
+
+impl LxqtBackend {
+    pub fn new(runner: Box<dyn CommandRunner>, fit: FitMode) -> Self {
+        Self { runner, fit }
+    }
+
+    /// `pcmanfm-qt` arguments that show `photo` fitted with `fit`
+    pub fn args(photo: &std::path::Path, fit: FitMode) -> Vec<String> {
+        vec![
+            "--set-wallpaper".to_string(),
+            photo.to_string_lossy().into_owned(),
+            format!("--wallpaper-mode={}", fit.pcmanfm_mode()),
+        ]
+    }
+}
+
+impl WallpaperBackend for LxqtBackend {
+    fn desktop(&self) -> DesktopEnvironment {
+        DesktopEnvironment::Lxqt
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // pcmanfm-qt shows one wallpaper everywhere
+        Capabilities {
+            per_monitor: false,
+            per_virtual_desktop: false,
+            monitors: 1,
+            virtual_desktops: 1,
+        }
+    }
+
+    fn set(&self, _target: Target, photo: &std::path::Path) -> Result<(), PhotoError> {
+        let args = Self::args(photo, self.fit);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.runner.run("pcmanfm-qt", &args).map(drop)
+    }
+}
+
+/// `feh` on X11, which gives each Xinerama screen the next photo on its command line
+pub struct FehBackend {
+    runner: Box<dyn CommandRunner>,
+    fit: FitMode,
+    /// The photo given to each screen so far, since feh sets them all at once
+    screens: std::cell::RefCell<Vec<Option<PathBuf>>>,
+    /// `DISPLAY`, without which feh can't draw anything
+    display: Option<String>,
+    /// The restore script feh writes, which i3 and Openbox users run at login
+    fehbg: PathBuf,
+}
+
+impl FehBackend {
+    pub fn new(
+        runner: Box<dyn CommandRunner>,
+        fit: FitMode,
+        monitors: usize,
+        display: Option<String>,
+    ) -> Self {
+        Self {
+            runner,
+            fit,
+            screens: std::cell::RefCell::new(vec![None; monitors.max(1)]),
+            display,
+            fehbg: PathBuf::from(expand_tilde("~/.fehbg")),
+        }
+    }
+
+    /// `feh` arguments that show `photos` on the screens in order, fitted with `fit`.
+    /// Spanned photos cover every screen as one.
+    pub fn args(photos: &[&std::path::Path], fit: FitMode, spanned: bool) -> Vec<String> {
+        let mut args = Vec::with_capacity(photos.len() + 2);
+        if spanned {
+            args.push("--no-xinerama".to_string());
+        }
+        args.push(fit.feh_option().to_string());
+        args.extend(photos.iter().map(|p| p.to_string_lossy().into_owned()));
+        args
+    }
+
+    fn run(&self, photos: &[&std::path::Path], spanned: bool) -> Result<(), PhotoError> {
+        if self.display.as_deref().is_none_or(|d| d.trim().is_empty()) {
+            return Err(PhotoError::Wallpaper(
+                "feh only works on X11, and DISPLAY isn't set. On Wayland, install a \
+                 Wayland wallpaper tool such as swaybg, swww, or hyprpaper"
+                    .to_string(),
+            ));
+        }
+        let args = Self::args(photos, self.fit, spanned);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.runner.run("feh", &args)?;
+
+        if fehbg_restores(&self.fehbg, photos) {
+            debug!(fehbg = %self.fehbg.display(), "feh saved the wallpaper for next login");
+        } else {
+            warn!(
+                "feh didn't update {}, so the wallpaper won't be restored at login",
+                self.fehbg.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Whether the `~/.fehbg` script at `fehbg` shows every one of `photos`
+pub fn fehbg_restores(fehbg: &std::path::Path, photos: &[&std::path::Path]) -> bool {
+    std::fs::read_to_string(fehbg).is_ok_and(|script| {
+        photos
+            .iter()
+            .all(|photo| script.contains(photo.to_string_lossy().as_ref()))
+    })
+}
+
+impl WallpaperBackend for FehBackend {
+    fn desktop(&self) -> DesktopEnvironment {
+        DesktopEnvironment::Feh
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let monitors = self.screens.borrow().len();
+        Capabilities {
+            per_monitor: monitors > 1,
+            per_virtual_desktop: false,
+            monitors,
+            virtual_desktops: 1,
+        }
+    }
+
+    fn set(&self, target: Target, photo: &std::path::Path) -> Result<(), PhotoError> {
+        let photos = {
+            let mut screens = self.screens.borrow_mut();
+            match target {
+                Target::Monitor(index) if index < screens.len() => {
+                    screens[index] = Some(photo.to_path_buf());
+                }
+                _ => screens.fill(Some(photo.to_path_buf())),
+            }
+            screens.clone()
+        };
+        // Screens not assigned yet show this photo until their turn comes
+        let photos: Vec<&std::path::Path> = photos
+            .iter()
+            .map(|screen| screen.as_deref().unwrap_or(photo))
+            .collect();
+        self.run(&photos, false)
+    }
+
+    fn spans_natively(&self) -> bool {
+        true
+    }
+
+    fn set_spanned(&self, photo: &std::path::Path) -> Result<(), PhotoError> {
+        self.run(&[photo], true)
+    }
+}
+
+/// Windows, through `SystemParametersInfoW` and the `IDesktopWallpaper` COM interface
+#[cfg(target_os = "windows")]
+#[allow(unsafe_code)]
+mod windows_wallpaper {
+    use super::{Capabilities, DesktopEnvironment, PhotoError, Target, WallpaperBackend};
+    use std::os::windows::ffi::OsStrExt;
+    use windows::core::HSTRING;
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoTaskMemFree, CLSCTX_ALL, COINIT_APARTMENTTHREADED,
+    };
+    use windows::Win32::UI::Shell::{DesktopWallpaper, IDesktopWallpaper};
+    use windows::Win32::UI::WindowsAndMessaging::{
+        SystemParametersInfoW, SPIF_SENDCHANGE, SPIF_UPDATEINIFILE, SPI_SETDESKWALLPAPER,
+    };
+
+    fn wallpaper_error(e: &windows::core::Error) -> PhotoError {
+        PhotoError::Wallpaper(e.to_string())
+    }
+
+    pub struct WindowsBackend {
+        /// `None` if the COM object couldn't be created; then only one wallpaper is set
+        desktop_wallpaper: Option<IDesktopWallpaper>,
+        /// Monitor device paths, in `IDesktopWallpaper` order
+        monitors: Vec<String>,
+    }
+
+    impl WindowsBackend {
+        pub fn new() -> Self {
+            let desktop_wallpaper = create_desktop_wallpaper().ok();
+            let monitors = desktop_wallpaper
+                .as_ref()
+                .and_then(|dw| monitor_ids(dw).ok())
+                .unwrap_or_default();
+            Self {
+                desktop_wallpaper,
+                monitors,
+            }
+        }
+    }
+
+    fn create_desktop_wallpaper() -> windows::core::Result<IDesktopWallpaper> {
+        // SAFETY: COM is initialized for this thread before the object is created.
+        // Initializing again is reference counted, so the result can be ignored.
+        unsafe {
+            let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+            CoCreateInstance(&DesktopWallpaper, None, CLSCTX_ALL)
+        }
+    }
+
+    fn monitor_ids(dw: &IDesktopWallpaper) -> windows::core::Result<Vec<String>> {
+        // SAFETY: `dw` is a live COM object; each returned string is freed after copying
+        unsafe {
+            let count = dw.GetMonitorDevicePathCount()?;
+            let mut ids = Vec::new();
+            for i in 0..count {
+                let id = dw.GetMonitorDevicePathAt(i)?;
+                ids.push(id.to_string().unwrap_or_default());
+                CoTaskMemFree(Some(id.0.cast_const().cast()));
+            }
+            Ok(ids)
+        }
+    }
+
+    /// The monitor device path a target refers to, or `None` for every monitor
+    pub fn monitor_for(monitors: &[String], target: Target) -> Result<Option<&str>, PhotoError> {
+        match target {
+            Target::Monitor(idx) => monitors
+                .get(idx)
+                .map(|id| Some(id.as_str()))
+                .ok_or_else(|| PhotoError::Wallpaper(format!("No monitor {}", idx + 1))),
+            Target::AllMonitors => Ok(None),
+        }
+    }
+
+    /// Set one wallpaper on every monitor
+    fn set_single(photo: &std::path::Path) -> Result<(), PhotoError> {
+        let mut wide: Vec<u16> = photo.as_os_str().encode_wide().chain([0]).collect();
+        // SAFETY: `wide` is a NUL-terminated UTF-16 path that outlives the call
+        unsafe {
+            SystemParametersInfoW(
+                SPI_SETDESKWALLPAPER,
+                0,
+                Some(wide.as_mut_ptr().cast()),
+                SPIF_UPDATEINIFILE | SPIF_SENDCHANGE,
+            )
+        }
+        .map_err(|e| wallpaper_error(&e))
+    }
+
+    impl WallpaperBackend for WindowsBackend {
+        fn desktop(&self) -> DesktopEnvironment {
+            DesktopEnvironment::Windows
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                per_monitor: self.monitors.len() > 1,
+                per_virtual_desktop: false,
+                monitors: self.monitors.len().max(1),
+                virtual_desktops: 1,
+            }
+        }
+
+        fn set(&self, target: Target, photo: &std::path::Path) -> Result<(), PhotoError> {
+            let (Some(monitor), Some(dw)) = (
+                monitor_for(&self.monitors, target)?,
+                &self.desktop_wallpaper,
+            ) else {
+                return set_single(photo);
+            };
+            // SAFETY: `dw` is a live COM object and both strings outlive the call
+            unsafe { dw.SetWallpaper(&HSTRING::from(monitor), &HSTRING::from(photo.as_os_str())) }
+                .map_err(|e| wallpaper_error(&e))
+        }
+    }
+}
+
+/// Settings for backends that support them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackendOptions {
+    pub transition: TransitionOptions,
+    /// How photos are scaled; `zoom` on gsettings-based desktops and feh, and `fit` on `LXQt`, if unset
+    pub fit: Option<FitMode>,
+    /// A photo for GNOME to show while its dark style is on
+    pub dark_photo: Option<PathBuf>,
+    /// The backend to use instead of the detected one
+    pub backend: Option<DesktopEnvironment>,
+}
+
+impl DesktopEnvironment {
+    /// The backend for this desktop, querying its monitors and virtual desktops
+    pub fn backend(self) -> Option<Box<dyn WallpaperBackend>> {
+        self.backend_with(&BackendOptions::default())
+    }
+
+    /// The backend for this desktop, configured by `options` where supported
+    pub fn backend_with(self, options: &BackendOptions) -> Option<Box<dyn WallpaperBackend>> {
+        match self {
+            Self::KdePlasma6 => {
+                let transport = PlasmaTransport::select(self, &LiveSystem)
+                    .unwrap_or(PlasmaTransport::Qdbus("qdbus6"));
+                let containments = transport
+                    .evaluate_script(&plasma_containments_script(), &LiveCommands)
+                    .map(|output| parse_plasma_containments(&output))
+                    .unwrap_or_default();
+                Some(Box::new(KdePlasma6Backend {
+                    transport,
+                    monitors: get_monitor_count(transport, &LiveCommands),
+                    virtual_desktops: get_virtual_desktop_count(transport, &LiveCommands),
+                    per_desktop_containments: has_per_desktop_containments(&containments),
+                }))
+            }
+            Self::KdePlasma5 => {
+                let transport = PlasmaTransport::select(self, &LiveSystem)
+                    .unwrap_or(PlasmaTransport::Qdbus("qdbus"));
+                Some(Box::new(KdePlasma5Backend {
+                    transport,
+                    monitors: get_monitor_count(transport, &LiveCommands),
+                }))
+            }
+            Self::PlasmaFallback => Some(Box::new(PlasmaApplyBackend::new(Box::new(LiveCommands)))),
+            Self::Gnome | Self::Cinnamon | Self::Mate => Some(Box::new(GsettingsBackend::new(
+                self,
+                Box::new(LiveCommands),
+                options.fit.unwrap_or(FitMode::Zoom),
+                options.dark_photo.clone(),
+            ))),
+            Self::Feh => Some(Box::new(FehBackend::new(
+                Box::new(LiveCommands),
+                options.fit.unwrap_or(FitMode::Zoom),
+                enumerate_monitors(self).len(),
+                std::env::var("DISPLAY").ok(),
+            ))),
+            Self::Hyprland => Some(Box::new(HyprlandBackend::new(Box::new(LiveCommands)))),
+            Self::Swww => Some(Box::new(SwwwBackend::new(
+                Box::new(LiveCommands),
+                options.transition.clone(),
+            ))),
+            Self::Xfce => Some(Box::new(XfceBackend::new(Box::new(LiveCommands)))),
+            Self::Lxqt => Some(Box::new(LxqtBackend::new(
+                Box::new(LiveCommands),
+                options.fit.unwrap_or_default(),
+            ))),
+            #[cfg(target_os = "windows")]
+            Self::Windows => Some(Box::new(windows_wallpaper::WindowsBackend::new())),
+            #[cfg(not(target_os = "windows"))]
+            Self::Windows => None,
+            Self::Unknown => None,
+        }
+    }
+}
+
+/// Detect the desktop and return its wallpaper backend
+pub fn detect_wallpaper_backend() -> Result<Box<dyn WallpaperBackend>, PhotoError> {
+    detect_wallpaper_backend_with(&BackendOptions::default())
+}
+
+/// Detect the desktop and return its wallpaper backend, configured by `options`.
+/// `options.backend` skips detection.
+pub fn detect_wallpaper_backend_with(
+    options: &BackendOptions,
+) -> Result<Box<dyn WallpaperBackend>, PhotoError> {
+    match options.backend {
+        Some(de) => de.backend_with(options).ok_or_else(|| {
+            PhotoError::Wallpaper(format!("The {de:?} backend isn't available on this system"))
+        }),
+        None => detect_desktop_environment()
+            .backend_with(options)
+            .ok_or_else(|| PhotoError::Wallpaper(unsupported_desktop_reason(&LiveSystem))),
+    }
+}
+
+/// Main wallpaper setting function (uses default photo directory)
+pub fn set_wallpapers(mode: WallpaperMode) -> Result<(), PhotoError> {
+    set_wallpapers_with_options(mode, None, &WallpaperOptions::default()).map(drop)
+}
+
+/// Main wallpaper setting function with optional custom path (for backwards compatibility)
+pub fn set_wallpapers_with_path(
+    mode: WallpaperMode,
+    path: Option<String>,
+) -> Result<(), PhotoError> {
+    set_wallpapers_with_options(mode, path, &WallpaperOptions::default()).map(drop)
+}
+
+/// Path of the log written when setting wallpapers
+pub fn wallpaper_log_path() -> Result<String, PhotoError> {
+    Ok(format!("{}wallpaper.log", log_dir()?))
+}
+
+/// Path of the log that collects events from every command
+pub fn app_log_path() -> Result<String, PhotoError> {
+    Ok(format!("{}natgeo-wallpapers.log", log_dir()?))
+}
+
+/// A wallpaper assignment and how applying it went
+#[derive(Debug)]
+pub struct AssignmentResult {
+    pub assignment: WallpaperAssignment,
+    pub result: Result<(), PhotoError>,
+}
+
+/// What `set_wallpapers_with_options` found and did
+#[derive(Debug)]
+pub struct WallpaperReport {
+    pub desktop: DesktopEnvironment,
+    /// The mode asked for
+    pub requested_mode: WallpaperMode,
+    /// The mode used, which is `Monitors` where the desktop can't do more
+    pub mode: WallpaperMode,
+    pub sort: SortOrder,
+    pub spread: SpreadStrategy,
+    /// The brightness photos were picked by, with `Auto` resolved
+    pub brightness: Option<BrightnessFilter>,
+    pub photos_found: usize,
+    pub monitors: Vec<MonitorInfo>,
+    pub monitor_count: usize,
+    pub virtual_desktop_count: usize,
+    /// Problems that didn't stop the wallpaper from being set
+    pub warnings: Vec<String>,
+    /// Each assignment in order, with its outcome
+    pub results: Vec<AssignmentResult>,
+    /// Whether the assignments were only planned, so none was applied
+    pub dry_run: bool,
+}
+
+impl WallpaperReport {
+    /// Number of assignments that were applied
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|r| r.result.is_ok()).count()
+    }
+
+    /// The photo applied to the primary monitor, if it was applied
+    pub fn primary_photo(&self) -> Option<PathBuf> {
+        current_wallpapers(self.mode, self.monitor_count, &self.results)
+            .into_iter()
+            .find_map(|(monitor, photo)| (monitor == 0).then_some(photo))
+    }
+
+    /// Assignments and their outcomes in a serializable form
+    pub fn applied(&self) -> Vec<AppliedWallpaper> {
+        self.results.iter().map(AppliedWallpaper::from).collect()
+    }
+
+    /// What was found and the assignments made, in a serializable form
+    pub fn plan(&self) -> WallpaperPlan {
+        WallpaperPlan {
+            desktop: self.desktop,
+            requested_mode: self.requested_mode,
+            mode: self.mode,
+            monitor_count: self.monitor_count,
+            virtual_desktop_count: self.virtual_desktop_count,
+            photos_found: self.photos_found,
+            warnings: self.warnings.clone(),
+            assignments: self.results.iter().map(|r| r.assignment.clone()).collect(),
+        }
+    }
+}
+
+/// The wallpapers `set --dry-run` would apply
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WallpaperPlan {
+    pub desktop: DesktopEnvironment,
+    pub requested_mode: WallpaperMode,
+    /// The mode that would be used, after falling back to what the desktop can do
+    pub mode: WallpaperMode,
+    pub monitor_count: usize,
+    pub virtual_desktop_count: usize,
+    pub photos_found: usize,
+    pub warnings: Vec<String>,
+    pub assignments: Vec<WallpaperAssignment>,
+}
+
+/// How `set_wallpapers_with_options` picks and prepares photos
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WallpaperOptions {
+    pub sort: SortOrder,
+    pub spread: SpreadStrategy,
+    /// Only use dark or light photos
+    pub brightness: Option<BrightnessFilter>,
+    /// Leave out photos of the wrong size or orientation, unless that leaves none
+    pub dimensions: DimensionFilter,
+    /// Resize photos to each monitor's resolution instead of letting the desktop scale them
+    pub resize: Option<ResizeMode>,
+    /// Stretch the first photo across every monitor; takes precedence over `resize`
+    pub span: bool,
+    /// Only set these monitors and virtual desktops
+    pub targets: TargetFilter,
+    /// Only pick photos from the favorites
+    pub favorites_only: bool,
+    /// The photos under the path, already listed by a [`PhotoListingCache`], so
+    /// the tree isn't walked again
+    pub listed: Option<Vec<PathBuf>>,
+    /// Plan the assignments without applying them, logging, or recording history
+    pub dry_run: bool,
+}
+
+/// Main wallpaper setting function with all options.
+///
+/// Prints nothing; the returned report lists each assignment and whether it
+/// was applied. Fails if there are no photos, the desktop isn't supported, or
+/// no assignment could be applied. With `options.dry_run` the report holds the
+/// planned assignments and no backend command is run.
+#[allow(clippy::needless_pass_by_value)]
+pub fn set_wallpapers_with_options(
+    mode: WallpaperMode,
+    path: Option<String>,
+    options: &WallpaperOptions,
+) -> Result<WallpaperReport, PhotoError> {
+    let backend = detect_wallpaper_backend()?;
+    set_wallpapers_with_backend(backend.as_ref(), mode, path.as_deref(), options)
+}
+
+/// Set wallpapers from the photos in `path` (or the default directory) through
+/// `backend`, picked and prepared as `options` asks
+pub fn set_wallpapers_with_backend(
+    backend: &dyn WallpaperBackend,
+    mode: WallpaperMode,
+    path: Option<&str>,
+    options: &WallpaperOptions,
+) -> Result<WallpaperReport, PhotoError> {
+    let WallpaperOptions {
+        sort,
+        brightness,
+        span,
+        ref targets,
+        ..
+    } = *options;
+    let log_path = wallpaper_log_path()?;
+    if !options.dry_run {
+        write_log_checked(
+            &log_path,
+            &format!("Starting wallpaper set with mode: {}", mode),
+        )?;
+    }
+
+    let de = backend.desktop();
+    let activities = requested_activities(backend, mode)?;
+    let mut warnings = Vec::new();
+    let brightness = brightness.and_then(|filter| resolve_brightness(filter, de, &mut warnings));
+
+    let mut photos = wallpaper_candidates(path, options, brightness, &mut warnings)?;
+
+    // Fall back to per-monitor wallpapers where virtual desktops can't differ,
+    // and span over monitors alone
+    let capabilities = backend.capabilities();
+    let effective_mode =
+        if !span && (capabilities.per_virtual_desktop || mode == WallpaperMode::Activities) {
+            mode
+        } else {
+            WallpaperMode::Monitors
+        };
+    if de == DesktopEnvironment::KdePlasma6
+        && !capabilities.per_virtual_desktop
+        && matches!(mode, WallpaperMode::VirtualDesktops | WallpaperMode::Both)
+    {
+        warnings.push(PLASMA_SHARED_DESKTOPS_WARNING.to_string());
+    }
+
+    // Listed in the backend's own order, so names resolve to its indices
+    let monitors = enumerate_monitors(de);
+    let targets = &targets.resolve_names(&monitors)?;
+    let desktops = checked_desktop_count(targets, effective_mode, &capabilities, activities.len())?;
+
+    // Random picks skip photos shown recently, until every one has had a turn
+    let photos_found = photos.len();
+    let mut history = RotationHistory::load(Path::new(&history_path()?));
+    if sort == SortOrder::Random && !span {
+        let needed = assignment_count(effective_mode, capabilities.monitors, desktops, targets);
+        photos = history.unused_photos(photos, needed);
+    }
+
+    let mut assignments = plan_assignments(
+        effective_mode,
+        &photos,
+        &monitors,
+        &capabilities,
+        desktops,
+        targets,
+        span,
+    );
+    if effective_mode == WallpaperMode::Activities {
+        for (assignment, activity) in assignments.iter_mut().zip(&activities) {
+            assignment.location = format!("Activity {}", activity.name);
+        }
+    }
+
+    let results = if options.dry_run {
+        assignments
+            .into_iter()
+            .map(|assignment| AssignmentResult {
+                assignment,
+                result: Ok(()),
+            })
+            .collect()
+    } else {
+        transcode_for_desktop(de, &mut assignments, &log_path, &mut warnings)?;
+
+        // Apply wallpapers, spanned or resized if asked
+        let results = {
+            let prepared = preparing_backend(backend, &monitors, options, &mut warnings);
+            apply_with_backend(
+                prepared.as_deref().unwrap_or(backend),
+                effective_mode,
+                assignments,
+                &log_path,
+                shutdown_flag(),
+            )?
+        };
+
+        warnings.extend(record_applied(
+            &mut history,
+            effective_mode,
+            capabilities.monitors,
+            &results,
+            &log_path,
+        )?);
+
+        write_log(&log_path, "Wallpaper setting completed");
+        results
+    };
+
+    Ok(WallpaperReport {
+        desktop: de,
+        requested_mode: mode,
+        mode: effective_mode,
+        sort,
+        spread: options.spread,
+        brightness,
+        photos_found,
+        monitors,
+        monitor_count: capabilities.monitors,
+        virtual_desktop_count: capabilities.virtual_desktops,
+        warnings,
+        results,
+        dry_run: options.dry_run,
+    })
+}
+
+/// The photos in `path` (or the default directory) to pick from, filtered,
+/// ordered and spread as `options` asks
+fn wallpaper_candidates(
+    path: Option<&str>,
+    options: &WallpaperOptions,
+    brightness: Option<BrightnessFilter>,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<PathBuf>, PhotoError> {
+    let photos = match &options.listed {
+        Some(listed) if listed.is_empty() => {
+            return Err(PhotoError::NoPhotos(format!(
+                "No photos found in {}",
+                photo_dir_or(path)?
+            )));
+        }
+        Some(listed) => {
+            let mut photos = listed.clone();
+            sort_photos(&mut photos, options.sort);
+            let photos = filter_brightness(photos, path, brightness)?;
+            select_wallpaper_photos(photos, options.sort, options.favorites_only)?
+        }
+        None => find_wallpaper_photos(path, options.sort, brightness, options.favorites_only)?,
+    };
+    let (mut photos, warning) = keep_fitting_photos(photos, &options.dimensions);
+    warnings.extend(warning);
+    if options.spread != SpreadStrategy::None {
+        photos = apply_spread(photos, options.spread);
+    }
+    Ok(photos)
+}
+
+/// Assign `photos` to the targets, matching them to monitor shapes where
+/// those are known. A spanned photo is given to every monitor and cut up by
+/// the backend, and a desktop without per-monitor wallpapers gets one photo.
+fn plan_assignments(
+    mode: WallpaperMode,
+    photos: &[PathBuf],
+    monitors: &[MonitorInfo],
+    capabilities: &Capabilities,
+    desktops: usize,
+    targets: &TargetFilter,
+    span: bool,
+) -> Vec<WallpaperAssignment> {
+    let mut assignments = if span {
+        let all = TargetFilter::default();
+        build_assignments(mode, &photos[..1], capabilities.monitors, 1, &all)
+    } else {
+        build_assignments_smart(
+            mode,
+            photos,
+            monitors,
+            capabilities.monitors,
+            desktops,
+            targets,
+        )
+    };
+    if !capabilities.per_monitor {
+        assignments.truncate(1);
+    }
+    assignments
+}
+
+/// Resolve `Auto` brightness from the desktop's color scheme, warning if it can't be read
+fn resolve_brightness(
+    filter: BrightnessFilter,
+    de: DesktopEnvironment,
+    warnings: &mut Vec<String>,
+) -> Option<BrightnessFilter> {
+    let resolved = filter.resolve(de, &LiveCommands);
+    if resolved.is_none() {
+        warnings.push(
+            "Could not read the desktop color scheme; using photos of any brightness".to_string(),
+        );
+    }
+    resolved
+}
+
+/// How many virtual desktops, or activities in activity mode, get photos,
+/// after checking `targets` are all there
+fn checked_desktop_count(
+    targets: &TargetFilter,
+    mode: WallpaperMode,
+    capabilities: &Capabilities,
+    activities: usize,
+) -> Result<usize, PhotoError> {
+    let desktops = if mode == WallpaperMode::Activities {
+        activities
+    } else {
+        capabilities.virtual_desktops
+    };
+    targets.validate(mode, capabilities.monitors, desktops)?;
+    if !capabilities.per_monitor && !targets.monitors.is_empty() {
+        return Err(PhotoError::Config(
+            "This desktop shows one wallpaper on every monitor, so --monitor can't be used"
+                .to_string(),
+        ));
+    }
+    Ok(desktops)
+}
+
+/// The activities to give photos in activity mode, or none in other modes.
+/// Fails if the desktop has no activities.
+fn requested_activities(
+    backend: &dyn WallpaperBackend,
+    mode: WallpaperMode,
+) -> Result<Vec<Activity>, PhotoError> {
+    if mode != WallpaperMode::Activities {
+        return Ok(Vec::new());
+    }
+    backend
+        .activities()
+        .filter(|activities| !activities.is_empty())
+        .ok_or_else(|| {
+            PhotoError::Wallpaper(format!(
+                "--mode activities needs KDE Plasma activities, but the desktop is {:?}",
+                backend.desktop()
+            ))
+        })
+}
+
+/// `backend` wrapped to span or resize photos as `options` asks, or `None` if
+/// they are applied as they are. Adds a warning when that isn't possible.
+fn preparing_backend<'a>(
+    backend: &'a dyn WallpaperBackend,
+    monitors: &'a [MonitorInfo],
+    options: &WallpaperOptions,
+    warnings: &mut Vec<String>,
+) -> Option<Box<dyn WallpaperBackend + 'a>> {
+    if options.span {
+        let capabilities = backend.capabilities();
+        let positioned =
+            monitors.len() == capabilities.monitors && monitors.iter().all(|m| m.layout.is_some());
+        if backend.spans_natively() || (capabilities.per_monitor && positioned) {
+            let derived = derived_dir()
+                .inspect_err(|e| warnings.push(format!("The photo is not spanned: {}", e)))
+                .ok()?;
+            return Some(Box::new(SpanningBackend::new(backend, monitors, derived)));
+        }
+        warnings.push(if capabilities.per_monitor {
+            "Monitor positions are unknown, so the photo is not spanned".to_string()
+        } else {
+            "This desktop can't span a photo across monitors".to_string()
+        });
+        return None;
+    }
+
+    // Resize photos for each monitor once their resolutions are known
+    let resize = options.resize?;
+    if monitors.is_empty() {
+        warnings.push(
+            "Monitor resolutions are unknown, so photos are applied without resizing".to_string(),
+        );
+        return None;
+    }
+    let derived = derived_dir()
+        .inspect_err(|e| warnings.push(format!("Photos are applied without resizing: {}", e)))
+        .ok()?;
+    Some(Box::new(ResizingBackend::new(
+        backend, monitors, resize, derived,
+    )))
+}
+
+/// Transcode the photos in `assignments` that the desktop `de` can't display to
+/// JPEG, adding a warning for each one that can't be converted
+fn transcode_for_desktop(
+    de: DesktopEnvironment,
+    assignments: &mut [WallpaperAssignment],
+    log_path: &str,
+    warnings: &mut Vec<String>,
+) -> Result<(), PhotoError> {
+    let converted_dir = converted_dir()?;
+    for assignment in assignments {
+        shutdown_flag().check()?;
+        let ext = assignment
+            .photo_path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        if de.displays_extension(ext) {
+            continue;
+        }
+        match transcode_to_jpeg(&assignment.photo_path, &converted_dir) {
+            Ok(converted) => {
+                write_log(
+                    log_path,
+                    &format!(
+                        "Converted {} to {}",
+                        assignment.photo_path.display(),
+                        converted.display()
+                    ),
+                );
+                assignment.photo_path = converted;
+            }
+            Err(e) => warnings.push(format!(
+                "Could not convert {} to JPEG: {}",
+                assignment.photo_path.display(),
+                e
+            )),
+        }
+    }
+    Ok(())
+}
+
+/// Apply each assignment in order with `set`.
+///
+/// Each applied assignment then runs the post-set hook. Every outcome is logged
+/// and returned; a failed assignment doesn't stop the rest. Fails if none of
+/// them could be applied.
+pub fn apply_assignments(
+    assignments: Vec<WallpaperAssignment>,
+    log_path: &str,
+    cancel: &CancelFlag,
+    set: &mut dyn FnMut(&WallpaperAssignment) -> Result<(), PhotoError>,
+) -> Result<Vec<AssignmentResult>, PhotoError> {
+    let mut results = Vec::with_capacity(assignments.len());
+    for assignment in assignments {
+        cancel.check()?;
+        let result = set(&assignment);
+        match &result {
+            Ok(()) => write_log(
+                log_path,
+                &format!(
+                    "Set {} to: {}",
+                    assignment.location,
+                    assignment.photo_path.display()
+                ),
+            ),
+            Err(e) => write_log(
+                log_path,
+                &format!("Failed to set {}: {}", assignment.location, e),
+            ),
+        }
+        results.push(AssignmentResult { assignment, result });
+    }
+
+    if !results.is_empty() && results.iter().all(|r| r.result.is_err()) {
+        let total = results.len();
+        let first = results
+            .into_iter()
+            .find_map(|r| r.result.err())
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        return Err(PhotoError::Wallpaper(format!(
+            "none of {} wallpaper(s) could be set: {}",
+            total, first
+        )));
+    }
+    Ok(results)
+}
+
+/// Run the post-set hook for every assignment in `results` that was applied
+///
+/// Runs once the wallpapers, `current` links and history are recorded, so a
+/// failing hook can't make an applied wallpaper count as failed. Every hook
+/// runs; with `hooks.strict` the first failure is returned.
+pub fn run_post_set_hooks(
+    hooks: &HooksConfig,
+    results: &[AssignmentResult],
+    log_path: &str,
+) -> Result<(), PhotoError> {
+    let mut first_error = None;
+    for applied in results.iter().filter(|r| r.result.is_ok()) {
+        let context = HookContext::applied(&applied.assignment);
+        if let Err(e) = run_hooks_with(hooks, HookEvent::PostSet, &context, log_path) {
+            first_error.get_or_insert(e);
+        }
+    }
+    first_error.map_or(Ok(()), Err)
+}
+
+/// Apply `assignments` through `backend`, one per monitor or virtual desktop as `mode` asks
+pub fn apply_with_backend(
+    backend: &dyn WallpaperBackend,
+    mode: WallpaperMode,
+    assignments: Vec<WallpaperAssignment>,
+    log_path: &str,
+    cancel: &CancelFlag,
+) -> Result<Vec<AssignmentResult>, PhotoError> {
+    let capabilities = backend.capabilities();
+    let activities = if mode == WallpaperMode::Activities {
+        backend.activities().unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    apply_assignments(assignments, log_path, cancel, &mut |assignment| {
+        let (i, photo) = (assignment.slot, &assignment.photo_path);
+        match mode {
+            _ if !capabilities.per_monitor => backend.set(Target::AllMonitors, photo),
+            WallpaperMode::Monitors => backend.set(Target::Monitor(i), photo),
+            // Same wallpaper on all monitors for this VD
+            WallpaperMode::VirtualDesktops => backend.set_on_virtual_desktop(i, None, photo),
+            WallpaperMode::Both => {
+                let monitors = capabilities.monitors.max(1);
+                backend.set_on_virtual_desktop(i / monitors, Some(i % monitors), photo)
+            }
+            WallpaperMode::Activities => match activities.get(i) {
+                Some(activity) => backend.set_on_activity(activity, photo),
+                None => Err(PhotoError::Wallpaper(format!("No activity {}", i + 1))),
+            },
+        }
+    })
+}
+
+/// Name of the link to the wallpaper on monitor `index`, where 0 is the primary
+pub fn current_link_name(index: usize) -> String {
+    if index == 0 {
+        "current".to_string()
+    } else {
+        format!("current-monitor-{}", index + 1)
+    }
+}
+
+/// The applied photo on each monitor of the first virtual desktop, by monitor index
+pub fn current_wallpapers(
+    mode: WallpaperMode,
+    monitors: usize,
+    results: &[AssignmentResult],
+) -> Vec<(usize, PathBuf)> {
+    results
+        .iter()
+        .filter(|r| r.result.is_ok())
+        .filter_map(|r| {
+            let i = r.assignment.slot;
+            let monitor = match mode {
+                WallpaperMode::Monitors => Some(i),
+                // The first desktop's photo is on every monitor, and activities
+                // are listed with the current one first
+                WallpaperMode::VirtualDesktops | WallpaperMode::Activities => (i == 0).then_some(0),
+                WallpaperMode::Both => (i < monitors.max(1)).then_some(i),
+            }?;
+            Some((monitor, r.assignment.photo_path.clone()))
+        })
+        .collect()
+}
+
+/// Point `current` and `current-monitor-N` in `dir` at each monitor's wallpaper,
+/// returning the links updated
+///
+/// Each link is made under a temporary name and renamed over the old one, so
+/// it is never missing. Where symlinks aren't supported the photo is copied.
+/// Links for monitors beyond the `monitors` connected now are removed.
+pub fn update_current_links(
+    dir: &Path,
+    monitors: usize,
+    wallpapers: &[(usize, PathBuf)],
+) -> io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let stale = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("current-monitor-"))
+            .and_then(|n| n.parse::<usize>().ok())
+            .is_some_and(|n| n > monitors.max(1));
+        if stale {
+            std::fs::remove_file(dir.join(name))?;
+        }
+    }
+    let mut links = Vec::with_capacity(wallpapers.len());
+    for (index, photo) in wallpapers {
+        let name = current_link_name(*index);
+        let link = dir.join(&name);
+        let tmp = dir.join(format!(".{}.tmp", name));
+        let _ = std::fs::remove_file(&tmp);
+        let target = photo.canonicalize().unwrap_or_else(|_| photo.clone());
+        link_or_copy(&target, &tmp)?;
+        std::fs::rename(&tmp, &link)?;
+        links.push(link);
+    }
+    Ok(links)
+}
+
+/// Update the `current` links in the photo directory, logging each one.
+/// Returns a warning if they couldn't be updated.
+fn link_current_wallpapers(
+    monitors: usize,
+    wallpapers: &[(usize, PathBuf)],
+    log_path: &str,
+) -> Option<String> {
+    let links = photo_dir()
+        .and_then(|dir| Ok(update_current_links(Path::new(&dir), monitors, wallpapers)?));
+    match links {
+        Ok(links) => {
+            for link in links {
+                write_log(log_path, &format!("Updated {}", link.display()));
+            }
+            None
+        }
+        Err(e) => Some(format!(
+            "Could not update the current wallpaper link: {}",
+            e
+        )),
+    }
+}
+
+/// Symlink `link` to `target`, or copy `target` there if that fails
+fn link_or_copy(target: &Path, link: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    let linked = std::os::unix::fs::symlink(target, link).is_ok();
+    #[cfg(windows)]
+    let linked = std::os::windows::fs::symlink_file(target, link).is_ok();
+    #[cfg(not(any(unix, windows)))]
+    let linked = false;
+    if !linked {
+        std::fs::copy(target, link)?;
+    }
+    Ok(())
+}
+
+/// Arguments for pywal's `wal` theming the terminal from `photo`; `-n` leaves
+/// the wallpaper to us
+pub fn pywal_args(photo: &Path) -> Vec<String> {
+    vec![
+        "-n".to_string(),
+        "-i".to_string(),
+        photo.display().to_string(),
+    ]
+}
+
+/// Theme the terminal with pywal from the photo on the primary monitor, if it
+/// was applied, logging the result
+///
+/// Fails with install instructions when `wal` isn't on the PATH.
+pub fn run_pywal(
+    report: &WallpaperReport,
+    runner: &dyn CommandRunner,
+    system: &dyn SystemProbe,
+    log_path: &str,
+) -> Result<(), PhotoError> {
+    let Some(photo) = report.primary_photo() else {
+        return Ok(());
+    };
+    if !system.command_exists("wal") {
+        let e = PhotoError::Command(
+            "wal not found; install pywal (e.g. `pip install pywal`) or turn off --pywal"
+                .to_string(),
+        );
+        write_log(log_path, &format!("pywal failed: {}", e));
+        return Err(e);
+    }
+    let args = pywal_args(&photo);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match runner.run("wal", &args) {
+        Ok(_) => {
+            write_log(log_path, &format!("Ran pywal on {}", photo.display()));
+            Ok(())
+        }
+        Err(e) => {
+            write_log(log_path, &format!("pywal failed: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// ============================================================================
+// Lock Screen
+// ============================================================================
+
+/// Interface the KDE screen locker exposes on `org.freedesktop.ScreenSaver`
+const KDE_SCREENSAVER_CONFIGURE: [&str; 3] = [
+    "org.freedesktop.ScreenSaver",
+    "/ScreenSaver",
+    "org.kde.screensaver.configure",
+];
+
+/// How the lock screen wallpaper is set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockScreenMethod {
+    /// `kscreenlockerrc` through `kwriteconfig6` or `kwriteconfig5`, then the
+    /// screen locker is told to reload it with `qdbus6` or `qdbus`, if installed
+    Kde {
+        kwriteconfig: &'static str,
+        qdbus: Option<&'static str>,
+    },
+    /// `org.gnome.desktop.screensaver picture-uri` through gsettings
+    Gnome,
+    /// The `image` line of the swaylock config file at this path
+    Swaylock(PathBuf),
+}
+
+impl LockScreenMethod {
+    /// How to set the lock screen on `env`, or why it can't be set
+    pub fn select(env: &DetectedEnvironment, probe: &dyn SystemProbe) -> Result<Self, PhotoError> {
+        let first_installed = |tools: &[&'static str]| {
+            tools
+                .iter()
+                .copied()
+                .find(|tool| probe.command_exists(tool))
+        };
+        match env.de {
+            DesktopEnvironment::KdePlasma6
+            | DesktopEnvironment::KdePlasma5
+            | DesktopEnvironment::PlasmaFallback => {
+                let kwriteconfig = first_installed(&["kwriteconfig6", "kwriteconfig5"])
+                    .ok_or_else(|| {
+                        PhotoError::Command(
+                            "kwriteconfig6 or kwriteconfig5 is needed to set the KDE lock screen"
+                                .to_string(),
+                        )
+                    })?;
+                let qdbus = if env.de == DesktopEnvironment::KdePlasma5 {
+                    first_installed(&["qdbus"])
+                } else {
+                    first_installed(&["qdbus6", "qdbus"])
+                };
+                Ok(Self::Kde {
+                    kwriteconfig,
+                    qdbus,
+                })
+            }
+            DesktopEnvironment::Gnome if probe.command_exists("gsettings") => Ok(Self::Gnome),
+            DesktopEnvironment::Gnome => Err(PhotoError::Command(
+                "gsettings is needed to set the GNOME lock screen".to_string(),
+            )),
+            _ if env.session == SessionType::Wayland && probe.command_exists("swaylock") => {
+                Ok(Self::Swaylock(swaylock_config_path(probe)))
+            }
+            de => Err(PhotoError::Wallpaper(format!(
+                "Setting the lock screen isn't supported on {de:?}; \
+                 it works on KDE Plasma, GNOME, and Wayland with swaylock"
+            ))),
+        }
+    }
+
+    /// Show `photo` on the lock screen, running tools through `runner`
+    pub fn apply(&self, photo: &Path, runner: &dyn CommandRunner) -> Result<(), PhotoError> {
+        let uri = format!("file://{}", photo.to_string_lossy());
+        match self {
+            Self::Kde {
+                kwriteconfig,
+                qdbus,
+            } => {
+                runner.run(kwriteconfig, &kde_lock_screen_args(&uri))?;
+                // Without this the running locker keeps the old photo until the next login
+                if let Some(qdbus) = qdbus {
+                    if let Err(e) = runner.run(qdbus, &KDE_SCREENSAVER_CONFIGURE) {
+                        debug!(error = %e, "The screen locker didn't reload its settings");
+                    }
+                }
+                Ok(())
+            }
+            Self::Gnome => runner
+                .run(
+                    "gsettings",
+                    &["set", "org.gnome.desktop.screensaver", "picture-uri", &uri],
+                )
+                .map(drop),
+            Self::Swaylock(config) => {
+                let text = match std::fs::read_to_string(config) {
+                    Ok(text) => text,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+                    Err(e) => return Err(PhotoError::File(e)),
+                };
+                if let Some(dir) = config.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                std::fs::write(config, swaylock_config_with_image(&text, photo))?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// `kwriteconfig` arguments that make the KDE lock screen show the image at `uri`
+pub const fn kde_lock_screen_args(uri: &str) -> [&str; 13] {
+    [
+        "--file",
+        "kscreenlockerrc",
+        "--group",
+        "Greeter",
+        "--group",
+        "Wallpaper",
+        "--group",
+        "org.kde.image",
+        "--group",
+        "General",
+        "--key",
+        "Image",
+        uri,
+    ]
+}
+
+/// The swaylock config file: `~/.swaylock/config` if it exists, else
+/// `swaylock/config` under `XDG_CONFIG_HOME`
+fn swaylock_config_path(probe: &dyn SystemProbe) -> PathBuf {
+    let home = probe
+        .env_var("HOME")
+        .filter(|home| !home.is_empty())
+        .map_or_else(|| PathBuf::from(expand_tilde("~")), PathBuf::from);
+    let legacy = home.join(".swaylock").join("config");
+    if legacy.is_file() {
+        return legacy;
+    }
+    probe
+        .env_var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| home.join(".config"))
+        .join("swaylock")
+        .join("config")
+}
+
+/// swaylock config `text` with any `image` lines replaced by one showing `photo`
+pub fn swaylock_config_with_image(text: &str, photo: &Path) -> String {
+    let mut lines: Vec<String> = text
+        .lines()
+        .filter(|line| line.split('=').next().map(str::trim) != Some("image"))
+        .map(str::to_string)
+        .collect();
+    lines.push(format!("image={}", photo.display()));
+    lines.join("\n") + "\n"
+}
+
+/// Show `photo` on the lock screen of the desktop in `env`
+pub fn set_lock_screen(photo: &Path, env: &DetectedEnvironment) -> Result<(), PhotoError> {
+    LockScreenMethod::select(env, &LiveSystem)?.apply(photo, &LiveCommands)
+}
+
+// ============================================================================
+// Rotation History
+// ============================================================================
+
+/// Wallpapers remembered for each monitor or desktop
+pub const HISTORY_PER_TARGET: usize = 20;
+
+/// Most photos remembered as used in the current random cycle
+const HISTORY_CYCLE_LIMIT: usize = 1000;
+
+/// Name of the history of applied wallpapers in the data directory
+pub const HISTORY_FILE: &str = "history.json";
+
+/// Path of the history of applied wallpapers
+pub fn history_path() -> Result<String, PhotoError> {
+    Ok(format!("{}{}", log_dir()?, HISTORY_FILE))
+}
+
+/// A wallpaper that was applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub applied_at: chrono::DateTime<Local>,
+    pub mode: WallpaperMode,
+    /// Where it was shown, as listed by `set`
+    pub target: String,
+    /// Position among the mode's assignments, to set it there again
+    pub slot: usize,
+    pub photo: PathBuf,
+}
+
+/// Recently applied wallpapers, and the photos random selection has used
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationHistory {
+    /// Oldest first, at most `HISTORY_PER_TARGET` for each target
+    #[serde(default)]
+    pub entries: Vec<HistoryEntry>,
+    /// Photos applied since random selection last ran out of unused ones
+    #[serde(default)]
+    pub cycle: Vec<PathBuf>,
+}
+
+impl RotationHistory {
+    /// Read the history at `path`; a missing or unreadable file starts a new one
+    pub fn load(path: &Path) -> Self {
+        let Ok(json) = std::fs::read_to_string(path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&json).unwrap_or_else(|e| {
+            warn!(path = %path.display(), "Starting a new history: {}", e);
+            Self::default()
+        })
+    }
+
+    /// Write the history to `path` through a temporary file, so it is never left half written
+    pub fn save(&self, path: &Path) -> Result<(), PhotoError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Add the wallpapers of `results` that were applied at `now`
+    pub fn record(
+        &mut self,
+        mode: WallpaperMode,
+        results: &[AssignmentResult],
+        now: chrono::DateTime<Local>,
+    ) {
+        for applied in results.iter().filter(|r| r.result.is_ok()) {
+            let photo = &applied.assignment.photo_path;
+            self.entries.push(HistoryEntry {
+                applied_at: now,
+                mode,
+                target: applied.assignment.location.clone(),
+                slot: applied.assignment.slot,
+                photo: photo.clone(),
+            });
+            if !self.cycle.contains(photo) {
+                self.cycle.push(photo.clone());
+            }
+        }
+
+        // Keep the newest entries of each target
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        let keep: Vec<bool> = self
+            .entries
+            .iter()
+            .rev()
+            .map(|entry| {
+                let count = seen.entry(entry.target.as_str()).or_default();
+                *count += 1;
+                *count <= HISTORY_PER_TARGET
+            })
+            .collect();
+        let mut keep = keep.into_iter().rev();
+        self.entries.retain(|_| keep.next().unwrap_or(false));
+        let excess = self.cycle.len().saturating_sub(HISTORY_CYCLE_LIMIT);
+        self.cycle.drain(..excess);
+    }
+
+    /// The photo shown on each target now
+    pub fn current(&self) -> Vec<&PathBuf> {
+        self.latest_per_target(1)
+            .into_iter()
+            .map(|entry| &entry.photo)
+            .collect()
+    }
+
+    /// The wallpapers shown before the current ones, on each target of the latest mode
+    pub fn previous(&self) -> Vec<&HistoryEntry> {
+        let mut previous = self.latest_per_target(2);
+        previous.sort_by_key(|entry| entry.slot);
+        previous
+    }
+
+    /// The `nth` newest entry of each target set in the mode used last
+    fn latest_per_target(&self, nth: usize) -> Vec<&HistoryEntry> {
+        let Some(mode) = self.entries.last().map(|entry| entry.mode) else {
+            return Vec::new();
+        };
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        self.entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.mode == mode)
+            .filter(|entry| {
+                let count = seen.entry(entry.target.as_str()).or_default();
+                *count += 1;
+                *count == nth
+            })
+            .collect()
+    }
+
+    /// Leave out photos already used in this cycle, so random picks don't repeat
+    /// until every photo has been shown
+    ///
+    /// Once fewer than `needed` are left the cycle starts over, trying the
+    /// photos on screen now last.
+    pub fn unused_photos(&mut self, photos: Vec<PathBuf>, needed: usize) -> Vec<PathBuf> {
+        let used: std::collections::HashSet<&PathBuf> = self.cycle.iter().collect();
+        let (fresh, used): (Vec<PathBuf>, Vec<PathBuf>) =
+            photos.into_iter().partition(|photo| !used.contains(photo));
+        if fresh.len() >= needed.max(1) {
+            return fresh;
+        }
+        let showing: Vec<PathBuf> = self.current().into_iter().cloned().collect();
+        self.cycle.clear();
+        let (on_screen, rest): (Vec<PathBuf>, Vec<PathBuf>) =
+            used.into_iter().partition(|photo| showing.contains(photo));
+        fresh.into_iter().chain(rest).chain(on_screen).collect()
+    }
+}
+
+/// Add what was applied to the history and point `current` at it, for tools
+/// that want a stable path, then run the post-set hooks
+///
+/// Returns warnings for what couldn't be updated, or the failure of a strict hook.
+fn record_applied(
+    history: &mut RotationHistory,
+    mode: WallpaperMode,
+    monitors: usize,
+    results: &[AssignmentResult],
+    log_path: &str,
+) -> Result<Vec<String>, PhotoError> {
+    let mut warnings = Vec::new();
+    if config().wallpaper.current_link != Some(false) {
+        let wallpapers = current_wallpapers(mode, monitors, results);
+        warnings.extend(link_current_wallpapers(monitors, &wallpapers, log_path));
+    }
+    history.record(mode, results, Local::now());
+    let saved = history_path().and_then(|path| history.save(Path::new(&path)));
+    if let Err(e) = saved {
+        warnings.push(format!("Could not save the wallpaper history: {}", e));
+    }
+    run_post_set_hooks(&config().hooks, results, log_path)?;
+    Ok(warnings)
+}
+
+/// Set the wallpapers shown before the current ones again, as the history
+/// recorded them
+///
+/// Going back is recorded too, so doing it twice returns to where it started.
+pub fn set_previous_wallpapers(
+    backend: &dyn WallpaperBackend,
+) -> Result<WallpaperReport, PhotoError> {
+    let mut history = RotationHistory::load(Path::new(&history_path()?));
+    let assignments: Vec<WallpaperAssignment> = history
+        .previous()
+        .into_iter()
+        .filter(|entry| entry.photo.exists())
+        .map(|entry| WallpaperAssignment {
+            location: entry.target.clone(),
+            photo_path: entry.photo.clone(),
+            is_newest: false,
+            slot: entry.slot,
+        })
+        .collect();
+    let mode = history
+        .previous()
+        .first()
+        .map(|entry| entry.mode)
+        .filter(|_| !assignments.is_empty())
+        .ok_or_else(|| {
+            PhotoError::Wallpaper("No earlier wallpaper in the history to go back to".to_string())
+        })?;
+
+    let log_path = wallpaper_log_path()?;
+    write_log_checked(&log_path, "Setting the previous wallpapers again")?;
+    let de = backend.desktop();
+    let capabilities = backend.capabilities();
+    let results = apply_with_backend(backend, mode, assignments, &log_path, shutdown_flag())?;
+    let warnings = record_applied(
+        &mut history,
+        mode,
+        capabilities.monitors,
+        &results,
+        &log_path,
+    )?;
+
+    Ok(WallpaperReport {
+        desktop: de,
+        requested_mode: mode,
+        mode,
+        sort: SortOrder::default(),
+        spread: SpreadStrategy::None,
+        brightness: None,
+        photos_found: results.len(),
+        monitors: enumerate_monitors(de),
+        monitor_count: capabilities.monitors,
+        virtual_desktop_count: capabilities.virtual_desktops,
+        warnings,
+        results,
+        dry_run: false,
+    })
+}
+
+// ============================================================================
+// Monitor Hotplug
+// ============================================================================
+
+/// How long the monitor set must stay unchanged before wallpapers are re-applied.
+/// A mode switch or dock connection fires a burst of events within a second or two.
+pub const HOTPLUG_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How often `xrandr` is polled for output changes on X11
+const XRANDR_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Longest wait between checks of the cancel flag while watching
+const WATCH_CANCEL_POLL: Duration = Duration::from_millis(500);
+
+/// `KScreen` backend interface whose `configChanged` fires on any output change
+const KSCREEN_BACKEND_INTERFACE: &str = "org.kde.kscreen.Backend";
+
+/// Interface of the `KScreen` kded module, which reports newly connected outputs
+const KSCREEN_MODULE_INTERFACE: &str = "org.kde.KScreen";
+
+/// Where monitor change events come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputWatcher {
+    /// `KScreen` signals on the session D-Bus
+    KScreen,
+    /// `swaymsg -t subscribe` output events
+    Sway,
+    /// Polling `xrandr --current`, for other X11 sessions
+    Xrandr,
+}
+
+impl std::fmt::Display for OutputWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KScreen => write!(f, "KScreen"),
+            Self::Sway => write!(f, "sway"),
+            Self::Xrandr => write!(f, "xrandr"),
+        }
+    }
+}
+
+impl OutputWatcher {
+    /// The event source for the session, or `None` where changes can't be watched
+    pub fn select(de: DesktopEnvironment, probe: &dyn SystemProbe) -> Option<Self> {
+        if matches!(
+            de,
+            DesktopEnvironment::KdePlasma6
+                | DesktopEnvironment::KdePlasma5
+                | DesktopEnvironment::PlasmaFallback
+        ) && cfg!(target_os = "linux")
+        {
+            return Some(Self::KScreen);
+        }
+        if probe.env_var("SWAYSOCK").is_some() && probe.command_exists("swaymsg") {
+            return Some(Self::Sway);
+        }
+        if probe.env_var("DISPLAY").is_some() && probe.command_exists("xrandr") {
+            return Some(Self::Xrandr);
+        }
+        None
+    }
+
+    /// Start watching in the background, sending `()` on `events` for every change.
+    /// The sender is dropped when the source goes away, which ends [`run_debounced`].
+    pub fn spawn(self, events: Sender<()>) -> Result<(), PhotoError> {
+        match self {
+            Self::KScreen => plasma_dbus::watch_kscreen(events),
+            Self::Sway => watch_sway(events),
+            Self::Xrandr => watch_xrandr(events),
+        }
+    }
+}
+
+/// Whether a D-Bus signal from `KScreen` means the outputs changed
+pub fn is_kscreen_output_signal(interface: &str, member: &str) -> bool {
+    match interface {
+        KSCREEN_BACKEND_INTERFACE => member == "configChanged",
+        KSCREEN_MODULE_INTERFACE => matches!(member, "outputConnected" | "unknownOutputConnected"),
+        _ => false,
+    }
+}
+
+/// Whether a line from `swaymsg -r -m -t subscribe '["output"]'` is an output event.
+/// The `{"success": true}` acknowledging the subscription is not.
+pub fn decode_sway_event(line: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(line).is_ok_and(|event| event.get("change").is_some())
+}
+
+/// Subscribe to sway output events
+fn watch_sway(events: Sender<()>) -> Result<(), PhotoError> {
+    let mut child = Command::new("swaymsg")
+        .args(["-r", "-m", "-t", "subscribe", r#"["output"]"#])
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| PhotoError::Command(format!("swaymsg: {}", e)))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| PhotoError::Command("swaymsg: no output".to_string()))?;
+    std::thread::spawn(move || {
+        use std::io::BufRead;
+        for line in io::BufReader::new(stdout).lines().map_while(Result::ok) {
+            if decode_sway_event(&line) && events.send(()).is_err() {
+                break;
+            }
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    });
+    Ok(())
+}
+
+/// Poll `xrandr` and report whenever the connected outputs or their modes change
+fn watch_xrandr(events: Sender<()>) -> Result<(), PhotoError> {
+    let xrandr_outputs =
+        || command_stdout("xrandr", &["--current"]).map(|text| parse_xrandr_outputs(&text));
+    let mut last = xrandr_outputs()
+        .ok_or_else(|| PhotoError::Command("xrandr --current failed".to_string()))?;
+    std::thread::spawn(move || loop {
+        std::thread::sleep(XRANDR_POLL_INTERVAL);
+        let Some(outputs) = xrandr_outputs() else {
+            break;
+        };
+        if outputs != last {
+            last = outputs;
+            if events.send(()).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Collapses a burst of change events into one, once `quiet` has passed without any
+#[derive(Debug, Clone)]
+pub struct Debouncer {
+    quiet: Duration,
+    last_event: Option<Instant>,
+}
+
+impl Debouncer {
+    pub const fn new(quiet: Duration) -> Self {
+        Self {
+            quiet,
+            last_event: None,
+        }
+    }
+
+    /// Record a change seen at `now`
+    pub const fn event(&mut self, now: Instant) {
+        self.last_event = Some(now);
+    }
+
+    /// How long until a pending change is due, or `None` with nothing pending
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.last_event.map(|last| {
+            self.quiet
+                .saturating_sub(now.saturating_duration_since(last))
+        })
+    }
+
+    /// Whether a pending change is due at `now`, clearing it if so
+    pub fn fire(&mut self, now: Instant) -> bool {
+        let due = self.remaining(now).is_some_and(|left| left.is_zero());
+        if due {
+            self.last_event = None;
+        }
+        due
+    }
+
+    /// Clear a pending change whether or not it is due, returning whether there was one
+    pub const fn flush(&mut self) -> bool {
+        self.last_event.take().is_some()
+    }
+}
+
+/// Call `on_change` once per burst of `events`, after `quiet` without new ones
+///
+/// Returns `PhotoError::Interrupted` when `cancel` is raised, and an error once
+/// every sender is gone, after handling a change still pending.
+pub fn run_debounced(
+    events: &Receiver<()>,
+    quiet: Duration,
+    cancel: &CancelFlag,
+    mut on_change: impl FnMut(),
+) -> Result<(), PhotoError> {
+    let mut debouncer = Debouncer::new(quiet);
+    loop {
+        cancel.check()?;
+        let wait = debouncer
+            .remaining(Instant::now())
+            .map_or(WATCH_CANCEL_POLL, |left| left.min(WATCH_CANCEL_POLL));
+        match events.recv_timeout(wait) {
+            Ok(()) => debouncer.event(Instant::now()),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                if debouncer.flush() {
+                    on_change();
+                }
+                return Err(PhotoError::Command(
+                    "Stopped receiving monitor change events".to_string(),
+                ));
+            }
+        }
+        if debouncer.fire(Instant::now()) {
+            on_change();
+        }
+    }
+}
+
+// ============================================================================
+// Daemon
+// ============================================================================
+
+/// Path of the pidfile that keeps a second `daemon` from starting
+pub fn daemon_pid_path() -> Result<String, PhotoError> {
+    Ok(format!("{}daemon.pid", log_dir()?))
+}
+
+/// When the daemon next changes the wallpaper
+#[derive(Debug, Clone)]
+pub struct RotationSchedule {
+    interval: Duration,
+    next: Instant,
+}
+
+impl RotationSchedule {
+    /// A schedule rotating every `interval`, starting at `now`
+    pub const fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            next: now,
+        }
+    }
+
+    /// Whether a rotation is due at `now`
+    pub fn due(&self, now: Instant) -> bool {
+        now >= self.next
+    }
+
+    /// How long until the next rotation
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.next.saturating_duration_since(now)
+    }
+
+    /// Schedule the rotation after one done at `now`.
+    /// Rotations stay on the original beat, but ones missed while the loop
+    /// was busy are skipped rather than run back to back.
+    pub fn advance(&mut self, now: Instant) {
+        self.next += self.interval;
+        if self.next <= now {
+            self.next = now + self.interval;
+        }
+    }
+
+    /// Rotate right away, restarting the interval from there
+    pub const fn rotate_now(&mut self, now: Instant) {
+        self.next = now;
+    }
+}
+
+/// Rotate the wallpaper on `schedule` until `cancel` is raised, then return.
+/// A message on `rescan` rotates immediately, with the photos found then.
+pub fn run_rotation(
+    schedule: &mut RotationSchedule,
+    rescan: &Receiver<()>,
+    cancel: &CancelFlag,
+    mut rotate: impl FnMut(),
+) {
+    while !cancel.is_cancelled() {
+        if schedule.due(Instant::now()) {
+            rotate();
+            schedule.advance(Instant::now());
+            continue;
+        }
+        let wait = schedule.remaining(Instant::now()).min(WATCH_CANCEL_POLL);
+        match rescan.recv_timeout(wait) {
+            Ok(()) => schedule.rotate_now(Instant::now()),
+            Err(RecvTimeoutError::Timeout) => {}
+            // Nothing sends rescans on this platform
+            Err(RecvTimeoutError::Disconnected) => std::thread::sleep(wait),
+        }
+    }
+}
+
+/// Send `()` on `rescan` for every SIGHUP, from a background thread
+#[cfg(unix)]
+pub fn forward_hangups(rescan: Sender<()>) -> Result<(), PhotoError> {
+    use futures_lite::StreamExt;
+    let mut signals = async_signal::Signals::new([async_signal::Signal::Hup])?;
+    std::thread::spawn(move || {
+        futures_lite::future::block_on(async {
+            while let Some(Ok(_)) = signals.next().await {
+                if rescan.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+    });
+    Ok(())
+}
+
+/// There is no SIGHUP off Unix, so `rescan` is dropped
+#[cfg(not(unix))]
+pub fn forward_hangups(_rescan: Sender<()>) -> Result<(), PhotoError> {
+    Ok(())
+}
+
+/// A pidfile held while this process runs, removed when dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write this process's pid to `path`, failing if a live process holds it
+    pub fn acquire(path: impl Into<PathBuf>) -> Result<Self, PhotoError> {
+        Self::acquire_with(path, pid_alive)
+    }
+
+    /// [`PidFile::acquire`], with `alive` deciding whether a recorded pid is still running.
+    /// A pidfile left by a process that died without removing it is taken over.
+    pub fn acquire_with(
+        path: impl Into<PathBuf>,
+        alive: impl Fn(u32) -> bool,
+    ) -> Result<Self, PhotoError> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    writeln!(file, "{}", std::process::id())?;
+                    return Ok(Self { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let holder = std::fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|pid| pid.trim().parse::<u32>().ok());
+                    if let Some(pid) = holder.filter(|&pid| alive(pid)) {
+                        return Err(PhotoError::Command(format!(
+                            "Already running as pid {} ({})",
+                            pid,
+                            path.display()
+                        )));
+                    }
+                    std::fs::remove_file(&path)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(PhotoError::Command(format!(
+            "Couldn't create {}",
+            path.display()
+        )))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Whether a process with this pid is running
+fn pid_alive(pid: u32) -> bool {
+    if cfg!(windows) {
+        return command_stdout("tasklist", &["/FI", &format!("PID eq {}", pid), "/NH"])
+            .is_some_and(|out| out.contains(&pid.to_string()));
+    }
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .output()
+        .is_ok_and(|o| o.status.success())
+}
+
+// ============================================================================
+// Scheduling
+// ============================================================================
+
+/// When the scheduled job runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleType {
+    /// Fixed daily time (e.g., "02:00")
+    DailyTime(String),
+    /// Interval (e.g., "1h", "30m")
+    Interval(String),
+}
+
+impl std::fmt::Display for ScheduleType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DailyTime(time) => write!(f, "{} daily", time),
+            Self::Interval(interval) => write!(f, "every {}", interval),
+        }
+    }
+}
+
+impl ScheduleType {
+    /// Hour and minute of a daily time
+    pub fn daily_time(&self) -> Option<(u8, u8)> {
+        let Self::DailyTime(time) = self else {
+            return None;
+        };
+        let (hour, minute) = time.split_once(':')?;
+        Some((hour.parse().ok()?, minute.parse().ok()?))
+    }
+
+    /// Seconds between runs of an interval, such as 9000 for "2h30m"
+    pub fn interval_secs(&self) -> Option<u64> {
+        let Self::Interval(interval) = self else {
+            return None;
+        };
+        let mut total = 0;
+        let mut value = String::new();
+        for c in interval.to_lowercase().chars() {
+            match c {
+                'h' => total += value.parse::<u64>().ok()? * 3600,
+                'm' => total += value.parse::<u64>().ok()? * 60,
+                _ => {
+                    value.push(c);
+                    continue;
+                }
+            }
+            value.clear();
+        }
+        Some(total)
+    }
+}
+
+/// Validate time format HH:MM
+pub fn is_valid_time(time: &str) -> bool {
+    if time.len() != 5 {
+        return false;
+    }
+    let parts: Vec<&str> = time.split(':').collect();
+    if parts.len() != 2 {
+        return false;
+    }
+    let hour: Result<u8, _> = parts[0].parse();
+    let minute: Result<u8, _> = parts[1].parse();
+
+    matches!((hour, minute), (Ok(h), Ok(m)) if h < 24 && m < 60)
+}
+
+/// Validate interval format (e.g., "1h", "30m", "2h30m")
+pub fn is_valid_interval(interval: &str) -> bool {
+    if interval.is_empty() {
+        return false;
+    }
+    // Must contain at least one h or m
+    if !interval.contains('h') && !interval.contains('m') {
+        return false;
+    }
+    // Check format: optional number+h followed by optional number+m
+    let s = interval.to_lowercase();
+    let mut has_value = false;
+
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            has_value = true;
+        } else if c == 'h' || c == 'm' {
+            if !has_value {
+                return false;
+            }
+            has_value = false;
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+/// Seconds in an interval such as `30m` or `1h30m`; `None` if it isn't one or is zero
+pub fn interval_seconds(interval: &str) -> Option<u64> {
+    if !is_valid_interval(interval) {
+        return None;
+    }
+    let mut total: u64 = 0;
+    let mut value: u64 = 0;
+    for c in interval.to_lowercase().chars() {
+        match c {
+            'h' | 'm' => {
+                let unit = if c == 'h' { 3600 } else { 60 };
+                total = total.checked_add(value.checked_mul(unit)?)?;
+                value = 0;
+            }
+            digit => {
+                value = value
+                    .checked_mul(10)?
+                    .checked_add(u64::from(digit.to_digit(10)?))?;
+            }
+        }
+    }
+    (total > 0).then_some(total)
+}
+
+/// Parse time or interval from command line argument
+pub fn parse_schedule(time_arg: &str) -> Result<ScheduleType, PhotoError> {
+    if is_valid_time(time_arg) {
+        Ok(ScheduleType::DailyTime(time_arg.to_string()))
+    } else if is_valid_interval(time_arg) {
+        Ok(ScheduleType::Interval(time_arg.to_string()))
+    } else {
+        Err(PhotoError::Command(format!(
+            "Invalid time/interval format: {}. Use HH:MM for daily time or intervals like 1h, 30m",
+            time_arg
+        )))
+    }
+}
+
+impl ScheduleType {
+    /// The five cron fields for this schedule. Intervals must divide an hour or a
+    /// day evenly, since cron restarts its count at each hour and midnight.
+    pub fn cron_expression(&self) -> Result<String, PhotoError> {
+        if let Some((hour, minute)) = self.daily_time() {
+            return Ok(format!("{} {} * * *", minute, hour));
+        }
+        let minutes = self.interval_secs().unwrap_or(0) / 60;
+        match minutes {
+            1..=59 if 60_u64.is_multiple_of(minutes) => Ok(format!("*/{} * * * *", minutes)),
+            60 => Ok("0 * * * *".to_string()),
+            _ if minutes.is_multiple_of(60) && 24_u64.is_multiple_of(minutes / 60) => {
+                Ok(format!("0 */{} * * *", minutes / 60))
+            }
+            _ => Err(PhotoError::Command(format!(
+                "cron can't run {}: use an interval that divides an hour or a day evenly",
+                self
+            ))),
+        }
+    }
+}
+
+/// Comment marking the crontab line this tool manages
+pub const CRON_TAG: &str = "# natgeo-wallpapers";
+
+/// Quote an argument for `/bin/sh`
+pub fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// A tagged crontab line running the shell `command` on `schedule` with `env` exported
+pub fn cron_line(
+    schedule: &ScheduleType,
+    env: &[(&str, &str)],
+    command: &str,
+) -> Result<String, PhotoError> {
+    let mut fields = vec![schedule.cron_expression()?];
+    fields.extend(
+        env.iter()
+            .map(|(key, value)| format!("{}={}", key, shell_quote(value))),
+    );
+    // cron turns unescaped % into newlines
+    fields.push(command.replace('%', "\\%"));
+    fields.push(CRON_TAG.to_string());
+    Ok(fields.join(" "))
+}
+
+/// The crontab from the output of `crontab -l`
+///
+/// `crontab -l` also fails when the user has no crontab yet, which is an
+/// empty one. Any other failure is an error, since writing back a crontab
+/// that couldn't be read would wipe the user's other jobs.
+pub fn crontab_from_listing(
+    success: bool,
+    stdout: &str,
+    stderr: &str,
+) -> Result<String, PhotoError> {
+    if success {
+        Ok(stdout.to_string())
+    } else if stderr.to_lowercase().contains("no crontab for") {
+        Ok(String::new())
+    } else {
+        Err(PhotoError::Command(format!(
+            "crontab -l failed: {}",
+            stderr.trim()
+        )))
+    }
+}
+
+/// `crontab` with any tagged line replaced by `line`
+pub fn crontab_with_line(crontab: &str, line: &str) -> String {
+    let (mut kept, _) = crontab_without_line(crontab);
+    kept.push_str(line);
+    kept.push('\n');
+    kept
+}
+
+/// `crontab` without tagged lines, and whether any were removed
+pub fn crontab_without_line(crontab: &str) -> (String, bool) {
+    let mut removed = false;
+    let mut kept = String::new();
+    for line in crontab.lines() {
+        if line.trim_end().ends_with(CRON_TAG) {
+            removed = true;
+        } else {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    (kept, removed)
+}
+
+/// launchd label of the macOS agent
+pub const LAUNCHD_LABEL: &str = "com.natgeo-wallpapers";
+
+/// Escape text for an XML element
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Contents of the macOS launch agent plist running `program_arguments` on `schedule`,
+/// with output appended to `log_path`
+pub fn launchd_plist(
+    program_arguments: &[String],
+    schedule: &ScheduleType,
+    log_path: &str,
+) -> Result<String, PhotoError> {
+    let invalid = || PhotoError::Command(format!("Invalid schedule: {:?}", schedule));
+    let trigger = match schedule {
+        ScheduleType::DailyTime(_) => {
+            let (hour, minute) = schedule.daily_time().ok_or_else(invalid)?;
+            format!(
+                "    <key>StartCalendarInterval</key>
+    <dict>
+        <key>Hour</key>
+        <integer>{hour}</integer>
+        <key>Minute</key>
+        <integer>{minute}</integer>
+    </dict>
+"
+            )
+        }
+        ScheduleType::Interval(_) => format!(
+            "    <key>StartInterval</key>
+    <integer>{}</integer>
+",
+            schedule
+                .interval_secs()
+                .filter(|s| *s > 0)
+                .ok_or_else(invalid)?
+        ),
+    };
+    let mut arguments = String::new();
+    for arg in program_arguments {
+        arguments.push_str("        <string>");
+        arguments.push_str(&xml_escape(arg));
+        arguments.push_str("</string>\n");
+    }
+    let log_path = xml_escape(log_path);
+
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{LAUNCHD_LABEL}</string>
+    <key>ProgramArguments</key>
+    <array>
+{arguments}    </array>
+{trigger}    <key>RunAtLoad</key>
+    <true/>
+    <key>StandardOutPath</key>
+    <string>{log_path}</string>
+    <key>StandardErrorPath</key>
+    <string>{log_path}</string>
+</dict>
+</plist>
+"#
+    ))
+}
+
+/// File name of the login autostart entry in `~/.config/autostart/`
+pub const AUTOSTART_FILE_NAME: &str = "natgeo-wallpapers.desktop";
+
+/// Quote an argument for the `Exec` key of a desktop entry
+///
+/// Inside quotes `"`, `` ` ``, `$` and `\` are backslash-escaped, and the
+/// string escaping of the file then doubles every backslash again.
+fn desktop_exec_quote(arg: &str) -> String {
+    // A lone % starts a field code such as %f
+    let arg = arg.replace('%', "%%");
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-+,:=@%".contains(c))
+    {
+        return arg;
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '`' | '$' => {
+                quoted.push_str("\\\\");
+                quoted.push(c);
+            }
+            '\\' => quoted.push_str("\\\\\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Contents of a desktop entry that runs `binary run` with the given set
+/// options when the user logs in
+pub fn autostart_desktop_entry(
+    binary: &str,
+    random: bool,
+    path: Option<&str>,
+    lock_screen: bool,
+) -> String {
+    let mut args = vec![desktop_exec_quote(binary), "run".to_string()];
+    if random {
+        args.push("--random".to_string());
+    }
+    if let Some(path) = path {
+        args.push("--path".to_string());
+        args.push(desktop_exec_quote(path));
+    }
+    if lock_screen {
+        args.push("--lock-screen".to_string());
+    }
+
+    format!(
+        "[Desktop Entry]
+Type=Application
+Name=National Geographic Wallpaper
+Comment=Download the National Geographic Photo of the Day and set it as wallpaper
+Exec={}
+Terminal=false
+NoDisplay=true
+X-GNOME-Autostart-enabled=true
+",
+        args.join(" ")
+    )
+}
+
+// ============================================================================
+// Backfill
+// ============================================================================
+
+/// Default pause between the days of a backfill, to go easy on the site
+pub const DEFAULT_BACKFILL_DELAY: Duration = Duration::from_secs(2);
+
+/// An inclusive range of days
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateRange {
+    pub from: chrono::NaiveDate,
+    pub to: chrono::NaiveDate,
+}
+
+impl DateRange {
+    /// Days from `from` to `to`. The range must not be empty or end in the future.
+    pub fn new(from: chrono::NaiveDate, to: chrono::NaiveDate) -> Result<Self, PhotoError> {
+        if to < from {
+            return Err(PhotoError::InvalidDate(format!(
+                "{} is before {}",
+                to, from
+            )));
+        }
+        validate_photo_date(to)?;
+        Ok(Self { from, to })
+    }
+
+    /// The last `days` days, ending with `today`
+    pub fn last(days: u32, today: chrono::NaiveDate) -> Result<Self, PhotoError> {
+        let from = days
+            .checked_sub(1)
+            .and_then(|back| today.checked_sub_days(chrono::Days::new(u64::from(back))))
+            .ok_or_else(|| PhotoError::InvalidDate(format!("can't go back {} days", days)))?;
+        Self::new(from, today)
+    }
+
+    /// Each day in the range, oldest first
+    pub fn days(&self) -> impl Iterator<Item = chrono::NaiveDate> {
+        let to = self.to;
+        self.from.iter_days().take_while(move |day| *day <= to)
+    }
+}
+
+/// Settings for `backfill`
+#[derive(Debug, Clone)]
+pub struct BackfillOptions {
+    /// Directory holding the daily date directories
+    pub base_dir: String,
+    /// Pause between days that had to be fetched
+    pub delay: Duration,
+    /// Post-processing and HTTP settings for each photo
+    pub download: DownloadOptions,
+    /// Log shared by every day of the backfill
+    pub log_path: String,
+}
+
+impl BackfillOptions {
+    /// Options for the default photo and log directories
+    pub fn from_defaults() -> Result<Self, PhotoError> {
+        Ok(Self {
+            base_dir: photo_dir()?,
+            delay: DEFAULT_BACKFILL_DELAY,
+            download: DownloadOptions::default(),
+            log_path: format!("{}backfill.log", log_dir()?),
+        })
+    }
+}
+
+/// How one day of a backfill ended up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackfillStatus {
+    /// The day's photo was saved here
+    Downloaded(PathBuf),
+    /// The day already had this photo, so nothing was fetched or saved
+    Skipped(PathBuf),
+    /// Fetching or saving failed for the given reason
+    Failed(String),
+}
+
+/// Called with each day of a backfill once it is done
+pub type BackfillProgress<'a> = &'a dyn Fn(chrono::NaiveDate, &BackfillStatus);
+
+/// Result of a backfill, per day and in total
+#[derive(Debug, Default)]
+pub struct BackfillReport {
+    pub days: Vec<(chrono::NaiveDate, BackfillStatus)>,
+    pub downloaded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// A photo already saved in `dir`, if any
+fn first_photo_in(dir: &Path) -> Option<PathBuf> {
+    let mut photos: Vec<_> = std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| has_photo_extension(path))
+        .collect();
+    photos.sort();
+    photos.into_iter().next()
+}
+
+/// Download the photo of every day in `range` into its date directory.
+/// Days that already have a photo are skipped without a request. `progress`, if
+/// given, is told how each day ended up.
+pub fn backfill(
+    range: &DateRange,
+    options: &BackfillOptions,
+    cancel: &CancelFlag,
+    progress: Option<BackfillProgress<'_>>,
+) -> Result<BackfillReport, PhotoError> {
+    let client = NatGeoClient::new(options.download.http.clone())?;
+    let mut fetch = |date, save_dir: &str| {
+        let info = client.photo_for_date(date)?;
+        let title = downloaded_title(save_dir, &info.title);
+        let log_path = format!("{}/{}.log", save_dir, title);
+        let outcome = client.download_with_options(
+            &info.image_url,
+            save_dir,
+            &title,
+            &log_path,
+            &options.download,
+        )?;
+        if let DownloadOutcome::Downloaded(path) = &outcome {
+            record_photo_metadata(&info, path, &options.download, &log_path);
+            run_hooks(
+                HookEvent::PostDownload,
+                &HookContext::downloaded(&info, path),
+                &log_path,
+            )?;
+        }
+        Ok(outcome)
+    };
+    backfill_with(range, options, cancel, &mut fetch, progress)
+}
+
+/// Like `backfill`, with `fetch` downloading a day's photo into the given directory
+pub fn backfill_with(
+    range: &DateRange,
+    options: &BackfillOptions,
+    cancel: &CancelFlag,
+    fetch: &mut dyn FnMut(chrono::NaiveDate, &str) -> Result<DownloadOutcome, PhotoError>,
+    progress: Option<BackfillProgress<'_>>,
+) -> Result<BackfillReport, PhotoError> {
+    let log_path = options.log_path.as_str();
+    let base_dir = if options.base_dir.ends_with('/') {
+        options.base_dir.clone()
+    } else {
+        format!("{}/", options.base_dir)
+    };
+    ensure_managed_dir(&base_dir)?;
+    write_log_checked(
+        log_path,
+        &format!("Starting backfill from {} to {}", range.from, range.to),
+    )?;
+
+    let mut report = BackfillReport::default();
+    let mut fetched_before = false;
+    for date in range.days() {
+        if cancel.is_cancelled() {
+            write_log(
+                log_path,
+                &format!(
+                    "Interrupted: {} downloaded, {} skipped, {} failed",
+                    report.downloaded, report.skipped, report.failed
+                ),
+            );
+            return Err(PhotoError::Interrupted);
+        }
+
+        let save_dir = date_dir_for(&base_dir, date);
+        let status = if let Some(existing) = first_photo_in(Path::new(&save_dir)) {
+            BackfillStatus::Skipped(existing)
+        } else {
+            if fetched_before {
+                std::thread::sleep(options.delay);
+            }
+            fetched_before = true;
+            std::fs::create_dir_all(&save_dir)?;
+            let status = match fetch(date, &save_dir) {
+                Ok(DownloadOutcome::Downloaded(path)) => BackfillStatus::Downloaded(path),
+                Ok(DownloadOutcome::AlreadyExists(path) | DownloadOutcome::Duplicate(path)) => {
+                    BackfillStatus::Skipped(path)
+                }
+                Err(PhotoError::Interrupted) => return Err(PhotoError::Interrupted),
+                Err(e) => BackfillStatus::Failed(e.to_string()),
+            };
+            // Don't leave an empty directory behind for a day with nothing saved
+            let _ = std::fs::remove_dir(&save_dir);
+            status
+        };
+
+        let line = match &status {
+            BackfillStatus::Downloaded(path) => {
+                report.downloaded += 1;
+                format!("{}: downloaded {}", date, path.display())
+            }
+            BackfillStatus::Skipped(path) => {
+                report.skipped += 1;
+                format!("{}: skipped, already have {}", date, path.display())
+            }
+            BackfillStatus::Failed(reason) => {
+                report.failed += 1;
+                format!("{}: failed: {}", date, reason)
+            }
+        };
+        write_log(log_path, &line);
+        if let Some(progress) = progress {
+            progress(date, &status);
+        }
+        report.days.push((date, status));
+    }
+
+    write_log(
+        log_path,
+        &format!(
+            "Backfill complete: {} downloaded, {} skipped, {} failed",
+            report.downloaded, report.skipped, report.failed
+        ),
+    );
+    Ok(report)
+}
+
+// ============================================================================
+// Download and Set
+// ============================================================================
+
+/// Times `run` tries the download before falling back to existing photos
+pub const DEFAULT_RUN_ATTEMPTS: u32 = 3;
+
+/// Base pause between download attempts in `run`, doubled after each failure
+pub const RUN_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Retry behavior for the download phase of `download_then_set`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunOptions {
+    pub attempts: u32,
+    pub retry_delay: Duration,
+    /// Treat a failed download as a success when the wallpaper was still set.
+    /// Off, any failed download fails the run.
+    pub offline_ok: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            attempts: DEFAULT_RUN_ATTEMPTS,
+            retry_delay: RUN_RETRY_DELAY,
+            offline_ok: true,
+        }
+    }
+}
+
+/// How a download-then-set run went
+#[derive(Debug)]
+pub enum RunOutcome {
+    /// Both phases succeeded
+    Complete,
+    /// Offline, so the wallpaper was set from existing photos
+    Degraded(PhotoError),
+    /// The download failed, but the wallpaper was set from existing photos
+    DownloadFailed(PhotoError),
+    /// The download failed without `offline_ok`, so the run failed even though
+    /// the wallpaper was set from existing photos
+    DownloadRequired(PhotoError),
+    /// The photo was downloaded but the wallpaper couldn't be set
+    SetFailed(PhotoError),
+    /// Neither phase succeeded
+    Failed {
+        download: PhotoError,
+        set: PhotoError,
+    },
+}
+
+impl RunOutcome {
+    /// Whether the run should be reported as a failure: when neither phase
+    /// succeeded, or the download failed without `offline_ok`. The other
+    /// outcomes still leave a usable wallpaper.
+    pub const fn is_failure(&self) -> bool {
+        matches!(self, Self::Failed { .. } | Self::DownloadRequired(_))
+    }
+}
+
+impl std::fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Complete => write!(f, "Run complete: downloaded and set wallpaper"),
+            Self::Degraded(e) => write!(
+                f,
+                "Run degraded: offline ({}), set wallpaper from existing photos",
+                e
+            ),
+            Self::DownloadFailed(e) => write!(
+                f,
+                "Run degraded: download failed ({}), set wallpaper from existing photos",
+                e
+            ),
+            Self::DownloadRequired(e) => write!(
+                f,
+                "Run failed: download failed ({}), set wallpaper from existing photos",
+                e
+            ),
+            Self::SetFailed(e) => write!(
+                f,
+                "Run degraded: downloaded, but could not set wallpaper ({})",
+                e
+            ),
+            Self::Failed { download, set } => write!(
+                f,
+                "Run failed: download failed ({}) and could not set wallpaper ({})",
+                download, set
+            ),
+        }
+    }
+}
+
+/// Run `download`, retrying with backoff, then `set` whether or not it succeeded.
+///
+/// The wallpaper still changes when the download fails. With `offline_ok` that
+/// counts as a degraded success; without it the run fails, so a scheduled run
+/// that never fetches a new photo gets noticed. Only an interruption is an
+/// error; everything else is reported in the outcome.
+pub fn download_then_set(
+    options: RunOptions,
+    cancel: &CancelFlag,
+    download: &mut dyn FnMut() -> Result<(), PhotoError>,
+    set: &mut dyn FnMut() -> Result<(), PhotoError>,
+) -> Result<RunOutcome, PhotoError> {
+    let mut download_error = None;
+    for attempt in 1..=options.attempts.max(1) {
+        cancel.check()?;
+        if attempt > 1 {
+            std::thread::sleep(backoff_delay(options.retry_delay, attempt - 1));
+            cancel.check()?;
+        }
+        match download() {
+            Ok(()) => {
+                download_error = None;
+                break;
+            }
+            Err(PhotoError::Interrupted) => return Err(PhotoError::Interrupted),
+            Err(e) if e.is_permanent() => {
+                download_error = Some(e);
+                break;
+            }
+            Err(e) => download_error = Some(e),
+        }
+    }
+
+    cancel.check()?;
+    let outcome = match (download_error, set()) {
+        (_, Err(PhotoError::Interrupted)) => return Err(PhotoError::Interrupted),
+        (None, Ok(())) => RunOutcome::Complete,
+        (Some(download), Ok(())) if options.offline_ok && download.is_offline() => {
+            RunOutcome::Degraded(download)
+        }
+        (Some(download), Ok(())) if options.offline_ok => RunOutcome::DownloadFailed(download),
+        (Some(download), Ok(())) => RunOutcome::DownloadRequired(download),
+        (None, Err(set)) => RunOutcome::SetFailed(set),
+        (Some(download), Err(set)) => RunOutcome::Failed { download, set },
+    };
+    Ok(outcome)
+}
+
+// ============================================================================
+// Photo Listing
+// ============================================================================
+
+/// A downloaded photo as shown by `list`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PhotoEntry {
+    pub path: PathBuf,
+    /// Name of the directory the photo is in (a date or a collection)
+    pub directory: String,
+    pub filename: String,
+    pub size: u64,
+    /// Pixel dimensions, when the image header can be read
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Date of the photo's date directory, or of its last modification otherwise
+    pub date: chrono::NaiveDate,
+}
+
+impl PhotoEntry {
+    /// Describe the photo at `path`
+    pub fn from_path(path: &Path) -> Result<Self, PhotoError> {
+        let metadata = std::fs::metadata(path)?;
+        let name_of = |p: Option<&Path>| {
+            p.and_then(|p| p.file_name())
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let date = photo_dir_date(path).unwrap_or_else(|| {
+            let modified: chrono::DateTime<Local> =
+                metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH).into();
+            modified.date_naive()
+        });
+        let dimensions = photo_dimensions(path).ok();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            directory: name_of(path.parent()),
+            filename: name_of(Some(path)),
+            size: metadata.len(),
+            width: dimensions.map(|(w, _)| w),
+            height: dimensions.map(|(_, h)| h),
+            date,
+        })
+    }
+}
+
+/// Filters for `list_photos`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListOptions {
+    /// Also list photos under the collections directory
+    pub include_collections: bool,
+    /// Stop after this many photos
+    pub limit: Option<usize>,
+    /// Skip photos dated before this day
+    pub since: Option<chrono::NaiveDate>,
+}
+
+/// Photos yielded by `list_photos`, newest first.
+/// Each photo is only stat-ed and probed when it is reached.
+#[derive(Debug)]
+pub struct PhotoEntries {
+    photos: std::vec::IntoIter<PathBuf>,
+    since: Option<chrono::NaiveDate>,
+    remaining: Option<usize>,
+}
+
+impl Iterator for PhotoEntries {
+    type Item = PhotoEntry;
+
+    fn next(&mut self) -> Option<PhotoEntry> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+        // Photos removed since the scan are skipped
+        let entry = self
+            .photos
+            .by_ref()
+            .filter_map(|path| PhotoEntry::from_path(&path).ok())
+            .find(|entry| self.since.is_none_or(|since| entry.date >= since))?;
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= 1;
+        }
+        Some(entry)
+    }
+}
+
+/// List the photos under `photo_root`, leaving out `collection_root` unless
+/// `options.include_collections` is set. A missing directory lists nothing.
+pub fn list_photos(
+    photo_root: &Path,
+    collection_root: &Path,
+    options: &ListOptions,
+) -> Result<PhotoEntries, PhotoError> {
+    let mut photos = Vec::new();
+    collect_photos(photo_root, &mut photos);
+    if options.include_collections {
+        if !collection_root.starts_with(photo_root) {
+            collect_photos(collection_root, &mut photos);
+        }
+    } else {
+        photos.retain(|photo| !photo.starts_with(collection_root));
+    }
+    sort_photos(&mut photos, SortOrder::Date);
+
+    Ok(PhotoEntries {
+        photos: photos.into_iter(),
+        since: options.since,
+        remaining: options.limit,
+    })
+}
+
+// ============================================================================
+// Photo Picker
+// ============================================================================
+
+/// A photo offered by `set --pick`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PickCandidate {
+    pub entry: PhotoEntry,
+    /// The file name without its extension, with `_` shown as spaces
+    pub title: String,
+}
+
+/// The photos under `path` (or the photo directory) to pick from, newest first
+pub fn pick_candidates(path: Option<&str>) -> Result<Vec<PickCandidate>, PhotoError> {
+    let candidates = find_photos_in_path_sorted(path, SortOrder::Date)?
+        .iter()
+        .filter_map(|photo| PhotoEntry::from_path(photo).ok())
+        .map(|entry| PickCandidate {
+            title: picker_title(&entry.path),
+            entry,
+        })
+        .collect();
+    Ok(candidates)
+}
+
+/// Readable title of a photo: its sanitized title is the file name
+pub fn picker_title(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().replace('_', " "))
+        .unwrap_or_default()
+}
+
+/// Lowercase `text` and treat `_`, `-` and `.` as spaces
+fn search_text(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '_' | '-' | '.' => ' ',
+            c => c,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// How well `query` matches `title`, or `None` if it doesn't.
+///
+/// A substring match beats any subsequence match, and earlier substrings
+/// and tighter subsequences score higher. An empty query matches everything.
+pub fn fuzzy_score(query: &str, title: &str) -> Option<u32> {
+    let query = search_text(query);
+    let query = query.split_whitespace().collect::<Vec<_>>().join(" ");
+    let title = search_text(title);
+    if query.is_empty() {
+        return Some(0);
+    }
+    if let Some(position) = title.find(&query) {
+        let position = u32::try_from(position).unwrap_or(u32::MAX);
+        return Some(2000 - position.min(999));
+    }
+
+    // Every query character, in order, with as few skipped characters as possible
+    let mut wanted = query.chars().filter(|c| *c != ' ').peekable();
+    let mut gaps = 0u32;
+    let mut started = false;
+    for c in title.chars() {
+        let Some(&next) = wanted.peek() else { break };
+        if c == next {
+            wanted.next();
+            started = true;
+        } else if started {
+            gaps += 1;
+        }
+    }
+    if wanted.peek().is_some() {
+        return None;
+    }
+    Some(1000 - gaps.min(999))
+}
+
+/// The candidates matching `query`, best match first; ties stay newest first
+pub fn search_candidates<'a>(
+    candidates: &'a [PickCandidate],
+    query: &str,
+) -> Vec<&'a PickCandidate> {
+    let mut matches: Vec<(u32, &PickCandidate)> = candidates
+        .iter()
+        .filter_map(|candidate| Some((fuzzy_score(query, &candidate.title)?, candidate)))
+        .collect();
+    matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    matches
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Read a 1-based choice out of `len` listed photos as a 0-based index.
+///
+/// Input that isn't a number is `None`, to be searched for instead.
+pub fn parse_pick_index(input: &str, len: usize) -> Result<Option<usize>, PhotoError> {
+    let Ok(number) = input.trim().parse::<usize>() else {
+        return Ok(None);
+    };
+    if (1..=len).contains(&number) {
+        Ok(Some(number - 1))
+    } else {
+        Err(PhotoError::Config(format!(
+            "Pick a number from 1 to {len}, not {number}"
+        )))
+    }
+}
+
+// ============================================================================
+// Retention
+// ============================================================================
+
+/// Which photo directories `clean_photos` may delete.
+/// Directories dated today or later are always kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Directory holding the daily date directories
+    pub photo_root: PathBuf,
+    /// Directory holding one subdirectory per collection
+    pub collection_root: PathBuf,
+    /// Keep directories dated within this many days of `today`
+    pub keep_days: Option<u32>,
+    /// Keep this many of the newest directories
+    pub keep_count: Option<usize>,
+    /// Delete the oldest directories until the library is no larger than this
+    pub max_size: Option<u64>,
+    /// Collections are dated by their modification time and never deleted otherwise
+    pub include_collections: bool,
+    /// Report what would be deleted without deleting it
+    pub dry_run: bool,
+    pub today: chrono::NaiveDate,
+}
+
+impl RetentionPolicy {
+    /// A policy for the default photo directories that keeps everything
+    pub fn new() -> Result<Self, PhotoError> {
+        Ok(Self {
+            photo_root: PathBuf::from(photo_dir()?),
+            collection_root: PathBuf::from(collection_dir()?),
+            keep_days: None,
+            keep_count: None,
+            max_size: None,
+            include_collections: false,
+            dry_run: false,
+            today: Local::now().date_naive(),
+        })
+    }
+}
+
+/// A directory removed by `clean_photos`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemovedDir {
+    pub path: PathBuf,
+    pub photos: usize,
+    pub bytes: u64,
+}
+
+/// What `clean_photos` removed, or would remove in a dry run
+#[derive(Debug, Default)]
+pub struct CleanReport {
+    /// Removed directories, oldest first
+    pub removed: Vec<RemovedDir>,
+    pub removed_photos: usize,
+    pub freed_bytes: u64,
+    /// Size of the library once the removals are done
+    pub remaining_bytes: u64,
+}
+
+/// Total size of the files under `dir` and how many of them are photos
+fn dir_usage(dir: &Path) -> io::Result<(u64, usize)> {
+    let mut bytes = 0;
+    let mut photos = 0;
+    if !dir.is_dir() {
+        return Ok((bytes, photos));
+    }
+    for entry in jwalk::WalkDir::new(dir).skip_hidden(false) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            bytes += entry.metadata()?.len();
+            if has_photo_extension(&entry.path()) {
+                photos += 1;
+            }
+        }
+    }
+    Ok((bytes, photos))
+}
+
+/// Subdirectories of `dir` with the date that decides their age
+fn dated_subdirs(
+    dir: &Path,
+    date_of: impl Fn(&Path) -> Option<chrono::NaiveDate>,
+) -> io::Result<Vec<(chrono::NaiveDate, PathBuf)>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if let Some(date) = date_of(&path) {
+                dirs.push((date, path));
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+/// Delete the oldest photo directories until `policy` is satisfied.
+/// Only date directories are considered, plus collections with `include_collections`.
+pub fn clean_photos(policy: &RetentionPolicy) -> Result<CleanReport, PhotoError> {
+    let mut candidates = dated_subdirs(&policy.photo_root, |dir| {
+        dir.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(parse_date_dir)
+    })?;
+    if policy.include_collections {
+        candidates.extend(dated_subdirs(&policy.collection_root, |dir| {
+            let modified: chrono::DateTime<Local> = modified_time(dir)?.into();
+            Some(modified.date_naive())
+        })?);
+    }
+    candidates.sort();
+
+    let mut total = dir_usage(&policy.photo_root)?.0;
+    if !policy.collection_root.starts_with(&policy.photo_root) && policy.include_collections {
+        total += dir_usage(&policy.collection_root)?.0;
+    }
+
+    let newest_kept = policy
+        .keep_count
+        .map_or(0, |count| candidates.len().saturating_sub(count));
+    let mut report = CleanReport::default();
+    for (index, (date, dir)) in candidates.into_iter().enumerate() {
+        if date >= policy.today {
+            continue;
+        }
+        let too_old = policy
+            .keep_days
+            .is_some_and(|days| (policy.today - date).num_days() >= i64::from(days));
+        let too_many = index < newest_kept;
+        let too_big = policy.max_size.is_some_and(|max| total > max);
+        if !(too_old || too_many || too_big) {
+            continue;
+        }
+
+        let (bytes, photos) = dir_usage(&dir)?;
+        if !policy.dry_run {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        total = total.saturating_sub(bytes);
+        report.removed_photos += photos;
+        report.freed_bytes += bytes;
+        report.removed.push(RemovedDir {
+            path: dir,
+            photos,
+            bytes,
+        });
+    }
+    report.remaining_bytes = total;
+    Ok(report)
+}
+
+/// Parse a size such as `500M`, `2G` or `2GiB` into bytes (powers of 1024)
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let number: u64 = value[..digits].parse().ok()?;
+    let shift = match value[digits..].trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        _ => return None,
+    };
+    number.checked_mul(1 << shift)
+}
+
+// ============================================================================
+// Local Collections
+// ============================================================================
+
+/// Names of the collection directories in `root`, sorted
+fn collection_names(root: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(root)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    names.sort();
+    names
+}
+
+/// The collections downloaded to `root`, with how many photos each holds
+pub fn local_collections(root: &Path) -> Result<Vec<CollectionStats>, PhotoError> {
+    collection_names(root)
+        .into_iter()
+        .map(|name| {
+            let mut photos = Vec::new();
+            collect_photos(&root.join(&name), &mut photos);
+            Ok(CollectionStats {
+                name,
+                photos: photos.len(),
+            })
+        })
+        .collect()
+}
+
+/// Directory of the collection in `root` called `name`, or the only one whose
+/// name starts with it
+pub fn resolve_collection(root: &Path, name: &str) -> Result<PathBuf, PhotoError> {
+    let names = collection_names(root);
+    if names.iter().any(|n| n == name) {
+        return Ok(root.join(name));
+    }
+    let prefix = name.to_lowercase();
+    let matches: Vec<&String> = names
+        .iter()
+        .filter(|n| n.to_lowercase().starts_with(&prefix))
+        .collect();
+    match matches.as_slice() {
+        [only] => Ok(root.join(only)),
+        [] if names.is_empty() => Err(PhotoError::Config(format!(
+            "No collections downloaded to {}",
+            root.display()
+        ))),
+        [] => Err(PhotoError::Config(format!(
+            "No collection matches \"{}\"; downloaded collections: {}",
+            name,
+            names.join(", ")
+        ))),
+        several => Err(PhotoError::Config(format!(
+            "\"{}\" matches several collections: {}",
+            name,
+            several
+                .iter()
+                .map(|n| n.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+// ============================================================================
+// Library Statistics
+// ============================================================================
+
+/// Photo count for one downloaded collection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CollectionStats {
+    pub name: String,
+    pub photos: usize,
+}
+
+/// The largest photo in the library
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LargestPhoto {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// Summary of the photo library, as reported by `stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PhotoStats {
+    /// Photos in date directories
+    pub daily_photos: usize,
+    /// Collections under `collections/`, by name
+    pub collections: Vec<CollectionStats>,
+    /// Photos outside date directories and collections
+    pub other_photos: usize,
+    /// Size of every file under the base path, sidecars included
+    pub total_bytes: u64,
+    /// Dates of the oldest and newest date directories holding a photo
+    pub oldest: Option<chrono::NaiveDate>,
+    pub newest: Option<chrono::NaiveDate>,
+    pub largest: Option<LargestPhoto>,
+    /// Photos wider than they are tall
+    pub landscape: usize,
+    /// Photos taller than they are wide
+    pub portrait: usize,
+    /// Photos that failed validation or couldn't be read; not counted above
+    pub corrupt: Vec<PathBuf>,
+}
+
+/// Where a photo sits in the library layout
+enum PhotoKind {
+    Daily(chrono::NaiveDate),
+    Collection(String),
+    Other,
+}
+
+/// Classify `photo` by its path below `base`
+fn photo_kind(base: &Path, photo: &Path) -> PhotoKind {
+    let parts: Vec<_> = photo
+        .strip_prefix(base)
+        .unwrap_or(photo)
+        .iter()
+        .filter_map(|part| part.to_str())
+        .collect();
+    match parts.as_slice() {
+        [dir, _] => parse_date_dir(dir).map_or(PhotoKind::Other, PhotoKind::Daily),
+        ["collections", name, _] => PhotoKind::Collection((*name).to_string()),
+        _ => PhotoKind::Other,
+    }
+}
+
+/// Count and size the photos under `base`. Unreadable or corrupt photos are
+/// listed in `corrupt` instead of stopping the scan.
+pub fn gather_stats(base: &Path) -> Result<PhotoStats, PhotoError> {
+    if !base.is_dir() {
+        return Err(PhotoError::NoPhotos(format!(
+            "Path not found: {}",
+            base.display()
+        )));
+    }
+    let mut photos = Vec::new();
+    collect_photos(base, &mut photos);
+    photos.sort();
+
+    let mut stats = PhotoStats {
+        total_bytes: dir_usage(base)?.0,
+        ..PhotoStats::default()
+    };
+    let mut collections: std::collections::BTreeMap<String, usize> =
+        std::collections::BTreeMap::new();
+    for photo in photos {
+        let bytes = match std::fs::metadata(&photo) {
+            Ok(metadata) if validate_image(&photo).is_ok() => metadata.len(),
+            _ => {
+                stats.corrupt.push(photo);
+                continue;
+            }
+        };
+
+        match photo_kind(base, &photo) {
+            PhotoKind::Daily(date) => {
+                stats.daily_photos += 1;
+                stats.oldest = Some(stats.oldest.map_or(date, |oldest| oldest.min(date)));
+                stats.newest = Some(stats.newest.map_or(date, |newest| newest.max(date)));
+            }
+            PhotoKind::Collection(name) => *collections.entry(name).or_default() += 1,
+            PhotoKind::Other => stats.other_photos += 1,
+        }
+        match photo_dimensions(&photo).ok().and_then(orientation_of) {
+            Some(Orientation::Landscape) => stats.landscape += 1,
+            Some(Orientation::Portrait) => stats.portrait += 1,
+            None => {}
+        }
+        if stats
+            .largest
+            .as_ref()
+            .is_none_or(|largest| bytes > largest.bytes)
+        {
+            stats.largest = Some(LargestPhoto { path: photo, bytes });
+        }
+    }
+    stats.collections = collections
+        .into_iter()
+        .map(|(name, photos)| CollectionStats { name, photos })
+        .collect();
+    Ok(stats)
+}
+
+// ============================================================================
+// Library Verification
+// ============================================================================
+
+/// What is wrong with a photo found by `verify_photos`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PhotoProblem {
+    /// Smaller than a real photo, e.g. an empty file or a thumbnail
+    Undersized(u64),
+    /// The image can't be read or is truncated
+    Corrupt(String),
+    /// A valid image whose extension doesn't match its contents
+    WrongExtension { detected: &'static str },
+}
+
+impl std::fmt::Display for PhotoProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Undersized(bytes) => write!(f, "too small ({} bytes)", bytes),
+            Self::Corrupt(reason) => write!(f, "corrupt ({})", reason),
+            Self::WrongExtension { detected } => write!(f, "actually a .{} file", detected),
+        }
+    }
+}
+
+/// A photo that failed verification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhotoIssue {
+    pub path: PathBuf,
+    pub problem: PhotoProblem,
+}
+
+/// Result of checking every photo in the library
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Number of photos checked
+    pub checked: usize,
+    pub issues: Vec<PhotoIssue>,
+}
+
+/// What `repair_photo` did about an issue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    /// Fetched again from the URL in the photo's metadata sidecar
+    Redownloaded(PathBuf),
+    /// Given the extension matching its contents
+    Renamed(PathBuf),
+    /// Deleted along with its sidecars, since there was no URL to fetch it from
+    Deleted,
+    /// Nothing could be done; the reason is given
+    Failed(String),
+}
+
+/// Extensions matching the format detected from a file's first bytes
+fn content_extensions(head: &[u8]) -> Option<&'static [&'static str]> {
+    if parse_jxl_dimensions(head).is_some() {
+        return Some(&["jxl"]);
+    }
+    if parse_avif_dimensions(head).is_some() {
+        return Some(&["avif"]);
+    }
+    image::guess_format(head)
+        .ok()
+        .map(image::ImageFormat::extensions_str)
+}
+
+/// Check a single photo, returning its problem if it has one
+fn check_photo(path: &Path, min_size: u64) -> Option<PhotoProblem> {
+    let bytes = match std::fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return Some(PhotoProblem::Corrupt(e.to_string())),
+    };
+    if bytes < min_size {
+        return Some(PhotoProblem::Undersized(bytes));
+    }
+    if let Err(e) = validate_image(path) {
+        let reason = match e {
+            PhotoError::CorruptImage(reason) => {
+                let prefix = format!("{}: ", path.display());
+                reason
+                    .strip_prefix(&prefix)
+                    .map_or_else(|| reason.clone(), str::to_string)
+            }
+            other => other.to_string(),
+        };
+        return Some(PhotoProblem::Corrupt(reason));
+    }
+
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    let detected = content_extensions(&read_header(path).ok()?)?;
+    if detected.contains(&extension.as_str()) {
+        None
+    } else {
+        Some(PhotoProblem::WrongExtension {
+            detected: detected[0],
+        })
+    }
+}
+
+/// Check every photo under `root` for the problems interrupted downloads leave behind
+pub fn verify_photos(root: &Path) -> Result<VerifyReport, PhotoError> {
+    verify_photos_with(root, MIN_PHOTO_SIZE_BYTES)
+}
+
+/// Like `verify_photos`, treating photos under `min_size` bytes as undersized
+pub fn verify_photos_with(root: &Path, min_size: u64) -> Result<VerifyReport, PhotoError> {
+    let mut photos = Vec::new();
+    collect_photos(root, &mut photos);
+    photos.sort();
+
+    let mut report = VerifyReport {
+        checked: photos.len(),
+        ..VerifyReport::default()
+    };
+    for path in photos {
+        if let Some(problem) = check_photo(&path, min_size) {
+            report.issues.push(PhotoIssue { path, problem });
+        }
+    }
+    Ok(report)
+}
+
+/// Fix a photo that failed verification. Misnamed photos are renamed; broken ones
+/// are fetched again when their sidecar records a URL, and deleted otherwise.
+pub fn repair_photo(issue: &PhotoIssue, client: &NatGeoClient) -> Repair {
+    let path = &issue.path;
+    if let PhotoProblem::WrongExtension { detected } = issue.problem {
+        let target = path.with_extension(detected);
+        if target.exists() {
+            return Repair::Failed(format!("{} already exists", target.display()));
+        }
+        return match std::fs::rename(path, &target) {
+            Ok(()) => Repair::Renamed(target),
+            Err(e) => Repair::Failed(e.to_string()),
+        };
+    }
+
+    let metadata = read_metadata(&metadata_path_for(path)).ok();
+    if let Err(e) = std::fs::remove_file(path) {
+        return Repair::Failed(e.to_string());
+    }
+    let Some(metadata) = metadata else {
+        for sidecar in find_sidecars(path) {
+            let _ = std::fs::remove_file(sidecar);
+        }
+        return Repair::Deleted;
+    };
+
+    let (Some(dir), Some(stem)) = (
+        path.parent().and_then(|d| d.to_str()),
+        path.file_stem().and_then(|s| s.to_str()),
+    ) else {
+        return Repair::Failed(format!("Invalid path: {}", path.display()));
+    };
+    let log_path = format!("{}/{}.log", dir, stem);
+    write_log(
+        &log_path,
+        &format!("Re-downloading {} ({})", path.display(), issue.problem),
+    );
+    let info = PhotoInfo {
+        image_url: metadata.url,
+        title: metadata.title,
+        caption: metadata.caption,
+        photographer: metadata.photographer,
+    };
+    let options = DownloadOptions::default();
+    match client.download_with_options(&info.image_url, dir, stem, &log_path, &options) {
+        Ok(outcome) => {
+            let saved = outcome.path().to_path_buf();
+            record_photo_metadata(&info, &saved, &options, &log_path);
+            Repair::Redownloaded(saved)
+        }
+        Err(e) => Repair::Failed(format!("deleted, but re-download failed: {}", e)),
+    }
+}
+
+// ============================================================================
+// Library Normalization
+// ============================================================================
+
+/// Filename template matching what `download` produces today
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{title}";
+
+/// A planned or performed photo rename
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Result of applying a normalization
+#[derive(Debug, Default)]
+pub struct NormalizeReport {
+    /// Photo renames performed
+    pub renames: Vec<Rename>,
+    /// Sidecar files (logs, metadata) renamed alongside their photo
+    pub sidecars: Vec<Rename>,
+    /// Reference files and links rewritten to point at the new names
+    pub updated_references: Vec<PathBuf>,
+    /// Photos left as they were because a sidecar's new name was already
+    /// taken, each with the sidecar rename that clashed
+    pub conflicts: Vec<Rename>,
+}
+
+/// Expand a filename template for a photo.
+///
+/// Supports `{title}` (the sanitized title from the photo's metadata sidecar,
+/// or its current name when it has none), `{stem}` (current name as-is), and
+/// `{date}` (name of the containing directory).
+///
+/// Taking the title from the sidecar keeps normalizing idempotent: a template
+/// such as `{date}_{title}` renders the same name however often it is applied.
+#[allow(clippy::literal_string_with_formatting_args)]
+pub fn render_filename_template(template: &str, photo: &std::path::Path) -> String {
+    let stem = photo
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("photo");
+    let title = read_metadata(&metadata_path_for(photo))
+        .ok()
+        .map(|metadata| metadata.title)
+        .filter(|title| !title.trim().is_empty())
+        .unwrap_or_else(|| stem.to_string());
+    let date = photo
+        .parent()
+        .and_then(|p| p.file_name())
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+
+    let rendered = template
+        .replace("{title}", &sanitize_title(&title))
+        .replace("{stem}", stem)
+        .replace("{date}", date);
+    sanitize_title(&rendered)
+}
+
+/// Work out the renames needed to bring every photo in line with `template`.
+/// Names that would collide with an existing file or another rename get a numeric suffix.
+pub fn plan_normalize(photos: &[PathBuf], template: &str) -> Vec<Rename> {
+    let mut renames = Vec::new();
+    let mut claimed: std::collections::HashSet<PathBuf> = photos.iter().cloned().collect();
+
+    for photo in photos {
+        let Some(dir) = photo.parent() else { continue };
+        let ext = photo
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("jpg")
+            .to_string();
+        let base = render_filename_template(template, photo);
+
+        // A photo already holding one of its candidate names stays put, so a
+        // collision suffix from an earlier run is not shuffled on the next one
+        let mut target = dir.join(format!("{}.{}", base, ext));
+        let mut n = 2;
+        while &target != photo && (claimed.contains(&target) || target.exists()) {
+            target = dir.join(format!("{}_{}.{}", base, n, ext));
+            n += 1;
+        }
+        if &target == photo {
+            continue;
+        }
+
+        claimed.remove(photo);
+        claimed.insert(target.clone());
+        renames.push(Rename {
+            from: photo.clone(),
+            to: target,
+        });
+    }
+
+    renames
+}
+
+/// Non-photo files next to `photo` that share its stem (e.g. `title.log`)
+fn find_sidecars(photo: &std::path::Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(stem)) = (photo.parent(), photo.file_stem()) else {
+        return Vec::new();
+    };
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.file_stem() == Some(stem) && path.is_file())
+        .filter(|path| !has_photo_extension(path))
+        .collect()
+}
+
+/// Apply planned renames, moving sidecars along and rewriting the references in
+/// `data_dir`'s [`REFERENCE_FILES`] and symlinks in `photo_root`.
+///
+/// A photo whose sidecar can't move because its new name is taken is left
+/// alone and reported as a conflict, so no sidecar is separated from its photo.
+pub fn apply_normalize(
+    renames: &[Rename],
+    data_dir: &std::path::Path,
+    photo_root: &std::path::Path,
+) -> Result<NormalizeReport, PhotoError> {
+    let mut report = NormalizeReport::default();
+
+    for rename in renames {
+        let new_stem = rename
+            .to
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        let sidecars: Vec<Rename> = find_sidecars(&rename.from)
+            .into_iter()
+            .map(|sidecar| {
+                let ext = sidecar
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or_default();
+                let to = sidecar.with_file_name(format!("{}.{}", new_stem, ext));
+                Rename { from: sidecar, to }
+            })
+            .collect();
+        if let Some(clash) = sidecars.iter().find(|sidecar| sidecar.to.exists()) {
+            report.conflicts.push(clash.clone());
+            continue;
+        }
+
+        std::fs::rename(&rename.from, &rename.to)?;
+        for sidecar in sidecars {
+            std::fs::rename(&sidecar.from, &sidecar.to)?;
+            report.sidecars.push(sidecar);
+        }
+        report.renames.push(rename.clone());
+    }
+
+    report.updated_references = rewrite_references(&report.renames, data_dir, photo_root)?;
+
+    Ok(report)
+}
+
+/// New name for `value` if it is a renamed photo, as an absolute path or relative to `photo_root`
+fn lookup_renamed(renames: &[Rename], photo_root: &std::path::Path, value: &str) -> Option<String> {
+    renames.iter().find_map(|r| {
+        if r.from.to_str() == Some(value) {
+            return r.to.to_str().map(String::from);
+        }
+        let rel_from = r.from.strip_prefix(photo_root).ok()?;
+        if rel_from.to_str() == Some(value) {
+            return r
+                .to
+                .strip_prefix(photo_root)
+                .ok()?
+                .to_str()
+                .map(String::from);
+        }
+        None
+    })
+}
+
+/// Files in the data directory that name photos and follow them when renamed.
+///
+/// These are the favorite and exclusion lists, the wallpaper history, and the
+/// hash manifest. Logs keep the names they were written with.
+pub const REFERENCE_FILES: [&str; 4] = [
+    PhotoList::Favorites.file_name(),
+    PhotoList::Excluded.file_name(),
+    HISTORY_FILE,
+    MANIFEST_FILE,
+];
+
+/// Rewrite the path lists and JSON state among [`REFERENCE_FILES`] in `data_dir`,
+/// and symlinks in `photo_root`
+fn rewrite_references(
+    renames: &[Rename],
+    data_dir: &std::path::Path,
+    photo_root: &std::path::Path,
+) -> Result<Vec<PathBuf>, PhotoError> {
+    let mut updated_references = Vec::new();
+    if renames.is_empty() {
+        return Ok(updated_references);
+    }
+    let lookup = |value: &str| lookup_renamed(renames, photo_root, value);
+
+    for name in REFERENCE_FILES {
+        let path = data_dir.join(name);
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        let updated = match ext {
+            "txt" => {
+                let mut changed = false;
+                let lines: Vec<String> = contents
+                    .lines()
+                    .map(|line| {
+                        lookup(line.trim()).map_or_else(
+                            || line.to_string(),
+                            |new| {
+                                changed = true;
+                                new
+                            },
+                        )
+                    })
+                    .collect();
+                changed.then(|| lines.join("\n") + "\n")
+            }
+            "json" => {
+                let Ok(mut value) = serde_json::from_str::<serde_json::Value>(&contents) else {
+                    continue;
+                };
+                rewrite_json_paths(&mut value, &lookup)
+                    .then(|| serde_json::to_string_pretty(&value))
+                    .transpose()?
+            }
+            _ => None,
+        };
+        if let Some(updated) = updated {
+            std::fs::write(&path, updated)?;
+            updated_references.push(path);
+        }
+    }
+
+    for entry in std::fs::read_dir(photo_root)
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let link = entry.path();
+        let Ok(target) = std::fs::read_link(&link) else {
+            continue;
+        };
+        let Some(new_target) = target.to_str().and_then(lookup) else {
+            continue;
+        };
+        let tmp = link.with_extension("normalize-tmp");
+        let _ = std::fs::remove_file(&tmp);
+        link_or_copy(Path::new(&new_target), &tmp)?;
+        std::fs::rename(&tmp, &link)?;
+        updated_references.push(link);
+    }
+
+    Ok(updated_references)
+}
+
+/// Replace every string in a JSON document (values and object keys) that names a renamed photo
+fn rewrite_json_paths(
+    value: &mut serde_json::Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> bool {
+    match value {
+        serde_json::Value::String(s) => lookup(s).is_some_and(|new| {
+            *s = new;
+            true
+        }),
+        serde_json::Value::Array(items) => items.iter_mut().fold(false, |changed, item| {
+            rewrite_json_paths(item, lookup) | changed
+        }),
+        serde_json::Value::Object(map) => {
+            let mut changed = false;
+            let entries = std::mem::take(map);
+            for (key, mut item) in entries {
+                changed |= rewrite_json_paths(&mut item, lookup);
+                let key = lookup(&key).map_or(key, |new| {
+                    changed = true;
+                    new
+                });
+                map.insert(key, item);
+            }
+            changed
+        }
+        _ => false,
+    }
+}
+
+// ============================================================================
+// Duplicate Detection
+// ============================================================================
+
+/// Name of the content hash manifest inside the log directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// SHA-256 of every downloaded photo, mapped to its path relative to the photo root
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhotoManifest {
+    pub photos: std::collections::BTreeMap<String, PathBuf>,
+}
+
+impl PhotoManifest {
+    /// Read a manifest, starting empty if it doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self, PhotoError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the manifest through a temporary file so a crash never truncates it
+    pub fn save(&self, path: &Path) -> Result<(), PhotoError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// The photo recorded under `hash`, if it is still on disk
+    pub fn find(&self, hash: &str, root: &Path) -> Option<PathBuf> {
+        let path = root.join(self.photos.get(hash)?);
+        path.is_file().then_some(path)
+    }
+
+    /// Record `photo` under `hash`, relative to `root` when it lives inside it
+    pub fn record(&mut self, hash: String, photo: &Path, root: &Path) {
+        let relative = photo.strip_prefix(root).unwrap_or(photo);
+        self.photos.insert(hash, relative.to_path_buf());
+    }
+}
+
+/// Where the manifest lives and which directory its paths are relative to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestConfig {
+    pub path: PathBuf,
+    pub root: PathBuf,
+}
+
+impl ManifestConfig {
+    /// The manifest in the default log directory, relative to the photo directory
+    pub fn from_defaults() -> Result<Self, PhotoError> {
+        Ok(Self {
+            path: Path::new(&log_dir()?).join(MANIFEST_FILE),
+            root: PathBuf::from(photo_dir()?),
+        })
+    }
+
+    /// The existing photo with this content hash. A manifest that can't be read
+    /// is logged and treated as empty so it never blocks a download.
+    fn find(&self, hash: &str, log_path: &str) -> Option<PathBuf> {
+        match PhotoManifest::load(&self.path) {
+            Ok(manifest) => manifest.find(hash, &self.root),
+            Err(e) => {
+                write_log(log_path, &format!("Ignoring unreadable manifest: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Add a freshly saved photo to the manifest, logging rather than failing on errors
+    fn record(&self, hash: String, photo: &Path, log_path: &str) {
+        let result = PhotoManifest::load(&self.path).and_then(|mut manifest| {
+            manifest.record(hash, photo, &self.root);
+            manifest.save(&self.path)
+        });
+        if let Err(e) = result {
+            write_log(log_path, &format!("Failed to update manifest: {}", e));
+        }
+    }
+}
+
+/// Hex-encoded SHA-256 of a file's contents
+pub fn hash_file(path: &Path) -> Result<String, PhotoError> {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// What `dedup` does with a photo whose content matches an earlier one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateAction {
+    /// Only list duplicates
+    #[default]
+    Report,
+    /// Replace the duplicate with a hard link to the original
+    HardLink,
+    /// Delete the duplicate and its sidecar files
+    Remove,
+}
+
+/// A photo with the same content as one seen earlier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate {
+    pub path: PathBuf,
+    pub original: PathBuf,
+}
+
+/// Result of scanning the photo tree for duplicates
+#[derive(Debug, Default)]
+pub struct DedupReport {
+    /// Number of photos hashed
+    pub scanned: usize,
+    pub duplicates: Vec<Duplicate>,
+    /// Bytes freed by linking or removing duplicates
+    pub reclaimed_bytes: u64,
+}
+
+/// Whether two paths are the same file on disk, e.g. already hard linked
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Whether two paths are the same file on disk
+///
+/// Without inode numbers hard links can't be told apart from copies, so only
+/// paths that resolve to the same place match.
+#[cfg(not(unix))]
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Hash every photo under `config.root` and apply `action` to byte-identical copies.
+///
+/// Every photo is recorded in the manifest. Photos already in it are treated as the
+/// originals; otherwise the first path in sorted order wins.
+pub fn dedup_photos(
+    config: &ManifestConfig,
+    action: DuplicateAction,
+) -> Result<DedupReport, PhotoError> {
+    let mut photos = Vec::new();
+    collect_photos(&config.root, &mut photos);
+    photos.sort();
+
+    let mut manifest = PhotoManifest::load(&config.path)?;
+    manifest
+        .photos
+        .retain(|_, photo| config.root.join(photo).is_file());
+
+    let mut report = DedupReport::default();
+    for photo in photos {
+        report.scanned += 1;
+        let hash = hash_file(&photo)?;
+        let Some(original) = manifest.find(&hash, &config.root) else {
+            manifest.record(hash, &photo, &config.root);
+            continue;
+        };
+        if original == photo || same_file(&original, &photo) {
+            continue;
+        }
+
+        let size = std::fs::metadata(&photo)?.len();
+        match action {
+            DuplicateAction::Report => {}
+            DuplicateAction::HardLink => {
+                // Link under a temporary name first so the duplicate is never missing
+                let tmp = photo.with_extension(format!(
+                    "{}.link",
+                    photo.extension().and_then(|e| e.to_str()).unwrap_or("jpg")
+                ));
+                std::fs::hard_link(&original, &tmp)?;
+                std::fs::rename(&tmp, &photo)?;
+                report.reclaimed_bytes += size;
+            }
+            DuplicateAction::Remove => {
+                for sidecar in find_sidecars(&photo) {
+                    std::fs::remove_file(sidecar)?;
+                }
+                std::fs::remove_file(&photo)?;
+                report.reclaimed_bytes += size;
+            }
+        }
+        report.duplicates.push(Duplicate {
+            path: photo,
+            original,
+        });
+    }
+
+    manifest.save(&config.path)?;
+    Ok(report)
+}
+
+// ============================================================================
+// Uninstall Functions
+// ============================================================================
+
+/// Locations that `uninstall` may remove
+#[derive(Debug, Clone)]
+pub struct UninstallPaths {
+    pub data_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    pub config_file: PathBuf,
+    pub photo_dirs: Vec<PathBuf>,
+    /// Built-in data and cache locations, which belong to the tool even when
+    /// an older version created them without the managed marker
+    pub default_dirs: Vec<PathBuf>,
+}
+
+impl UninstallPaths {
+    /// The directories this process resolved, from overrides or defaults
+    pub fn from_defaults() -> Result<Self, PhotoError> {
+        let defaults = resolve_data_dirs(&|_| None, &Config::default(), &BaseDirs::from_system())?;
+        Ok(Self {
+            data_dir: PathBuf::from(log_dir()?),
+            cache_dir: PathBuf::from(cache_dir()?),
+            config_file: config_path(),
+            photo_dirs: vec![
+                PathBuf::from(photo_dir()?),
+                PathBuf::from(collection_dir()?),
+            ],
+            default_dirs: vec![PathBuf::from(defaults.logs), PathBuf::from(defaults.cache)],
+        })
+    }
+}
+
+/// Which tiers of data `uninstall` should remove
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UninstallOptions {
+    /// Also remove the config file
+    pub config: bool,
+    /// Also remove photo and collection directories (only if tool-managed)
+    pub photos: bool,
+}
+
+/// What an uninstall run would remove, and what it refused to touch
+#[derive(Debug, Default)]
+pub struct UninstallPlan {
+    pub remove: Vec<PathBuf>,
+    pub photo_dirs: Vec<PathBuf>,
+    pub refused: Vec<(PathBuf, String)>,
+}
+
+/// Build the list of paths to remove for the given options.
+/// Only existing paths are included. Directories without the managed marker
+/// are refused, apart from the built-in data and cache locations.
+pub fn plan_uninstall(paths: &UninstallPaths, options: UninstallOptions) -> UninstallPlan {
+    let mut plan = UninstallPlan::default();
+    let unmarked = || {
+        format!(
+            "not created by natgeo-wallpapers (no {} marker)",
+            MANAGED_DIR_MARKER
+        )
+    };
+
+    for path in [&paths.data_dir, &paths.cache_dir] {
+        if !path.exists() || plan.remove.contains(path) {
+            continue;
+        }
+        if is_managed_dir(path) || paths.default_dirs.contains(path) {
+            plan.remove.push(path.clone());
+        } else {
+            plan.refused.push((path.clone(), unmarked()));
+        }
+    }
+
+    if options.config && paths.config_file.exists() {
+        plan.remove.push(paths.config_file.clone());
+    }
+
+    if options.photos {
+        for dir in &paths.photo_dirs {
+            if !dir.exists() || plan.photo_dirs.iter().any(|p| dir.starts_with(p)) {
+                continue;
+            }
+            if is_managed_dir(dir) {
+                plan.photo_dirs.push(dir.clone());
+            } else {
+                plan.refused.push((dir.clone(), unmarked()));
+            }
+        }
+    }
+
+    plan
+}
+
+/// Remove everything in the plan, returning the paths that were removed
+pub fn execute_uninstall(plan: &UninstallPlan) -> Result<Vec<PathBuf>, PhotoError> {
+    let mut removed = Vec::new();
+
+    for path in plan.remove.iter().chain(&plan.photo_dirs) {
+        if path.is_dir() {
+            std::fs::remove_dir_all(path)?;
+        } else {
+            std::fs::remove_file(path)?;
+        }
+        removed.push(path.clone());
+    }
+
+    Ok(removed)
+}
+
+// ============================================================================
+// Diagnostics
+// ============================================================================
+
+/// Unit of the daily download timer
+const DOWNLOAD_TIMER: &str = "natgeo-wallpaper.timer";
+
+/// Tools whose presence decides which desktop is detected
+const DETECTION_COMMANDS: &[&str] = &[
+    "swww",
+    "hyprctl",
+    "qdbus6",
+    "qdbus",
+    "xfconf-query",
+    "plasma-apply-wallpaperimage",
+    "gsettings",
+    "feh",
+];
+
+/// Processes whose running decides which desktop is detected
+const DETECTION_PROCESSES: &[&str] = &[
+    "swww-daemon",
+    "Hyprland",
+    "plasmashell",
+    "xfce4-session",
+    "pcmanfm-qt",
+    "cinnamon",
+    "mate-session",
+    "gnome-shell",
+];
+
+/// How a `doctor` check came out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// The outcome of one `doctor` check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    /// What was checked, such as `Network`
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+impl CheckResult {
+    fn new(name: &str, status: CheckStatus, message: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn pass(name: &str, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Pass, message)
+    }
+
+    pub fn warn(name: &str, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Warn, message)
+    }
+
+    pub fn fail(name: &str, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Fail, message)
+    }
+}
+
+/// What `doctor_checks` looks at, so the checks can run against fakes
+pub struct DoctorProbes<'a> {
+    pub system: &'a dyn SystemProbe,
+    pub commands: &'a dyn CommandRunner,
+    /// The HTTP status code of a GET for a URL
+    pub http_status: &'a dyn Fn(&str) -> Result<u16, PhotoError>,
+    /// Monitors and virtual desktops as the desktop's backend reports them
+    pub capabilities: &'a dyn Fn(DesktopEnvironment) -> Option<Capabilities>,
+    pub photo_dir: PathBuf,
+    pub log_dir: PathBuf,
+}
+
+/// Run every `doctor` check in order
+pub fn doctor_checks(probes: &DoctorProbes) -> Vec<CheckResult> {
+    let detected = if cfg!(target_os = "windows") {
+        DetectedEnvironment {
+            de: DesktopEnvironment::Windows,
+            session: SessionType::detect(probes.system),
+        }
+    } else {
+        detect_environment_with(probes.system)
+    };
+    vec![
+        check_network((probes.http_status)(NATGEO_POD_URL)),
+        check_directory("Photo directory", &probes.photo_dir),
+        check_directory("Log directory", &probes.log_dir),
+        check_desktop(detected, probes.system),
+        check_displays(detected.de, (probes.capabilities)(detected.de)),
+        check_session(probes.system),
+        check_timer(probes.commands),
+    ]
+}
+
+/// Whether the Photo of the Day page answered, from the status of a GET for it
+pub fn check_network(status: Result<u16, PhotoError>) -> CheckResult {
+    match status {
+        Ok(code) if (200..400).contains(&code) => {
+            CheckResult::pass("Network", format!("{NATGEO_POD_URL} answered {code}"))
+        }
+        Ok(403) => CheckResult::fail(
+            "Network",
+            format!("{NATGEO_POD_URL} answered 403; the site is refusing automated requests"),
+        ),
+        Ok(code) => CheckResult::fail("Network", format!("{NATGEO_POD_URL} answered {code}")),
+        Err(e) => CheckResult::fail("Network", format!("{NATGEO_POD_URL} is unreachable: {e}")),
+    }
+}
+
+/// Whether `path` is a directory we can write to, by creating and removing a file in it.
+/// A missing directory is only a warning, since it is created when first needed.
+pub fn check_directory(name: &str, path: &Path) -> CheckResult {
+    if !path.exists() {
+        return CheckResult::warn(
+            name,
+            format!(
+                "{} doesn't exist yet; it is created when needed",
+                path.display()
+            ),
+        );
+    }
+    if !path.is_dir() {
+        return CheckResult::fail(name, format!("{} is not a directory", path.display()));
+    }
+    let probe = path.join(format!(".natgeo-doctor-{}", std::process::id()));
+    match std::fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+            CheckResult::pass(name, format!("{} is writable", path.display()))
+        }
+        Err(e) => CheckResult::fail(name, format!("{} is not writable: {e}", path.display())),
+    }
+}
+
+/// The tools, processes, and variables `detect_environment_with` went by
+pub fn desktop_evidence(probe: &dyn SystemProbe) -> Vec<String> {
+    let mut evidence: Vec<String> = DETECTION_COMMANDS
+        .iter()
+        .filter(|command| probe.command_exists(command))
+        .map(|command| format!("{command} installed"))
+        .collect();
+    evidence.extend(
+        DETECTION_PROCESSES
+            .iter()
+            .filter(|process| probe.process_running(process))
+            .map(|process| format!("{process} running")),
+    );
+    if probe.dbus_name_has_owner(PLASMA_SHELL_SERVICE) {
+        evidence.push(format!("{PLASMA_SHELL_SERVICE} on D-Bus"));
+    }
+    for key in [
+        "XDG_CURRENT_DESKTOP",
+        "DESKTOP_SESSION",
+        "XDG_SESSION_TYPE",
+        "WAYLAND_DISPLAY",
+        "DISPLAY",
+    ] {
+        if let Some(value) = probe.env_var(key).filter(|v| !v.trim().is_empty()) {
+            evidence.push(format!("{key}={value}"));
+        }
+    }
+    evidence
+}
+
+/// Which desktop and session were detected and why, failing if no desktop is supported
+pub fn check_desktop(detected: DetectedEnvironment, probe: &dyn SystemProbe) -> CheckResult {
+    let evidence = desktop_evidence(probe);
+    let found = if evidence.is_empty() {
+        "nothing relevant found".to_string()
+    } else {
+        format!("found {}", evidence.join(", "))
+    };
+    match detected.de {
+        DesktopEnvironment::Unknown => CheckResult::fail(
+            "Desktop",
+            format!("{} ({found})", unsupported_desktop_reason(probe)),
+        ),
+        DesktopEnvironment::Windows => CheckResult::pass("Desktop", "Windows"),
+        de => CheckResult::pass(
+            "Desktop",
+            format!("{de:?} on {} ({found})", detected.session),
+        ),
+    }
+}
+
+/// How many monitors and virtual desktops the desktop reports
+pub fn check_displays(de: DesktopEnvironment, capabilities: Option<Capabilities>) -> CheckResult {
+    match capabilities {
+        Some(capabilities) => CheckResult::pass(
+            "Displays",
+            format!(
+                "{} monitor(s), {} virtual desktop(s)",
+                capabilities.monitors, capabilities.virtual_desktops
+            ),
+        ),
+        None if de == DesktopEnvironment::Unknown => {
+            CheckResult::warn("Displays", "No supported desktop to ask")
+        }
+        None => CheckResult::warn("Displays", format!("Couldn't ask {de:?} for its monitors")),
+    }
+}
+
+/// Whether the variables that reach the desktop are set, as they often aren't
+/// under systemd or cron
+pub fn check_session(probe: &dyn SystemProbe) -> CheckResult {
+    if cfg!(target_os = "windows") {
+        return CheckResult::pass("Session", "Not needed on Windows");
+    }
+    let set = |key: &str| probe.env_var(key).filter(|v| !v.trim().is_empty());
+    let described: Vec<String> = ["DISPLAY", "WAYLAND_DISPLAY", "DBUS_SESSION_BUS_ADDRESS"]
+        .iter()
+        .map(|key| match set(key) {
+            Some(value) => format!("{key}={value}"),
+            None => format!("{key} unset"),
+        })
+        .collect();
+    let described = described.join(", ");
+    if set("DISPLAY").is_none() && set("WAYLAND_DISPLAY").is_none() {
+        CheckResult::fail(
+            "Session",
+            format!("{described}; no display to set wallpapers on"),
+        )
+    } else if set("DBUS_SESSION_BUS_ADDRESS").is_none() {
+        CheckResult::warn(
+            "Session",
+            format!("{described}; KDE Plasma and GNOME can't be reached"),
+        )
+    } else {
+        CheckResult::pass("Session", described)
+    }
+}
+
+/// Whether the daily timer is installed and active, as `systemctl` reports it
+pub fn check_timer(runner: &dyn CommandRunner) -> CheckResult {
+    if !cfg!(target_os = "linux") {
+        return CheckResult::pass("Timer", "systemd isn't used on this system");
+    }
+    let output = match runner.run(
+        "systemctl",
+        &[
+            "--user",
+            "show",
+            DOWNLOAD_TIMER,
+            "--property=LoadState",
+            "--property=ActiveState",
+        ],
+    ) {
+        Ok(output) => output,
+        Err(e) => return CheckResult::warn("Timer", format!("Couldn't ask systemd: {e}")),
+    };
+    let property = |name: &str| {
+        output.lines().find_map(|line| {
+            line.strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('='))
+                .map(str::trim)
+        })
+    };
+    match (property("LoadState"), property("ActiveState")) {
+        (Some("not-found"), _) => CheckResult::pass("Timer", "No systemd timer installed"),
+        (_, Some("active")) => CheckResult::pass("Timer", format!("{DOWNLOAD_TIMER} is active")),
+        (_, state) => CheckResult::warn(
+            "Timer",
+            format!(
+                "{DOWNLOAD_TIMER} is installed but {}; start it with \
+                 `systemctl --user enable --now {DOWNLOAD_TIMER}`",
+                state.unwrap_or("in an unknown state")
+            ),
+        ),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write as IoWrite;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_get_extension_from_content_type() {
+        // Valid content types
+        assert_eq!(
+            get_extension_from_content_type("image/jpeg").unwrap(),
+            "jpg"
+        );
+        assert_eq!(
+            get_extension_from_content_type("image/jpeg; charset=utf-8").unwrap(),
+            "jpg"
+        );
+        assert_eq!(get_extension_from_content_type("image/png").unwrap(), "png");
+        assert_eq!(get_extension_from_content_type("image/gif").unwrap(), "gif");
+
+        // Invalid content types
+        assert!(get_extension_from_content_type("text/html").is_err());
+        assert!(get_extension_from_content_type("application/pdf").is_err());
+    }
+
+    #[test]
+    fn test_write_log() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("test.log");
+
+        // Write log entries
+        write_log(log_path.to_str().unwrap(), "Test message 1");
+        write_log(log_path.to_str().unwrap(), "Test message 2");
+
+        // Read and verify
+        let contents = fs::read_to_string(&log_path).unwrap();
+        assert!(contents.contains("Test message 1"));
+        assert!(contents.contains("Test message 2"));
+        assert!(contents.contains("[202")); // Check for timestamp format
+        assert_eq!(contents.lines().count(), 2); // Should have 2 lines
+    }
+
+    #[test]
+    fn test_log_rotation_keeps_content_across_the_boundary() {
+        let temp_dir = TempDir::new().unwrap();
+        let log = temp_dir.path().join("wallpaper.log");
+        let log_path = log.to_str().unwrap();
+        let rotation = LogRotation {
+            max_bytes: 200,
+            keep: 2,
+        };
+
+        for n in 0..30 {
+            append_log(log_path, &format!("entry {n:02}"), rotation).unwrap();
+        }
+
+        // Each line is 31 bytes, so a file takes 7 before reaching 200
+        let read = |path: &Path| fs::read_to_string(path).unwrap();
+        let files = [
+            rotated_log_path(&log, 2),
+            rotated_log_path(&log, 1),
+            log.clone(),
+        ];
+        for file in &files {
+            assert!(fs::metadata(file).unwrap().len() < 200 + 31);
+        }
+        assert!(!rotated_log_path(&log, 3).exists());
+
+        // Oldest copy first, the entries run on without a gap up to the last one
+        let entries: Vec<u32> = files
+            .iter()
+            .flat_map(|file| {
+                read(file)
+                    .lines()
+                    .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(entries.last(), Some(&29));
+        assert!(entries.windows(2).all(|pair| pair[1] == pair[0] + 1));
+        assert_eq!(entries.len(), 7 + 7 + 30 % 7);
+    }
+
+    #[test]
+    fn test_rotate_log_only_past_the_threshold() {
+        let temp_dir = TempDir::new().unwrap();
+        let log = temp_dir.path().join("collection.log");
+        let rotation = LogRotation {
+            max_bytes: 10,
+            keep: 1,
+        };
+
+        assert!(!rotate_log(&log, rotation).unwrap());
+        fs::write(&log, "short").unwrap();
+        assert!(!rotate_log(&log, rotation).unwrap());
+
+        fs::write(&log, "long enough to rotate").unwrap();
+        assert!(rotate_log(&log, rotation).unwrap());
+        assert!(!log.exists());
+        assert_eq!(
+            fs::read_to_string(rotated_log_path(&log, 1)).unwrap(),
+            "long enough to rotate"
+        );
+
+        // Rotation off
+        fs::write(&log, "long enough to rotate").unwrap();
+        let off = LogRotation {
+            max_bytes: 0,
+            ..rotation
+        };
+        assert!(!rotate_log(&log, off).unwrap());
+    }
+
+    #[test]
+    fn test_try_write_log_creates_missing_directories() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("collections/gone/collection.log");
+
+        try_write_log(log_path.to_str().unwrap(), "Recreated").unwrap();
+        assert!(fs::read_to_string(&log_path).unwrap().contains("Recreated"));
+    }
+
+    #[test]
+    fn test_try_write_log_reports_unwritable_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("not-a-dir");
+        fs::write(&file, "").unwrap();
+        let log_path = file.join("test.log");
+        let log_path = log_path.to_str().unwrap();
+
+        assert!(try_write_log(log_path, "Lost").is_err());
+        // The lenient version only warns
+        write_log(log_path, "Lost");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_try_write_log_readonly_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let readonly = temp_dir.path().join("readonly");
+        fs::create_dir(&readonly).unwrap();
+        fs::set_permissions(&readonly, fs::Permissions::from_mode(0o555)).unwrap();
+        // Permissions don't apply to root, so there is nothing to check there
+        let writable = fs::write(readonly.join("probe"), "").is_ok();
+
+        let log_path = readonly.join("test.log");
+        let result = try_write_log(log_path.to_str().unwrap(), "Lost");
+        assert_eq!(result.is_err(), !writable);
+        fs::set_permissions(&readonly, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_write_log_checked_fails_only_when_strict() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("not-a-dir");
+        fs::write(&file, "").unwrap();
+        let unwritable = file.join("test.log");
+        let unwritable = unwritable.to_str().unwrap();
+        let writable = temp_dir.path().join("test.log");
+        let writable = writable.to_str().unwrap();
+
+        assert!(write_log_checked(unwritable, "Lost").is_ok());
+        set_strict_logging(true);
+        let result = write_log_checked(unwritable, "Lost");
+        let ok = write_log_checked(writable, "Kept");
+        set_strict_logging(false);
+
+        assert!(matches!(result, Err(PhotoError::Log(ref msg)) if msg.starts_with(unwritable)));
+        assert!(ok.is_ok());
+        assert!(fs::read_to_string(writable).unwrap().contains("Kept"));
+    }
+
+    #[test]
+    fn test_sanitize_title_special_characters() {
+        // Test various special characters
+        assert_eq!(
+            sanitize_title("Photo: 2024/01/20 | Test"),
+            "Photo_2024_01_20_-_Test"
+        );
+        assert_eq!(
+            sanitize_title("Test/Photo: Title|Name"),
+            "Test_Photo_Title-Name"
+        );
+
+        // Verify problematic characters are removed
+        let sanitized = sanitize_title("Bad/Path:Name|Value");
+        assert!(!sanitized.contains("/"));
+        assert!(!sanitized.contains(":"));
+        assert!(!sanitized.contains("|"));
+    }
+
+    #[test]
+    fn test_sanitize_title_length_limit() {
+        let long_title = "A".repeat(150);
+        let sanitized = sanitize_title(&long_title);
+        assert_eq!(sanitized.len(), 100);
+    }
+
+    #[test]
+    fn test_sanitize_title_matches_legacy_for_simple_titles() {
+        for title in [
+            "Photo: 2024/01/20 | Test",
+            "Northern Lights over Iceland",
+            "A fox in the snow",
+            "Café in Montréal",
+            "Mount Everest, Nepal (8,849 m)",
+        ] {
+            assert_eq!(
+                sanitize_title(title),
+                legacy_sanitize_title(title),
+                "{title}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sanitize_title_replaces_unsafe_characters() {
+        assert_eq!(sanitize_title(r#"What is "this"?"#), "What_is_this");
+        assert_eq!(sanitize_title(r"C:\Windows\*.jpg"), "C_Windows_.jpg");
+        assert_eq!(sanitize_title("<b>Bold</b>"), "b_Bold_b");
+        assert_eq!(
+            sanitize_title("Line\nbreak\tand\0nul"),
+            "Line_break_and_nul"
+        );
+        assert_eq!(sanitize_title("Hidden\u{200B}\u{202E}text"), "Hidden_text");
+        assert_eq!(sanitize_title("Two  spaces"), "Two_spaces");
+    }
+
+    #[test]
+    fn test_sanitize_title_trims_separators_and_dots() {
+        assert_eq!(sanitize_title("  Sunset...  "), "Sunset");
+        assert_eq!(sanitize_title(".hidden"), "hidden");
+        assert_eq!(sanitize_title("-flag"), "flag");
+        assert_eq!(sanitize_title("???"), "untitled");
+        assert_eq!(sanitize_title(""), "untitled");
+    }
+
+    #[test]
+    fn test_sanitize_title_windows_reserved_names() {
+        assert_eq!(sanitize_title("CON"), "CON_");
+        assert_eq!(sanitize_title("nul"), "nul_");
+        assert_eq!(sanitize_title("LPT1.jpg"), "LPT1.jpg_");
+        assert_eq!(sanitize_title("Com9"), "Com9_");
+        assert_eq!(sanitize_title("CONTOUR"), "CONTOUR");
+        assert_eq!(sanitize_title("COM10"), "COM10");
+    }
+
+    #[test]
+    fn test_sanitize_title_caps_bytes_on_char_boundary() {
+        // 'é' is two bytes, so 100 bytes would split the 51st one
+        let sanitized = sanitize_title(&format!("a{}", "é".repeat(80)));
+        assert_eq!(sanitized.len(), 99);
+        assert!(sanitized.chars().skip(1).all(|c| c == 'é'));
+
+        let sanitized = sanitize_title(&format!("{}_tail", "b".repeat(99)));
+        assert_eq!(sanitized, "b".repeat(99));
+    }
+
+    #[test]
+    fn test_unique_title_suffixes_different_photos() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let save = |stem: &str, url: &str| {
+            let photo = dir.path().join(format!("{stem}.jpg"));
+            std::fs::write(&photo, b"jpeg").unwrap();
+            let info = PhotoInfo {
+                image_url: url.to_string(),
+                title: "Fox".to_string(),
+                caption: None,
+                photographer: None,
+            };
+            let metadata = PhotoMetadata::for_download(&info, &photo).unwrap();
+            write_metadata(&metadata_path_for(&photo), &metadata).unwrap();
+        };
+
+        assert_eq!(unique_title(path, "Fox", "https://cdn/a.jpg"), "Fox");
+        save("Fox", "https://cdn/a.jpg");
+        assert_eq!(unique_title(path, "Fox", "https://cdn/a.jpg"), "Fox");
+        // A crop of the same photo is still the same photo
+        assert_eq!(unique_title(path, "Fox", "https://cdn/a.jpg?w=640"), "Fox");
+        assert_eq!(unique_title(path, "Fox", "https://cdn/b.jpg"), "Fox_2");
+
+        save("Fox_2", "https://cdn/b.jpg");
+        assert_eq!(unique_title(path, "Fox", "https://cdn/b.jpg"), "Fox_2");
+        assert_eq!(unique_title(path, "Fox", "https://cdn/c.jpg"), "Fox_3");
+    }
+
+    #[test]
+    fn test_unique_title_without_sidecar_keeps_name() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Fox.jpg"), b"jpeg").unwrap();
+        let path = dir.path().to_str().unwrap();
+        assert_eq!(unique_title(path, "Fox", "https://cdn/b.jpg"), "Fox");
+    }
+
+    #[test]
+    fn test_downloaded_title_reuses_legacy_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        let title = "What is this?";
+        assert_eq!(downloaded_title(dir, title), "What_is_this");
+
+        std::fs::write(format!("{dir}/What_is_this?.jpg"), b"old").unwrap();
+        assert_eq!(downloaded_title(dir, title), "What_is_this?");
+    }
+
+    #[test]
+    fn test_html_parsing_og_image() {
+        // Simulate HTML with og:image meta tag
+        let html = r#"<html><head><meta property="og:image" content="https://example.com/image.jpg"/></head></html>"#;
+
+        let info = parse_photo_info_from_html(html).unwrap();
+
+        assert_eq!(info.image_url, "https://example.com/image.jpg");
+    }
+
+    #[test]
+    fn test_html_parsing_og_title() {
+        // Simulate HTML with og:title meta tag
+        let html = r#"<html><head><meta property="og:image" content="https://example.com/image.jpg"/><meta property="og:title" content="Test Photo"/></head></html>"#;
+
+        let info = parse_photo_info_from_html(html).unwrap();
+
+        assert_eq!(info.title, "Test Photo");
+    }
+
+    #[test]
+    fn test_download_and_save_mock_image() {
+        let temp_dir = TempDir::new().unwrap();
+        let save_dir = temp_dir.path().to_str().unwrap();
+        let sanitized_title = "test_photo";
+
+        // Create a mock image file
+        let photo_filename = format!("{}/{}.jpg", save_dir, sanitized_title);
+        let mut file = File::create(&photo_filename).unwrap();
+        file.write_all(b"fake image data").unwrap();
+
+        // Verify file was created
+        assert!(std::path::Path::new(&photo_filename).exists());
+
+        // Verify file contents
+        let contents = fs::read(&photo_filename).unwrap();
+        assert_eq!(contents, b"fake image data");
+    }
+
+    #[test]
+    fn test_date_format() {
+        // Test the date format used in directory structure
+        let date = Local::now().format(DATE_DIR_FORMAT).to_string();
+
+        // Verify format is yyyy-mm-dd (should be 10 characters)
+        assert_eq!(date.len(), 10);
+        assert_eq!(date.chars().nth(4), Some('-'));
+        assert_eq!(date.chars().nth(7), Some('-'));
+        assert!(parse_date_dir(&date).is_some());
+    }
+
+    #[test]
+    fn test_title_fallback_from_url() {
+        // Test extracting filename from image URL
+        let url = "https://i.natgeofe.com/n/d0888b52-1d37-403a-a25a-84c1dc53bbdf/NationalGeographic_433254.jpg";
+
+        let filename = url
+            .split('/')
+            .next_back()
+            .and_then(|filename| filename.split('.').next())
+            .unwrap_or("photo-of-the-day");
+
+        assert_eq!(filename, "NationalGeographic_433254");
+    }
+
+    #[test]
+    fn test_title_fallback_logic() {
+        // Test that short/meaningless titles trigger fallback
+        let og_title_test = "Test";
+        let og_title_empty = "";
+        let og_title_short = "Hi";
+        let og_title_good = "Beautiful Sunset Over Mountains";
+
+        // These should trigger fallback (too short or "Test")
+        assert!(og_title_test.to_lowercase() == "test");
+        assert!(og_title_empty.is_empty());
+        assert!(og_title_short.len() < 5);
+
+        // This should NOT trigger fallback
+        assert!(og_title_good.len() >= 5);
+        assert!(og_title_good.to_lowercase() != "test");
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        // Test tilde expansion
+        let home = std::env::var("HOME").unwrap();
+
+        // Should expand tilde
+        assert_eq!(expand_tilde("~/test/path"), format!("{}/test/path", home));
+        assert_eq!(expand_tilde("~/"), format!("{}/", home));
+
+        // Should not modify paths without tilde
+        assert_eq!(expand_tilde("/absolute/path"), "/absolute/path");
+        assert_eq!(expand_tilde("relative/path"), "relative/path");
+        assert_eq!(expand_tilde("~notahome"), "~notahome");
+    }
+
+    #[test]
+    fn test_resolve_home_path() {
+        let home = Path::new("C:\\Users\\ada");
+        assert_eq!(
+            resolve_home_path("%USERPROFILE%\\Pictures", Some(home), &[]),
+            "C:\\Users\\ada\\Pictures"
+        );
+        assert_eq!(
+            resolve_home_path("%userprofile%/x", Some(home), &[]),
+            "C:\\Users\\ada/x"
+        );
+        assert_eq!(
+            resolve_home_path("~\\x", Some(home), &[]),
+            "C:\\Users\\ada\\x"
+        );
+        // No home to expand to
+        assert_eq!(resolve_home_path("~/x", None, &[]), "~/x");
+
+        // Known folders win over the home directory
+        let known = [
+            ("~/Pictures/", Some(PathBuf::from("D:\\Photos"))),
+            ("~/.cache/", None),
+        ];
+        assert_eq!(
+            resolve_home_path("~/Pictures/NationalGeographic/", Some(home), &known),
+            format!("D:\\Photos{}NationalGeographic/", std::path::MAIN_SEPARATOR)
+        );
+        assert_eq!(
+            resolve_home_path("~/.cache/natgeo-wallpapers/", Some(home), &known),
+            "C:\\Users\\ada/.cache/natgeo-wallpapers/"
+        );
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn test_windows_default_dirs() {
+        let pictures = dirs::picture_dir().unwrap();
+        let local = dirs::data_local_dir().unwrap();
+        let dirs =
+            resolve_data_dirs(&|_| None, &Config::default(), &BaseDirs::from_system()).unwrap();
+        assert!(PathBuf::from(&dirs.photos).starts_with(&pictures));
+        assert!(PathBuf::from(&dirs.logs).starts_with(&local));
+        assert!(dirs.logs.ends_with("natgeo-wallpapers/"));
+    }
+
+    // ========================================================================
+    // Collection Scraping Tests
+    // ========================================================================
+
+    #[test]
+    fn test_extract_collection_name_from_url() {
+        // Test various URL formats
+        assert_eq!(
+            extract_collection_name_from_url(
+                "https://www.nationalgeographic.com/photography/article/best-photos-october-2018"
+            ),
+            "best-photos-october-2018"
+        );
+        assert_eq!(
+            extract_collection_name_from_url(
+                "https://www.nationalgeographic.com/photography/article/best-photos-september-2018"
+            ),
+            "best-photos-september-2018"
+        );
+
+        // Edge case: URL with trailing slash
+        assert_eq!(
+            extract_collection_name_from_url("https://example.com/path/to/collection/"),
+            ""
+        );
+
+        // Edge case: simple path
+        assert_eq!(
+            extract_collection_name_from_url("collection-name"),
+            "collection-name"
+        );
+    }
+
+    /// URLs of the images in `body`, the widest size of each file
+    fn extract_natgeo_image_urls(body: &str) -> Vec<String> {
+        extract_natgeo_image_variants(body)
+            .into_iter()
+            .map(|variant| variant.url)
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_natgeo_image_urls() {
+        // Test HTML with multiple image URLs
+        let html = r#"
+            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg">
+            <img src="https://i.natgeofe.com/n/def456/photo2.jpg">
+            <script>{"url": "https://i.natgeofe.com/n/ghi789/photo3.jpg"}</script>
+        "#;
+
+        let urls = extract_natgeo_image_urls(html);
+        assert_eq!(urls.len(), 3);
+        assert!(urls.contains(&"https://i.natgeofe.com/n/abc123/photo1.jpg".to_string()));
+        assert!(urls.contains(&"https://i.natgeofe.com/n/def456/photo2.jpg".to_string()));
+        assert!(urls.contains(&"https://i.natgeofe.com/n/ghi789/photo3.jpg".to_string()));
+    }
+
+    #[test]
+    fn test_extract_natgeo_image_urls_filters_crops() {
+        // Test that crop variants are filtered out
+        let html = r#"
+            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg">
+            <img src="https://i.natgeofe.com/n/abc123/photo1_16x9.jpg">
+            <img src="https://i.natgeofe.com/n/abc123/photo1_3x2.jpg">
+            <img src="https://i.natgeofe.com/n/abc123/photo1_square.jpg">
+        "#;
+
+        let urls = extract_natgeo_image_urls(html);
+        // Should only include the raw image, not crop variants
+        assert_eq!(urls.len(), 1);
+        assert!(urls.contains(&"https://i.natgeofe.com/n/abc123/photo1.jpg".to_string()));
+    }
+
+    #[test]
+    fn test_extract_natgeo_image_urls_deduplicates() {
+        // Test that duplicate URLs are deduplicated
+        let html = r#"
+            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg">
+            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg">
+            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg">
+        "#;
+
+        let urls = extract_natgeo_image_urls(html);
+        assert_eq!(urls.len(), 1);
+    }
+
+    #[test]
+    fn test_extract_natgeo_image_urls_handles_query_params() {
+        // Test that URLs with query parameters are handled correctly
+        let html = r#"
+            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg?w=1200&amp;h=800">
+            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg?w=2400&amp;h=1600">
+            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg">
+        "#;
+
+        let urls = extract_natgeo_image_urls(html);
+        assert_eq!(urls.len(), 1);
+        // Should strip query params
+        assert!(urls.contains(&"https://i.natgeofe.com/n/abc123/photo1.jpg".to_string()));
+    }
+
+    #[test]
+    fn test_photo_collection_struct() {
+        let collection = PhotoCollection {
+            name: "Best Photos - October 2018".to_string(),
+            photos: vec![
+                PhotoInfo {
+                    image_url: "https://example.com/photo1.jpg".to_string(),
+                    title: "Photo 1".to_string(),
+                    caption: None,
+                    photographer: None,
+                },
+                PhotoInfo {
+                    image_url: "https://example.com/photo2.jpg".to_string(),
+                    title: "Photo 2".to_string(),
+                    caption: None,
+                    photographer: None,
+                },
+            ],
+        };
+
+        assert_eq!(collection.name, "Best Photos - October 2018");
+        assert_eq!(collection.photos.len(), 2);
+        assert_eq!(collection.photos[0].title, "Photo 1");
+        assert_eq!(collection.photos[1].title, "Photo 2");
+    }
+
+    #[test]
+    fn test_collection_download_result_struct() {
+        let result = CollectionDownloadResult {
+            downloaded: 5,
+            skipped: 3,
+            failed: 1,
+            photos: Vec::new(),
+        };
+
+        assert_eq!(result.downloaded, 5);
+        assert_eq!(result.skipped, 3);
+        assert_eq!(result.failed, 1);
+    }
+
+    #[test]
+    fn test_is_collection_photo_filename() {
+        let best_pod = CollectionFilter::default();
+
+        // Should match "best-pod" patterns
+        assert!(best_pod.matches("01-best-pod-october-18"));
+        assert!(best_pod.matches("02-best-pod-september-18"));
+        assert!(best_pod.matches("09-best-pod-july-18"));
+        assert!(best_pod.matches("best_pod_landscapes"));
+        assert!(best_pod.matches("01-best_pod-august-18"));
+
+        // Case insensitive
+        assert!(best_pod.matches("01-BEST-POD-October-18"));
+        assert!(best_pod.matches("BEST_POD_Landscapes"));
+
+        // Should NOT match other patterns
+        assert!(!best_pod.matches("MossForest"));
+        assert!(!best_pod.matches("GettyImages-109899052"));
+        assert!(!best_pod.matches("disneyplus"));
+        assert!(!best_pod.matches("kids"));
+        assert!(!best_pod.matches("SPI-1162458"));
+    }
+
+    #[test]
+    fn test_find_downloaded_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+
+        // Create a test file
+        let test_file = temp_dir.path().join("test_photo.jpg");
+        fs::write(&test_file, "fake image data").unwrap();
+
+        // Should find the file
+        let found = find_downloaded_file(dir, "test_photo");
+        assert!(found.is_some());
+        assert_eq!(found.unwrap(), test_file);
+
+        // Should not find non-existent file
+        let not_found = find_downloaded_file(dir, "nonexistent");
+        assert!(not_found.is_none());
+    }
+
+    #[test]
+    fn test_min_photo_size_constant() {
+        // Verify the minimum size is reasonable (50KB)
+        assert_eq!(MIN_PHOTO_SIZE_BYTES, 50_000);
+    }
+
+    // ========================================================================
+    // Uninstall Tests
+    // ========================================================================
+
+    fn uninstall_paths(root: &std::path::Path) -> UninstallPaths {
+        UninstallPaths {
+            data_dir: root.join("data"),
+            cache_dir: root.join("cache"),
+            config_file: root.join("config/config.toml"),
+            photo_dirs: vec![root.join("photos"), root.join("photos/collections")],
+            default_dirs: vec![root.join("data"), root.join("cache")],
+        }
+    }
+
+    #[test]
+    fn test_ensure_managed_dir_marks_only_new_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let new_dir = temp_dir.path().join("new");
+        ensure_managed_dir(new_dir.to_str().unwrap()).unwrap();
+        assert!(is_managed_dir(&new_dir));
+
+        let existing = temp_dir.path().join("existing");
+        fs::create_dir(&existing).unwrap();
+        ensure_managed_dir(existing.to_str().unwrap()).unwrap();
+        assert!(!is_managed_dir(&existing));
+    }
+
+    #[test]
+    fn test_plan_uninstall_default_scope() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths = uninstall_paths(temp_dir.path());
+        fs::create_dir_all(&paths.data_dir).unwrap();
+        fs::create_dir_all(&paths.cache_dir).unwrap();
+        fs::create_dir_all(paths.config_file.parent().unwrap()).unwrap();
+        fs::write(&paths.config_file, "").unwrap();
+        ensure_managed_dir(paths.photo_dirs[0].to_str().unwrap()).unwrap();
+
+        let plan = plan_uninstall(&paths, UninstallOptions::default());
+        assert_eq!(
+            plan.remove,
+            vec![paths.data_dir.clone(), paths.cache_dir.clone()]
+        );
+        assert!(plan.photo_dirs.is_empty());
+        assert!(plan.refused.is_empty());
+
+        let plan = plan_uninstall(
+            &paths,
+            UninstallOptions {
+                config: true,
+                photos: false,
+            },
+        );
+        assert!(plan.remove.contains(&paths.config_file));
+        assert!(plan.photo_dirs.is_empty());
+    }
+
+    #[test]
+    fn test_plan_uninstall_skips_missing_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths = uninstall_paths(temp_dir.path());
+
+        let plan = plan_uninstall(
+            &paths,
+            UninstallOptions {
+                config: true,
+                photos: true,
+            },
+        );
+        assert!(plan.remove.is_empty());
+        assert!(plan.photo_dirs.is_empty());
+        assert!(plan.refused.is_empty());
+    }
+
+    #[test]
+    fn test_plan_uninstall_photos_requires_marker() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths = uninstall_paths(temp_dir.path());
+        fs::create_dir_all(&paths.photo_dirs[1]).unwrap();
+
+        let options = UninstallOptions {
+            config: false,
+            photos: true,
+        };
+        let plan = plan_uninstall(&paths, options);
+        assert!(plan.photo_dirs.is_empty());
+        assert_eq!(plan.refused.len(), 2);
+
+        // Nested collections dir is covered by the managed parent
+        fs::write(paths.photo_dirs[0].join(MANAGED_DIR_MARKER), "").unwrap();
+        let plan = plan_uninstall(&paths, options);
+        assert_eq!(plan.photo_dirs, vec![paths.photo_dirs[0].clone()]);
+        assert!(plan.refused.is_empty());
+    }
+
+    #[test]
+    fn test_plan_uninstall_refuses_unmarked_override_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut paths = uninstall_paths(temp_dir.path());
+        // Cache pointed at a folder the user already had, e.g. ~/.cache
+        paths.cache_dir = temp_dir.path().join("home/.cache");
+        fs::create_dir_all(paths.cache_dir.join("other-app")).unwrap();
+        paths.data_dir = temp_dir.path().join("custom-data");
+        ensure_managed_dir(paths.data_dir.to_str().unwrap()).unwrap();
+
+        let plan = plan_uninstall(&paths, UninstallOptions::default());
+        assert_eq!(plan.remove, vec![paths.data_dir.clone()]);
+        assert_eq!(plan.refused.len(), 1);
+        assert_eq!(plan.refused[0].0, paths.cache_dir);
+
+        execute_uninstall(&plan).unwrap();
+        assert!(paths.cache_dir.join("other-app").exists());
+        assert!(!paths.data_dir.exists());
+    }
+
+    #[test]
+    fn test_execute_uninstall_removes_only_planned_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths = uninstall_paths(temp_dir.path());
+        fs::create_dir_all(&paths.data_dir).unwrap();
+        fs::write(paths.data_dir.join("wallpaper.log"), "log").unwrap();
+        fs::create_dir_all(paths.config_file.parent().unwrap()).unwrap();
+        fs::write(&paths.config_file, "").unwrap();
+        fs::create_dir_all(&paths.photo_dirs[0]).unwrap();
+        fs::write(paths.photo_dirs[0].join("photo.jpg"), "data").unwrap();
+
+        let plan = plan_uninstall(
+            &paths,
+            UninstallOptions {
+                config: false,
+                photos: true,
+            },
+        );
+        let removed = execute_uninstall(&plan).unwrap();
+
+        assert_eq!(removed, vec![paths.data_dir.clone()]);
+        assert!(!paths.data_dir.exists());
+        assert!(paths.config_file.exists());
+        assert!(paths.photo_dirs[0].join("photo.jpg").exists());
+    }
+
+    // ========================================================================
+    // Monitor Enumeration Tests
+    // ========================================================================
+
+    fn monitor(width: u32, height: u32, scale: f64) -> MonitorInfo {
+        MonitorInfo {
+            name: "TEST-1".to_string(),
+            width,
+            height,
+            scale,
+            layout: None,
+        }
+    }
+
+    #[test]
+    fn test_monitor_size_spaces() {
+        let m = monitor(3840, 2160, 2.0);
+        assert_eq!(m.size(PixelSpace::Physical), (3840, 2160));
+        assert_eq!(m.size(PixelSpace::Logical), (1920, 1080));
+
+        let m = monitor(2560, 1600, 1.25);
+        assert_eq!(m.size(PixelSpace::Logical), (2048, 1280));
+    }
+
+    #[test]
+    fn test_fit_quality_table() {
+        // (photo, monitor physical resolution, scale, expected)
+        let cases = [
+            ((3840, 2160), (3840, 2160), 1.0, FitQuality::Exact),
+            ((3840, 2160), (3840, 2160), 2.0, FitQuality::Exact),
+            ((1920, 1080), (3840, 2160), 2.0, FitQuality::Upscaled),
+            ((1920, 1080), (1920, 1080), 1.0, FitQuality::Exact),
+            ((2048, 1365), (1920, 1080), 1.0, FitQuality::Downscaled),
+            ((2048, 1365), (2560, 1440), 1.0, FitQuality::Upscaled),
+            ((2048, 1365), (2560, 1600), 1.25, FitQuality::Upscaled),
+            ((6000, 4000), (2880, 1800), 2.0, FitQuality::Downscaled),
+            ((2400, 1600), (1920, 1200), 1.0, FitQuality::Downscaled),
+            ((1080, 1920), (1920, 1080), 1.0, FitQuality::Upscaled),
+        ];
+
+        for ((pw, ph), (mw, mh), scale, expected) in cases {
+            assert_eq!(
+                fit_quality(pw, ph, &monitor(mw, mh, scale)),
+                expected,
+                "photo {}x{} on {}x{} @ {}",
+                pw,
+                ph,
+                mw,
+                mh,
+                scale
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_kscreen_outputs() {
+        let json = r#"{"outputs": [
+            {"name": "eDP-1", "enabled": true, "connected": true, "scale": 2,
+             "pos": {"x": 0, "y": 0}, "currentModeId": "2", "modes": [
+                {"id": "1", "size": {"width": 1920, "height": 1080}},
+                {"id": "2", "size": {"width": 3840, "height": 2160}}]},
+            {"name": "DP-1", "enabled": true, "connected": true, "scale": 1,
+             "currentModeId": "5", "modes": [
+                {"id": "5", "size": {"width": 2560, "height": 1440}}]},
+            {"name": "HDMI-1", "enabled": false, "connected": false, "scale": 1,
+             "currentModeId": "", "modes": []}
+        ]}"#;
+
+        let monitors = parse_kscreen_outputs(json);
+        assert_eq!(
+            monitors,
+            vec![
+                MonitorInfo {
+                    name: "eDP-1".to_string(),
+                    width: 3840,
+                    height: 2160,
+                    scale: 2.0,
+                    layout: Some(LayoutRect {
+                        x: 0,
+                        y: 0,
+                        width: 1920,
+                        height: 1080
+                    }),
+                },
+                MonitorInfo {
+                    name: "DP-1".to_string(),
+                    width: 2560,
+                    height: 1440,
+                    scale: 1.0,
+                    layout: None,
+                },
+            ]
+        );
+        assert!(parse_kscreen_outputs("not json").is_empty());
+    }
+
+    #[test]
+    fn test_parse_sway_outputs() {
+        let json = r#"[
+            {"name": "eDP-1", "active": true, "scale": 1.5,
+             "rect": {"x": -1920, "y": 0, "width": 1920, "height": 1200},
+             "current_mode": {"width": 2880, "height": 1800, "refresh": 60000}},
+            {"name": "HDMI-A-1", "active": false, "scale": 1.0, "current_mode": {}}
+        ]"#;
+
+        let monitors = parse_sway_outputs(json);
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].name, "eDP-1");
+        assert_eq!(monitors[0].size(PixelSpace::Physical), (2880, 1800));
+        assert_eq!(monitors[0].size(PixelSpace::Logical), (1920, 1200));
+        assert_eq!(
+            monitors[0].layout,
+            Some(LayoutRect {
+                x: -1920,
+                y: 0,
+                width: 1920,
+                height: 1200
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_hyprland_monitors() {
+        let json = r#"[
+            {"id": 0, "name": "DP-1", "width": 3840, "height": 2160, "scale": 2.0,
+             "disabled": false},
+            {"id": 1, "name": "HDMI-A-1", "width": 1920, "height": 1080, "scale": 1.0,
+             "disabled": true},
+            {"id": 2, "name": "eDP-1", "width": 2560, "height": 1600, "scale": 1.25,
+             "x": 1920, "y": 0}
+        ]"#;
+
+        let monitors = parse_hyprland_monitors(json);
+        let names: Vec<&str> = monitors.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["DP-1", "eDP-1"]);
+        assert_eq!(monitors[0].size(PixelSpace::Logical), (1920, 1080));
+        assert_eq!(monitors[0].layout, None);
+        assert_eq!(
+            monitors[1].layout,
+            Some(LayoutRect {
+                x: 1920,
+                y: 0,
+                width: 2048,
+                height: 1280
+            })
+        );
+        assert!(parse_hyprland_monitors("not json").is_empty());
+    }
+
+    #[test]
+    fn test_parse_swww_query() {
+        let old = "DP-1: 2560x1440, scale: 1, currently displaying: color: 000000
+eDP-1: 2880x1800, scale: 2, currently displaying: image: /photos/a.jpg
+";
+        let monitors = parse_swww_query(old);
+        let names: Vec<&str> = monitors.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["DP-1", "eDP-1"]);
+        assert_eq!(monitors[1].size(PixelSpace::Logical), (1440, 900));
+
+        let new = ": HDMI-A-1: 1920x1080, scale: 1, currently displaying: image: /photos/b.jpg\n";
+        let monitors = parse_swww_query(new);
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].name, "HDMI-A-1");
+        assert_eq!(monitors[0].size(PixelSpace::Physical), (1920, 1080));
+
+        assert!(parse_swww_query("").is_empty());
+        assert!(parse_swww_query("Error: failed to connect to the socket").is_empty());
+    }
+
+    #[test]
+    fn test_parse_xrandr_outputs() {
+        let text = "Screen 0: minimum 8 x 8, current 5760 x 2160, maximum 32767 x 32767
+eDP-1 connected primary 3840x2160+0+0 (normal left inverted right x axis y axis) 344mm x 194mm
+   3840x2160     60.00*+
+DP-1 connected 1920x1080+3840+0 (normal left inverted right x axis y axis) 527mm x 296mm
+   1920x1080     60.00*+
+HDMI-1 disconnected (normal left inverted right x axis y axis)
+DP-2 connected (normal left inverted right x axis y axis)
+";
+
+        let monitors = parse_xrandr_outputs(text);
+        assert_eq!(monitors.len(), 2);
+        assert_eq!(monitors[0].name, "eDP-1");
+        assert_eq!(monitors[0].size(PixelSpace::Physical), (3840, 2160));
+        assert!((monitors[0].scale - 3.0).abs() < f64::EPSILON);
+        assert_eq!(monitors[1].name, "DP-1");
+        assert!((monitors[1].scale - 1.0).abs() < f64::EPSILON);
+        // X11 lays outputs out in physical pixels, whatever their DPI
+        assert_eq!(
+            monitors[1].layout,
+            Some(LayoutRect {
+                x: 3840,
+                y: 0,
+                width: 1920,
+                height: 1080
+            })
+        );
+    }
+
+    #[test]
+    fn test_scale_from_dpi() {
+        assert!((scale_from_dpi(1920, 527) - 1.0).abs() < f64::EPSILON);
+        assert!((scale_from_dpi(2560, 336) - 2.0).abs() < f64::EPSILON);
+        assert!((scale_from_dpi(1920, 0) - 1.0).abs() < f64::EPSILON);
+    }
+
+    // ========================================================================
+    // Photo Discovery Tests
+    // ========================================================================
+
+    /// The original sequential `read_dir` walk, kept as a reference implementation
+    fn collect_photos_sequential(
+        dir: &std::path::Path,
+        photos: &mut Vec<PathBuf>,
+    ) -> io::Result<()> {
+        if dir.is_dir() {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    collect_photos_sequential(&path, photos)?;
+                } else if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+                    if matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg" | "png" | "gif") {
+                        photos.push(path);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_walk_matches_sequential_walk() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        for day in 1..=12 {
+            let dir = root.join(format!("{:02}-01-2026", day));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("photo_{}.jpg", day)), "x").unwrap();
+            fs::write(dir.join(format!("photo_{}.log", day)), "x").unwrap();
+            fs::write(dir.join(format!("extra_{}.PNG", day)), "x").unwrap();
+        }
+        let nested = root.join("collections/best-photos-october-2018");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(nested.join("01-best-pod.gif"), "x").unwrap();
+        fs::write(nested.join(".hidden.jpeg"), "x").unwrap();
+        fs::write(nested.join("collection.log"), "x").unwrap();
+
+        let mut parallel = Vec::new();
+        collect_photos(root, &mut parallel);
+        let mut sequential = Vec::new();
+        collect_photos_sequential(root, &mut sequential).unwrap();
+
+        parallel.sort();
+        sequential.sort();
+        assert_eq!(parallel.len(), 12 * 2 + 2);
+        assert_eq!(parallel, sequential);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_collect_photos_skips_dangling_current_link() {
+        let temp_dir = TempDir::new().unwrap();
+        let day = temp_dir.path().join("2026-01-01");
+        fs::create_dir(&day).unwrap();
+        fs::write(day.join("kept.jpg"), "x").unwrap();
+        std::os::unix::fs::symlink(day.join("removed.jpg"), temp_dir.path().join("current"))
+            .unwrap();
+
+        let mut photos = Vec::new();
+        collect_photos(temp_dir.path(), &mut photos);
+        assert_eq!(photos, [day.join("kept.jpg")]);
+
+        let cache = PhotoListingCache::new(temp_dir.path()).unwrap();
+        assert_eq!(cache.photos(), &[day.join("kept.jpg")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_collect_photos_does_not_loop_through_symlinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let library = temp_dir.path().join("library");
+        let day = library.join("2026-01-01");
+        let elsewhere = temp_dir.path().join("elsewhere");
+        fs::create_dir_all(&day).unwrap();
+        fs::create_dir_all(&elsewhere).unwrap();
+        fs::write(day.join("kept.jpg"), "x").unwrap();
+        fs::write(elsewhere.join("outside.jpg"), "x").unwrap();
+        // Back into the library, relative and absolute
+        std::os::unix::fs::symlink("..", day.join("up")).unwrap();
+        std::os::unix::fs::symlink(&day, library.join("again")).unwrap();
+        // Out of the library, and from there round in a circle
+        std::os::unix::fs::symlink(&elsewhere, library.join("extra")).unwrap();
+        std::os::unix::fs::symlink(&elsewhere, elsewhere.join("self")).unwrap();
+
+        let expected = [day.join("kept.jpg"), library.join("extra/outside.jpg")];
+        let mut photos = Vec::new();
+        collect_photos(&library, &mut photos);
+        photos.sort();
+        assert_eq!(photos, expected);
+
+        let mut cache = PhotoListingCache::new(&library).unwrap();
+        cache.refresh().unwrap();
+        let mut cached = cache.photos().to_vec();
+        cached.sort();
+        assert_eq!(cached, expected);
+    }
+
+    #[test]
+    fn test_collect_photos_missing_dir_is_empty() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut photos = Vec::new();
+        collect_photos(&temp_dir.path().join("missing"), &mut photos);
+        assert!(photos.is_empty());
+    }
+
+    // ========================================================================
+    // Photo Listing Cache Tests
+    // ========================================================================
+
+    /// Backdate a directory's mtime so the cache treats it as settled
+    fn backdate(path: &std::path::Path) {
+        let an_hour_ago = SystemTime::now() - Duration::from_hours(1);
+        File::open(path)
+            .unwrap()
+            .set_times(fs::FileTimes::new().set_modified(an_hour_ago))
+            .unwrap();
+    }
+
+    fn settled_library() -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        for day in ["01-01-2026", "02-01-2026"] {
+            let dir = temp_dir.path().join(day);
+            fs::create_dir(&dir).unwrap();
+            fs::write(dir.join("photo.jpg"), "x").unwrap();
+            backdate(&dir);
+        }
+        backdate(temp_dir.path());
+        temp_dir
+    }
+
+    #[test]
+    fn test_photo_cache_unchanged_when_nothing_changes() {
+        let library = settled_library();
+        let mut cache = PhotoListingCache::new(library.path()).unwrap();
+        assert_eq!(cache.photos().len(), 2);
+
+        assert_eq!(cache.refresh().unwrap(), RefreshOutcome::Unchanged);
+        assert_eq!(cache.photos().len(), 2);
+    }
+
+    #[test]
+    fn test_photo_cache_picks_up_new_date_folder() {
+        let library = settled_library();
+        let mut cache = PhotoListingCache::new(library.path()).unwrap();
+
+        let new_day = library.path().join("03-01-2026");
+        fs::create_dir(&new_day).unwrap();
+        fs::write(new_day.join("new.jpg"), "x").unwrap();
+
+        assert_eq!(cache.refresh().unwrap(), RefreshOutcome::Partial(1));
+        assert_eq!(cache.photos().len(), 3);
+        assert_eq!(cache.photos()[0], new_day.join("new.jpg"));
+    }
+
+    #[test]
+    fn test_photo_cache_file_added_and_removed_in_existing_folder() {
+        let library = settled_library();
+        let mut cache = PhotoListingCache::new(library.path()).unwrap();
+        let day = library.path().join("01-01-2026");
+
+        fs::write(day.join("second.png"), "x").unwrap();
+        fs::write(day.join("notes.log"), "x").unwrap();
+        assert_eq!(cache.refresh().unwrap(), RefreshOutcome::Partial(1));
+        assert!(cache.photos().contains(&day.join("second.png")));
+        assert_eq!(cache.photos().len(), 3);
+
+        fs::remove_file(day.join("photo.jpg")).unwrap();
+        cache.refresh().unwrap();
+        assert!(!cache.photos().contains(&day.join("photo.jpg")));
+        assert_eq!(cache.photos().len(), 2);
+    }
+
+    #[test]
+    fn test_photo_cache_full_rescan_when_folder_removed() {
+        let library = settled_library();
+        let mut cache = PhotoListingCache::new(library.path()).unwrap();
+
+        fs::remove_dir_all(library.path().join("02-01-2026")).unwrap();
+
+        assert_eq!(cache.refresh().unwrap(), RefreshOutcome::Full);
+        assert_eq!(
+            cache.photos(),
+            &[library.path().join("01-01-2026/photo.jpg")]
+        );
+    }
+
+    // ========================================================================
+    // Image Probing and Conversion Tests
+    // ========================================================================
+
+    /// JPEG XL codestream headers (signature + `SizeHeader` only)
+    const JXL_64X48: &[u8] = &[0xff, 0x0a, 0xcb, 0x00];
+    const JXL_1920X1080: &[u8] = &[0xff, 0x0a, 0xba, 0x21, 0x05];
+    const JXL_20000X300: &[u8] = &[0xff, 0x0a, 0x58, 0x09, 0x3f, 0x9c, 0x00];
+
+    fn write_tiff_fixture(path: &std::path::Path, width: u32, height: u32) {
+        image::RgbImage::from_pixel(width, height, image::Rgb([30, 120, 200]))
+            .save(path)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tiff_and_jxl_content_types() {
+        assert_eq!(
+            get_extension_from_content_type("image/tiff").unwrap(),
+            "tiff"
+        );
+        assert_eq!(get_extension_from_content_type("image/jxl").unwrap(), "jxl");
+    }
+
+    #[test]
+    fn test_parse_jxl_dimensions() {
+        assert_eq!(parse_jxl_dimensions(JXL_64X48), Some((64, 48)));
+        assert_eq!(parse_jxl_dimensions(JXL_1920X1080), Some((1920, 1080)));
+        assert_eq!(parse_jxl_dimensions(JXL_20000X300), Some((20000, 300)));
+        assert_eq!(parse_jxl_dimensions(b"\xff\xd8\xff"), None);
+        assert_eq!(parse_jxl_dimensions(&[0xff, 0x0a]), None);
+
+        // Same codestream wrapped in an ISOBMFF container
+        let mut container = b"\0\0\0\x0cJXL \r\n\x87\n".to_vec();
+        container.extend_from_slice(&[0, 0, 0, 20]);
+        container.extend_from_slice(b"ftypjxl \0\0\0\0jxl ");
+        container.extend_from_slice(&[0, 0, 0, 13]);
+        container.extend_from_slice(b"jxlc");
+        container.extend_from_slice(JXL_1920X1080);
+        assert_eq!(parse_jxl_dimensions(&container), Some((1920, 1080)));
+    }
+
+    #[test]
+    fn test_tiff_and_jxl_discovery_and_probing() {
+        let temp_dir = TempDir::new().unwrap();
+        let day = temp_dir.path().join("01-02-2026");
+        fs::create_dir(&day).unwrap();
+        write_tiff_fixture(&day.join("potd.tif"), 40, 30);
+        fs::write(day.join("import.jxl"), JXL_1920X1080).unwrap();
+
+        let photos = find_photos_in_path(Some(temp_dir.path().to_str().unwrap())).unwrap();
+        assert_eq!(photos.len(), 2);
+
+        assert_eq!(probe_dimensions(&day.join("potd.tif")).unwrap(), (40, 30));
+        assert_eq!(
+            probe_dimensions(&day.join("import.jxl")).unwrap(),
+            (1920, 1080)
+        );
+    }
+
+    #[test]
+    fn test_transcode_tiff_to_jpeg() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("potd.tiff");
+        write_tiff_fixture(&src, 16, 12);
+        let out_dir = temp_dir.path().join("converted");
+
+        let converted = transcode_to_jpeg(&src, &out_dir).unwrap();
+        assert_eq!(converted.parent(), Some(out_dir.as_path()));
+        assert_eq!(converted.extension().unwrap(), "jpg");
+        assert_eq!(
+            image::ImageFormat::from_path(&converted).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        assert_eq!(
+            image::open(&converted).unwrap().to_rgb8().dimensions(),
+            (16, 12)
+        );
+
+        // A fresh conversion is reused rather than redone
+        let before = fs::metadata(&converted).unwrap().modified().unwrap();
+        transcode_to_jpeg(&src, &out_dir).unwrap();
+        let after = fs::metadata(&converted).unwrap().modified().unwrap();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn test_transcode_keeps_same_named_photos_apart() {
+        let temp_dir = TempDir::new().unwrap();
+        let out_dir = temp_dir.path().join("converted");
+        let mut converted = Vec::new();
+        for (day, width) in [("2026-02-01", 16), ("2026-02-02", 24)] {
+            let dir = temp_dir.path().join(day);
+            fs::create_dir(&dir).unwrap();
+            write_tiff_fixture(&dir.join("potd.tif"), width, 12);
+            converted.push(transcode_to_jpeg(&dir.join("potd.tif"), &out_dir).unwrap());
+        }
+
+        assert_ne!(converted[0], converted[1]);
+        assert!(converted[0]
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("potd-"));
+        assert_eq!(image::image_dimensions(&converted[0]).unwrap(), (16, 12));
+        assert_eq!(image::image_dimensions(&converted[1]).unwrap(), (24, 12));
+    }
+
+    #[test]
+    fn test_transcode_jxl_without_djxl_is_a_clear_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("import.jxl");
+        fs::write(&src, JXL_64X48).unwrap();
+        let host = FakeHost {
+            search_path: temp_dir.path().join("empty-bin").into_os_string(),
+            proc_root: temp_dir.path().join("proc"),
+        };
+
+        let err = transcode_to_jpeg_in(&host, &src, &temp_dir.path().join("converted"))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("djxl is required to convert JPEG XL photos"),
+            "{err}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_transcode_jxl_decodes_with_djxl() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("import.jxl");
+        fs::write(&src, JXL_64X48).unwrap();
+        // A stand-in djxl that "decodes" to a prepared PNG
+        let decoded = temp_dir.path().join("decoded.png");
+        image::RgbImage::new(64, 48).save(&decoded).unwrap();
+        let bin = temp_dir.path().join("bin");
+        fs::create_dir(&bin).unwrap();
+        let djxl = bin.join("djxl");
+        fs::write(
+            &djxl,
+            format!("#!/bin/sh\ncp '{}' \"$2\"\n", decoded.display()),
+        )
+        .unwrap();
+        fs::set_permissions(&djxl, fs::Permissions::from_mode(0o755)).unwrap();
+        let host = FakeHost {
+            search_path: bin.into_os_string(),
+            proc_root: temp_dir.path().join("proc"),
+        };
+
+        let converted =
+            transcode_to_jpeg_in(&host, &src, &temp_dir.path().join("converted")).unwrap();
+        assert_eq!(
+            image::ImageFormat::from_path(&converted).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        assert_eq!(image::image_dimensions(&converted).unwrap(), (64, 48));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_convert_avif_decodes_outside_the_photo_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let day = temp_dir.path().join("photos/2026-02-01");
+        fs::create_dir_all(&day).unwrap();
+        let src = day.join("Heron.avif");
+        fs::write(&src, "avif").unwrap();
+        let decoded = temp_dir.path().join("decoded.png");
+        image::RgbImage::new(8, 6).save(&decoded).unwrap();
+        // A stand-in avifdec that notes where it was asked to write
+        let bin = temp_dir.path().join("bin");
+        fs::create_dir(&bin).unwrap();
+        let avifdec = bin.join("avifdec");
+        fs::write(
+            &avifdec,
+            format!(
+                "#!/bin/sh\necho \"$2\" > '{}'\ncp '{}' \"$2\"\n",
+                temp_dir.path().join("out.txt").display(),
+                decoded.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&avifdec, fs::Permissions::from_mode(0o755)).unwrap();
+        let host = FakeHost {
+            search_path: bin.into_os_string(),
+            proc_root: temp_dir.path().join("proc"),
+        };
+        let scratch = temp_dir.path().join("cache/converted");
+
+        let converted = convert_image_in(&host, &src, ConvertFormat::Jpeg, 85, &scratch).unwrap();
+
+        assert_eq!(converted, day.join("Heron.jpg"));
+        let written = fs::read_to_string(temp_dir.path().join("out.txt")).unwrap();
+        assert!(Path::new(written.trim()).starts_with(&scratch), "{written}");
+        let mut left: Vec<_> = fs::read_dir(&day)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["Heron.jpg"]);
+    }
+
+    #[test]
+    fn test_displays_extension() {
+        assert!(DesktopEnvironment::KdePlasma6.displays_extension("JPG"));
+        assert!(DesktopEnvironment::Gnome.displays_extension("tiff"));
+        assert!(!DesktopEnvironment::Feh.displays_extension("tif"));
+        assert!(!DesktopEnvironment::KdePlasma6.displays_extension("jxl"));
+        assert!(!DesktopEnvironment::Gnome.displays_extension("jxl"));
+        assert!(DesktopEnvironment::Hyprland.displays_extension("webp"));
+        assert!(!DesktopEnvironment::Hyprland.displays_extension("gif"));
+        assert!(DesktopEnvironment::Swww.displays_extension("gif"));
+        assert!(!DesktopEnvironment::Swww.displays_extension("avif"));
+    }
+
+    // ========================================================================
+    // Library Normalization Tests
+    // ========================================================================
+
+    #[test]
+    fn test_render_filename_template() {
+        let photo = std::path::Path::new("/photos/01-02-2026/Sunset over: the Alps.jpg");
+        assert_eq!(
+            render_filename_template(DEFAULT_FILENAME_TEMPLATE, photo),
+            "Sunset_over_the_Alps"
+        );
+        assert_eq!(
+            render_filename_template("{date}_{title}", photo),
+            "01-02-2026_Sunset_over_the_Alps"
+        );
+    }
+
+    #[test]
+    fn test_plan_normalize_handles_collisions() {
+        let temp_dir = TempDir::new().unwrap();
+        let day = temp_dir.path().join("01-02-2026");
+        fs::create_dir(&day).unwrap();
+        for name in ["Red Fox.jpg", "Red_Fox.jpg", "Red: Fox.jpg", "Owl.png"] {
+            fs::write(day.join(name), "x").unwrap();
+        }
+        let mut photos = vec![
+            day.join("Red Fox.jpg"),
+            day.join("Red_Fox.jpg"),
+            day.join("Red: Fox.jpg"),
+            day.join("Owl.png"),
+        ];
+        photos.sort();
+
+        let renames = plan_normalize(&photos, DEFAULT_FILENAME_TEMPLATE);
+        let targets: Vec<_> = renames.iter().map(|r| r.to.clone()).collect();
+
+        // Already-normalized names stay put and nothing is mapped onto them
+        assert!(!renames.iter().any(|r| r.from == day.join("Red_Fox.jpg")));
+        assert!(!renames.iter().any(|r| r.from == day.join("Owl.png")));
+        assert_eq!(renames.len(), 2);
+        assert!(targets.contains(&day.join("Red_Fox_2.jpg")));
+        assert!(targets.contains(&day.join("Red_Fox_3.jpg")));
+    }
+
+    #[test]
+    fn test_apply_normalize_updates_sidecars_and_references() {
+        let temp_dir = TempDir::new().unwrap();
+        let photo_root = temp_dir.path().join("photos");
+        let data_dir = temp_dir.path().join("data");
+        let day = photo_root.join("01-02-2026");
+        fs::create_dir_all(&day).unwrap();
+        fs::create_dir_all(&data_dir).unwrap();
+
+        let old = day.join("Snowy Owl.jpg");
+        let other = day.join("Heron.jpg");
+        fs::write(&old, "owl").unwrap();
+        fs::write(day.join("Snowy Owl.log"), "log").unwrap();
+        fs::write(&other, "heron").unwrap();
+
+        fs::write(
+            data_dir.join("favorites.txt"),
+            format!("{}\n{}\n", old.display(), other.display()),
+        )
+        .unwrap();
+        fs::write(
+            data_dir.join(HISTORY_FILE),
+            serde_json::json!({
+                "current": old.to_str().unwrap(),
+                "monitors": [old.to_str().unwrap()],
+            })
+            .to_string(),
+        )
+        .unwrap();
+        fs::write(
+            data_dir.join(MANIFEST_FILE),
+            serde_json::json!({ "index": { "01-02-2026/Snowy Owl.jpg": "abc123" } }).to_string(),
+        )
+        .unwrap();
+        fs::write(data_dir.join("wallpaper.log"), old.to_str().unwrap()).unwrap();
+        fs::write(data_dir.join("notes.txt"), format!("{}\n", old.display())).unwrap();
+        std::os::unix::fs::symlink(&old, photo_root.join("current")).unwrap();
+
+        let photos = find_photos_in_path(Some(photo_root.to_str().unwrap())).unwrap();
+        let renames = plan_normalize(&photos, DEFAULT_FILENAME_TEMPLATE);
+        assert_eq!(renames.len(), 1);
+
+        let report = apply_normalize(&renames, &data_dir, &photo_root).unwrap();
+        let new = day.join("Snowy_Owl.jpg");
+
+        assert_eq!(report.renames[0].to, new);
+        assert_eq!(fs::read_to_string(&new).unwrap(), "owl");
+        assert!(day.join("Snowy_Owl.log").exists());
+        assert_eq!(report.sidecars.len(), 1);
+
+        let favorites = fs::read_to_string(data_dir.join("favorites.txt")).unwrap();
+        assert_eq!(
+            favorites,
+            format!("{}\n{}\n", new.display(), other.display())
+        );
+
+        let read_json = |name: &str| -> serde_json::Value {
+            serde_json::from_str(&fs::read_to_string(data_dir.join(name)).unwrap()).unwrap()
+        };
+        let history = read_json(HISTORY_FILE);
+        assert_eq!(history["current"], new.to_str().unwrap());
+        assert_eq!(history["monitors"][0], new.to_str().unwrap());
+        assert_eq!(
+            read_json(MANIFEST_FILE)["index"]["01-02-2026/Snowy_Owl.jpg"],
+            "abc123"
+        );
+
+        // Files the tool doesn't own are left alone
+        assert_eq!(
+            fs::read_to_string(data_dir.join("notes.txt")).unwrap(),
+            format!("{}\n", old.display())
+        );
+
+        // Logs are history, not references
+        assert_eq!(
+            fs::read_to_string(data_dir.join("wallpaper.log")).unwrap(),
+            old.to_str().unwrap()
+        );
+        assert_eq!(fs::read_link(photo_root.join("current")).unwrap(), new);
+        assert_eq!(report.updated_references.len(), 4);
+        assert!(report.conflicts.is_empty());
+    }
+
+    /// A photo with a metadata sidecar titled `title`
+    fn photo_with_sidecar(path: &std::path::Path, title: &str) {
+        fs::write(path, "x").unwrap();
+        let metadata = PhotoMetadata {
+            url: "https://example.com/photo.jpg".to_string(),
+            title: title.to_string(),
+            downloaded_at: "2026-02-01T08:00:00+00:00".to_string(),
+            content_type: "image/jpeg".to_string(),
+            file_size: 1,
+            caption: None,
+            photographer: None,
+            brightness: None,
+            width: None,
+            height: None,
+        };
+        write_metadata(&metadata_path_for(path), &metadata).unwrap();
+    }
+
+    #[test]
+    fn test_normalize_takes_title_from_sidecar_and_is_idempotent() {
+        let temp_dir = TempDir::new().unwrap();
+        let photo_root = temp_dir.path().join("photos");
+        let data_dir = temp_dir.path().join("data");
+        let day = photo_root.join("2026-02-01");
+        fs::create_dir_all(&day).unwrap();
+        fs::create_dir_all(&data_dir).unwrap();
+        photo_with_sidecar(&day.join("01-best-pod-february-1.jpg"), "Snowy Owl");
+
+        assert_eq!(
+            render_filename_template("{date}_{title}", &day.join("01-best-pod-february-1.jpg")),
+            "2026-02-01_Snowy_Owl"
+        );
+
+        for expected_renames in [1, 0] {
+            let photos = find_photos_in_path(Some(photo_root.to_str().unwrap())).unwrap();
+            let renames = plan_normalize(&photos, "{date}_{title}");
+            assert_eq!(renames.len(), expected_renames);
+            apply_normalize(&renames, &data_dir, &photo_root).unwrap();
+        }
+        assert!(day.join("2026-02-01_Snowy_Owl.jpg").exists());
+        assert!(day.join("2026-02-01_Snowy_Owl.json").exists());
+    }
+
+    #[test]
+    fn test_normalize_keeps_collision_suffixes_stable() {
+        let temp_dir = TempDir::new().unwrap();
+        let photo_root = temp_dir.path().join("photos");
+        let data_dir = temp_dir.path().join("data");
+        let day = photo_root.join("2026-02-01");
+        fs::create_dir_all(&day).unwrap();
+        fs::create_dir_all(&data_dir).unwrap();
+        photo_with_sidecar(&day.join("Fox.jpg"), "Fox");
+        photo_with_sidecar(&day.join("Fox_2.jpg"), "Fox");
+
+        let photos = find_photos_in_path(Some(photo_root.to_str().unwrap())).unwrap();
+        let renames = plan_normalize(&photos, DEFAULT_FILENAME_TEMPLATE);
+        assert!(renames.is_empty(), "{renames:?}");
+        apply_normalize(&renames, &data_dir, &photo_root).unwrap();
+
+        let photos = find_photos_in_path(Some(photo_root.to_str().unwrap())).unwrap();
+        assert!(plan_normalize(&photos, DEFAULT_FILENAME_TEMPLATE).is_empty());
+    }
+
+    #[test]
+    fn test_apply_normalize_reports_sidecar_conflicts() {
+        let temp_dir = TempDir::new().unwrap();
+        let day = temp_dir.path().join("2026-02-01");
+        fs::create_dir(&day).unwrap();
+        fs::write(day.join("Snowy Owl.jpg"), "owl").unwrap();
+        fs::write(day.join("Snowy Owl.log"), "log").unwrap();
+        // Left over from some other photo, in the way of the log's new name
+        fs::write(day.join("Snowy_Owl.log"), "stray").unwrap();
+
+        let renames = vec![Rename {
+            from: day.join("Snowy Owl.jpg"),
+            to: day.join("Snowy_Owl.jpg"),
+        }];
+        let report = apply_normalize(&renames, temp_dir.path(), temp_dir.path()).unwrap();
+
+        assert!(report.renames.is_empty());
+        assert_eq!(
+            report.conflicts,
+            [Rename {
+                from: day.join("Snowy Owl.log"),
+                to: day.join("Snowy_Owl.log"),
+            }]
+        );
+        // Neither the photo nor its sidecar moved
+        assert!(day.join("Snowy Owl.jpg").exists());
+        assert_eq!(
+            fs::read_to_string(day.join("Snowy Owl.log")).unwrap(),
+            "log"
+        );
+        assert_eq!(
+            fs::read_to_string(day.join("Snowy_Owl.log")).unwrap(),
+            "stray"
+        );
+    }
+
+    // ========================================================================
+    // Desktop Detection Tests
+    // ========================================================================
+
+    #[derive(Default)]
+    struct FakeSystem {
+        commands: Vec<&'static str>,
+        processes: Vec<&'static str>,
+        env: Vec<(&'static str, &'static str)>,
+        schemas: Vec<&'static str>,
+        /// Names owned on the session D-Bus
+        bus_names: Vec<&'static str>,
+    }
+
+    impl SystemProbe for FakeSystem {
+        fn command_exists(&self, cmd: &str) -> bool {
+            self.commands.contains(&cmd)
+        }
+
+        fn process_running(&self, name: &str) -> bool {
+            self.processes.contains(&name)
+        }
+
+        fn env_var(&self, key: &str) -> Option<String> {
+            self.env
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| (*v).to_string())
+        }
+
+        fn gsettings_schema_exists(&self, schema: &str) -> bool {
+            self.schemas.contains(&schema)
+        }
+
+        fn dbus_name_has_owner(&self, name: &str) -> bool {
+            self.bus_names.contains(&name)
+        }
+    }
+
+    /// (commands on PATH, running processes, environment, expected backend)
+    type DetectionCase = (
+        &'static [&'static str],
+        &'static [&'static str],
+        &'static [(&'static str, &'static str)],
+        DesktopEnvironment,
+    );
+
+    fn detect(
+        commands: &[&'static str],
+        processes: &[&'static str],
+        env: &[(&'static str, &'static str)],
+    ) -> DesktopEnvironment {
+        detect_desktop_environment_with(&FakeSystem {
+            commands: commands.to_vec(),
+            processes: processes.to_vec(),
+            env: env.to_vec(),
+            ..FakeSystem::default()
+        })
+    }
+
+    #[test]
+    fn test_detect_gnome_requires_session_evidence() {
+        let cases: &[DetectionCase] = &[
+            // Previously misdetected as GNOME: gsettings present under sway/i3
+            (
+                &["gsettings"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "sway")],
+                DesktopEnvironment::Unknown,
+            ),
+            (&["gsettings"], &["i3"], &[], DesktopEnvironment::Unknown),
+            (
+                &["gsettings", "feh"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "i3"), ("DISPLAY", ":0")],
+                DesktopEnvironment::Feh,
+            ),
+            // Confirmed GNOME and GNOME-based sessions
+            (
+                &["gsettings"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "GNOME")],
+                DesktopEnvironment::Gnome,
+            ),
+            (
+                &["gsettings"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "ubuntu:GNOME")],
+                DesktopEnvironment::Gnome,
+            ),
+            (
+                &["gsettings"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "Unity")],
+                DesktopEnvironment::Gnome,
+            ),
+            (
+                &["gsettings"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "Budgie:GNOME")],
+                DesktopEnvironment::Gnome,
+            ),
+            (
+                &["gsettings", "feh"],
+                &["gnome-shell"],
+                &[],
+                DesktopEnvironment::Gnome,
+            ),
+            // GNOME evidence without gsettings can't be acted on
+            (
+                &["feh"],
+                &["gnome-shell"],
+                &[("DISPLAY", ":0")],
+                DesktopEnvironment::Feh,
+            ),
+            // Plasma still wins over everything else
+            (
+                &["qdbus6", "gsettings"],
+                &["plasmashell"],
+                &[("XDG_CURRENT_DESKTOP", "KDE")],
+                DesktopEnvironment::KdePlasma6,
+            ),
+        ];
+
+        for (commands, processes, env, expected) in cases {
+            assert_eq!(
+                detect(commands, processes, env),
+                *expected,
+                "commands={commands:?} processes={processes:?} env={env:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_detect_feh_requires_x11_session() {
+        let cases: &[DetectionCase] = &[
+            // Previously picked feh on Wayland, where the wallpaper never appears
+            (
+                &["feh"],
+                &[],
+                &[("XDG_SESSION_TYPE", "wayland"), ("DISPLAY", ":0")],
+                DesktopEnvironment::Unknown,
+            ),
+            (
+                &["gsettings", "feh"],
+                &[],
+                &[
+                    ("XDG_CURRENT_DESKTOP", "sway"),
+                    ("XDG_SESSION_TYPE", "wayland"),
+                ],
+                DesktopEnvironment::Unknown,
+            ),
+            // No display to draw on
+            (
+                &["feh"],
+                &[],
+                &[("XDG_SESSION_TYPE", "x11")],
+                DesktopEnvironment::Unknown,
+            ),
+            (
+                &["feh"],
+                &[],
+                &[("DISPLAY", "")],
+                DesktopEnvironment::Unknown,
+            ),
+            // Real X11 sessions
+            (
+                &["feh"],
+                &[],
+                &[("XDG_SESSION_TYPE", "x11"), ("DISPLAY", ":0")],
+                DesktopEnvironment::Feh,
+            ),
+            (&["feh"], &[], &[("DISPLAY", ":1")], DesktopEnvironment::Feh),
+            // GNOME on Wayland is unaffected
+            (
+                &["gsettings", "feh"],
+                &[],
+                &[
+                    ("XDG_CURRENT_DESKTOP", "GNOME"),
+                    ("XDG_SESSION_TYPE", "wayland"),
+                ],
+                DesktopEnvironment::Gnome,
+            ),
+        ];
+
+        for (commands, processes, env, expected) in cases {
+            assert_eq!(
+                detect(commands, processes, env),
+                *expected,
+                "commands={commands:?} processes={processes:?} env={env:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_detect_cinnamon_and_mate_before_gnome() {
+        let mint = |processes: Vec<&'static str>, env, schemas| {
+            detect_desktop_environment_with(&FakeSystem {
+                commands: vec!["gsettings", "feh"],
+                processes,
+                env,
+                schemas,
+                ..FakeSystem::default()
+            })
+        };
+        let all_schemas = vec![
+            "org.gnome.desktop.background",
+            "org.cinnamon.desktop.background",
+            "org.mate.background",
+        ];
+
+        assert_eq!(
+            mint(
+                vec!["cinnamon"],
+                vec![("XDG_CURRENT_DESKTOP", "X-Cinnamon")],
+                all_schemas.clone()
+            ),
+            DesktopEnvironment::Cinnamon
+        );
+        // Cinnamon sessions that also claim GNOME still get Cinnamon
+        assert_eq!(
+            mint(
+                vec![],
+                vec![("XDG_CURRENT_DESKTOP", "X-Cinnamon:GNOME")],
+                all_schemas.clone()
+            ),
+            DesktopEnvironment::Cinnamon
+        );
+        assert_eq!(
+            mint(
+                vec!["mate-session"],
+                vec![("XDG_CURRENT_DESKTOP", "MATE")],
+                all_schemas.clone()
+            ),
+            DesktopEnvironment::Mate
+        );
+        // Schemas installed alongside GNOME don't make it Cinnamon or MATE
+        assert_eq!(
+            mint(
+                vec!["gnome-shell"],
+                vec![("XDG_CURRENT_DESKTOP", "GNOME")],
+                all_schemas
+            ),
+            DesktopEnvironment::Gnome
+        );
+        // A cinnamon process without its schema can't be driven
+        assert_eq!(
+            mint(
+                vec!["cinnamon"],
+                vec![("DISPLAY", ":0")],
+                vec!["org.gnome.desktop.background"]
+            ),
+            DesktopEnvironment::Feh
+        );
+    }
+
+    #[test]
+    fn test_gsettings_wallpaper_args() {
+        let photo = Path::new("/photos/a.jpg");
+        let dark = Path::new("/photos/night.jpg");
+        let set =
+            |schema: &str, key: &str, value: &str| ["set", schema, key, value].map(String::from);
+        let gnome = "org.gnome.desktop.background";
+
+        // Without a dark photo, GNOME's dark style shows the same photo
+        assert_eq!(
+            gsettings_wallpaper_args(DesktopEnvironment::Gnome, photo, None, "zoom"),
+            [
+                set(gnome, "picture-options", "zoom"),
+                set(gnome, "picture-uri", "file:///photos/a.jpg"),
+                set(gnome, "picture-uri-dark", "file:///photos/a.jpg"),
+            ]
+        );
+        assert_eq!(
+            gsettings_wallpaper_args(DesktopEnvironment::Gnome, photo, Some(dark), "scaled"),
+            [
+                set(gnome, "picture-options", "scaled"),
+                set(gnome, "picture-uri", "file:///photos/a.jpg"),
+                set(gnome, "picture-uri-dark", "file:///photos/night.jpg"),
+            ]
+        );
+
+        // Cinnamon and MATE have no dark variant
+        for dark_photo in [None, Some(dark)] {
+            assert_eq!(
+                gsettings_wallpaper_args(DesktopEnvironment::Cinnamon, photo, dark_photo, "zoom"),
+                [
+                    set("org.cinnamon.desktop.background", "picture-options", "zoom"),
+                    set(
+                        "org.cinnamon.desktop.background",
+                        "picture-uri",
+                        "file:///photos/a.jpg"
+                    ),
+                ]
+            );
+            // MATE takes a plain path, not a URI
+            assert_eq!(
+                gsettings_wallpaper_args(DesktopEnvironment::Mate, photo, dark_photo, "centered"),
+                [
+                    set("org.mate.background", "picture-options", "centered"),
+                    set("org.mate.background", "picture-filename", "/photos/a.jpg"),
+                ]
+            );
+        }
+        assert!(gsettings_wallpaper_args(DesktopEnvironment::Feh, photo, None, "zoom").is_empty());
+
+        let options = [
+            FitMode::Fit,
+            FitMode::Zoom,
+            FitMode::Stretch,
+            FitMode::Center,
+            FitMode::Tile,
+        ]
+        .map(FitMode::gsettings_option);
+        assert_eq!(
+            options,
+            ["scaled", "zoom", "stretched", "centered", "wallpaper"]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_reason_explains_x11_tool_on_wayland() {
+        let wayland = FakeSystem {
+            commands: vec!["feh"],
+            env: vec![("XDG_SESSION_TYPE", "wayland")],
+            ..FakeSystem::default()
+        };
+        let reason = unsupported_desktop_reason(&wayland);
+        assert!(reason.contains("feh"), "{reason}");
+        assert!(reason.contains("wayland"), "{reason}");
+        assert!(reason.contains("swaybg"), "{reason}");
+
+        let nothing = FakeSystem {
+            env: vec![("XDG_SESSION_TYPE", "wayland")],
+            ..FakeSystem::default()
+        };
+        assert_eq!(
+            unsupported_desktop_reason(&nothing),
+            "No supported wallpaper tool found"
+        );
+    }
+
+    #[test]
+    fn test_detect_hyprland() {
+        let cases: &[DetectionCase] = &[
+            (
+                &["hyprctl", "feh"],
+                &[],
+                &[
+                    ("HYPRLAND_INSTANCE_SIGNATURE", "abc_123"),
+                    ("DISPLAY", ":0"),
+                ],
+                DesktopEnvironment::Hyprland,
+            ),
+            (
+                &["hyprctl"],
+                &["Hyprland"],
+                &[],
+                DesktopEnvironment::Hyprland,
+            ),
+            // hyprctl installed but Hyprland isn't the running session
+            (
+                &["hyprctl", "qdbus6"],
+                &["plasmashell"],
+                &[],
+                DesktopEnvironment::KdePlasma6,
+            ),
+            (
+                &[],
+                &[],
+                &[("HYPRLAND_INSTANCE_SIGNATURE", "abc_123")],
+                DesktopEnvironment::Unknown,
+            ),
+        ];
+
+        for (commands, processes, env, expected) in cases {
+            assert_eq!(
+                detect(commands, processes, env),
+                *expected,
+                "commands={commands:?} processes={processes:?} env={env:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_detect_swww() {
+        let cases: &[DetectionCase] = &[
+            // A running daemon wins, even under Hyprland
+            (
+                &["swww", "hyprctl"],
+                &["swww-daemon", "Hyprland"],
+                &[],
+                DesktopEnvironment::Swww,
+            ),
+            (
+                &["swww", "qdbus6"],
+                &["plasmashell"],
+                &[],
+                DesktopEnvironment::KdePlasma6,
+            ),
+            // Installed on Wayland without a daemon, so setting explains how to start it
+            (
+                &["swww"],
+                &[],
+                &[("XDG_SESSION_TYPE", "wayland")],
+                DesktopEnvironment::Swww,
+            ),
+            (
+                &["swww"],
+                &[],
+                &[("WAYLAND_DISPLAY", "wayland-1")],
+                DesktopEnvironment::Swww,
+            ),
+            (
+                &["swww"],
+                &[],
+                &[("XDG_SESSION_TYPE", "x11")],
+                DesktopEnvironment::Unknown,
+            ),
+        ];
+
+        for (commands, processes, env, expected) in cases {
+            assert_eq!(
+                detect(commands, processes, env),
+                *expected,
+                "commands={commands:?} processes={processes:?} env={env:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_detect_xfce() {
+        let cases: &[DetectionCase] = &[
+            (
+                &["xfconf-query", "plasma-apply-wallpaperimage", "feh"],
+                &["xfce4-session"],
+                &[("DISPLAY", ":0")],
+                DesktopEnvironment::Xfce,
+            ),
+            // xfconf installed outside an XFCE session
+            (
+                &["xfconf-query", "gsettings"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "GNOME")],
+                DesktopEnvironment::Gnome,
+            ),
+        ];
+
+        for (commands, processes, env, expected) in cases {
+            assert_eq!(
+                detect(commands, processes, env),
+                *expected,
+                "commands={commands:?} processes={processes:?} env={env:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_detect_lxqt() {
+        let cases: &[DetectionCase] = &[
+            // LXQt often pulls in KDE tools; pcmanfm-qt still draws the desktop
+            (
+                &["plasma-apply-wallpaperimage", "feh"],
+                &["pcmanfm-qt"],
+                &[("XDG_CURRENT_DESKTOP", "LXQt"), ("DISPLAY", ":0")],
+                DesktopEnvironment::Lxqt,
+            ),
+            // pcmanfm-qt as a file manager in another session
+            (
+                &["gsettings"],
+                &["pcmanfm-qt"],
+                &[("XDG_CURRENT_DESKTOP", "GNOME")],
+                DesktopEnvironment::Gnome,
+            ),
+            // LXQt session without the desktop running
+            (
+                &["feh"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "LXQt"), ("DISPLAY", ":0")],
+                DesktopEnvironment::Feh,
+            ),
+            // Plasma wins when it is actually running
+            (
+                &["qdbus6"],
+                &["plasmashell", "pcmanfm-qt"],
+                &[("XDG_CURRENT_DESKTOP", "LXQt")],
+                DesktopEnvironment::KdePlasma6,
+            ),
+        ];
+
+        for (commands, processes, env, expected) in cases {
+            assert_eq!(
+                detect(commands, processes, env),
+                *expected,
+                "commands={commands:?} processes={processes:?} env={env:?}"
+            );
+        }
+    }
+
+    // ========================================================================
+    // Photo Selection Tests
+    // ========================================================================
+
+    #[test]
+    fn test_parse_month_from_dir_name() {
+        let cases = [
+            ("18-10-2018", Some((2018, 10))),
+            ("2018-10-18", Some((2018, 10))),
+            ("2018-03", Some((2018, 3))),
+            ("best-photos-october-2018", Some((2018, 10))),
+            ("best-pod-sept-2019", None),
+            ("best_pod_sep_2019", Some((2019, 9))),
+            ("collections", None),
+            ("13-13-2018", None),
+            ("NationalGeographic", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(parse_month_from_dir_name(name), expected, "{name}");
+        }
+    }
+
+    /// Daily photos across four months, listed newest first
+    fn multi_month_library(root: &std::path::Path) -> Vec<PathBuf> {
+        let layout = [
+            ("20-10-2018", "a"),
+            ("19-10-2018", "b"),
+            ("18-10-2018", "c"),
+            ("17-10-2018", "d"),
+            ("30-09-2018", "e"),
+            ("29-09-2018", "f"),
+            ("15-08-2018", "g"),
+            ("best-photos-july-2018", "h"),
+            ("best-photos-july-2018", "i"),
+        ];
+        layout
+            .iter()
+            .map(|(dir, name)| {
+                let dir = root.join(dir);
+                fs::create_dir_all(&dir).unwrap();
+                let photo = dir.join(format!("{name}.jpg"));
+                File::create(&photo).unwrap();
+                photo
+            })
+            .collect()
+    }
+
+    fn names(photos: &[PathBuf]) -> Vec<String> {
+        photos
+            .iter()
+            .map(|p| p.file_stem().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_spread_monthly_one_photo_per_month_first() {
+        let temp_dir = TempDir::new().unwrap();
+        let photos = multi_month_library(temp_dir.path());
+
+        let spread = apply_spread(photos.clone(), SpreadStrategy::Monthly);
+        assert_eq!(
+            names(&spread),
+            ["a", "e", "g", "h", "b", "f", "i", "c", "d"]
+        );
+
+        // 2 monitors x 4 desktops: the first four slots cover four distinct months,
+        // the rest fall back to the most recent months
+        let assignments =
+            build_assignments(WallpaperMode::Both, &spread, 2, 4, &TargetFilter::default());
+        let assigned: Vec<PathBuf> = assignments.into_iter().map(|a| a.photo_path).collect();
+        assert_eq!(names(&assigned), ["a", "e", "g", "h", "b", "f", "i", "c"]);
+
+        assert_eq!(apply_spread(photos.clone(), SpreadStrategy::None), photos);
+    }
+
+    #[test]
+    fn test_spread_monthly_keeps_prior_order_within_month() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut photos = multi_month_library(temp_dir.path());
+
+        // Simulate a filter and a shuffle having run first
+        photos.retain(|p| !p.ends_with("20-10-2018/a.jpg"));
+        photos.swap(0, 2);
+
+        let spread = apply_spread(photos, SpreadStrategy::Monthly);
+        assert_eq!(names(&spread)[..4], ["d", "e", "g", "h"]);
+    }
+
+    #[test]
+    fn test_photo_month_falls_back_to_mtime() {
+        let temp_dir = TempDir::new().unwrap();
+        let photo = temp_dir.path().join("undated.jpg");
+        File::create(&photo).unwrap();
+        let march_2020 = chrono::NaiveDate::from_ymd_opt(2020, 3, 15)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap();
+        File::open(&photo)
+            .unwrap()
+            .set_times(fs::FileTimes::new().set_modified(march_2020.into()))
+            .unwrap();
+
+        assert_eq!(photo_month(&photo), Some((2020, 3)));
+    }
+
+    // ========================================================================
+    // Cancellation Tests
+    // ========================================================================
+
+    #[test]
+    fn test_cancel_flag_check() {
+        let flag = CancelFlag::new();
+        let shared = flag.clone();
+        assert!(flag.check().is_ok());
+
+        shared.cancel();
+        assert!(flag.is_cancelled());
+        assert!(matches!(flag.check(), Err(PhotoError::Interrupted)));
+    }
+
+    #[test]
+    fn test_in_progress_file_removed_unless_finished() {
+        let temp_dir = TempDir::new().unwrap();
+        let abandoned = temp_dir.path().join("abandoned.jpg");
+        let completed = temp_dir.path().join("completed.jpg");
+
+        {
+            let _guard = InProgressFile::track(&abandoned);
+            fs::write(&abandoned, b"half a photo").unwrap();
+        }
+        let guard = InProgressFile::track(&completed);
+        fs::write(&completed, b"a whole photo").unwrap();
+        guard.finish();
+
+        assert!(!abandoned.exists());
+        assert!(completed.exists());
+    }
+
+    #[test]
+    fn test_in_progress_file_registered_until_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+        let partial = temp_dir.path().join("partial.jpg");
+        let registered = |path: &std::path::Path| {
+            in_progress_files()
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|p| p == path)
+        };
+
+        // The forced-exit path deletes whatever is registered
+        let guard = InProgressFile::track(&partial);
+        assert!(registered(&partial));
+        drop(guard);
+        assert!(!registered(&partial));
+    }
+
+    // ========================================================================
+    // Download Outcome Tests
+    // ========================================================================
+
+    #[test]
+    fn test_download_reports_existing_photo_without_fetching() {
+        let temp_dir = TempDir::new().unwrap();
+        let save_dir = temp_dir.path().to_str().unwrap();
+        let existing = temp_dir.path().join("Sunset.png");
+        fs::write(&existing, b"already here").unwrap();
+        let log_path = format!("{}/Sunset.log", save_dir);
+
+        // The URL is never contacted when the photo is already on disk
+        let outcome = download_natgeo_photo_of_the_day(
+            "http://127.0.0.1:9/unreachable.jpg",
+            save_dir,
+            "Sunset",
+            &log_path,
+        )
+        .unwrap();
+
+        assert_eq!(outcome, DownloadOutcome::AlreadyExists(existing.clone()));
+        assert_eq!(outcome.path(), existing);
+        assert_eq!(fs::read(&existing).unwrap(), b"already here");
+        assert!(fs::read_to_string(&log_path)
+            .unwrap()
+            .contains("Photo already exists"));
+    }
+
+    // ========================================================================
+    // Date Directory Tests
+    // ========================================================================
+
+    #[test]
+    fn test_parse_date_dir_accepts_both_formats() {
+        let feb_1 = chrono::NaiveDate::from_ymd_opt(2025, 2, 1);
+        assert_eq!(parse_date_dir("2025-02-01"), feb_1);
+        assert_eq!(parse_date_dir("01-02-2025"), feb_1);
+        assert_eq!(parse_date_dir("best-photos-october-2018"), None);
+        assert_eq!(parse_date_dir("31-02-2025"), None);
+    }
+
+    #[test]
+    fn test_newest_photo_wins_across_old_and_new_dir_names() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        // Lexically, "31-01-2025" sorts after both "01-02-2025" and "2025-02-03"
+        let layout = [
+            ("31-01-2025", "jan31"),
+            ("01-02-2025", "feb01"),
+            ("2025-02-02", "feb02"),
+            ("2025-02-03", "feb03"),
+            ("30-12-2024", "dec30"),
+            ("wallpapers", "undated"),
+        ];
+        for (dir, name) in layout {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            File::create(root.join(dir).join(format!("{name}.jpg"))).unwrap();
+        }
+
+        let photos = find_photos_in_path(root.to_str()).unwrap();
+        let order: Vec<_> = photos
+            .iter()
+            .map(|p| p.file_stem().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            order,
+            ["feb03", "feb02", "feb01", "jan31", "dec30", "undated"]
+        );
+    }
+
+    #[test]
+    fn test_date_dir_for_reuses_legacy_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = format!("{}/", temp_dir.path().display());
+        let date = chrono::NaiveDate::from_ymd_opt(2025, 2, 1).unwrap();
+
+        assert_eq!(date_dir_for(&base, date), format!("{base}2025-02-01"));
+
+        fs::create_dir(format!("{base}01-02-2025")).unwrap();
+        assert_eq!(date_dir_for(&base, date), format!("{base}01-02-2025"));
+    }
+
+    // ========================================================================
+    // Sort Order Tests
+    // ========================================================================
+
+    /// Create photos whose mtimes are `age` hours in the past
+    fn photos_with_ages(root: &std::path::Path, files: &[(&str, u64)]) {
+        for (file, age) in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(&path).unwrap();
+            let modified = SystemTime::now() - Duration::from_hours(*age);
+            File::open(&path)
+                .unwrap()
+                .set_times(fs::FileTimes::new().set_modified(modified))
+                .unwrap();
+        }
+    }
+
+    fn sorted_names(root: &std::path::Path, order: SortOrder) -> Vec<String> {
+        find_photos_in_path_sorted(root.to_str(), order)
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_undated_directory_by_mtime() {
+        let temp_dir = TempDir::new().unwrap();
+        photos_with_ages(
+            temp_dir.path(),
+            &[("alpine.jpg", 30), ("beach.jpg", 2), ("canyon.jpg", 10)],
+        );
+
+        let newest_first = ["beach.jpg", "canyon.jpg", "alpine.jpg"];
+        assert_eq!(sorted_names(temp_dir.path(), SortOrder::Date), newest_first);
+        assert_eq!(
+            sorted_names(temp_dir.path(), SortOrder::Mtime),
+            newest_first
+        );
+        assert_eq!(
+            sorted_names(temp_dir.path(), SortOrder::Name),
+            ["alpine.jpg", "beach.jpg", "canyon.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_sort_date_dirs_before_mtime_fallback() {
+        let temp_dir = TempDir::new().unwrap();
+        photos_with_ages(
+            temp_dir.path(),
+            &[
+                ("2025-01-01/old_dir_new_file.jpg", 1),
+                ("2025-03-01/new_dir_old_file.jpg", 500),
+                ("misc/loose.jpg", 5),
+            ],
+        );
+
+        assert_eq!(
+            sorted_names(temp_dir.path(), SortOrder::Date),
+            ["new_dir_old_file.jpg", "old_dir_new_file.jpg", "loose.jpg"]
+        );
+        assert_eq!(
+            sorted_names(temp_dir.path(), SortOrder::Mtime),
+            ["old_dir_new_file.jpg", "loose.jpg", "new_dir_old_file.jpg"]
+        );
+        assert_eq!(
+            find_photos_in_path(temp_dir.path().to_str()).unwrap(),
+            find_photos_in_path_sorted(temp_dir.path().to_str(), SortOrder::Date).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_sort_random_is_a_permutation() {
+        let mut photos: Vec<PathBuf> = (0..50)
+            .map(|i| PathBuf::from(format!("/photos/{i:02}.jpg")))
+            .collect();
+        let original = photos.clone();
+
+        sort_photos(&mut photos, SortOrder::Random);
+        let mut restored = photos.clone();
+        restored.sort();
+        assert_eq!(restored, original);
+    }
+
+    // ========================================================================
+    // Image Validation Tests
+    // ========================================================================
+
+    fn encoded(format: image::ImageFormat) -> Vec<u8> {
+        let img = image::RgbImage::from_pixel(64, 48, image::Rgb([200, 120, 40]));
+        let mut bytes = io::Cursor::new(Vec::new());
+        img.write_to(&mut bytes, format).unwrap();
+        bytes.into_inner()
+    }
+
+    fn validate_bytes(name: &str, bytes: &[u8]) -> Result<(), PhotoError> {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(name);
+        fs::write(&path, bytes).unwrap();
+        validate_image(&path)
+    }
+
+    #[test]
+    fn test_validate_image_accepts_complete_images() {
+        for format in [
+            image::ImageFormat::Jpeg,
+            image::ImageFormat::Png,
+            image::ImageFormat::Gif,
+            image::ImageFormat::Tiff,
+        ] {
+            let bytes = encoded(format);
+            // Named .part like an in-flight download: detection must not rely on the extension
+            assert!(
+                validate_bytes("photo.jpg.part", &bytes).is_ok(),
+                "{format:?} should validate"
+            );
+        }
+        assert!(validate_bytes("photo.jxl", JXL_64X48).is_ok());
+    }
+
+    #[test]
+    fn test_validate_image_rejects_garbage_and_truncation() {
+        let html = b"<!DOCTYPE html><html><body>Access Denied</body></html>";
+        let jpeg = encoded(image::ImageFormat::Jpeg);
+        let png = encoded(image::ImageFormat::Png);
+
+        let cases: [(&str, &[u8]); 5] = [
+            ("html", html),
+            ("empty", b""),
+            ("random", &[0x13, 0x37, 0xde, 0xad, 0xbe, 0xef, 0x00, 0x42]),
+            ("truncated jpeg", &jpeg[..jpeg.len() / 2]),
+            ("truncated png", &png[..png.len() - 12]),
+        ];
+        for (label, bytes) in cases {
+            assert!(
+                matches!(
+                    validate_bytes("photo.jpg", bytes),
+                    Err(PhotoError::CorruptImage(_))
+                ),
+                "{label} should be rejected"
+            );
+        }
+    }
+
+    // ========================================================================
+    // WebP and AVIF Tests
+    // ========================================================================
+
+    /// Minimal AVIF header: `ftyp` with AVIF brands, then a `meta` box holding `ispe`
+    fn avif_header(width: u32, height: u32) -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 28];
+        data.extend_from_slice(b"ftypavif\0\0\0\0avifmif1miaf");
+        data.extend_from_slice(&[0, 0, 0, 28]);
+        data.extend_from_slice(b"meta\0\0\0\0");
+        data.extend_from_slice(&[0, 0, 0, 20]);
+        data.extend_from_slice(b"ispe\0\0\0\0");
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_webp_and_avif_content_types() {
+        assert_eq!(
+            get_extension_from_content_type("image/webp").unwrap(),
+            "webp"
+        );
+        assert_eq!(
+            get_extension_from_content_type("image/avif").unwrap(),
+            "avif"
+        );
+        assert_eq!(
+            get_extension_from_content_type("image/webp; charset=binary").unwrap(),
+            "webp"
+        );
+    }
+
+    #[test]
+    fn test_webp_and_avif_are_discovered_and_deduplicated() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+        fs::write(temp_dir.path().join("Reef.webp"), b"riff").unwrap();
+        fs::write(temp_dir.path().join("Dunes.AVIF"), b"ftyp").unwrap();
+
+        assert_eq!(
+            find_downloaded_file(dir, "Reef"),
+            Some(temp_dir.path().join("Reef.webp"))
+        );
+        let photos = find_photos_in_path_sorted(Some(dir), SortOrder::Name).unwrap();
+        assert_eq!(
+            photos,
+            [
+                temp_dir.path().join("Dunes.AVIF"),
+                temp_dir.path().join("Reef.webp")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_avif_dimensions() {
+        assert_eq!(
+            parse_avif_dimensions(&avif_header(4000, 2667)),
+            Some((4000, 2667))
+        );
+        assert_eq!(parse_avif_dimensions(&avif_header(0, 10)), None);
+
+        // HEIC shares the container but not the brand
+        let mut heic = avif_header(4000, 2667);
+        heic[8..12].copy_from_slice(b"heic");
+        heic[16..20].copy_from_slice(b"heic");
+        heic[20..24].copy_from_slice(b"mif1");
+        heic[24..28].copy_from_slice(b"miaf");
+        assert_eq!(parse_avif_dimensions(&heic), None);
+    }
+
+    #[test]
+    fn test_validate_webp_and_avif() {
+        let webp = encoded(image::ImageFormat::WebP);
+        assert!(validate_bytes("photo.webp.part", &webp).is_ok());
+        assert!(matches!(
+            validate_bytes("photo.webp", &webp[..webp.len() - 10]),
+            Err(PhotoError::CorruptImage(_))
+        ));
+        assert!(validate_bytes("photo.avif.part", &avif_header(64, 48)).is_ok());
+
+        let temp_dir = TempDir::new().unwrap();
+        let avif = temp_dir.path().join("photo.avif");
+        fs::write(&avif, avif_header(1920, 1080)).unwrap();
+        assert_eq!(probe_dimensions(&avif).unwrap(), (1920, 1080));
+    }
+
+    #[test]
+    fn test_convert_webp_to_jpeg() {
+        let temp_dir = TempDir::new().unwrap();
+        let webp = temp_dir.path().join("Reef.webp");
+        fs::write(&webp, encoded(image::ImageFormat::WebP)).unwrap();
+
+        let converted = convert_image(&webp, ConvertFormat::Jpeg, 85).unwrap();
+
+        assert_eq!(converted, temp_dir.path().join("Reef.jpg"));
+        assert!(!webp.exists());
+        let bytes = fs::read(&converted).unwrap();
+        assert_eq!(
+            image::guess_format(&bytes).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        let decoded = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (64, 48));
+    }
+
+    // ========================================================================
+    // Meta Tag Parsing Tests
+    // ========================================================================
+
+    #[test]
+    fn test_parse_photo_info_meta_layouts() {
+        let cases = [
+            r#"<meta property="og:image" content="https://example.com/a/Reef.jpg"><meta property="og:title" content="Coral Reef at Dawn">"#,
+            r#"<meta content="https://example.com/a/Reef.jpg" property="og:image"/><meta content="Coral Reef at Dawn" property="og:title"/>"#,
+            "<meta property='og:image' content='https://example.com/a/Reef.jpg'><meta property='og:title' content='Coral Reef at Dawn'>",
+            "<meta\n  property = \"og:image\"\n  content=\"https://example.com/a/Reef.jpg\"\n>\n<meta name=\"x\" property=\"og:title\"\tcontent=\"Coral Reef at Dawn\">",
+            r#"<meta property="og:title" content="  Coral Reef at Dawn "><meta property="og:description" content="nope"><meta property="og:image" content="https://example.com/a/Reef.jpg">"#,
+        ];
+
+        for head in cases {
+            let html = format!("<html><head>{head}</head><body></body></html>");
+            let info = parse_photo_info_from_html(&html).unwrap();
+            assert_eq!(info.image_url, "https://example.com/a/Reef.jpg", "{head}");
+            assert_eq!(info.title, "Coral Reef at Dawn", "{head}");
+        }
+    }
+
+    #[test]
+    fn test_parse_photo_info_decodes_entities() {
+        let html = r#"<meta property="og:title" content="Lions &amp; Tigers &#8212; &quot;Kenya&quot;"><meta property="og:image" content="https://example.com/p.jpg?w=1&amp;h=2">"#;
+
+        let info = parse_photo_info_from_html(html).unwrap();
+
+        assert_eq!(info.title, "Lions & Tigers \u{2014} \"Kenya\"");
+        assert_eq!(info.image_url, "https://example.com/p.jpg?w=1&h=2");
+    }
+
+    #[test]
+    fn test_parse_photo_info_falls_back_to_filename() {
+        for title_tag in [
+            "",
+            r#"<meta property="og:title" content="Test">"#,
+            r#"<meta property="og:title" content="   ">"#,
+            r#"<meta property="og:title" content="abc">"#,
+        ] {
+            let html = format!(
+                r#"{title_tag}<meta property="og:image" content="https://i.natgeofe.com/n/abc/NationalGeographic_433254.jpg">"#
+            );
+            let info = parse_photo_info_from_html(&html).unwrap();
+            assert_eq!(info.title, "NationalGeographic_433254", "{title_tag}");
+        }
+    }
+
+    #[test]
+    fn test_parse_photo_info_requires_image() {
+        let html = r#"<meta name="og:image" content="https://example.com/a.jpg"><meta property="og:image" content="">"#;
+
+        assert!(matches!(
+            parse_photo_info_from_html(html),
+            Err(PhotoError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_photo_info_caption_and_credit() {
+        let html = r#"<html><head>
+<meta property="og:image" content="https://i.natgeofe.com/n/abc/fox.jpg">
+<meta property="og:title" content="Red Fox in the Snow">
+<meta property="og:description" content="A red fox hunts for voles in Yellowstone&#39;s Lamar Valley.">
+<script>window['__natgeo__']={"page":{"content":{"image":{"credit":null,"caption":{"credit":"Photograph by Jane Doe, National Geographic"}}}}};</script>
+</head><body></body></html>"#;
+
+        let info = parse_photo_info_from_html(html).unwrap();
+
+        assert_eq!(
+            info.caption.as_deref(),
+            Some("A red fox hunts for voles in Yellowstone's Lamar Valley.")
+        );
+        assert_eq!(
+            info.photographer.as_deref(),
+            Some("Photograph by Jane Doe, National Geographic")
+        );
+    }
+
+    #[test]
+    fn test_parse_photo_info_prefers_photographer_field() {
+        let html = r#"<meta property="og:image" content="https://example.com/a.jpg">
+<script type="application/json">{"credit": "Getty Images", "photographer" : "Jane \"JD\" Doe"}</script>"#;
+
+        let info = parse_photo_info_from_html(html).unwrap();
+
+        assert_eq!(info.photographer.as_deref(), Some("Jane \"JD\" Doe"));
+        assert_eq!(info.caption, None);
+    }
+
+    #[test]
+    fn test_parse_photo_info_without_caption_or_credit() {
+        let html = r#"<meta property="og:image" content="https://example.com/a.jpg">
+<meta property="og:description" content="  ">
+<script>{"credit": ""}</script>
+<p>"credit": "not in a script"</p>"#;
+
+        let info = parse_photo_info_from_html(html).unwrap();
+
+        assert_eq!(info.caption, None);
+        assert_eq!(info.photographer, None);
+    }
+
+    // ========================================================================
+    // Photo Metadata Tests
+    // ========================================================================
+
+    #[test]
+    fn test_photo_metadata_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let photo = temp_dir.path().join("Red_Fox.jpg");
+        fs::write(&photo, [0u8; 1234]).unwrap();
+        let info = PhotoInfo {
+            image_url: "https://example.com/fox.jpg".to_string(),
+            title: "Red Fox".to_string(),
+            caption: Some("A fox in the snow".to_string()),
+            photographer: Some("Jane Doe".to_string()),
+        };
+
+        let metadata = PhotoMetadata::for_download(&info, &photo).unwrap();
+        let sidecar = metadata_path_for(&photo);
+        write_metadata(&sidecar, &metadata).unwrap();
+
+        assert_eq!(sidecar, temp_dir.path().join("Red_Fox.json"));
+        assert_eq!(metadata.file_size, 1234);
+        assert_eq!(metadata.content_type, "image/jpeg");
+        assert!(chrono::DateTime::parse_from_rfc3339(&metadata.downloaded_at).is_ok());
+        assert_eq!(read_metadata(&sidecar).unwrap(), metadata);
+    }
+
+    #[test]
+    fn test_photo_metadata_omits_missing_credit() {
+        let metadata = PhotoMetadata {
+            url: "https://example.com/a.png".to_string(),
+            title: "A".to_string(),
+            downloaded_at: "2026-02-01T02:00:00+00:00".to_string(),
+            content_type: "image/png".to_string(),
+            file_size: 1,
+            caption: None,
+            photographer: None,
+            brightness: None,
+            width: None,
+            height: None,
+        };
+
+        let json = serde_json::to_string(&metadata).unwrap();
+
+        assert!(!json.contains("caption") && !json.contains("photographer"));
+        assert_eq!(
+            serde_json::from_str::<PhotoMetadata>(&json).unwrap(),
+            metadata
+        );
+    }
+
+    #[test]
+    fn test_record_photo_metadata_next_to_photo() {
+        let temp_dir = TempDir::new().unwrap();
+        let photo = temp_dir.path().join("2026-02-01").join("Reef.webp");
+        fs::create_dir_all(photo.parent().unwrap()).unwrap();
+        fs::write(&photo, b"RIFF").unwrap();
+        let info = PhotoInfo {
+            image_url: "https://example.com/reef.webp".to_string(),
+            title: "Reef".to_string(),
+            caption: None,
+            photographer: None,
+        };
+        let log_path = temp_dir.path().join("test.log");
+
+        record_photo_metadata(
+            &info,
+            &photo,
+            &DownloadOptions::default(),
+            log_path.to_str().unwrap(),
+        );
+
+        let metadata = read_metadata(&photo.with_extension("json")).unwrap();
+        assert_eq!(metadata.content_type, "image/webp");
+        assert!(!log_path.exists());
+        // The sidecar is never picked up as a wallpaper
+        let found = find_photos_in_path(temp_dir.path().to_str()).unwrap();
+        assert_eq!(found, vec![photo]);
+    }
+
+    // ========================================================================
+    // Embedded Metadata Tests
+    // ========================================================================
+
+    fn fox_info() -> PhotoInfo {
+        PhotoInfo {
+            image_url: "https://example.com/fox.jpg".to_string(),
+            title: "Red Fox & Snow".to_string(),
+            caption: Some("A red fox hunts for voles".to_string()),
+            photographer: Some("Jane Doe".to_string()),
+        }
+    }
+
+    fn read_exif_ascii(path: &Path, tag: exif::Tag) -> Option<String> {
+        let file = File::open(path).unwrap();
+        let exif = exif::Reader::new()
+            .read_from_container(&mut io::BufReader::new(file))
+            .unwrap();
+        let field = exif.get_field(tag, exif::In::PRIMARY)?;
+        match &field.value {
+            exif::Value::Ascii(values) => Some(String::from_utf8_lossy(&values[0]).into_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_embed_metadata_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("Red_Fox.jpg");
+        fs::write(&path, encoded(image::ImageFormat::Jpeg)).unwrap();
+        let pixels_before = image::open(&path).unwrap().to_rgb8();
+
+        embed_metadata(&path, &fox_info()).unwrap();
+
+        assert_eq!(
+            read_exif_ascii(&path, exif::Tag::ImageDescription).as_deref(),
+            Some("A red fox hunts for voles")
+        );
+        assert_eq!(
+            read_exif_ascii(&path, exif::Tag::Artist).as_deref(),
+            Some("Jane Doe")
+        );
+        let bytes = fs::read(&path).unwrap();
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.contains("http://ns.adobe.com/xap/1.0/"));
+        assert!(text.contains("Red Fox &amp; Snow"));
+        assert!(text.contains("<rdf:li>Jane Doe</rdf:li>"));
+
+        // The image itself is untouched and no temp copy is left behind
+        assert_eq!(image::open(&path).unwrap().to_rgb8(), pixels_before);
+        validate_image(&path).unwrap();
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_embed_metadata_replaces_previous_values() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("photo.jpg");
+        fs::write(&path, encoded(image::ImageFormat::Jpeg)).unwrap();
+        let mut info = fox_info();
+        info.caption = None;
+        embed_metadata(&path, &fox_info()).unwrap();
+
+        info.photographer = Some("John Roe".to_string());
+        embed_metadata(&path, &info).unwrap();
+
+        assert_eq!(
+            read_exif_ascii(&path, exif::Tag::ImageDescription).as_deref(),
+            Some("Red Fox & Snow")
+        );
+        assert_eq!(
+            read_exif_ascii(&path, exif::Tag::Artist).as_deref(),
+            Some("John Roe")
+        );
+        let bytes = fs::read(&path).unwrap();
+        let xmp_segments = bytes
+            .windows(XMP_APP1_PREFIX.len())
+            .filter(|window| *window == XMP_APP1_PREFIX)
+            .count();
+        assert_eq!(xmp_segments, 1);
+    }
+
+    #[test]
+    fn test_embed_metadata_leaves_bad_file_untouched() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("photo.jpg");
+        fs::write(&path, b"<html>not a photo</html>").unwrap();
+
+        assert!(matches!(
+            embed_metadata(&path, &fox_info()),
+            Err(PhotoError::CorruptImage(_))
+        ));
+        assert_eq!(fs::read(&path).unwrap(), b"<html>not a photo</html>");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_record_photo_metadata_embeds_only_when_asked() {
+        let temp_dir = TempDir::new().unwrap();
+        let plain = temp_dir.path().join("plain.jpg");
+        let tagged = temp_dir.path().join("tagged.jpg");
+        let original = encoded(image::ImageFormat::Jpeg);
+        fs::write(&plain, &original).unwrap();
+        fs::write(&tagged, &original).unwrap();
+        let log_path = temp_dir.path().join("test.log");
+        let log_path = log_path.to_str().unwrap();
+
+        record_photo_metadata(&fox_info(), &plain, &DownloadOptions::default(), log_path);
+        let options = DownloadOptions {
+            embed_metadata: true,
+            ..DownloadOptions::default()
+        };
+        record_photo_metadata(&fox_info(), &tagged, &options, log_path);
+
+        assert_eq!(fs::read(&plain).unwrap(), original);
+        assert_eq!(
+            read_exif_ascii(&tagged, exif::Tag::Artist).as_deref(),
+            Some("Jane Doe")
+        );
+        // The sidecar describes the file as it ends up on disk
+        let metadata = read_metadata(&metadata_path_for(&tagged)).unwrap();
+        assert_eq!(metadata.file_size, fs::metadata(&tagged).unwrap().len());
+    }
+
+    // ========================================================================
+    // HTTP Retry Tests
+    // ========================================================================
+
+    #[test]
+    fn test_is_retryable_status() {
+        for (code, retryable) in [
+            (200, false),
+            (301, false),
+            (400, false),
+            (403, false),
+            (404, false),
+            (429, true),
+            (500, true),
+            (502, true),
+            (503, true),
+        ] {
+            let status = reqwest::StatusCode::from_u16(code).unwrap();
+            assert_eq!(is_retryable_status(status), retryable, "{code}");
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_mins(2)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let future = (chrono::Utc::now() + chrono::Duration::seconds(90)).to_rfc2822();
+        let wait = parse_retry_after(&future).unwrap();
+        assert!(wait > Duration::from_secs(80) && wait <= Duration::from_secs(90));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_backoff_delay_doubles_with_jitter() {
+        let base = Duration::from_millis(100);
+        for (retry, min_ms) in [(1, 100), (2, 200), (3, 400)] {
+            let delay = backoff_delay(base, retry);
+            let min = Duration::from_millis(min_ms);
+            assert!(delay >= min && delay <= min * 3 / 2, "{retry}: {delay:?}");
+        }
+        assert_eq!(backoff_delay(base, 30), RETRY_MAX_DELAY);
+    }
+
+    // ========================================================================
+    // Proxy Tests
+    // ========================================================================
+
+    fn env_only(env: Vec<(&'static str, &'static str)>) -> FakeSystem {
+        FakeSystem {
+            env,
+            ..FakeSystem::default()
+        }
+    }
+
+    #[test]
+    fn test_proxy_config_from_environment() {
+        let probe = env_only(vec![
+            ("HTTP_PROXY", "http://plain.corp:3128"),
+            ("https_proxy", "http://secure.corp:3128"),
+            ("NO_PROXY", "localhost,.internal"),
+        ]);
+
+        assert_eq!(
+            ProxyConfig::resolve(None, &probe),
+            ProxyConfig {
+                http: Some("http://plain.corp:3128".to_string()),
+                https: Some("http://secure.corp:3128".to_string()),
+                no_proxy: Some("localhost,.internal".to_string()),
+            }
+        );
+        assert_eq!(
+            ProxyConfig::resolve(None, &env_only(vec![("HTTPS_PROXY", " ")])),
+            ProxyConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_explicit_proxy_overrides_environment() {
+        let probe = env_only(vec![
+            ("HTTP_PROXY", "http://plain.corp:3128"),
+            ("NO_PROXY", "localhost"),
+        ]);
+
+        let config = ProxyConfig::resolve(Some("http://cli.proxy:8080"), &probe);
+
+        assert_eq!(config.http.as_deref(), Some("http://cli.proxy:8080"));
+        assert_eq!(config.https.as_deref(), Some("http://cli.proxy:8080"));
+        assert_eq!(config.no_proxy.as_deref(), Some("localhost"));
+    }
+
+    #[test]
+    fn test_proxy_config_applies_to_client_builder() {
+        let config = ProxyConfig::resolve(Some("http://cli.proxy:8080"), &env_only(vec![]));
+        assert!(config.apply(Client::builder()).unwrap().build().is_ok());
+        assert!(ProxyConfig::default()
+            .apply(Client::builder())
+            .unwrap()
+            .build()
+            .is_ok());
+
+        let bad = ProxyConfig::resolve(Some("http://bad host:80"), &env_only(vec![]));
+        match bad.apply(Client::builder()) {
+            Err(PhotoError::InvalidProxy(message)) => {
+                assert!(message.starts_with("http://bad host:80"));
+            }
+            other => panic!("expected InvalidProxy, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    // ========================================================================
+    // Image Variant Tests
+    // ========================================================================
+
+    fn variant(url: &str, width: Option<u32>) -> ImageVariant {
+        ImageVariant {
+            url: url.to_string(),
+            width,
+        }
+    }
+
+    #[test]
+    fn test_parse_srcset() {
+        let srcset = "https://x.test/a.jpg?w=640&h=427 640w,\n  https://x.test/a.jpg?w=1280 1280w, https://x.test/a.jpg?w=300 2x, https://x.test/b.jpg";
+
+        assert_eq!(
+            parse_srcset(srcset),
+            [
+                variant("https://x.test/a.jpg?w=640&h=427", Some(640)),
+                variant("https://x.test/a.jpg?w=1280", Some(1280)),
+                variant("https://x.test/a.jpg?w=300", Some(300)),
+                variant("https://x.test/b.jpg", None),
+            ]
+        );
+        assert!(parse_srcset("").is_empty());
+    }
+
+    #[test]
+    fn test_widest_variant() {
+        let mut variants = vec![
+            variant("https://x.test/a_16x9.jpg?w=2048", Some(2048)),
+            variant("https://x.test/a_16x9.jpg", None),
+            variant("https://x.test/a.jpg?w=2048", Some(2048)),
+            variant("https://x.test/a.jpg?w=640", Some(640)),
+        ];
+        assert_eq!(
+            widest_variant(&variants).unwrap().url,
+            "https://x.test/a.jpg?w=2048"
+        );
+
+        // The unsized original beats every resized copy
+        variants.push(variant("https://x.test/a.jpg", None));
+        assert_eq!(
+            widest_variant(&variants).unwrap().url,
+            "https://x.test/a.jpg"
+        );
+
+        let undeclared = [
+            variant("https://x.test/a.jpg", None),
+            variant("https://x.test/b.jpg", None),
+        ];
+        assert_eq!(
+            widest_variant(&undeclared).unwrap().url,
+            "https://x.test/a.jpg"
+        );
+        assert!(widest_variant(&[]).is_none());
+    }
+
+    #[test]
+    fn test_photo_info_picks_widest_srcset_variant() {
+        let html = r#"
+            <meta property="og:image" content="https://i.natgeofe.com/n/u1/reef_2x1.jpg?w=1200">
+            <meta property="og:title" content="Coral Reef at Dawn">
+            <picture>
+              <source srcset="https://i.natgeofe.com/n/u1/reef.jpg?w=768&amp;h=512 768w, https://i.natgeofe.com/n/u1/reef.jpg?w=3072&amp;h=2048 3072w">
+              <img src="https://i.natgeofe.com/n/u1/reef.jpg?w=1536&amp;h=1024" srcset="https://i.natgeofe.com/n/u2/other.jpg?w=4096 4096w">
+            </picture>
+        "#;
+
+        let info = parse_photo_info_from_html(html).unwrap();
+
+        assert_eq!(
+            info.image_url,
+            "https://i.natgeofe.com/n/u1/reef.jpg?w=3072&h=2048"
+        );
+    }
+
+    #[test]
+    fn test_extract_natgeo_image_urls_reads_srcset_widths() {
+        let html = r#"
+            <img src="https://i.natgeofe.com/n/u1/01-best-pod.jpg?w=640"
+                 srcset="https://i.natgeofe.com/n/u1/01-best-pod.jpg?w=320 320w, https://i.natgeofe.com/n/u1/01-best-pod.jpg?v=2 2560w">
+            <img src="https://i.natgeofe.com/n/u2/02-best-pod.jpg">
+        "#;
+
+        assert_eq!(
+            extract_natgeo_image_urls(html),
+            [
+                "https://i.natgeofe.com/n/u1/01-best-pod.jpg?v=2",
+                "https://i.natgeofe.com/n/u2/02-best-pod.jpg",
+            ]
+        );
+    }
+
+    // ========================================================================
+    // Structured Data Tests
+    // ========================================================================
+
+    #[test]
+    fn test_structured_data_preferred_over_meta_tags() {
+        let html = r#"
+            <meta property="og:image" content="https://example.com/small.jpg">
+            <meta property="og:title" content="Coral Reef at Dawn">
+            <meta property="og:description" content="Meta caption">
+            <script type="application/ld+json">[
+                {"@type": "Organization", "name": "National Geographic"},
+                {"@type": "ImageObject", "contentUrl": "https://example.com/full.jpg",
+                 "caption": " JSON caption ", "creditText": "Photograph by A. Diver"}
+            ]</script>
+        "#;
+
+        let info = parse_photo_info_from_html(html).unwrap();
+
+        assert_eq!(info.image_url, "https://example.com/full.jpg");
+        assert_eq!(info.title, "Coral Reef at Dawn");
+        assert_eq!(info.caption.as_deref(), Some("JSON caption"));
+        assert_eq!(info.photographer.as_deref(), Some("Photograph by A. Diver"));
+    }
+
+    #[test]
+    fn test_structured_data_tolerates_unexpected_shapes() {
+        // Non-string caption and credit are ignored in favor of the meta tags
+        let html = r#"
+            <meta property="og:image" content="https://example.com/small.jpg">
+            <meta property="og:description" content="Meta caption">
+            <script type="application/ld+json">{"image": {"contentUrl": "https://example.com/full.jpg",
+                "caption": {"@value": "nested"}, "creditText": ["a", "b"]}}</script>
+            <script>{"credit": "Embedded credit"}</script>
+        "#;
+
+        let info = parse_photo_info_from_html(html).unwrap();
+
+        assert_eq!(info.image_url, "https://example.com/full.jpg");
+        assert_eq!(info.caption.as_deref(), Some("Meta caption"));
+        assert_eq!(info.photographer.as_deref(), Some("Embedded credit"));
+    }
+
+    #[test]
+    fn test_structured_data_errors_only_when_no_script_has_the_photo() {
+        let broken = r#"<script type="application/ld+json">{"contentUrl": </script>"#;
+        let document = Html::parse_document(broken);
+        assert!(matches!(
+            structured_photo(&document),
+            Err(PhotoError::Json(_))
+        ));
+
+        let recovered = format!(
+            r#"{}<script id="__NEXT_DATA__">{{"props": {{"contentUrl": "https://example.com/a.jpg"}}}}</script>"#,
+            broken
+        );
+        let document = Html::parse_document(&recovered);
+        let photo = structured_photo(&document).unwrap().unwrap();
+        assert_eq!(
+            photo.content_url.as_deref(),
+            Some("https://example.com/a.jpg")
+        );
+
+        let document = Html::parse_document(
+            r#"<script type="application/ld+json">{"@type": "WebPage"}</script>"#,
+        );
+        assert!(structured_photo(&document).unwrap().is_none());
+    }
+
+    // ========================================================================
+    // Collection Page Order Tests
+    // ========================================================================
+
+    #[test]
+    fn test_collection_figures_follow_page_order() {
+        let html = r#"
+            <meta property="og:image" content="https://i.natgeofe.com/n/u3/03-best-pod.jpg">
+            <figure><img src="https://i.natgeofe.com/n/u1/01-best-pod.jpg?w=800">
+                <figcaption>  First
+                    photo </figcaption></figure>
+            <figure><picture><source srcset="https://i.natgeofe.com/n/u2/02-best-pod.jpg?w=640 640w"></picture></figure>
+            <figure><img src="/local/logo.png"><figcaption>Not a photo</figcaption></figure>
+        "#;
+
+        assert_eq!(
+            collection_figures(html),
+            [
+                (
+                    "https://i.natgeofe.com/n/u1/01-best-pod.jpg".to_string(),
+                    Some("First photo".to_string())
+                ),
+                (
+                    "https://i.natgeofe.com/n/u2/02-best-pod.jpg".to_string(),
+                    None
+                ),
+            ]
+        );
+        let ordered: Vec<(String, Option<String>)> = order_by_figures(
+            extract_natgeo_image_variants(html),
+            &collection_figures(html),
+        )
+        .into_iter()
+        .map(|(image, caption)| (image.url, caption))
+        .collect();
+        assert_eq!(
+            ordered,
+            [
+                (
+                    "https://i.natgeofe.com/n/u1/01-best-pod.jpg?w=800".to_string(),
+                    Some("First photo".to_string())
+                ),
+                (
+                    "https://i.natgeofe.com/n/u2/02-best-pod.jpg?w=640".to_string(),
+                    None
+                ),
+                (
+                    "https://i.natgeofe.com/n/u3/03-best-pod.jpg".to_string(),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collection_photo_stem() {
+        let photo = |caption: Option<&str>| PhotoInfo {
+            image_url: "https://example.com/07-best-pod.jpg".to_string(),
+            title: "07-best-pod".to_string(),
+            caption: caption.map(String::from),
+            photographer: None,
+        };
+
+        assert_eq!(
+            collection_photo_stem(6, 30, &photo(Some("A fox in the snow."))),
+            "07-A_fox_in_the_snow"
+        );
+        assert_eq!(
+            collection_photo_stem(6, 120, &photo(Some("A fox"))),
+            "007-A_fox"
+        );
+        assert_eq!(collection_photo_stem(6, 30, &photo(None)), "07-best-pod");
+    }
+
+    // ========================================================================
+    // Collection Filter Tests
+    // ========================================================================
+
+    #[test]
+    fn test_collection_filter_pattern_and_none() {
+        let aurora = CollectionFilter::Pattern("Aurora_Gallery".to_string());
+        assert!(aurora.matches("04-aurora-gallery-2024"));
+        assert!(!aurora.matches("04-best-pod-2024"));
+
+        assert!(CollectionFilter::None.matches("GettyImages-109899052"));
+        assert_eq!(
+            CollectionFilter::default(),
+            CollectionFilter::Pattern("best-pod".to_string())
+        );
+    }
+
+    #[test]
+    fn test_collection_filter_error_suggests_no_filter() {
+        let html = r#"<img src="https://i.natgeofe.com/n/u1/wildlife-1.jpg">
+            <img src="https://i.natgeofe.com/n/u2/wildlife-2.jpg">"#;
+
+        match parse_collection_page(
+            "https://example.com/wildlife",
+            html,
+            &CollectionFilter::default(),
+            &|_| None,
+        ) {
+            Err(PhotoError::NoPhotos(message)) => {
+                assert!(message.contains("'best-pod'"), "{message}");
+                assert!(message.contains("2 other image(s)"), "{message}");
+                assert!(message.contains("--no-filter"), "{message}");
+            }
+            other => panic!("expected NoPhotos, got {other:?}"),
+        }
+
+        let all = parse_collection_page(
+            "https://example.com/wildlife",
+            html,
+            &CollectionFilter::None,
+            &|_| None,
+        )
+        .unwrap();
+        assert_eq!(all.photos.len(), 2);
+    }
+
+    // ========================================================================
+    // Duplicate Photo Tests
+    // ========================================================================
+
+    #[test]
+    fn test_group_urls_by_uuid() {
+        let urls: Vec<String> = [
+            "https://i.natgeofe.com/n/aaa/01-best-pod.jpg",
+            "https://i.natgeofe.com/n/bbb/02-best-pod.jpg",
+            "https://i.natgeofe.com/n/aaa/best-pod-retina-2x.jpg?w=2400",
+            "https://example.com/logo.png",
+            "https://i.natgeofe.com/n/aaa/01_best_pod.jpg",
+        ]
+        .map(String::from)
+        .to_vec();
+
+        assert_eq!(
+            group_urls_by_uuid(&urls),
+            [
+                vec![urls[0].clone(), urls[2].clone(), urls[4].clone()],
+                vec![urls[1].clone()],
+                vec![urls[3].clone()],
+            ]
+        );
+        assert!(group_urls_by_uuid(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_largest_of_group_prefers_declared_width() {
+        let group = vec![
+            (
+                ImageVariant::from_url("https://i.natgeofe.com/n/aaa/a.jpg"),
+                None,
+            ),
+            (
+                ImageVariant::from_url("https://i.natgeofe.com/n/aaa/a-2x.jpg?w=2400"),
+                None,
+            ),
+            (
+                ImageVariant::from_url("https://i.natgeofe.com/n/aaa/a.jpg?w=1200"),
+                Some("Caption".to_string()),
+            ),
+        ];
+
+        let (image, caption) = largest_of_group(group, &|_| panic!("no HEAD needed")).unwrap();
+
+        assert_eq!(image.url, "https://i.natgeofe.com/n/aaa/a-2x.jpg?w=2400");
+        assert_eq!(caption.as_deref(), Some("Caption"));
+    }
+
+    #[test]
+    fn test_largest_of_group_falls_back_to_content_length() {
+        let group = || {
+            vec![
+                (
+                    ImageVariant::from_url("https://x.test/n/aaa/small.jpg"),
+                    None,
+                ),
+                (
+                    ImageVariant::from_url("https://x.test/n/aaa/large.jpg"),
+                    None,
+                ),
+                (
+                    ImageVariant::from_url("https://x.test/n/aaa/unknown.jpg"),
+                    None,
+                ),
+            ]
+        };
+        let sizes = |url: &str| match url.rsplit('/').next() {
+            Some("small.jpg") => Some(80_000),
+            Some("large.jpg") => Some(900_000),
+            _ => None,
+        };
+
+        let (image, _) = largest_of_group(group(), &sizes).unwrap();
+        assert_eq!(image.url, "https://x.test/n/aaa/large.jpg");
+
+        // Without any sizes the first copy is kept
+        let (image, _) = largest_of_group(group(), &|_| None).unwrap();
+        assert_eq!(image.url, "https://x.test/n/aaa/small.jpg");
+    }
+
+    // ========================================================================
+    // Duplicate Detection Tests
+    // ========================================================================
+
+    /// A photo root with a manifest kept outside it
+    fn manifest_in(temp_dir: &TempDir) -> ManifestConfig {
+        ManifestConfig {
+            path: temp_dir.path().join("data").join(MANIFEST_FILE),
+            root: temp_dir.path().join("photos"),
+        }
+    }
+
+    #[test]
+    fn test_hash_file_is_sha256_hex() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("abc.jpg");
+        fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            hash_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_manifest_created_on_first_run() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = manifest_in(&temp_dir);
+        assert_eq!(
+            PhotoManifest::load(&config.path).unwrap(),
+            PhotoManifest::default()
+        );
+
+        let day = config.root.join("2026-03-01");
+        fs::create_dir_all(&day).unwrap();
+        fs::write(day.join("Fox.jpg"), "fox").unwrap();
+        fs::write(day.join("Owl.jpg"), "owl").unwrap();
+
+        let report = dedup_photos(&config, DuplicateAction::Report).unwrap();
+        assert_eq!(report.scanned, 2);
+        assert!(report.duplicates.is_empty());
+
+        let manifest = PhotoManifest::load(&config.path).unwrap();
+        assert_eq!(manifest.photos.len(), 2);
+        let fox = hash_file(&day.join("Fox.jpg")).unwrap();
+        assert_eq!(manifest.photos[&fox], PathBuf::from("2026-03-01/Fox.jpg"));
+        assert_eq!(manifest.find(&fox, &config.root), Some(day.join("Fox.jpg")));
+    }
+
+    #[test]
+    fn test_manifest_ignores_recorded_photo_that_was_deleted() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = manifest_in(&temp_dir);
+        let mut manifest = PhotoManifest::default();
+        manifest.record(
+            "abc".to_string(),
+            &config.root.join("2026-03-01/Gone.jpg"),
+            &config.root,
+        );
+        assert_eq!(manifest.find("abc", &config.root), None);
+    }
+
+    #[test]
+    fn test_dedup_reports_copies_across_date_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = manifest_in(&temp_dir);
+        let first = config.root.join("2025-12-24");
+        let rerun = config.root.join("2026-12-24");
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&rerun).unwrap();
+        fs::write(first.join("Snow.jpg"), "same bytes").unwrap();
+        fs::write(rerun.join("Snow Again.jpg"), "same bytes").unwrap();
+        fs::write(rerun.join("Different.jpg"), "other bytes").unwrap();
+
+        let report = dedup_photos(&config, DuplicateAction::Report).unwrap();
+        assert_eq!(
+            report.duplicates,
+            vec![Duplicate {
+                path: rerun.join("Snow Again.jpg"),
+                original: first.join("Snow.jpg"),
+            }]
+        );
+        assert_eq!(report.reclaimed_bytes, 0);
+        assert!(rerun.join("Snow Again.jpg").exists());
+    }
+
+    #[test]
+    fn test_dedup_prefers_photo_already_in_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = manifest_in(&temp_dir);
+        let early = config.root.join("2026-01-01");
+        let late = config.root.join("2026-01-02");
+        fs::create_dir_all(&early).unwrap();
+        fs::create_dir_all(&late).unwrap();
+        fs::write(early.join("Copy.jpg"), "same bytes").unwrap();
+        fs::write(late.join("Original.jpg"), "same bytes").unwrap();
+
+        let mut manifest = PhotoManifest::default();
+        let hash = hash_file(&late.join("Original.jpg")).unwrap();
+        manifest.record(hash, &late.join("Original.jpg"), &config.root);
+        manifest.save(&config.path).unwrap();
+
+        let report = dedup_photos(&config, DuplicateAction::Report).unwrap();
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(report.duplicates[0].path, early.join("Copy.jpg"));
+        assert_eq!(report.duplicates[0].original, late.join("Original.jpg"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_dedup_hard_links_duplicates_once() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = manifest_in(&temp_dir);
+        let day = config.root.join("2026-01-01");
+        fs::create_dir_all(&day).unwrap();
+        fs::write(day.join("A.jpg"), "same bytes").unwrap();
+        fs::write(day.join("B.jpg"), "same bytes").unwrap();
+
+        let report = dedup_photos(&config, DuplicateAction::HardLink).unwrap();
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(report.reclaimed_bytes, 10);
+        assert_eq!(
+            fs::metadata(day.join("A.jpg")).unwrap().ino(),
+            fs::metadata(day.join("B.jpg")).unwrap().ino()
+        );
+        assert_eq!(fs::read_dir(&day).unwrap().count(), 2);
+
+        // Linked copies are the same file and aren't reported again
+        let report = dedup_photos(&config, DuplicateAction::HardLink).unwrap();
+        assert!(report.duplicates.is_empty());
+    }
+
+    #[test]
+    fn test_dedup_remove_deletes_duplicate_and_sidecars() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = manifest_in(&temp_dir);
+        let first = config.root.join("2025-12-24");
+        let rerun = config.root.join("2026-12-24");
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&rerun).unwrap();
+        fs::write(first.join("Snow.jpg"), "same bytes").unwrap();
+        fs::write(first.join("Snow.log"), "log").unwrap();
+        fs::write(rerun.join("Snow Again.jpg"), "same bytes").unwrap();
+        fs::write(rerun.join("Snow Again.log"), "log").unwrap();
+        fs::write(rerun.join("Snow Again.json"), "{}").unwrap();
+
+        let report = dedup_photos(&config, DuplicateAction::Remove).unwrap();
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(fs::read_dir(&rerun).unwrap().count(), 0);
+        assert!(first.join("Snow.jpg").exists());
+        assert!(first.join("Snow.log").exists());
+    }
+
+    // ========================================================================
+    // Photo Listing Tests
+    // ========================================================================
+
+    /// Two date directories and one collection under `photos/`
+    fn listing_layout(temp_dir: &TempDir) -> (PathBuf, PathBuf) {
+        let root = temp_dir.path().join("photos");
+        let collections = root.join("collections");
+        for dir in ["2026-02-01", "2026-02-03", "collections/best-of-2025"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(
+            root.join("2026-02-01/Fox.png"),
+            encoded(image::ImageFormat::Png),
+        )
+        .unwrap();
+        fs::write(root.join("2026-02-01/Fox.log"), "log").unwrap();
+        fs::write(
+            root.join("2026-02-03/Owl.jpg"),
+            encoded(image::ImageFormat::Jpeg),
+        )
+        .unwrap();
+        fs::write(
+            root.join("collections/best-of-2025/01-Reef.jpg"),
+            "not an image",
+        )
+        .unwrap();
+        (root, collections)
+    }
+
+    #[test]
+    fn test_list_photos_newest_first_without_collections() {
+        let temp_dir = TempDir::new().unwrap();
+        let (root, collections) = listing_layout(&temp_dir);
+
+        let entries: Vec<_> = list_photos(&root, &collections, &ListOptions::default())
+            .unwrap()
+            .collect();
+        let names: Vec<_> = entries
+            .iter()
+            .map(|e| (e.directory.as_str(), e.filename.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("2026-02-03", "Owl.jpg"), ("2026-02-01", "Fox.png")]
+        );
+
+        let fox = &entries[1];
+        assert_eq!(fox.path, root.join("2026-02-01/Fox.png"));
+        assert_eq!((fox.width, fox.height), (Some(64), Some(48)));
+        assert_eq!(fox.size, fs::metadata(&fox.path).unwrap().len());
+        assert_eq!(
+            fox.date,
+            chrono::NaiveDate::from_ymd_opt(2026, 2, 1).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_list_photos_with_collections_and_filters() {
+        let temp_dir = TempDir::new().unwrap();
+        let (root, collections) = listing_layout(&temp_dir);
+
+        let options = ListOptions {
+            include_collections: true,
+            ..ListOptions::default()
+        };
+        let entries: Vec<_> = list_photos(&root, &collections, &options)
+            .unwrap()
+            .collect();
+        assert_eq!(entries.len(), 3);
+        let reef = entries
+            .iter()
+            .find(|e| e.directory == "best-of-2025")
+            .unwrap();
+        assert_eq!((reef.width, reef.height), (None, None));
+
+        let options = ListOptions {
+            since: chrono::NaiveDate::from_ymd_opt(2026, 2, 2),
+            ..ListOptions::default()
+        };
+        let names: Vec<_> = list_photos(&root, &collections, &options)
+            .unwrap()
+            .map(|e| e.filename)
+            .collect();
+        assert_eq!(names, vec!["Owl.jpg"]);
+
+        let options = ListOptions {
+            limit: Some(1),
+            ..ListOptions::default()
+        };
+        assert_eq!(
+            list_photos(&root, &collections, &options).unwrap().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_list_photos_missing_root_is_empty() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("missing");
+        let entries = list_photos(&root, &root.join("collections"), &ListOptions::default());
+        assert_eq!(entries.unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_photo_entry_json_fields() {
+        let temp_dir = TempDir::new().unwrap();
+        let (root, _) = listing_layout(&temp_dir);
+        let entry = PhotoEntry::from_path(&root.join("2026-02-03/Owl.jpg")).unwrap();
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["directory"], "2026-02-03");
+        assert_eq!(json["filename"], "Owl.jpg");
+        assert_eq!(json["width"], 64);
+        assert_eq!(json["date"], "2026-02-03");
+    }
+
+    // ========================================================================
+    // Retention Tests
+    // ========================================================================
+
+    /// Date directories for 1-10 Feb 2026 (one 100-byte photo each, plus a legacy-named
+    /// 31 Jan) and a collection, with "today" being 10 Feb
+    fn retention_tree(temp_dir: &TempDir) -> RetentionPolicy {
+        let root = temp_dir.path().join("photos");
+        for day in 1..=10 {
+            let dir = root.join(format!("2026-02-{:02}", day));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("Photo.jpg"), [0u8; 100]).unwrap();
+        }
+        fs::create_dir_all(root.join("31-01-2026")).unwrap();
+        fs::write(root.join("31-01-2026/Old.jpg"), [0u8; 100]).unwrap();
+        fs::create_dir_all(root.join("collections/best-of-2025")).unwrap();
+        fs::write(
+            root.join("collections/best-of-2025/01-Reef.jpg"),
+            [0u8; 100],
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("wallpapers")).unwrap();
+
+        RetentionPolicy {
+            collection_root: root.join("collections"),
+            photo_root: root,
+            today: chrono::NaiveDate::from_ymd_opt(2026, 2, 10).unwrap(),
+            ..RetentionPolicy::new().unwrap()
+        }
+    }
+
+    fn removed_names(report: &CleanReport) -> Vec<String> {
+        report
+            .removed
+            .iter()
+            .map(|dir| dir.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_keep_days() {
+        let temp_dir = TempDir::new().unwrap();
+        let policy = RetentionPolicy {
+            keep_days: Some(7),
+            ..retention_tree(&temp_dir)
+        };
+
+        let report = clean_photos(&policy).unwrap();
+        assert_eq!(
+            removed_names(&report),
+            vec!["31-01-2026", "2026-02-01", "2026-02-02", "2026-02-03"]
+        );
+        assert_eq!(report.removed_photos, 4);
+        assert_eq!(report.freed_bytes, 400);
+        assert_eq!(report.remaining_bytes, 800);
+        assert!(!policy.photo_root.join("2026-02-03").exists());
+        assert!(policy.photo_root.join("2026-02-04").exists());
+        assert!(policy.collection_root.join("best-of-2025").exists());
+        assert!(policy.photo_root.join("wallpapers").exists());
+    }
+
+    #[test]
+    fn test_clean_keep_count_and_dry_run() {
+        let temp_dir = TempDir::new().unwrap();
+        let policy = RetentionPolicy {
+            keep_count: Some(3),
+            dry_run: true,
+            ..retention_tree(&temp_dir)
+        };
+
+        let report = clean_photos(&policy).unwrap();
+        assert_eq!(report.removed.len(), 8);
+        assert_eq!(removed_names(&report).last().unwrap(), "2026-02-07");
+        // Nothing is deleted in a dry run
+        assert!(policy.photo_root.join("31-01-2026").exists());
+        assert_eq!(report.remaining_bytes, 400);
+    }
+
+    #[test]
+    fn test_clean_max_size_removes_oldest_first() {
+        let temp_dir = TempDir::new().unwrap();
+        let policy = RetentionPolicy {
+            max_size: Some(750),
+            ..retention_tree(&temp_dir)
+        };
+
+        let report = clean_photos(&policy).unwrap();
+        assert_eq!(
+            removed_names(&report),
+            vec![
+                "31-01-2026",
+                "2026-02-01",
+                "2026-02-02",
+                "2026-02-03",
+                "2026-02-04"
+            ]
+        );
+        assert_eq!(report.remaining_bytes, 700);
+    }
+
+    #[test]
+    fn test_clean_always_keeps_today() {
+        let temp_dir = TempDir::new().unwrap();
+        let policy = RetentionPolicy {
+            keep_days: Some(0),
+            keep_count: Some(0),
+            max_size: Some(0),
+            ..retention_tree(&temp_dir)
+        };
+
+        let report = clean_photos(&policy).unwrap();
+        assert_eq!(report.removed.len(), 10);
+        assert!(policy.photo_root.join("2026-02-10/Photo.jpg").exists());
+        assert!(policy.collection_root.join("best-of-2025").exists());
+        assert_eq!(report.remaining_bytes, 200);
+    }
+
+    #[test]
+    fn test_clean_collections_only_when_asked() {
+        let temp_dir = TempDir::new().unwrap();
+        let policy = retention_tree(&temp_dir);
+        let collection = policy.collection_root.join("best-of-2025");
+        let last_year = SystemTime::now() - Duration::from_hours(400 * 24);
+        File::open(&collection)
+            .unwrap()
+            .set_modified(last_year)
+            .unwrap();
+
+        let policy = RetentionPolicy {
+            keep_days: Some(30),
+            today: Local::now().date_naive(),
+            ..policy
+        };
+        assert!(clean_photos(&policy)
+            .unwrap()
+            .removed
+            .iter()
+            .all(|d| d.path != collection));
+        assert!(collection.exists());
+
+        let policy = RetentionPolicy {
+            include_collections: true,
+            ..policy
+        };
+        let report = clean_photos(&policy).unwrap();
+        assert!(report.removed.iter().any(|d| d.path == collection));
+        assert!(!collection.exists());
+    }
+
+    #[test]
+    fn test_clean_missing_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let policy = RetentionPolicy {
+            photo_root: temp_dir.path().join("missing"),
+            collection_root: temp_dir.path().join("missing/collections"),
+            keep_days: Some(1),
+            ..RetentionPolicy::new().unwrap()
+        };
+        assert!(clean_photos(&policy).unwrap().removed.is_empty());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+        assert_eq!(parse_size("500M"), Some(500 << 20));
+        assert_eq!(parse_size("1gib"), Some(1 << 30));
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("2X"), None);
+        assert_eq!(parse_size("G"), None);
+    }
+
+    // ========================================================================
+    // Library Statistics Tests
+    // ========================================================================
+
+    #[test]
+    fn test_gather_stats_known_tree() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path();
+        let jpeg = encoded(image::ImageFormat::Jpeg);
+        let png = encoded(image::ImageFormat::Png);
+        let mut large = io::Cursor::new(Vec::new());
+        image::RgbImage::from_fn(256, 192, |x, y| {
+            image::Rgb([x.to_le_bytes()[0], y.to_le_bytes()[0], 0])
+        })
+        .write_to(&mut large, image::ImageFormat::Jpeg)
+        .unwrap();
+        let large = large.into_inner();
+        let files: [(&str, &[u8]); 9] = [
+            ("2026-02-01/Fox.jpg", &jpeg),
+            ("2026-02-01/Fox.json", b"{}"),
+            ("03-02-2026/Owl.png", &png),
+            ("2026-02-05/Heron.jpg", &large),
+            ("2026-02-06/Broken.jpg", b"<html>not found</html>"),
+            ("collections/best-of-2025/01-Reef.jpg", &jpeg),
+            ("collections/best-of-2025/02-Dune.jpg", &jpeg),
+            ("collections/wildlife/01-Bear.jpg", &jpeg),
+            ("Loose.jpg", &jpeg),
+        ];
+        for (path, contents) in files {
+            let path = base.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let stats = gather_stats(base).unwrap();
+        assert_eq!(stats.daily_photos, 3);
+        assert_eq!(
+            stats.collections,
+            vec![
+                CollectionStats {
+                    name: "best-of-2025".to_string(),
+                    photos: 2
+                },
+                CollectionStats {
+                    name: "wildlife".to_string(),
+                    photos: 1
+                },
+            ]
+        );
+        assert_eq!(stats.other_photos, 1);
+        let total: u64 = files.iter().map(|(_, c)| c.len() as u64).sum();
+        assert_eq!(stats.total_bytes, total);
+        assert_eq!(stats.oldest, chrono::NaiveDate::from_ymd_opt(2026, 2, 1));
+        assert_eq!(stats.newest, chrono::NaiveDate::from_ymd_opt(2026, 2, 5));
+        assert_eq!(
+            stats.largest,
+            Some(LargestPhoto {
+                path: base.join("2026-02-05/Heron.jpg"),
+                bytes: large.len() as u64,
+            })
+        );
+        assert_eq!(stats.corrupt, vec![base.join("2026-02-06/Broken.jpg")]);
+    }
+
+    #[test]
+    fn test_gather_stats_empty_and_missing() {
+        let temp_dir = TempDir::new().unwrap();
+        let stats = gather_stats(temp_dir.path()).unwrap();
+        assert_eq!(stats, PhotoStats::default());
+
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["daily_photos"], 0);
+        assert!(json["largest"].is_null());
+
+        assert!(matches!(
+            gather_stats(&temp_dir.path().join("missing")),
+            Err(PhotoError::NoPhotos(_))
+        ));
+    }
+
+    // ========================================================================
+    // Library Verification Tests
+    // ========================================================================
+
+    /// A good JPEG, an empty file, a PNG named `.jpg` and a truncated JPEG
+    fn seeded_library(temp_dir: &TempDir) -> PathBuf {
+        let day = temp_dir.path().join("2026-02-01");
+        fs::create_dir_all(&day).unwrap();
+        let jpeg = encoded(image::ImageFormat::Jpeg);
+        fs::write(day.join("Good.jpg"), &jpeg).unwrap();
+        fs::write(day.join("Empty.jpg"), "").unwrap();
+        fs::write(day.join("Misnamed.jpg"), encoded(image::ImageFormat::Png)).unwrap();
+        fs::write(day.join("Truncated.jpg"), &jpeg[..jpeg.len() / 2]).unwrap();
+        day
+    }
+
+    #[test]
+    fn test_verify_classifies_photos() {
+        let temp_dir = TempDir::new().unwrap();
+        let day = seeded_library(&temp_dir);
+
+        let report = verify_photos_with(temp_dir.path(), 1).unwrap();
+        assert_eq!(report.checked, 4);
+        let problems: Vec<_> = report
+            .issues
+            .iter()
+            .map(|issue| {
+                (
+                    issue.path.file_name().unwrap().to_str().unwrap(),
+                    &issue.problem,
+                )
+            })
+            .collect();
+        assert_eq!(problems.len(), 3);
+        assert_eq!(problems[0], ("Empty.jpg", &PhotoProblem::Undersized(0)));
+        assert_eq!(
+            problems[1],
+            (
+                "Misnamed.jpg",
+                &PhotoProblem::WrongExtension { detected: "png" }
+            )
+        );
+        assert_eq!(problems[2].0, "Truncated.jpg");
+        assert!(matches!(problems[2].1, PhotoProblem::Corrupt(_)));
+        assert!(!problems[2].1.to_string().contains(day.to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_verify_default_min_size_flags_small_photos() {
+        let temp_dir = TempDir::new().unwrap();
+        seeded_library(&temp_dir);
+        let report = verify_photos(temp_dir.path()).unwrap();
+        assert_eq!(report.issues.len(), 4);
+        assert!(report
+            .issues
+            .iter()
+            .all(|issue| matches!(issue.problem, PhotoProblem::Undersized(_))));
+    }
+
+    #[test]
+    fn test_repair_renames_and_deletes() {
+        let temp_dir = TempDir::new().unwrap();
+        let day = seeded_library(&temp_dir);
+        fs::write(day.join("Empty.log"), "log").unwrap();
+        let client = NatGeoClient::new(HttpOptions::default()).unwrap();
+
+        let report = verify_photos_with(temp_dir.path(), 1).unwrap();
+        let repairs: Vec<_> = report
+            .issues
+            .iter()
+            .map(|issue| repair_photo(issue, &client))
+            .collect();
+        assert_eq!(
+            repairs,
+            vec![
+                Repair::Deleted,
+                Repair::Renamed(day.join("Misnamed.png")),
+                Repair::Deleted
+            ]
+        );
+
+        let mut left: Vec<_> = fs::read_dir(&day)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, vec!["Good.jpg", "Misnamed.png"]);
+        assert!(verify_photos_with(temp_dir.path(), 1)
+            .unwrap()
+            .issues
+            .is_empty());
+    }
+
+    // ========================================================================
+    // Past Date Tests
+    // ========================================================================
+
+    #[test]
+    fn test_archive_url_for_date() {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 11, 3).unwrap();
+        assert_eq!(
+            archive_url_for_date(NATGEO_POD_ARCHIVE_URL, date),
+            "https://web.archive.org/web/20241103/https://www.nationalgeographic.com/photo-of-the-day"
+        );
+    }
+
+    #[test]
+    fn test_archive_snapshot_date() {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 11, 3);
+        assert_eq!(
+            archive_snapshot_date(
+                "https://web.archive.org/web/20241103083000/https://www.nationalgeographic.com/photo-of-the-day"
+            ),
+            date
+        );
+        assert_eq!(
+            archive_snapshot_date("https://web.archive.org/web/20241103/x"),
+            date
+        );
+        assert_eq!(
+            archive_snapshot_date("https://web.archive.org/web/2024/x"),
+            None
+        );
+        assert_eq!(archive_snapshot_date(NATGEO_POD_URL), None);
+    }
+
+    #[test]
+    fn test_past_date_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = format!("{}/", temp_dir.path().display());
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 11, 3).unwrap();
+        assert_eq!(date_dir_for(&base, date), format!("{}2024-11-03", base));
+
+        fs::create_dir(temp_dir.path().join("03-11-2024")).unwrap();
+        assert_eq!(date_dir_for(&base, date), format!("{}03-11-2024", base));
+    }
+
+    #[test]
+    fn test_validate_photo_date() {
+        let today = Local::now().date_naive();
+        assert!(validate_photo_date(today).is_ok());
+        assert!(validate_photo_date(today - chrono::Days::new(400)).is_ok());
+        assert!(matches!(
+            validate_photo_date(today + chrono::Days::new(1)),
+            Err(PhotoError::InvalidDate(_))
+        ));
+    }
+
+    // ========================================================================
+    // Backfill Tests
+    // ========================================================================
+
+    fn day(d: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2024, 1, d).unwrap()
+    }
+
+    fn backfill_options(temp_dir: &TempDir) -> BackfillOptions {
+        BackfillOptions {
+            base_dir: temp_dir.path().join("photos").display().to_string(),
+            delay: Duration::ZERO,
+            download: DownloadOptions::default(),
+            log_path: temp_dir
+                .path()
+                .join("data/backfill.log")
+                .display()
+                .to_string(),
+        }
+    }
+
+    #[test]
+    fn test_date_range() {
+        let range = DateRange::new(day(30), day(31)).unwrap();
+        assert_eq!(range.days().collect::<Vec<_>>(), vec![day(30), day(31)]);
+        assert_eq!(DateRange::new(day(1), day(31)).unwrap().days().count(), 31);
+        assert!(matches!(
+            DateRange::new(day(2), day(1)),
+            Err(PhotoError::InvalidDate(_))
+        ));
+
+        let today = Local::now().date_naive();
+        let last = DateRange::last(3, today).unwrap();
+        assert_eq!(last.from, today - chrono::Days::new(2));
+        assert_eq!(last.to, today);
+        assert!(DateRange::last(0, today).is_err());
+        assert!(DateRange::new(today, today + chrono::Days::new(1)).is_err());
+    }
+
+    #[test]
+    fn test_backfill_downloads_skips_and_fails_per_day() {
+        let temp_dir = TempDir::new().unwrap();
+        let options = backfill_options(&temp_dir);
+        let base = temp_dir.path().join("photos");
+        fs::create_dir_all(base.join("2024-01-02")).unwrap();
+        fs::write(base.join("2024-01-02/Already.jpg"), "photo").unwrap();
+
+        let mut fetched = Vec::new();
+        let mut fetch = |date: chrono::NaiveDate, save_dir: &str| {
+            fetched.push(date);
+            match chrono::Datelike::day(&date) {
+                1 => {
+                    let path = PathBuf::from(save_dir).join("New.jpg");
+                    fs::write(&path, "photo").unwrap();
+                    Ok(DownloadOutcome::Downloaded(path))
+                }
+                3 => Err(PhotoError::NoPhotos("No photo archived".to_string())),
+                _ => Ok(DownloadOutcome::Duplicate(base.join("2024-01-01/New.jpg"))),
+            }
+        };
+        let seen = std::cell::RefCell::new(Vec::new());
+        let progress = |date, _: &BackfillStatus| seen.borrow_mut().push(date);
+
+        let range = DateRange::new(day(1), day(4)).unwrap();
+        let report = backfill_with(
+            &range,
+            &options,
+            &CancelFlag::new(),
+            &mut fetch,
+            Some(&progress),
+        )
+        .unwrap();
+
+        assert_eq!(fetched, vec![day(1), day(3), day(4)]);
+        assert_eq!(*seen.borrow(), vec![day(1), day(2), day(3), day(4)]);
+        assert_eq!(
+            (report.downloaded, report.skipped, report.failed),
+            (1, 2, 1)
+        );
+        assert_eq!(
+            report.days[0].1,
+            BackfillStatus::Downloaded(base.join("2024-01-01/New.jpg"))
+        );
+        assert_eq!(
+            report.days[1].1,
+            BackfillStatus::Skipped(base.join("2024-01-02/Already.jpg"))
+        );
+        assert!(
+            matches!(&report.days[2].1, BackfillStatus::Failed(reason) if reason.contains("No photo archived"))
+        );
+        assert_eq!(
+            report.days[3].1,
+            BackfillStatus::Skipped(base.join("2024-01-01/New.jpg"))
+        );
+
+        // Days that saved nothing don't leave empty directories behind
+        assert!(!base.join("2024-01-03").exists());
+        assert!(!base.join("2024-01-04").exists());
+
+        let log = fs::read_to_string(&options.log_path).unwrap();
+        assert!(log.contains("2024-01-03: failed"));
+        assert!(log.contains("Backfill complete: 1 downloaded, 2 skipped, 1 failed"));
+    }
+
+    #[test]
+    fn test_backfill_stops_when_cancelled() {
+        let temp_dir = TempDir::new().unwrap();
+        let options = backfill_options(&temp_dir);
+        let cancel = CancelFlag::new();
+        let mut fetch = |_, _: &str| {
+            cancel.cancel();
+            Err(PhotoError::NoPhotos("offline".to_string()))
+        };
+
+        let range = DateRange::new(day(1), day(5)).unwrap();
+        let result = backfill_with(&range, &options, &cancel.clone(), &mut fetch, None);
+        assert!(matches!(result, Err(PhotoError::Interrupted)));
+    }
+
+    // ========================================================================
+    // Download and Set Tests
+    // ========================================================================
+
+    fn quick_run(attempts: u32) -> RunOptions {
+        RunOptions {
+            attempts,
+            retry_delay: Duration::ZERO,
+            ..RunOptions::default()
+        }
+    }
+
+    /// A real connection error, from a port nothing is listening on
+    fn offline_error() -> PhotoError {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        reqwest::blocking::get(format!("http://127.0.0.1:{port}/"))
+            .unwrap_err()
+            .into()
+    }
+
+    #[test]
+    fn test_is_offline_only_for_unreachable_server() {
+        assert!(offline_error().is_offline());
+        assert!(!PhotoError::NoPhotos("none".to_string()).is_offline());
+    }
+
+    #[test]
+    fn test_download_then_set_sets_wallpaper_when_offline() {
+        let mut downloads = 0;
+        let mut sets = 0;
+        let outcome = download_then_set(
+            quick_run(3),
+            &CancelFlag::new(),
+            &mut || {
+                downloads += 1;
+                Err(offline_error())
+            },
+            &mut || {
+                sets += 1;
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(downloads, 3);
+        assert_eq!(sets, 1);
+        assert!(matches!(outcome, RunOutcome::Degraded(_)));
+        assert!(!outcome.is_failure());
+        assert!(outcome.to_string().starts_with("Run degraded"));
+    }
+
+    #[test]
+    fn test_download_then_set_other_download_errors_are_not_offline() {
+        let mut sets = 0;
+        let outcome = download_then_set(
+            quick_run(1),
+            &CancelFlag::new(),
+            &mut || Err(PhotoError::NoPhotos("layout changed".to_string())),
+            &mut || {
+                sets += 1;
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(sets, 1);
+        assert!(matches!(outcome, RunOutcome::DownloadFailed(_)));
+        assert!(!outcome.is_failure());
+        assert!(outcome.to_string().contains("download failed"));
+
+        let strict = RunOptions {
+            offline_ok: false,
+            ..quick_run(1)
+        };
+        let outcome = download_then_set(
+            strict,
+            &CancelFlag::new(),
+            &mut || Err(offline_error()),
+            &mut || Ok(()),
+        )
+        .unwrap();
+        assert!(matches!(outcome, RunOutcome::DownloadRequired(_)));
+        assert!(outcome.is_failure());
+        assert!(outcome.to_string().starts_with("Run failed"));
+    }
+
+    #[test]
+    fn test_download_then_set_retries_until_download_succeeds() {
+        let mut downloads = 0;
+        let outcome = download_then_set(
+            quick_run(3),
+            &CancelFlag::new(),
+            &mut || {
+                downloads += 1;
+                if downloads < 2 {
+                    Err(PhotoError::NoPhotos("offline".to_string()))
+                } else {
+                    Ok(())
+                }
+            },
+            &mut || Ok(()),
+        )
+        .unwrap();
+
+        assert_eq!(downloads, 2);
+        assert!(matches!(outcome, RunOutcome::Complete));
+    }
+
+    #[test]
+    fn test_download_then_set_fails_only_when_both_fail() {
+        let outcome = download_then_set(
+            quick_run(1),
+            &CancelFlag::new(),
+            &mut || Err(PhotoError::NoPhotos("offline".to_string())),
+            &mut || Err(PhotoError::Wallpaper("no backend".to_string())),
+        )
+        .unwrap();
+        assert!(outcome.is_failure());
+
+        let outcome = download_then_set(
+            quick_run(1),
+            &CancelFlag::new(),
+            &mut || Ok(()),
+            &mut || Err(PhotoError::Wallpaper("no backend".to_string())),
+        )
+        .unwrap();
+        assert!(matches!(outcome, RunOutcome::SetFailed(_)));
+        assert!(!outcome.is_failure());
+    }
+
+    #[test]
+    fn test_download_then_set_stops_on_interrupt() {
+        let cancel = CancelFlag::new();
+        let mut sets = 0;
+        let result = download_then_set(
+            quick_run(3),
+            &cancel,
+            &mut || {
+                cancel.cancel();
+                Err(PhotoError::NoPhotos("offline".to_string()))
+            },
+            &mut || {
+                sets += 1;
+                Ok(())
+            },
+        );
+
+        assert!(matches!(result, Err(PhotoError::Interrupted)));
+        assert_eq!(sets, 0);
+    }
+
+    // ========================================================================
+    // JSON Output Tests
+    // ========================================================================
+
+    fn sample_photo() -> PhotoInfo {
+        PhotoInfo {
+            image_url: "https://example.com/photo.jpg".to_string(),
+            title: "Foggy Morning".to_string(),
+            caption: None,
+            photographer: None,
+        }
+    }
+
+    #[test]
+    fn test_download_report_marks_existing_photos_skipped() {
+        let photo = sample_photo();
+        let path = PathBuf::from("/photos/2026-02-01/Foggy_Morning.jpg");
+
+        let report = DownloadReport::new(&photo, &DownloadOutcome::Downloaded(path.clone()));
+        assert!(!report.skipped);
+        assert_eq!(report.path, path);
+        assert_eq!(report.url, photo.image_url);
+
+        let report = DownloadReport::new(&photo, &DownloadOutcome::AlreadyExists(path.clone()));
+        assert!(report.skipped);
+        let report = DownloadReport::new(&photo, &DownloadOutcome::Duplicate(path));
+        assert!(report.skipped);
+    }
+
+    #[test]
+    fn test_download_report_json_schema() {
+        let report = DownloadReport::new(
+            &sample_photo(),
+            &DownloadOutcome::Downloaded(PathBuf::from("/photos/Foggy_Morning.jpg")),
+        );
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(json["path"], "/photos/Foggy_Morning.jpg");
+        assert_eq!(json["title"], "Foggy Morning");
+        assert_eq!(json["url"], "https://example.com/photo.jpg");
+        assert_eq!(json["skipped"], false);
+        assert_eq!(
+            serde_json::from_value::<DownloadReport>(json).unwrap(),
+            report
+        );
+    }
+
+    #[test]
+    fn test_applied_wallpaper_json_is_flat() {
+        let assignment = WallpaperAssignment {
+            location: "Monitor 1".to_string(),
+            photo_path: PathBuf::from("/photos/a.jpg"),
+            is_newest: true,
+            slot: 0,
+        };
+        let applied: Vec<AppliedWallpaper> = [
+            AssignmentResult {
+                assignment: assignment.clone(),
+                result: Ok(()),
+            },
+            AssignmentResult {
+                assignment,
+                result: Err(PhotoError::Wallpaper("busy".to_string())),
+            },
+        ]
+        .iter()
+        .map(AppliedWallpaper::from)
+        .collect();
+        let json = serde_json::to_string(&applied).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(value[0]["location"], "Monitor 1");
+        assert_eq!(value[0]["photo_path"], "/photos/a.jpg");
+        assert_eq!(value[0]["is_newest"], true);
+        assert_eq!(value[0]["success"], true);
+        assert!(value[0]["error"].is_null());
+        assert_eq!(value[1]["success"], false);
+        assert_eq!(value[1]["error"], "Wallpaper error: busy");
+        assert_eq!(
+            serde_json::from_str::<Vec<AppliedWallpaper>>(&json).unwrap(),
+            applied
+        );
+    }
+
+    // ========================================================================
+    // Wallpaper Report Tests
+    // ========================================================================
+
+    /// A backend that records each call and fails for chosen targets
+    struct MockBackend {
+        capabilities: Capabilities,
+        failing: Vec<Target>,
+        calls: std::cell::RefCell<Vec<(Target, PathBuf)>>,
+    }
+
+    impl MockBackend {
+        fn new(monitors: usize, virtual_desktops: usize) -> Self {
+            Self {
+                capabilities: Capabilities {
+                    per_monitor: monitors > 1,
+                    per_virtual_desktop: virtual_desktops > 1,
+                    monitors,
+                    virtual_desktops,
+                },
+                failing: Vec::new(),
+                calls: std::cell::RefCell::default(),
+            }
+        }
+
+        fn failing(mut self, targets: &[Target]) -> Self {
+            self.failing = targets.to_vec();
+            self
+        }
+
+        fn targets(&self) -> Vec<Target> {
+            self.calls
+                .borrow()
+                .iter()
+                .map(|(target, _)| *target)
+                .collect()
+        }
+    }
+
+    impl WallpaperBackend for MockBackend {
+        fn desktop(&self) -> DesktopEnvironment {
+            DesktopEnvironment::KdePlasma6
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            self.capabilities
+        }
+
+        fn set(&self, target: Target, photo: &std::path::Path) -> Result<(), PhotoError> {
+            self.calls.borrow_mut().push((target, photo.to_path_buf()));
+            if self.failing.contains(&target) {
+                Err(PhotoError::Wallpaper("qdbus failed".to_string()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn report_assignments(count: usize) -> Vec<WallpaperAssignment> {
+        (0..count)
+            .map(|i| WallpaperAssignment {
+                location: format!("Monitor {}", i + 1),
+                photo_path: PathBuf::from(format!("/photos/{i}.jpg")),
+                is_newest: i == 0,
+                slot: i,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_apply_with_backend_reports_each_outcome() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("wallpaper.log");
+        let log_path = log_path.to_str().unwrap();
+        let backend = MockBackend::new(3, 1).failing(&[Target::Monitor(1)]);
+
+        let results = apply_with_backend(
+            &backend,
+            WallpaperMode::Monitors,
+            report_assignments(3),
+            log_path,
+            &CancelFlag::new(),
+        )
+        .unwrap();
+
+        let outcomes: Vec<bool> = results.iter().map(|r| r.result.is_ok()).collect();
+        assert_eq!(outcomes, [true, false, true]);
+        assert_eq!(results[1].assignment.location, "Monitor 2");
+        assert_eq!(
+            backend.calls.borrow()[2],
+            (Target::Monitor(2), PathBuf::from("/photos/2.jpg"))
+        );
+
+        let log = fs::read_to_string(log_path).unwrap();
+        assert!(log.contains("Set Monitor 1 to: /photos/0.jpg"));
+        assert!(log.contains("Failed to set Monitor 2: Wallpaper error: qdbus failed"));
+    }
+
+    #[test]
+    fn test_apply_with_backend_fails_when_nothing_applied() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("wallpaper.log");
+        let backend = MockBackend::new(2, 1).failing(&[Target::Monitor(0), Target::Monitor(1)]);
+
+        let result = apply_with_backend(
+            &backend,
+            WallpaperMode::Monitors,
+            report_assignments(2),
+            log_path.to_str().unwrap(),
+            &CancelFlag::new(),
+        );
+
+        // Every assignment is still tried
+        assert_eq!(backend.targets().len(), 2);
+        let Err(PhotoError::Wallpaper(message)) = result else {
+            panic!("expected a wallpaper error");
+        };
+        assert!(message.contains("none of 2"));
+        assert!(message.contains("qdbus failed"));
+    }
+
+    #[test]
+    fn test_apply_with_backend_targets_follow_mode() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("wallpaper.log");
+        let log_path = log_path.to_str().unwrap();
+        let cancel = CancelFlag::new();
+
+        // Both: monitor index wraps for each virtual desktop
+        let backend = MockBackend::new(2, 2);
+        apply_with_backend(
+            &backend,
+            WallpaperMode::Both,
+            report_assignments(4),
+            log_path,
+            &cancel,
+        )
+        .unwrap();
+        assert_eq!(
+            backend.targets(),
+            [
+                Target::Monitor(0),
+                Target::Monitor(1),
+                Target::Monitor(0),
+                Target::Monitor(1)
+            ]
+        );
+
+        // Virtual desktops: each wallpaper goes on every monitor
+        let backend = MockBackend::new(2, 2);
+        apply_with_backend(
+            &backend,
+            WallpaperMode::VirtualDesktops,
+            report_assignments(2),
+            log_path,
+            &cancel,
+        )
+        .unwrap();
+        assert_eq!(
+            backend.targets(),
+            [Target::AllMonitors, Target::AllMonitors]
+        );
+
+        // A single-wallpaper desktop is always set as a whole
+        let backend = MockBackend::new(1, 1);
+        apply_with_backend(
+            &backend,
+            WallpaperMode::Monitors,
+            report_assignments(1),
+            log_path,
+            &cancel,
+        )
+        .unwrap();
+        assert_eq!(backend.targets(), [Target::AllMonitors]);
+    }
+
+    #[test]
+    fn test_unknown_desktop_has_no_backend() {
+        assert!(DesktopEnvironment::Unknown.backend().is_none());
+        let gnome = DesktopEnvironment::Gnome.backend().unwrap();
+        assert_eq!(gnome.desktop(), DesktopEnvironment::Gnome);
+        assert!(!gnome.capabilities().per_monitor);
+    }
+
+    #[test]
+    fn test_apply_assignments_stops_when_cancelled() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("wallpaper.log");
+        let cancel = CancelFlag::new();
+        let mut calls = 0;
+
+        let result = apply_assignments(
+            report_assignments(3),
+            log_path.to_str().unwrap(),
+            &cancel,
+            &mut |_| {
+                calls += 1;
+                cancel.cancel();
+                Ok(())
+            },
+        );
+
+        assert!(matches!(result, Err(PhotoError::Interrupted)));
+        assert_eq!(calls, 1);
+    }
+
+    // ========================================================================
+    // Hyprland Tests
+    // ========================================================================
+
+    /// A command runner that records calls and answers from canned output
+    #[derive(Default)]
+    struct FakeCommands {
+        /// (program and first argument, stdout); anything else prints nothing
+        outputs: Vec<(&'static str, &'static str, &'static str)>,
+        /// Programs that exit with an error
+        failing: Vec<&'static str>,
+        calls: std::rc::Rc<std::cell::RefCell<Vec<String>>>,
+    }
+
+    impl CommandRunner for FakeCommands {
+        fn run(&self, program: &str, args: &[&str]) -> Result<String, PhotoError> {
+            self.calls
+                .borrow_mut()
+                .push(format!("{} {}", program, args.join(" ")));
+            if self.failing.contains(&program) {
+                return Err(PhotoError::Wallpaper(format!("{program} failed")));
+            }
+            Ok(self
+                .outputs
+                .iter()
+                .find(|(p, first, _)| *p == program && args.first() == Some(first))
+                .map(|(_, _, out)| (*out).to_string())
+                .unwrap_or_default())
+        }
+    }
+
+    const HYPRCTL_MONITORS: &str = r#"[
+        {"name": "DP-1", "width": 2560, "height": 1440, "scale": 1.0},
+        {"name": "DP-2", "width": 2560, "height": 1440, "scale": 1.0}
+    ]"#;
+
+    fn hyprland_backend() -> (
+        HyprlandBackend,
+        std::rc::Rc<std::cell::RefCell<Vec<String>>>,
+    ) {
+        let runner = FakeCommands {
+            outputs: vec![("hyprctl", "monitors", HYPRCTL_MONITORS)],
+            ..FakeCommands::default()
+        };
+        let calls = runner.calls.clone();
+        (HyprlandBackend::new(Box::new(runner)), calls)
+    }
+
+    #[test]
+    fn test_hyprland_commands() {
+        let commands = HyprlandBackend::commands("DP-1", Path::new("/photos/a b.jpg"));
+        assert_eq!(
+            commands,
+            [
+                vec!["hyprpaper", "preload", "/photos/a b.jpg"],
+                vec!["hyprpaper", "wallpaper", "DP-1,/photos/a b.jpg"],
+                vec!["hyprpaper", "unload", "all"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hyprland_backend_sets_each_monitor_by_name() {
+        let (backend, calls) = hyprland_backend();
+        assert_eq!(backend.capabilities().monitors, 2);
+        assert!(!backend.capabilities().per_virtual_desktop);
+
+        backend
+            .set(Target::Monitor(1), Path::new("/photos/b.jpg"))
+            .unwrap();
+        backend
+            .set(Target::AllMonitors, Path::new("/photos/c.jpg"))
+            .unwrap();
+        assert!(backend
+            .set(Target::Monitor(2), Path::new("/photos/d.jpg"))
+            .is_err());
+
+        assert_eq!(
+            *calls.borrow(),
+            [
+                "hyprctl monitors -j",
+                "hyprctl hyprpaper preload /photos/b.jpg",
+                "hyprctl hyprpaper wallpaper DP-2,/photos/b.jpg",
+                "hyprctl hyprpaper unload all",
+                "hyprctl hyprpaper preload /photos/c.jpg",
+                "hyprctl hyprpaper wallpaper ,/photos/c.jpg",
+                "hyprctl hyprpaper unload all",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hyprland_assigns_a_photo_per_monitor() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("wallpaper.log");
+        let (backend, calls) = hyprland_backend();
+        let photos = [
+            PathBuf::from("/photos/new.jpg"),
+            PathBuf::from("/photos/old.jpg"),
+        ];
+        let capabilities = backend.capabilities();
+        let assignments = build_assignments(
+            WallpaperMode::Monitors,
+            &photos,
+            capabilities.monitors,
+            capabilities.virtual_desktops,
+            &TargetFilter::default(),
+        );
+
+        apply_with_backend(
+            &backend,
+            WallpaperMode::Monitors,
+            assignments,
+            log_path.to_str().unwrap(),
+            &CancelFlag::new(),
+        )
+        .unwrap();
+
+        let wallpapers: Vec<String> = calls
+            .borrow()
+            .iter()
+            .filter(|c| c.contains(" wallpaper "))
+            .cloned()
+            .collect();
+        assert_eq!(
+            wallpapers,
+            [
+                "hyprctl hyprpaper wallpaper DP-1,/photos/new.jpg",
+                "hyprctl hyprpaper wallpaper DP-2,/photos/old.jpg",
+            ]
+        );
+    }
+
+    // ========================================================================
+    // swww Tests
+    // ========================================================================
+
+    const SWWW_QUERY: &str = "DP-1: 2560x1440, scale: 1, currently displaying: color: 000000
+DP-2: 1920x1080, scale: 1, currently displaying: color: 000000
+";
+
+    #[test]
+    fn test_swww_img_args() {
+        let photo = Path::new("/photos/a b.jpg");
+        assert_eq!(
+            SwwwBackend::img_args(photo, None, &TransitionOptions::default()),
+            ["img", "/photos/a b.jpg"]
+        );
+
+        let transition = TransitionOptions {
+            kind: Some("fade".to_string()),
+            duration: Some(Duration::from_millis(500)),
+        };
+        assert_eq!(
+            SwwwBackend::img_args(photo, Some("DP-2"), &transition),
+            [
+                "img",
+                "/photos/a b.jpg",
+                "--outputs",
+                "DP-2",
+                "--transition-type",
+                "fade",
+                "--transition-duration",
+                "0.5",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_swww_backend_sets_each_output_by_name() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("wallpaper.log");
+        let runner = FakeCommands {
+            outputs: vec![("swww", "query", SWWW_QUERY)],
+            ..FakeCommands::default()
+        };
+        let calls = runner.calls.clone();
+        let backend = SwwwBackend::new(
+            Box::new(runner),
+            TransitionOptions {
+                kind: Some("wipe".to_string()),
+                duration: None,
+            },
+        );
+        let capabilities = backend.capabilities();
+        assert!(capabilities.per_monitor);
+        assert_eq!(capabilities.monitors, 2);
+
+        let photos = [
+            PathBuf::from("/photos/new.jpg"),
+            PathBuf::from("/photos/old.jpg"),
+        ];
+        let assignments = build_assignments(
+            WallpaperMode::Monitors,
+            &photos,
+            capabilities.monitors,
+            capabilities.virtual_desktops,
+            &TargetFilter::default(),
+        );
+        apply_with_backend(
+            &backend,
+            WallpaperMode::Monitors,
+            assignments,
+            log_path.to_str().unwrap(),
+            &CancelFlag::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            *calls.borrow(),
+            [
+                "swww query",
+                "swww img /photos/new.jpg --outputs DP-1 --transition-type wipe",
+                "swww img /photos/old.jpg --outputs DP-2 --transition-type wipe",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_swww_without_daemon_says_to_start_it() {
+        let runner = FakeCommands {
+            failing: vec!["swww"],
+            ..FakeCommands::default()
+        };
+        let calls = runner.calls.clone();
+        let backend = SwwwBackend::new(Box::new(runner), TransitionOptions::default());
+        assert!(!backend.capabilities().per_monitor);
+
+        let err = backend
+            .set(Target::AllMonitors, Path::new("/photos/a.jpg"))
+            .unwrap_err();
+        assert!(matches!(err, PhotoError::Wallpaper(_)));
+        assert!(err.to_string().contains("swww-daemon"), "{err}");
+        // Only the query ran
+        assert_eq!(calls.borrow().len(), 1);
+    }
+
+    // ========================================================================
+    // XFCE Tests
+    // ========================================================================
+
+    /// `xfconf-query -c xfce4-desktop -l` from a two-monitor, two-workspace session
+    const XFCONF_LIST: &str = "/backdrop/screen0/monitor0/image-path
+/backdrop/screen0/monitor0/image-show
+/backdrop/screen0/monitorHDMI-1/workspace0/color-style
+/backdrop/screen0/monitorHDMI-1/workspace0/image-style
+/backdrop/screen0/monitorHDMI-1/workspace0/last-image
+/backdrop/screen0/monitorHDMI-1/workspace1/last-image
+/backdrop/screen0/monitoreDP-1/workspace0/color-style
+/backdrop/screen0/monitoreDP-1/workspace0/image-style
+/backdrop/screen0/monitoreDP-1/workspace0/last-image
+/backdrop/screen0/monitoreDP-1/workspace1/image-style
+/backdrop/screen0/monitoreDP-1/workspace1/last-image
+/backdrop/single-workspace-mode
+/desktop-icons/style
+";
+
+    fn xfce_backend() -> (XfceBackend, std::rc::Rc<std::cell::RefCell<Vec<String>>>) {
+        let runner = FakeCommands {
+            outputs: vec![("xfconf-query", "-c", XFCONF_LIST)],
+            ..FakeCommands::default()
+        };
+        let calls = runner.calls.clone();
+        (XfceBackend::new(Box::new(runner)), calls)
+    }
+
+    /// The properties written by `xfconf-query -s` calls, with their values
+    fn xfconf_writes(calls: &[String]) -> Vec<String> {
+        calls
+            .iter()
+            .filter(|c| c.contains(" -s "))
+            .map(|c| {
+                let args: Vec<&str> = c.split(' ').collect();
+                format!("{}={}", args[4], args[args.len() - 1])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_xfce_backdrops() {
+        let backdrops = parse_xfce_backdrops(XFCONF_LIST);
+        let found: Vec<(&str, usize, bool)> = backdrops
+            .iter()
+            .map(|b| (b.monitor.as_str(), b.workspace, b.has_image_style))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("HDMI-1", 0, true),
+                ("HDMI-1", 1, false),
+                ("eDP-1", 0, true),
+                ("eDP-1", 1, true),
+            ]
+        );
+        assert_eq!(
+            backdrops[2].property,
+            "/backdrop/screen0/monitoreDP-1/workspace0"
+        );
+        assert!(parse_xfce_backdrops("").is_empty());
+    }
+
+    #[test]
+    fn test_select_xfce_backdrops() {
+        let backdrops = parse_xfce_backdrops(XFCONF_LIST);
+        let selected = |monitor, workspace| -> Vec<(String, usize)> {
+            select_xfce_backdrops(&backdrops, monitor, workspace)
+                .iter()
+                .map(|b| (b.monitor.clone(), b.workspace))
+                .collect()
+        };
+
+        assert_eq!(
+            selected(Some(1), None),
+            [("eDP-1".to_string(), 0), ("eDP-1".to_string(), 1)]
+        );
+        assert_eq!(
+            selected(None, Some(1)),
+            [("HDMI-1".to_string(), 1), ("eDP-1".to_string(), 1)]
+        );
+        assert_eq!(selected(Some(0), Some(0)), [("HDMI-1".to_string(), 0)]);
+        assert_eq!(selected(None, None).len(), 4);
+        assert!(selected(Some(2), None).is_empty());
+    }
+
+    #[test]
+    fn test_xfce_commands_set_missing_image_style() {
+        let backdrops = parse_xfce_backdrops(XFCONF_LIST);
+        let photo = Path::new("/photos/a.jpg");
+
+        assert_eq!(
+            backdrops[0].commands(photo),
+            [[
+                "-c",
+                "xfce4-desktop",
+                "-p",
+                "/backdrop/screen0/monitorHDMI-1/workspace0/last-image",
+                "-n",
+                "-t",
+                "string",
+                "-s",
+                "/photos/a.jpg",
+            ]]
+        );
+
+        let commands = backdrops[1].commands(photo);
+        assert_eq!(commands.len(), 2);
+        assert_eq!(
+            commands[1][3],
+            "/backdrop/screen0/monitorHDMI-1/workspace1/image-style"
+        );
+        assert_eq!(commands[1][6..], ["int", "-s", "5"]);
+    }
+
+    #[test]
+    fn test_xfce_backend_honors_each_mode() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("wallpaper.log");
+        let photos = [PathBuf::from("/p/1.jpg"), PathBuf::from("/p/2.jpg")];
+        let writes = |mode| {
+            let (backend, calls) = xfce_backend();
+            let capabilities = backend.capabilities();
+            assert_eq!(
+                (capabilities.monitors, capabilities.virtual_desktops),
+                (2, 2)
+            );
+            let assignments = build_assignments(
+                mode,
+                &photos,
+                capabilities.monitors,
+                capabilities.virtual_desktops,
+                &TargetFilter::default(),
+            );
+            apply_with_backend(
+                &backend,
+                mode,
+                assignments,
+                log_path.to_str().unwrap(),
+                &CancelFlag::new(),
+            )
+            .unwrap();
+            let calls = calls.borrow();
+            xfconf_writes(&calls)
+                .into_iter()
+                .filter(|w| w.contains("last-image"))
+                .map(|w| {
+                    w.replace("/backdrop/screen0/monitor", "")
+                        .replace("/last-image", "")
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            writes(WallpaperMode::Monitors),
+            [
+                "HDMI-1/workspace0=/p/1.jpg",
+                "HDMI-1/workspace1=/p/1.jpg",
+                "eDP-1/workspace0=/p/2.jpg",
+                "eDP-1/workspace1=/p/2.jpg",
+            ]
+        );
+        assert_eq!(
+            writes(WallpaperMode::VirtualDesktops),
+            [
+                "HDMI-1/workspace0=/p/1.jpg",
+                "eDP-1/workspace0=/p/1.jpg",
+                "HDMI-1/workspace1=/p/2.jpg",
+                "eDP-1/workspace1=/p/2.jpg",
+            ]
+        );
+        assert_eq!(
+            writes(WallpaperMode::Both),
+            [
+                "HDMI-1/workspace0=/p/1.jpg",
+                "eDP-1/workspace0=/p/2.jpg",
+                "HDMI-1/workspace1=/p/1.jpg",
+                "eDP-1/workspace1=/p/2.jpg",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_xfce_without_backdrops_errors() {
+        let backend = XfceBackend::new(Box::new(FakeCommands::default()));
+        let err = backend
+            .set(Target::AllMonitors, Path::new("/p/1.jpg"))
+            .unwrap_err();
+        assert!(err.to_string().contains("Desktop Settings"), "{err}");
+    }
+
+    // ========================================================================
+    // LXQt Tests
+    // ========================================================================
+
+    #[test]
+    fn test_lxqt_args() {
+        let photo = Path::new("/photos/a b.jpg");
+        assert_eq!(
+            LxqtBackend::args(photo, FitMode::default()),
+            ["--set-wallpaper", "/photos/a b.jpg", "--wallpaper-mode=fit"]
+        );
+        assert_eq!(
+            LxqtBackend::args(photo, FitMode::Zoom)[2],
+            "--wallpaper-mode=zoom"
+        );
+    }
+
+    #[test]
+    fn test_lxqt_sets_a_single_wallpaper() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("wallpaper.log");
+        let runner = FakeCommands::default();
+        let calls = runner.calls.clone();
+        let backend = LxqtBackend::new(Box::new(runner), FitMode::Stretch);
+        assert!(!backend.capabilities().per_monitor);
+
+        let photos = [PathBuf::from("/p/1.jpg"), PathBuf::from("/p/2.jpg")];
+        let mut assignments = build_assignments(
+            WallpaperMode::Monitors,
+            &photos,
+            2,
+            1,
+            &TargetFilter::default(),
+        );
+        assignments.truncate(1);
+        apply_with_backend(
+            &backend,
+            WallpaperMode::Monitors,
+            assignments,
+            log_path.to_str().unwrap(),
+            &CancelFlag::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            *calls.borrow(),
+            ["pcmanfm-qt --set-wallpaper /p/1.jpg --wallpaper-mode=stretch"]
+        );
+    }
+
+    // ========================================================================
+    // Windows Tests
+    // ========================================================================
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn test_windows_monitor_for_target() {
+        use windows_wallpaper::monitor_for;
+
+        let monitors = vec![r"\\?\DISPLAY#A".to_string(), r"\\?\DISPLAY#B".to_string()];
+        assert_eq!(
+            monitor_for(&monitors, Target::Monitor(1)).unwrap(),
+            Some(r"\\?\DISPLAY#B")
+        );
+        assert_eq!(monitor_for(&monitors, Target::AllMonitors).unwrap(), None);
+        assert!(monitor_for(&monitors, Target::Monitor(2)).is_err());
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn test_windows_is_detected() {
+        assert_eq!(detect_desktop_environment(), DesktopEnvironment::Windows);
+    }
+
+    // ========================================================================
+    // Scheduling Tests
+    // ========================================================================
+
+    #[test]
+    fn test_parse_schedule() {
+        assert_eq!(
+            parse_schedule("02:00").unwrap(),
+            ScheduleType::DailyTime("02:00".to_string())
+        );
+        assert_eq!(
+            parse_schedule("2h30m").unwrap(),
+            ScheduleType::Interval("2h30m".to_string())
+        );
+        assert!(parse_schedule("24:00").is_err());
+        assert!(parse_schedule("h").is_err());
+        assert!(parse_schedule("soon").is_err());
+
+        assert_eq!(
+            ScheduleType::DailyTime("22:45".to_string()).daily_time(),
+            Some((22, 45))
+        );
+        assert_eq!(
+            ScheduleType::Interval("2h30m".to_string()).interval_secs(),
+            Some(9000)
+        );
+        assert_eq!(
+            ScheduleType::Interval("45M".to_string()).interval_secs(),
+            Some(2700)
+        );
+        assert_eq!(
+            ScheduleType::Interval("1h".to_string()).to_string(),
+            "every 1h"
+        );
+    }
+
+    #[test]
+    fn test_cron_expression() {
+        let cron = |schedule: ScheduleType| schedule.cron_expression();
+        assert_eq!(
+            cron(ScheduleType::DailyTime("02:05".to_string())).unwrap(),
+            "5 2 * * *"
+        );
+        assert_eq!(
+            cron(ScheduleType::Interval("30m".to_string())).unwrap(),
+            "*/30 * * * *"
+        );
+        assert_eq!(
+            cron(ScheduleType::Interval("60m".to_string())).unwrap(),
+            "0 * * * *"
+        );
+        assert_eq!(
+            cron(ScheduleType::Interval("6h".to_string())).unwrap(),
+            "0 */6 * * *"
+        );
+        // Can't be expressed without drifting
+        assert!(cron(ScheduleType::Interval("45m".to_string())).is_err());
+        assert!(cron(ScheduleType::Interval("2h30m".to_string())).is_err());
+        assert!(cron(ScheduleType::Interval("5h".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_cron_line() {
+        let line = cron_line(
+            &ScheduleType::Interval("1h".to_string()),
+            &[
+                ("DISPLAY", ":0"),
+                ("DBUS_SESSION_BUS_ADDRESS", "unix:path=/run/user/1000/bus"),
+            ],
+            "'/bin/ngw' 'run' '--path' '/photos/100%'",
+        )
+        .unwrap();
+        assert_eq!(
+            line,
+            "0 * * * * DISPLAY=':0' DBUS_SESSION_BUS_ADDRESS='unix:path=/run/user/1000/bus' \
+             '/bin/ngw' 'run' '--path' '/photos/100\\%' # natgeo-wallpapers"
+        );
+    }
+
+    #[test]
+    fn test_crontab_add_and_remove_line() {
+        let existing = "MAILTO=me@example.com\n0 3 * * * backup.sh\n";
+        let line = "0 2 * * * '/bin/ngw' 'run' # natgeo-wallpapers";
+
+        let added = crontab_with_line(existing, line);
+        assert_eq!(
+            added,
+            format!("MAILTO=me@example.com\n0 3 * * * backup.sh\n{line}\n")
+        );
+
+        // Installing again replaces the old line instead of adding another
+        let replaced =
+            crontab_with_line(&added, "*/30 * * * * '/bin/ngw' 'run' # natgeo-wallpapers");
+        assert_eq!(replaced.matches(CRON_TAG).count(), 1);
+        assert!(replaced.contains("*/30 * * * *"));
+
+        let (removed, found) = crontab_without_line(&replaced);
+        assert!(found);
+        assert_eq!(removed, existing);
+
+        let (unchanged, found) = crontab_without_line(existing);
+        assert!(!found);
+        assert_eq!(unchanged, existing);
+        assert_eq!(crontab_with_line("", line), format!("{line}\n"));
+    }
+
+    #[test]
+    fn test_crontab_from_listing() {
+        assert_eq!(
+            crontab_from_listing(true, "0 3 * * * backup.sh\n", "").unwrap(),
+            "0 3 * * * backup.sh\n"
+        );
+        assert_eq!(
+            crontab_from_listing(false, "", "no crontab for alice\n").unwrap(),
+            ""
+        );
+        // Anything else must not be taken for an empty crontab
+        let err = crontab_from_listing(false, "", "crontab: Permission denied\n").unwrap_err();
+        assert!(err.to_string().contains("Permission denied"), "{err}");
+    }
+
+    #[test]
+    fn test_launchd_plist_daily() {
+        let arguments = [
+            "/usr/local/bin/natgeo-wallpapers".to_string(),
+            "run".to_string(),
+        ];
+        let plist = launchd_plist(
+            &arguments,
+            &ScheduleType::DailyTime("02:05".to_string()),
+            "/Users/ada/log & more.log",
+        )
+        .unwrap();
+
+        assert_eq!(
+            plist,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>com.natgeo-wallpapers</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>/usr/local/bin/natgeo-wallpapers</string>
+        <string>run</string>
+    </array>
+    <key>StartCalendarInterval</key>
+    <dict>
+        <key>Hour</key>
+        <integer>2</integer>
+        <key>Minute</key>
+        <integer>5</integer>
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>StandardOutPath</key>
+    <string>/Users/ada/log &amp; more.log</string>
+    <key>StandardErrorPath</key>
+    <string>/Users/ada/log &amp; more.log</string>
+</dict>
+</plist>
+"#
+        );
+    }
+
+    #[test]
+    fn test_launchd_plist_interval() {
+        let arguments = ["/bin/natgeo-wallpapers".to_string(), "run".to_string()];
+        let plist = launchd_plist(
+            &arguments,
+            &ScheduleType::Interval("1h30m".to_string()),
+            "/tmp/launchd.log",
+        )
+        .unwrap();
+
+        assert!(plist.contains(
+            "    <key>StartInterval</key>\n    <integer>5400</integer>\n    <key>RunAtLoad</key>"
+        ));
+        assert!(!plist.contains("StartCalendarInterval"));
+
+        assert!(launchd_plist(
+            &arguments,
+            &ScheduleType::Interval("0m".to_string()),
+            "/tmp/launchd.log"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_autostart_desktop_entry_plain() {
+        let entry = autostart_desktop_entry("/usr/bin/natgeo-wallpapers", false, None, false);
+        assert!(entry.starts_with("[Desktop Entry]\nType=Application\n"));
+        assert!(entry.contains("\nExec=/usr/bin/natgeo-wallpapers run\n"));
+        assert!(entry.contains("\nX-GNOME-Autostart-enabled=true\n"));
+    }
+
+    #[test]
+    fn test_autostart_desktop_entry_passes_set_options() {
+        let entry = autostart_desktop_entry(
+            "/usr/bin/natgeo-wallpapers",
+            true,
+            Some("/home/me/Pictures/NationalGeographic/collections"),
+            true,
+        );
+        assert!(entry.contains(
+            "\nExec=/usr/bin/natgeo-wallpapers run --random \
+             --path /home/me/Pictures/NationalGeographic/collections --lock-screen\n"
+        ));
+    }
+
+    #[test]
+    fn test_autostart_desktop_entry_quotes_paths() {
+        let entry = autostart_desktop_entry(
+            "/opt/Nat Geo/natgeo-wallpapers",
+            false,
+            Some(r#"/home/me/My "Best" $100 \ 50% off"#),
+            false,
+        );
+        assert!(entry.contains(
+            r#"Exec="/opt/Nat Geo/natgeo-wallpapers" run --path "/home/me/My \\"Best\\" \\$100 \\\\ 50%% off""#
+        ));
+    }
+
+    // ========================================================================
+    // Configuration Tests
+    // ========================================================================
+
+    #[test]
+    fn test_parse_config_reads_every_table() {
+        let (config, unknown) = parse_config(
+            r#"
+[paths]
+photos = "~/Wallpapers"
+[wallpaper]
+mode = "virtual-desktops"
+random = true
+[retention]
+keep_days = 90
+[http]
+retries = 5
+timeout = 45
+proxy = "http://proxy:3128"
+"#,
+        )
+        .unwrap();
+        assert!(unknown.is_empty());
+        assert_eq!(config.paths.photos.as_deref(), Some("~/Wallpapers"));
+        assert_eq!(config.paths.collections, None);
+        assert_eq!(config.wallpaper.mode, Some(WallpaperMode::VirtualDesktops));
+        assert_eq!(config.wallpaper.random, Some(true));
+        assert_eq!(config.wallpaper.lock_screen, None);
+        assert_eq!(config.retention.keep_days, Some(90));
+        let http = config.http_options();
+        assert_eq!(http.retries, 5);
+        assert_eq!(http.timeout, Duration::from_secs(45));
+        assert_eq!(http.proxy.as_deref(), Some("http://proxy:3128"));
+    }
+
+    #[test]
+    fn test_parse_config_reports_unknown_keys() {
+        let (config, unknown) =
+            parse_config("colour = \"blue\"\n[wallpaper]\nrandom = true\nshuffle = true\n")
+                .unwrap();
+        assert_eq!(config.wallpaper.random, Some(true));
+        assert_eq!(unknown, vec!["colour", "wallpaper.shuffle"]);
+    }
+
+    #[test]
+    fn test_parse_config_rejects_bad_values() {
+        assert!(parse_config("[wallpaper]\nmode = \"sideways\"\n").is_err());
+        assert!(parse_config("[retention]\nmax_size = \"lots\"\n").is_err());
+        assert!(parse_config("[http]\ntimeout = 0\n").is_err());
+        assert!(parse_config("[http\n").is_err());
+    }
+
+    #[test]
+    fn test_default_config_file_is_all_defaults() {
+        let (config, unknown) = parse_config(&default_config_file()).unwrap();
+        assert_eq!(config, Config::default());
+        assert!(unknown.is_empty());
+
+        // Uncommenting every key gives a valid file too
+        let uncommented: String = default_config_file()
+            .lines()
+            .filter_map(|line| match line.strip_prefix("# ") {
+                Some(key) if key.contains(" = ") => Some(format!("{}\n", key)),
+                _ if line.starts_with('[') => Some(format!("{}\n", line)),
+                _ => None,
+            })
+            .collect();
+        let (config, unknown) = parse_config(&uncommented).unwrap();
+        assert!(unknown.is_empty());
+        assert_eq!(config.retention.keep_days, Some(90));
+        assert_eq!(config.http.retries, Some(DEFAULT_HTTP_RETRIES));
+        assert_eq!(
+            config.paths.cache.as_deref(),
+            Some("~/.cache/natgeo-wallpapers/")
+        );
+    }
+
+    #[test]
+    fn test_config_merge_prefers_overrides() {
+        let file = Config {
+            wallpaper: WallpaperConfig {
+                mode: Some(WallpaperMode::Both),
+                random: Some(true),
+                lock_screen: Some(true),
+                current_link: None,
+                pywal: None,
+                favorite_weight: Some(5),
+            },
+            http: HttpConfig {
+                retries: Some(5),
+                timeout: Some(60),
+                proxy: Some("http://file".to_string()),
+            },
+            ..Config::default()
+        };
+        let flags = Config {
+            wallpaper: WallpaperConfig {
+                mode: Some(WallpaperMode::Monitors),
+                ..WallpaperConfig::default()
+            },
+            http: HttpConfig {
+                retries: Some(0),
+                ..HttpConfig::default()
+            },
+            ..Config::default()
+        };
+
+        let merged = file.merged(&flags);
+        assert_eq!(merged.wallpaper.mode, Some(WallpaperMode::Monitors));
+        assert_eq!(merged.wallpaper.random, Some(true));
+        assert_eq!(merged.wallpaper.lock_screen, Some(true));
+        assert_eq!(merged.wallpaper.favorite_weight, Some(5));
+        assert_eq!(merged.http.retries, Some(0));
+        assert_eq!(merged.http.timeout, Some(60));
+        assert_eq!(merged.http.proxy.as_deref(), Some("http://file"));
+
+        // Nothing to override leaves the file as it was
+        assert_eq!(file.merged(&Config::default()), file);
+    }
+
+    #[test]
+    fn test_config_merge_replaces_retention_as_a_whole() {
+        let file = Config {
+            retention: RetentionConfig {
+                keep_days: Some(90),
+                max_size: Some("2G".to_string()),
+                ..RetentionConfig::default()
+            },
+            ..Config::default()
+        };
+        let flags = Config {
+            retention: RetentionConfig {
+                keep_count: Some(10),
+                ..RetentionConfig::default()
+            },
+            ..Config::default()
+        };
+
+        assert_eq!(
+            file.merged(&flags).retention,
+            RetentionConfig {
+                keep_count: Some(10),
+                ..RetentionConfig::default()
+            }
+        );
+        assert_eq!(file.merged(&Config::default()).retention, file.retention);
+    }
+
+    #[test]
+    fn test_config_log_rotation() {
+        let (config, _) = parse_config("[logging]\nmax_size = \"500K\"\nkeep = 5\n").unwrap();
+        assert_eq!(
+            config.log_rotation(),
+            LogRotation {
+                max_bytes: 500 * 1024,
+                keep: 5,
+            }
+        );
+        assert_eq!(Config::default().log_rotation().max_bytes, 1 << 20);
+        assert!(parse_config("[logging]\nmax_size = \"lots\"\n").is_err());
+    }
+
+    #[test]
+    fn test_config_defaults_come_last() {
+        let dirs = DataDirs {
+            photos: "/home/ada/Pictures/NationalGeographic/".to_string(),
+            collections: "/home/ada/Pictures/NationalGeographic/collections/".to_string(),
+            logs: "/home/ada/.local/share/natgeo-wallpapers/".to_string(),
+            cache: "/home/ada/.cache/natgeo-wallpapers/".to_string(),
+        };
+        let defaults = Config::default().with_defaults(&dirs);
+        assert_eq!(defaults.paths.photos.as_ref(), Some(&dirs.photos));
+        assert_eq!(defaults.paths.collections.as_ref(), Some(&dirs.collections));
+        assert_eq!(defaults.paths.logs.as_ref(), Some(&dirs.logs));
+        assert_eq!(defaults.paths.cache.as_ref(), Some(&dirs.cache));
+        assert_eq!(defaults.wallpaper.mode, Some(WallpaperMode::Monitors));
+        assert_eq!(defaults.wallpaper.random, Some(false));
+        assert_eq!(defaults.http.retries, Some(DEFAULT_HTTP_RETRIES));
+        assert!(defaults.retention.is_empty());
+        assert_eq!(defaults.http.proxy, None);
+        assert_eq!(defaults.log_rotation(), LogRotation::default());
+
+        let file = Config {
+            wallpaper: WallpaperConfig {
+                random: Some(true),
+                ..WallpaperConfig::default()
+            },
+            ..Config::default()
+        };
+        assert_eq!(file.with_defaults(&dirs).wallpaper.random, Some(true));
+    }
+
+    // ========================================================================
+    // Data Directory Tests
+    // ========================================================================
+
+    /// Platform folders of a Linux user `ada`
+    fn linux_base_dirs() -> BaseDirs {
+        BaseDirs {
+            home: Some(PathBuf::from("/home/ada")),
+            pictures: Some(PathBuf::from("/home/ada/Bilder")),
+            data: Some(PathBuf::from("/home/ada/.local/share")),
+            cache: Some(PathBuf::from("/home/ada/.cache")),
+        }
+    }
+
+    #[test]
+    fn test_data_dirs_default_to_platform_folders() {
+        let dirs = resolve_data_dirs(&|_| None, &Config::default(), &linux_base_dirs()).unwrap();
+        assert_eq!(
+            dirs,
+            DataDirs {
+                photos: "/home/ada/Bilder/NationalGeographic/".to_string(),
+                collections: "/home/ada/Bilder/NationalGeographic/collections/".to_string(),
+                logs: "/home/ada/.local/share/natgeo-wallpapers/".to_string(),
+                cache: "/home/ada/.cache/natgeo-wallpapers/".to_string(),
+            }
+        );
+
+        // XDG_CACHE_HOME moves the cache like XDG_DATA_HOME moves the logs
+        let xdg = BaseDirs {
+            cache: Some(PathBuf::from("/var/cache/ada")),
+            ..linux_base_dirs()
+        };
+        let dirs = resolve_data_dirs(&|_| None, &Config::default(), &xdg).unwrap();
+        assert_eq!(dirs.cache, "/var/cache/ada/natgeo-wallpapers/");
+
+        // Without user-dirs.dirs or the XDG variables, fall back to the home directory
+        let bare = BaseDirs {
+            home: Some(PathBuf::from("/home/ada")),
+            ..BaseDirs::default()
+        };
+        let dirs = resolve_data_dirs(&|_| None, &Config::default(), &bare).unwrap();
+        assert_eq!(dirs.photos, "/home/ada/Pictures/NationalGeographic/");
+        assert_eq!(dirs.logs, "/home/ada/.local/share/natgeo-wallpapers/");
+        assert_eq!(dirs.cache, "/home/ada/.cache/natgeo-wallpapers/");
+    }
+
+    #[test]
+    fn test_data_dirs_config_overrides_platform_folders() {
+        let config = Config {
+            paths: PathsConfig {
+                photos: Some("~/Wallpapers".to_string()),
+                logs: Some("/var/tmp/ngw".to_string()),
+                cache: Some("~/tmp/ngw-cache".to_string()),
+                ..PathsConfig::default()
+            },
+            ..Config::default()
+        };
+        let dirs = resolve_data_dirs(&|_| None, &config, &linux_base_dirs()).unwrap();
+        assert_eq!(dirs.photos, "/home/ada/Wallpapers/");
+        // Collections follow a moved photo directory
+        assert_eq!(dirs.collections, "/home/ada/Wallpapers/collections/");
+        assert_eq!(dirs.logs, "/var/tmp/ngw/");
+        assert_eq!(dirs.cache, "/home/ada/tmp/ngw-cache/");
+    }
+
+    #[test]
+    fn test_data_dirs_environment_overrides_config() {
+        let config = Config {
+            paths: PathsConfig {
+                photos: Some("~/Wallpapers".to_string()),
+                collections: Some("~/Collections".to_string()),
+                ..PathsConfig::default()
+            },
+            ..Config::default()
+        };
+        let env = |var: &str| match var {
+            PHOTO_DIR_ENV => Some("/mnt/photos/".to_string()),
+            DATA_DIR_ENV => Some("~/state".to_string()),
+            CACHE_DIR_ENV => Some("/tmp/ngw-cache".to_string()),
+            // Empty variables are ignored
+            COLLECTION_DIR_ENV => Some(String::new()),
+            _ => None,
+        };
+        let dirs = resolve_data_dirs(&env, &config, &linux_base_dirs()).unwrap();
+        assert_eq!(dirs.photos, "/mnt/photos/");
+        assert_eq!(dirs.collections, "/home/ada/Collections/");
+        assert_eq!(dirs.logs, "/home/ada/state/");
+        assert_eq!(dirs.cache, "/tmp/ngw-cache/");
+    }
+
+    #[test]
+    fn test_data_dirs_without_home_is_an_error() {
+        let nowhere = BaseDirs::default();
+        assert!(resolve_data_dirs(&|_| None, &Config::default(), &nowhere).is_err());
+
+        // A tilde path can't be expanded either
+        let env = |var: &str| (var == PHOTO_DIR_ENV).then(|| "~/Photos".to_string());
+        let base = BaseDirs {
+            data: Some(PathBuf::from("/data")),
+            ..BaseDirs::default()
+        };
+        assert!(resolve_data_dirs(&env, &Config::default(), &base).is_err());
+
+        // The cache needs a home too, unless it is configured
+        let env = |var: &str| match var {
+            PHOTO_DIR_ENV => Some("/photos".to_string()),
+            DATA_DIR_ENV => Some("/data".to_string()),
+            _ => None,
+        };
+        assert!(resolve_data_dirs(&env, &Config::default(), &nowhere).is_err());
+
+        // Absolute overrides need no home at all
+        let env = |var: &str| match var {
+            PHOTO_DIR_ENV => Some("/photos".to_string()),
+            DATA_DIR_ENV => Some("/data".to_string()),
+            CACHE_DIR_ENV => Some("/cache".to_string()),
+            _ => None,
+        };
+        let dirs = resolve_data_dirs(&env, &Config::default(), &nowhere).unwrap();
+        assert_eq!(dirs.collections, "/photos/collections/");
+    }
+
+    #[test]
+    fn test_config_path_respects_xdg_config_home() {
+        assert_eq!(
+            config_path_in(Some(PathBuf::from("/tmp/xdg"))),
+            PathBuf::from("/tmp/xdg/natgeo-wallpapers/config.toml")
+        );
+        assert_eq!(
+            config_path_in(Some(PathBuf::from("relative"))),
+            PathBuf::from(expand_tilde(CONFIG_PATH))
+        );
+        assert_eq!(
+            config_path_in(None),
+            PathBuf::from(expand_tilde(CONFIG_PATH))
+        );
+    }
+
+    // ========================================================================
+    // Event Tests
+    // ========================================================================
+
+    /// Collects `LEVEL message` for every event
+    #[derive(Clone, Default)]
+    struct CapturedEvents(Arc<Mutex<Vec<String>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CapturedEvents {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            struct Message(String);
+            impl tracing::field::Visit for Message {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    if field.name() == "message" {
+                        self.0 = format!("{value:?}");
+                    }
+                }
+            }
+            let mut message = Message(String::new());
+            event.record(&mut message);
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", event.metadata().level(), message.0));
+        }
+    }
+
+    /// Events emitted while running `f`
+    fn capture_events(f: impl FnOnce()) -> Vec<String> {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let capture = CapturedEvents::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, f);
+        let events = capture.0.lock().unwrap().clone();
+        events
+    }
+
+    #[test]
+    fn test_download_emits_start_and_skip_events() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+        fs::write(temp_dir.path().join("Fox.jpg"), b"jpeg").unwrap();
+        let log_path = format!("{dir}/Fox.log");
+        let client = NatGeoClient::new(HttpOptions::default()).unwrap();
+
+        let events = capture_events(|| {
+            let outcome = client
+                .download_with_options(
+                    "http://127.0.0.1:9/fox.jpg",
+                    dir,
+                    "Fox",
+                    &log_path,
+                    &DownloadOptions::default(),
+                )
+                .unwrap();
+            assert!(matches!(outcome, DownloadOutcome::AlreadyExists(_)));
+        });
+
+        assert!(events.contains(&"INFO Starting download".to_string()));
+        assert!(events.contains(&"INFO Photo already saved, skipping".to_string()));
+        // Per-download log entries are mirrored as debug events
+        assert!(events
+            .iter()
+            .any(|e| e.starts_with("DEBUG Photo already exists")));
+    }
+
+    #[test]
+    fn test_download_emits_failure_event() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+        let log_path = format!("{dir}/Fox.log");
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let client = NatGeoClient::new(HttpOptions {
+            retries: 0,
+            ..HttpOptions::default()
+        })
+        .unwrap();
+
+        let events = capture_events(|| {
+            let result = client.download_with_options(
+                &format!("http://127.0.0.1:{port}/fox.jpg"),
+                dir,
+                "Fox",
+                &log_path,
+                &DownloadOptions::default(),
+            );
+            assert!(result.is_err());
+        });
+
+        assert!(events.contains(&"INFO Starting download".to_string()));
+        assert!(events.contains(&"WARN Download failed".to_string()));
+        assert!(!events.iter().any(|e| e.contains("skipping")));
+    }
+
+    // ========================================================================
+    // Notification Tests
+    // ========================================================================
+
+    #[test]
+    fn test_notification_messages() {
+        let photo = Path::new("/photos/2026-02-01/Foggy_Morning.jpg");
+        let downloaded = Notification::downloaded("Foggy Morning", photo);
+        assert_eq!(downloaded.summary, "New NatGeo photo: Foggy Morning");
+        assert_eq!(downloaded.icon.as_deref(), Some(photo));
+        assert_eq!(downloaded.urgency, Urgency::Normal);
+
+        assert_eq!(Notification::wallpaper_set(photo, 0).body, "Foggy Morning");
+        assert_eq!(
+            Notification::wallpaper_set(photo, 2).body,
+            "Foggy Morning and 2 other photos"
+        );
+
+        let failed = Notification::failed("Download", &PhotoError::NoPhotos("empty".to_string()));
+        assert_eq!(failed.summary, "NatGeo wallpaper: Download failed");
+        assert_eq!(failed.body, "No photos found: empty");
+        assert_eq!(failed.urgency, Urgency::Critical);
+        assert_eq!(failed.icon, None);
+    }
+
+    #[test]
+    fn test_notify_send_args() {
+        let photo = Path::new("/photos/Fox.jpg");
+        assert_eq!(
+            notify_send_args(&Notification::downloaded("Fox", photo)),
+            [
+                "--app-name=natgeo-wallpapers",
+                "--urgency=normal",
+                "--icon=/photos/Fox.jpg",
+                "New NatGeo photo: Fox",
+                "/photos/Fox.jpg",
+            ]
+        );
+
+        let failed = Notification::failed("Download", &PhotoError::Interrupted);
+        let args = notify_send_args(&failed);
+        assert!(args.contains(&"--urgency=critical".to_string()));
+        assert!(!args.iter().any(|arg| arg.starts_with("--icon")));
+    }
+
+    #[test]
+    fn test_osascript_notification_escapes_quotes() {
+        let notification = Notification {
+            summary: "Say \"hi\"".to_string(),
+            body: r"C:\photos".to_string(),
+            icon: None,
+            urgency: Urgency::Normal,
+        };
+        assert_eq!(
+            osascript_notification(&notification),
+            r#"display notification "C:\\photos" with title "Say \"hi\"""#
+        );
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn test_desktop_notifier_runs_notify_send() {
+        let runner = FakeCommands::default();
+        let calls = runner.calls.clone();
+        let notifier = DesktopNotifier::new(Box::new(runner));
+
+        notifier
+            .notify(&Notification::downloaded("Fox", Path::new("/p/Fox.jpg")))
+            .unwrap();
+        assert_eq!(
+            *calls.borrow(),
+            ["notify-send --app-name=natgeo-wallpapers --urgency=normal --icon=/p/Fox.jpg New NatGeo photo: Fox /p/Fox.jpg"]
+        );
+
+        let failing = DesktopNotifier::new(Box::new(FakeCommands {
+            failing: vec!["notify-send"],
+            ..FakeCommands::default()
+        }));
+        assert!(failing
+            .notify(&Notification::failed("Download", &PhotoError::Interrupted))
+            .is_err());
+    }
+
+    // ========================================================================
+    // Hook Tests
+    // ========================================================================
+
+    /// A hook script in `dir` that records its `NATGEO_*` variables to `env.txt`
+    #[cfg(unix)]
+    fn recording_hook(dir: &Path) -> String {
+        use std::os::unix::fs::PermissionsExt;
+        let script = dir.join("record-env.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nenv | grep '^NATGEO_' | sort > \"$(dirname \"$0\")/env.txt\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        script.display().to_string()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_post_download_hook_gets_photo_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("hooks.log").display().to_string();
+        let hooks = HooksConfig {
+            post_download: Some(recording_hook(dir.path())),
+            ..HooksConfig::default()
+        };
+        let photo = dir.path().join("Red_Fox_&_Snow.jpg");
+        let context = HookContext::downloaded(&fox_info(), &photo);
+
+        run_hooks_with(&hooks, HookEvent::PostDownload, &context, &log_path).unwrap();
+
+        let env = std::fs::read_to_string(dir.path().join("env.txt")).unwrap();
+        assert_eq!(
+            env,
+            format!(
+                "NATGEO_EVENT=post-download\nNATGEO_PHOTO_PATH={}\nNATGEO_PHOTO_TITLE=Red Fox & Snow\n",
+                photo.display()
+            )
+        );
+        assert!(std::fs::read_to_string(&log_path)
+            .unwrap()
+            .contains("Ran post-download hook"));
+
+        // Nothing is configured for the other event
+        std::fs::remove_file(dir.path().join("env.txt")).unwrap();
+        run_hooks_with(&hooks, HookEvent::PostSet, &context, &log_path).unwrap();
+        assert!(!dir.path().join("env.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_post_set_hook_gets_assignment() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("wallpaper.log").display().to_string();
+        let hooks = HooksConfig {
+            post_set: Some(recording_hook(dir.path())),
+            ..HooksConfig::default()
+        };
+        let assignment = WallpaperAssignment {
+            location: "Monitor 2".to_string(),
+            photo_path: dir.path().join("Foggy_Morning.jpg"),
+            is_newest: true,
+            slot: 1,
+        };
+        // Without a sidecar the title comes from the file name
+        let context = HookContext::applied(&assignment);
+        assert_eq!(context.title, "Foggy Morning");
+
+        run_hooks_with(&hooks, HookEvent::PostSet, &context, &log_path).unwrap();
+
+        let env = std::fs::read_to_string(dir.path().join("env.txt")).unwrap();
+        assert!(env.contains("NATGEO_ASSIGNMENT=Monitor 2\n"));
+        assert!(env.contains("NATGEO_EVENT=post-set\n"));
+        assert!(env.contains("NATGEO_PHOTO_TITLE=Foggy Morning\n"));
+    }
+
+    #[test]
+    fn test_failing_hook_only_fails_when_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("hooks.log").display().to_string();
+        let context = HookContext::downloaded(&fox_info(), &dir.path().join("Fox.jpg"));
+        let mut hooks = HooksConfig {
+            post_download: Some("exit 3".to_string()),
+            ..HooksConfig::default()
+        };
+
+        run_hooks_with(&hooks, HookEvent::PostDownload, &context, &log_path).unwrap();
+        assert!(std::fs::read_to_string(&log_path)
+            .unwrap()
+            .contains("post-download hook failed: exit 3"));
+
+        hooks.strict = Some(true);
+        let err = run_hooks_with(&hooks, HookEvent::PostDownload, &context, &log_path).unwrap_err();
+        assert!(matches!(err, PhotoError::Hook(_)));
+        assert!(err
+            .to_string()
+            .starts_with("Hook error: post-download: exit 3"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_post_set_hooks_run_only_for_applied_wallpapers() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("wallpaper.log").display().to_string();
+        let mut hooks = HooksConfig {
+            post_set: Some(format!(
+                "echo \"$NATGEO_ASSIGNMENT\" >> '{}'; exit 1",
+                dir.path().join("ran.txt").display()
+            )),
+            ..HooksConfig::default()
+        };
+        let mut results: Vec<AssignmentResult> = report_assignments(3)
+            .into_iter()
+            .map(|assignment| AssignmentResult {
+                assignment,
+                result: Ok(()),
+            })
+            .collect();
+        results[1].result = Err(PhotoError::Wallpaper("busy".to_string()));
+
+        run_post_set_hooks(&hooks, &results, &log_path).unwrap();
+
+        // With strict hooks the failure is returned once every hook has run
+        hooks.strict = Some(true);
+        let err = run_post_set_hooks(&hooks, &results, &log_path).unwrap_err();
+        assert!(matches!(err, PhotoError::Hook(_)), "{err}");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("ran.txt")).unwrap(),
+            "Monitor 1\nMonitor 3\nMonitor 1\nMonitor 3\n"
+        );
+    }
+
+    #[test]
+    fn test_hooks_config() {
+        let (config, unknown) = parse_config(
+            "[hooks]\npost_download = \"notify-photo.sh\"\npost_set = \"  \"\nstrict = true\n",
+        )
+        .unwrap();
+        assert!(unknown.is_empty());
+        assert_eq!(
+            config.hooks.command(HookEvent::PostDownload),
+            Some("notify-photo.sh")
+        );
+        // A blank command is no hook
+        assert_eq!(config.hooks.command(HookEvent::PostSet), None);
+
+        let overrides = Config {
+            hooks: HooksConfig {
+                post_set: Some("echo set".to_string()),
+                ..HooksConfig::default()
+            },
+            ..Config::default()
+        };
+        let merged = config.merged(&overrides);
+        assert_eq!(merged.hooks.command(HookEvent::PostSet), Some("echo set"));
+        assert_eq!(merged.hooks.strict, Some(true));
+    }
+
+    // ========================================================================
+    // Current Wallpaper Link Tests
+    // ========================================================================
+
+    fn applied(photos: &[&str], failed: &[usize]) -> Vec<AssignmentResult> {
+        photos
+            .iter()
+            .enumerate()
+            .map(|(i, photo)| AssignmentResult {
+                assignment: WallpaperAssignment {
+                    location: format!("Monitor {}", i + 1),
+                    photo_path: PathBuf::from(photo),
+                    is_newest: i == 0,
+                    slot: i,
+                },
+                result: if failed.contains(&i) {
+                    Err(PhotoError::Wallpaper("busy".to_string()))
+                } else {
+                    Ok(())
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_current_wallpapers_by_mode() {
+        let results = applied(&["/p/a.jpg", "/p/b.jpg", "/p/c.jpg", "/p/d.jpg"], &[1]);
+        assert_eq!(
+            current_wallpapers(WallpaperMode::Monitors, 4, &results),
+            [
+                (0, PathBuf::from("/p/a.jpg")),
+                (2, PathBuf::from("/p/c.jpg")),
+                (3, PathBuf::from("/p/d.jpg")),
+            ]
+        );
+        assert_eq!(
+            current_wallpapers(WallpaperMode::VirtualDesktops, 2, &results),
+            [(0, PathBuf::from("/p/a.jpg"))]
+        );
+        // Two monitors by two desktops: only the first desktop counts
+        assert_eq!(
+            current_wallpapers(WallpaperMode::Both, 2, &results),
+            [(0, PathBuf::from("/p/a.jpg"))]
+        );
+        assert_eq!(current_link_name(0), "current");
+        assert_eq!(current_link_name(1), "current-monitor-2");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_current_links_follow_repeated_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let photos: Vec<PathBuf> = ["a.jpg", "b.jpg", "c.jpg"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, name).unwrap();
+                path.canonicalize().unwrap()
+            })
+            .collect();
+        let links_dir = dir.path().join("links");
+
+        let links = update_current_links(
+            &links_dir,
+            2,
+            &[(0, photos[0].clone()), (1, photos[1].clone())],
+        )
+        .unwrap();
+        assert_eq!(
+            links,
+            [
+                links_dir.join("current"),
+                links_dir.join("current-monitor-2")
+            ]
+        );
+        assert_eq!(std::fs::read_link(&links[0]).unwrap(), photos[0]);
+        assert_eq!(std::fs::read_link(&links[1]).unwrap(), photos[1]);
+
+        update_current_links(
+            &links_dir,
+            2,
+            &[(0, photos[2].clone()), (1, photos[0].clone())],
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_link(&links[0]).unwrap(), photos[2]);
+        assert_eq!(std::fs::read_link(&links[1]).unwrap(), photos[0]);
+        assert_eq!(std::fs::read_to_string(&links[0]).unwrap(), "c.jpg");
+
+        // Only the links are left behind, and they aren't taken for photos
+        let mut names: Vec<_> = std::fs::read_dir(&links_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["current", "current-monitor-2"]);
+        assert!(!has_photo_extension(&links[0]));
+
+        // Unplugging the second monitor drops its link rather than leaving it stale
+        update_current_links(&links_dir, 1, &[(0, photos[1].clone())]).unwrap();
+        assert_eq!(std::fs::read_link(&links[0]).unwrap(), photos[1]);
+        assert!(std::fs::symlink_metadata(&links[1]).is_err());
+    }
+
+    #[test]
+    fn test_current_link_is_on_by_default() {
+        let (config, _) = parse_config("[wallpaper]\ncurrent_link = false\n").unwrap();
+        assert_eq!(config.wallpaper.current_link, Some(false));
+        let dirs = DataDirs {
+            photos: "/photos/".to_string(),
+            collections: "/photos/collections/".to_string(),
+            logs: "/logs/".to_string(),
+            cache: "/cache/".to_string(),
+        };
+        assert_eq!(
+            Config::default()
+                .with_defaults(&dirs)
+                .wallpaper
+                .current_link,
+            Some(true)
+        );
+        assert_eq!(
+            config.with_defaults(&dirs).wallpaper.current_link,
+            Some(false)
+        );
+    }
+
+    // ========================================================================
+    // Pywal Tests
+    // ========================================================================
+
+    fn monitors_report(results: Vec<AssignmentResult>) -> WallpaperReport {
+        WallpaperReport {
+            desktop: DesktopEnvironment::Gnome,
+            requested_mode: WallpaperMode::Monitors,
+            mode: WallpaperMode::Monitors,
+            sort: SortOrder::Date,
+            spread: SpreadStrategy::None,
+            brightness: None,
+            photos_found: results.len(),
+            monitors: Vec::new(),
+            monitor_count: results.len(),
+            virtual_desktop_count: 1,
+            warnings: Vec::new(),
+            results,
+            dry_run: false,
+        }
+    }
+
+    fn system_with(commands: &[&'static str]) -> FakeSystem {
+        FakeSystem {
+            commands: commands.to_vec(),
+            processes: Vec::new(),
+            env: Vec::new(),
+            ..FakeSystem::default()
+        }
+    }
+
+    #[test]
+    fn test_pywal_themes_from_primary_wallpaper() {
+        assert_eq!(
+            pywal_args(Path::new("/p/Fox Den.jpg")),
+            ["-n", "-i", "/p/Fox Den.jpg"]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("wallpaper.log").display().to_string();
+        let runner = FakeCommands::default();
+        let calls = runner.calls.clone();
+        let report = monitors_report(applied(&["/p/a.jpg", "/p/b.jpg"], &[]));
+
+        run_pywal(&report, &runner, &system_with(&["wal"]), &log_path).unwrap();
+        assert_eq!(*calls.borrow(), ["wal -n -i /p/a.jpg"]);
+        assert!(std::fs::read_to_string(&log_path)
+            .unwrap()
+            .contains("Ran pywal on /p/a.jpg"));
+
+        // Nothing to theme from when the primary monitor wasn't set
+        calls.borrow_mut().clear();
+        let report = monitors_report(applied(&["/p/a.jpg", "/p/b.jpg"], &[0]));
+        run_pywal(&report, &runner, &system_with(&["wal"]), &log_path).unwrap();
+        assert!(calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_pywal_failures_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("wallpaper.log").display().to_string();
+        let report = monitors_report(applied(&["/p/a.jpg"], &[]));
+
+        let runner = FakeCommands::default();
+        let err = run_pywal(&report, &runner, &system_with(&[]), &log_path).unwrap_err();
+        assert!(err.to_string().contains("install pywal"));
+        assert!(runner.calls.borrow().is_empty());
+
+        let runner = FakeCommands {
+            failing: vec!["wal"],
+            ..FakeCommands::default()
+        };
+        assert!(run_pywal(&report, &runner, &system_with(&["wal"]), &log_path).is_err());
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        assert_eq!(log.matches("pywal failed").count(), 2);
+        // The wallpaper itself still counts as set
+        assert_eq!(report.succeeded(), 1);
+    }
+
+    // ========================================================================
+    // Photo Brightness Tests
+    // ========================================================================
+
+    /// Save a flat gray PNG of luminance `luma` at `path`
+    fn gray_photo(path: &Path, luma: u8) {
+        image::GrayImage::from_pixel(8, 8, image::Luma([luma]))
+            .save(path)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_brightness_filter_keeps_matching_photos() {
+        let dir = tempfile::tempdir().unwrap();
+        gray_photo(&dir.path().join("night.png"), 20);
+        gray_photo(&dir.path().join("snow.png"), 230);
+        gray_photo(&dir.path().join("dusk.png"), 90);
+        let path = dir.path().to_str().unwrap();
+        let names = |photos: Vec<PathBuf>| -> Vec<String> {
+            photos
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert_eq!(
+            average_luminance(&dir.path().join("snow.png")).unwrap(),
+            230
+        );
+        let dark =
+            find_photos_in_path_filtered(Some(path), SortOrder::Name, Some(BrightnessFilter::Dark))
+                .unwrap();
+        assert_eq!(names(dark), ["dusk.png", "night.png"]);
+        let light = find_photos_in_path_filtered(
+            Some(path),
+            SortOrder::Name,
+            Some(BrightnessFilter::Light),
+        )
+        .unwrap();
+        assert_eq!(names(light), ["snow.png"]);
+        let all = find_photos_in_path_filtered(Some(path), SortOrder::Name, None).unwrap();
+        assert_eq!(all.len(), 3);
+    }
+
+    #[test]
+    fn test_brightness_filter_with_no_matches_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        gray_photo(&dir.path().join("snow.png"), 230);
+        let err = find_photos_in_path_filtered(
+            dir.path().to_str(),
+            SortOrder::Date,
+            Some(BrightnessFilter::Dark),
+        )
+        .unwrap_err();
+        assert!(matches!(err, PhotoError::NoPhotos(_)));
+        assert!(err.to_string().contains("No dark photos"));
+    }
+
+    #[test]
+    fn test_photo_brightness_is_cached_in_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let photo = dir.path().join("Snow.png");
+        gray_photo(&photo, 230);
+        let sidecar = metadata_path_for(&photo);
+        write_metadata(
+            &sidecar,
+            &PhotoMetadata::for_download(&fox_info(), &photo).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(photo_brightness(&photo).unwrap(), 230);
+        assert_eq!(read_metadata(&sidecar).unwrap().brightness, Some(230));
+
+        // Later lookups trust the sidecar instead of decoding again
+        gray_photo(&photo, 10);
+        assert_eq!(photo_brightness(&photo).unwrap(), 230);
+
+        // Photos without a sidecar are measured every time
+        let plain = dir.path().join("Night.png");
+        gray_photo(&plain, 10);
+        assert_eq!(photo_brightness(&plain).unwrap(), 10);
+        assert!(!metadata_path_for(&plain).exists());
+    }
+
+    #[test]
+    fn test_auto_brightness_follows_color_scheme() {
+        let gnome_dark = FakeCommands {
+            outputs: vec![("gsettings", "get", "'prefer-dark'\n")],
+            ..FakeCommands::default()
+        };
+        assert_eq!(
+            BrightnessFilter::Auto.resolve(DesktopEnvironment::Gnome, &gnome_dark),
+            Some(BrightnessFilter::Dark)
+        );
+        let gnome_default = FakeCommands {
+            outputs: vec![("gsettings", "get", "'default'\n")],
+            ..FakeCommands::default()
+        };
+        assert_eq!(
+            BrightnessFilter::Auto.resolve(DesktopEnvironment::Cinnamon, &gnome_default),
+            Some(BrightnessFilter::Light)
+        );
+
+        let kde = FakeCommands {
+            outputs: vec![("kreadconfig6", "--group", "BreezeDark\n")],
+            ..FakeCommands::default()
+        };
+        let calls = kde.calls.clone();
+        assert_eq!(
+            system_prefers_dark(DesktopEnvironment::KdePlasma6, &kde),
+            Some(true)
+        );
+        assert_eq!(
+            *calls.borrow(),
+            ["kreadconfig6 --group General --key ColorScheme"]
+        );
+
+        // Nothing to go by
+        let unknown = FakeCommands {
+            failing: vec!["gsettings"],
+            ..FakeCommands::default()
+        };
+        assert_eq!(
+            BrightnessFilter::Auto.resolve(DesktopEnvironment::Gnome, &unknown),
+            None
+        );
+        assert_eq!(
+            BrightnessFilter::Light.resolve(DesktopEnvironment::Gnome, &unknown),
+            Some(BrightnessFilter::Light)
+        );
+    }
+
+    // ========================================================================
+    // Photo Dimension Tests
+    // ========================================================================
+
+    /// Save a `width` x `height` PNG at `path`
+    fn sized_photo(path: &Path, width: u32, height: u32) -> PathBuf {
+        image::RgbImage::from_pixel(width, height, image::Rgb([90, 120, 60]))
+            .save(path)
+            .unwrap();
+        path.to_path_buf()
+    }
+
+    #[test]
+    fn test_dimension_filter_matches() {
+        assert_eq!(orientation_of((1920, 1080)), Some(Orientation::Landscape));
+        assert_eq!(orientation_of((1080, 1920)), Some(Orientation::Portrait));
+        assert_eq!(orientation_of((800, 800)), None);
+
+        let filter = DimensionFilter {
+            min_width: Some(1920),
+            min_height: Some(1080),
+            orientation: Some(Orientation::Landscape),
+        };
+        assert!(filter.matches((2560, 1080)));
+        assert!(!filter.matches((1280, 1080)));
+        assert!(!filter.matches((1920, 1000)));
+        assert!(!filter.matches((2000, 2000)));
+        assert_eq!(
+            filter.to_string(),
+            "landscape, at least 1920px wide, at least 1080px tall"
+        );
+        assert!(DimensionFilter::default().is_empty());
+        assert!(DimensionFilter::default().matches((1, 1)));
+    }
+
+    #[test]
+    fn test_filter_by_dimensions_mixed_photos() {
+        let dir = tempfile::tempdir().unwrap();
+        let wide = sized_photo(&dir.path().join("wide.png"), 48, 20);
+        let tall = sized_photo(&dir.path().join("tall.png"), 20, 48);
+        let small = sized_photo(&dir.path().join("small.png"), 12, 6);
+        let photos = vec![wide.clone(), tall.clone(), small.clone()];
+
+        let landscape = DimensionFilter {
+            orientation: Some(Orientation::Landscape),
+            ..DimensionFilter::default()
+        };
+        assert_eq!(
+            filter_by_dimensions(&photos, &landscape),
+            [wide.clone(), small]
+        );
+        let big_landscape = DimensionFilter {
+            min_width: Some(24),
+            ..landscape
+        };
+        assert_eq!(filter_by_dimensions(&photos, &big_landscape), [wide]);
+        let portrait = DimensionFilter {
+            orientation: Some(Orientation::Portrait),
+            ..DimensionFilter::default()
+        };
+        assert_eq!(filter_by_dimensions(&photos, &portrait), [tall]);
+
+        // Filtering out everything falls back to every photo
+        let huge = DimensionFilter {
+            min_width: Some(4000),
+            ..DimensionFilter::default()
+        };
+        let (kept, warning) = keep_fitting_photos(photos.clone(), &huge);
+        assert_eq!(kept, photos);
+        assert_eq!(
+            warning.as_deref(),
+            Some("No photos are at least 4000px wide; using all 3 photos")
+        );
+        assert_eq!(keep_fitting_photos(photos, &big_landscape).1, None);
+    }
+
+    #[test]
+    fn test_photo_dimensions_come_from_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let photo = sized_photo(&dir.path().join("Fox.png"), 30, 20);
+        assert_eq!(photo_dimensions(&photo).unwrap(), (30, 20));
+
+        let sidecar = metadata_path_for(&photo);
+        let mut metadata = PhotoMetadata::for_download(&fox_info(), &photo).unwrap();
+        assert_eq!((metadata.width, metadata.height), (Some(30), Some(20)));
+        metadata.width = Some(3000);
+        metadata.height = Some(2000);
+        write_metadata(&sidecar, &metadata).unwrap();
+        assert_eq!(photo_dimensions(&photo).unwrap(), (3000, 2000));
+        assert_eq!(PhotoEntry::from_path(&photo).unwrap().width, Some(3000));
+    }
+
+    #[test]
+    fn test_stats_count_orientations() {
+        let dir = tempfile::tempdir().unwrap();
+        sized_photo(&dir.path().join("wide.png"), 48, 20);
+        sized_photo(&dir.path().join("wider.png"), 64, 20);
+        sized_photo(&dir.path().join("tall.png"), 20, 48);
+        sized_photo(&dir.path().join("square.png"), 20, 20);
+
+        let stats = gather_stats(dir.path()).unwrap();
+        assert_eq!(stats.other_photos, 4);
+        assert_eq!((stats.landscape, stats.portrait), (2, 1));
+    }
+
+    // ========================================================================
+    // Smart Assignment Tests
+    // ========================================================================
+
+    const UHD: (u32, u32) = (3840, 2160);
+    const PORTRAIT_WUXGA: (u32, u32) = (1200, 1920);
+
+    #[test]
+    fn test_aspect_score() {
+        assert!(aspect_score((1920, 1080), UHD).abs() < 1e-9);
+        // A photo twice as wide as the screen is as far off as one half as wide
+        let wide = aspect_score((3840, 1080), (1920, 1080));
+        let narrow = aspect_score((960, 1080), (1920, 1080));
+        assert!((wide - narrow).abs() < 1e-9);
+        // 3:2 is close to 16:9, portrait is far from it
+        assert!(aspect_score((3000, 2000), UHD) < ASPECT_TOLERANCE);
+        assert!(aspect_score((1600, 2000), UHD) > 0.5);
+        assert!(
+            aspect_score((1600, 2000), PORTRAIT_WUXGA) < aspect_score((3000, 2000), PORTRAIT_WUXGA)
+        );
+    }
+
+    #[test]
+    fn test_pick_photo_prefers_matching_shape() {
+        // Newest first: two landscape photos, a portrait one, and an unreadable one
+        let photos = [
+            Some((3000, 2000)),
+            Some((1920, 1080)),
+            Some((1600, 2000)),
+            None,
+        ];
+        let none_used = [false; 4];
+        let uhd = monitor(UHD.0, UHD.1, 1.0);
+        let portrait = monitor(PORTRAIT_WUXGA.0, PORTRAIT_WUXGA.1, 1.0);
+
+        // 3:2 is close enough to 16:9 that the newest photo wins
+        assert_eq!(pick_photo(&uhd, &photos, &none_used), Some(0));
+        assert_eq!(pick_photo(&portrait, &photos, &none_used), Some(2));
+        assert_eq!(
+            pick_photo(&uhd, &photos, &[true, false, false, false]),
+            Some(1)
+        );
+        // With only a landscape photo and an unreadable one left, the landscape one is closer
+        assert_eq!(
+            pick_photo(&portrait, &photos, &[true, false, true, false]),
+            Some(1)
+        );
+        assert_eq!(
+            pick_photo(&uhd, &photos, &[true, true, true, false]),
+            Some(3)
+        );
+        assert_eq!(pick_photo(&uhd, &photos, &[true; 4]), None);
+        assert_eq!(pick_photo(&uhd, &[], &[]), None);
+    }
+
+    #[test]
+    fn test_pick_photo_prefers_sharp_photos_by_physical_pixels() {
+        // Newest first: a 1080p photo, then a 4K one of the same shape
+        let photos = [Some((1920, 1080)), Some((3840, 2160))];
+        let none_used = [false; 2];
+        let cases = [
+            // (physical width, height, scale, expected photo)
+            (1920, 1080, 1.0, 0),
+            // A 4K panel at 200% is 1080p logically, but still has 4K physical pixels
+            (3840, 2160, 2.0, 1),
+            (3840, 2160, 1.0, 1),
+            // 1440p at 125% is 2048x1152 logically, but its 2560x1440 physical
+            // pixels (scale × logical) are more than the 1080p photo covers
+            (2560, 1440, 1.25, 1),
+            // A 1080p panel at 150% has 1080p physical pixels, which the 1080p photo covers
+            (1920, 1080, 1.5, 0),
+        ];
+        for (width, height, scale, expected) in cases {
+            assert_eq!(
+                pick_photo(&monitor(width, height, scale), &photos, &none_used),
+                Some(expected),
+                "{width}x{height} @ {scale}x"
+            );
+        }
+
+        // When every photo is too small, the newest is still shown
+        let small = [Some((1280, 720)), Some((1920, 1080))];
+        assert_eq!(
+            pick_photo(&monitor(3840, 2160, 2.0), &small, &none_used),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_build_assignments_smart_gives_portrait_monitor_portrait_photo() {
+        let dir = tempfile::tempdir().unwrap();
+        let photos = vec![
+            sized_photo(&dir.path().join("wide1.png"), 48, 32),
+            sized_photo(&dir.path().join("wide2.png"), 64, 36),
+            sized_photo(&dir.path().join("tall.png"), 32, 40),
+        ];
+        let monitors = [monitor(3840, 2160, 1.0), monitor(1200, 1920, 1.0)];
+        let chosen = |assignments: Vec<WallpaperAssignment>| -> Vec<(String, String)> {
+            assignments
+                .into_iter()
+                .map(|a| {
+                    let name = a
+                        .photo_path
+                        .file_stem()
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned();
+                    (a.location, name)
+                })
+                .collect()
+        };
+
+        let assignments = build_assignments_smart(
+            WallpaperMode::Monitors,
+            &photos,
+            &monitors,
+            2,
+            1,
+            &TargetFilter::default(),
+        );
+        assert!(assignments[0].is_newest);
+        assert!(!assignments[1].is_newest);
+        assert_eq!(
+            chosen(assignments),
+            [
+                ("Monitor 1 (TEST-1)".to_string(), "wide1".to_string()),
+                ("Monitor 2 (TEST-1)".to_string(), "tall".to_string()),
+            ]
+        );
+
+        // Photos are reused once each has been shown
+        let assignments = build_assignments_smart(
+            WallpaperMode::Both,
+            &photos,
+            &monitors,
+            2,
+            2,
+            &TargetFilter::default(),
+        );
+        assert_eq!(
+            chosen(assignments),
+            [
+                ("Monitor 1 (TEST-1), VD 1".to_string(), "wide1".to_string()),
+                ("Monitor 2 (TEST-1), VD 1".to_string(), "tall".to_string()),
+                ("Monitor 1 (TEST-1), VD 2".to_string(), "wide2".to_string()),
+                ("Monitor 2 (TEST-1), VD 2".to_string(), "tall".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_assignments_smart_falls_back_to_round_robin() {
+        let photos = vec![PathBuf::from("/p/a.jpg"), PathBuf::from("/p/b.jpg")];
+        let monitors = [monitor(3840, 2160, 1.0), monitor(1200, 1920, 1.0)];
+        for (mode, monitors, count) in [
+            // Nothing known about the monitors
+            (WallpaperMode::Monitors, &monitors[..0], 2),
+            // The backend sees a different number of monitors
+            (WallpaperMode::Monitors, &monitors[..], 3),
+            // One photo spans every monitor
+            (WallpaperMode::VirtualDesktops, &monitors[..], 2),
+        ] {
+            assert_eq!(
+                build_assignments_smart(
+                    mode,
+                    &photos,
+                    monitors,
+                    count,
+                    3,
+                    &TargetFilter::default()
+                ),
+                build_assignments(mode, &photos, count, 3, &TargetFilter::default())
+            );
+        }
+    }
+
+    #[test]
+    fn test_monitor_parsers_apply_rotation() {
+        let kscreen = r#"{"outputs": [
+            {"name": "DP-2", "enabled": true, "connected": true, "scale": 1, "rotation": 8,
+             "currentModeId": "1", "modes": [{"id": "1", "size": {"width": 1920, "height": 1200}}]}
+        ]}"#;
+        assert_eq!(
+            parse_kscreen_outputs(kscreen)[0].orientation(),
+            Some(Orientation::Portrait)
+        );
+
+        let sway = r#"[
+            {"name": "DP-2", "active": true, "scale": 1.0, "transform": "flipped-270",
+             "current_mode": {"width": 1920, "height": 1200}},
+            {"name": "DP-3", "active": true, "scale": 1.0, "transform": "180",
+             "current_mode": {"width": 1920, "height": 1200}}
+        ]"#;
+        let monitors = parse_sway_outputs(sway);
+        assert_eq!(monitors[0].size(PixelSpace::Physical), PORTRAIT_WUXGA);
+        assert_eq!(monitors[1].size(PixelSpace::Physical), (1920, 1200));
+
+        let hyprland =
+            r#"[{"name": "DP-2", "width": 1920, "height": 1200, "scale": 1.0, "transform": 1}]"#;
+        assert_eq!(
+            parse_hyprland_monitors(hyprland)[0].size(PixelSpace::Physical),
+            PORTRAIT_WUXGA
+        );
+    }
+
+    // ========================================================================
+    // Resized Wallpaper Tests
+    // ========================================================================
+
+    #[test]
+    fn test_resize_for_monitor_output_dimensions() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = temp_dir.path().join("derived");
+        let photo = sized_photo(&temp_dir.path().join("wide.png"), 400, 100);
+
+        for mode in [ResizeMode::Crop, ResizeMode::Fit, ResizeMode::Stretch] {
+            let resized = resize_for_monitor(&photo, (160, 90), mode, &cache).unwrap();
+            assert!(resized.starts_with(&cache));
+            assert!(resized
+                .to_str()
+                .unwrap()
+                .ends_with(&format!("_160x90_{mode}.jpg")));
+            assert_eq!(image::image_dimensions(&resized).unwrap(), (160, 90));
+        }
+
+        // Fit letterboxes the wide photo with black bars above and below
+        let fit = resize_for_monitor(&photo, (160, 90), ResizeMode::Fit, &cache).unwrap();
+        let fit = image::open(fit).unwrap().to_rgb8();
+        assert!(fit.get_pixel(80, 2).0.iter().all(|&c| c < 16));
+        assert!(fit.get_pixel(80, 45).0[1] > 64);
+    }
+
+    #[test]
+    fn test_resize_for_monitor_reuses_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = temp_dir.path().join("derived");
+        let photo = sized_photo(&temp_dir.path().join("photo.png"), 300, 200);
+
+        let first = resize_for_monitor(&photo, (120, 80), ResizeMode::Crop, &cache).unwrap();
+        fs::write(&first, b"cached").unwrap();
+        let second = resize_for_monitor(&photo, (120, 80), ResizeMode::Crop, &cache).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(fs::read(&second).unwrap(), b"cached");
+
+        // Another size or mode is a separate entry
+        let other = resize_for_monitor(&photo, (80, 120), ResizeMode::Crop, &cache).unwrap();
+        assert_ne!(other, first);
+        let other = resize_for_monitor(&photo, (120, 80), ResizeMode::Fit, &cache).unwrap();
+        assert_ne!(other, first);
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_resizing_backend_sizes_each_monitor() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = temp_dir.path().join("derived");
+        let photo = sized_photo(&temp_dir.path().join("photo.png"), 300, 200);
+        let monitors = [monitor(120, 80, 1.0), monitor(60, 120, 1.0)];
+        let inner = MockBackend::new(2, 1);
+        let backend = ResizingBackend::new(&inner, &monitors, ResizeMode::Crop, cache.clone());
+
+        backend.set(Target::Monitor(1), &photo).unwrap();
+        backend.set(Target::AllMonitors, &photo).unwrap();
+        backend
+            .set(Target::Monitor(0), Path::new("/missing.png"))
+            .unwrap();
+
+        let calls = inner.calls.borrow();
+        assert_eq!(calls[0].0, Target::Monitor(1));
+        assert!(calls[0].1.starts_with(&cache));
+        assert_eq!(image::image_dimensions(&calls[0].1).unwrap(), (60, 120));
+        assert_eq!(image::image_dimensions(&calls[1].1).unwrap(), (120, 80));
+        // A photo that can't be resized is passed through unchanged
+        assert_eq!(calls[2].1, PathBuf::from("/missing.png"));
+    }
+
+    #[test]
+    fn test_purge_image_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let derived = temp_dir.path().join("derived");
+        let converted = temp_dir.path().join("converted");
+        fs::create_dir_all(&derived).unwrap();
+        fs::create_dir_all(&converted).unwrap();
+        fs::write(derived.join("a_1x1_crop.jpg"), b"12345").unwrap();
+        fs::write(converted.join("b.jpg"), b"123").unwrap();
+        fs::write(temp_dir.path().join("photos.json"), b"[]").unwrap();
+
+        let dry = purge_image_cache(temp_dir.path(), true).unwrap();
+        assert_eq!(dry, CachePurge { files: 2, bytes: 8 });
+        assert!(derived.join("a_1x1_crop.jpg").exists());
+
+        let purge = purge_image_cache(temp_dir.path(), false).unwrap();
+        assert_eq!(purge, dry);
+        assert_eq!(fs::read_dir(&derived).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(&converted).unwrap().count(), 0);
+        assert!(temp_dir.path().join("photos.json").exists());
+
+        // An empty or missing cache is fine
+        let empty = TempDir::new().unwrap();
+        assert_eq!(
+            purge_image_cache(empty.path(), false).unwrap(),
+            CachePurge::default()
+        );
+    }
+
+    // ========================================================================
+    // Spanned Wallpaper Tests
+    // ========================================================================
+
+    /// A `width` x `height` monitor at `(x, y)` on a desktop laid out in physical pixels
+    fn placed(x: i32, y: i32, width: u32, height: u32) -> MonitorInfo {
+        MonitorInfo {
+            layout: Some(LayoutRect {
+                x,
+                y,
+                width,
+                height,
+            }),
+            ..monitor(width, height, 1.0)
+        }
+    }
+
+    /// The `(x, y, width, height)` of each tile's crop
+    fn crops(tiles: &[SpanTile]) -> Vec<(u32, u32, u32, u32)> {
+        tiles
+            .iter()
+            .map(|t| (t.x, t.y, t.width, t.height))
+            .collect()
+    }
+
+    #[test]
+    fn test_span_tiles_side_by_side() {
+        let monitors = [placed(0, 0, 1920, 1080), placed(1920, 0, 1920, 1080)];
+
+        // A photo exactly the size of the desktop is cut in half
+        let tiles = span_tiles((3840, 1080), &monitors).unwrap();
+        assert_eq!(crops(&tiles), [(0, 0, 1920, 1080), (1920, 0, 1920, 1080)]);
+        assert!(tiles.iter().all(|t| t.output == (1920, 1080)));
+
+        // A larger photo of the same shape is cut at the same proportions
+        let tiles = span_tiles((7680, 2160), &monitors).unwrap();
+        assert_eq!(crops(&tiles), [(0, 0, 3840, 2160), (3840, 0, 3840, 2160)]);
+
+        // A wider photo loses its sides evenly
+        let tiles = span_tiles((6000, 1080), &monitors).unwrap();
+        assert_eq!(
+            crops(&tiles),
+            [(1080, 0, 1920, 1080), (3000, 0, 1920, 1080)]
+        );
+
+        // Order follows the monitors, not their positions
+        let swapped = [monitors[1].clone(), monitors[0].clone()];
+        let tiles = span_tiles((3840, 1080), &swapped).unwrap();
+        assert_eq!(crops(&tiles), [(1920, 0, 1920, 1080), (0, 0, 1920, 1080)]);
+    }
+
+    #[test]
+    fn test_span_tiles_mixed_resolutions() {
+        // A 1080p monitor beside a taller 1440p one, centred vertically
+        let monitors = [placed(0, 180, 1920, 1080), placed(1920, 0, 2560, 1440)];
+        let tiles = span_tiles((4480, 1440), &monitors).unwrap();
+        assert_eq!(crops(&tiles), [(0, 180, 1920, 1080), (1920, 0, 2560, 1440)]);
+        assert_eq!(tiles[0].output, (1920, 1080));
+        assert_eq!(tiles[1].output, (2560, 1440));
+
+        // A HiDPI panel covers the same part of the photo as its logical size,
+        // but its tile is scaled to its physical resolution
+        let hidpi = MonitorInfo {
+            layout: Some(LayoutRect {
+                x: 0,
+                y: 0,
+                width: 1920,
+                height: 1080,
+            }),
+            ..monitor(3840, 2160, 2.0)
+        };
+        let monitors = [hidpi, placed(1920, 0, 1920, 1080)];
+        let tiles = span_tiles((3840, 1080), &monitors).unwrap();
+        assert_eq!(crops(&tiles), [(0, 0, 1920, 1080), (1920, 0, 1920, 1080)]);
+        assert_eq!(tiles[0].output, (3840, 2160));
+        assert_eq!(tiles[1].output, (1920, 1080));
+    }
+
+    #[test]
+    fn test_span_tiles_share_edges() {
+        // Stacked monitors above the origin, with a photo that scales unevenly
+        let monitors = [placed(0, -1080, 1920, 1080), placed(0, 0, 1920, 1080)];
+        let tiles = span_tiles((1000, 1000), &monitors).unwrap();
+        assert_eq!(crops(&tiles), [(56, 0, 888, 500), (56, 500, 888, 500)]);
+        assert_eq!(tiles[0].y + tiles[0].height, tiles[1].y);
+
+        // Three monitors of different widths leave no gaps or overlaps
+        let monitors = [
+            placed(0, 0, 1280, 1024),
+            placed(1280, 0, 1920, 1080),
+            placed(3200, 0, 1366, 768),
+        ];
+        let tiles = span_tiles((3001, 677), &monitors).unwrap();
+        assert_eq!(tiles[0].x + tiles[0].width, tiles[1].x);
+        assert_eq!(tiles[1].x + tiles[1].width, tiles[2].x);
+        assert!(tiles.iter().all(|t| t.y + t.height <= 677));
+        assert!(tiles[2].x + tiles[2].width <= 3001);
+    }
+
+    #[test]
+    fn test_span_tiles_needs_positions() {
+        assert_eq!(span_tiles((3840, 1080), &[]), None);
+        let monitors = [placed(0, 0, 1920, 1080), monitor(1920, 1080, 1.0)];
+        assert_eq!(span_tiles((3840, 1080), &monitors), None);
+        assert_eq!(span_tiles((0, 0), &monitors[..1]), None);
+    }
+
+    #[test]
+    fn test_span_photo_cuts_and_caches_tiles() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = temp_dir.path().join("derived");
+        let photo = temp_dir.path().join("panorama.png");
+        image::RgbImage::from_fn(200, 50, |x, _| {
+            if x < 100 {
+                image::Rgb([200, 0, 0])
+            } else {
+                image::Rgb([0, 0, 200])
+            }
+        })
+        .save(&photo)
+        .unwrap();
+        let monitors = [placed(0, 0, 80, 40), placed(80, 0, 80, 40)];
+
+        let tiles = span_photo(&photo, &monitors, &cache).unwrap();
+        assert_eq!(tiles.len(), 2);
+        let left = image::open(&tiles[0]).unwrap().to_rgb8();
+        let right = image::open(&tiles[1]).unwrap().to_rgb8();
+        assert_eq!(left.dimensions(), (80, 40));
+        assert_eq!(right.dimensions(), (80, 40));
+        assert!(left.get_pixel(40, 20).0[0] > 150);
+        assert!(right.get_pixel(40, 20).0[2] > 150);
+
+        fs::write(&tiles[0], b"cached").unwrap();
+        assert_eq!(span_photo(&photo, &monitors, &cache).unwrap(), tiles);
+        assert_eq!(fs::read(&tiles[0]).unwrap(), b"cached");
+    }
+
+    #[test]
+    fn test_spanning_backend_sets_a_tile_per_monitor() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = temp_dir.path().join("derived");
+        let photo = sized_photo(&temp_dir.path().join("photo.png"), 160, 40);
+        let monitors = [placed(0, 0, 80, 40), placed(80, 0, 80, 40)];
+        let inner = MockBackend::new(2, 1);
+        let backend = SpanningBackend::new(&inner, &monitors, cache.clone());
+
+        backend.set(Target::Monitor(0), &photo).unwrap();
+        backend.set(Target::Monitor(1), &photo).unwrap();
+
+        let calls = inner.calls.borrow();
+        assert!(calls.iter().all(|(_, path)| path.starts_with(&cache)));
+        assert_ne!(calls[0].1, calls[1].1);
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_preparing_backend_for_span() {
+        let inner = MockBackend::new(2, 1);
+        let options = WallpaperOptions {
+            span: true,
+            resize: Some(ResizeMode::Crop),
+            ..WallpaperOptions::default()
+        };
+
+        let mut warnings = Vec::new();
+        let monitors = [placed(0, 0, 1920, 1080), placed(1920, 0, 1920, 1080)];
+        assert!(preparing_backend(&inner, &monitors, &options, &mut warnings).is_some());
+        assert!(warnings.is_empty());
+
+        let monitors = [monitor(1920, 1080, 1.0), monitor(1920, 1080, 1.0)];
+        assert!(preparing_backend(&inner, &monitors, &options, &mut warnings).is_none());
+        assert_eq!(
+            warnings,
+            ["Monitor positions are unknown, so the photo is not spanned"]
+        );
+
+        // GNOME spans on its own; other single-wallpaper desktops can't
+        let gnome = GsettingsBackend::new(
+            DesktopEnvironment::Gnome,
+            Box::new(FakeCommands::default()),
+            FitMode::Zoom,
+            None,
+        );
+        assert!(gnome.spans_natively());
+        assert!(preparing_backend(&gnome, &[], &options, &mut Vec::new()).is_some());
+        let plasma = PlasmaApplyBackend::new(Box::new(FakeCommands::default()));
+        assert!(!plasma.spans_natively());
+        assert!(preparing_backend(&plasma, &[], &options, &mut Vec::new()).is_none());
+    }
+
+    // ========================================================================
+    // gsettings Backend Tests
+    // ========================================================================
+
+    #[test]
+    fn test_gsettings_backend_commands() {
+        let runner = FakeCommands::default();
+        let calls = runner.calls.clone();
+        let backend = GsettingsBackend::new(
+            DesktopEnvironment::Gnome,
+            Box::new(runner),
+            FitMode::Zoom,
+            Some(PathBuf::from("/photos/night.jpg")),
+        );
+
+        backend
+            .set(Target::AllMonitors, Path::new("/photos/day.jpg"))
+            .unwrap();
+        backend.set_spanned(Path::new("/photos/pano.jpg")).unwrap();
+        assert_eq!(
+            *calls.borrow(),
+            [
+                "gsettings set org.gnome.desktop.background picture-options zoom",
+                "gsettings set org.gnome.desktop.background picture-uri file:///photos/day.jpg",
+                "gsettings set org.gnome.desktop.background picture-uri-dark file:///photos/night.jpg",
+                "gsettings set org.gnome.desktop.background picture-options spanned",
+                "gsettings set org.gnome.desktop.background picture-uri file:///photos/pano.jpg",
+                "gsettings set org.gnome.desktop.background picture-uri-dark file:///photos/night.jpg",
+            ]
+        );
+
+        // A failing gsettings stops before the photo is changed
+        let runner = FakeCommands {
+            failing: vec!["gsettings"],
+            ..FakeCommands::default()
+        };
+        let calls = runner.calls.clone();
+        let backend = GsettingsBackend::new(
+            DesktopEnvironment::Mate,
+            Box::new(runner),
+            FitMode::Fit,
+            None,
+        );
+        assert!(backend
+            .set(Target::AllMonitors, Path::new("/photos/day.jpg"))
+            .is_err());
+        assert_eq!(
+            *calls.borrow(),
+            ["gsettings set org.mate.background picture-options scaled"]
+        );
+    }
+
+    #[test]
+    fn test_pick_dark_photo() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        gray_photo(&dir.path().join("a_bright.png"), 220);
+        gray_photo(&dir.path().join("b_dim.png"), 140);
+        assert_eq!(
+            pick_dark_photo(path, SortOrder::Name).unwrap(),
+            dir.path().join("b_dim.png")
+        );
+
+        gray_photo(&dir.path().join("c_night.png"), 30);
+        gray_photo(&dir.path().join("d_dusk.png"), 60);
+        assert_eq!(
+            pick_dark_photo(path, SortOrder::Name).unwrap(),
+            dir.path().join("c_night.png")
+        );
+
+        let empty = tempfile::tempdir().unwrap();
+        assert!(pick_dark_photo(empty.path().to_str().unwrap(), SortOrder::Name).is_err());
+    }
+
+    // ========================================================================
+    // feh Tests
+    // ========================================================================
+
+    /// A feh backend for `monitors` screens that records its commands, with
+    /// its restore script in `dir`
+    fn feh_backend(
+        monitors: usize,
+        dir: &Path,
+    ) -> (FehBackend, std::rc::Rc<std::cell::RefCell<Vec<String>>>) {
+        let runner = FakeCommands::default();
+        let calls = runner.calls.clone();
+        let mut backend = FehBackend::new(
+            Box::new(runner),
+            FitMode::Zoom,
+            monitors,
+            Some(":0".to_string()),
+        );
+        backend.fehbg = dir.join(".fehbg");
+        (backend, calls)
+    }
+
+    #[test]
+    fn test_feh_args() {
+        let a = Path::new("/photos/a.jpg");
+        let b = Path::new("/photos/b b.jpg");
+        assert_eq!(
+            FehBackend::args(&[a], FitMode::Zoom, false),
+            ["--bg-fill", "/photos/a.jpg"]
+        );
+        assert_eq!(
+            FehBackend::args(&[a, b], FitMode::Fit, false),
+            ["--bg-max", "/photos/a.jpg", "/photos/b b.jpg"]
+        );
+        assert_eq!(
+            FehBackend::args(&[a], FitMode::Stretch, true),
+            ["--no-xinerama", "--bg-scale", "/photos/a.jpg"]
+        );
+        let options = [
+            FitMode::Fit,
+            FitMode::Zoom,
+            FitMode::Stretch,
+            FitMode::Center,
+            FitMode::Tile,
+        ]
+        .map(FitMode::feh_option);
+        assert_eq!(
+            options,
+            [
+                "--bg-max",
+                "--bg-fill",
+                "--bg-scale",
+                "--bg-center",
+                "--bg-tile"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_feh_sets_every_screen_at_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backend, calls) = feh_backend(2, dir.path());
+        assert_eq!(backend.capabilities().monitors, 2);
+        assert!(backend.capabilities().per_monitor);
+
+        backend
+            .set(Target::Monitor(0), Path::new("/photos/a.jpg"))
+            .unwrap();
+        backend
+            .set(Target::Monitor(1), Path::new("/photos/b.jpg"))
+            .unwrap();
+        backend
+            .set(Target::AllMonitors, Path::new("/photos/c.jpg"))
+            .unwrap();
+        assert_eq!(
+            *calls.borrow(),
+            [
+                "feh --bg-fill /photos/a.jpg /photos/a.jpg",
+                "feh --bg-fill /photos/a.jpg /photos/b.jpg",
+                "feh --bg-fill /photos/c.jpg /photos/c.jpg",
+            ]
+        );
+
+        let (backend, calls) = feh_backend(1, dir.path());
+        assert!(!backend.capabilities().per_monitor);
+        backend.set_spanned(Path::new("/photos/pano.jpg")).unwrap();
+        assert_eq!(
+            *calls.borrow(),
+            ["feh --no-xinerama --bg-fill /photos/pano.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_feh_needs_x11() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut backend, calls) = feh_backend(1, dir.path());
+        backend.display = None;
+        let err = backend
+            .set(Target::AllMonitors, Path::new("/photos/a.jpg"))
+            .unwrap_err();
+        assert!(err.to_string().contains("DISPLAY"));
+        assert!(calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_fehbg_restores() {
+        let dir = tempfile::tempdir().unwrap();
+        let fehbg = dir.path().join(".fehbg");
+        let (a, b) = (Path::new("/photos/a.jpg"), Path::new("/photos/b.jpg"));
+        assert!(!fehbg_restores(&fehbg, &[a]));
+
+        fs::write(
+            &fehbg,
+            "#!/bin/sh\nfeh --no-fehbg --bg-fill '/photos/a.jpg' '/photos/b.jpg' \n",
+        )
+        .unwrap();
+        assert!(fehbg_restores(&fehbg, &[a, b]));
+        assert!(!fehbg_restores(&fehbg, &[Path::new("/photos/c.jpg")]));
+    }
+
+    // ========================================================================
+    // Plasma Slideshow Tests
+    // ========================================================================
+
+    #[test]
+    fn test_interval_seconds() {
+        assert_eq!(interval_seconds("30m"), Some(1800));
+        assert_eq!(interval_seconds("1h"), Some(3600));
+        assert_eq!(interval_seconds("1h30m"), Some(5400));
+        assert_eq!(interval_seconds("2h5m"), Some(7500));
+        assert_eq!(interval_seconds("0m"), None);
+        assert_eq!(interval_seconds("90"), None);
+        assert_eq!(interval_seconds("99999999999999999999h"), None);
+    }
+
+    #[test]
+    fn test_plasma_slideshow_script() {
+        let script = plasma_slideshow_script(Path::new("/home/ada/Pictures/NatGeo"), 1800);
+        assert!(script.contains("d.wallpaperPlugin = 'org.kde.slideshow';"));
+        assert!(script.contains("Array('Wallpaper', 'org.kde.slideshow', 'General')"));
+        assert!(script.contains("d.writeConfig('SlidePaths', '/home/ada/Pictures/NatGeo');"));
+        assert!(script.contains("d.writeConfig('SlideInterval', 1800);"));
+        assert!(script.contains("for (var i = 0; i < allDesktops.length; i++)"));
+
+        // Quotes and backslashes can't end the string early
+        let script = plasma_slideshow_script(Path::new("/photos/Ada's \\ pics"), 60);
+        assert!(script.contains(r"'/photos/Ada\'s \\ pics'"));
+        assert_eq!(js_string("a\nb\u{2028}"), r"a\nb\u2028");
+    }
+
+    #[test]
+    fn test_set_plasma_slideshow_runs_qdbus() {
+        let dir = tempfile::tempdir().unwrap();
+        for qdbus in ["qdbus6", "qdbus"] {
+            let runner = FakeCommands::default();
+            set_plasma_slideshow(PlasmaTransport::Qdbus(qdbus), dir.path(), 3600, &runner).unwrap();
+            let calls = runner.calls.borrow();
+            assert_eq!(calls.len(), 1);
+            assert!(calls[0].starts_with(&format!(
+                "{qdbus} org.kde.plasmashell /PlasmaShell org.kde.PlasmaShell.evaluateScript "
+            )));
+            assert!(calls[0].contains("'SlideInterval', 3600"));
+        }
+
+        let runner = FakeCommands::default();
+        let missing = dir.path().join("missing");
+        let transport = PlasmaTransport::Qdbus("qdbus6");
+        assert!(set_plasma_slideshow(transport, &missing, 60, &runner).is_err());
+        assert!(runner.calls.borrow().is_empty());
+    }
+
+    // ========================================================================
+    // Plasma Screen Tests
+    // ========================================================================
+
+    #[test]
+    fn test_plasma_wallpaper_script_picks_screen_by_position() {
+        let script = plasma_wallpaper_script(1, Path::new("/photos/a.jpg"));
+        // Screens are ordered by geometry, not by containment order
+        assert!(script.starts_with(PLASMA_SORTED_SCREENS));
+        assert!(script.contains("for (var s = 0; s < screenCount; s++)"));
+        assert!(script.contains("return ga.x - gb.x || ga.y - gb.y;"));
+        assert!(script.contains("var screen = screens[1];"));
+        assert!(script.contains("throw new Error('No monitor 2');"));
+        // Every containment on that screen is set, not desktops()[1]
+        assert!(script.contains("if (d.screen != screen) {"));
+        assert!(!script.contains("allDesktops[1]"));
+        assert!(script.contains("d.wallpaperPlugin = 'org.kde.image';"));
+        assert!(script.contains("d.writeConfig('Image', 'file:///photos/a.jpg');"));
+
+        let script = plasma_wallpaper_script(0, Path::new("/photos/it's.jpg"));
+        assert!(script.contains("var screen = screens[0];"));
+        assert!(script.contains(r"'file:///photos/it\'s.jpg'"));
+    }
+
+    #[test]
+    fn test_plasma_screen_count_script_counts_distinct_screens() {
+        assert!(PLASMA_SCREEN_COUNT_SCRIPT.contains("var screen = allDesktops[i].screen;"));
+        assert!(PLASMA_SCREEN_COUNT_SCRIPT.contains("if (screen >= 0 && !seen[screen]) {"));
+        assert!(PLASMA_SCREEN_COUNT_SCRIPT.ends_with("print(count);"));
+        assert!(!PLASMA_SCREEN_COUNT_SCRIPT.contains("print(allDesktops.length)"));
+    }
+
+    #[test]
+    fn test_parse_kscreen_outputs_orders_by_position() {
+        let json = r#"{"outputs": [
+            {"name": "HDMI-1", "enabled": true, "connected": true, "scale": 1,
+             "pos": {"x": 1920, "y": 0}, "currentModeId": "1", "modes": [
+                {"id": "1", "size": {"width": 1920, "height": 1080}}]},
+            {"name": "DP-2", "enabled": true, "connected": true, "scale": 1,
+             "pos": {"x": 0, "y": 1080}, "currentModeId": "1", "modes": [
+                {"id": "1", "size": {"width": 1920, "height": 1080}}]},
+            {"name": "DP-1", "enabled": true, "connected": true, "scale": 1,
+             "pos": {"x": 0, "y": 0}, "currentModeId": "1", "modes": [
+                {"id": "1", "size": {"width": 1920, "height": 1080}}]}
+        ]}"#;
+        let names: Vec<String> = parse_kscreen_outputs(json)
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        assert_eq!(names, ["DP-1", "DP-2", "HDMI-1"]);
+    }
+
+    // ========================================================================
+    // Plasma Transport Tests
+    // ========================================================================
+
+    fn plasma_system(commands: &[&'static str], bus_names: &[&'static str]) -> FakeSystem {
+        FakeSystem {
+            commands: commands.to_vec(),
+            bus_names: bus_names.to_vec(),
+            ..FakeSystem::default()
+        }
+    }
+
+    #[test]
+    fn test_plasma_transport_prefers_dbus() {
+        let system = plasma_system(&["qdbus6", "qdbus"], &["org.kde.plasmashell"]);
+        for de in [
+            DesktopEnvironment::KdePlasma6,
+            DesktopEnvironment::KdePlasma5,
+        ] {
+            assert_eq!(
+                PlasmaTransport::select(de, &system),
+                Some(PlasmaTransport::DBus)
+            );
+        }
+    }
+
+    #[test]
+    fn test_plasma_transport_falls_back_to_qdbus() {
+        let both = plasma_system(&["qdbus6", "qdbus"], &[]);
+        assert_eq!(
+            PlasmaTransport::select(DesktopEnvironment::KdePlasma6, &both),
+            Some(PlasmaTransport::Qdbus("qdbus6"))
+        );
+        assert_eq!(
+            PlasmaTransport::select(DesktopEnvironment::KdePlasma5, &both),
+            Some(PlasmaTransport::Qdbus("qdbus"))
+        );
+
+        // Each Plasma needs its own tool when the bus can't be reached
+        let only_qdbus = plasma_system(&["qdbus"], &[]);
+        assert_eq!(
+            PlasmaTransport::select(DesktopEnvironment::KdePlasma6, &only_qdbus),
+            None
+        );
+        assert_eq!(
+            PlasmaTransport::select(DesktopEnvironment::KdePlasma6, &plasma_system(&[], &[])),
+            None
+        );
+
+        // Other desktops never talk to plasmashell
+        let everything = plasma_system(&["qdbus6"], &["org.kde.plasmashell"]);
+        assert_eq!(
+            PlasmaTransport::select(DesktopEnvironment::Gnome, &everything),
+            None
+        );
+    }
+
+    #[test]
+    fn test_plasma_transport_runs_qdbus_tool() {
+        let runner = FakeCommands::default();
+        PlasmaTransport::Qdbus("qdbus6")
+            .evaluate_script("print(1);", &runner)
+            .unwrap();
+        assert_eq!(
+            runner.calls.borrow().as_slice(),
+            ["qdbus6 org.kde.plasmashell /PlasmaShell org.kde.PlasmaShell.evaluateScript print(1);"]
+        );
+    }
+
+    #[test]
+    fn test_detect_plasma_on_the_bus_without_qdbus() {
+        let on_bus = |env: Vec<(&'static str, &'static str)>| {
+            detect_desktop_environment_with(&FakeSystem {
+                commands: vec!["gsettings"],
+                env,
+                bus_names: vec!["org.kde.plasmashell"],
+                ..FakeSystem::default()
+            })
+        };
+        assert_eq!(on_bus(vec![]), DesktopEnvironment::KdePlasma6);
+        assert_eq!(
+            on_bus(vec![("KDE_SESSION_VERSION", "6")]),
+            DesktopEnvironment::KdePlasma6
+        );
+        assert_eq!(
+            on_bus(vec![("KDE_SESSION_VERSION", "5")]),
+            DesktopEnvironment::KdePlasma5
+        );
+
+        // Running but unreachable: no bus name and no qdbus
+        assert_eq!(
+            detect(&["gsettings"], &["plasmashell"], &[]),
+            DesktopEnvironment::Unknown
+        );
+    }
+
+    // ========================================================================
+    // Plasma Virtual Desktop Tests
+    // ========================================================================
+
+    #[test]
+    fn test_plasma_containments_script_lists_screen_and_desktop() {
+        let script = plasma_containments_script();
+        assert!(script.starts_with(PLASMA_SORTED_SCREENS));
+        assert!(script.contains("var desktop = typeof d.desktop === 'number' ? d.desktop : -1;"));
+        // Screens are reported by position, matching monitor numbers
+        assert!(script.contains("print(screens.indexOf(d.screen) + ' ' + desktop);"));
+    }
+
+    #[test]
+    fn test_parse_plasma_containments() {
+        let containments = parse_plasma_containments("0 -1\n1 -1\nnoise\n-1 2\n");
+        assert_eq!(
+            containments,
+            [
+                PlasmaContainment {
+                    monitor: Some(0),
+                    virtual_desktop: None,
+                },
+                PlasmaContainment {
+                    monitor: Some(1),
+                    virtual_desktop: None,
+                },
+                PlasmaContainment {
+                    monitor: None,
+                    virtual_desktop: Some(2),
+                },
+            ]
+        );
+        assert!(!has_per_desktop_containments(&containments[..2]));
+        assert!(has_per_desktop_containments(&containments));
+        assert!(!has_per_desktop_containments(&parse_plasma_containments(
+            ""
+        )));
+    }
+
+    #[test]
+    fn test_plasma_virtual_desktop_script() {
+        let script = plasma_virtual_desktop_script(2, Some(1), Path::new("/photos/a.jpg"));
+        assert!(script.starts_with(PLASMA_SORTED_SCREENS));
+        assert!(script.contains("var screen = screens[1];"));
+        assert!(script.contains("throw new Error('No monitor 2');"));
+        assert!(script.contains("if (d.desktop !== 2 || d.screen != screen) {"));
+        assert!(script.contains("d.writeConfig('Image', 'file:///photos/a.jpg');"));
+        assert!(script.contains("throw new Error('No containment for virtual desktop 3');"));
+
+        // Every monitor of the desktop
+        let script = plasma_virtual_desktop_script(0, None, Path::new("/photos/it's.jpg"));
+        assert!(!script.contains("var screen ="));
+        assert!(script.contains("if (d.desktop !== 0) {"));
+        assert!(script.contains(r"'file:///photos/it\'s.jpg'"));
+    }
+
+    // ========================================================================
+    // Activity Tests
+    // ========================================================================
+
+    #[test]
+    fn test_build_assignments_per_activity() {
+        let photos = vec![
+            PathBuf::from("/photos/a.jpg"),
+            PathBuf::from("/photos/b.jpg"),
+        ];
+        let assignments = build_assignments(
+            WallpaperMode::Activities,
+            &photos,
+            2,
+            3,
+            &TargetFilter::default(),
+        );
+        let locations: Vec<&str> = assignments.iter().map(|a| a.location.as_str()).collect();
+        // One per activity whatever the monitor count, reusing photos
+        assert_eq!(locations, ["Activity 1", "Activity 2", "Activity 3"]);
+        assert_eq!(assignments[2].photo_path, photos[0]);
+        assert!(assignments[0].is_newest);
+        assert!(!assignments[1].is_newest);
+
+        let monitors = [monitor(1920, 1080, 1.0), monitor(1080, 1920, 1.0)];
+        let smart = build_assignments_smart(
+            WallpaperMode::Activities,
+            &photos,
+            &monitors,
+            2,
+            3,
+            &TargetFilter::default(),
+        );
+        assert_eq!(smart.len(), 3);
+        assert_eq!(smart[1].location, "Activity 2");
+    }
+
+    #[test]
+    fn test_parse_activity_list() {
+        let output = "c9d7e8f0-1111-2222-3333-444455556666\n\n  0a1b2c3d-aaaa-bbbb-cccc-ddddeeeeffff \nc9d7e8f0-1111-2222-3333-444455556666\n";
+        assert_eq!(
+            parse_activity_list(output),
+            [
+                "c9d7e8f0-1111-2222-3333-444455556666",
+                "0a1b2c3d-aaaa-bbbb-cccc-ddddeeeeffff"
+            ]
+        );
+        assert!(parse_activity_list("").is_empty());
+    }
+
+    #[test]
+    fn test_plasma_activity_script_filters_by_activity() {
+        let script = plasma_activity_script("c9d7e8f0", Path::new("/photos/a.jpg"));
+        assert!(script.contains("if (d.activity != 'c9d7e8f0') {"));
+        assert!(script.contains("d.wallpaperPlugin = 'org.kde.image';"));
+        assert!(script.contains("d.writeConfig('Image', 'file:///photos/a.jpg');"));
+        assert!(script.contains("throw new Error('No desktops in activity c9d7e8f0');"));
+
+        let script = plasma_activity_script("it's", Path::new("/photos/a.jpg"));
+        assert!(script.contains(r"if (d.activity != 'it\'s') {"));
+    }
+
+    #[test]
+    fn test_apply_activities_without_activities_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("wallpaper.log");
+        let backend = MockBackend::new(2, 1);
+        assert_eq!(backend.activities(), None);
+
+        let result = apply_with_backend(
+            &backend,
+            WallpaperMode::Activities,
+            report_assignments(2),
+            log_path.to_str().unwrap(),
+            &CancelFlag::new(),
+        );
+        assert!(result.is_err());
+        assert!(backend.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_activity_mode_rejected_without_activities() {
+        let backend = MockBackend::new(2, 1);
+        let err = requested_activities(&backend, WallpaperMode::Activities).unwrap_err();
+        assert!(err.to_string().contains("--mode activities"), "{err}");
+        assert!(requested_activities(&backend, WallpaperMode::Monitors)
+            .unwrap()
+            .is_empty());
+    }
+
+    // ========================================================================
+    // Target Filter Tests
+    // ========================================================================
+
+    fn targets(monitors: &[usize], desktops: &[usize]) -> TargetFilter {
+        TargetFilter {
+            monitors: monitors.to_vec(),
+            desktops: desktops.to_vec(),
+            ..TargetFilter::default()
+        }
+    }
+
+    fn locations_and_slots(assignments: &[WallpaperAssignment]) -> Vec<(&str, usize)> {
+        assignments
+            .iter()
+            .map(|a| (a.location.as_str(), a.slot))
+            .collect()
+    }
+
+    #[test]
+    fn test_build_assignments_for_targets() {
+        let photos = vec![
+            PathBuf::from("/photos/a.jpg"),
+            PathBuf::from("/photos/b.jpg"),
+        ];
+
+        let assignments =
+            build_assignments(WallpaperMode::Monitors, &photos, 3, 1, &targets(&[1], &[]));
+        assert_eq!(locations_and_slots(&assignments), [("Monitor 2", 1)]);
+        // The targeted monitor gets the newest photo
+        assert_eq!(assignments[0].photo_path, photos[0]);
+        assert!(assignments[0].is_newest);
+
+        let assignments = build_assignments(
+            WallpaperMode::VirtualDesktops,
+            &photos,
+            2,
+            4,
+            &targets(&[], &[0, 3]),
+        );
+        assert_eq!(
+            locations_and_slots(&assignments),
+            [("Virtual Desktop 1", 0), ("Virtual Desktop 4", 3)]
+        );
+
+        // No targets means everything
+        let all = build_assignments(WallpaperMode::Both, &photos, 2, 2, &TargetFilter::default());
+        assert_eq!(all.len(), 4);
+        assert_eq!(all.iter().map(|a| a.slot).collect::<Vec<_>>(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_build_assignments_both_mode_target_matrix() {
+        let photos = vec![PathBuf::from("/photos/a.jpg")];
+        let both = |monitors: &[usize], desktops: &[usize]| {
+            let assignments = build_assignments(
+                WallpaperMode::Both,
+                &photos,
+                2,
+                3,
+                &targets(monitors, desktops),
+            );
+            assignments
+                .iter()
+                .map(|a| (a.location.clone(), a.slot))
+                .collect::<Vec<_>>()
+        };
+        let expect = |pairs: &[(&str, usize)]| {
+            pairs
+                .iter()
+                .map(|(l, s)| ((*l).to_string(), *s))
+                .collect::<Vec<_>>()
+        };
+
+        // One monitor on every desktop
+        assert_eq!(
+            both(&[1], &[]),
+            expect(&[
+                ("Monitor 2, VD 1", 1),
+                ("Monitor 2, VD 2", 3),
+                ("Monitor 2, VD 3", 5)
+            ])
+        );
+        // Every monitor on one desktop
+        assert_eq!(
+            both(&[], &[2]),
+            expect(&[("Monitor 1, VD 3", 4), ("Monitor 2, VD 3", 5)])
+        );
+        // One monitor on one desktop
+        assert_eq!(both(&[0], &[1]), expect(&[("Monitor 1, VD 2", 2)]));
+        // Several of each
+        assert_eq!(both(&[0, 1], &[0, 2]).len(), 4);
+    }
+
+    #[test]
+    fn test_build_assignments_smart_for_targets() {
+        let dir = TempDir::new().unwrap();
+        let landscape = sized_photo(&dir.path().join("landscape.jpg"), 64, 36);
+        let portrait = sized_photo(&dir.path().join("portrait.jpg"), 36, 64);
+        let photos = vec![landscape, portrait.clone()];
+        let monitors = [monitor(1920, 1080, 1.0), monitor(1080, 1920, 1.0)];
+
+        let assignments = build_assignments_smart(
+            WallpaperMode::Monitors,
+            &photos,
+            &monitors,
+            2,
+            1,
+            &targets(&[1], &[]),
+        );
+        assert_eq!(
+            locations_and_slots(&assignments),
+            [("Monitor 2 (TEST-1)", 1)]
+        );
+        assert_eq!(assignments[0].photo_path, portrait);
+    }
+
+    #[test]
+    fn test_target_filter_validate() {
+        let both = targets(&[1], &[2]);
+        assert!(both.validate(WallpaperMode::Both, 2, 3).is_ok());
+
+        let err = both.validate(WallpaperMode::Both, 1, 3).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("no monitor 2: 1 monitor(s) detected"),
+            "{err}"
+        );
+        let err = both.validate(WallpaperMode::Both, 2, 2).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("no virtual desktop 3: 2 virtual desktop(s) detected"),
+            "{err}"
+        );
+
+        // Targets the mode doesn't have
+        assert!(targets(&[], &[0])
+            .validate(WallpaperMode::Monitors, 2, 3)
+            .is_err());
+        assert!(targets(&[0], &[])
+            .validate(WallpaperMode::VirtualDesktops, 2, 3)
+            .is_err());
+        assert!(targets(&[0], &[])
+            .validate(WallpaperMode::Activities, 2, 3)
+            .is_err());
+        assert!(TargetFilter::default()
+            .validate(WallpaperMode::Activities, 2, 3)
+            .is_ok());
+    }
+
+    // ========================================================================
+    // Monitor Name Tests
+    // ========================================================================
+
+    fn named(names: &[&str]) -> TargetFilter {
+        TargetFilter {
+            monitor_names: names.iter().map(ToString::to_string).collect(),
+            ..TargetFilter::default()
+        }
+    }
+
+    /// Indices the names resolve to among `monitors`
+    fn resolved(names: &[&str], monitors: &[MonitorInfo]) -> Vec<usize> {
+        named(names).resolve_names(monitors).unwrap().monitors
+    }
+
+    #[test]
+    fn test_monitor_names_from_kscreen() {
+        // Listed right to left, numbered left to right
+        let json = r#"{"outputs": [
+            {"name": "HDMI-A-1", "enabled": true, "connected": true, "scale": 1,
+             "pos": {"x": 2560, "y": 0}, "currentModeId": "1", "modes": [
+                {"id": "1", "size": {"width": 1920, "height": 1080}}]},
+            {"name": "DP-1", "enabled": true, "connected": true, "scale": 1,
+             "pos": {"x": 0, "y": 0}, "currentModeId": "2", "modes": [
+                {"id": "2", "size": {"width": 2560, "height": 1440}}]}
+        ]}"#;
+        let monitors = parse_kscreen_outputs(json);
+        let names: Vec<_> = monitors.iter().filter_map(MonitorInfo::connector).collect();
+        assert_eq!(names, ["DP-1", "HDMI-A-1"]);
+        assert_eq!(resolved(&["HDMI-A-1", "DP-1"], &monitors), [1, 0]);
+    }
+
+    #[test]
+    fn test_monitor_names_from_sway() {
+        let json = r#"[
+            {"name": "eDP-1", "active": true, "scale": 1.0,
+             "current_mode": {"width": 1920, "height": 1080}},
+            {"name": "DP-3", "active": false, "scale": 1.0, "current_mode": {}},
+            {"name": "DP-4", "active": true, "scale": 1.0,
+             "current_mode": {"width": 2560, "height": 1440}}
+        ]"#;
+        let monitors = parse_sway_outputs(json);
+        let names: Vec<_> = monitors.iter().filter_map(MonitorInfo::connector).collect();
+        assert_eq!(names, ["eDP-1", "DP-4"]);
+        // Inactive outputs can't be targeted
+        assert_eq!(resolved(&["dp-4"], &monitors), [1]);
+        assert!(named(&["DP-3"]).resolve_names(&monitors).is_err());
+    }
+
+    #[test]
+    fn test_monitor_names_from_xrandr() {
+        let text = "Screen 0: minimum 8 x 8, current 3840 x 1080, maximum 32767 x 32767
+eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 194mm
+   1920x1080     60.00*+
+HDMI-1 connected 1920x1080+1920+0 (normal left inverted right x axis y axis) 527mm x 296mm
+   1920x1080     60.00*+
+";
+        let monitors = parse_xrandr_outputs(text);
+        let names: Vec<_> = monitors.iter().filter_map(MonitorInfo::connector).collect();
+        assert_eq!(names, ["eDP-1", "HDMI-1"]);
+        assert_eq!(resolved(&["HDMI-1"], &monitors), [1]);
+    }
+
+    #[test]
+    fn test_resolve_monitor_names() {
+        let mut monitors = vec![monitor(1920, 1080, 1.0), monitor(2560, 1440, 1.0)];
+        monitors[0].name = "eDP-1".to_string();
+        monitors[1].name = "DP-1".to_string();
+
+        // Names join numbered monitors, without repeats
+        let targets = TargetFilter {
+            monitors: vec![1],
+            monitor_names: vec!["DP-1".to_string(), "eDP-1".to_string()],
+            desktops: vec![0],
+        };
+        assert_eq!(
+            targets.resolve_names(&monitors).unwrap(),
+            TargetFilter {
+                monitors: vec![1, 0],
+                monitor_names: Vec::new(),
+                desktops: vec![0],
+            }
+        );
+
+        let err = named(&["HDMI-A-2"]).resolve_names(&monitors).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("no monitor named HDMI-A-2: detected eDP-1, DP-1"),
+            "{err}"
+        );
+
+        // Without names there is nothing to match
+        monitors[0].name = "unknown".to_string();
+        monitors[1].name = String::new();
+        let err = named(&["DP-1"]).resolve_names(&monitors).unwrap_err();
+        assert!(err.to_string().contains("couldn't be read"), "{err}");
+        assert_eq!(monitor_label(0, &monitors), "Monitor 1");
+    }
+
+    #[test]
+    fn test_locations_show_monitor_names() {
+        let photos = vec![PathBuf::from("/photos/a.jpg")];
+        let mut monitors = vec![monitor(1920, 1080, 1.0), monitor(2560, 1440, 1.0)];
+        monitors[1].name = "DP-1".to_string();
+        let slots = assignment_slots(WallpaperMode::Both, 2, 1, &monitors);
+        let locations: Vec<&str> = slots.iter().map(|s| s.location.as_str()).collect();
+        assert_eq!(
+            locations,
+            ["Monitor 1 (TEST-1), VD 1", "Monitor 2 (DP-1), VD 1"]
+        );
+
+        // Unknown when the monitors can't be listed
+        let assignments = build_assignments(
+            WallpaperMode::Monitors,
+            &photos,
+            2,
+            1,
+            &TargetFilter::default(),
+        );
+        assert_eq!(assignments[1].location, "Monitor 2");
+    }
+
+    // ========================================================================
+    // Monitor Hotplug Tests
+    // ========================================================================
+
+    #[test]
+    fn test_decode_sway_event() {
+        assert!(decode_sway_event(r#"{"change":"unspecified"}"#));
+        assert!(decode_sway_event(r#"{ "change": "unspecified" }"#));
+        // The subscription acknowledgement and garbage are not changes
+        assert!(!decode_sway_event(r#"{"success":true}"#));
+        assert!(!decode_sway_event(""));
+        assert!(!decode_sway_event("not json"));
+    }
+
+    #[test]
+    fn test_is_kscreen_output_signal() {
+        assert!(is_kscreen_output_signal(
+            "org.kde.kscreen.Backend",
+            "configChanged"
+        ));
+        assert!(is_kscreen_output_signal(
+            "org.kde.KScreen",
+            "outputConnected"
+        ));
+        assert!(is_kscreen_output_signal(
+            "org.kde.KScreen",
+            "unknownOutputConnected"
+        ));
+        assert!(!is_kscreen_output_signal(
+            "org.kde.kscreen.Backend",
+            "backendReady"
+        ));
+        assert!(!is_kscreen_output_signal("org.kde.KWin", "configChanged"));
+    }
+
+    #[test]
+    fn test_output_watcher_select() {
+        let sway = FakeSystem {
+            commands: vec!["swaymsg", "xrandr"],
+            env: vec![("SWAYSOCK", "/run/user/1000/sway.sock"), ("DISPLAY", ":0")],
+            ..FakeSystem::default()
+        };
+        assert_eq!(
+            OutputWatcher::select(DesktopEnvironment::Swww, &sway),
+            Some(OutputWatcher::Sway)
+        );
+        let x11 = FakeSystem {
+            commands: vec!["xrandr"],
+            env: vec![("DISPLAY", ":0")],
+            ..FakeSystem::default()
+        };
+        assert_eq!(
+            OutputWatcher::select(DesktopEnvironment::Feh, &x11),
+            Some(OutputWatcher::Xrandr)
+        );
+        assert_eq!(
+            OutputWatcher::select(DesktopEnvironment::Unknown, &FakeSystem::default()),
+            None
+        );
+        if cfg!(target_os = "linux") {
+            assert_eq!(
+                OutputWatcher::select(DesktopEnvironment::KdePlasma6, &x11),
+                Some(OutputWatcher::KScreen)
+            );
+        }
+    }
+
+    #[test]
+    fn test_debouncer_collapses_bursts() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut debouncer = Debouncer::new(Duration::from_millis(100));
+        assert_eq!(debouncer.remaining(at(0)), None);
+        assert!(!debouncer.fire(at(0)));
+
+        // A burst keeps pushing the deadline back
+        for ms in [0, 40, 80] {
+            debouncer.event(at(ms));
+        }
+        assert_eq!(
+            debouncer.remaining(at(100)),
+            Some(Duration::from_millis(80))
+        );
+        assert!(!debouncer.fire(at(150)));
+        assert!(debouncer.fire(at(180)));
+        assert!(!debouncer.fire(at(500)));
+
+        // A later event starts a new burst
+        debouncer.event(at(600));
+        assert!(debouncer.fire(at(700)));
+        assert!(!debouncer.flush());
+    }
+
+    #[test]
+    fn test_run_debounced_handles_pending_change_when_events_end() {
+        let (sender, events) = std::sync::mpsc::channel();
+        for _ in 0..5 {
+            sender.send(()).unwrap();
+        }
+        drop(sender);
+        let mut changes = 0;
+        let result = run_debounced(&events, Duration::from_hours(1), &CancelFlag::new(), || {
+            changes += 1;
+        });
+        assert!(matches!(result, Err(PhotoError::Command(_))));
+        assert_eq!(changes, 1);
+    }
+
+    #[test]
+    fn test_run_debounced_fires_after_quiet_period() {
+        let (sender, events) = std::sync::mpsc::channel();
+        let feeder = std::thread::spawn(move || {
+            for _ in 0..2 {
+                for _ in 0..3 {
+                    sender.send(()).unwrap();
+                }
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        });
+        let mut changes = 0;
+        let result = run_debounced(
+            &events,
+            Duration::from_millis(20),
+            &CancelFlag::new(),
+            || {
+                changes += 1;
+            },
+        );
+        feeder.join().unwrap();
+        assert!(result.is_err());
+        assert_eq!(changes, 2);
+    }
+
+    #[test]
+    fn test_run_debounced_stops_when_cancelled() {
+        let (_sender, events) = std::sync::mpsc::channel::<()>();
+        let cancel = CancelFlag::new();
+        cancel.cancel();
+        let mut changes = 0;
+        let result = run_debounced(&events, HOTPLUG_DEBOUNCE, &cancel, || changes += 1);
+        assert!(matches!(result, Err(PhotoError::Interrupted)));
+        assert_eq!(changes, 0);
+    }
+
+    // ========================================================================
+    // Daemon Tests
+    // ========================================================================
+
+    #[test]
+    fn test_rotation_schedule_ticks() {
+        let start = Instant::now();
+        let at = |mins| start + Duration::from_mins(mins);
+        let mut schedule = RotationSchedule::new(Duration::from_mins(20), start);
+        // The first rotation happens right away
+        assert!(schedule.due(start));
+        schedule.advance(at(0));
+        assert!(!schedule.due(at(19)));
+        assert_eq!(schedule.remaining(at(15)), Duration::from_mins(5));
+
+        // A slow rotation doesn't shift the beat
+        assert!(schedule.due(at(20)));
+        schedule.advance(at(21));
+        assert_eq!(schedule.remaining(at(21)), Duration::from_mins(19));
+
+        // Missed rotations are skipped, not caught up on
+        schedule.advance(at(100));
+        assert_eq!(schedule.remaining(at(100)), Duration::from_mins(20));
+    }
+
+    #[test]
+    fn test_rotation_schedule_rotate_now_restarts_interval() {
+        let start = Instant::now();
+        let at = |mins| start + Duration::from_mins(mins);
+        let mut schedule = RotationSchedule::new(Duration::from_mins(20), start);
+        schedule.advance(at(0));
+        schedule.rotate_now(at(7));
+        assert!(schedule.due(at(7)));
+        schedule.advance(at(7));
+        assert_eq!(schedule.remaining(at(7)), Duration::from_mins(20));
+    }
+
+    #[test]
+    fn test_run_rotation_rescans_and_stops_when_cancelled() {
+        let (sender, rescan) = std::sync::mpsc::channel();
+        sender.send(()).unwrap();
+        sender.send(()).unwrap();
+        let cancel = CancelFlag::new();
+        let mut schedule = RotationSchedule::new(Duration::from_hours(1), Instant::now());
+        let mut rotations = 0;
+        // One rotation at start, then one per rescan
+        run_rotation(&mut schedule, &rescan, &cancel, || {
+            rotations += 1;
+            if rotations == 3 {
+                cancel.cancel();
+            }
+        });
+        assert_eq!(rotations, 3);
+        assert!(!schedule.due(Instant::now()));
+    }
+
+    #[test]
+    fn test_pidfile_blocks_second_instance() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("logs/daemon.pid");
+        let pidfile = PidFile::acquire(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap().trim(),
+            std::process::id().to_string()
+        );
+
+        let err = PidFile::acquire_with(&path, |_| true).unwrap_err();
+        assert!(err.to_string().contains(&std::process::id().to_string()));
+
+        drop(pidfile);
+        assert!(!path.exists());
+        assert!(PidFile::acquire(&path).is_ok());
+    }
+
+    #[test]
+    fn test_pidfile_takes_over_stale_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("daemon.pid");
+        fs::write(&path, "4000000\n").unwrap();
+        let pidfile = PidFile::acquire_with(&path, |pid| pid != 4_000_000).unwrap();
+        assert_eq!(
+            fs::read_to_string(pidfile.path()).unwrap().trim(),
+            std::process::id().to_string()
+        );
+
+        // A pidfile that doesn't hold a pid is stale too
+        drop(pidfile);
+        fs::write(&path, "garbage").unwrap();
+        assert!(PidFile::acquire_with(&path, |_| true).is_ok());
+    }
+
+    // ========================================================================
+    // Rotation History Tests
+    // ========================================================================
+
+    fn applied_on(target: &str, slot: usize, photo: &Path) -> AssignmentResult {
+        AssignmentResult {
+            assignment: WallpaperAssignment {
+                location: target.to_string(),
+                photo_path: photo.to_path_buf(),
+                is_newest: false,
+                slot,
+            },
+            result: Ok(()),
+        }
+    }
+
+    fn pool(count: usize) -> Vec<PathBuf> {
+        (0..count)
+            .map(|i| PathBuf::from(format!("/photos/{}.jpg", i)))
+            .collect()
+    }
+
+    #[test]
+    fn test_history_random_rotations_use_every_photo_before_repeating() {
+        let photos = pool(5);
+        let mut history = RotationHistory::default();
+        let mut shown = Vec::new();
+        for _ in 0..5 {
+            let mut candidates = photos.clone();
+            candidates.shuffle(&mut rand::thread_rng());
+            let picked = history.unused_photos(candidates, 1)[0].clone();
+            history.record(
+                WallpaperMode::Monitors,
+                &[applied_on("Monitor 1", 0, &picked)],
+                Local::now(),
+            );
+            shown.push(picked);
+        }
+        shown.sort();
+        assert_eq!(shown, photos);
+
+        // The pool is used up, so a new cycle starts without repeating the photo on screen
+        let current = history.current()[0].clone();
+        let next = history.unused_photos(photos, 1);
+        assert_eq!(next.len(), 5);
+        assert_eq!(next.last(), Some(&current));
+        assert!(history.cycle.is_empty());
+    }
+
+    #[test]
+    fn test_history_unused_photos_needs_one_per_target() {
+        let photos = pool(3);
+        let mut history = RotationHistory::default();
+        history.record(
+            WallpaperMode::Monitors,
+            &[applied_on("Monitor 1", 0, &photos[0])],
+            Local::now(),
+        );
+        assert_eq!(history.unused_photos(photos.clone(), 2), photos[1..]);
+        // Two monitors need more than the one unused photo left
+        history.record(
+            WallpaperMode::Monitors,
+            &[applied_on("Monitor 1", 0, &photos[1])],
+            Local::now(),
+        );
+        assert_eq!(history.unused_photos(photos, 2).len(), 3);
+    }
+
+    #[test]
+    fn test_history_keeps_newest_entries_per_target() {
+        let photos = pool(HISTORY_PER_TARGET + 5);
+        let mut history = RotationHistory::default();
+        for photo in &photos {
+            history.record(
+                WallpaperMode::Monitors,
+                &[
+                    applied_on("Monitor 1", 0, photo),
+                    applied_on("Monitor 2", 1, &photos[0]),
+                ],
+                Local::now(),
+            );
+        }
+        let monitor_1: Vec<&PathBuf> = history
+            .entries
+            .iter()
+            .filter(|e| e.target == "Monitor 1")
+            .map(|e| &e.photo)
+            .collect();
+        assert_eq!(monitor_1.len(), HISTORY_PER_TARGET);
+        assert_eq!(monitor_1.last(), photos.last().as_ref());
+        assert_eq!(history.entries.len(), 2 * HISTORY_PER_TARGET);
+
+        // Failed assignments aren't recorded
+        let mut failed = applied_on("Monitor 1", 0, Path::new("/photos/failed.jpg"));
+        failed.result = Err(PhotoError::Wallpaper("no".to_string()));
+        history.record(WallpaperMode::Monitors, &[failed], Local::now());
+        assert_eq!(history.entries.len(), 2 * HISTORY_PER_TARGET);
+    }
+
+    #[test]
+    fn test_history_previous_per_target_and_mode() {
+        let photos = pool(4);
+        let mut history = RotationHistory::default();
+        assert!(history.previous().is_empty());
+        history.record(
+            WallpaperMode::Monitors,
+            &[
+                applied_on("Monitor 1", 0, &photos[0]),
+                applied_on("Monitor 2", 1, &photos[1]),
+            ],
+            Local::now(),
+        );
+        assert!(history.previous().is_empty());
+        history.record(
+            WallpaperMode::Monitors,
+            &[applied_on("Monitor 2", 1, &photos[2])],
+            Local::now(),
+        );
+        history.record(
+            WallpaperMode::Monitors,
+            &[applied_on("Monitor 1", 0, &photos[3])],
+            Local::now(),
+        );
+        let previous: Vec<(usize, &PathBuf)> = history
+            .previous()
+            .iter()
+            .map(|e| (e.slot, &e.photo))
+            .collect();
+        assert_eq!(previous, [(0, &photos[0]), (1, &photos[1])]);
+
+        // Only targets of the latest mode count
+        history.record(
+            WallpaperMode::VirtualDesktops,
+            &[applied_on("Virtual Desktop 1", 0, &photos[1])],
+            Local::now(),
+        );
+        assert!(history.previous().is_empty());
+        assert_eq!(history.current(), [&photos[1]]);
+    }
+
+    #[test]
+    fn test_history_save_load_and_corruption() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("data/history.json");
+        assert_eq!(RotationHistory::load(&path), RotationHistory::default());
+
+        let mut history = RotationHistory::default();
+        history.record(
+            WallpaperMode::Both,
+            &[applied_on(
+                "Monitor 1, VD 2",
+                2,
+                Path::new("/photos/owl.jpg"),
+            )],
+            Local::now(),
+        );
+        history.save(&path).unwrap();
+        assert_eq!(RotationHistory::load(&path), history);
+        assert!(!path.with_extension("json.tmp").exists());
+
+        fs::write(&path, "{\"entries\": [{\"target\": ").unwrap();
+        assert_eq!(RotationHistory::load(&path), RotationHistory::default());
+        fs::write(&path, "[]").unwrap();
+        assert_eq!(RotationHistory::load(&path), RotationHistory::default());
+    }
+
+    #[test]
+    fn test_assignment_count() {
+        let all = TargetFilter::default();
+        assert_eq!(assignment_count(WallpaperMode::Monitors, 2, 4, &all), 2);
+        assert_eq!(assignment_count(WallpaperMode::Both, 2, 4, &all), 8);
+        assert_eq!(
+            assignment_count(WallpaperMode::Both, 2, 4, &targets(&[1], &[0, 3])),
+            2
+        );
+    }
+
+    // ========================================================================
+    // Favorites and Exclusions Tests
+    // ========================================================================
+
+    #[test]
+    fn test_photo_list_add_remove_and_read() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().join("data");
+        let owl = Path::new("/photos/owl.jpg");
+        let fox = Path::new("/photos/fox.jpg");
+        assert!(PhotoList::Favorites.read(&data_dir).is_empty());
+
+        assert!(PhotoList::Favorites.add(&data_dir, owl).unwrap());
+        assert!(PhotoList::Favorites.add(&data_dir, fox).unwrap());
+        assert!(!PhotoList::Favorites.add(&data_dir, owl).unwrap());
+        assert_eq!(PhotoList::Favorites.read(&data_dir), [owl, fox]);
+        assert!(PhotoList::Excluded.read(&data_dir).is_empty());
+
+        assert!(PhotoList::Favorites.remove(&data_dir, owl).unwrap());
+        assert!(!PhotoList::Favorites.remove(&data_dir, owl).unwrap());
+        assert_eq!(
+            fs::read_to_string(data_dir.join("favorites.txt")).unwrap(),
+            "/photos/fox.jpg\n"
+        );
+
+        // Hand-edited lists may have comments and blank lines
+        fs::write(
+            data_dir.join("excluded.txt"),
+            "# never again\n\n  /photos/owl.jpg  \n",
+        )
+        .unwrap();
+        assert_eq!(PhotoList::Excluded.read(&data_dir), [owl]);
+    }
+
+    #[test]
+    fn test_photo_lists_select() {
+        let temp_dir = TempDir::new().unwrap();
+        let photos = pool(4);
+        fs::write(
+            temp_dir.path().join("excluded.txt"),
+            format!("{}\n", photos[1].display()),
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("favorites.txt"),
+            format!("{}\n{}\n", photos[2].display(), photos[3].display()),
+        )
+        .unwrap();
+        let lists = PhotoLists::load(temp_dir.path());
+
+        assert_eq!(
+            lists.select(photos.clone(), false).unwrap(),
+            [photos[0].clone(), photos[2].clone(), photos[3].clone()]
+        );
+        assert_eq!(lists.select(photos.clone(), true).unwrap(), photos[2..]);
+        assert!(matches!(
+            lists.select(photos[..2].to_vec(), true),
+            Err(PhotoError::NoPhotos(_))
+        ));
+        // No lists, no change
+        assert_eq!(
+            PhotoLists::default().select(photos.clone(), false).unwrap(),
+            photos
+        );
+    }
+
+    #[test]
+    fn test_photo_lists_match_photos_found_through_symlinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let real = temp_dir.path().join("real");
+        fs::create_dir(&real).unwrap();
+        fs::write(real.join("owl.jpg"), "owl").unwrap();
+        std::os::unix::fs::symlink(&real, temp_dir.path().join("linked")).unwrap();
+        PhotoList::Excluded
+            .add(
+                temp_dir.path(),
+                &real.join("owl.jpg").canonicalize().unwrap(),
+            )
+            .unwrap();
+        let lists = PhotoLists::load(temp_dir.path());
+        assert!(lists.is_excluded(&temp_dir.path().join("linked/owl.jpg")));
+        assert!(!lists.is_favorite(&temp_dir.path().join("linked/owl.jpg")));
+    }
+
+    #[test]
+    fn test_shuffle_weighted_favors_favorites() {
+        use rand::SeedableRng;
+        let temp_dir = TempDir::new().unwrap();
+        let photos = pool(10);
+        PhotoList::Favorites
+            .add(temp_dir.path(), &photos[7])
+            .unwrap();
+        let lists = PhotoLists::load(temp_dir.path());
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+
+        let first_with = |weight: u32, rng: &mut rand::rngs::StdRng| {
+            (0..2000)
+                .filter(|_| lists.shuffle_weighted(photos.clone(), weight, rng)[0] == photos[7])
+                .count()
+        };
+        // Weight 1 is a plain shuffle: first about 1 time in 10.
+        // Weight 3 makes it about 3 times in 12.
+        let plain = first_with(1, &mut rng);
+        let weighted = first_with(3, &mut rng);
+        assert!((120..=280).contains(&plain), "{}", plain);
+        assert!((400..=600).contains(&weighted), "{}", weighted);
+
+        let mut shuffled = lists.shuffle_weighted(photos.clone(), 3, &mut rng);
+        shuffled.sort();
+        let mut sorted = photos;
+        sorted.sort();
+        assert_eq!(shuffled, sorted);
+    }
+
+    #[test]
+    fn test_resolve_photo_arg() {
+        let temp_dir = TempDir::new().unwrap();
+        let owl = temp_dir.path().join("owl.jpg");
+        let fox = temp_dir.path().join("fox.jpg");
+        fs::write(&owl, "owl").unwrap();
+        fs::write(&fox, "fox").unwrap();
+        let link = temp_dir.path().join("current");
+        let mut history = RotationHistory::default();
+
+        assert!(resolve_photo_arg("current", &link, &history).is_err());
+        history.record(
+            WallpaperMode::Monitors,
+            &[applied_on("Monitor 1", 0, &fox)],
+            Local::now(),
+        );
+        assert_eq!(resolve_photo_arg("current", &link, &history).unwrap(), fox);
+
+        // The `current` link wins over the history
+        std::os::unix::fs::symlink(&owl, &link).unwrap();
+        assert_eq!(
+            resolve_photo_arg("current", &link, &history).unwrap(),
+            owl.canonicalize().unwrap()
+        );
+
+        assert_eq!(
+            resolve_photo_arg(fox.to_str().unwrap(), &link, &history).unwrap(),
+            fox.canonicalize().unwrap()
+        );
+        assert!(resolve_photo_arg("/no/such/photo.jpg", &link, &history).is_err());
+        assert!(resolve_photo_arg(temp_dir.path().to_str().unwrap(), &link, &history).is_err());
+    }
+
+    // ========================================================================
+    // Local Collections Tests
+    // ========================================================================
+
+    fn collections_root() -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        for (name, photos) in [
+            ("best-photos-october-2018", 3),
+            ("best-photos-november-2018", 1),
+            ("wildlife", 2),
+            ("wildlife-2019", 0),
+        ] {
+            let dir = temp_dir.path().join(name);
+            fs::create_dir_all(&dir).unwrap();
+            for i in 0..photos {
+                fs::write(dir.join(format!("{}.jpg", i)), "photo").unwrap();
+            }
+        }
+        fs::create_dir(temp_dir.path().join(".cache")).unwrap();
+        fs::write(temp_dir.path().join("notes.txt"), "").unwrap();
+        temp_dir
+    }
+
+    #[test]
+    fn test_resolve_collection_by_prefix() {
+        let root = collections_root();
+        let root = root.path();
+        assert_eq!(
+            resolve_collection(root, "best-photos-october-2018").unwrap(),
+            root.join("best-photos-october-2018")
+        );
+        assert_eq!(
+            resolve_collection(root, "best-photos-oct").unwrap(),
+            root.join("best-photos-october-2018")
+        );
+        assert_eq!(
+            resolve_collection(root, "BEST-PHOTOS-NOV").unwrap(),
+            root.join("best-photos-november-2018")
+        );
+        // An exact name wins over longer names it prefixes
+        assert_eq!(
+            resolve_collection(root, "wildlife").unwrap(),
+            root.join("wildlife")
+        );
     }
 
-    println!("{}", "=== National Geographic Wallpaper ===".green());
-    println!("Mode: {}\n", mode.to_string().yellow());
+    #[test]
+    fn test_resolve_collection_ambiguous_or_missing() {
+        let root = collections_root();
+        let err = resolve_collection(root.path(), "best")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("matches several collections"), "{}", err);
+        assert!(err.contains("best-photos-november-2018, best-photos-october-2018"));
 
-    write_log(
-        &log_path,
-        &format!("Starting wallpaper set with mode: {}", mode),
-    );
+        let err = resolve_collection(root.path(), "birds")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("No collection matches"), "{}", err);
+        assert!(err.contains("wildlife-2019"));
+        assert!(!err.contains(".cache"));
 
-    // Find photos (from custom path or default)
-    let mut photos = find_photos_in_path(path.as_deref())?;
-    if let Some(ref p) = path {
-        println!("{} Using path: {}", "✓".green(), p);
+        let empty = TempDir::new().unwrap();
+        let err = resolve_collection(&empty.path().join("missing"), "x").unwrap_err();
+        assert!(err.to_string().contains("No collections downloaded"));
     }
-    if random {
-        println!("{} Random selection enabled", "✓".green());
-        let mut rng = rand::thread_rng();
-        photos.shuffle(&mut rng);
+
+    #[test]
+    fn test_local_collections_counts_photos() {
+        let root = collections_root();
+        let collections = local_collections(root.path()).unwrap();
+        let counts: Vec<(&str, usize)> = collections
+            .iter()
+            .map(|c| (c.name.as_str(), c.photos))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                ("best-photos-november-2018", 1),
+                ("best-photos-october-2018", 3),
+                ("wildlife", 2),
+                ("wildlife-2019", 0),
+            ]
+        );
+        assert!(local_collections(&root.path().join("missing"))
+            .unwrap()
+            .is_empty());
     }
-    println!("{} Found {} photo(s)\n", "✓".green(), photos.len());
 
-    // Detect desktop environment
-    let de = detect_desktop_environment();
-    let monitor_count = get_monitor_count(de);
-    let vd_count = get_virtual_desktop_count(de);
+    // ========================================================================
+    // Photo Picker Tests
+    // ========================================================================
 
-    match de {
-        DesktopEnvironment::KdePlasma6 => {
-            println!(
-                "{} Detected KDE Plasma 6: {} monitor(s), {} virtual desktop(s)",
-                "✓".green(),
-                monitor_count,
-                vd_count
-            );
-        }
-        DesktopEnvironment::KdePlasma5 => {
-            println!(
-                "{} Detected KDE Plasma 5: {} monitor(s)",
-                "✓".green(),
-                monitor_count
-            );
-            if matches!(mode, WallpaperMode::VirtualDesktops | WallpaperMode::Both) {
-                println!(
-                    "{} Virtual desktop mode requires Plasma 6+, falling back to monitors",
-                    "!".yellow()
-                );
-            }
-        }
-        DesktopEnvironment::PlasmaFallback => {
-            println!(
-                "{} Using plasma-apply-wallpaperimage (single wallpaper mode)",
-                "!".yellow()
-            );
-        }
-        DesktopEnvironment::Gnome => {
-            println!("{} Detected GNOME, using gsettings", "✓".green());
-        }
-        DesktopEnvironment::Feh => {
-            println!("{} Using feh for X11", "✓".green());
-        }
-        DesktopEnvironment::Unknown => {
-            return Err(PhotoError::Wallpaper(
-                "No supported wallpaper tool found".to_string(),
-            ));
+    fn candidate(title: &str) -> PickCandidate {
+        let path = PathBuf::from(format!(
+            "/photos/2026-03-01/{}.jpg",
+            title.replace(' ', "_")
+        ));
+        PickCandidate {
+            entry: PhotoEntry {
+                path,
+                directory: "2026-03-01".to_string(),
+                filename: format!("{}.jpg", title.replace(' ', "_")),
+                size: 0,
+                width: None,
+                height: None,
+                date: chrono::NaiveDate::from_ymd_opt(2026, 3, 1).unwrap(),
+            },
+            title: title.to_string(),
         }
     }
-    println!();
 
-    // Determine effective mode based on DE capabilities
-    let effective_mode = match de {
-        DesktopEnvironment::KdePlasma6 => mode,
-        _ => WallpaperMode::Monitors, // Single wallpaper or monitor-only for non-Plasma6
-    };
+    #[test]
+    fn test_picker_title_from_file_name() {
+        assert_eq!(
+            picker_title(Path::new("/p/2026-03-01/Snowy_Owl_in_Flight.jpg")),
+            "Snowy Owl in Flight"
+        );
+        assert_eq!(picker_title(Path::new("/p/Reef-2.png")), "Reef-2");
+    }
 
-    // Build assignments
-    let assignments = build_assignments(effective_mode, &photos, monitor_count, vd_count);
+    #[test]
+    fn test_fuzzy_score_prefers_substrings() {
+        // Case, `_` and `-` don't matter
+        assert!(fuzzy_score("snowy owl", "Snowy_Owl").is_some());
+        assert!(fuzzy_score("OWL", "snowy-owl").is_some());
 
-    // Calculate needed wallpapers
-    let total_needed = assignments.len();
-    println!("Wallpapers needed: {}", total_needed);
+        let substring = fuzzy_score("owl", "Snowy Owl").unwrap();
+        let subsequence = fuzzy_score("owl", "Old Willow").unwrap();
+        assert!(substring > subsequence);
+        // Earlier substrings and tighter subsequences rank higher
+        assert!(fuzzy_score("owl", "Owl at Dusk").unwrap() > substring);
+        assert!(fuzzy_score("rf", "Reef").unwrap() > fuzzy_score("rf", "Red Fox").unwrap());
 
-    if photos.len() < total_needed {
-        println!(
-            "{} Only {} photos available, will reuse as needed\n",
-            "!".yellow(),
-            photos.len()
+        assert_eq!(fuzzy_score("whale", "Snowy Owl"), None);
+        assert_eq!(fuzzy_score("lwo", "Owl"), None);
+        assert_eq!(fuzzy_score("  ", "Owl"), Some(0));
+    }
+
+    #[test]
+    fn test_search_candidates_best_match_first() {
+        let candidates = [
+            candidate("Old Willow"),
+            candidate("Snowy Owl"),
+            candidate("Coral Reef"),
+            candidate("Owl at Dusk"),
+        ];
+        let titles: Vec<_> = search_candidates(&candidates, "owl")
+            .iter()
+            .map(|c| c.title.as_str())
+            .collect();
+        assert_eq!(titles, ["Owl at Dusk", "Snowy Owl", "Old Willow"]);
+
+        // Equal scores keep the newest-first order
+        let titles: Vec<_> = search_candidates(&candidates, "")
+            .iter()
+            .map(|c| c.title.as_str())
+            .collect();
+        assert_eq!(
+            titles,
+            ["Old Willow", "Snowy Owl", "Coral Reef", "Owl at Dusk"]
         );
+        assert!(search_candidates(&candidates, "whale").is_empty());
     }
-    println!();
 
-    // Display assignments
-    println!("{}", "Wallpaper assignments:".yellow());
-    for assignment in &assignments {
-        let photo_date = assignment
-            .photo_path
-            .parent()
-            .and_then(|p| p.file_name())
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown");
-        let photo_name = assignment
-            .photo_path
-            .file_stem()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown");
-
-        if assignment.is_newest {
-            println!(
-                "  {}: {} - {} {}",
-                assignment.location,
-                photo_date.green(),
-                photo_name,
-                "(newest)".yellow()
-            );
-        } else {
-            println!(
-                "  {}: {} - {}",
-                assignment.location,
-                photo_date.green(),
-                photo_name
-            );
+    #[test]
+    fn test_parse_pick_index() {
+        assert_eq!(parse_pick_index("1", 3).unwrap(), Some(0));
+        assert_eq!(parse_pick_index(" 3\n", 3).unwrap(), Some(2));
+        assert_eq!(parse_pick_index("owl", 3).unwrap(), None);
+        assert_eq!(parse_pick_index("-1", 3).unwrap(), None);
+        assert!(matches!(
+            parse_pick_index("0", 3),
+            Err(PhotoError::Config(_))
+        ));
+        assert!(matches!(
+            parse_pick_index("4", 3),
+            Err(PhotoError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_pick_candidates_newest_first() {
+        let temp_dir = TempDir::new().unwrap();
+        for (dir, name) in [
+            ("2026-02-01", "Red_Fox.jpg"),
+            ("2026-02-03", "Snowy_Owl.jpg"),
+        ] {
+            let dir = temp_dir.path().join(dir);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(name), "jpeg").unwrap();
         }
+
+        let candidates = pick_candidates(temp_dir.path().to_str()).unwrap();
+        let found: Vec<_> = candidates
+            .iter()
+            .map(|c| (c.entry.directory.as_str(), c.title.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [("2026-02-03", "Snowy Owl"), ("2026-02-01", "Red Fox")]
+        );
     }
-    println!();
 
-    // Apply wallpapers
-    println!("{}", "Applying wallpapers...".yellow());
-    println!();
+    // ========================================================================
+    // Dry Run Tests
+    // ========================================================================
 
-    match de {
-        DesktopEnvironment::KdePlasma6 => {
-            apply_kde_plasma6_wallpapers(&assignments, effective_mode, monitor_count, &log_path);
-        }
-        DesktopEnvironment::KdePlasma5 => {
-            apply_kde_plasma5_wallpapers(&assignments, &log_path);
-        }
-        DesktopEnvironment::PlasmaFallback => {
-            if let Some(first) = assignments.first() {
-                match set_wallpaper_plasma_apply(&first.photo_path) {
-                    Ok(()) => {
-                        println!("{} Wallpaper set", "✓".green());
-                        write_log(
-                            &log_path,
-                            &format!("Set wallpaper to: {}", first.photo_path.display()),
-                        );
-                    }
-                    Err(e) => {
-                        println!("{} Failed to set wallpaper: {}", "✗".red(), e);
-                    }
-                }
-            }
-        }
-        DesktopEnvironment::Gnome => {
-            if let Some(first) = assignments.first() {
-                match set_wallpaper_gnome(&first.photo_path) {
-                    Ok(()) => {
-                        println!("{} Wallpaper set via gsettings", "✓".green());
-                        write_log(
-                            &log_path,
-                            &format!("Set wallpaper to: {}", first.photo_path.display()),
-                        );
-                    }
-                    Err(e) => {
-                        println!("{} Failed to set wallpaper: {}", "✗".red(), e);
-                    }
-                }
-            }
-        }
-        DesktopEnvironment::Feh => {
-            if let Some(first) = assignments.first() {
-                match set_wallpaper_feh(&first.photo_path) {
-                    Ok(()) => {
-                        println!("{} Wallpaper set via feh", "✓".green());
-                        write_log(
-                            &log_path,
-                            &format!("Set wallpaper to: {}", first.photo_path.display()),
-                        );
-                    }
-                    Err(e) => {
-                        println!("{} Failed to set wallpaper: {}", "✗".red(), e);
-                    }
-                }
-            }
+    fn dated_photos(temp_dir: &TempDir, days: &[&str]) -> String {
+        for day in days {
+            let dir = temp_dir.path().join(day);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("Photo_{day}.jpg")), "jpeg").unwrap();
         }
-        DesktopEnvironment::Unknown => unreachable!(),
+        temp_dir.path().to_string_lossy().into_owned()
     }
 
-    println!();
-    println!("{}", "=== Completed ===".green());
-    write_log(&log_path, "Wallpaper setting completed");
+    #[test]
+    fn test_dry_run_plans_without_calling_the_backend() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = dated_photos(&temp_dir, &["2026-03-01", "2026-03-02", "2026-03-03"]);
+        let backend = MockBackend::new(3, 1);
+        let options = WallpaperOptions {
+            dry_run: true,
+            ..WallpaperOptions::default()
+        };
 
-    println!("\nLog file: {}", log_path);
+        let report =
+            set_wallpapers_with_backend(&backend, WallpaperMode::Monitors, Some(&path), &options)
+                .unwrap();
+        assert!(backend.calls.borrow().is_empty());
+        assert!(report.dry_run);
+        assert_eq!(report.photos_found, 3);
+        assert_eq!(report.results.len(), 3);
+        assert!(report.results.iter().all(|r| r.result.is_ok()));
+        let newest = &report.results[0].assignment;
+        assert!(newest.is_newest);
+        assert!(newest
+            .photo_path
+            .ends_with("2026-03-03/Photo_2026-03-03.jpg"));
+    }
 
-    Ok(())
-}
+    #[test]
+    fn test_dry_run_plan_shows_the_mode_fallback() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = dated_photos(&temp_dir, &["2026-03-01", "2026-03-02"]);
+        // One virtual desktop wallpaper for all, so `both` falls back to monitors
+        let backend = MockBackend::new(2, 1);
+        let options = WallpaperOptions {
+            dry_run: true,
+            ..WallpaperOptions::default()
+        };
 
-/// Apply wallpapers for KDE Plasma 6
-fn apply_kde_plasma6_wallpapers(
-    assignments: &[WallpaperAssignment],
-    mode: WallpaperMode,
-    monitor_count: usize,
-    log_path: &str,
-) {
-    match mode {
-        WallpaperMode::Monitors => {
-            for (i, assignment) in assignments.iter().enumerate() {
-                match set_wallpaper_qdbus6(i, &assignment.photo_path) {
-                    Ok(()) => {
-                        println!("{} {}", "✓".green(), assignment.location);
-                        write_log(
-                            log_path,
-                            &format!(
-                                "Set {} to: {}",
-                                assignment.location,
-                                assignment.photo_path.display()
-                            ),
-                        );
-                    }
-                    Err(e) => {
-                        println!("{} Failed: {} - {}", "✗".red(), assignment.location, e);
-                    }
-                }
-            }
-        }
-        WallpaperMode::VirtualDesktops => {
-            for assignment in assignments {
-                // Set same wallpaper on all monitors for this VD
-                for mon in 0..monitor_count {
-                    let _ = set_wallpaper_qdbus6(mon, &assignment.photo_path);
-                }
-                println!("{} {} (all monitors)", "✓".green(), assignment.location);
-                write_log(
-                    log_path,
-                    &format!(
-                        "Set {} to: {}",
-                        assignment.location,
-                        assignment.photo_path.display()
-                    ),
-                );
-            }
-        }
-        WallpaperMode::Both => {
-            for (i, assignment) in assignments.iter().enumerate() {
-                let mon_idx = i % monitor_count;
-                match set_wallpaper_qdbus6(mon_idx, &assignment.photo_path) {
-                    Ok(()) => {
-                        println!("{} {}", "✓".green(), assignment.location);
-                        write_log(
-                            log_path,
-                            &format!(
-                                "Set {} to: {}",
-                                assignment.location,
-                                assignment.photo_path.display()
-                            ),
-                        );
-                    }
-                    Err(e) => {
-                        println!("{} Failed: {} - {}", "✗".red(), assignment.location, e);
-                    }
-                }
-            }
+        let report =
+            set_wallpapers_with_backend(&backend, WallpaperMode::Both, Some(&path), &options)
+                .unwrap();
+        assert!(backend.calls.borrow().is_empty());
+
+        let plan = report.plan();
+        assert_eq!(plan.requested_mode, WallpaperMode::Both);
+        assert_eq!(plan.mode, WallpaperMode::Monitors);
+        assert_eq!(plan.warnings, [PLASMA_SHARED_DESKTOPS_WARNING]);
+
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["desktop"], "kde-plasma6");
+        assert_eq!(json["requested_mode"], "both");
+        assert_eq!(json["mode"], "monitors");
+        assert_eq!(json["monitor_count"], 2);
+        let assignments = json["assignments"].as_array().unwrap();
+        assert_eq!(assignments.len(), 2);
+        assert_eq!(assignments[0]["location"], "Monitor 1");
+        assert_eq!(assignments[0]["is_newest"], true);
+    }
+
+    // ========================================================================
+    // Diagnostics Tests
+    // ========================================================================
+
+    fn plasma_session() -> FakeSystem {
+        FakeSystem {
+            commands: vec!["qdbus6", "gsettings"],
+            processes: vec!["plasmashell"],
+            env: vec![
+                ("XDG_CURRENT_DESKTOP", "KDE"),
+                ("XDG_SESSION_TYPE", "wayland"),
+                ("WAYLAND_DISPLAY", "wayland-0"),
+                ("DBUS_SESSION_BUS_ADDRESS", "unix:path=/run/user/1000/bus"),
+            ],
+            ..FakeSystem::default()
         }
     }
-}
 
-/// Apply wallpapers for KDE Plasma 5
-fn apply_kde_plasma5_wallpapers(assignments: &[WallpaperAssignment], log_path: &str) {
-    for (i, assignment) in assignments.iter().enumerate() {
-        match set_wallpaper_qdbus(i, &assignment.photo_path) {
-            Ok(()) => {
-                println!("{} {}", "✓".green(), assignment.location);
-                write_log(
-                    log_path,
-                    &format!(
-                        "Set {} to: {}",
-                        assignment.location,
-                        assignment.photo_path.display()
-                    ),
-                );
-            }
-            Err(e) => {
-                println!("{} Failed: {} - {}", "✗".red(), assignment.location, e);
-            }
+    fn systemctl_answering(output: &'static str) -> FakeCommands {
+        FakeCommands {
+            outputs: vec![("systemctl", "--user", output)],
+            ..FakeCommands::default()
         }
     }
-}
 
-#[cfg(test)]
-#[allow(clippy::unwrap_used)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use std::io::Write as IoWrite;
-    use tempfile::TempDir;
+    #[test]
+    fn test_check_network_status_codes() {
+        assert_eq!(check_network(Ok(200)).status, CheckStatus::Pass);
+        assert_eq!(check_network(Ok(301)).status, CheckStatus::Pass);
+
+        let forbidden = check_network(Ok(403));
+        assert_eq!(forbidden.status, CheckStatus::Fail);
+        assert!(forbidden.message.contains("403"));
+        assert_eq!(check_network(Ok(503)).status, CheckStatus::Fail);
+
+        let offline = check_network(Err(PhotoError::Command("no route".to_string())));
+        assert_eq!(offline.status, CheckStatus::Fail);
+        assert!(offline.message.contains("unreachable"));
+        assert!(offline.message.contains(NATGEO_POD_URL));
+    }
 
     #[test]
-    fn test_get_extension_from_content_type() {
-        // Valid content types
+    fn test_check_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let writable = check_directory("Photo directory", temp_dir.path());
+        assert_eq!(writable.status, CheckStatus::Pass);
+        assert_eq!(writable.name, "Photo directory");
+        // The probe file is cleaned up
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+
+        let missing = check_directory("Log directory", &temp_dir.path().join("logs"));
+        assert_eq!(missing.status, CheckStatus::Warn);
+
+        let file = temp_dir.path().join("file");
+        fs::write(&file, "").unwrap();
         assert_eq!(
-            get_extension_from_content_type("image/jpeg").unwrap(),
-            "jpg"
+            check_directory("Log directory", &file).status,
+            CheckStatus::Fail
         );
+    }
+
+    #[test]
+    fn test_check_desktop_explains_detection() {
+        let system = plasma_session();
+        let check = check_desktop(detect_environment_with(&system), &system);
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert!(check.message.starts_with("KdePlasma6"));
+        for evidence in [
+            "qdbus6 installed",
+            "plasmashell running",
+            "XDG_CURRENT_DESKTOP=KDE",
+        ] {
+            assert!(check.message.contains(evidence), "{}", check.message);
+        }
+
+        // feh alone can't set a Wayland wallpaper
+        let system = FakeSystem {
+            commands: vec!["feh"],
+            env: vec![("XDG_SESSION_TYPE", "wayland")],
+            ..FakeSystem::default()
+        };
+        let check = check_desktop(detect_environment_with(&system), &system);
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.message.contains("only works on X11"));
+        assert!(check.message.contains("feh installed"));
+    }
+
+    #[test]
+    fn test_check_displays() {
+        let capabilities = Capabilities {
+            per_monitor: true,
+            per_virtual_desktop: true,
+            monitors: 2,
+            virtual_desktops: 4,
+        };
+        let check = check_displays(DesktopEnvironment::KdePlasma6, Some(capabilities));
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert_eq!(check.message, "2 monitor(s), 4 virtual desktop(s)");
         assert_eq!(
-            get_extension_from_content_type("image/jpeg; charset=utf-8").unwrap(),
-            "jpg"
+            check_displays(DesktopEnvironment::Unknown, None).status,
+            CheckStatus::Warn
         );
-        assert_eq!(get_extension_from_content_type("image/png").unwrap(), "png");
-        assert_eq!(get_extension_from_content_type("image/gif").unwrap(), "gif");
+    }
 
-        // Invalid content types
-        assert!(get_extension_from_content_type("text/html").is_err());
-        assert!(get_extension_from_content_type("application/pdf").is_err());
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn test_check_session_variables() {
+        let check = check_session(&plasma_session());
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert!(check.message.contains("DISPLAY unset"));
+        assert!(check.message.contains("WAYLAND_DISPLAY=wayland-0"));
+
+        let no_bus = FakeSystem {
+            env: vec![("DISPLAY", ":0")],
+            ..FakeSystem::default()
+        };
+        assert_eq!(check_session(&no_bus).status, CheckStatus::Warn);
+        assert_eq!(
+            check_session(&FakeSystem::default()).status,
+            CheckStatus::Fail
+        );
     }
 
     #[test]
-    fn test_write_log() {
-        let temp_dir = TempDir::new().unwrap();
-        let log_path = temp_dir.path().join("test.log");
+    #[cfg(target_os = "linux")]
+    fn test_check_timer_states() {
+        let runner = systemctl_answering("LoadState=loaded\nActiveState=active\n");
+        let check = check_timer(&runner);
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert_eq!(
+            runner.calls.borrow()[0],
+            "systemctl --user show natgeo-wallpaper.timer --property=LoadState --property=ActiveState"
+        );
 
-        // Write log entries
-        write_log(log_path.to_str().unwrap(), "Test message 1");
-        write_log(log_path.to_str().unwrap(), "Test message 2");
+        let missing = check_timer(&systemctl_answering(
+            "LoadState=not-found\nActiveState=inactive\n",
+        ));
+        assert_eq!(missing.status, CheckStatus::Pass);
+        assert!(missing.message.contains("No systemd timer"));
 
-        // Read and verify
-        let contents = fs::read_to_string(&log_path).unwrap();
-        assert!(contents.contains("Test message 1"));
-        assert!(contents.contains("Test message 2"));
-        assert!(contents.contains("[202")); // Check for timestamp format
-        assert_eq!(contents.lines().count(), 2); // Should have 2 lines
+        let stopped = check_timer(&systemctl_answering(
+            "LoadState=loaded\nActiveState=inactive\n",
+        ));
+        assert_eq!(stopped.status, CheckStatus::Warn);
+        assert!(stopped.message.contains("inactive"));
+
+        let no_systemd = FakeCommands {
+            failing: vec!["systemctl"],
+            ..FakeCommands::default()
+        };
+        assert_eq!(check_timer(&no_systemd).status, CheckStatus::Warn);
     }
 
     #[test]
-    fn test_sanitize_title_special_characters() {
-        // Test various special characters
+    #[cfg(target_os = "linux")]
+    fn test_doctor_checks_with_fake_probes() {
+        let temp_dir = TempDir::new().unwrap();
+        let system = plasma_session();
+        let runner = systemctl_answering("LoadState=loaded\nActiveState=active\n");
+        let asked = std::cell::RefCell::new(Vec::new());
+        let http_status = |url: &str| {
+            asked.borrow_mut().push(url.to_string());
+            Ok(200)
+        };
+        let capabilities = |de: DesktopEnvironment| {
+            (de == DesktopEnvironment::KdePlasma6).then_some(Capabilities {
+                per_monitor: true,
+                per_virtual_desktop: true,
+                monitors: 2,
+                virtual_desktops: 1,
+            })
+        };
+
+        let checks = doctor_checks(&DoctorProbes {
+            system: &system,
+            commands: &runner,
+            http_status: &http_status,
+            capabilities: &capabilities,
+            photo_dir: temp_dir.path().to_path_buf(),
+            log_dir: temp_dir.path().join("logs"),
+        });
+        let names: Vec<_> = checks.iter().map(|c| c.name.as_str()).collect();
         assert_eq!(
-            sanitize_title("Photo: 2024/01/20 | Test"),
-            "Photo_2024_01_20_-_Test"
+            names,
+            [
+                "Network",
+                "Photo directory",
+                "Log directory",
+                "Desktop",
+                "Displays",
+                "Session",
+                "Timer"
+            ]
         );
+        let statuses: Vec<_> = checks.iter().map(|c| c.status).collect();
         assert_eq!(
-            sanitize_title("Test/Photo: Title|Name"),
-            "Test_Photo_Title-Name"
+            statuses,
+            [
+                CheckStatus::Pass,
+                CheckStatus::Pass,
+                CheckStatus::Warn,
+                CheckStatus::Pass,
+                CheckStatus::Pass,
+                CheckStatus::Pass,
+                CheckStatus::Pass
+            ]
         );
+        assert_eq!(*asked.borrow(), [NATGEO_POD_URL]);
 
-        // Verify problematic characters are removed
-        let sanitized = sanitize_title("Bad/Path:Name|Value");
-        assert!(!sanitized.contains("/"));
-        assert!(!sanitized.contains(":"));
-        assert!(!sanitized.contains("|"));
+        let json = serde_json::to_value(&checks[2]).unwrap();
+        assert_eq!(json["status"], "warn");
+        assert_eq!(json["name"], "Log directory");
     }
 
-    #[test]
-    fn test_sanitize_title_length_limit() {
-        let long_title = "A".repeat(150);
-        let sanitized = sanitize_title(&long_title);
-        assert_eq!(sanitized.len(), 100);
-    }
+    // ========================================================================
+    // Command and Process Lookup Tests
+    // ========================================================================
 
-    #[test]
-    fn test_html_parsing_og_image() {
-        // Simulate HTML with og:image meta tag
-        let html = r#"<html><head><meta property="og:image" content="https://example.com/image.jpg"/></head></html>"#;
+    /// A `PATH` and proc filesystem made up for a test
+    struct FakeHost {
+        search_path: std::ffi::OsString,
+        proc_root: PathBuf,
+    }
 
-        let image_url = html
-            .split("property=\"og:image\"")
-            .nth(1)
-            .and_then(|s| s.split("content=\"").nth(1))
-            .and_then(|s| s.split('"').next())
-            .unwrap_or("");
+    impl HostPaths for FakeHost {
+        fn search_path(&self) -> Option<std::ffi::OsString> {
+            Some(self.search_path.clone())
+        }
 
-        assert_eq!(image_url, "https://example.com/image.jpg");
+        fn proc_root(&self) -> PathBuf {
+            self.proc_root.clone()
+        }
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_html_parsing_og_title() {
-        // Simulate HTML with og:title meta tag
-        let html = r#"<html><head><meta property="og:title" content="Test Photo"/></head></html>"#;
+    fn test_command_exists_searches_path_for_executables() {
+        use std::os::unix::fs::PermissionsExt;
 
-        let title = html
-            .split("property=\"og:title\"")
-            .nth(1)
-            .and_then(|s| s.split("content=\"").nth(1))
-            .and_then(|s| s.split('"').next())
-            .unwrap_or("");
+        let temp_dir = TempDir::new().unwrap();
+        let bin = temp_dir.path().join("bin");
+        fs::create_dir_all(bin.join("qdbus-dir")).unwrap();
+        for (name, mode) in [("qdbus6", 0o755), ("notes.txt", 0o644)] {
+            fs::write(bin.join(name), "#!/bin/sh\n").unwrap();
+            fs::set_permissions(bin.join(name), fs::Permissions::from_mode(mode)).unwrap();
+        }
+        // Missing directories and empty entries are passed over
+        let search_path =
+            std::env::join_paths([temp_dir.path().join("missing"), PathBuf::new(), bin.clone()])
+                .unwrap();
+        let host = FakeHost {
+            search_path,
+            proc_root: temp_dir.path().join("proc"),
+        };
 
-        assert_eq!(title, "Test Photo");
+        assert!(command_exists_in(&host, "qdbus6"));
+        assert!(!command_exists_in(&host, "notes.txt"));
+        assert!(!command_exists_in(&host, "qdbus-dir"));
+        assert!(!command_exists_in(&host, "qdbus"));
+        assert!(!command_exists_in(&host, ""));
+        // A path is checked as given
+        assert!(command_exists_in(
+            &host,
+            bin.join("qdbus6").to_str().unwrap()
+        ));
+        assert!(!command_exists_in(
+            &host,
+            bin.join("notes.txt").to_str().unwrap()
+        ));
     }
 
     #[test]
-    fn test_download_and_save_mock_image() {
+    fn test_search_dir_expands_home_and_empty_entries() {
+        assert_eq!(search_dir(Path::new("")), PathBuf::from("."));
+        assert_eq!(search_dir(Path::new("/usr/bin")), PathBuf::from("/usr/bin"));
+        assert_eq!(
+            search_dir(Path::new("~/.local/bin")),
+            PathBuf::from(expand_tilde("~/.local/bin"))
+        );
+        assert!(!search_dir(Path::new("~/.local/bin")).starts_with("~"));
+    }
+
+    #[test]
+    fn test_process_running_reads_proc_comm() {
         let temp_dir = TempDir::new().unwrap();
-        let save_dir = temp_dir.path().to_str().unwrap();
-        let sanitized_title = "test_photo";
+        let proc_root = temp_dir.path().join("proc");
+        for (pid, comm) in [
+            ("1", "systemd\n"),
+            ("812", "plasmashell\n"),
+            // Names are cut to 15 bytes
+            ("913", "xdg-desktop-por\n"),
+            // Not a process directory
+            ("self-test", "gnome-shell\n"),
+        ] {
+            fs::create_dir_all(proc_root.join(pid)).unwrap();
+            fs::write(proc_root.join(pid).join("comm"), comm).unwrap();
+        }
+        // A process that exited mid-scan has no comm
+        fs::create_dir_all(proc_root.join("999")).unwrap();
+        let host = FakeHost {
+            search_path: std::ffi::OsString::new(),
+            proc_root,
+        };
 
-        // Create a mock image file
-        let photo_filename = format!("{}/{}.jpg", save_dir, sanitized_title);
-        let mut file = File::create(&photo_filename).unwrap();
-        file.write_all(b"fake image data").unwrap();
+        assert!(process_running_in(&host, "plasmashell"));
+        assert!(process_running_in(&host, "systemd"));
+        assert!(process_running_in(&host, "xdg-desktop-portal"));
+        // Matched exactly, as `pgrep -x` does
+        assert!(!process_running_in(&host, "plasma"));
+        assert!(!process_running_in(&host, "gnome-shell"));
+        assert!(!process_running_in(&host, "swww-daemon"));
+    }
 
-        // Verify file was created
-        assert!(std::path::Path::new(&photo_filename).exists());
+    // ============================================================================
+    // Session Type Tests
+    // ============================================================================
 
-        // Verify file contents
-        let contents = fs::read(&photo_filename).unwrap();
-        assert_eq!(contents, b"fake image data");
+    fn env_system(
+        commands: &[&'static str],
+        processes: &[&'static str],
+        env: &[(&'static str, &'static str)],
+    ) -> FakeSystem {
+        FakeSystem {
+            commands: commands.to_vec(),
+            processes: processes.to_vec(),
+            env: env.to_vec(),
+            ..FakeSystem::default()
+        }
+    }
+
+    #[test]
+    fn test_session_type_detect() {
+        let cases: &[(&[(&str, &str)], SessionType)] = &[
+            (&[("DISPLAY", ":0")], SessionType::X11),
+            (
+                &[("XDG_SESSION_TYPE", "x11"), ("DISPLAY", ":0")],
+                SessionType::X11,
+            ),
+            (&[("XDG_SESSION_TYPE", "wayland")], SessionType::Wayland),
+            (&[("WAYLAND_DISPLAY", "wayland-0")], SessionType::Wayland),
+            // Xwayland sets DISPLAY too
+            (
+                &[("XDG_SESSION_TYPE", "Wayland"), ("DISPLAY", ":0")],
+                SessionType::Wayland,
+            ),
+            (&[], SessionType::Headless),
+            (&[("XDG_SESSION_TYPE", "x11")], SessionType::Headless),
+            (
+                &[("XDG_SESSION_TYPE", "tty"), ("DISPLAY", " ")],
+                SessionType::Headless,
+            ),
+        ];
+        for (env, expected) in cases {
+            let system = FakeSystem {
+                env: env.to_vec(),
+                ..FakeSystem::default()
+            };
+            assert_eq!(SessionType::detect(&system), *expected, "{env:?}");
+        }
+    }
+
+    type SessionCase = (
+        &'static [&'static str],
+        &'static [&'static str],
+        &'static [(&'static str, &'static str)],
+        DetectedEnvironment,
+    );
+
+    #[test]
+    fn test_detect_environment_by_session() {
+        let cases: &[SessionCase] = &[
+            // X11: feh is usable
+            (
+                &["feh"],
+                &[],
+                &[("DISPLAY", ":0")],
+                DetectedEnvironment {
+                    de: DesktopEnvironment::Feh,
+                    session: SessionType::X11,
+                },
+            ),
+            // Wayland GNOME with feh installed and no gsettings on PATH: feh is skipped
+            (
+                &["feh"],
+                &["gnome-shell"],
+                &[("XDG_SESSION_TYPE", "wayland"), ("DISPLAY", ":0")],
+                DetectedEnvironment {
+                    de: DesktopEnvironment::Unknown,
+                    session: SessionType::Wayland,
+                },
+            ),
+            // Wayland: an installed swww is the fallback, feh is not
+            (
+                &["feh", "swww"],
+                &[],
+                &[("WAYLAND_DISPLAY", "wayland-1")],
+                DetectedEnvironment {
+                    de: DesktopEnvironment::Swww,
+                    session: SessionType::Wayland,
+                },
+            ),
+            // Headless: neither X11-only tools nor the swww fallback
+            (
+                &["feh", "swww"],
+                &[],
+                &[],
+                DetectedEnvironment {
+                    de: DesktopEnvironment::Unknown,
+                    session: SessionType::Headless,
+                },
+            ),
+            // Headless: desktops found by their processes are still reported
+            (
+                &["xfconf-query", "feh"],
+                &["xfce4-session"],
+                &[],
+                DetectedEnvironment {
+                    de: DesktopEnvironment::Xfce,
+                    session: SessionType::Headless,
+                },
+            ),
+        ];
+        for (commands, processes, env, expected) in cases {
+            let system = env_system(commands, processes, env);
+            assert_eq!(
+                detect_environment_with(&system),
+                *expected,
+                "{commands:?} {processes:?} {env:?}"
+            );
+            assert_eq!(detect_desktop_environment_with(&system), expected.de);
+        }
     }
 
     #[test]
-    fn test_date_format() {
-        // Test the date format used in directory structure
-        let date = Local::now().format("%d-%m-%Y").to_string();
+    fn test_detection_prefers_wayland_native_rules() {
+        let rules = [
+            DetectionRule {
+                support: SessionSupport::Any,
+                detect: |p| {
+                    p.command_exists("gsettings")
+                        .then_some(DesktopEnvironment::Gnome)
+                },
+            },
+            DetectionRule {
+                support: SessionSupport::X11Only,
+                detect: |p| p.command_exists("feh").then_some(DesktopEnvironment::Feh),
+            },
+            DetectionRule {
+                support: SessionSupport::WaylandNative,
+                detect: |p| {
+                    p.command_exists("hyprctl")
+                        .then_some(DesktopEnvironment::Hyprland)
+                },
+            },
+        ];
+        let commands = ["gsettings", "feh", "hyprctl"];
 
-        // Verify format is dd-mm-yyyy (should be 10 characters)
-        assert_eq!(date.len(), 10);
-        assert_eq!(date.chars().nth(2), Some('-'));
-        assert_eq!(date.chars().nth(5), Some('-'));
+        let wayland = env_system(&commands, &[], &[("XDG_SESSION_TYPE", "wayland")]);
+        assert_eq!(
+            detect_with_rules(&rules, &wayland).de,
+            DesktopEnvironment::Hyprland
+        );
+        // Elsewhere the table order decides
+        let x11 = env_system(&commands, &[], &[("DISPLAY", ":0")]);
+        assert_eq!(
+            detect_with_rules(&rules, &x11).de,
+            DesktopEnvironment::Gnome
+        );
+        let x11 = env_system(&["feh", "hyprctl"], &[], &[("DISPLAY", ":0")]);
+        assert_eq!(detect_with_rules(&rules, &x11).de, DesktopEnvironment::Feh);
+        let headless = env_system(&["feh", "hyprctl"], &[], &[]);
+        assert_eq!(
+            detect_with_rules(&rules, &headless).de,
+            DesktopEnvironment::Hyprland
+        );
     }
 
     #[test]
-    fn test_title_fallback_from_url() {
-        // Test extracting filename from image URL
-        let url = "https://i.natgeofe.com/n/d0888b52-1d37-403a-a25a-84c1dc53bbdf/NationalGeographic_433254.jpg";
-
-        let filename = url
-            .split('/')
-            .next_back()
-            .and_then(|filename| filename.split('.').next())
-            .unwrap_or("photo-of-the-day");
+    fn test_unsupported_reason_explains_headless_session() {
+        let reason = unsupported_desktop_reason(&env_system(&["feh"], &[], &[]));
+        assert!(reason.contains("feh"), "{reason}");
+        assert!(reason.contains("DISPLAY"), "{reason}");
+        assert!(reason.contains("desktop session"), "{reason}");
 
-        assert_eq!(filename, "NationalGeographic_433254");
+        let reason = unsupported_desktop_reason(&env_system(
+            &["feh"],
+            &[],
+            &[("WAYLAND_DISPLAY", "wayland-0")],
+        ));
+        assert!(reason.contains("wayland session"), "{reason}");
+        assert!(reason.contains("swaybg"), "{reason}");
     }
 
     #[test]
-    fn test_title_fallback_logic() {
-        // Test that short/meaningless titles trigger fallback
-        let og_title_test = "Test";
-        let og_title_empty = "";
-        let og_title_short = "Hi";
-        let og_title_good = "Beautiful Sunset Over Mountains";
+    fn test_check_desktop_reports_session() {
+        let system = env_system(&["feh"], &[], &[("DISPLAY", ":0")]);
+        let check = check_desktop(detect_environment_with(&system), &system);
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert!(check.message.starts_with("Feh on X11"), "{}", check.message);
+        assert!(check.message.contains("DISPLAY=:0"), "{}", check.message);
+    }
 
-        // These should trigger fallback (too short or "Test")
-        assert!(og_title_test.to_lowercase() == "test");
-        assert!(og_title_empty.is_empty());
-        assert!(og_title_short.len() < 5);
+    // ============================================================================
+    // Session Desktop Tests
+    // ============================================================================
 
-        // This should NOT trigger fallback
-        assert!(og_title_good.len() >= 5);
-        assert!(og_title_good.to_lowercase() != "test");
+    #[test]
+    fn test_desktop_from_session_vars() {
+        let cases: &[(Option<&str>, Option<&str>, Option<DesktopEnvironment>)] = &[
+            (Some("KDE"), None, Some(DesktopEnvironment::PlasmaFallback)),
+            (Some("GNOME"), None, Some(DesktopEnvironment::Gnome)),
+            (Some("ubuntu:GNOME"), None, Some(DesktopEnvironment::Gnome)),
+            (
+                Some("GNOME-Classic:GNOME"),
+                None,
+                Some(DesktopEnvironment::Gnome),
+            ),
+            (Some("Budgie:GNOME"), None, Some(DesktopEnvironment::Gnome)),
+            (Some("X-Cinnamon"), None, Some(DesktopEnvironment::Cinnamon)),
+            (Some("MATE"), None, Some(DesktopEnvironment::Mate)),
+            (Some("XFCE"), None, Some(DesktopEnvironment::Xfce)),
+            (Some("LXQt"), None, Some(DesktopEnvironment::Lxqt)),
+            (Some("Hyprland"), None, Some(DesktopEnvironment::Hyprland)),
+            (Some("sway"), None, Some(DesktopEnvironment::Swww)),
+            // Case and whitespace don't matter
+            (Some(" xfce "), None, Some(DesktopEnvironment::Xfce)),
+            // The first recognized entry wins
+            (
+                Some("X-Cinnamon:GNOME"),
+                None,
+                Some(DesktopEnvironment::Cinnamon),
+            ),
+            (Some("Pop:GNOME"), None, Some(DesktopEnvironment::Gnome)),
+            // DESKTOP_SESSION when XDG_CURRENT_DESKTOP is unset or unrecognized
+            (
+                None,
+                Some("plasma"),
+                Some(DesktopEnvironment::PlasmaFallback),
+            ),
+            (Some("i3"), Some("xfce"), Some(DesktopEnvironment::Xfce)),
+            (
+                Some(""),
+                Some("gnome-xorg"),
+                Some(DesktopEnvironment::Gnome),
+            ),
+            (
+                None,
+                Some("/usr/share/xsessions/plasma"),
+                Some(DesktopEnvironment::PlasmaFallback),
+            ),
+            (None, Some("lxqt.desktop"), Some(DesktopEnvironment::Lxqt)),
+            // XDG_CURRENT_DESKTOP comes first
+            (
+                Some("GNOME"),
+                Some("plasma"),
+                Some(DesktopEnvironment::Gnome),
+            ),
+            // Unrecognized or absent
+            (Some("i3"), None, None),
+            (Some("openbox"), Some("openbox"), None),
+            (None, None, None),
+        ];
+        for (current_desktop, desktop_session, expected) in cases {
+            assert_eq!(
+                desktop_from_session_vars(*current_desktop, *desktop_session),
+                *expected,
+                "XDG_CURRENT_DESKTOP={current_desktop:?} DESKTOP_SESSION={desktop_session:?}"
+            );
+        }
     }
 
     #[test]
-    fn test_expand_tilde() {
-        // Test tilde expansion
-        let home = std::env::var("HOME").unwrap();
-
-        // Should expand tilde
-        assert_eq!(expand_tilde("~/test/path"), format!("{}/test/path", home));
-        assert_eq!(expand_tilde("~/"), format!("{}/", home));
+    fn test_detect_honors_session_desktop() {
+        let cases: &[DetectionCase] = &[
+            // A KDE app pulled in plasma-apply-wallpaperimage on a GNOME box
+            (
+                &["plasma-apply-wallpaperimage", "gsettings"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "GNOME")],
+                DesktopEnvironment::Gnome,
+            ),
+            (
+                &["plasma-apply-wallpaperimage", "gsettings"],
+                &[],
+                &[("DESKTOP_SESSION", "ubuntu")],
+                DesktopEnvironment::Gnome,
+            ),
+            // KDE is narrowed down to the running Plasma
+            (
+                &["qdbus6"],
+                &["plasmashell"],
+                &[("XDG_CURRENT_DESKTOP", "KDE")],
+                DesktopEnvironment::KdePlasma6,
+            ),
+            (
+                &["qdbus"],
+                &["plasmashell"],
+                &[("DESKTOP_SESSION", "plasma")],
+                DesktopEnvironment::KdePlasma5,
+            ),
+            (
+                &["plasma-apply-wallpaperimage"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "KDE")],
+                DesktopEnvironment::PlasmaFallback,
+            ),
+            // sway is only trusted with swww installed
+            (
+                &["swww", "gsettings"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "sway")],
+                DesktopEnvironment::Swww,
+            ),
+            // A named desktop whose tool is missing falls back to the table
+            (
+                &["plasma-apply-wallpaperimage"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "GNOME")],
+                DesktopEnvironment::PlasmaFallback,
+            ),
+            // Unrecognized names fall back to the detection table
+            (
+                &["plasma-apply-wallpaperimage", "feh"],
+                &[],
+                &[("XDG_CURRENT_DESKTOP", "i3"), ("DISPLAY", ":0")],
+                DesktopEnvironment::PlasmaFallback,
+            ),
+            (
+                &["feh"],
+                &[],
+                &[("DESKTOP_SESSION", "openbox"), ("DISPLAY", ":0")],
+                DesktopEnvironment::Feh,
+            ),
+        ];
 
-        // Should not modify paths without tilde
-        assert_eq!(expand_tilde("/absolute/path"), "/absolute/path");
-        assert_eq!(expand_tilde("relative/path"), "relative/path");
-        assert_eq!(expand_tilde("~notahome"), "~notahome");
+        for (commands, processes, env, expected) in cases {
+            assert_eq!(
+                detect(commands, processes, env),
+                *expected,
+                "commands={commands:?} processes={processes:?} env={env:?}"
+            );
+        }
     }
 
-    // ========================================================================
-    // Collection Scraping Tests
-    // ========================================================================
+    // ============================================================================
+    // Lock Screen Tests
+    // ============================================================================
+
+    fn environment(de: DesktopEnvironment, session: SessionType) -> DetectedEnvironment {
+        DetectedEnvironment { de, session }
+    }
 
     #[test]
-    fn test_extract_collection_name_from_url() {
-        // Test various URL formats
+    fn test_lock_screen_method_select() {
+        let kde6 = environment(DesktopEnvironment::KdePlasma6, SessionType::Wayland);
+        let system = env_system(
+            &["kwriteconfig6", "kwriteconfig5", "qdbus6", "qdbus"],
+            &[],
+            &[],
+        );
         assert_eq!(
-            extract_collection_name_from_url(
-                "https://www.nationalgeographic.com/photography/article/best-photos-october-2018"
-            ),
-            "best-photos-october-2018"
+            LockScreenMethod::select(&kde6, &system).unwrap(),
+            LockScreenMethod::Kde {
+                kwriteconfig: "kwriteconfig6",
+                qdbus: Some("qdbus6"),
+            }
         );
+        let kde5 = environment(DesktopEnvironment::KdePlasma5, SessionType::X11);
         assert_eq!(
-            extract_collection_name_from_url(
-                "https://www.nationalgeographic.com/photography/article/best-photos-september-2018"
-            ),
-            "best-photos-september-2018"
+            LockScreenMethod::select(&kde5, &system).unwrap(),
+            LockScreenMethod::Kde {
+                kwriteconfig: "kwriteconfig6",
+                qdbus: Some("qdbus"),
+            }
         );
+        // Without qdbus the config is still written
+        let system = env_system(&["kwriteconfig5"], &[], &[]);
+        assert_eq!(
+            LockScreenMethod::select(&kde5, &system).unwrap(),
+            LockScreenMethod::Kde {
+                kwriteconfig: "kwriteconfig5",
+                qdbus: None,
+            }
+        );
+        let err = LockScreenMethod::select(&kde6, &env_system(&["qdbus6"], &[], &[])).unwrap_err();
+        assert!(err.to_string().contains("kwriteconfig6"), "{err}");
 
-        // Edge case: URL with trailing slash
+        let gnome = environment(DesktopEnvironment::Gnome, SessionType::Wayland);
+        let system = env_system(&["gsettings", "swaylock"], &[], &[]);
         assert_eq!(
-            extract_collection_name_from_url("https://example.com/path/to/collection/"),
-            ""
+            LockScreenMethod::select(&gnome, &system).unwrap(),
+            LockScreenMethod::Gnome
         );
+        assert!(LockScreenMethod::select(&gnome, &env_system(&[], &[], &[])).is_err());
 
-        // Edge case: simple path
+        // sway and other wlroots compositors use swaylock
+        let sway = environment(DesktopEnvironment::Swww, SessionType::Wayland);
+        let system = env_system(
+            &["swaylock"],
+            &[],
+            &[("HOME", "/nonexistent"), ("XDG_CONFIG_HOME", "/xdg")],
+        );
         assert_eq!(
-            extract_collection_name_from_url("collection-name"),
-            "collection-name"
+            LockScreenMethod::select(&sway, &system).unwrap(),
+            LockScreenMethod::Swaylock(PathBuf::from("/xdg/swaylock/config"))
+        );
+        let system = env_system(&["swaylock"], &[], &[("HOME", "/nonexistent")]);
+        assert_eq!(
+            LockScreenMethod::select(&sway, &system).unwrap(),
+            LockScreenMethod::Swaylock(PathBuf::from("/nonexistent/.config/swaylock/config"))
         );
+
+        // swaylock can't lock an X11 session
+        let feh = environment(DesktopEnvironment::Feh, SessionType::X11);
+        let err = LockScreenMethod::select(&feh, &env_system(&["swaylock"], &[], &[])).unwrap_err();
+        assert!(err.to_string().contains("Feh"), "{err}");
+        let sway_without_swaylock = env_system(&[], &[], &[]);
+        assert!(LockScreenMethod::select(&sway, &sway_without_swaylock).is_err());
     }
 
     #[test]
-    fn test_extract_natgeo_image_urls() {
-        // Test HTML with multiple image URLs
-        let html = r#"
-            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg">
-            <img src="https://i.natgeofe.com/n/def456/photo2.jpg">
-            <script>{"url": "https://i.natgeofe.com/n/ghi789/photo3.jpg"}</script>
-        "#;
-
-        let urls = extract_natgeo_image_urls(html);
-        assert_eq!(urls.len(), 3);
-        assert!(urls.contains(&"https://i.natgeofe.com/n/abc123/photo1.jpg".to_string()));
-        assert!(urls.contains(&"https://i.natgeofe.com/n/def456/photo2.jpg".to_string()));
-        assert!(urls.contains(&"https://i.natgeofe.com/n/ghi789/photo3.jpg".to_string()));
+    fn test_lock_screen_prefers_existing_legacy_swaylock_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let legacy = temp_dir.path().join(".swaylock").join("config");
+        fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+        fs::write(&legacy, "color=000000\n").unwrap();
+        let home = temp_dir.path().to_string_lossy().to_string();
+        let system = FakeSystem {
+            commands: vec!["swaylock"],
+            env: vec![("HOME", Box::leak(home.into_boxed_str()))],
+            ..FakeSystem::default()
+        };
+        let sway = environment(DesktopEnvironment::Swww, SessionType::Wayland);
+        assert_eq!(
+            LockScreenMethod::select(&sway, &system).unwrap(),
+            LockScreenMethod::Swaylock(legacy)
+        );
     }
 
     #[test]
-    fn test_extract_natgeo_image_urls_filters_crops() {
-        // Test that crop variants are filtered out
-        let html = r#"
-            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg">
-            <img src="https://i.natgeofe.com/n/abc123/photo1_16x9.jpg">
-            <img src="https://i.natgeofe.com/n/abc123/photo1_3x2.jpg">
-            <img src="https://i.natgeofe.com/n/abc123/photo1_square.jpg">
-        "#;
+    fn test_lock_screen_kde_commands() {
+        let runner = FakeCommands::default();
+        let method = LockScreenMethod::Kde {
+            kwriteconfig: "kwriteconfig6",
+            qdbus: Some("qdbus6"),
+        };
+        method
+            .apply(Path::new("/photos/2024-01-01/reef.jpg"), &runner)
+            .unwrap();
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec![
+                "kwriteconfig6 --file kscreenlockerrc --group Greeter --group Wallpaper \
+                 --group org.kde.image --group General --key Image \
+                 file:///photos/2024-01-01/reef.jpg"
+                    .to_string(),
+                "qdbus6 org.freedesktop.ScreenSaver /ScreenSaver org.kde.screensaver.configure"
+                    .to_string(),
+            ]
+        );
 
-        let urls = extract_natgeo_image_urls(html);
-        // Should only include the raw image, not crop variants
-        assert_eq!(urls.len(), 1);
-        assert!(urls.contains(&"https://i.natgeofe.com/n/abc123/photo1.jpg".to_string()));
+        // A locker that can't be reached doesn't undo the config change
+        let runner = FakeCommands {
+            failing: vec!["qdbus"],
+            ..FakeCommands::default()
+        };
+        let method = LockScreenMethod::Kde {
+            kwriteconfig: "kwriteconfig5",
+            qdbus: Some("qdbus"),
+        };
+        assert!(method.apply(Path::new("/photos/reef.jpg"), &runner).is_ok());
+        assert_eq!(runner.calls.borrow().len(), 2);
+
+        let runner = FakeCommands {
+            failing: vec!["kwriteconfig5"],
+            ..FakeCommands::default()
+        };
+        assert!(method
+            .apply(Path::new("/photos/reef.jpg"), &runner)
+            .is_err());
+        assert_eq!(runner.calls.borrow().len(), 1);
     }
 
     #[test]
-    fn test_extract_natgeo_image_urls_deduplicates() {
-        // Test that duplicate URLs are deduplicated
-        let html = r#"
-            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg">
-            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg">
-            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg">
-        "#;
-
-        let urls = extract_natgeo_image_urls(html);
-        assert_eq!(urls.len(), 1);
+    fn test_lock_screen_gnome_command() {
+        let runner = FakeCommands::default();
+        LockScreenMethod::Gnome
+            .apply(Path::new("/photos/reef.jpg"), &runner)
+            .unwrap();
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec![
+                "gsettings set org.gnome.desktop.screensaver picture-uri file:///photos/reef.jpg"
+                    .to_string()
+            ]
+        );
     }
 
     #[test]
-    fn test_extract_natgeo_image_urls_handles_query_params() {
-        // Test that URLs with query parameters are handled correctly
-        let html = r#"
-            <img src="https://i.natgeofe.com/n/abc123/photo1.jpg?w=1200">
-        "#;
+    fn test_lock_screen_swaylock_config() {
+        assert_eq!(
+            swaylock_config_with_image("", Path::new("/photos/reef.jpg")),
+            "image=/photos/reef.jpg\n"
+        );
+        assert_eq!(
+            swaylock_config_with_image(
+                "# mine\ncolor=000000\nimage = /old.jpg\nscaling=fill\nimage=eDP-1:/old.png",
+                Path::new("/photos/reef.jpg")
+            ),
+            "# mine\ncolor=000000\nscaling=fill\nimage=/photos/reef.jpg\n"
+        );
+        // Options that only start with "image" are kept
+        assert_eq!(
+            swaylock_config_with_image("image-scaling=fill\n", Path::new("/reef.jpg")),
+            "image-scaling=fill\nimage=/reef.jpg\n"
+        );
 
-        let urls = extract_natgeo_image_urls(html);
-        assert_eq!(urls.len(), 1);
-        // Should strip query params
-        assert!(urls.contains(&"https://i.natgeofe.com/n/abc123/photo1.jpg".to_string()));
+        let temp_dir = TempDir::new().unwrap();
+        let config = temp_dir.path().join("swaylock").join("config");
+        let method = LockScreenMethod::Swaylock(config.clone());
+        let runner = FakeCommands::default();
+        method.apply(Path::new("/photos/a.jpg"), &runner).unwrap();
+        method.apply(Path::new("/photos/b.jpg"), &runner).unwrap();
+        assert_eq!(
+            fs::read_to_string(&config).unwrap(),
+            "image=/photos/b.jpg\n"
+        );
+        assert!(runner.calls.borrow().is_empty());
     }
 
     #[test]
-    fn test_photo_collection_struct() {
-        let collection = PhotoCollection {
-            name: "Best Photos - October 2018".to_string(),
-            photos: vec![
-                PhotoInfo {
-                    image_url: "https://example.com/photo1.jpg".to_string(),
-                    title: "Photo 1".to_string(),
-                },
-                PhotoInfo {
-                    image_url: "https://example.com/photo2.jpg".to_string(),
-                    title: "Photo 2".to_string(),
-                },
-            ],
+    fn test_primary_photo_is_monitor_one() {
+        let result = |slot: usize, ok: bool| AssignmentResult {
+            assignment: WallpaperAssignment {
+                location: format!("Monitor {}", slot + 1),
+                photo_path: PathBuf::from(format!("/photos/{slot}.jpg")),
+                is_newest: slot == 0,
+                slot,
+            },
+            result: if ok {
+                Ok(())
+            } else {
+                Err(PhotoError::Wallpaper("failed".to_string()))
+            },
         };
+        let report = monitors_report(vec![result(1, true), result(0, true)]);
+        assert_eq!(report.primary_photo(), Some(PathBuf::from("/photos/0.jpg")));
+        // Another monitor's photo never stands in for it
+        let report = monitors_report(vec![result(0, false), result(1, true)]);
+        assert_eq!(report.primary_photo(), None);
+    }
 
-        assert_eq!(collection.name, "Best Photos - October 2018");
-        assert_eq!(collection.photos.len(), 2);
-        assert_eq!(collection.photos[0].title, "Photo 1");
-        assert_eq!(collection.photos[1].title, "Photo 2");
+    // ============================================================================
+    // Command Runner Tests
+    // ============================================================================
+
+    #[test]
+    fn test_plasma_apply_backend_command() {
+        let runner = FakeCommands::default();
+        let calls = runner.calls.clone();
+        let backend = PlasmaApplyBackend::new(Box::new(runner));
+        backend
+            .set(Target::AllMonitors, Path::new("/photos/reef.jpg"))
+            .unwrap();
+        assert_eq!(
+            *calls.borrow(),
+            vec!["plasma-apply-wallpaperimage /photos/reef.jpg".to_string()]
+        );
+        assert_eq!(backend.desktop(), DesktopEnvironment::PlasmaFallback);
+
+        let backend = PlasmaApplyBackend::new(Box::new(FakeCommands {
+            failing: vec!["plasma-apply-wallpaperimage"],
+            ..FakeCommands::default()
+        }));
+        assert!(matches!(
+            backend.set(Target::AllMonitors, Path::new("/photos/reef.jpg")),
+            Err(PhotoError::Wallpaper(_))
+        ));
     }
 
     #[test]
-    fn test_collection_download_result_struct() {
-        let result = CollectionDownloadResult {
-            downloaded: 5,
-            skipped: 3,
-            failed: 1,
+    fn test_plasma_counts_over_qdbus() {
+        let runner = FakeCommands {
+            outputs: vec![
+                ("qdbus6", "org.kde.plasmashell", "2\n"),
+                ("qdbus6", "org.kde.KWin", "4\n"),
+            ],
+            ..FakeCommands::default()
         };
+        let transport = PlasmaTransport::Qdbus("qdbus6");
+        assert_eq!(get_monitor_count(transport, &runner), 2);
+        assert_eq!(get_virtual_desktop_count(transport, &runner), 4);
+        let calls = runner.calls.borrow();
+        assert_eq!(
+            calls[0],
+            format!(
+                "qdbus6 org.kde.plasmashell /PlasmaShell org.kde.PlasmaShell.evaluateScript \
+                 {PLASMA_SCREEN_COUNT_SCRIPT}"
+            )
+        );
+        assert_eq!(
+            calls[1],
+            "qdbus6 org.kde.KWin /VirtualDesktopManager org.kde.KWin.VirtualDesktopManager.count"
+        );
 
-        assert_eq!(result.downloaded, 5);
-        assert_eq!(result.skipped, 3);
-        assert_eq!(result.failed, 1);
+        // Unreadable or failed answers count as one
+        let runner = FakeCommands {
+            outputs: vec![("qdbus", "org.kde.plasmashell", "0\n")],
+            ..FakeCommands::default()
+        };
+        let transport = PlasmaTransport::Qdbus("qdbus");
+        assert_eq!(get_monitor_count(transport, &runner), 1);
+        assert_eq!(get_virtual_desktop_count(transport, &runner), 1);
+        let runner = FakeCommands {
+            failing: vec!["qdbus"],
+            ..FakeCommands::default()
+        };
+        assert_eq!(get_monitor_count(transport, &runner), 1);
+        assert_eq!(get_virtual_desktop_count(transport, &runner), 1);
     }
 
     #[test]
-    fn test_is_collection_photo_filename() {
-        // Should match "best-pod" patterns
-        assert!(is_collection_photo_filename("01-best-pod-october-18"));
-        assert!(is_collection_photo_filename("02-best-pod-september-18"));
-        assert!(is_collection_photo_filename("09-best-pod-july-18"));
-        assert!(is_collection_photo_filename("best_pod_landscapes"));
-        assert!(is_collection_photo_filename("01-best_pod-august-18"));
+    fn test_enumerate_monitors_with_runner() {
+        let runner = FakeCommands {
+            outputs: vec![("hyprctl", "monitors", HYPRCTL_MONITORS)],
+            ..FakeCommands::default()
+        };
+        let monitors = enumerate_monitors_with(
+            DesktopEnvironment::Hyprland,
+            &runner,
+            &FakeSystem::default(),
+        );
+        let names: Vec<&str> = monitors.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["DP-1", "DP-2"]);
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec!["hyprctl monitors -j".to_string()]
+        );
 
-        // Case insensitive
-        assert!(is_collection_photo_filename("01-BEST-POD-October-18"));
-        assert!(is_collection_photo_filename("BEST_POD_Landscapes"));
+        // A tool that fails falls through to xrandr
+        let runner = FakeCommands {
+            failing: vec!["hyprctl"],
+            outputs: vec![(
+                "xrandr",
+                "--current",
+                "HDMI-1 connected 1920x1080+0+0 (normal) 527mm x 296mm\n",
+            )],
+            ..FakeCommands::default()
+        };
+        let monitors = enumerate_monitors_with(
+            DesktopEnvironment::Hyprland,
+            &runner,
+            &FakeSystem::default(),
+        );
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].name, "HDMI-1");
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec![
+                "hyprctl monitors -j".to_string(),
+                "xrandr --current".to_string()
+            ]
+        );
 
-        // Should NOT match other patterns
-        assert!(!is_collection_photo_filename("MossForest"));
-        assert!(!is_collection_photo_filename("GettyImages-109899052"));
-        assert!(!is_collection_photo_filename("disneyplus"));
-        assert!(!is_collection_photo_filename("kids"));
-        assert!(!is_collection_photo_filename("SPI-1162458"));
+        // swaymsg is only asked inside sway
+        let runner = FakeCommands::default();
+        let sway = env_system(&[], &[], &[("SWAYSOCK", "/run/user/1000/sway-ipc.sock")]);
+        assert!(enumerate_monitors_with(DesktopEnvironment::Unknown, &runner, &sway).is_empty());
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec![
+                "swaymsg -t get_outputs -r".to_string(),
+                "xrandr --current".to_string()
+            ]
+        );
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_find_downloaded_file() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir = temp_dir.path().to_str().unwrap();
-
-        // Create a test file
-        let test_file = temp_dir.path().join("test_photo.jpg");
-        fs::write(&test_file, "fake image data").unwrap();
+    fn test_live_commands_error_mapping() {
+        assert_eq!(LiveCommands.run("sh", &["-c", "echo hi"]).unwrap(), "hi\n");
+        // Exiting with an error is the wallpaper tool's complaint, from stderr
+        match LiveCommands.run("sh", &["-c", "echo oops >&2; exit 3"]) {
+            Err(PhotoError::Wallpaper(message)) => assert_eq!(message, "oops"),
+            other => panic!("expected a wallpaper error, got {other:?}"),
+        }
+        // Failing to start is a command error naming the program
+        match LiveCommands.run("natgeo-no-such-program", &[]) {
+            Err(PhotoError::Command(message)) => {
+                assert!(message.starts_with("natgeo-no-such-program:"), "{message}");
+            }
+            other => panic!("expected a command error, got {other:?}"),
+        }
+    }
 
-        // Should find the file
-        let found = find_downloaded_file(dir, "test_photo");
-        assert!(found.is_some());
-        assert_eq!(found.unwrap(), test_file);
+    // ============================================================================
+    // HTTP Status Error Tests
+    // ============================================================================
 
-        // Should not find non-existent file
-        let not_found = find_downloaded_file(dir, "nonexistent");
-        assert!(not_found.is_none());
+    #[test]
+    fn test_http_status_is_permanent() {
+        let error = |status| PhotoError::HttpStatus {
+            status,
+            url: "https://example.com/".to_string(),
+        };
+        for status in [400, 401, 403, 404, 410] {
+            assert!(error(status).is_permanent(), "{status}");
+        }
+        for status in [408, 429, 500, 502, 503] {
+            assert!(!error(status).is_permanent(), "{status}");
+        }
+        assert!(!PhotoError::Parse("no image".to_string()).is_permanent());
+        assert_eq!(error(404).to_string(), "HTTP 404 from https://example.com/");
     }
 
     #[test]
-    fn test_min_photo_size_constant() {
-        // Verify the minimum size is reasonable (50KB)
-        assert_eq!(MIN_PHOTO_SIZE_BYTES, 50_000);
+    fn test_download_then_set_stops_retrying_permanent_errors() {
+        let mut downloads = 0;
+        let outcome = download_then_set(
+            quick_run(3),
+            &CancelFlag::new(),
+            &mut || {
+                downloads += 1;
+                Err(PhotoError::HttpStatus {
+                    status: 404,
+                    url: NATGEO_POD_URL.to_string(),
+                })
+            },
+            &mut || Ok(()),
+        )
+        .unwrap();
+        assert_eq!(downloads, 1);
+        assert!(matches!(
+            outcome,
+            RunOutcome::DownloadFailed(PhotoError::HttpStatus { status: 404, .. })
+        ));
+
+        let mut downloads = 0;
+        download_then_set(
+            quick_run(3),
+            &CancelFlag::new(),
+            &mut || {
+                downloads += 1;
+                Err(PhotoError::HttpStatus {
+                    status: 503,
+                    url: NATGEO_POD_URL.to_string(),
+                })
+            },
+            &mut || Ok(()),
+        )
+        .unwrap();
+        assert_eq!(downloads, 3);
     }
 }
@@ -1,21 +1,91 @@
-use chrono::Local;
-use clap::{Parser, Subcommand, ValueEnum};
+use chrono::{Local, NaiveDate};
+use clap::{ArgGroup, Args, CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use clap_complete::Shell;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
 use natgeo_wallpapers::{
-    download_collection, download_natgeo_photo_of_the_day, expand_tilde,
-    extract_collection_name_from_url, get_collection_photos, get_current_web_natgeo_gallery,
-    sanitize_title, set_wallpapers_with_options, write_log, PhotoError, WallpaperMode,
-    PHOTO_SAVE_PATH,
+    app_log_path, apply_normalize, auto_color, autostart_desktop_entry, backfill, cache_dir,
+    clean_photos, collection_dir, color_output, command_exists, config, config_path, cron_line,
+    crontab_from_listing, crontab_with_line, crontab_without_line, current_link_name,
+    daemon_pid_path, data_dirs, date_dir_for, dedup_photos, default_config_file,
+    detect_desktop_environment, detect_wallpaper_backend_with, doctor_checks, download_collection,
+    download_then_set, downloaded_title, ensure_managed_dir, execute_uninstall, expand_tilde,
+    extract_collection_name_from_url, find_photos_in_path, forward_hangups, gather_stats,
+    history_path, init_data_dirs, install_interrupt_handler, interval_seconds, is_valid_interval,
+    is_valid_time, launchd_plist, list_photos, load_config, local_collections, log_dir,
+    parse_pick_index, parse_schedule, parse_size, photo_dir, photo_dir_or, pick_candidates,
+    pick_dark_photo, plan_normalize, plan_uninstall, purge_image_cache, record_photo_metadata,
+    remove_in_progress_files, repair_photo, resolve_collection, resolve_photo_arg, rotate_log,
+    run_debounced, run_hooks, run_pywal, run_rotation, search_candidates, set_color_output,
+    set_config, set_json_output, set_lock_screen, set_plasma_slideshow, set_previous_wallpapers,
+    set_quiet_output, set_strict_logging, set_wallpapers_with_backend, shell_quote, shutdown_flag,
+    status, validate_photo_date, verify_photos, wallpaper_log_path, write_log, write_log_checked,
+    AssignmentResult, BackendOptions, BackfillOptions, BackfillStatus, BrightnessFilter,
+    CheckStatus, CollectionDownloadOptions, CollectionDownloadResult, CollectionFilter,
+    CollectionProgress, CommandRunner, Config, ConvertFormat, DateRange, DesktopEnvironment,
+    DesktopNotifier, DetectedEnvironment, DimensionFilter, DoctorProbes, DownloadOptions,
+    DownloadOutcome, DownloadReport, DuplicateAction, FitMode, HookContext, HookEvent, HooksConfig,
+    HttpConfig, ListOptions, LiveCommands, LiveSystem, ManifestConfig, NatGeoClient, Notification,
+    Notifier, Orientation, OutputWatcher, Paint, PhotoError, PhotoList, PhotoListingCache,
+    PickCandidate, PidFile, PlasmaTransport, Repair, ResizeMode, RetentionConfig, RetentionPolicy,
+    RotationHistory, RotationSchedule, RunOptions, RunOutcome, ScheduleType, SessionType,
+    SortOrder, SpreadStrategy, TargetFilter, TransitionOptions, UninstallOptions, UninstallPaths,
+    WallpaperConfig, WallpaperMode, WallpaperOptions, WallpaperReport, AUTOSTART_FILE_NAME,
+    CRON_TAG, DEFAULT_BACKFILL_DELAY, DEFAULT_FILENAME_TEMPLATE, DEFAULT_RUN_ATTEMPTS,
+    HOTPLUG_DEBOUNCE, INTERRUPTED_EXIT_CODE, LAUNCHD_LABEL, SWWW_TRANSITIONS,
 };
-use owo_colors::OwoColorize;
+use std::fmt::Write as _;
 use std::fs;
-use std::io::{self, Write};
-use std::process::Command;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, ExitCode};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
 #[command(name = "natgeo-wallpapers")]
 #[command(about = "National Geographic Photo of the Day downloader and wallpaper setter")]
 #[command(version)]
+// Each global flag is its own switch
+#[allow(clippy::struct_excessive_bools)]
 struct Cli {
+    /// Print a JSON document describing the result on stdout (download, download-collection, set, list, stats)
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Fail instead of warning when a log file can't be written
+    #[arg(long, global = true)]
+    strict_logs: bool,
+
+    /// Print what the tool is doing on stderr; repeat for more detail (-vv, -vvv)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Only print results and errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Show a desktop notification for new photos, wallpaper changes, and failures
+    #[arg(long, global = true)]
+    notify: bool,
+
+    /// Shell command to run after each new photo is saved
+    #[arg(long, global = true, value_name = "CMD")]
+    hook_post_download: Option<String>,
+
+    /// Shell command to run after each wallpaper is applied
+    #[arg(long, global = true, value_name = "CMD")]
+    hook_post_set: Option<String>,
+
+    /// Fail when a hook fails instead of only logging it
+    #[arg(long, global = true)]
+    hook_strict: bool,
+
+    /// When to color output; `auto` colors a terminal unless `NO_COLOR` is set
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t = ColorWhen::Auto)]
+    color: ColorWhen,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -23,24 +93,82 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Download today's National Geographic Photo of the Day
-    Download,
+    Download {
+        /// Download the photo from a past date (YYYY-MM-DD) instead of today's
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        date: Option<NaiveDate>,
+
+        #[command(flatten)]
+        options: DownloadArgs,
+    },
     /// Set wallpaper(s) from downloaded photos
     Set {
-        /// How to distribute wallpapers across monitors/desktops
-        #[arg(short, long, value_enum, default_value_t = Mode::Monitors)]
-        mode: Mode,
+        #[command(flatten)]
+        set: SetArgs,
 
-        /// Also set the lock screen wallpaper (KDE Plasma only)
-        #[arg(short, long)]
-        lock_screen: bool,
+        /// Have KDE Plasma rotate the photos itself with its slideshow wallpaper
+        #[arg(long)]
+        plasma_slideshow: bool,
 
-        /// Path to a specific photo or directory to use (default: ~/Pictures/NationalGeographic/)
-        #[arg(short, long)]
-        path: Option<String>,
+        /// How often the Plasma slideshow changes photo (e.g. 30m, 1h)
+        #[arg(
+            long,
+            value_name = "INTERVAL",
+            value_parser = parse_interval,
+            default_value = "30m",
+            requires = "plasma_slideshow"
+        )]
+        interval: String,
 
-        /// Select a random photo instead of the newest
-        #[arg(short, long)]
-        random: bool,
+        /// Set the wallpapers shown before the current ones again
+        #[arg(
+            long,
+            conflicts_with_all = ["plasma_slideshow", "mode", "path", "photo", "monitors", "desktops", "random", "sort", "spread", "favorites_only", "from_collection"]
+        )]
+        previous: bool,
+
+        /// Choose the photo from a numbered list, or by typing part of its name
+        #[arg(
+            long,
+            conflicts_with_all = ["plasma_slideshow", "previous", "photo", "random", "sort", "spread", "favorites_only"]
+        )]
+        pick: bool,
+
+        /// Show what would be set without changing any wallpaper
+        #[arg(long, conflicts_with_all = ["plasma_slideshow", "previous"])]
+        dry_run: bool,
+    },
+    /// Download today's photo, then set the wallpaper, falling back to existing photos when offline
+    Run {
+        #[command(flatten)]
+        set: SetArgs,
+
+        /// Times to try the download before falling back to existing photos
+        #[arg(long, default_value_t = DEFAULT_RUN_ATTEMPTS, value_parser = clap::value_parser!(u32).range(1..))]
+        attempts: u32,
+
+        /// Exit with an error when the download fails, even though the wallpaper was set
+        #[arg(long = "no-offline-ok", action = clap::ArgAction::SetFalse)]
+        offline_ok: bool,
+
+        #[command(flatten)]
+        download: DownloadArgs,
+    },
+    /// Keep running and re-apply the wallpaper whenever monitors are connected or changed
+    Watch {
+        #[command(flatten)]
+        set: SetArgs,
+    },
+    /// Keep running and set a new wallpaper every interval, without a timer
+    ///
+    /// SIGHUP sets a new wallpaper right away, with photos added since the last one.
+    Daemon {
+        #[command(flatten)]
+        set: SetArgs,
+
+        /// How often to change the wallpaper (e.g. 20m, 1h)
+        #[arg(long, value_name = "INTERVAL", value_parser = parse_interval, default_value = "30m")]
+        interval: String,
     },
     /// Set up systemd timer, download today's photo, and set wallpaper
     Install {
@@ -56,677 +184,4906 @@ enum Commands {
         #[arg(short, long)]
         random: bool,
 
-        /// Path to photos for wallpaper (default: ~/Pictures/NationalGeographic/)
+        /// Path to photos for wallpaper (default: the photo directory, ~/Pictures/NationalGeographic/)
         #[arg(short, long)]
         path: Option<String>,
 
-        /// Also set the lock screen wallpaper (KDE Plasma only)
+        /// Also set the lock screen wallpaper (KDE Plasma, GNOME, or swaylock)
         #[arg(short, long)]
         lock_screen: bool,
+
+        /// Delete photos older than N days after each run
+        #[arg(long, value_name = "N")]
+        keep_days: Option<u32>,
+
+        /// Replace an existing schedule without asking
+        #[arg(short, long)]
+        force: bool,
+
+        /// Delay each run by a random amount up to this interval (e.g., 15m)
+        #[arg(long, value_name = "INTERVAL", value_parser = parse_interval)]
+        jitter: Option<String>,
+
+        /// Also rotate the wallpaper through downloaded photos this often (e.g., 1h)
+        #[arg(long, value_name = "INTERVAL", value_parser = parse_interval)]
+        set_every: Option<String>,
+
+        /// Also run `watch` as a user service to re-apply the wallpaper when monitors change
+        #[arg(long, conflicts_with_all = ["uninstall", "status"])]
+        watch: bool,
+
+        /// Show the installed schedule and its last and next runs
+        #[arg(long, conflicts_with = "uninstall")]
+        status: bool,
+
+        /// Run at login from ~/.config/autostart instead of on a timer
+        #[arg(
+            long,
+            conflicts_with_all = ["uninstall", "status", "time", "keep_days", "jitter", "set_every", "watch"]
+        )]
+        autostart: bool,
+    },
+    /// Download the photos of a range of past days
+    #[command(group(ArgGroup::new("range").required(true)))]
+    Backfill {
+        /// First day to download (YYYY-MM-DD)
+        #[arg(long, value_name = "DATE", value_parser = parse_date, group = "range")]
+        from: Option<NaiveDate>,
+
+        /// Last day to download (default: today)
+        #[arg(long, value_name = "DATE", value_parser = parse_date, requires = "from")]
+        to: Option<NaiveDate>,
+
+        /// Download the last N days, ending today
+        #[arg(long, value_name = "N", group = "range", conflicts_with = "to")]
+        last: Option<u32>,
+
+        /// Seconds to wait between days
+        #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_BACKFILL_DELAY.as_secs())]
+        delay: u64,
+
+        #[command(flatten)]
+        options: DownloadArgs,
     },
     /// Download photos from a monthly "Best of Photo of the Day" collection
     DownloadCollection {
         /// URL of the collection page
         #[arg(short, long)]
         url: String,
+
+        /// Only download images whose filename contains PATTERN (default: best-pod)
+        #[arg(long, value_name = "PATTERN", conflicts_with = "no_filter")]
+        filter: Option<String>,
+
+        /// Download every image on the page, whatever its filename
+        #[arg(long)]
+        no_filter: bool,
+
+        #[command(flatten)]
+        options: DownloadArgs,
     },
-}
+    /// Rename photos to a consistent filename template (dry run unless --apply)
+    Normalize {
+        /// Filename template using {title}, {stem}, and {date} (default: {title})
+        #[arg(short, long)]
+        template: Option<String>,
 
-#[derive(Copy, Clone, ValueEnum)]
-enum Mode {
-    /// Different wallpaper per physical monitor
-    Monitors,
-    /// Different wallpaper per virtual desktop (same across monitors)
-    VirtualDesktops,
-    /// Different wallpaper per monitor x virtual desktop combination
-    Both,
-}
+        /// Path to the photo library (default: the photo directory, ~/Pictures/NationalGeographic/)
+        #[arg(short, long)]
+        path: Option<String>,
 
-impl From<Mode> for WallpaperMode {
-    fn from(mode: Mode) -> Self {
-        match mode {
-            Mode::Monitors => Self::Monitors,
-            Mode::VirtualDesktops => Self::VirtualDesktops,
-            Mode::Both => Self::Both,
-        }
-    }
-}
+        /// Only show what would be renamed (the default)
+        #[arg(long, conflicts_with = "apply")]
+        dry_run: bool,
 
-fn main() -> Result<(), PhotoError> {
-    let cli = Cli::parse();
+        /// Actually rename files and update references
+        #[arg(long)]
+        apply: bool,
+    },
+    /// List downloaded photos, newest first
+    List {
+        /// Include photos from downloaded collections
+        #[arg(long)]
+        collections: bool,
 
-    match cli.command {
-        Some(Commands::Download) => download()?,
-        Some(Commands::Set {
-            mode,
-            lock_screen,
-            path,
-            random,
-        }) => {
-            set_wallpapers_with_options(mode.into(), path, random)?;
-            if lock_screen {
-                set_lock_screen_wallpaper()?;
-            }
-        }
-        Some(Commands::Install {
-            time,
-            uninstall,
-            random,
-            path,
-            lock_screen,
-        }) => {
-            if uninstall {
-                uninstall_systemd_timer()?;
-            } else {
-                install_systemd_timer(time, random, path, lock_screen)?;
-            }
-        }
-        Some(Commands::DownloadCollection { url }) => {
-            download_collection_cmd(&url)?;
-        }
-        None => {
-            // Default behavior: download (backwards compatibility)
-            download()?;
-        }
-    }
+        /// Show at most N photos
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
 
-    Ok(())
-}
+        /// Only show photos from this date (YYYY-MM-DD) onwards
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        since: Option<NaiveDate>,
+    },
+    /// Mark a photo as a favorite, which random selection picks more often
+    Favorite {
+        #[command(flatten)]
+        list: PhotoListArgs,
+    },
+    /// Never use a photo as wallpaper again
+    Exclude {
+        #[command(flatten)]
+        list: PhotoListArgs,
+    },
+    /// Show the wallpapers applied most recently, newest first
+    History {
+        /// Show at most N wallpapers
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+    },
+    /// List downloaded collections and their photo counts
+    Collections {
+        /// Print only the names, one per line, as shell completion uses them
+        #[arg(long)]
+        names: bool,
+    },
+    /// Show photo counts, dates, and disk usage for the library
+    Stats {
+        /// Path to the photo library (default: the photo directory, ~/Pictures/NationalGeographic/)
+        #[arg(short, long)]
+        path: Option<String>,
+    },
+    /// Find empty, truncated, or misnamed photos
+    Verify {
+        /// Path to the photo library (default: the photo directory, ~/Pictures/NationalGeographic/)
+        #[arg(short, long)]
+        path: Option<String>,
 
-/// Download today's National Geographic Photo of the Day
-fn download() -> Result<(), PhotoError> {
-    println!("{}", "=== National Geographic Photo Downloader ===".green());
-    println!();
+        /// Rename misnamed photos, and re-download or delete broken ones
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Delete the oldest date directories to keep the library within limits
+    #[command(group(ArgGroup::new("policy").multiple(true)))]
+    Clean {
+        /// Keep photos from the last N days
+        #[arg(long, value_name = "N", group = "policy")]
+        keep_days: Option<u32>,
 
-    // Get the current date to create a directory for that date
-    let today_date = Local::now().format("%d-%m-%Y").to_string();
-    let expanded_base_path = expand_tilde(PHOTO_SAVE_PATH);
-    let save_dir = format!("{}{}", expanded_base_path, today_date);
+        /// Keep the N newest date directories
+        #[arg(long, value_name = "N", group = "policy")]
+        keep_count: Option<usize>,
 
-    // Create a directory for today's date (if it doesn't exist)
-    if let Err(e) = fs::create_dir_all(&save_dir) {
-        return Err(PhotoError::File(e));
-    }
+        /// Delete the oldest directories until the library fits in SIZE (e.g. 500M, 2G)
+        #[arg(long, value_name = "SIZE", group = "policy", value_parser = parse_max_size)]
+        max_size: Option<String>,
 
-    // Get the current photo data
-    println!("Fetching photo information...");
-    let photo_info = match get_current_web_natgeo_gallery() {
-        Ok(info) => {
-            println!("{} Found: {}", "✓".green(), info.title);
-            info
-        }
-        Err(e) => {
-            println!("{} Failed to fetch photo information: {}", "✗".red(), e);
-            let log_path = format!("{}/error.log", save_dir);
-            let error_msg = format!("Failed to fetch photo information: {}", e);
-            write_log(&log_path, &error_msg);
-            return Err(e);
-        }
-    };
+        /// Also delete old collections
+        #[arg(long)]
+        collections: bool,
 
-    // Sanitize the title to make it a valid filename
-    let sanitized_title = sanitize_title(&photo_info.title);
-    let log_path = format!("{}/{}.log", save_dir, sanitized_title);
+        /// Delete the resized and converted copies of photos kept in the cache
+        #[arg(long)]
+        cache: bool,
 
-    // Log start of download
-    write_log(
-        &log_path,
-        &format!("Starting download for: {}", photo_info.title),
-    );
-    write_log(&log_path, &format!("Image URL: {}", photo_info.image_url));
+        /// Only show what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Find byte-identical photos across the library and record them in the manifest
+    Dedup {
+        /// Replace duplicates with hard links to the original
+        #[arg(long, conflicts_with = "remove")]
+        link: bool,
 
-    // Download the photo and save it with the correct extension
-    println!("Downloading photo...");
-    match download_natgeo_photo_of_the_day(
-        &photo_info.image_url,
-        &save_dir,
-        &sanitized_title,
-        &log_path,
-    ) {
-        Ok(()) => {
-            println!(
-                "{} Photo saved to: {}/{}",
-                "✓".green(),
-                save_dir,
-                sanitized_title
-            );
-            let success_msg = format!(
-                "Successfully downloaded photo to: {}/{}",
-                save_dir, sanitized_title
-            );
-            write_log(&log_path, &success_msg);
-        }
-        Err(e) => {
-            println!("{} Failed to download photo: {}", "✗".red(), e);
-            let error_msg = format!("Failed to download photo: {}", e);
-            write_log(&log_path, &error_msg);
-            write_log(&log_path, &format!("Error details: {:?}", e));
-            return Err(e);
-        }
-    }
+        /// Delete duplicates along with their logs and metadata
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Create or show the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Check the network, directories, desktop, and timer for problems
+    Doctor,
+    /// Print a shell completion script
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Remove the systemd timer, logs, state, and caches
+    Uninstall {
+        /// Also remove the config file
+        #[arg(long)]
+        config: bool,
 
-    write_log(&log_path, "Download process completed successfully");
+        /// Also delete the photo and collection directories
+        #[arg(long)]
+        photos: bool,
 
-    println!();
-    println!("{}", "=== Download Complete ===".green());
+        /// Don't ask for confirmation before deleting photos
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
 
-    Ok(())
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Write a commented config file listing every key and its default
+    Init {
+        /// Overwrite an existing config file
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Print the effective configuration, with defaults filled in
+    Show,
 }
 
-/// Download photos from a "Best of Photo of the Day" collection
-fn download_collection_cmd(url: &str) -> Result<(), PhotoError> {
-    println!(
-        "{}",
-        "=== National Geographic Collection Downloader ===".green()
-    );
-    println!();
+/// Options shared by `favorite` and `exclude`
+#[derive(Args)]
+struct PhotoListArgs {
+    /// Path of the photo, or `current` for the wallpaper on the primary monitor
+    #[arg(value_name = "PATH|current", required_unless_present = "list")]
+    photo: Option<String>,
 
-    // Validate URL contains expected pattern
-    if !url.contains("nationalgeographic.com") {
-        println!(
-            "{} Invalid URL: must be a National Geographic URL",
-            "✗".red()
-        );
-        return Err(PhotoError::InvalidContentType(
-            "Invalid URL: must be a National Geographic URL".to_string(),
-        ));
-    }
+    /// Print the photos on the list
+    #[arg(long, conflicts_with_all = ["photo", "remove"])]
+    list: bool,
 
-    // Fetch the collection
-    println!("Fetching collection from: {}", url);
-    println!();
+    /// Take the photo off the list instead
+    #[arg(long)]
+    remove: bool,
+}
 
-    let collection = match get_collection_photos(url) {
-        Ok(c) => {
-            println!("{} Collection: {}", "✓".green(), c.name);
-            println!("{} Found {} photo(s)", "✓".green(), c.photos.len());
-            c
-        }
-        Err(e) => {
-            println!("{} Failed to fetch collection: {}", "✗".red(), e);
-            return Err(e);
-        }
-    };
+/// Options shared by `set` and `run`
+#[derive(Args)]
+// Each flag is its own switch
+#[allow(clippy::struct_excessive_bools)]
+struct SetArgs {
+    /// How to distribute wallpapers across monitors/desktops [default: monitors]
+    #[arg(short, long, value_enum)]
+    mode: Option<Mode>,
 
-    println!();
-    println!("{}", "Photos in collection:".yellow());
-    for (i, photo) in collection.photos.iter().enumerate() {
-        println!("  {}. {}", i + 1, photo.title);
-    }
-    println!();
+    /// Also set the lock screen wallpaper (KDE Plasma, GNOME, or swaylock)
+    #[arg(short, long)]
+    lock_screen: bool,
 
-    // Extract collection name from URL for directory
-    let collection_name = extract_collection_name_from_url(url);
+    /// Theme the terminal from the new wallpaper with pywal (`wal -n -i PHOTO`)
+    #[arg(long)]
+    pywal: bool,
 
-    // Download the collection
-    println!("{}", "Downloading photos...".yellow());
-    println!();
+    /// Path to a specific photo or directory to use (default: the photo directory, ~/Pictures/NationalGeographic/)
+    #[arg(short, long, value_hint = ValueHint::AnyPath)]
+    path: Option<String>,
 
-    let result = download_collection(&collection, &collection_name)?;
+    /// Only use photos from this downloaded collection; a unique prefix of its name will do
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["path", "photo"])]
+    from_collection: Option<String>,
 
-    println!();
-    println!("{}", "=== Download Summary ===".green());
-    println!("  Downloaded: {}", result.downloaded.to_string().green());
-    println!(
-        "  Skipped (already exist): {}",
-        result.skipped.to_string().yellow()
-    );
-    if result.failed > 0 {
-        println!("  Failed: {}", result.failed.to_string().red());
-    }
+    /// Set this photo on every target instead of picking one
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["path", "random", "sort", "spread"]
+    )]
+    photo: Option<String>,
 
-    let save_path = format!(
-        "{}{}",
-        expand_tilde(natgeo_wallpapers::COLLECTION_SAVE_PATH),
-        collection_name
-    );
-    println!();
-    println!("Photos saved to: {}", save_path.green());
+    /// Only set this monitor, by number from 1 or by name such as DP-1; repeat for more [default: all]
+    #[arg(long = "monitor", value_name = "N|NAME", value_parser = parse_monitor)]
+    monitors: Vec<MonitorArg>,
 
-    Ok(())
-}
+    /// Only set this virtual desktop, counting from 1; repeat for more [default: all]
+    #[arg(long = "desktop", value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    desktops: Vec<u16>,
 
-/// Set the lock screen wallpaper (KDE Plasma only)
-fn set_lock_screen_wallpaper() -> Result<(), PhotoError> {
-    use natgeo_wallpapers::find_all_photos;
+    /// Only pick photos listed with `favorite`
+    #[arg(long, conflicts_with = "photo")]
+    favorites_only: bool,
 
-    println!();
-    println!("{}", "Setting lock screen wallpaper...".yellow());
+    /// Select a random photo instead of the newest (same as --sort random)
+    #[arg(short, long, conflicts_with = "sort")]
+    random: bool,
 
-    // Find the newest photo
-    let photos = find_all_photos()?;
-    let newest_photo = photos
-        .first()
-        .ok_or_else(|| PhotoError::Command("No photos found".to_string()))?;
+    /// Order in which photos are picked [default: date]
+    #[arg(long, value_enum)]
+    sort: Option<Sort>,
 
-    // Determine which kwriteconfig to use
-    let kwriteconfig = if Command::new("which")
-        .arg("kwriteconfig6")
-        .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
-    {
-        "kwriteconfig6"
-    } else if Command::new("which")
-        .arg("kwriteconfig5")
-        .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
-    {
-        "kwriteconfig5"
-    } else {
-        println!("{} kwriteconfig not found (KDE Plasma required)", "✗".red());
-        return Err(PhotoError::Command("kwriteconfig not found".to_string()));
-    };
+    /// Spread assignments across photos from different months
+    #[arg(long, value_enum, default_value_t = Spread::None)]
+    spread: Spread,
 
-    let image_url = format!("file://{}", newest_photo.display());
-
-    let output = Command::new(kwriteconfig)
-        .args([
-            "--file",
-            "kscreenlockerrc",
-            "--group",
-            "Greeter",
-            "--group",
-            "Wallpaper",
-            "--group",
-            "org.kde.image",
-            "--group",
-            "General",
-            "--key",
-            "Image",
-            &image_url,
-        ])
-        .output()
-        .map_err(|e| PhotoError::Command(e.to_string()))?;
+    /// Only use dark or light photos; `auto` follows the desktop's color scheme
+    #[arg(long, value_enum)]
+    brightness: Option<Brightness>,
 
-    if output.status.success() {
-        println!("{} Lock screen wallpaper set", "✓".green());
-        println!(
-            "  {}",
-            "Note: Changes apply on next lock screen activation".yellow()
-        );
-        Ok(())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        println!(
-            "{} Failed to set lock screen wallpaper: {}",
-            "✗".red(),
-            stderr
-        );
-        Err(PhotoError::Command(stderr.to_string()))
-    }
-}
+    /// Skip photos narrower than this many pixels
+    #[arg(long, value_name = "PIXELS")]
+    min_width: Option<u32>,
 
-/// Get the path to the current binary
-fn get_binary_path() -> Result<String, PhotoError> {
-    std::env::current_exe()
-        .map(|p| p.to_string_lossy().to_string())
-        .map_err(PhotoError::File)
-}
+    /// Skip photos shorter than this many pixels
+    #[arg(long, value_name = "PIXELS")]
+    min_height: Option<u32>,
 
-/// Schedule type for the timer
-enum ScheduleType {
-    /// Fixed daily time (e.g., "02:00")
-    DailyTime(String),
-    /// Interval (e.g., "1h", "30m")
-    Interval(String),
-}
+    /// Only use photos with this orientation
+    #[arg(long, value_enum, default_value_t = PhotoOrientation::Any)]
+    orientation: PhotoOrientation,
 
-/// Prompt user for time/interval selection
-fn prompt_for_schedule() -> Result<ScheduleType, PhotoError> {
-    println!("{}", "Setting up systemd timer...".yellow());
-    println!();
-    println!("When would you like the wallpaper to update?");
-    println!("  1) Daily at 02:00 (recommended for daily photo)");
-    println!("  2) Every hour (good for random rotation)");
-    println!("  3) Every 30 minutes");
-    println!("  4) Custom time (HH:MM)");
-    println!("  5) Custom interval (e.g., 2h, 15m)");
-    println!("  6) Cancel");
-    println!();
+    /// Transition animation when changing wallpapers (swww only)
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(SWWW_TRANSITIONS))]
+    transition: Option<String>,
 
-    loop {
-        print!("Enter choice [1-6]: ");
-        io::stdout().flush().ok();
+    /// Length of the transition in milliseconds (swww only)
+    #[arg(long, value_name = "MS")]
+    transition_ms: Option<u64>,
 
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .map_err(PhotoError::File)?;
+    /// How the photo is fitted to the screen (GNOME, Cinnamon, MATE, feh and pcmanfm-qt) [default: zoom, or fit with pcmanfm-qt]
+    #[arg(long, value_enum)]
+    fit: Option<ImageFit>,
 
-        match input.trim() {
-            "1" => return Ok(ScheduleType::DailyTime("02:00".to_string())),
-            "2" => return Ok(ScheduleType::Interval("1h".to_string())),
-            "3" => return Ok(ScheduleType::Interval("30m".to_string())),
-            "4" => loop {
-                print!("Enter time (HH:MM format, e.g., 22:45): ");
-                io::stdout().flush().ok();
+    /// Photo or directory to pick a dark photo from for GNOME's dark style
+    #[arg(long, value_name = "PATH")]
+    dark_path: Option<String>,
+
+    /// Resize photos to each monitor's resolution before setting them
+    #[arg(long, value_enum)]
+    resize: Option<Resize>,
+
+    /// Stretch one photo across all monitors instead of giving each its own
+    #[arg(long, conflicts_with_all = ["resize", "monitors", "desktops"])]
+    span: bool,
+
+    /// Use this wallpaper backend instead of the detected one
+    #[arg(long, value_enum)]
+    backend: Option<Backend>,
+}
+
+impl SetArgs {
+    /// Backend options from `--transition`, `--transition-ms` and `--fit`
+    fn backend_options(&self) -> BackendOptions {
+        BackendOptions {
+            transition: TransitionOptions {
+                kind: self.transition.clone(),
+                duration: self.transition_ms.map(Duration::from_millis),
+            },
+            fit: self.fit.map(Into::into),
+            dark_photo: None,
+            backend: self.backend.map(Into::into),
+        }
+    }
+
+    /// The `[wallpaper]` settings with these flags layered over the config file
+    fn wallpaper_config(&self) -> Result<WallpaperConfig, PhotoError> {
+        let flags = Config {
+            wallpaper: WallpaperConfig {
+                mode: self.mode.map(Into::into),
+                random: self.random.then_some(true),
+                lock_screen: self.lock_screen.then_some(true),
+                current_link: None,
+                pywal: self.pywal.then_some(true),
+                favorite_weight: None,
+            },
+            ..Config::default()
+        };
+        Ok(config()
+            .merged(&flags)
+            .with_defaults(data_dirs()?)
+            .wallpaper)
+    }
+
+    /// How photos are picked and prepared, with `sort` already resolved
+    fn wallpaper_options(&self, sort: Sort) -> WallpaperOptions {
+        WallpaperOptions {
+            sort: sort.into(),
+            spread: self.spread.into(),
+            brightness: self.brightness.map(Into::into),
+            dimensions: DimensionFilter {
+                min_width: self.min_width,
+                min_height: self.min_height,
+                orientation: self.orientation.into(),
+            },
+            resize: self.resize.map(Into::into),
+            span: self.span,
+            targets: TargetFilter {
+                monitors: self
+                    .monitors
+                    .iter()
+                    .filter_map(|m| match m {
+                        MonitorArg::Number(n) => Some(n - 1),
+                        MonitorArg::Name(_) => None,
+                    })
+                    .collect(),
+                monitor_names: self
+                    .monitors
+                    .iter()
+                    .filter_map(|m| match m {
+                        MonitorArg::Name(name) => Some(name.clone()),
+                        MonitorArg::Number(_) => None,
+                    })
+                    .collect(),
+                desktops: self.desktops.iter().map(|&n| usize::from(n - 1)).collect(),
+            },
+            favorites_only: self.favorites_only,
+            listed: None,
+            dry_run: false,
+        }
+    }
+
+    /// Where photos come from: the `--photo` file, the `--from-collection`
+    /// directory, or `--path`
+    fn photo_source(&self) -> Result<Option<String>, PhotoError> {
+        if let Some(name) = &self.from_collection {
+            let dir = resolve_collection(std::path::Path::new(&collection_dir()?), name)?;
+            return Ok(Some(dir.to_string_lossy().into_owned()));
+        }
+        match &self.photo {
+            Some(photo) if !std::path::Path::new(&expand_tilde(photo)).is_file() => Err(
+                PhotoError::NoPhotos(format!("--photo {photo} is not a photo file")),
+            ),
+            Some(photo) => Ok(Some(photo.clone())),
+            None => Ok(self.path.clone()),
+        }
+    }
+
+    /// Set the wallpaper, and the lock screen if asked
+    fn apply(&self) -> Result<WallpaperReport, PhotoError> {
+        self.apply_or_plan(false, None)
+    }
+
+    /// A listing of the photo source to keep between rotations, if it's a directory
+    fn listing_cache(&self) -> Option<PhotoListingCache> {
+        let root = photo_dir_or(self.photo_source().ok()?.as_deref()).ok()?;
+        if !std::path::Path::new(&root).is_dir() {
+            return None;
+        }
+        PhotoListingCache::new(root)
+            .inspect_err(|e| status!("{} Couldn't list the photos: {}", "!".yellow(), e))
+            .ok()
+    }
+
+    /// Set the wallpaper as `apply` does, picking from the photos `listing`
+    /// has found after bringing it up to date
+    fn apply_from(
+        &self,
+        listing: &mut Option<PhotoListingCache>,
+    ) -> Result<WallpaperReport, PhotoError> {
+        let listed = listing.as_mut().and_then(|cache| match cache.refresh() {
+            Ok(_) => Some(cache.photos().to_vec()),
+            Err(e) => {
+                status!("{} Couldn't refresh the photo listing: {}", "!".yellow(), e);
+                None
+            }
+        });
+        self.apply_or_plan(false, listed)
+    }
+
+    /// Set the wallpaper as `apply` does, or with `dry_run` only report what
+    /// would be set. `listed` are the photos in the source, if already known.
+    fn apply_or_plan(
+        &self,
+        dry_run: bool,
+        listed: Option<Vec<std::path::PathBuf>>,
+    ) -> Result<WallpaperReport, PhotoError> {
+        let settings = self.wallpaper_config()?;
+        // An explicit --sort beats `random` from the config file
+        let sort = match self.sort {
+            _ if self.random => Sort::Random,
+            Some(sort) => sort,
+            None if settings.random == Some(true) => Sort::Random,
+            None => Sort::Date,
+        };
+        let mut backend_options = self.backend_options();
+        if let Some(dark_path) = &self.dark_path {
+            backend_options.dark_photo = Some(pick_dark_photo(dark_path, sort.into())?);
+        }
+        let report = set_and_report(
+            settings.mode.unwrap_or_default(),
+            self.photo_source()?.as_deref(),
+            &WallpaperOptions {
+                dry_run,
+                listed,
+                ..self.wallpaper_options(sort)
+            },
+            &backend_options,
+        )?;
+        if let Some(dark_photo) = &backend_options.dark_photo {
+            if report.desktop == DesktopEnvironment::Gnome {
+                status!("{} Dark style photo: {}", "✓".green(), dark_photo.display());
+            } else {
+                status!("{} --dark-path only applies on GNOME", "!".yellow());
+            }
+        }
+        if dry_run {
+            return Ok(report);
+        }
+        if settings.lock_screen == Some(true) {
+            set_lock_screen_from(&report)?;
+        }
+        // The wallpaper is set either way, so a pywal problem is only a warning
+        if settings.pywal == Some(true) {
+            if let Err(e) = run_pywal(&report, &LiveCommands, &LiveSystem, &wallpaper_log_path()?) {
+                eprintln!("{} pywal: {}", "!".yellow(), e);
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Point KDE Plasma's slideshow wallpaper at `path` (or the photo directory),
+/// changing photo every `interval`
+fn plasma_slideshow(path: Option<&str>, interval: &str) -> Result<(), PhotoError> {
+    let dir = photo_dir_or(path)?;
+    let seconds = interval_seconds(interval)
+        .ok_or_else(|| PhotoError::Config(format!("--interval {interval} is too short")))?;
+    let de = detect_desktop_environment();
+    let transport = PlasmaTransport::select(de, &LiveSystem).ok_or_else(|| {
+        PhotoError::Wallpaper(format!(
+            "The Plasma slideshow needs KDE Plasma, but the desktop is {de:?}"
+        ))
+    })?;
+    set_plasma_slideshow(transport, dir.as_ref(), seconds, &LiveCommands)?;
+    write_log(
+        &wallpaper_log_path()?,
+        &format!("Plasma slideshow of {} every {}s", dir, seconds),
+    );
+    status!(
+        "{} Plasma will show a photo from {} every {}",
+        "✓".green(),
+        dir,
+        interval.yellow()
+    );
+    status!(
+        "  The wallpaper rotation timer isn't needed while the slideshow runs ({})",
+        "install --uninstall".green()
+    );
+    Ok(())
+}
+
+/// Print each assignment, then whether it was applied
+fn print_assignments(report: &WallpaperReport, sort: SortOrder) {
+    status!(
+        "{} {}",
+        "Wallpaper assignments:".yellow(),
+        format!("(ordered by {})", sort).dimmed()
+    );
+    for AssignmentResult { assignment, .. } in &report.results {
+        let photo_date = assignment
+            .photo_path
+            .parent()
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown");
+        let photo_name = assignment
+            .photo_path
+            .file_stem()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown");
+
+        if assignment.is_newest && sort == SortOrder::Date {
+            status!(
+                "  {}: {} - {} {}",
+                assignment.location,
+                photo_date.green(),
+                photo_name,
+                "(newest)".yellow()
+            );
+        } else {
+            status!(
+                "  {}: {} - {}",
+                assignment.location,
+                photo_date.green(),
+                photo_name
+            );
+        }
+    }
+    status!();
+    if report.dry_run {
+        status!("{} Dry run, so no wallpaper was changed", "!".yellow());
+        return;
+    }
+
+    status!("{}", "Applying wallpapers...".yellow());
+    status!();
+    let per_monitor = matches!(
+        report.desktop,
+        DesktopEnvironment::KdePlasma6
+            | DesktopEnvironment::KdePlasma5
+            | DesktopEnvironment::Hyprland
+            | DesktopEnvironment::Swww
+            | DesktopEnvironment::Xfce
+            | DesktopEnvironment::Windows
+    );
+    for AssignmentResult { assignment, result } in &report.results {
+        match (result, per_monitor) {
+            (Ok(()), true) if matches!(report.mode, WallpaperMode::VirtualDesktops) => {
+                status!("{} {} (all monitors)", "✓".green(), assignment.location);
+            }
+            (Ok(()), true) => status!("{} {}", "✓".green(), assignment.location),
+            (Ok(()), false) => status!("{} Wallpaper set", "✓".green()),
+            (Err(e), true) => status!("{} Failed: {} - {}", "✗".red(), assignment.location, e),
+            (Err(e), false) => status!("{} Failed to set wallpaper: {}", "✗".red(), e),
+        }
+    }
+}
+
+/// Print the detected desktop and what it can do with `mode`
+fn print_desktop(report: &WallpaperReport, mode: WallpaperMode) {
+    match report.desktop {
+        DesktopEnvironment::KdePlasma6 => status!(
+            "{} Detected KDE Plasma 6: {} monitor(s), {} virtual desktop(s)",
+            "✓".green(),
+            report.monitor_count,
+            report.virtual_desktop_count
+        ),
+        DesktopEnvironment::KdePlasma5 => {
+            status!(
+                "{} Detected KDE Plasma 5: {} monitor(s)",
+                "✓".green(),
+                report.monitor_count
+            );
+            if matches!(mode, WallpaperMode::VirtualDesktops | WallpaperMode::Both) {
+                status!(
+                    "{} Virtual desktop mode requires Plasma 6+, falling back to monitors",
+                    "!".yellow()
+                );
+            }
+        }
+        DesktopEnvironment::PlasmaFallback => status!(
+            "{} Using plasma-apply-wallpaperimage (single wallpaper mode)",
+            "!".yellow()
+        ),
+        DesktopEnvironment::Lxqt => status!(
+            "{} Using pcmanfm-qt for LXQt (single wallpaper mode)",
+            "!".yellow()
+        ),
+        DesktopEnvironment::Gnome => status!("{} Detected GNOME, using gsettings", "✓".green()),
+        DesktopEnvironment::Cinnamon => {
+            status!("{} Detected Cinnamon, using gsettings", "✓".green());
+        }
+        DesktopEnvironment::Mate => status!("{} Detected MATE, using gsettings", "✓".green()),
+        DesktopEnvironment::Feh => status!("{} Using feh for X11", "✓".green()),
+        DesktopEnvironment::Hyprland => {
+            status!(
+                "{} Detected Hyprland: {} monitor(s), using hyprpaper",
+                "✓".green(),
+                report.monitor_count
+            );
+            if matches!(mode, WallpaperMode::VirtualDesktops | WallpaperMode::Both) {
+                status!(
+                    "{} Hyprland workspaces share their monitor's wallpaper, falling back to monitors",
+                    "!".yellow()
+                );
+            }
+        }
+        DesktopEnvironment::Swww => {
+            status!(
+                "{} Using swww: {} output(s)",
+                "✓".green(),
+                report.monitor_count
+            );
+            if matches!(mode, WallpaperMode::VirtualDesktops | WallpaperMode::Both) {
+                status!(
+                    "{} swww has no virtual desktops, falling back to monitors",
+                    "!".yellow()
+                );
+            }
+        }
+        DesktopEnvironment::Xfce => status!(
+            "{} Detected XFCE: {} monitor(s), {} workspace(s)",
+            "✓".green(),
+            report.monitor_count,
+            report.virtual_desktop_count
+        ),
+        DesktopEnvironment::Windows => status!(
+            "{} Detected Windows: {} monitor(s)",
+            "✓".green(),
+            report.monitor_count
+        ),
+        DesktopEnvironment::Unknown => {}
+    }
+}
+
+/// Set wallpapers, printing what was found and which assignments were applied
+fn set_and_report(
+    mode: WallpaperMode,
+    path: Option<&str>,
+    options: &WallpaperOptions,
+    backend_options: &BackendOptions,
+) -> Result<WallpaperReport, PhotoError> {
+    status!("{}", "=== National Geographic Wallpaper ===".green());
+    status!("Mode: {}\n", mode.to_string().yellow());
+
+    let backend = detect_wallpaper_backend_with(backend_options)?;
+    let report = set_wallpapers_with_backend(backend.as_ref(), mode, path, options)?;
+    let WallpaperOptions {
+        sort,
+        spread,
+        dimensions,
+        resize,
+        span,
+        ..
+    } = *options;
+
+    if let Some(p) = path {
+        status!("{} Using path: {}", "✓".green(), p);
+    }
+    if sort == SortOrder::Random {
+        status!("{} Random selection enabled", "✓".green());
+    } else if sort != SortOrder::Date {
+        status!("{} Ordering photos by {}", "✓".green(), sort);
+    }
+    if spread != SpreadStrategy::None {
+        status!("{} Spreading photos: {}", "✓".green(), spread);
+    }
+    if let Some(brightness) = report.brightness {
+        status!("{} Using {} photos only", "✓".green(), brightness);
+    }
+    if !dimensions.is_empty() {
+        status!("{} Using photos that are {}", "✓".green(), dimensions);
+    }
+    if span {
+        status!("{} Spanning one photo across all monitors", "✓".green());
+    } else if let Some(resize) = resize {
+        status!(
+            "{} Resizing photos to each monitor ({})",
+            "✓".green(),
+            resize
+        );
+    }
+    status!("{} Found {} photo(s)\n", "✓".green(), report.photos_found);
+
+    print_desktop(&report, mode);
+    if report.dry_run && report.mode != mode {
+        status!(
+            "{} Would use {} mode instead of {}",
+            "!".yellow(),
+            report.mode,
+            mode
+        );
+    }
+    for (i, monitor) in report.monitors.iter().enumerate() {
+        status!("  Monitor {}: {}", i + 1, monitor);
+    }
+    status!();
+
+    for warning in &report.warnings {
+        status!("{} {}", "!".yellow(), warning);
+    }
+
+    let total_needed = report.results.len();
+    status!("Wallpapers needed: {}", total_needed);
+    if report.photos_found < total_needed {
+        status!(
+            "{} Only {} photos available, will reuse as needed\n",
+            "!".yellow(),
+            report.photos_found
+        );
+    }
+    status!();
+
+    print_assignments(&report, sort);
+
+    status!();
+    if report.dry_run {
+        status!("{}", "=== Dry Run Completed ===".green());
+        return Ok(report);
+    }
+    status!("{}", "=== Completed ===".green());
+    status!("\nLog file: {}", wallpaper_log_path()?);
+
+    Ok(report)
+}
+
+/// Options shared by `download` and `download-collection`
+#[derive(Args)]
+struct DownloadArgs {
+    /// Convert WebP and AVIF downloads to this format
+    #[arg(long, value_enum)]
+    convert_to: Option<ConvertTo>,
+
+    /// Quality (1-100) used when converting
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+
+    /// Write the title, caption and photographer into JPEG EXIF/XMP metadata
+    #[arg(long)]
+    embed_metadata: bool,
+
+    /// Times to retry a request after a network error, 429, or 5xx response [default: 2]
+    #[arg(long)]
+    retries: Option<u32>,
+
+    /// Seconds to wait for a response (and between chunks of a download) before giving up [default: 30]
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+
+    /// Proxy URL for all requests, overriding the proxy environment variables
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+}
+
+/// Options for the daily download when no flags are given
+fn daily_download_options() -> DownloadOptions {
+    DownloadOptions {
+        http: config().http_options(),
+        manifest: ManifestConfig::from_defaults().ok(),
+        ..DownloadOptions::default()
+    }
+}
+
+impl From<DownloadArgs> for DownloadOptions {
+    fn from(args: DownloadArgs) -> Self {
+        Self {
+            convert_to: args.convert_to.map(|ConvertTo::Jpg| ConvertFormat::Jpeg),
+            quality: args.quality,
+            embed_metadata: args.embed_metadata,
+            http: config()
+                .merged(&Config {
+                    http: HttpConfig {
+                        retries: args.retries,
+                        timeout: args.timeout,
+                        proxy: args.proxy,
+                    },
+                    ..Config::default()
+                })
+                .http_options(),
+            manifest: ManifestConfig::from_defaults().ok(),
+        }
+    }
+}
+
+impl Cli {
+    /// The `[hooks]` keys set by the hook flags, to layer over the config file
+    fn hooks_config(&self) -> Config {
+        Config {
+            hooks: HooksConfig {
+                post_download: self.hook_post_download.clone(),
+                post_set: self.hook_post_set.clone(),
+                strict: self.hook_strict.then_some(true),
+            },
+            ..Config::default()
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+enum ColorWhen {
+    /// Color when printing to a terminal and `NO_COLOR` is unset
+    Auto,
+    /// Always color
+    Always,
+    /// Never color
+    Never,
+}
+
+impl ColorWhen {
+    /// Whether to color output, given the `NO_COLOR` variable and whether stdout is a terminal
+    fn enabled(self, no_color: Option<&std::ffi::OsStr>, is_terminal: bool) -> bool {
+        match self {
+            Self::Auto => auto_color(no_color, is_terminal),
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum ConvertTo {
+    /// JPEG
+    Jpg,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Mode {
+    /// Different wallpaper per physical monitor
+    Monitors,
+    /// Different wallpaper per virtual desktop (same across monitors)
+    VirtualDesktops,
+    /// Different wallpaper per monitor x virtual desktop combination
+    Both,
+    /// Different wallpaper per KDE Plasma activity
+    Activities,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Sort {
+    /// Newest date folder first, then newest modified
+    Date,
+    /// Alphabetical by path
+    Name,
+    /// Most recently modified first
+    Mtime,
+    /// Random order
+    Random,
+}
+
+impl From<Sort> for SortOrder {
+    fn from(sort: Sort) -> Self {
+        match sort {
+            Sort::Date => Self::Date,
+            Sort::Name => Self::Name,
+            Sort::Mtime => Self::Mtime,
+            Sort::Random => Self::Random,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Brightness {
+    /// Photos darker than average, for dark themes and night time
+    Dark,
+    /// Photos brighter than average
+    Light,
+    /// Dark or light to match the GNOME or KDE color scheme
+    Auto,
+}
+
+impl From<Brightness> for BrightnessFilter {
+    fn from(brightness: Brightness) -> Self {
+        match brightness {
+            Brightness::Dark => Self::Dark,
+            Brightness::Light => Self::Light,
+            Brightness::Auto => Self::Auto,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum PhotoOrientation {
+    /// Wider than tall
+    Landscape,
+    /// Taller than wide
+    Portrait,
+    /// Either way round
+    Any,
+}
+
+impl From<PhotoOrientation> for Option<Orientation> {
+    fn from(orientation: PhotoOrientation) -> Self {
+        match orientation {
+            PhotoOrientation::Landscape => Some(Orientation::Landscape),
+            PhotoOrientation::Portrait => Some(Orientation::Portrait),
+            PhotoOrientation::Any => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Spread {
+    /// Fill assignments in selection order (newest first, or random)
+    None,
+    /// At most one photo per calendar month, most recent months first
+    Monthly,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum ImageFit {
+    /// Scale to fit inside the screen, keeping the aspect ratio
+    Fit,
+    /// Scale to fill the screen, cropping the edges
+    Zoom,
+    /// Stretch to the screen, ignoring the aspect ratio
+    Stretch,
+    /// Center without scaling
+    Center,
+    /// Repeat across the screen
+    Tile,
+}
+
+impl From<ImageFit> for FitMode {
+    fn from(fit: ImageFit) -> Self {
+        match fit {
+            ImageFit::Fit => Self::Fit,
+            ImageFit::Zoom => Self::Zoom,
+            ImageFit::Stretch => Self::Stretch,
+            ImageFit::Center => Self::Center,
+            ImageFit::Tile => Self::Tile,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Backend {
+    /// KDE Plasma 6 over D-Bus
+    Plasma6,
+    /// KDE Plasma 5 over D-Bus
+    Plasma5,
+    /// KDE Plasma's plasma-apply-wallpaperimage
+    PlasmaApply,
+    /// GNOME's gsettings
+    Gnome,
+    /// Cinnamon's gsettings
+    Cinnamon,
+    /// MATE's gsettings
+    Mate,
+    /// XFCE's xfconf-query
+    Xfce,
+    /// pcmanfm-qt, which draws the desktop on `LXQt`
+    Lxqt,
+    /// Hyprland's hyprpaper
+    Hyprland,
+    /// swww on Wayland
+    Swww,
+    /// feh on X11
+    Feh,
+    /// Windows
+    Windows,
+}
+
+impl From<Backend> for DesktopEnvironment {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Plasma6 => Self::KdePlasma6,
+            Backend::Plasma5 => Self::KdePlasma5,
+            Backend::PlasmaApply => Self::PlasmaFallback,
+            Backend::Gnome => Self::Gnome,
+            Backend::Cinnamon => Self::Cinnamon,
+            Backend::Mate => Self::Mate,
+            Backend::Xfce => Self::Xfce,
+            Backend::Lxqt => Self::Lxqt,
+            Backend::Hyprland => Self::Hyprland,
+            Backend::Swww => Self::Swww,
+            Backend::Feh => Self::Feh,
+            Backend::Windows => Self::Windows,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Resize {
+    /// Fill the screen, cropping the edges that don't fit
+    Crop,
+    /// Fit inside the screen, with black bars where the shapes differ
+    Fit,
+    /// Stretch to the screen, ignoring the aspect ratio
+    Stretch,
+}
+
+impl From<Resize> for ResizeMode {
+    fn from(resize: Resize) -> Self {
+        match resize {
+            Resize::Crop => Self::Crop,
+            Resize::Fit => Self::Fit,
+            Resize::Stretch => Self::Stretch,
+        }
+    }
+}
+
+impl From<Spread> for SpreadStrategy {
+    fn from(spread: Spread) -> Self {
+        match spread {
+            Spread::None => Self::None,
+            Spread::Monthly => Self::Monthly,
+        }
+    }
+}
+
+impl From<Mode> for WallpaperMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Monitors => Self::Monitors,
+            Mode::VirtualDesktops => Self::VirtualDesktops,
+            Mode::Both => Self::Both,
+            Mode::Activities => Self::Activities,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    if let Err(e) = install_interrupt_handler() {
+        eprintln!("{} {}", "!".yellow(), e);
+    }
+
+    let json = cli.json;
+    set_json_output(json);
+    set_strict_logging(cli.strict_logs);
+    set_quiet_output(cli.quiet);
+    set_color_output(cli.color.enabled(
+        std::env::var_os("NO_COLOR").as_deref(),
+        io::stdout().is_terminal(),
+    ));
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(PhotoError::Interrupted) => {
+            let removed = remove_in_progress_files();
+            if removed > 0 {
+                eprintln!("Removed {} partially written file(s)", removed);
+            }
+            eprintln!("{}", "Interrupted".yellow());
+            ExitCode::from(INTERRUPTED_EXIT_CODE)
+        }
+        Err(e) => {
+            // Failed checks are already in the JSON report
+            if json && !matches!(e, PhotoError::ChecksFailed(_)) {
+                println!("{}", serde_json::json!({ "error": e.to_string() }));
+            }
+            eprintln!("Error: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn run(cli: Cli) -> Result<(), PhotoError> {
+    // `config init` has to work even when the existing file doesn't parse,
+    // and completions don't need the config at all
+    if matches!(
+        cli.command,
+        Some(
+            Commands::Config {
+                action: ConfigAction::Init { .. }
+            } | Commands::Completions { .. }
+        )
+    ) {
+        init_logging(cli.verbose, cli.quiet, None);
+    } else {
+        let (file_config, unknown) = load_config(&config_path())?;
+        for key in unknown {
+            eprintln!("{} Unknown config key: {}", "!".yellow(), key);
+        }
+        set_config(file_config.merged(&cli.hooks_config()));
+        init_data_dirs()?;
+        init_logging(cli.verbose, cli.quiet, Some(&app_log_path()?));
+    }
+
+    let desktop_notifier = DesktopNotifier::new(Box::new(LiveCommands));
+    let notifier: Option<&dyn Notifier> =
+        (cli.notify || config().notifications.enabled == Some(true)).then_some(&desktop_notifier);
+
+    match cli.command {
+        Some(Commands::Download { date, options }) => match download(&options.into(), date) {
+            Ok(report) => {
+                notify_downloaded(notifier, &report);
+                if cli.json {
+                    print_json(&report)?;
+                }
+            }
+            Err(e) => {
+                notify(notifier, &Notification::failed("Download", &e));
+                return Err(e);
+            }
+        },
+        Some(Commands::Set {
+            set,
+            plasma_slideshow: true,
+            interval,
+            ..
+        }) => plasma_slideshow(set.path.as_deref(), &interval)?,
+        Some(Commands::Set {
+            set,
+            previous: true,
+            ..
+        }) => {
+            let report = set_previous(&set).inspect_err(|e| {
+                notify(notifier, &Notification::failed("Setting the wallpaper", e));
+            })?;
+            notify_wallpaper_set(notifier, &report);
+            if cli.json {
+                print_json(&report.applied())?;
+            }
+        }
+        Some(Commands::Set {
+            mut set,
+            pick,
+            dry_run,
+            ..
+        }) => {
+            if pick {
+                set.photo = Some(pick_photo(set.photo_source()?.as_deref())?);
+                set.path = None;
+                set.from_collection = None;
+            }
+            if dry_run {
+                let report = set.apply_or_plan(true, None)?;
+                if cli.json {
+                    print_json(&report.plan())?;
+                }
+            } else {
+                let report = set.apply().inspect_err(|e| {
+                    notify(notifier, &Notification::failed("Setting the wallpaper", e));
+                })?;
+                notify_wallpaper_set(notifier, &report);
+                if cli.json {
+                    print_json(&report.applied())?;
+                }
+            }
+        }
+        Some(Commands::Run {
+            set,
+            attempts,
+            offline_ok,
+            download,
+        }) => {
+            let options = RunOptions {
+                attempts,
+                offline_ok,
+                ..RunOptions::default()
+            };
+            run_cmd(&set, &download.into(), options, notifier)?;
+        }
+        Some(Commands::Watch { set }) => watch(&set, notifier)?,
+        Some(Commands::Daemon { set, interval }) => daemon(&set, &interval, notifier)?,
+        Some(Commands::Install {
+            time,
+            uninstall,
+            random,
+            path,
+            lock_screen,
+            keep_days,
+            force,
+            status,
+            jitter,
+            set_every,
+            watch,
+            autostart,
+        }) => {
+            if cfg!(target_os = "windows") {
+                return Err(PhotoError::Command(
+                    "install needs systemd, which isn't available on Windows. \
+                     Schedule `natgeo-wallpapers run` with Task Scheduler instead"
+                        .to_string(),
+                ));
+            }
+            if status {
+                install_status()?;
+            } else if uninstall {
+                uninstall_schedule()?;
+            } else if autostart {
+                install_autostart(random, path.as_deref(), lock_screen)?;
+            } else {
+                install_schedule(InstallOptions {
+                    time,
+                    random,
+                    path,
+                    lock_screen,
+                    keep_days,
+                    force,
+                    jitter,
+                    set_every,
+                    watch,
+                })?;
+            }
+        }
+        Some(Commands::Backfill {
+            from,
+            to,
+            last,
+            delay,
+            options,
+        }) => {
+            let today = Local::now().date_naive();
+            let range = match (from, last) {
+                (Some(from), _) => DateRange::new(from, to.unwrap_or(today))?,
+                (None, Some(days)) => DateRange::last(days, today)?,
+                (None, None) => unreachable!("clap requires --from or --last"),
+            };
+            let options = BackfillOptions {
+                delay: Duration::from_secs(delay),
+                download: options.into(),
+                ..BackfillOptions::from_defaults()?
+            };
+            backfill_cmd(range, &options)?;
+        }
+        Some(Commands::DownloadCollection {
+            url,
+            filter,
+            no_filter,
+            options,
+        }) => {
+            let filter = match (no_filter, filter) {
+                (true, _) => CollectionFilter::None,
+                (false, Some(pattern)) => CollectionFilter::Pattern(pattern),
+                (false, None) => CollectionFilter::default(),
+            };
+            let result = download_collection_cmd(&url, options.into(), &filter)?;
+            if cli.json {
+                print_json(&result)?;
+            }
+        }
+        Some(Commands::Normalize {
+            template,
+            path,
+            dry_run: _,
+            apply,
+        }) => {
+            normalize(template.as_deref(), path.as_deref(), apply)?;
+        }
+        Some(Commands::List {
+            collections,
+            limit,
+            since,
+        }) => {
+            let options = ListOptions {
+                include_collections: collections,
+                limit,
+                since,
+            };
+            list(&options, cli.json)?;
+        }
+        Some(Commands::Favorite { list }) => photo_list(PhotoList::Favorites, &list, cli.json)?,
+        Some(Commands::Exclude { list }) => photo_list(PhotoList::Excluded, &list, cli.json)?,
+        Some(Commands::History { limit }) => history(limit, cli.json)?,
+        Some(Commands::Collections { names }) => collections(cli.json, names)?,
+        Some(Commands::Doctor) => doctor(cli.json)?,
+        Some(Commands::Completions { shell }) => write_completions(shell, &mut io::stdout())?,
+        Some(Commands::Stats { path }) => stats(path.as_deref(), cli.json)?,
+        Some(Commands::Verify { path, fix }) => verify(path.as_deref(), fix)?,
+        Some(Commands::Clean {
+            keep_days,
+            keep_count,
+            max_size,
+            collections,
+            cache,
+            dry_run,
+        }) => {
+            // Policy flags replace the `[retention]` table as a whole
+            let retention = config()
+                .merged(&Config {
+                    retention: RetentionConfig {
+                        keep_days,
+                        keep_count,
+                        max_size,
+                    },
+                    ..Config::default()
+                })
+                .retention;
+            if cache {
+                clean_cache(dry_run)?;
+                if retention.is_empty() {
+                    return Ok(());
+                }
+                status!();
+            }
+            if retention.is_empty() {
+                return Err(PhotoError::Config(
+                    "clean needs --keep-days, --keep-count or --max-size, \
+                     or a [retention] policy in the config file"
+                        .to_string(),
+                ));
+            }
+            clean(&RetentionPolicy {
+                keep_days: retention.keep_days,
+                keep_count: retention.keep_count,
+                max_size: retention.max_size.as_deref().and_then(parse_size),
+                include_collections: collections,
+                dry_run,
+                ..RetentionPolicy::new()?
+            })?;
+        }
+        Some(Commands::Dedup { link, remove }) => {
+            let action = if link {
+                DuplicateAction::HardLink
+            } else if remove {
+                DuplicateAction::Remove
+            } else {
+                DuplicateAction::Report
+            };
+            dedup(action)?;
+        }
+        Some(Commands::Config {
+            action: ConfigAction::Init { force },
+        }) => config_init(force)?,
+        Some(Commands::Config {
+            action: ConfigAction::Show,
+        }) => config_show()?,
+        Some(Commands::Uninstall {
+            config,
+            photos,
+            yes,
+        }) => {
+            uninstall(UninstallOptions { config, photos }, yes)?;
+        }
+        None => {
+            // Default behavior: download (backwards compatibility)
+            download(&daily_download_options(), None)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Level of events shown on stderr for `-v` repeated `verbosity` times, or `--quiet`
+const fn terminal_level(verbosity: u8, quiet: bool) -> LevelFilter {
+    if quiet {
+        return LevelFilter::OFF;
+    }
+    match verbosity {
+        0 => LevelFilter::ERROR,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Filter for this crate's events at `level`, unless `RUST_LOG` says otherwise
+fn event_filter(level: LevelFilter) -> EnvFilter {
+    let directive = format!("natgeo_wallpapers={}", level)
+        .parse()
+        .unwrap_or_else(|_| level.into());
+    EnvFilter::builder()
+        .with_default_directive(directive)
+        .from_env_lossy()
+}
+
+/// Show library events on stderr at the level picked by `-v`/`-q`, and append
+/// them to `log_file` (rotated first if it is too big) at info level or more detailed
+fn init_logging(verbosity: u8, quiet: bool, log_file: Option<&str>) {
+    let level = terminal_level(verbosity, quiet);
+    let terminal = tracing_subscriber::fmt::layer()
+        .with_writer(io::stderr)
+        .with_ansi(color_output())
+        .with_target(false)
+        .without_time()
+        .with_filter(event_filter(level));
+
+    let file = log_file.and_then(|path| {
+        let path = std::path::Path::new(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok()?;
+        }
+        rotate_log(path, config().log_rotation()).ok()?;
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .ok()
+    });
+    let file = file.map(|file| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(std::sync::Mutex::new(file))
+            .with_ansi(false)
+            .with_filter(event_filter(level.max(LevelFilter::INFO)))
+    });
+
+    let _ = tracing_subscriber::registry()
+        .with(terminal)
+        .with(file)
+        .try_init();
+}
+
+/// Print `value` as the command's JSON document
+fn print_json<T: serde::Serialize + ?Sized>(value: &T) -> Result<(), PhotoError> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Download today's National Geographic Photo of the Day
+fn download(
+    options: &DownloadOptions,
+    date: Option<NaiveDate>,
+) -> Result<DownloadReport, PhotoError> {
+    status!("{}", "=== National Geographic Photo Downloader ===".green());
+    status!();
+
+    if let Some(date) = date {
+        validate_photo_date(date)?;
+    }
+
+    // Save into the directory for the photo's date, today unless asked otherwise
+    let expanded_base_path = photo_dir()?;
+    let save_dir = date_dir_for(
+        &expanded_base_path,
+        date.unwrap_or_else(|| Local::now().date_naive()),
+    );
+
+    // Create a directory for today's date (if it doesn't exist)
+    ensure_managed_dir(&expanded_base_path)?;
+    if let Err(e) = fs::create_dir_all(&save_dir) {
+        return Err(PhotoError::File(e));
+    }
+
+    // Get the current photo data
+    status!("Fetching photo information...");
+    let error_log_path = format!("{}/error.log", save_dir);
+    let client = NatGeoClient::new(options.http.clone())?.with_log(&error_log_path);
+    let photo_info = match date.map_or_else(
+        || client.photo_of_the_day(),
+        |date| client.photo_for_date(date),
+    ) {
+        Ok(info) => {
+            status!("{} Found: {}", "✓".green(), info.title);
+            if let Some(photographer) = &info.photographer {
+                status!("  {}", format!("Photo: {photographer}").dimmed());
+            }
+            info
+        }
+        Err(e) => {
+            status!("{} Failed to fetch photo information: {}", "✗".red(), e);
+            let error_msg = format!("Failed to fetch photo information: {}", e);
+            write_log(&error_log_path, &error_msg);
+            return Err(e);
+        }
+    };
+
+    // Sanitize the title to make it a valid filename
+    let sanitized_title = downloaded_title(&save_dir, &photo_info.title);
+    let log_path = format!("{}/{}.log", save_dir, sanitized_title);
+
+    // Log start of download
+    write_log_checked(
+        &log_path,
+        &format!("Starting download for: {}", photo_info.title),
+    )?;
+    write_log(&log_path, &format!("Image URL: {}", photo_info.image_url));
+    if let Some(photographer) = &photo_info.photographer {
+        write_log(&log_path, &format!("Photographer: {}", photographer));
+    }
+    if let Some(caption) = &photo_info.caption {
+        write_log(&log_path, &format!("Caption: {}", caption));
+    }
+
+    // Download the photo and save it with the correct extension
+    status!("Downloading photo...");
+    let outcome = client.download_with_options(
+        &photo_info.image_url,
+        &save_dir,
+        &sanitized_title,
+        &log_path,
+        options,
+    );
+    match &outcome {
+        Ok(DownloadOutcome::Downloaded(path)) => {
+            status!("{} Photo saved to: {}", "✓".green(), path.display());
+            let success_msg = format!("Successfully downloaded photo to: {}", path.display());
+            write_log(&log_path, &success_msg);
+            record_photo_metadata(&photo_info, path, options, &log_path);
+        }
+        Ok(DownloadOutcome::AlreadyExists(path)) => {
+            status!(
+                "{} Photo already exists, skipping: {}",
+                "✓".green(),
+                path.display()
+            );
+        }
+        Ok(DownloadOutcome::Duplicate(path)) => {
+            status!(
+                "{} Same photo already saved, skipping: {}",
+                "✓".green(),
+                path.display()
+            );
+        }
+        Err(e) => {
+            status!("{} Failed to download photo: {}", "✗".red(), e);
+            let error_msg = format!("Failed to download photo: {}", e);
+            write_log(&log_path, &error_msg);
+            write_log(&log_path, &format!("Error details: {:?}", e));
+        }
+    }
+    let outcome = outcome?;
+    if let DownloadOutcome::Downloaded(path) = &outcome {
+        run_hooks(
+            HookEvent::PostDownload,
+            &HookContext::downloaded(&photo_info, path),
+            &log_path,
+        )?;
+    }
+
+    write_log(&log_path, "Download process completed successfully");
+
+    status!();
+    status!("{}", "=== Download Complete ===".green());
+
+    Ok(DownloadReport::new(&photo_info, &outcome))
+}
+
+/// Progress bar showing photos done, the current photo, and its bytes received.
+/// Hidden when stderr isn't a terminal (e.g. under systemd).
+fn collection_progress_bar(total: usize) -> ProgressBar {
+    let bar = ProgressBar::new(total as u64);
+    let template = if color_output() {
+        "[{bar:30.green}] {pos}/{len} {wide_msg}"
+    } else {
+        "[{bar:30}] {pos}/{len} {wide_msg}"
+    };
+    if let Ok(style) = ProgressStyle::with_template(template) {
+        bar.set_style(style.progress_chars("=> "));
+    }
+    bar
+}
+
+/// Download photos from a "Best of Photo of the Day" collection
+fn download_collection_cmd(
+    url: &str,
+    options: DownloadOptions,
+    filter: &CollectionFilter,
+) -> Result<CollectionDownloadResult, PhotoError> {
+    status!(
+        "{}",
+        "=== National Geographic Collection Downloader ===".green()
+    );
+    status!();
+
+    // Validate URL contains expected pattern
+    if !url.contains("nationalgeographic.com") {
+        status!(
+            "{} Invalid URL: must be a National Geographic URL",
+            "✗".red()
+        );
+        return Err(PhotoError::Config(
+            "Invalid URL: must be a National Geographic URL".to_string(),
+        ));
+    }
+
+    // Fetch the collection
+    status!("Fetching collection from: {}", url);
+    status!();
+
+    let collection =
+        match NatGeoClient::new(options.http.clone())?.collection_with_filter(url, filter) {
+            Ok(c) => {
+                status!("{} Collection: {}", "✓".green(), c.name);
+                status!("{} Found {} photo(s)", "✓".green(), c.photos.len());
+                c
+            }
+            Err(e) => {
+                status!("{} Failed to fetch collection: {}", "✗".red(), e);
+                return Err(e);
+            }
+        };
+
+    status!();
+    status!("{}", "Photos in collection:".yellow());
+    for (i, photo) in collection.photos.iter().enumerate() {
+        status!(
+            "  {}. {}",
+            i + 1,
+            photo.caption.as_deref().unwrap_or(&photo.title)
+        );
+    }
+    status!();
+
+    // Extract collection name from URL for directory
+    let collection_name = extract_collection_name_from_url(url);
+
+    // Download the collection
+    status!("{}", "Downloading photos...".yellow());
+    status!();
+
+    let options = CollectionDownloadOptions {
+        download: options,
+        ..CollectionDownloadOptions::default()
+    };
+    let bar = collection_progress_bar(collection.photos.len());
+    let progress = |event: CollectionProgress| match event {
+        CollectionProgress::Started { title, .. } => bar.set_message(title.to_string()),
+        CollectionProgress::Transfer {
+            title,
+            bytes,
+            total_bytes,
+        } => bar.set_message(match total_bytes {
+            Some(total) => format!("{} ({} / {})", title, HumanBytes(bytes), HumanBytes(total)),
+            None => format!("{} ({})", title, HumanBytes(bytes)),
+        }),
+        CollectionProgress::Finished { completed, .. } => bar.set_position(completed as u64),
+    };
+    let result = download_collection(&collection, &collection_name, &options, Some(&progress));
+    bar.finish_and_clear();
+    let result = result?;
+
+    status!();
+    status!("{}", "=== Download Summary ===".green());
+    status!("  Downloaded: {}", result.downloaded.to_string().green());
+    status!(
+        "  Skipped (already exist): {}",
+        result.skipped.to_string().yellow()
+    );
+    if result.failed > 0 {
+        status!("  Failed: {}", result.failed.to_string().red());
+    }
+
+    let save_path = format!("{}{}", collection_dir()?, collection_name);
+    status!();
+    status!("Photos saved to: {}", save_path.green());
+
+    Ok(result)
+}
+
+/// Rename photos to a consistent filename template
+fn normalize(template: Option<&str>, path: Option<&str>, apply: bool) -> Result<(), PhotoError> {
+    status!("{}", "=== Normalize Photo Filenames ===".green());
+    status!();
+
+    let template = template.unwrap_or(DEFAULT_FILENAME_TEMPLATE);
+    let photo_root = photo_dir_or(path)?;
+    let photos = find_photos_in_path(Some(&photo_root))?;
+    let renames = plan_normalize(&photos, template);
+
+    status!("Template: {}", template.yellow());
+    status!(
+        "{} {} of {} photo(s) need renaming",
+        "✓".green(),
+        renames.len(),
+        photos.len()
+    );
+    status!();
+
+    if renames.is_empty() {
+        return Ok(());
+    }
+
+    if !apply {
+        for rename in &renames {
+            status!("  {} -> {}", rename.from.display(), rename.to.display());
+        }
+        status!();
+        status!(
+            "{} Dry run only, re-run with {} to rename",
+            "!".yellow(),
+            "--apply".green()
+        );
+        return Ok(());
+    }
+
+    let report = apply_normalize(
+        &renames,
+        std::path::Path::new(&log_dir()?),
+        std::path::Path::new(&photo_root),
+    )?;
+
+    for rename in report.renames.iter().chain(&report.sidecars) {
+        status!(
+            "{} {} -> {}",
+            "✓".green(),
+            rename.from.display(),
+            rename.to.display()
+        );
+    }
+    for reference in &report.updated_references {
+        status!("{} Updated {}", "✓".green(), reference.display());
+    }
+    for conflict in &report.conflicts {
+        status!(
+            "{} Left {}'s photo as it was: {} already exists",
+            "✗".red(),
+            conflict.from.display(),
+            conflict.to.display()
+        );
+    }
+
+    status!();
+    status!(
+        "{}",
+        format!("=== Renamed {} photo(s) ===", report.renames.len()).green()
+    );
+
+    Ok(())
+}
+
+/// Parse a `--since` date
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|e| format!("expected a date like 2026-02-01: {}", e))
+}
+
+/// A monitor named by `--monitor`
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum MonitorArg {
+    /// Counting from 1, in the order the desktop numbers its monitors
+    Number(usize),
+    /// A connector name such as `DP-1`, which stays put when monitors come and go
+    Name(String),
+}
+
+/// Parse `--monitor` as a number from 1 or a connector name
+fn parse_monitor(value: &str) -> Result<MonitorArg, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err("expected a monitor number or name".to_string());
+    }
+    if !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok(MonitorArg::Name(value.to_string()));
+    }
+    match value.parse::<usize>() {
+        Ok(n) if n >= 1 => Ok(MonitorArg::Number(n)),
+        _ => Err("monitors are numbered from 1".to_string()),
+    }
+}
+
+/// Parse a schedule interval such as `15m` or `1h30m`
+fn parse_interval(value: &str) -> Result<String, String> {
+    if is_valid_interval(value) {
+        Ok(value.to_string())
+    } else {
+        Err("expected an interval like 15m, 1h or 1h30m".to_string())
+    }
+}
+
+/// Print downloaded photos as a table or JSON
+fn list(options: &ListOptions, json: bool) -> Result<(), PhotoError> {
+    let entries = list_photos(
+        std::path::Path::new(&photo_dir()?),
+        std::path::Path::new(&collection_dir()?),
+        options,
+    )?;
+
+    if json {
+        let entries: Vec<_> = entries.collect();
+        return print_json(&entries);
+    }
+
+    let mut count = 0;
+    for entry in entries {
+        let dimensions = match (entry.width, entry.height) {
+            (Some(width), Some(height)) => format!("{}x{}", width, height),
+            _ => "?".to_string(),
+        };
+        status!(
+            "{}  {}  {}  {}",
+            entry.directory.yellow(),
+            entry.filename,
+            HumanBytes(entry.size).to_string().green(),
+            dimensions
+        );
+        count += 1;
+    }
+
+    if count == 0 {
+        status!("{} No photos found", "!".yellow());
+    }
+    Ok(())
+}
+
+/// Add a photo to the favorites or exclusions, take it off, or print the list
+fn photo_list(list: PhotoList, args: &PhotoListArgs, json: bool) -> Result<(), PhotoError> {
+    let data_dir = log_dir()?;
+    let data_dir = std::path::Path::new(&data_dir);
+    let Some(photo) = &args.photo else {
+        let photos = list.read(data_dir);
+        if json {
+            return print_json(&photos);
+        }
+        for photo in &photos {
+            status!("{}", photo.display());
+        }
+        if photos.is_empty() {
+            status!("{} {} is empty", "!".yellow(), list.file_name());
+        }
+        return Ok(());
+    };
+
+    let history = RotationHistory::load(std::path::Path::new(&history_path()?));
+    let current_link = std::path::Path::new(&photo_dir()?).join(current_link_name(0));
+    let photo = resolve_photo_arg(photo, &current_link, &history)?;
+    let name = list.file_name();
+    if args.remove {
+        if list.remove(data_dir, &photo)? {
+            status!("{} Removed {} from {}", "✓".green(), photo.display(), name);
+        } else {
+            status!("{} {} is not in {}", "!".yellow(), photo.display(), name);
+        }
+        return Ok(());
+    }
+
+    // A photo is either a favorite or excluded, never both
+    let other = match list {
+        PhotoList::Favorites => PhotoList::Excluded,
+        PhotoList::Excluded => PhotoList::Favorites,
+    };
+    if other.remove(data_dir, &photo)? {
+        status!(
+            "{} Removed {} from {}",
+            "✓".green(),
+            photo.display(),
+            other.file_name()
+        );
+    }
+    if list.add(data_dir, &photo)? {
+        status!("{} Added {} to {}", "✓".green(), photo.display(), name);
+    } else {
+        status!(
+            "{} {} is already in {}",
+            "!".yellow(),
+            photo.display(),
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Print the applied wallpapers, newest first
+fn history(limit: Option<usize>, json: bool) -> Result<(), PhotoError> {
+    let history = RotationHistory::load(std::path::Path::new(&history_path()?));
+    let entries = history
+        .entries
+        .iter()
+        .rev()
+        .take(limit.unwrap_or(usize::MAX));
+
+    if json {
+        let entries: Vec<_> = entries.collect();
+        return print_json(&entries);
+    }
+
+    let mut count = 0;
+    for entry in entries {
+        status!(
+            "{}  {}  {}",
+            entry
+                .applied_at
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+                .yellow(),
+            entry.target,
+            entry.photo.display()
+        );
+        count += 1;
+    }
+
+    if count == 0 {
+        status!("{} No wallpapers set yet", "!".yellow());
+    }
+    Ok(())
+}
+
+/// Go back to the previous wallpapers, printing what was set
+fn set_previous(set: &SetArgs) -> Result<WallpaperReport, PhotoError> {
+    status!("{}", "=== National Geographic Wallpaper ===".green());
+    status!("Going back to the previous wallpapers\n");
+
+    let backend = detect_wallpaper_backend_with(&set.backend_options())?;
+    let report = set_previous_wallpapers(backend.as_ref())?;
+    for warning in &report.warnings {
+        status!("{} {}", "!".yellow(), warning);
+    }
+    for AssignmentResult { assignment, result } in &report.results {
+        match result {
+            Ok(()) => status!(
+                "  {}: {}",
+                assignment.location,
+                assignment.photo_path.display()
+            ),
+            Err(e) => status!("  {}: {} {}", assignment.location, "✗".red(), e),
+        }
+    }
+    if set.wallpaper_config()?.lock_screen == Some(true) {
+        set_lock_screen_from(&report)?;
+    }
+
+    status!();
+    status!("{}", "=== Completed ===".green());
+    Ok(report)
+}
+
+/// Photos listed at once by `set --pick`; typing part of a name narrows the list
+const PICK_PAGE: usize = 20;
+
+/// Let the user choose a photo under `path` (or the photo directory) on the terminal
+fn pick_photo(path: Option<&str>) -> Result<String, PhotoError> {
+    if !io::stdin().is_terminal() {
+        return Err(PhotoError::Config(
+            "--pick needs a terminal to choose in; use --photo PATH instead".to_string(),
+        ));
+    }
+    let candidates = pick_candidates(path)?;
+    if candidates.is_empty() {
+        return Err(PhotoError::NoPhotos(
+            "No photos to pick from, run `download` first".to_string(),
+        ));
+    }
+
+    let mut shown: Vec<&PickCandidate> = candidates.iter().collect();
+    loop {
+        for (number, candidate) in shown.iter().take(PICK_PAGE).enumerate() {
+            let size = match (candidate.entry.width, candidate.entry.height) {
+                (Some(width), Some(height)) => format!("{width}x{height}"),
+                _ => "?".to_string(),
+            };
+            println!(
+                "{:>3}. {}  {}  {}",
+                number + 1,
+                candidate.entry.directory.dimmed(),
+                candidate.title,
+                size.dimmed()
+            );
+        }
+        if shown.len() > PICK_PAGE {
+            println!("     ... and {} more", shown.len() - PICK_PAGE);
+        }
+        print!("Number, or part of a name (empty to cancel): ");
+        io::stdout().flush().ok();
+
+        let mut input = String::new();
+        let read = io::stdin()
+            .read_line(&mut input)
+            .map_err(PhotoError::File)?;
+        let input = input.trim();
+        if read == 0 || input.is_empty() {
+            return Err(PhotoError::Interrupted);
+        }
+        match parse_pick_index(input, shown.len().min(PICK_PAGE)) {
+            Ok(Some(index)) => return Ok(shown[index].entry.path.to_string_lossy().into_owned()),
+            Ok(None) => {
+                let matches = search_candidates(&candidates, input);
+                if matches.is_empty() {
+                    println!("{} No photos match {input:?}", "!".yellow());
+                } else {
+                    shown = matches;
+                }
+            }
+            Err(e) => println!("{} {e}", "!".yellow()),
+        }
+        println!();
+    }
+}
+
+/// Download today's photo and set the wallpaper, tolerating being offline
+fn run_cmd(
+    set: &SetArgs,
+    download_options: &DownloadOptions,
+    options: RunOptions,
+    notifier: Option<&dyn Notifier>,
+) -> Result<(), PhotoError> {
+    let outcome = download_then_set(
+        options,
+        shutdown_flag(),
+        &mut || download(download_options, None).map(|report| notify_downloaded(notifier, &report)),
+        &mut || {
+            set.apply()
+                .map(|report| notify_wallpaper_set(notifier, &report))
+        },
+    )?;
+
+    // Record whether the wallpaper changed despite a failure, or not at all
+    write_log_checked(&wallpaper_log_path()?, &outcome.to_string())?;
+    notify_run_failure(notifier, &outcome);
+
+    match outcome {
+        RunOutcome::Complete => Ok(()),
+        RunOutcome::Degraded(e) => {
+            status!(
+                "{} Offline ({}), set the wallpaper from existing photos",
+                "!".yellow(),
+                e
+            );
+            Ok(())
+        }
+        RunOutcome::DownloadFailed(e) => {
+            status!(
+                "{} Download failed ({}), set the wallpaper from existing photos",
+                "!".yellow(),
+                e
+            );
+            Ok(())
+        }
+        RunOutcome::DownloadRequired(e) => {
+            status!(
+                "{} Set the wallpaper from existing photos, but the download failed",
+                "✗".red()
+            );
+            Err(e)
+        }
+        RunOutcome::SetFailed(e) => {
+            status!(
+                "{} Photo downloaded, but setting the wallpaper failed: {}",
+                "!".yellow(),
+                e
+            );
+            Ok(())
+        }
+        RunOutcome::Failed { download, set } => {
+            status!("{} Download failed: {}", "✗".red(), download);
+            Err(set)
+        }
+    }
+}
+
+/// Re-apply the wallpaper after every burst of monitor changes, until interrupted
+fn watch(set: &SetArgs, notifier: Option<&dyn Notifier>) -> Result<(), PhotoError> {
+    let mut listing = set.listing_cache();
+    let watcher =
+        OutputWatcher::select(detect_desktop_environment(), &LiveSystem).ok_or_else(|| {
+            PhotoError::Command(
+                "Can't watch for monitor changes here; watch needs KDE Plasma, sway or xrandr"
+                    .to_string(),
+            )
+        })?;
+    let (sender, events) = mpsc::channel();
+    watcher.spawn(sender)?;
+    status!("Watching for monitor changes through {}", watcher);
+
+    run_debounced(&events, HOTPLUG_DEBOUNCE, shutdown_flag(), || {
+        status!("Monitors changed, re-applying the wallpaper");
+        match set.apply_from(&mut listing) {
+            Ok(report) => notify_wallpaper_set(notifier, &report),
+            Err(e) => {
+                status!("{} {}", "✗".red(), e);
+                notify(notifier, &Notification::failed("Setting the wallpaper", &e));
+            }
+        }
+    })
+}
+
+/// Set a new wallpaper every `interval` until SIGTERM or Ctrl-C
+fn daemon(
+    set: &SetArgs,
+    interval: &str,
+    notifier: Option<&dyn Notifier>,
+) -> Result<(), PhotoError> {
+    let seconds = interval_seconds(interval)
+        .ok_or_else(|| PhotoError::Config(format!("--interval {interval} is too short")))?;
+    let pidfile = PidFile::acquire(daemon_pid_path()?)?;
+    let (sender, rescan) = mpsc::channel();
+    forward_hangups(sender)?;
+
+    let log_path = wallpaper_log_path()?;
+    write_log(
+        &log_path,
+        &format!("Daemon started, rotating every {}s", seconds),
+    );
+    status!(
+        "Changing the wallpaper every {} (pid {}, {})",
+        interval.yellow(),
+        std::process::id(),
+        pidfile.path().display()
+    );
+
+    // Kept between rotations, so only directories that changed are read again
+    let mut listing = set.listing_cache();
+    let mut schedule = RotationSchedule::new(Duration::from_secs(seconds), Instant::now());
+    run_rotation(&mut schedule, &rescan, shutdown_flag(), || {
+        match set.apply_from(&mut listing) {
+            Ok(report) => notify_wallpaper_set(notifier, &report),
+            Err(e) => {
+                status!("{} {}", "✗".red(), e);
+                write_log(&log_path, &format!("Rotation failed: {}", e));
+                notify(notifier, &Notification::failed("Setting the wallpaper", &e));
+            }
+        }
+    });
+
+    write_log(&log_path, "Daemon stopped");
+    Ok(())
+}
+
+/// Show `notification` if notifications are on, warning when it can't be shown
+fn notify(notifier: Option<&dyn Notifier>, notification: &Notification) {
+    if let Some(notifier) = notifier {
+        if let Err(e) = notifier.notify(notification) {
+            status!("{} Couldn't show a notification: {}", "!".yellow(), e);
+        }
+    }
+}
+
+/// Announce a newly downloaded photo; photos that were already saved aren't news
+fn notify_downloaded(notifier: Option<&dyn Notifier>, report: &DownloadReport) {
+    if !report.skipped {
+        notify(
+            notifier,
+            &Notification::downloaded(&report.title, &report.path),
+        );
+    }
+}
+
+/// Announce the photos that are now the wallpaper
+fn notify_wallpaper_set(notifier: Option<&dyn Notifier>, report: &WallpaperReport) {
+    let mut photos: Vec<&std::path::Path> = Vec::new();
+    for applied in report.results.iter().filter(|r| r.result.is_ok()) {
+        if !photos.contains(&applied.assignment.photo_path.as_path()) {
+            photos.push(&applied.assignment.photo_path);
+        }
+    }
+    if let Some(first) = photos.first() {
+        notify(
+            notifier,
+            &Notification::wallpaper_set(first, photos.len() - 1),
+        );
+    }
+}
+
+/// Warn about whatever part of `run` failed, so a broken timer gets noticed
+fn notify_run_failure(notifier: Option<&dyn Notifier>, outcome: &RunOutcome) {
+    match outcome {
+        RunOutcome::Complete => {}
+        RunOutcome::Degraded(e)
+        | RunOutcome::DownloadFailed(e)
+        | RunOutcome::DownloadRequired(e) => {
+            notify(notifier, &Notification::failed("Download", e));
+        }
+        RunOutcome::SetFailed(e) => {
+            notify(notifier, &Notification::failed("Setting the wallpaper", e));
+        }
+        RunOutcome::Failed { download, set } => {
+            notify(notifier, &Notification::failed("Download", download));
+            notify(
+                notifier,
+                &Notification::failed("Setting the wallpaper", set),
+            );
+        }
+    }
+}
+
+/// Escape a value for a double-quoted word in a unit file
+///
+/// systemd unquotes "..." itself and expands % specifiers.
+fn systemd_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+}
+
+/// `ExecStart` line for the systemd service, running `run` with the given set options
+fn service_exec_start(binary: &str, random: bool, path: Option<&str>, lock_screen: bool) -> String {
+    let mut line = format!("ExecStart={} --color never run", binary);
+    if random {
+        line.push_str(" --random");
+    }
+    if let Some(path) = path {
+        let _ = write!(line, " --path \"{}\"", systemd_escape(path));
+    }
+    if lock_screen {
+        line.push_str(" --lock-screen");
+    }
+    line
+}
+
+/// Session variables the wallpaper tools need, as set when installing
+const SESSION_VARIABLES: &[&str] = &[
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "XDG_RUNTIME_DIR",
+];
+
+/// The session variables set in this process
+fn session_environment() -> Vec<(&'static str, String)> {
+    SESSION_VARIABLES
+        .iter()
+        .filter_map(|key| {
+            std::env::var(key)
+                .ok()
+                .filter(|v| !v.is_empty())
+                .map(|v| (*key, v))
+        })
+        .collect()
+}
+
+/// Contents of the systemd service running `run`.
+/// `run` downloads before touching the desktop, so a missing display only fails
+/// the wallpaper phase. `env` is written as `Environment=` lines because a timer
+/// can fire before the session has imported its variables into the user manager.
+fn systemd_service_unit(
+    binary: &str,
+    random: bool,
+    path: Option<&str>,
+    lock_screen: bool,
+    keep_days: Option<u32>,
+    env: &[(&str, String)],
+) -> String {
+    let mut unit = String::from(
+        "[Unit]
+Description=Download and set National Geographic Photo of the Day as wallpaper
+After=network-online.target network.target graphical-session.target
+Wants=network-online.target
+StartLimitIntervalSec=1h
+StartLimitBurst=3
+
+[Service]
+Type=oneshot
+Slice=background.slice
+Restart=on-failure
+RestartSec=5min
+",
+    );
+    push_environment(&mut unit, env);
+    unit.push_str(&service_exec_start(binary, random, path, lock_screen));
+    unit.push('\n');
+    // Clean up after a successful run so a failed download never deletes photos
+    if let Some(days) = keep_days {
+        let _ = writeln!(
+            unit,
+            "ExecStartPost={} --color never clean --keep-days {}",
+            binary, days
+        );
+    }
+    unit
+}
+
+/// Append `Environment=` lines for the saved session variables
+fn push_environment(unit: &mut String, env: &[(&str, String)]) {
+    for (key, value) in env {
+        let _ = writeln!(unit, "Environment=\"{}={}\"", key, systemd_escape(value));
+    }
+}
+
+/// Contents of the service that rotates the wallpaper through downloaded photos
+///
+/// It only runs `set --random`, so it never touches the network.
+fn systemd_rotate_service_unit(
+    binary: &str,
+    path: Option<&str>,
+    lock_screen: bool,
+    env: &[(&str, String)],
+) -> String {
+    let mut unit = String::from(
+        "[Unit]
+Description=Rotate the wallpaper through downloaded National Geographic photos
+After=graphical-session.target
+
+[Service]
+Type=oneshot
+Slice=background.slice
+",
+    );
+    push_environment(&mut unit, env);
+    let _ = write!(unit, "ExecStart={} --color never set --random", binary);
+    if let Some(path) = path {
+        let _ = write!(unit, " --path \"{}\"", systemd_escape(path));
+    }
+    if lock_screen {
+        unit.push_str(" --lock-screen");
+    }
+    unit.push('\n');
+    unit
+}
+
+/// Contents of the service that keeps `watch` running for the graphical session
+fn systemd_watch_service_unit(
+    binary: &str,
+    random: bool,
+    path: Option<&str>,
+    lock_screen: bool,
+    env: &[(&str, String)],
+) -> String {
+    let mut unit = String::from(
+        "[Unit]
+Description=Re-apply the National Geographic wallpaper when monitors change
+After=graphical-session.target
+PartOf=graphical-session.target
+
+[Service]
+Type=simple
+Restart=on-failure
+RestartSec=10s
+",
+    );
+    push_environment(&mut unit, env);
+    let _ = write!(unit, "ExecStart={} --color never watch", binary);
+    if random {
+        unit.push_str(" --random");
+    }
+    if let Some(path) = path {
+        let _ = write!(unit, " --path \"{}\"", systemd_escape(path));
+    }
+    if lock_screen {
+        unit.push_str(" --lock-screen");
+    }
+    unit.push_str(
+        "
+
+[Install]
+WantedBy=graphical-session.target
+",
+    );
+    unit
+}
+
+/// Contents of the timer that rotates the wallpaper every `interval`
+fn systemd_rotate_timer_unit(interval: &str) -> String {
+    format!(
+        "[Unit]
+Description=National Geographic wallpaper rotation
+
+[Timer]
+OnBootSec=1min
+OnUnitActiveSec={}
+
+[Install]
+WantedBy=timers.target
+",
+        interval
+    )
+}
+
+/// Contents of the systemd timer for `schedule`
+///
+/// `jitter` spreads the runs of many machines over a window instead of all
+/// of them hitting the site at the same second.
+fn systemd_timer_unit(schedule: &ScheduleType, jitter: Option<&str>) -> String {
+    let mut trigger = match schedule {
+        ScheduleType::DailyTime(time) => {
+            format!("OnCalendar=*-*-* {}:00\nOnBootSec=2min", time)
+        }
+        ScheduleType::Interval(interval) => {
+            format!("OnBootSec=1min\nOnUnitActiveSec={}", interval)
+        }
+    };
+    if let Some(jitter) = jitter {
+        let _ = write!(trigger, "\nRandomizedDelaySec={}", jitter);
+    }
+    format!(
+        "[Unit]
+Description=National Geographic Photo of the Day wallpaper update
+
+[Timer]
+{}
+Persistent=true
+
+[Install]
+WantedBy=timers.target
+",
+        trigger
+    )
+}
+
+/// Read the schedule back out of a timer written by `systemd_timer_unit`
+fn parse_timer_unit(contents: &str) -> Option<ScheduleType> {
+    for line in contents.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "OnCalendar" => {
+                let time = value.strip_prefix("*-*-* ")?;
+                // Drop the seconds we write, keeping a bare HH:MM as is
+                let time = match time.rsplit_once(':') {
+                    Some((hh_mm, "00")) if hh_mm.contains(':') => hh_mm,
+                    _ => time,
+                };
+                return is_valid_time(time).then(|| ScheduleType::DailyTime(time.to_string()));
+            }
+            "OnUnitActiveSec" => {
+                return is_valid_interval(value).then(|| ScheduleType::Interval(value.to_string()));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Name of the units that download and set the photo of the day
+const MAIN_UNIT: &str = "natgeo-wallpaper";
+
+/// Name of the units that only rotate the wallpaper, for `install --set-every`
+const ROTATE_UNIT: &str = "natgeo-wallpaper-rotate";
+
+/// Name of the service running `watch`, for `install --watch`
+const WATCH_UNIT: &str = "natgeo-wallpaper-watch";
+
+/// Path of the systemd user unit with the given name
+fn systemd_unit_path(name: &str) -> Result<String, PhotoError> {
+    let home =
+        std::env::var("HOME").map_err(|_| PhotoError::Command("HOME not set".to_string()))?;
+    Ok(format!("{}/.config/systemd/user/{}", home, name))
+}
+
+/// Download a range of past days, printing each day as it finishes
+fn backfill_cmd(range: DateRange, options: &BackfillOptions) -> Result<(), PhotoError> {
+    status!("{}", "=== Backfill Photos of the Day ===".green());
+    status!();
+    status!("Days: {} to {}", range.from, range.to);
+    status!("Log: {}", options.log_path);
+    status!();
+
+    let progress = |date: NaiveDate, status: &BackfillStatus| match status {
+        BackfillStatus::Downloaded(path) => {
+            status!("{} {} {}", "✓".green(), date, path.display());
+        }
+        BackfillStatus::Skipped(path) => status!(
+            "{} {} {}",
+            "-".dimmed(),
+            date,
+            format!("already have {}", path.display()).dimmed()
+        ),
+        BackfillStatus::Failed(reason) => status!("{} {} {}", "✗".red(), date, reason),
+    };
+    let report = backfill(&range, options, shutdown_flag(), Some(&progress))?;
+
+    status!();
+    status!("{}", "=== Backfill Complete ===".green());
+    status!("  Downloaded: {}", report.downloaded);
+    status!("  Skipped:    {}", report.skipped);
+    status!("  Failed:     {}", report.failed);
+
+    Ok(())
+}
+
+/// Print the downloaded collections with their photo counts
+fn collections(json: bool, names: bool) -> Result<(), PhotoError> {
+    let collections = local_collections(std::path::Path::new(&collection_dir()?))?;
+    if json {
+        return print_json(&collections);
+    }
+    if names {
+        for collection in &collections {
+            println!("{}", collection.name);
+        }
+        return Ok(());
+    }
+    for collection in &collections {
+        status!(
+            "{}  {}",
+            collection.name.yellow(),
+            format!("{} photos", collection.photos).dimmed()
+        );
+    }
+    if collections.is_empty() {
+        status!(
+            "{} No collections downloaded; get one with download-collection",
+            "!".yellow()
+        );
+    }
+    Ok(())
+}
+
+/// Run the environment checks and print each result, failing if any check failed
+fn doctor(json: bool) -> Result<(), PhotoError> {
+    let client = NatGeoClient::new(config().http_options())?;
+    let http_status = |url: &str| client.status(url);
+    let capabilities = |de: DesktopEnvironment| de.backend().map(|b| b.capabilities());
+    let checks = doctor_checks(&DoctorProbes {
+        system: &LiveSystem,
+        commands: &LiveCommands,
+        http_status: &http_status,
+        capabilities: &capabilities,
+        photo_dir: photo_dir()?.into(),
+        log_dir: log_dir()?.into(),
+    });
+
+    if json {
+        print_json(&checks)?;
+    } else {
+        status!("{}", "=== Doctor ===".green());
+        for check in &checks {
+            let mark = match check.status {
+                CheckStatus::Pass => "✓".green(),
+                CheckStatus::Warn => "!".yellow(),
+                CheckStatus::Fail => "✗".red(),
+            };
+            status!("{} {}: {}", mark, check.name, check.message);
+        }
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        return Err(PhotoError::ChecksFailed(failed));
+    }
+    Ok(())
+}
+
+/// Prints the downloaded collection names for completion scripts
+const COLLECTION_NAMES_COMMAND: &str = "natgeo-wallpapers collections --names 2>/dev/null";
+
+/// Write the completion script for `shell` to `out`. Where the shell allows
+/// it, `--from-collection` completes the names of downloaded collections.
+fn write_completions(shell: Shell, out: &mut dyn Write) -> io::Result<()> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), "natgeo-wallpapers", &mut script);
+    let script = complete_collection_names(shell, &String::from_utf8_lossy(&script));
+    out.write_all(script.as_bytes())
+}
+
+/// `script` with the file completion clap generates for `--from-collection`
+/// swapped for the names from `collections --names`.
+/// PowerShell and Elvish scripts can't run a command there, so stay as they are.
+fn complete_collection_names(shell: Shell, script: &str) -> String {
+    let mut completed = String::with_capacity(script.len());
+    let mut after_option = false;
+    for line in script.lines() {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        match shell {
+            // The line after the option's `case` pattern fills in COMPREPLY
+            Shell::Bash if after_option && trimmed.starts_with("COMPREPLY=") => {
+                let _ = writeln!(
+                    completed,
+                    "{indent}COMPREPLY=($(compgen -W \"$({COLLECTION_NAMES_COMMAND})\" -- \"${{cur}}\"))"
+                );
+            }
+            Shell::Zsh if line.contains("--from-collection=[") => {
+                let action =
+                    format!(":NAME:{{compadd -- ${{(f)\"$({COLLECTION_NAMES_COMMAND})\"}}}}'");
+                let _ = writeln!(completed, "{}", line.replace(":NAME:_default'", &action));
+            }
+            Shell::Fish if line.contains(" -l from-collection ") => {
+                let _ = writeln!(completed, "{line} -f -a \"({COLLECTION_NAMES_COMMAND})\"");
+            }
+            _ => {
+                completed.push_str(line);
+                completed.push('\n');
+            }
+        }
+        after_option = trimmed == "--from-collection)";
+    }
+    completed
+}
+
+/// Print library statistics as a summary or JSON
+fn stats(path: Option<&str>, json: bool) -> Result<(), PhotoError> {
+    let base = photo_dir_or(path)?;
+    let stats = gather_stats(std::path::Path::new(&base))?;
+
+    if json {
+        return print_json(&stats);
+    }
+
+    status!("{}", "=== Photo Library ===".green());
+    status!();
+    status!("Daily photos:  {}", stats.daily_photos);
+    if let (Some(oldest), Some(newest)) = (stats.oldest, stats.newest) {
+        status!("Date range:    {} to {}", oldest, newest);
+    }
+    status!("Collections:   {}", stats.collections.len());
+    for collection in &stats.collections {
+        status!(
+            "  {} ({} photos)",
+            collection.name.yellow(),
+            collection.photos
+        );
+    }
+    if stats.other_photos > 0 {
+        status!("Other photos:  {}", stats.other_photos);
+    }
+    status!(
+        "Orientation:   {} landscape, {} portrait",
+        stats.landscape,
+        stats.portrait
+    );
+    status!(
+        "Disk usage:    {}",
+        HumanBytes(stats.total_bytes).to_string().green()
+    );
+    if let Some(largest) = &stats.largest {
+        status!(
+            "Largest photo: {} ({})",
+            largest.path.display(),
+            HumanBytes(largest.bytes)
+        );
+    }
+
+    if !stats.corrupt.is_empty() {
+        status!();
+        status!(
+            "{} {} corrupt or unreadable photo(s):",
+            "!".yellow(),
+            stats.corrupt.len()
+        );
+        for photo in &stats.corrupt {
+            status!("  {}", photo.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the library for broken photos, optionally repairing them
+fn verify(path: Option<&str>, fix: bool) -> Result<(), PhotoError> {
+    status!("{}", "=== Verify Photos ===".green());
+    status!();
+
+    let root = photo_dir_or(path)?;
+    let report = verify_photos(std::path::Path::new(&root))?;
+    status!(
+        "{} Checked {} photo(s), {} with problems",
+        "✓".green(),
+        report.checked,
+        report.issues.len()
+    );
+    if report.issues.is_empty() {
+        return Ok(());
+    }
+    status!();
+
+    let width = report
+        .issues
+        .iter()
+        .map(|issue| issue.problem.to_string().len())
+        .max()
+        .unwrap_or_default();
+    for issue in &report.issues {
+        status!(
+            "  {:<width$}  {}",
+            issue.problem.to_string().yellow(),
+            issue.path.display(),
+            width = width
+        );
+    }
+    status!();
+
+    if !fix {
+        status!(
+            "{} Re-run with {} to repair them",
+            "!".yellow(),
+            "--fix".green()
+        );
+        return Ok(());
+    }
+
+    let client = NatGeoClient::new(config().http_options())?;
+    let mut failed = 0;
+    for issue in &report.issues {
+        match repair_photo(issue, &client) {
+            Repair::Redownloaded(path) => {
+                status!("{} Re-downloaded {}", "✓".green(), path.display());
+            }
+            Repair::Renamed(path) => status!(
+                "{} Renamed {} -> {}",
+                "✓".green(),
+                issue.path.display(),
+                path.display()
+            ),
+            Repair::Deleted => status!("{} Deleted {}", "✓".green(), issue.path.display()),
+            Repair::Failed(reason) => {
+                failed += 1;
+                status!("{} {}: {}", "✗".red(), issue.path.display(), reason);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(PhotoError::CorruptImage(format!(
+            "{} photo(s) could not be repaired",
+            failed
+        )));
+    }
+
+    Ok(())
+}
+
+/// Parse a `--max-size` value
+fn parse_max_size(value: &str) -> Result<String, String> {
+    parse_size(value)
+        .map(|_| value.to_string())
+        .ok_or_else(|| "expected a size like 500M or 2G".to_string())
+}
+
+/// Delete the resized and converted photos in the cache directory
+fn clean_cache(dry_run: bool) -> Result<(), PhotoError> {
+    let purge = purge_image_cache(cache_dir()?.as_ref(), dry_run)?;
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    status!(
+        "{} {} {} cached image(s), {}",
+        "✓".green(),
+        verb,
+        purge.files,
+        HumanBytes(purge.bytes)
+    );
+    Ok(())
+}
+
+/// Delete old photo directories according to `policy`
+fn clean(policy: &RetentionPolicy) -> Result<(), PhotoError> {
+    status!("{}", "=== Clean Old Photos ===".green());
+    status!();
+
+    let report = clean_photos(policy)?;
+    let verb = if policy.dry_run {
+        "Would remove"
+    } else {
+        "Removed"
+    };
+    for dir in &report.removed {
+        status!(
+            "{} {} {} ({} photo(s), {})",
+            "✓".green(),
+            verb,
+            dir.path.display(),
+            dir.photos,
+            HumanBytes(dir.bytes)
+        );
+    }
+    if !report.removed.is_empty() {
+        status!();
+    }
+
+    status!(
+        "{} {} photo(s) in {} director(ies), {} freed, {} remaining",
+        verb,
+        report.removed_photos,
+        report.removed.len(),
+        HumanBytes(report.freed_bytes),
+        HumanBytes(report.remaining_bytes)
+    );
+    if policy
+        .max_size
+        .is_some_and(|max| report.remaining_bytes > max)
+    {
+        status!(
+            "{} Still over --max-size: today's photos and collections are kept",
+            "!".yellow()
+        );
+    }
+    if policy.dry_run {
+        status!(
+            "{} Dry run only, re-run without {} to delete",
+            "!".yellow(),
+            "--dry-run".green()
+        );
+    }
+
+    Ok(())
+}
+
+/// Report, link, or remove duplicate photos in the library
+fn dedup(action: DuplicateAction) -> Result<(), PhotoError> {
+    status!("{}", "=== Find Duplicate Photos ===".green());
+    status!();
+
+    let config = ManifestConfig::from_defaults()?;
+    let report = dedup_photos(&config, action)?;
+
+    status!(
+        "{} {} duplicate(s) among {} photo(s)",
+        "✓".green(),
+        report.duplicates.len(),
+        report.scanned
+    );
+    status!("Manifest: {}", config.path.display());
+    status!();
+
+    if report.duplicates.is_empty() {
+        return Ok(());
+    }
+
+    let verb = match action {
+        DuplicateAction::Report => "",
+        DuplicateAction::HardLink => "Linked ",
+        DuplicateAction::Remove => "Removed ",
+    };
+    for duplicate in &report.duplicates {
+        status!(
+            "  {}{} (same as {})",
+            verb,
+            duplicate.path.display(),
+            duplicate.original.display()
+        );
+    }
+    status!();
+
+    if action == DuplicateAction::Report {
+        status!(
+            "{} Report only, re-run with {} or {} to reclaim space",
+            "!".yellow(),
+            "--link".green(),
+            "--remove".green()
+        );
+    } else {
+        status!(
+            "{}",
+            format!("=== Reclaimed {} ===", HumanBytes(report.reclaimed_bytes)).green()
+        );
+    }
+
+    Ok(())
+}
+
+/// Set the lock screen to the photo `report` applied to the primary monitor
+fn set_lock_screen_from(report: &WallpaperReport) -> Result<(), PhotoError> {
+    status!();
+    status!("{}", "Setting lock screen wallpaper...".yellow());
+
+    let photo = report.primary_photo().ok_or_else(|| {
+        PhotoError::Wallpaper("No wallpaper was applied, so the lock screen was left alone".into())
+    })?;
+    let env = DetectedEnvironment {
+        de: report.desktop,
+        session: SessionType::detect(&LiveSystem),
+    };
+    if let Err(e) = set_lock_screen(&photo, &env) {
+        status!("{} Failed to set lock screen wallpaper: {}", "✗".red(), e);
+        return Err(e);
+    }
+    status!("{} Lock screen wallpaper: {}", "✓".green(), photo.display());
+    status!(
+        "  {}",
+        "Note: Changes apply on next lock screen activation".yellow()
+    );
+    Ok(())
+}
+
+/// Get the path to the current binary
+fn get_binary_path() -> Result<String, PhotoError> {
+    std::env::current_exe()
+        .map(|p| p.to_string_lossy().to_string())
+        .map_err(PhotoError::File)
+}
+
+/// Prompt user for time/interval selection
+fn prompt_for_schedule() -> Result<ScheduleType, PhotoError> {
+    status!("{}", "Setting up automatic updates...".yellow());
+    status!();
+    status!("When would you like the wallpaper to update?");
+    status!("  1) Daily at 02:00 (recommended for daily photo)");
+    status!("  2) Every hour (good for random rotation)");
+    status!("  3) Every 30 minutes");
+    status!("  4) Custom time (HH:MM)");
+    status!("  5) Custom interval (e.g., 2h, 15m)");
+    status!("  6) Cancel");
+    status!();
+
+    loop {
+        print!("Enter choice [1-6]: ");
+        io::stdout().flush().ok();
+
+        let mut input = String::new();
+        io::stdin()
+            .read_line(&mut input)
+            .map_err(PhotoError::File)?;
+
+        match input.trim() {
+            "1" => return Ok(ScheduleType::DailyTime("02:00".to_string())),
+            "2" => return Ok(ScheduleType::Interval("1h".to_string())),
+            "3" => return Ok(ScheduleType::Interval("30m".to_string())),
+            "4" => loop {
+                print!("Enter time (HH:MM format, e.g., 22:45): ");
+                io::stdout().flush().ok();
 
                 let mut time_input = String::new();
                 io::stdin()
                     .read_line(&mut time_input)
                     .map_err(PhotoError::File)?;
 
-                let time = time_input.trim();
-                if is_valid_time(time) {
-                    return Ok(ScheduleType::DailyTime(time.to_string()));
-                }
-                println!(
-                    "{} Invalid format. Please use HH:MM (00:00-23:59)",
-                    "✗".red()
-                );
+                let time = time_input.trim();
+                if is_valid_time(time) {
+                    return Ok(ScheduleType::DailyTime(time.to_string()));
+                }
+                status!(
+                    "{} Invalid format. Please use HH:MM (00:00-23:59)",
+                    "✗".red()
+                );
+            },
+            "5" => loop {
+                print!("Enter interval (e.g., 1h, 30m, 2h30m): ");
+                io::stdout().flush().ok();
+
+                let mut interval_input = String::new();
+                io::stdin()
+                    .read_line(&mut interval_input)
+                    .map_err(PhotoError::File)?;
+
+                let interval = interval_input.trim();
+                if is_valid_interval(interval) {
+                    return Ok(ScheduleType::Interval(interval.to_string()));
+                }
+                status!(
+                    "{} Invalid format. Use h for hours, m for minutes (e.g., 1h, 30m, 2h30m)",
+                    "✗".red()
+                );
+            },
+            "6" => {
+                status!("{} Cancelled", "!".yellow());
+                return Err(PhotoError::Command("Cancelled by user".to_string()));
+            }
+            _ => {
+                status!("{} Invalid choice, please enter 1-6", "✗".red());
+            }
+        }
+    }
+}
+
+/// Print the schedule, then download today's photo and set the wallpaper right away
+fn finish_install(
+    schedule_desc: &str,
+    random: bool,
+    path: Option<&str>,
+    lock_screen: bool,
+) -> Result<(), PhotoError> {
+    status!();
+    status!("Schedule: {}", schedule_desc.yellow());
+    if random {
+        status!("Random selection: {}", "enabled".green());
+    }
+    if let Some(p) = path {
+        status!("Photo path: {}", p.green());
+    }
+    if lock_screen {
+        status!("Lock screen: {}", "enabled".green());
+    }
+    status!();
+
+    // Download and set wallpaper now
+    status!(
+        "{}",
+        "Downloading today's photo and setting wallpaper...".yellow()
+    );
+    status!();
+
+    download(&daily_download_options(), None)?;
+    status!();
+    let report = set_and_report(
+        WallpaperMode::Monitors,
+        path,
+        &WallpaperOptions {
+            sort: if random {
+                SortOrder::Random
+            } else {
+                SortOrder::Date
+            },
+            ..WallpaperOptions::default()
+        },
+        &BackendOptions::default(),
+    )?;
+    if lock_screen {
+        set_lock_screen_from(&report)?;
+    }
+
+    Ok(())
+}
+
+/// Remove the scheduled job for this platform
+fn uninstall_schedule() -> Result<(), PhotoError> {
+    if cfg!(target_os = "macos") {
+        return uninstall_launchd_agent();
+    }
+    UserSystemd::live()?.uninstall()?;
+    if command_exists("crontab") {
+        uninstall_cron_entry()?;
+    }
+    uninstall_autostart()
+}
+
+/// Path of the login autostart entry
+fn autostart_path() -> String {
+    format!(
+        "{}{}",
+        expand_tilde("~/.config/autostart/"),
+        AUTOSTART_FILE_NAME
+    )
+}
+
+/// Whether a timer, launch agent or crontab entry is installed
+fn timer_installed() -> Result<bool, PhotoError> {
+    if cfg!(target_os = "macos") {
+        return Ok(std::path::Path::new(&launchd_plist_path()).exists());
+    }
+    if std::path::Path::new(&systemd_unit_path("natgeo-wallpaper.timer")?).exists() {
+        return Ok(true);
+    }
+    Ok(command_exists("crontab")
+        && read_crontab()?
+            .lines()
+            .any(|line| line.trim_end().ends_with(CRON_TAG)))
+}
+
+/// Warn that the autostart entry and a timer will both update the wallpaper
+fn warn_double_schedule() {
+    status!(
+        "{} Both a timer and an autostart entry are installed, so the wallpaper updates at login and on the schedule",
+        "!".yellow()
+    );
+    status!(
+        "  Run {} and install again to keep only one",
+        "natgeo-wallpapers install --uninstall".green()
+    );
+    status!();
+}
+
+/// Write an autostart entry that downloads and sets the wallpaper at login
+fn install_autostart(
+    random: bool,
+    path: Option<&str>,
+    lock_screen: bool,
+) -> Result<(), PhotoError> {
+    if cfg!(target_os = "macos") {
+        return Err(PhotoError::Command(
+            "--autostart needs a freedesktop session; install without it for a launch agent"
+                .to_string(),
+        ));
+    }
+    status!("{}", "=== Autostart Setup ===".green());
+    status!();
+
+    let binary_path = get_binary_path()?;
+    fs::create_dir_all(expand_tilde("~/.config/autostart/"))?;
+    let entry_path = autostart_path();
+    fs::write(
+        &entry_path,
+        autostart_desktop_entry(&binary_path, random, path, lock_screen),
+    )?;
+    status!("{} Created {}", "✓".green(), entry_path);
+    if timer_installed()? {
+        status!();
+        warn_double_schedule();
+    }
+
+    status!();
+    status!("{}", "=== Autostart Setup Complete ===".green());
+    finish_install("at login", random, path, lock_screen)?;
+
+    status!();
+    status!("Useful commands:");
+    status!(
+        "  {} - Uninstall",
+        "natgeo-wallpapers install --uninstall".green()
+    );
+
+    Ok(())
+}
+
+/// Remove the autostart entry, if installed
+fn uninstall_autostart() -> Result<(), PhotoError> {
+    let entry_path = autostart_path();
+    if std::path::Path::new(&entry_path).exists() {
+        fs::remove_file(&entry_path)?;
+        status!("{} Removed {}", "✓".green(), entry_path);
+    }
+    Ok(())
+}
+
+/// Path of the macOS launch agent plist
+fn launchd_plist_path() -> String {
+    format!(
+        "{}{}.plist",
+        expand_tilde("~/Library/LaunchAgents/"),
+        LAUNCHD_LABEL
+    )
+}
+
+/// launchd domain of the logged-in user's GUI session, `gui/<uid>`
+fn launchd_domain() -> Result<String, PhotoError> {
+    let output = Command::new("id")
+        .arg("-u")
+        .output()
+        .map_err(|e| PhotoError::Command(e.to_string()))?;
+    let uid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || uid.is_empty() {
+        return Err(PhotoError::Command(
+            "Could not determine user id".to_string(),
+        ));
+    }
+    Ok(format!("gui/{}", uid))
+}
+
+/// Arguments running `run` with the given set options
+fn run_arguments(binary: &str, random: bool, path: Option<&str>, lock_screen: bool) -> Vec<String> {
+    let mut run = vec![binary.to_string(), "run".to_string()];
+    if random {
+        run.push("--random".to_string());
+    }
+    if let Some(path) = path {
+        run.extend(["--path".to_string(), path.to_string()]);
+    }
+    if lock_screen {
+        run.push("--lock-screen".to_string());
+    }
+    run
+}
+
+/// Shell command running `run`, followed by `clean` when `keep_days` is set
+fn scheduled_shell_command(
+    binary: &str,
+    random: bool,
+    path: Option<&str>,
+    lock_screen: bool,
+    keep_days: Option<u32>,
+) -> String {
+    let run: Vec<String> = run_arguments(binary, random, path, lock_screen)
+        .iter()
+        .map(|arg| shell_quote(arg))
+        .collect();
+    let mut command = run.join(" ");
+    // Clean up after a successful run so a failed download never deletes photos
+    if let Some(days) = keep_days {
+        let _ = write!(
+            command,
+            " && {} clean --keep-days {}",
+            shell_quote(binary),
+            days
+        );
+    }
+    command
+}
+
+/// launchd `ProgramArguments` running `run`, followed by `clean` when `keep_days` is set.
+/// launchd runs a single program, so the cleanup is chained through `/bin/sh`.
+fn launchd_program_arguments(
+    binary: &str,
+    random: bool,
+    path: Option<&str>,
+    lock_screen: bool,
+    keep_days: Option<u32>,
+) -> Vec<String> {
+    if keep_days.is_none() {
+        return run_arguments(binary, random, path, lock_screen);
+    }
+    vec![
+        "/bin/sh".to_string(),
+        "-c".to_string(),
+        scheduled_shell_command(binary, random, path, lock_screen, keep_days),
+    ]
+}
+
+/// The user's crontab, empty if they don't have one yet
+fn read_crontab() -> Result<String, PhotoError> {
+    let output = Command::new("crontab")
+        .arg("-l")
+        .output()
+        .map_err(|e| PhotoError::Command(format!("crontab: {}", e)))?;
+    crontab_from_listing(
+        output.status.success(),
+        &String::from_utf8_lossy(&output.stdout),
+        &String::from_utf8_lossy(&output.stderr),
+    )
+}
+
+/// Replace the user's crontab
+fn write_crontab(contents: &str) -> Result<(), PhotoError> {
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| PhotoError::Command(format!("crontab: {}", e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(contents.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(PhotoError::Command(format!(
+            "crontab failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Session variables cron jobs need to reach the desktop
+fn cron_environment() -> Vec<(&'static str, String)> {
+    let display = std::env::var("DISPLAY").unwrap_or_else(|_| ":0".to_string());
+    let bus = std::env::var("DBUS_SESSION_BUS_ADDRESS").ok().or_else(|| {
+        let uid = Command::new("id").arg("-u").output().ok()?;
+        let uid = String::from_utf8_lossy(&uid.stdout).trim().to_string();
+        (!uid.is_empty()).then(|| format!("unix:path=/run/user/{}/bus", uid))
+    });
+    let mut env = vec![("DISPLAY", display)];
+    if let Some(bus) = bus {
+        env.push(("DBUS_SESSION_BUS_ADDRESS", bus));
+    }
+    env
+}
+
+/// Install a tagged crontab line for automatic updates, for systems without systemd
+#[allow(clippy::needless_pass_by_value)]
+fn install_cron_entry(options: InstallOptions) -> Result<(), PhotoError> {
+    let InstallOptions {
+        time,
+        random,
+        path,
+        lock_screen,
+        keep_days,
+        jitter,
+        ..
+    } = options;
+    if jitter.is_some() {
+        return Err(PhotoError::Command(
+            "--jitter needs systemd; cron runs the job on time".to_string(),
+        ));
+    }
+    status!("{}", "=== Crontab Setup ===".green());
+    status!();
+
+    let schedule = match time {
+        Some(t) => parse_schedule(&t)?,
+        None => prompt_for_schedule()?,
+    };
+
+    let binary_path = get_binary_path()?;
+    let command = scheduled_shell_command(
+        &binary_path,
+        random,
+        path.as_deref(),
+        lock_screen,
+        keep_days,
+    );
+    let env = cron_environment();
+    let env: Vec<(&str, &str)> = env.iter().map(|(k, v)| (*k, v.as_str())).collect();
+    let line = cron_line(&schedule, &env, &command)?;
+
+    write_crontab(&crontab_with_line(&read_crontab()?, &line))?;
+    status!("{} Added to crontab: {}", "✓".green(), line);
+
+    status!();
+    status!("{}", "=== Crontab Setup Complete ===".green());
+    finish_install(&schedule.to_string(), random, path.as_deref(), lock_screen)?;
+
+    status!();
+    status!("Useful commands:");
+    status!("  {} - Show the entry", "crontab -l".green());
+    status!(
+        "  {} - Uninstall",
+        "natgeo-wallpapers install --uninstall".green()
+    );
+
+    Ok(())
+}
+
+/// Remove the tagged crontab line, if there is one
+fn uninstall_cron_entry() -> Result<(), PhotoError> {
+    let (crontab, removed) = crontab_without_line(&read_crontab()?);
+    if removed {
+        write_crontab(&crontab)?;
+        status!("{} Removed crontab entry", "✓".green());
+    }
+    Ok(())
+}
+
+/// Whether `systemctl` is on the PATH
+fn systemd_available() -> bool {
+    command_exists("systemctl")
+}
+
+/// What `install` schedules, from its command line
+// Each flag is its own switch
+#[allow(clippy::struct_excessive_bools)]
+struct InstallOptions {
+    time: Option<String>,
+    random: bool,
+    path: Option<String>,
+    lock_screen: bool,
+    keep_days: Option<u32>,
+    force: bool,
+    jitter: Option<String>,
+    set_every: Option<String>,
+    watch: bool,
+}
+
+/// Install with systemd, or offer a crontab entry where systemd is missing
+fn install_schedule(options: InstallOptions) -> Result<(), PhotoError> {
+    if std::path::Path::new(&autostart_path()).exists() {
+        warn_double_schedule();
+    }
+    let systemd = !cfg!(target_os = "macos") && systemd_available();
+    if !systemd && (options.jitter.is_some() || options.set_every.is_some() || options.watch) {
+        return Err(PhotoError::Command(
+            "--jitter, --set-every and --watch need systemd".to_string(),
+        ));
+    }
+    if cfg!(target_os = "macos") {
+        return install_launchd_agent(options);
+    }
+    if systemd {
+        return install_systemd_timer(options);
+    }
+
+    status!("{} systemctl not found", "!".yellow());
+    if confirm("Schedule updates with cron instead?")? {
+        install_cron_entry(options)
+    } else {
+        Err(PhotoError::Command("systemctl not found".to_string()))
+    }
+}
+
+/// Install a launchd agent for automatic updates on macOS
+#[allow(clippy::needless_pass_by_value)]
+fn install_launchd_agent(options: InstallOptions) -> Result<(), PhotoError> {
+    let InstallOptions {
+        time,
+        random,
+        path,
+        lock_screen,
+        keep_days,
+        ..
+    } = options;
+    status!("{}", "=== Launch Agent Setup ===".green());
+    status!();
+
+    let schedule = match time {
+        Some(t) => parse_schedule(&t)?,
+        None => prompt_for_schedule()?,
+    };
+
+    let binary_path = get_binary_path()?;
+    let arguments = launchd_program_arguments(
+        &binary_path,
+        random,
+        path.as_deref(),
+        lock_screen,
+        keep_days,
+    );
+    let log_dir = log_dir()?;
+    fs::create_dir_all(&log_dir)?;
+    let plist = launchd_plist(&arguments, &schedule, &format!("{}launchd.log", log_dir))?;
+
+    fs::create_dir_all(expand_tilde("~/Library/LaunchAgents/"))?;
+    let plist_path = launchd_plist_path();
+    fs::write(&plist_path, plist)?;
+    status!("{} Created {}", "✓".green(), plist_path);
+
+    // Replace an agent loaded by an earlier install
+    let domain = launchd_domain()?;
+    let _ = Command::new("launchctl")
+        .args(["bootout", &format!("{}/{}", domain, LAUNCHD_LABEL)])
+        .output();
+    let output = Command::new("launchctl")
+        .args(["bootstrap", &domain, &plist_path])
+        .output()
+        .map_err(|e| PhotoError::Command(e.to_string()))?;
+    if !output.status.success() {
+        return Err(PhotoError::Command(format!(
+            "launchctl bootstrap failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    status!("{} Loaded launch agent", "✓".green());
+
+    status!();
+    status!("{}", "=== Launch Agent Setup Complete ===".green());
+    finish_install(&schedule.to_string(), random, path.as_deref(), lock_screen)?;
+
+    status!();
+    status!("Useful commands:");
+    status!(
+        "  {} - Check agent status",
+        format!("launchctl print {}/{}", domain, LAUNCHD_LABEL).green()
+    );
+    status!(
+        "  {} - View logs",
+        format!("tail -f {}launchd.log", log_dir).green()
+    );
+    status!(
+        "  {} - Uninstall",
+        "natgeo-wallpapers install --uninstall".green()
+    );
+
+    Ok(())
+}
+
+/// Unload and remove the macOS launch agent
+fn uninstall_launchd_agent() -> Result<(), PhotoError> {
+    status!("{}", "=== Uninstalling Launch Agent ===".green());
+    status!();
+
+    let domain = launchd_domain()?;
+    let _ = Command::new("launchctl")
+        .args(["bootout", &format!("{}/{}", domain, LAUNCHD_LABEL)])
+        .output();
+    status!("{} Unloaded launch agent", "✓".green());
+
+    let plist_path = launchd_plist_path();
+    if std::path::Path::new(&plist_path).exists() {
+        fs::remove_file(&plist_path)?;
+        status!("{} Removed {}", "✓".green(), plist_path);
+    }
+
+    status!();
+    status!("{}", "=== Uninstall Complete ===".green());
+
+    Ok(())
+}
+
+/// Show the schedule of an installed timer and ask whether to keep it
+fn keep_existing_timer(timer_path: &str, force: bool) -> Result<bool, PhotoError> {
+    let Ok(existing) = fs::read_to_string(timer_path) else {
+        return Ok(false);
+    };
+    let current = parse_timer_unit(&existing)
+        .map_or_else(|| "unrecognized schedule".to_string(), |s| s.to_string());
+    status!("{} Existing timer: {}", "!".yellow(), current.yellow());
+    let keep = !force && !confirm("Replace it?")?;
+    status!();
+    Ok(keep)
+}
+
+/// Install systemd timer for automatic updates
+#[allow(clippy::needless_pass_by_value)]
+fn install_systemd_timer(options: InstallOptions) -> Result<(), PhotoError> {
+    let InstallOptions {
+        time,
+        random,
+        path,
+        lock_screen,
+        keep_days,
+        force,
+        jitter,
+        set_every,
+        watch,
+    } = options;
+    status!("{}", "=== Systemd Timer Setup ===".green());
+    status!();
+
+    let systemd = UserSystemd::live()?;
+    let timer_path = systemd_unit_path("natgeo-wallpaper.timer")?;
+    if keep_existing_timer(&timer_path, force)? {
+        status!("Kept the existing timer");
+        return Ok(());
+    }
+
+    // Get schedule (from argument or prompt)
+    let schedule = match time {
+        Some(t) => parse_schedule(&t)?,
+        None => prompt_for_schedule()?,
+    };
+
+    let binary_path = get_binary_path()?;
+    let home =
+        std::env::var("HOME").map_err(|_| PhotoError::Command("HOME not set".to_string()))?;
+    let systemd_dir = format!("{}/.config/systemd/user", home);
+
+    // Create systemd directory
+    fs::create_dir_all(&systemd_dir)?;
+
+    let env = session_environment();
+    let service_content = systemd_service_unit(
+        &binary_path,
+        random,
+        path.as_deref(),
+        lock_screen,
+        keep_days,
+        &env,
+    );
+    let service_path = format!("{}/natgeo-wallpaper.service", systemd_dir);
+    fs::write(&service_path, &service_content)?;
+    status!("{} Created {}", "✓".green(), service_path);
+    report_saved_environment(&env);
+
+    let schedule_desc = schedule.to_string();
+    let timer_content = systemd_timer_unit(&schedule, jitter.as_deref());
+    fs::write(&timer_path, timer_content)?;
+    status!("{} Created {}", "✓".green(), timer_path);
+
+    if let Some(interval) = &set_every {
+        let rotate_service = format!("{}/{}.service", systemd_dir, ROTATE_UNIT);
+        fs::write(
+            &rotate_service,
+            systemd_rotate_service_unit(&binary_path, path.as_deref(), lock_screen, &env),
+        )?;
+        status!("{} Created {}", "✓".green(), rotate_service);
+        let rotate_timer = format!("{}/{}.timer", systemd_dir, ROTATE_UNIT);
+        fs::write(&rotate_timer, systemd_rotate_timer_unit(interval))?;
+        status!("{} Created {}", "✓".green(), rotate_timer);
+    } else {
+        // Installing without --set-every drops the rotation from an earlier install
+        systemd.remove_timer(ROTATE_UNIT)?;
+    }
+
+    if watch {
+        let watch_service = systemd_unit_path(&format!("{}.service", WATCH_UNIT))?;
+        fs::write(
+            &watch_service,
+            systemd_watch_service_unit(&binary_path, random, path.as_deref(), lock_screen, &env),
+        )?;
+        status!("{} Created {}", "✓".green(), watch_service);
+    } else {
+        systemd.remove_service(WATCH_UNIT)?;
+    }
+
+    systemd.daemon_reload();
+    systemd.start_timer(MAIN_UNIT);
+    if set_every.is_some() {
+        systemd.start_timer(ROTATE_UNIT);
+    }
+    if watch {
+        systemd.start_service(WATCH_UNIT);
+    }
+
+    status!();
+    status!("{}", "=== Timer Setup Complete ===".green());
+    if let Some(jitter) = &jitter {
+        status!("Random delay: up to {}", jitter.yellow());
+    }
+    if let Some(interval) = &set_every {
+        status!("Wallpaper rotation: every {}", interval.yellow());
+    }
+    if watch {
+        status!("Monitor changes: {}", "re-apply the wallpaper".yellow());
+    }
+    finish_install(&schedule_desc, random, path.as_deref(), lock_screen)?;
+    print_systemd_commands();
+
+    Ok(())
+}
+
+/// Say which session variables were written into the service
+fn report_saved_environment(env: &[(&str, String)]) {
+    if env.is_empty() {
+        status!(
+            "{} No display variables set; the timer relies on the session importing them",
+            "!".yellow()
+        );
+    } else {
+        let names: Vec<&str> = env.iter().map(|(key, _)| *key).collect();
+        status!(
+            "{} Saved session environment: {}",
+            "✓".green(),
+            names.join(", ")
+        );
+    }
+}
+
+/// Print the commands for checking on and removing the timer
+fn print_systemd_commands() {
+    status!();
+    status!("Useful commands:");
+    status!(
+        "  {} - Check timer status",
+        "systemctl --user status natgeo-wallpaper.timer".green()
+    );
+    status!(
+        "  {} - View logs",
+        "journalctl --user -u natgeo-wallpaper.service".green()
+    );
+    status!(
+        "  {} - Uninstall",
+        "natgeo-wallpapers install --uninstall".green()
+    );
+}
+
+/// Print the installed schedule, with last and next runs where systemd knows them
+fn install_status() -> Result<(), PhotoError> {
+    if cfg!(target_os = "macos") {
+        let plist = launchd_plist_path();
+        if std::path::Path::new(&plist).exists() {
+            status!("launchd agent: {}", plist.green());
+        } else {
+            status!("No schedule installed");
+        }
+        return Ok(());
+    }
+
+    let entry_path = autostart_path();
+    let autostart = std::path::Path::new(&entry_path).exists();
+    if autostart {
+        status!("Autostart entry: {}", entry_path.green());
+    }
+
+    let timer_path = systemd_unit_path("natgeo-wallpaper.timer")?;
+    if let Ok(contents) = fs::read_to_string(&timer_path) {
+        let schedule = parse_timer_unit(&contents)
+            .map_or_else(|| "unrecognized schedule".to_string(), |s| s.to_string());
+        status!("Timer: {}", timer_path);
+        status!("Schedule: {}", schedule.yellow());
+        let rotate_path = systemd_unit_path(&format!("{}.timer", ROTATE_UNIT))?;
+        if let Some(ScheduleType::Interval(interval)) = fs::read_to_string(&rotate_path)
+            .ok()
+            .and_then(|contents| parse_timer_unit(&contents))
+        {
+            status!("Wallpaper rotation: every {}", interval.yellow());
+        }
+        if std::path::Path::new(&systemd_unit_path(&format!("{}.service", WATCH_UNIT))?).exists() {
+            status!("Monitor changes: {}", "watched".yellow());
+        }
+        status!();
+        let timers = UserSystemd::live()?.systemctl(&[
+            "list-timers",
+            "natgeo-wallpaper.timer",
+            "natgeo-wallpaper-rotate.timer",
+            "--all",
+            "--no-pager",
+        ])?;
+        print!("{}", timers);
+        return Ok(());
+    }
+
+    if command_exists("crontab") {
+        if let Some(line) = read_crontab()?
+            .lines()
+            .find(|line| line.trim_end().ends_with(CRON_TAG))
+        {
+            status!("cron entry: {}", line.green());
+            return Ok(());
+        }
+    }
+
+    if !autostart {
+        status!("No schedule installed");
+    }
+    Ok(())
+}
+
+/// Our units under the user's systemd, with their files in `unit_dir`
+struct UserSystemd<'a> {
+    runner: &'a dyn CommandRunner,
+    unit_dir: std::path::PathBuf,
+}
+
+impl UserSystemd<'static> {
+    /// The live user manager, with units in `~/.config/systemd/user`
+    fn live() -> Result<Self, PhotoError> {
+        Ok(Self {
+            runner: &LiveCommands,
+            unit_dir: std::path::PathBuf::from(systemd_unit_path("")?),
+        })
+    }
+}
+
+impl UserSystemd<'_> {
+    /// Run `systemctl --user` with `args`
+    fn systemctl(&self, args: &[&str]) -> Result<String, PhotoError> {
+        let mut user_args = vec!["--user"];
+        user_args.extend_from_slice(args);
+        self.runner.run("systemctl", &user_args)
+    }
+
+    /// Have systemd pick up changed unit files
+    fn daemon_reload(&self) {
+        let _ = self.systemctl(&["daemon-reload"]);
+        status!("{} Reloaded systemd daemon", "✓".green());
+    }
+
+    /// Enable and start one of our timers
+    fn start_timer(&self, unit: &str) {
+        let timer = format!("{}.timer", unit);
+        if self.systemctl(&["enable", &timer]).is_ok() {
+            status!("{} Enabled {}", "✓".green(), timer);
+        }
+        if self.systemctl(&["start", &timer]).is_ok() {
+            status!("{} Started {}", "✓".green(), timer);
+        }
+    }
+
+    /// Stop and disable one of our timers and remove its unit files, if installed
+    fn remove_timer(&self, unit: &str) -> Result<(), PhotoError> {
+        let service_path = self.unit_dir.join(format!("{}.service", unit));
+        let timer_path = self.unit_dir.join(format!("{}.timer", unit));
+        if !service_path.exists() && !timer_path.exists() {
+            return Ok(());
+        }
+
+        let timer = format!("{}.timer", unit);
+        let _ = self.systemctl(&["stop", &timer]);
+        status!("{} Stopped {}", "✓".green(), timer);
+        let _ = self.systemctl(&["disable", &timer]);
+        status!("{} Disabled {}", "✓".green(), timer);
+
+        for path in [service_path, timer_path] {
+            if path.exists() {
+                fs::remove_file(&path)?;
+                status!("{} Removed {}", "✓".green(), path.display());
+            }
+        }
+        Ok(())
+    }
+
+    /// Enable and start one of our long-running services
+    fn start_service(&self, unit: &str) {
+        let service = format!("{}.service", unit);
+        if self.systemctl(&["enable", "--now", &service]).is_ok() {
+            status!("{} Enabled and started {}", "✓".green(), service);
+        }
+    }
+
+    /// Stop and disable one of our services and remove its unit file, if installed
+    fn remove_service(&self, unit: &str) -> Result<(), PhotoError> {
+        let service = format!("{}.service", unit);
+        let path = self.unit_dir.join(&service);
+        if !path.exists() {
+            return Ok(());
+        }
+        let _ = self.systemctl(&["disable", "--now", &service]);
+        status!("{} Stopped and disabled {}", "✓".green(), service);
+        fs::remove_file(&path)?;
+        status!("{} Removed {}", "✓".green(), path.display());
+        Ok(())
+    }
+
+    /// Uninstall the download timer, the rotation timer and the monitor watch
+    fn uninstall(&self) -> Result<(), PhotoError> {
+        status!("{}", "=== Uninstalling Systemd Timer ===".green());
+        status!();
+
+        self.remove_timer(MAIN_UNIT)?;
+        self.remove_timer(ROTATE_UNIT)?;
+        self.remove_service(WATCH_UNIT)?;
+        self.daemon_reload();
+
+        status!();
+        status!("{}", "=== Uninstall Complete ===".green());
+
+        Ok(())
+    }
+}
+
+/// Ask a yes/no question on stdin, defaulting to no
+fn confirm(prompt: &str) -> Result<bool, PhotoError> {
+    print!("{} [y/N]: ", prompt);
+    io::stdout().flush().ok();
+
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .map_err(PhotoError::File)?;
+
+    Ok(matches!(input.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Write the commented default config file
+fn config_init(force: bool) -> Result<(), PhotoError> {
+    let path = config_path();
+    if path.exists() && !force {
+        return Err(PhotoError::Config(format!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        )));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, default_config_file())?;
+    status!("{} Wrote {}", "✓".green(), path.display());
+    Ok(())
+}
+
+/// Print the config file merged over the defaults, as TOML
+fn config_show() -> Result<(), PhotoError> {
+    let path = config_path();
+    let source = if path.exists() {
+        path.display().to_string()
+    } else {
+        format!("{} (not found, showing defaults)", path.display())
+    };
+    let effective = toml::to_string(&config().with_defaults(data_dirs()?))
+        .map_err(|e| PhotoError::Config(e.to_string()))?;
+    println!("# {}", source);
+    println!();
+    print!("{}", effective);
+    Ok(())
+}
+
+/// Fully uninstall: systemd units, logs, state, caches, and optionally config and photos
+fn uninstall(options: UninstallOptions, yes: bool) -> Result<(), PhotoError> {
+    uninstall_schedule()?;
+    status!();
+
+    status!("{}", "=== Removing Data ===".green());
+    status!();
+
+    let mut plan = plan_uninstall(&UninstallPaths::from_defaults()?, options);
+
+    for (path, reason) in &plan.refused {
+        status!(
+            "{} Refusing to delete {}: {}",
+            "!".yellow(),
+            path.display(),
+            reason
+        );
+    }
+
+    if !plan.photo_dirs.is_empty() && !yes {
+        status!(
+            "{}",
+            "The following photo directories will be deleted:".yellow()
+        );
+        for dir in &plan.photo_dirs {
+            status!("  {}", dir.display());
+        }
+        if !confirm("Delete these directories and all photos in them?")? {
+            status!("{} Keeping photos", "!".yellow());
+            plan.photo_dirs.clear();
+        }
+    }
+
+    let removed = execute_uninstall(&plan)?;
+    if removed.is_empty() {
+        status!("{} Nothing else to remove", "✓".green());
+    }
+    for path in &removed {
+        status!("{} Removed {}", "✓".green(), path.display());
+    }
+
+    status!();
+    status!("{}", "=== Uninstall Complete ===".green());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_accepts_set_options() {
+        let cli = Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "run",
+            "--random",
+            "--lock-screen",
+            "--path",
+            "/tmp/photos",
+            "--attempts",
+            "5",
+        ])
+        .unwrap();
+
+        let Some(Commands::Run { set, attempts, .. }) = cli.command else {
+            panic!("expected the run command");
+        };
+        assert!(set.random);
+        assert!(set.lock_screen);
+        assert_eq!(set.path.as_deref(), Some("/tmp/photos"));
+        assert_eq!(attempts, 5);
+    }
+
+    /// Notifier that keeps what it was asked to show
+    #[derive(Default)]
+    struct CapturedNotifications(std::cell::RefCell<Vec<Notification>>);
+
+    impl Notifier for CapturedNotifications {
+        fn notify(&self, notification: &Notification) -> Result<(), PhotoError> {
+            self.0.borrow_mut().push(notification.clone());
+            Ok(())
+        }
+    }
+
+    impl CapturedNotifications {
+        fn summaries(&self) -> Vec<String> {
+            self.0.borrow().iter().map(|n| n.summary.clone()).collect()
+        }
+    }
+
+    #[test]
+    fn test_notify_downloaded_skips_existing_photos() {
+        let captured = CapturedNotifications::default();
+        let mut report = DownloadReport {
+            path: "/photos/2026-02-01/Fox.jpg".into(),
+            title: "Fox".to_string(),
+            url: "https://example.com/fox.jpg".to_string(),
+            skipped: true,
+        };
+        notify_downloaded(Some(&captured), &report);
+        assert!(captured.summaries().is_empty());
+
+        report.skipped = false;
+        notify_downloaded(Some(&captured), &report);
+        notify_downloaded(None, &report);
+        assert_eq!(captured.summaries(), ["New NatGeo photo: Fox"]);
+        assert_eq!(
+            captured.0.borrow()[0].icon.as_deref(),
+            Some(std::path::Path::new("/photos/2026-02-01/Fox.jpg"))
+        );
+    }
+
+    #[test]
+    fn test_notify_wallpaper_set_counts_distinct_photos() {
+        let assignment = |location: &str, photo: &str, result| AssignmentResult {
+            assignment: natgeo_wallpapers::WallpaperAssignment {
+                location: location.to_string(),
+                photo_path: photo.into(),
+                is_newest: false,
+                slot: 0,
             },
-            "5" => loop {
-                print!("Enter interval (e.g., 1h, 30m, 2h30m): ");
-                io::stdout().flush().ok();
+            result,
+        };
+        let report = WallpaperReport {
+            desktop: DesktopEnvironment::Gnome,
+            requested_mode: WallpaperMode::Monitors,
+            mode: WallpaperMode::Monitors,
+            sort: SortOrder::Date,
+            spread: SpreadStrategy::None,
+            brightness: None,
+            photos_found: 2,
+            monitors: Vec::new(),
+            monitor_count: 3,
+            virtual_desktop_count: 1,
+            warnings: Vec::new(),
+            results: vec![
+                assignment(
+                    "Monitor 1",
+                    "/p/Fox.jpg",
+                    Err(PhotoError::Wallpaper("busy".to_string())),
+                ),
+                assignment("Monitor 2", "/p/Owl.jpg", Ok(())),
+                assignment("Monitor 3", "/p/Owl.jpg", Ok(())),
+            ],
+            dry_run: false,
+        };
 
-                let mut interval_input = String::new();
-                io::stdin()
-                    .read_line(&mut interval_input)
-                    .map_err(PhotoError::File)?;
+        let captured = CapturedNotifications::default();
+        notify_wallpaper_set(Some(&captured), &report);
+        let shown = captured.0.borrow();
+        assert_eq!(shown.len(), 1);
+        assert_eq!(shown[0].summary, "Wallpaper changed");
+        assert_eq!(shown[0].body, "Owl");
+    }
 
-                let interval = interval_input.trim();
-                if is_valid_interval(interval) {
-                    return Ok(ScheduleType::Interval(interval.to_string()));
-                }
-                println!(
-                    "{} Invalid format. Use h for hours, m for minutes (e.g., 1h, 30m, 2h30m)",
-                    "✗".red()
-                );
+    #[test]
+    fn test_notify_run_failure_is_critical() {
+        let captured = CapturedNotifications::default();
+        notify_run_failure(Some(&captured), &RunOutcome::Complete);
+        assert!(captured.summaries().is_empty());
+
+        notify_run_failure(
+            Some(&captured),
+            &RunOutcome::Failed {
+                download: PhotoError::NoPhotos("layout changed".to_string()),
+                set: PhotoError::Wallpaper("no display".to_string()),
             },
-            "6" => {
-                println!("{} Cancelled", "!".yellow());
-                return Err(PhotoError::Command("Cancelled by user".to_string()));
-            }
-            _ => {
-                println!("{} Invalid choice, please enter 1-6", "✗".red());
+        );
+        assert_eq!(
+            captured.summaries(),
+            [
+                "NatGeo wallpaper: Download failed",
+                "NatGeo wallpaper: Setting the wallpaper failed",
+            ]
+        );
+        assert!(captured
+            .0
+            .borrow()
+            .iter()
+            .all(|n| n.urgency == natgeo_wallpapers::Urgency::Critical));
+    }
+
+    #[test]
+    fn test_color_flag() {
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "list"]).unwrap();
+        assert_eq!(cli.color, ColorWhen::Auto);
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "list", "--color", "never"]).unwrap();
+        assert_eq!(cli.color, ColorWhen::Never);
+        assert!(
+            Cli::try_parse_from(["natgeo-wallpapers", "--color", "sometimes", "list"]).is_err()
+        );
+
+        let no_color = Some(std::ffi::OsStr::new("1"));
+        assert!(ColorWhen::Auto.enabled(None, true));
+        assert!(!ColorWhen::Auto.enabled(None, false));
+        assert!(!ColorWhen::Auto.enabled(no_color, true));
+        // An empty NO_COLOR doesn't count
+        assert!(ColorWhen::Auto.enabled(Some(std::ffi::OsStr::new("")), true));
+        // An explicit choice wins over NO_COLOR
+        assert!(ColorWhen::Always.enabled(no_color, false));
+        assert!(!ColorWhen::Never.enabled(None, true));
+    }
+
+    #[test]
+    fn test_hook_flags() {
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "list"]).unwrap();
+        assert_eq!(cli.hooks_config(), Config::default());
+
+        let cli = Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "download",
+            "--hook-post-download",
+            "notify-photo.sh",
+            "--hook-strict",
+        ])
+        .unwrap();
+        let hooks = cli.hooks_config().hooks;
+        assert_eq!(hooks.post_download.as_deref(), Some("notify-photo.sh"));
+        assert_eq!(hooks.post_set, None);
+        assert_eq!(hooks.strict, Some(true));
+    }
+
+    #[test]
+    fn test_verbosity_flags() {
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "-vv", "list"]).unwrap();
+        assert_eq!((cli.verbose, cli.quiet), (2, false));
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "list", "--quiet"]).unwrap();
+        assert_eq!((cli.verbose, cli.quiet), (0, true));
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "-v", "-q", "list"]).is_err());
+
+        assert_eq!(terminal_level(0, false), LevelFilter::ERROR);
+        assert_eq!(terminal_level(1, false), LevelFilter::INFO);
+        assert_eq!(terminal_level(2, false), LevelFilter::DEBUG);
+        assert_eq!(terminal_level(5, false), LevelFilter::TRACE);
+        assert_eq!(terminal_level(0, true), LevelFilter::OFF);
+    }
+
+    #[test]
+    fn test_set_backend_options() {
+        let cli = Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "set",
+            "--transition",
+            "fade",
+            "--transition-ms",
+            "500",
+        ])
+        .unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        assert_eq!(
+            set.backend_options().transition,
+            TransitionOptions {
+                kind: Some("fade".to_string()),
+                duration: Some(Duration::from_millis(500)),
             }
-        }
+        );
+
+        assert!(
+            Cli::try_parse_from(["natgeo-wallpapers", "set", "--transition", "sparkle"]).is_err()
+        );
+
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "set", "--fit", "zoom"]).unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        assert_eq!(set.backend_options().fit, Some(FitMode::Zoom));
+
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "set"]).unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        assert_eq!(set.backend_options().fit, None);
     }
-}
 
-/// Validate time format HH:MM
-fn is_valid_time(time: &str) -> bool {
-    if time.len() != 5 {
-        return false;
+    #[test]
+    fn test_set_wallpaper_options() {
+        let cli = Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "set",
+            "--resize",
+            "crop",
+            "--min-width",
+            "1920",
+        ])
+        .unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        let options = set.wallpaper_options(Sort::Random);
+        assert_eq!(options.sort, SortOrder::Random);
+        assert_eq!(options.resize, Some(ResizeMode::Crop));
+        assert_eq!(options.dimensions.min_width, Some(1920));
+
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "set"]).unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        assert_eq!(
+            set.wallpaper_options(Sort::Date),
+            WallpaperOptions::default()
+        );
+
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "set", "--span"]).unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        assert!(set.wallpaper_options(Sort::Date).span);
+        assert!(
+            Cli::try_parse_from(["natgeo-wallpapers", "set", "--span", "--resize", "fit"]).is_err()
+        );
+
+        let cli = Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "set",
+            "--plasma-slideshow",
+            "--interval",
+            "1h",
+        ])
+        .unwrap();
+        let Some(Commands::Set {
+            plasma_slideshow,
+            interval,
+            ..
+        }) = cli.command
+        else {
+            panic!("expected the set command");
+        };
+        assert!(plasma_slideshow);
+        assert_eq!(interval, "1h");
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "set", "--interval", "1h"]).is_err());
+        assert!(Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "set",
+            "--plasma-slideshow",
+            "--interval",
+            "soon"
+        ])
+        .is_err());
+
+        // `--cache` is enough for clean without a retention policy
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "clean", "--cache"]).is_ok());
+    }
+
+    #[test]
+    fn test_launchd_program_arguments() {
+        assert_eq!(
+            launchd_program_arguments("/bin/ngw", true, Some("/Users/ada/My Photos"), false, None),
+            [
+                "/bin/ngw",
+                "run",
+                "--random",
+                "--path",
+                "/Users/ada/My Photos"
+            ]
+        );
+
+        // Cleanup is chained through the shell, with every argument quoted
+        assert_eq!(
+            launchd_program_arguments("/bin/ngw", false, Some("/Users/ada/it's"), true, Some(30)),
+            [
+                "/bin/sh",
+                "-c",
+                "'/bin/ngw' 'run' '--path' '/Users/ada/it'\\''s' '--lock-screen' \
+                 && '/bin/ngw' clean --keep-days 30",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_systemd_service_unit_sets_session_environment() {
+        let env = [
+            ("DISPLAY", ":0".to_string()),
+            (
+                "DBUS_SESSION_BUS_ADDRESS",
+                "unix:path=/run/user/1000/bus".to_string(),
+            ),
+            ("WAYLAND_DISPLAY", "wayland-%1 \"x\"".to_string()),
+        ];
+        let unit = systemd_service_unit("/usr/bin/ngw", false, None, false, Some(30), &env);
+
+        assert!(
+            unit.contains("After=network-online.target network.target graphical-session.target\n")
+        );
+        assert!(unit.contains("Environment=\"DISPLAY=:0\"\n"));
+        assert!(unit
+            .contains("Environment=\"DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/1000/bus\"\n"));
+        assert!(unit.contains("Environment=\"WAYLAND_DISPLAY=wayland-%%1 \\\"x\\\"\"\n"));
+        assert!(unit.contains("Slice=background.slice\n"));
+        assert!(unit.contains("RestartSec=5min\n"));
+
+        // Environment comes before the commands, and cleanup runs after them
+        let exec = unit
+            .find("ExecStart=/usr/bin/ngw --color never run\n")
+            .unwrap();
+        assert!(unit.rfind("Environment=").unwrap() < exec);
+        assert!(unit[exec..]
+            .contains("ExecStartPost=/usr/bin/ngw --color never clean --keep-days 30\n"));
+
+        let bare = systemd_service_unit("/usr/bin/ngw", false, None, false, None, &[]);
+        assert!(!bare.contains("Environment="));
+        assert!(!bare.contains("ExecStartPost="));
     }
-    let parts: Vec<&str> = time.split(':').collect();
-    if parts.len() != 2 {
-        return false;
+
+    #[test]
+    fn test_systemd_timer_unit() {
+        let daily = systemd_timer_unit(&ScheduleType::DailyTime("02:00".to_string()), None);
+        assert!(
+            daily.contains("[Timer]\nOnCalendar=*-*-* 02:00:00\nOnBootSec=2min\nPersistent=true\n")
+        );
+
+        let interval = systemd_timer_unit(&ScheduleType::Interval("30m".to_string()), None);
+        assert!(interval.contains("[Timer]\nOnBootSec=1min\nOnUnitActiveSec=30m\n"));
+        assert!(!daily.contains("RandomizedDelaySec") && !interval.contains("RandomizedDelaySec"));
     }
-    let hour: Result<u8, _> = parts[0].parse();
-    let minute: Result<u8, _> = parts[1].parse();
 
-    matches!((hour, minute), (Ok(h), Ok(m)) if h < 24 && m < 60)
-}
+    #[test]
+    fn test_systemd_timer_unit_with_jitter() {
+        let daily = systemd_timer_unit(&ScheduleType::DailyTime("02:00".to_string()), Some("15m"));
+        assert!(daily.contains(
+            "[Timer]\nOnCalendar=*-*-* 02:00:00\nOnBootSec=2min\nRandomizedDelaySec=15m\nPersistent=true\n"
+        ));
 
-/// Validate interval format (e.g., "1h", "30m", "2h30m")
-fn is_valid_interval(interval: &str) -> bool {
-    if interval.is_empty() {
-        return false;
+        let interval = systemd_timer_unit(&ScheduleType::Interval("6h".to_string()), Some("1h"));
+        assert!(interval.contains("OnUnitActiveSec=6h\nRandomizedDelaySec=1h\n"));
+        assert_eq!(
+            parse_timer_unit(&interval),
+            Some(ScheduleType::Interval("6h".to_string()))
+        );
     }
-    // Must contain at least one h or m
-    if !interval.contains('h') && !interval.contains('m') {
-        return false;
+
+    #[test]
+    fn test_systemd_rotate_units() {
+        let env = [("DISPLAY", ":0".to_string())];
+        let service = systemd_rotate_service_unit("/usr/bin/ngw", None, false, &env);
+        assert!(service.contains("Environment=\"DISPLAY=:0\"\n"));
+        assert!(service.ends_with("ExecStart=/usr/bin/ngw --color never set --random\n"));
+        assert!(!service.contains(" run"));
+        assert!(!service.contains("network-online.target"));
+        assert!(!service.contains("ExecStartPost="));
+
+        let service =
+            systemd_rotate_service_unit("/usr/bin/ngw", Some("/home/me/100% nature"), true, &[]);
+        assert!(service.ends_with(
+            "ExecStart=/usr/bin/ngw --color never set --random --path \"/home/me/100%% nature\" --lock-screen\n"
+        ));
+
+        let timer = systemd_rotate_timer_unit("1h");
+        assert!(timer.contains("[Timer]\nOnBootSec=1min\nOnUnitActiveSec=1h\n"));
+        assert!(!timer.contains("RandomizedDelaySec"));
     }
-    // Check format: optional number+h followed by optional number+m
-    let s = interval.to_lowercase();
-    let mut has_value = false;
 
-    for c in s.chars() {
-        if c.is_ascii_digit() {
-            has_value = true;
-        } else if c == 'h' || c == 'm' {
-            if !has_value {
-                return false;
+    #[test]
+    fn test_install_jitter_and_set_every_flags() {
+        let cli = Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "install",
+            "--time",
+            "02:00",
+            "--jitter",
+            "15m",
+            "--set-every",
+            "1h",
+        ])
+        .unwrap();
+        match cli.command {
+            Some(Commands::Install {
+                jitter, set_every, ..
+            }) => {
+                assert_eq!(jitter.as_deref(), Some("15m"));
+                assert_eq!(set_every.as_deref(), Some("1h"));
             }
-            has_value = false;
-        } else {
-            return false;
+            _ => panic!("expected install"),
         }
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "install", "--jitter", "soon"]).is_err());
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "install", "--set-every", "5"]).is_err());
     }
-    true
-}
 
-/// Parse time or interval from command line argument
-fn parse_schedule(time_arg: &str) -> Result<ScheduleType, PhotoError> {
-    if is_valid_time(time_arg) {
-        Ok(ScheduleType::DailyTime(time_arg.to_string()))
-    } else if is_valid_interval(time_arg) {
-        Ok(ScheduleType::Interval(time_arg.to_string()))
-    } else {
-        Err(PhotoError::Command(format!(
-            "Invalid time/interval format: {}. Use HH:MM for daily time or intervals like 1h, 30m",
-            time_arg
-        )))
+    #[test]
+    fn test_offline_ok_defaults() {
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "run"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Run {
+                offline_ok: true,
+                ..
+            })
+        ));
+
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "run", "--no-offline-ok"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Run {
+                offline_ok: false,
+                ..
+            })
+        ));
+
+        // Falling back to existing photos needs the set step, so only `run` has it
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "download", "--offline-ok"]).is_err());
     }
-}
 
-/// Install systemd timer for automatic updates
-#[allow(clippy::too_many_lines, clippy::needless_pass_by_value)]
-fn install_systemd_timer(
-    time: Option<String>,
-    random: bool,
-    path: Option<String>,
-    lock_screen: bool,
-) -> Result<(), PhotoError> {
-    println!("{}", "=== Systemd Timer Setup ===".green());
-    println!();
+    #[test]
+    fn test_run_rejects_zero_attempts() {
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "run", "--attempts", "0"]).is_err());
+    }
 
-    // Check if systemctl exists
-    if Command::new("which")
-        .arg("systemctl")
-        .output()
-        .map(|o| !o.status.success())
-        .unwrap_or(true)
-    {
-        println!("{} systemctl not found", "✗".red());
-        println!("This feature requires systemd");
-        return Err(PhotoError::Command("systemctl not found".to_string()));
+    #[test]
+    fn test_service_exec_start_uses_run() {
+        assert_eq!(
+            service_exec_start("/usr/bin/natgeo-wallpapers", false, None, false),
+            "ExecStart=/usr/bin/natgeo-wallpapers --color never run"
+        );
+        assert_eq!(
+            service_exec_start(
+                "/usr/bin/natgeo-wallpapers",
+                true,
+                Some("/home/me/My \"Best\" 100%"),
+                true
+            ),
+            "ExecStart=/usr/bin/natgeo-wallpapers --color never run --random \
+             --path \"/home/me/My \\\"Best\\\" 100%%\" --lock-screen"
+        );
     }
 
-    // Get schedule (from argument or prompt)
-    let schedule = match time {
-        Some(t) => parse_schedule(&t)?,
-        None => prompt_for_schedule()?,
-    };
+    #[test]
+    fn test_parse_timer_unit_daily() {
+        let schedule = ScheduleType::DailyTime("07:30".to_string());
+        assert_eq!(
+            parse_timer_unit(&systemd_timer_unit(&schedule, None)),
+            Some(schedule)
+        );
+        assert_eq!(
+            parse_timer_unit("[Timer]\nOnCalendar=*-*-* 02:00\n"),
+            Some(ScheduleType::DailyTime("02:00".to_string()))
+        );
+    }
 
-    let binary_path = get_binary_path()?;
-    let home =
-        std::env::var("HOME").map_err(|_| PhotoError::Command("HOME not set".to_string()))?;
-    let systemd_dir = format!("{}/.config/systemd/user", home);
+    #[test]
+    fn test_parse_timer_unit_interval() {
+        let schedule = ScheduleType::Interval("6h".to_string());
+        assert_eq!(
+            parse_timer_unit(&systemd_timer_unit(&schedule, None)),
+            Some(schedule)
+        );
+    }
 
-    // Create systemd directory
-    fs::create_dir_all(&systemd_dir)?;
+    #[test]
+    fn test_parse_timer_unit_rejects_unknown_schedules() {
+        assert_eq!(parse_timer_unit("[Timer]\nOnCalendar=weekly\n"), None);
+        assert_eq!(parse_timer_unit("[Timer]\nOnUnitActiveSec=soon\n"), None);
+        assert_eq!(parse_timer_unit(""), None);
+    }
 
-    // Build the set command with options
-    let mut set_args = String::from("set");
-    if random {
-        set_args.push_str(" --random");
+    #[test]
+    fn test_install_force_and_status_flags() {
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "install", "--force"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Install {
+                force: true,
+                status: false,
+                ..
+            })
+        ));
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "install", "--status"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Install { status: true, .. })
+        ));
+        assert!(
+            Cli::try_parse_from(["natgeo-wallpapers", "install", "--status", "--uninstall"])
+                .is_err()
+        );
     }
-    if let Some(ref p) = path {
-        use std::fmt::Write;
-        let _ = write!(set_args, " --path '{}'", p);
+
+    #[test]
+    fn test_install_autostart_flag() {
+        let cli = Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "install",
+            "--autostart",
+            "--random",
+            "--path",
+            "/tmp/photos",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Install {
+                autostart: true,
+                random: true,
+                ..
+            })
+        ));
+        for conflicting in [
+            ["--time", "02:00"],
+            ["--jitter", "15m"],
+            ["--keep-days", "30"],
+        ] {
+            let mut args = vec!["natgeo-wallpapers", "install", "--autostart"];
+            args.extend(conflicting);
+            assert!(Cli::try_parse_from(args).is_err());
+        }
     }
-    if lock_screen {
-        set_args.push_str(" --lock-screen");
+
+    #[test]
+    fn test_set_targets() {
+        let cli = Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "set",
+            "--monitor",
+            "2",
+            "--monitor",
+            "3",
+            "--desktop",
+            "1",
+        ])
+        .unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        // Counted from 1 on the command line, from 0 inside
+        assert_eq!(
+            set.wallpaper_options(Sort::Date).targets,
+            TargetFilter {
+                monitors: vec![1, 2],
+                monitor_names: Vec::new(),
+                desktops: vec![0],
+            }
+        );
+
+        let cli = Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "set",
+            "--monitor",
+            "DP-1",
+            "--monitor",
+            "1",
+            "--monitor",
+            "HDMI-A-1",
+        ])
+        .unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        let targets = set.wallpaper_options(Sort::Date).targets;
+        assert_eq!(targets.monitors, [0]);
+        assert_eq!(targets.monitor_names, ["DP-1", "HDMI-A-1"]);
+
+        for args in [
+            &["--monitor", "0"][..],
+            &["--monitor", ""],
+            &["--desktop", "-1"],
+            &["--monitor", "1", "--span"],
+            &["--photo", "a.jpg", "--path", "photos"],
+            &["--photo", "a.jpg", "--random"],
+        ] {
+            let cli = Cli::try_parse_from(["natgeo-wallpapers", "set"].iter().chain(args));
+            assert!(cli.is_err(), "{args:?}");
+        }
     }
 
-    // Create service file with the configured options
-    let service_content = format!(
-        r"[Unit]
-Description=Download and set National Geographic Photo of the Day as wallpaper
-After=network-online.target network.target
-Wants=network-online.target
+    #[test]
+    fn test_set_photo_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let photo = dir.path().join("a.jpg");
+        fs::write(&photo, b"").unwrap();
+        let photo = photo.to_str().unwrap();
 
-[Service]
-Type=oneshot
-ExecStart=/bin/sh -c 'for i in 1 2 3; do {binary} download && {binary} {set_args} && exit 0 || sleep 60; done; exit 1'
-",
-        binary = binary_path,
-        set_args = set_args
-    );
-    let service_path = format!("{}/natgeo-wallpaper.service", systemd_dir);
-    fs::write(&service_path, &service_content)?;
-    println!("{} Created {}", "✓".green(), service_path);
+        let parse = |args: &[&str]| {
+            let cli = Cli::try_parse_from(["natgeo-wallpapers", "set"].iter().chain(args)).unwrap();
+            let Some(Commands::Set { set, .. }) = cli.command else {
+                panic!("expected the set command");
+            };
+            set
+        };
+        assert_eq!(
+            parse(&["--photo", photo])
+                .photo_source()
+                .unwrap()
+                .as_deref(),
+            Some(photo)
+        );
+        assert_eq!(
+            parse(&["--path", "~/photos"])
+                .photo_source()
+                .unwrap()
+                .as_deref(),
+            Some("~/photos")
+        );
+        assert_eq!(parse(&[]).photo_source().unwrap(), None);
+        // A directory is not a photo
+        let dir = dir.path().to_str().unwrap();
+        assert!(parse(&["--photo", dir]).photo_source().is_err());
+    }
 
-    // Create timer file based on schedule type
-    let (timer_content, schedule_desc) = match &schedule {
-        ScheduleType::DailyTime(time) => {
-            let content = format!(
-                r"[Unit]
-Description=National Geographic Photo of the Day wallpaper update
+    #[test]
+    fn test_systemd_watch_service_unit() {
+        let env = [("WAYLAND_DISPLAY", "wayland-0".to_string())];
+        let service = systemd_watch_service_unit("/usr/bin/ngw", true, None, false, &env);
+        assert!(service.contains("Type=simple\nRestart=on-failure\n"));
+        assert!(service.contains("PartOf=graphical-session.target\n"));
+        assert!(service.contains("Environment=\"WAYLAND_DISPLAY=wayland-0\"\n"));
+        assert!(service.contains("ExecStart=/usr/bin/ngw --color never watch --random\n"));
+        assert!(service.ends_with("[Install]\nWantedBy=graphical-session.target\n"));
 
-[Timer]
-OnCalendar=*-*-* {}:00
-OnBootSec=2min
-Persistent=true
+        let service = systemd_watch_service_unit(
+            "/usr/bin/ngw",
+            false,
+            Some("/home/me/100% nature"),
+            true,
+            &[],
+        );
+        assert!(service.contains(
+            "ExecStart=/usr/bin/ngw --color never watch --path \"/home/me/100%% nature\" --lock-screen\n"
+        ));
+    }
 
-[Install]
-WantedBy=timers.target
-",
-                time
+    #[test]
+    fn test_watch_flags() {
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "watch", "--random", "--mode", "both"])
+            .unwrap();
+        let Some(Commands::Watch { set }) = cli.command else {
+            panic!("expected the watch command");
+        };
+        assert!(set.random);
+
+        let cli =
+            Cli::try_parse_from(["natgeo-wallpapers", "install", "--time", "02:00", "--watch"])
+                .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Install { watch: true, .. })
+        ));
+        for conflict in ["--autostart", "--uninstall", "--status"] {
+            assert!(
+                Cli::try_parse_from(["natgeo-wallpapers", "install", "--watch", conflict]).is_err()
             );
-            (content, format!("{} daily", time))
         }
-        ScheduleType::Interval(interval) => {
-            let content = format!(
-                r"[Unit]
-Description=National Geographic Photo of the Day wallpaper update
+    }
 
-[Timer]
-OnBootSec=1min
-OnUnitActiveSec={}
-Persistent=true
+    #[test]
+    fn test_daemon_flags() {
+        let cli = Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "daemon",
+            "--interval",
+            "20m",
+            "--random",
+        ])
+        .unwrap();
+        let Some(Commands::Daemon { set, interval }) = cli.command else {
+            panic!("expected the daemon command");
+        };
+        assert!(set.random);
+        assert_eq!(interval, "20m");
 
-[Install]
-WantedBy=timers.target
-",
-                interval
-            );
-            (content, format!("every {}", interval))
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "daemon"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Daemon { interval, .. }) if interval == "30m"
+        ));
+        assert!(
+            Cli::try_parse_from(["natgeo-wallpapers", "daemon", "--interval", "often"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_set_previous_and_history_flags() {
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "set", "--previous", "--lock-screen"])
+            .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Set { previous: true, .. })
+        ));
+        for conflict in [
+            &["--random"][..],
+            &["--photo", "a.jpg"],
+            &["--mode", "both"],
+            &["--monitor", "1"],
+            &["--plasma-slideshow"],
+        ] {
+            let args = ["natgeo-wallpapers", "set", "--previous"]
+                .iter()
+                .chain(conflict);
+            assert!(Cli::try_parse_from(args).is_err(), "{:?}", conflict);
         }
-    };
 
-    let timer_path = format!("{}/natgeo-wallpaper.timer", systemd_dir);
-    fs::write(&timer_path, timer_content)?;
-    println!("{} Created {}", "✓".green(), timer_path);
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "history", "--limit", "5"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::History { limit: Some(5) })
+        ));
+    }
 
-    // Reload systemd
-    let _ = Command::new("systemctl")
-        .args(["--user", "daemon-reload"])
-        .output();
-    println!("{} Reloaded systemd daemon", "✓".green());
+    #[test]
+    fn test_favorite_and_exclude_flags() {
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "favorite", "current"]).unwrap();
+        let Some(Commands::Favorite { list }) = cli.command else {
+            panic!("expected the favorite command");
+        };
+        assert_eq!(list.photo.as_deref(), Some("current"));
+        assert!(!list.remove);
 
-    // Enable timer
-    let enable_result = Command::new("systemctl")
-        .args(["--user", "enable", "natgeo-wallpaper.timer"])
-        .output();
+        let cli =
+            Cli::try_parse_from(["natgeo-wallpapers", "exclude", "--remove", "a.jpg"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Exclude { list }) if list.remove && list.photo.as_deref() == Some("a.jpg")
+        ));
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "favorite", "--list"]).is_ok());
+        // A photo is needed unless listing
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "exclude"]).is_err());
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "favorite", "--list", "a.jpg"]).is_err());
 
-    if enable_result.map(|o| o.status.success()).unwrap_or(false) {
-        println!("{} Enabled timer", "✓".green());
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "set", "--favorites-only", "--random"])
+            .unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        assert!(set.wallpaper_options(Sort::Random).favorites_only);
+        assert!(Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "set",
+            "--favorites-only",
+            "--photo",
+            "a.jpg"
+        ])
+        .is_err());
     }
 
-    // Start timer
-    let start_result = Command::new("systemctl")
-        .args(["--user", "start", "natgeo-wallpaper.timer"])
-        .output();
-
-    if start_result.map(|o| o.status.success()).unwrap_or(false) {
-        println!("{} Started timer", "✓".green());
+    #[test]
+    fn test_from_collection_flags() {
+        let cli =
+            Cli::try_parse_from(["natgeo-wallpapers", "set", "--from-collection", "best-oct"])
+                .unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        assert_eq!(set.from_collection.as_deref(), Some("best-oct"));
+        for conflict in [["--path", "~/photos"], ["--photo", "a.jpg"]] {
+            let args = ["natgeo-wallpapers", "set", "--from-collection", "best-oct"]
+                .iter()
+                .chain(&conflict);
+            assert!(Cli::try_parse_from(args).is_err());
+        }
+        assert!(matches!(
+            Cli::try_parse_from(["natgeo-wallpapers", "collections"])
+                .unwrap()
+                .command,
+            Some(Commands::Collections { names: false })
+        ));
     }
 
-    println!();
-    println!("{}", "=== Timer Setup Complete ===".green());
-    println!();
-    println!("Schedule: {}", schedule_desc.yellow());
-    if random {
-        println!("Random selection: {}", "enabled".green());
+    #[test]
+    fn test_set_pick_flag() {
+        let cli =
+            Cli::try_parse_from(["natgeo-wallpapers", "set", "--pick", "--monitor", "2"]).unwrap();
+        let Some(Commands::Set { set, pick, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        assert!(pick);
+        assert_eq!(set.monitors.len(), 1);
+        assert!(Cli::try_parse_from([
+            "natgeo-wallpapers",
+            "set",
+            "--pick",
+            "--from-collection",
+            "x"
+        ])
+        .is_ok());
+        for conflict in ["--random", "--previous", "--favorites-only"] {
+            assert!(
+                Cli::try_parse_from(["natgeo-wallpapers", "set", "--pick", conflict]).is_err(),
+                "{}",
+                conflict
+            );
+        }
+        assert!(
+            Cli::try_parse_from(["natgeo-wallpapers", "set", "--pick", "--photo", "a.jpg"])
+                .is_err()
+        );
     }
-    if let Some(ref p) = path {
-        println!("Photo path: {}", p.green());
+
+    #[test]
+    fn test_set_dry_run_flag() {
+        let cli =
+            Cli::try_parse_from(["natgeo-wallpapers", "set", "--dry-run", "--random"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Set { dry_run: true, .. })
+        ));
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "set", "--dry-run", "--pick"]).is_ok());
+        for conflict in ["--previous", "--plasma-slideshow"] {
+            assert!(
+                Cli::try_parse_from(["natgeo-wallpapers", "set", "--dry-run", conflict]).is_err(),
+                "{}",
+                conflict
+            );
+        }
     }
-    if lock_screen {
-        println!("Lock screen: {}", "enabled".green());
+
+    fn completions(shell: Shell) -> String {
+        let mut script = Vec::new();
+        write_completions(shell, &mut script).unwrap();
+        String::from_utf8(script).unwrap()
     }
-    println!();
 
-    // Download and set wallpaper now
-    println!(
-        "{}",
-        "Downloading today's photo and setting wallpaper...".yellow()
-    );
-    println!();
+    #[test]
+    fn test_completions_for_each_shell() {
+        for (shell, marker) in [
+            (Shell::Bash, "complete -F _natgeo-wallpapers"),
+            (Shell::Zsh, "#compdef natgeo-wallpapers"),
+            (Shell::Fish, "complete -c natgeo-wallpapers"),
+            (Shell::PowerShell, "Register-ArgumentCompleter"),
+        ] {
+            let script = completions(shell);
+            assert!(script.contains(marker), "{shell}");
+            for subcommand in ["download-collection", "set", "collections", "completions"] {
+                assert!(script.contains(subcommand), "{shell}: {subcommand}");
+            }
+        }
 
-    download()?;
-    println!();
-    set_wallpapers_with_options(WallpaperMode::Monitors, path.clone(), random)?;
-    if lock_screen {
-        set_lock_screen_wallpaper()?;
+        // bash itself checks the syntax, where it is installed
+        let bash = Command::new("bash")
+            .arg("-n")
+            .stdin(std::process::Stdio::piped())
+            .spawn();
+        if let Ok(mut bash) = bash {
+            if let Some(mut stdin) = bash.stdin.take() {
+                stdin
+                    .write_all(completions(Shell::Bash).as_bytes())
+                    .unwrap();
+            }
+            assert!(bash.wait().unwrap().success());
+        }
     }
 
-    println!();
-    println!("Useful commands:");
-    println!(
-        "  {} - Check timer status",
-        "systemctl --user status natgeo-wallpaper.timer".green()
-    );
-    println!(
-        "  {} - View logs",
-        "journalctl --user -u natgeo-wallpaper.service".green()
-    );
-    println!(
-        "  {} - Uninstall",
-        "natgeo-wallpapers install --uninstall".green()
-    );
-
-    Ok(())
-}
+    #[test]
+    fn test_completions_offer_collection_names() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = completions(shell);
+            assert!(script.contains(COLLECTION_NAMES_COMMAND), "{shell}");
+        }
+        let bash = completions(Shell::Bash);
+        assert!(bash.contains(
+            "COMPREPLY=($(compgen -W \"$(natgeo-wallpapers collections --names 2>/dev/null)\" -- \"${cur}\"))"
+        ));
+        // Paths still complete as files
+        assert!(bash.contains("COMPREPLY=($(compgen -f \"${cur}\"))"));
+        assert!(completions(Shell::Zsh).contains(":PATH:_files"));
+        assert!(!completions(Shell::PowerShell).contains(COLLECTION_NAMES_COMMAND));
 
-/// Uninstall systemd timer
-fn uninstall_systemd_timer() -> Result<(), PhotoError> {
-    println!("{}", "=== Uninstalling Systemd Timer ===".green());
-    println!();
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "completions", "zsh"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Completions { shell: Shell::Zsh })
+        ));
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "completions", "tcsh"]).is_err());
+    }
 
-    let home =
-        std::env::var("HOME").map_err(|_| PhotoError::Command("HOME not set".to_string()))?;
-    let systemd_dir = format!("{}/.config/systemd/user", home);
+    #[test]
+    fn test_doctor_command() {
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "doctor", "--json"]).unwrap();
+        assert!(cli.json);
+        assert!(matches!(cli.command, Some(Commands::Doctor)));
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "doctor", "extra"]).is_err());
+    }
 
-    // Stop timer
-    let _ = Command::new("systemctl")
-        .args(["--user", "stop", "natgeo-wallpaper.timer"])
-        .output();
-    println!("{} Stopped timer", "✓".green());
+    #[test]
+    fn test_set_backend_overrides_detection() {
+        let cli =
+            Cli::try_parse_from(["natgeo-wallpapers", "set", "--backend", "plasma-apply"]).unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        assert_eq!(
+            set.backend_options().backend,
+            Some(DesktopEnvironment::PlasmaFallback)
+        );
 
-    // Disable timer
-    let _ = Command::new("systemctl")
-        .args(["--user", "disable", "natgeo-wallpaper.timer"])
-        .output();
-    println!("{} Disabled timer", "✓".green());
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "run", "--backend", "gnome"]).unwrap();
+        let Some(Commands::Run { set, .. }) = cli.command else {
+            panic!("expected the run command");
+        };
+        assert_eq!(
+            set.backend_options().backend,
+            Some(DesktopEnvironment::Gnome)
+        );
 
-    // Remove files
-    let service_path = format!("{}/natgeo-wallpaper.service", systemd_dir);
-    let timer_path = format!("{}/natgeo-wallpaper.timer", systemd_dir);
+        let cli = Cli::try_parse_from(["natgeo-wallpapers", "set"]).unwrap();
+        let Some(Commands::Set { set, .. }) = cli.command else {
+            panic!("expected the set command");
+        };
+        assert_eq!(set.backend_options().backend, None);
+        assert!(Cli::try_parse_from(["natgeo-wallpapers", "set", "--backend", "kde"]).is_err());
+    }
 
-    if std::path::Path::new(&service_path).exists() {
-        fs::remove_file(&service_path)?;
-        println!("{} Removed {}", "✓".green(), service_path);
+    /// Runner that records each command, failing for `failing` programs
+    #[derive(Default)]
+    struct RecordedCommands {
+        calls: std::cell::RefCell<Vec<String>>,
+        failing: Vec<&'static str>,
     }
 
-    if std::path::Path::new(&timer_path).exists() {
-        fs::remove_file(&timer_path)?;
-        println!("{} Removed {}", "✓".green(), timer_path);
+    impl CommandRunner for RecordedCommands {
+        fn run(&self, program: &str, args: &[&str]) -> Result<String, PhotoError> {
+            self.calls
+                .borrow_mut()
+                .push(format!("{} {}", program, args.join(" ")));
+            if self.failing.contains(&program) {
+                return Err(PhotoError::Wallpaper(format!("{program} failed")));
+            }
+            Ok(String::new())
+        }
     }
 
-    // Reload systemd
-    let _ = Command::new("systemctl")
-        .args(["--user", "daemon-reload"])
-        .output();
-    println!("{} Reloaded systemd daemon", "✓".green());
+    #[test]
+    fn test_user_systemd_starts_units() {
+        let runner = RecordedCommands::default();
+        let systemd = UserSystemd {
+            runner: &runner,
+            unit_dir: std::path::PathBuf::from("/nonexistent"),
+        };
+        systemd.daemon_reload();
+        systemd.start_timer(MAIN_UNIT);
+        systemd.start_service(WATCH_UNIT);
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec![
+                "systemctl --user daemon-reload",
+                "systemctl --user enable natgeo-wallpaper.timer",
+                "systemctl --user start natgeo-wallpaper.timer",
+                "systemctl --user enable --now natgeo-wallpaper-watch.service",
+            ]
+        );
+    }
 
-    println!();
-    println!("{}", "=== Uninstall Complete ===".green());
+    #[test]
+    fn test_user_systemd_uninstall() {
+        let dir = tempfile::tempdir().unwrap();
+        for unit in [
+            "natgeo-wallpaper.service",
+            "natgeo-wallpaper.timer",
+            "natgeo-wallpaper-watch.service",
+            "unrelated.timer",
+        ] {
+            fs::write(dir.path().join(unit), "").unwrap();
+        }
+        // systemctl failing to stop a unit doesn't leave its files behind
+        let runner = RecordedCommands {
+            failing: vec!["systemctl"],
+            ..RecordedCommands::default()
+        };
+        let systemd = UserSystemd {
+            runner: &runner,
+            unit_dir: dir.path().to_path_buf(),
+        };
+        systemd.uninstall().unwrap();
 
-    Ok(())
+        // Nothing is asked about the rotation timer, which wasn't installed
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec![
+                "systemctl --user stop natgeo-wallpaper.timer",
+                "systemctl --user disable natgeo-wallpaper.timer",
+                "systemctl --user disable --now natgeo-wallpaper-watch.service",
+                "systemctl --user daemon-reload",
+            ]
+        );
+        let left: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(left, ["unrelated.timer"]);
+    }
 }
@@ -1,10 +1,123 @@
 #![allow(clippy::unwrap_used)]
 
-use natgeo_wallpapers::{download_natgeo_photo_of_the_day, write_log, PhotoInfo};
+use chrono::NaiveDate;
+use natgeo_wallpapers::{
+    download_collection_into, download_natgeo_photo_of_the_day,
+    download_natgeo_photo_of_the_day_with_options, get_collection_photos,
+    get_collection_photos_with, get_photo_of_the_day_from, metadata_path_for, read_metadata,
+    repair_photo, verify_photos_with, write_log, write_metadata, CancelFlag,
+    CollectionDownloadOptions, CollectionFilter, CollectionProgress, ConvertFormat,
+    DownloadOptions, DownloadOutcome, HttpOptions, ManifestConfig, NatGeoClient, PhotoCollection,
+    PhotoError, PhotoInfo, PhotoMetadata, Repair,
+};
+use std::cell::RefCell;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 use tempfile::TempDir;
 
+/// Consume an HTTP request's line and headers so the response can be written.
+/// Returns the request line's method and path, e.g. `"GET /photo.jpg"`.
+fn read_request_head(stream: &TcpStream) -> String {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    let _ = reader.read_line(&mut request_line);
+    let mut line = String::new();
+    while reader.read_line(&mut line).is_ok_and(|n| n > 0) && line != "\r\n" {
+        line.clear();
+    }
+    request_line
+        .split_whitespace()
+        .take(2)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Deterministic RGB noise, which compresses poorly and so makes realistically sized files
+fn noise_pixels(width: u32, height: u32) -> Vec<u8> {
+    let mut state: u32 = 0x9e37_79b9;
+    (0..width * height * 3)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state.to_le_bytes()[0]
+        })
+        .collect()
+}
+
+/// A real JPEG comfortably above the collection thumbnail cutoff
+fn fixture_jpeg() -> &'static [u8] {
+    static JPEG: OnceLock<Vec<u8>> = OnceLock::new();
+    JPEG.get_or_init(|| {
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90)
+            .encode(
+                &noise_pixels(300, 300),
+                300,
+                300,
+                image::ExtendedColorType::Rgb8,
+            )
+            .unwrap();
+        jpeg
+    })
+}
+
+/// A large PNG, stored with fast compression so it stays roughly raw size
+fn large_png(width: u32, height: u32) -> Vec<u8> {
+    use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+    use image::ImageEncoder;
+
+    let mut png = Vec::new();
+    PngEncoder::new_with_quality(&mut png, CompressionType::Fast, FilterType::NoFilter)
+        .write_image(
+            &noise_pixels(width, height),
+            width,
+            height,
+            image::ExtendedColorType::Rgb8,
+        )
+        .unwrap();
+    png
+}
+
+/// Serve a fake JPEG for every request on a local port.
+/// `on_request` runs with the 1-based GET request number before each response is sent;
+/// HEAD requests get headers only and aren't counted.
+fn serve_fixture_images(on_request: impl Fn(usize) + Send + 'static) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&requests);
+
+    std::thread::spawn(move || {
+        let body = fixture_jpeg();
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            let request = read_request_head(&stream);
+
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            if request.starts_with("HEAD") {
+                let _ = stream.write_all(header.as_bytes());
+                continue;
+            }
+
+            let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            on_request(n);
+
+            let _ = stream.write_all(header.as_bytes());
+            let _ = stream.write_all(body);
+        }
+    });
+
+    (base_url, requests)
+}
+
 #[test]
 fn test_download_real_image() {
     // Integration test: download a small test image from httpbin
@@ -82,6 +195,8 @@ fn test_full_workflow_simulation() {
     let photo_info = PhotoInfo {
         image_url: String::from("https://example.com/photo.jpg"),
         title: String::from("Test Photo"),
+        caption: None,
+        photographer: None,
     };
 
     let sanitized_title = "Test_Photo";
@@ -121,3 +236,1788 @@ fn test_full_workflow_simulation() {
     assert!(log_contents.contains("Download process completed successfully"));
     assert_eq!(log_contents.lines().count(), 4);
 }
+
+#[test]
+fn test_collection_download_stops_at_next_photo_when_cancelled() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().join("best-photos-october-2018");
+    let save_dir = save_dir.to_str().unwrap();
+
+    // Raise the flag while the second photo is being served, as Ctrl-C would
+    let cancel = CancelFlag::new();
+    let trigger = cancel.clone();
+    let (base_url, requests) = serve_fixture_images(move |n| {
+        if n == 2 {
+            trigger.cancel();
+        }
+    });
+
+    let collection = PhotoCollection {
+        name: "Best of October".to_string(),
+        photos: (1..=4)
+            .map(|i| PhotoInfo {
+                image_url: format!("{}/{:02}-best-pod-october-18.jpg", base_url, i),
+                title: format!("Photo {}", i),
+                caption: None,
+                photographer: None,
+            })
+            .collect(),
+    };
+
+    let result = download_collection_into(
+        &collection,
+        save_dir,
+        &CollectionDownloadOptions::default(),
+        &cancel,
+        None,
+    );
+    assert!(matches!(result, Err(PhotoError::Interrupted)));
+
+    // The photo in flight completes; nothing after it is requested
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    let mut files: Vec<String> = fs::read_dir(save_dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "Photo_1.jpg",
+            "Photo_1.json",
+            "Photo_2.jpg",
+            "Photo_2.json",
+            "collection.log"
+        ]
+    );
+    for photo in ["Photo_1.jpg", "Photo_2.jpg"] {
+        let size = fs::metadata(temp_dir.path().join("best-photos-october-2018").join(photo))
+            .unwrap()
+            .len();
+        assert_eq!(
+            size,
+            fixture_jpeg().len() as u64,
+            "{photo} should be complete"
+        );
+    }
+
+    let log = fs::read_to_string(format!("{}/collection.log", save_dir)).unwrap();
+    assert!(log.contains("Interrupted: 2 downloaded, 0 skipped, 0 failed"));
+    assert!(!log.contains("Collection download complete"));
+}
+
+#[test]
+fn test_collection_download_completes_without_cancellation() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let (base_url, requests) = serve_fixture_images(|_| {});
+
+    let collection = PhotoCollection {
+        name: "Best of October".to_string(),
+        photos: (1..=3)
+            .map(|i| PhotoInfo {
+                image_url: format!("{}/{:02}-best-pod-october-18.jpg", base_url, i),
+                title: format!("Photo {}", i),
+                caption: None,
+                photographer: None,
+            })
+            .collect(),
+    };
+
+    let result = download_collection_into(
+        &collection,
+        save_dir,
+        &CollectionDownloadOptions::default(),
+        &CancelFlag::new(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(result.downloaded, 3);
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+    // Each photo gets a metadata sidecar in the same directory
+    let metadata = read_metadata(&temp_dir.path().join("Photo_2.json")).unwrap();
+    assert_eq!(
+        metadata.url,
+        format!("{}/02-best-pod-october-18.jpg", base_url)
+    );
+    assert_eq!(metadata.title, "Photo 2");
+    assert_eq!(metadata.content_type, "image/jpeg");
+    assert_eq!(metadata.file_size, fixture_jpeg().len() as u64);
+
+    // Re-running skips everything without touching the network
+    let result = download_collection_into(
+        &collection,
+        save_dir,
+        &CollectionDownloadOptions::default(),
+        &CancelFlag::new(),
+        None,
+    )
+    .unwrap();
+    assert_eq!((result.downloaded, result.skipped), (0, 3));
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_collection_result_json_lists_each_photo_status() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let (base_url, _) = serve_photo_and_thumbnail(true);
+    let collection = photo_and_thumbnail_collection(&base_url);
+
+    let result = download_collection_into(
+        &collection,
+        save_dir,
+        &CollectionDownloadOptions::default(),
+        &CancelFlag::new(),
+        None,
+    )
+    .unwrap();
+
+    let json: serde_json::Value =
+        serde_json::from_str(&serde_json::to_string(&result).unwrap()).unwrap();
+    assert_eq!(json["downloaded"], 1);
+    assert_eq!(json["skipped"], 1);
+    assert_eq!(json["failed"], 0);
+    let photos = json["photos"].as_array().unwrap();
+    assert_eq!(photos.len(), 2);
+    assert_eq!(photos[0]["title"], collection.photos[0].title);
+    assert_eq!(photos[0]["status"], "downloaded");
+    assert_eq!(photos[1]["status"], "skipped");
+}
+
+/// Run the binary with `HOME` pointed at `home`, returning stdout and whether it succeeded
+fn run_binary(home: &std::path::Path, args: &[&str]) -> (String, bool) {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_natgeo-wallpapers"))
+        .args(args)
+        .env("HOME", home)
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("XDG_DATA_HOME")
+        .env_remove("XDG_CACHE_HOME")
+        .env_remove("NATGEO_WALLPAPERS_PHOTO_DIR")
+        .env_remove("NATGEO_WALLPAPERS_COLLECTION_DIR")
+        .env_remove("NATGEO_WALLPAPERS_DATA_DIR")
+        .env_remove("NATGEO_WALLPAPERS_CACHE_DIR")
+        .output()
+        .unwrap();
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        output.status.success(),
+    )
+}
+
+#[test]
+fn test_color_only_when_asked_for_or_on_a_terminal() {
+    let home = TempDir::new().unwrap();
+    let library = home.path().join("Pictures/NationalGeographic");
+    fs::create_dir_all(library.join("2026-02-01")).unwrap();
+    fs::write(library.join("2026-02-01/Foggy_Morning.jpg"), fixture_jpeg()).unwrap();
+
+    // Output is piped here, so `auto` leaves colors out
+    for args in [&["stats"][..], &["--color", "never", "stats"]] {
+        let (stdout, success) = run_binary(home.path(), args);
+        assert!(success);
+        assert!(stdout.contains("=== Photo Library ==="));
+        assert!(!stdout.contains('\x1b'), "{args:?}: {stdout:?}");
+    }
+
+    let (stdout, success) = run_binary(home.path(), &["stats", "--color", "always"]);
+    assert!(success);
+    assert!(stdout.contains("\x1b[32m=== Photo Library ===\x1b[39m"));
+}
+
+#[test]
+fn test_json_flag_prints_only_a_json_document() {
+    let home = TempDir::new().unwrap();
+    let library = home.path().join("Pictures/NationalGeographic");
+    fs::create_dir_all(library.join("2026-02-01")).unwrap();
+    fs::write(library.join("2026-02-01/Foggy_Morning.jpg"), fixture_jpeg()).unwrap();
+
+    // The flag is global, so it can come before or after the subcommand
+    for args in [["--json", "stats"], ["stats", "--json"]] {
+        let (stdout, success) = run_binary(home.path(), &args);
+        assert!(success);
+        let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+        assert_eq!(json["daily_photos"], 1);
+        assert_eq!(json["total_bytes"], fixture_jpeg().len());
+    }
+
+    let (stdout, success) = run_binary(home.path(), &["list", "--json"]);
+    assert!(success);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let entries = json.as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["filename"], "Foggy_Morning.jpg");
+    assert_eq!(entries[0]["date"], "2026-02-01");
+}
+
+#[test]
+fn test_json_flag_reports_errors_as_json() {
+    let home = TempDir::new().unwrap();
+
+    let missing = home.path().join("missing");
+    let (stdout, success) = run_binary(
+        home.path(),
+        &["stats", "--json", "--path", missing.to_str().unwrap()],
+    );
+    assert!(!success);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert!(json["error"].as_str().unwrap().contains("No photos"));
+}
+
+#[test]
+fn test_set_pick_without_a_terminal_suggests_photo() {
+    let home = TempDir::new().unwrap();
+
+    // stdin isn't a terminal here, so there is nothing to pick with
+    let (stdout, success) = run_binary(home.path(), &["set", "--pick", "--json"]);
+    assert!(!success);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert!(json["error"].as_str().unwrap().contains("--photo"));
+}
+
+#[test]
+fn test_collections_names_lists_one_per_line() {
+    let home = TempDir::new().unwrap();
+    let collections = home.path().join("Pictures/NationalGeographic/collections");
+    for name in ["best-of-2025", "wildlife-oct"] {
+        fs::create_dir_all(collections.join(name)).unwrap();
+        fs::write(collections.join(name).join("01-Owl.jpg"), fixture_jpeg()).unwrap();
+    }
+
+    let (stdout, success) = run_binary(home.path(), &["collections", "--names"]);
+    assert!(success);
+    assert_eq!(stdout, "best-of-2025\nwildlife-oct\n");
+}
+
+#[test]
+fn test_download_streams_large_body_to_disk() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap().to_string();
+    let photo_path = temp_dir.path().join("large_photo.png");
+
+    // ~12 MB of noise, so misordered or dropped chunks are caught
+    let body = large_png(2048, 2048);
+    let expected = body.clone();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/large.png", listener.local_addr().unwrap());
+    let watched = temp_dir.path().join("large_photo.png.part");
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        read_request_head(&stream);
+
+        let header = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(header.as_bytes()).unwrap();
+        let (first, rest) = body.split_at(body.len() / 2);
+        stream.write_all(first).unwrap();
+        stream.flush().unwrap();
+
+        // Hold back the second half until bytes reach the partial file. A client that
+        // buffers the whole body would never write before this times out.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        let mut wrote_before_complete = false;
+        while std::time::Instant::now() < deadline {
+            if fs::metadata(&watched).is_ok_and(|m| m.len() > 0) {
+                wrote_before_complete = true;
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        stream.write_all(rest).unwrap();
+        wrote_before_complete
+    });
+
+    let log_path = format!("{}/download.log", save_dir);
+    let outcome =
+        download_natgeo_photo_of_the_day(&url, &save_dir, "large_photo", &log_path).unwrap();
+    assert_eq!(outcome, DownloadOutcome::Downloaded(photo_path.clone()));
+
+    assert!(
+        server.join().unwrap(),
+        "file should be written while the body is still arriving"
+    );
+    assert!(fs::read(&photo_path).unwrap() == expected);
+    let log = fs::read_to_string(&log_path).unwrap();
+    assert!(log.contains("Downloaded photo:"));
+
+    // A second call finds the file and skips it
+    let outcome =
+        download_natgeo_photo_of_the_day(&url, &save_dir, "large_photo", &log_path).unwrap();
+    assert_eq!(outcome, DownloadOutcome::AlreadyExists(photo_path));
+    let log = fs::read_to_string(&log_path).unwrap();
+    assert!(log.contains("Photo already exists:"));
+}
+
+/// Serve one response that promises `promised` bytes but sends only `sent` before closing
+fn serve_truncated_image(promised: usize, sent: usize) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/truncated.jpg", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        read_request_head(&stream);
+        let header = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            promised
+        );
+        let _ = stream.write_all(header.as_bytes());
+        let _ = stream.write_all(&vec![0xFF_u8; sent]);
+    });
+    url
+}
+
+#[test]
+fn test_failed_download_leaves_no_final_or_partial_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let url = serve_truncated_image(1_000_000, 300_000);
+    let log_path = format!("{}/download.log", save_dir);
+
+    let result = download_natgeo_photo_of_the_day(&url, save_dir, "truncated", &log_path);
+    assert!(result.is_err());
+
+    let leftovers: Vec<String> = fs::read_dir(save_dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("truncated"))
+        .collect();
+    assert!(leftovers.is_empty(), "left behind: {leftovers:?}");
+}
+
+#[test]
+fn test_download_replaces_leftover_partial_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let stale = temp_dir.path().join("Photo_1.jpg.part");
+    fs::write(&stale, b"from a crashed run").unwrap();
+    let stale_png = temp_dir.path().join("Photo_1.png.part");
+    fs::write(&stale_png, b"from another crashed run").unwrap();
+
+    // A stale partial must not count as an existing photo
+    let (base_url, requests) = serve_fixture_images(|_| {});
+    let log_path = format!("{}/download.log", save_dir);
+    let outcome = download_natgeo_photo_of_the_day(
+        &format!("{}/photo.jpg", base_url),
+        save_dir,
+        "Photo_1",
+        &log_path,
+    )
+    .unwrap();
+
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        outcome,
+        DownloadOutcome::Downloaded(temp_dir.path().join("Photo_1.jpg"))
+    );
+    assert_eq!(fs::read(outcome.path()).unwrap(), fixture_jpeg());
+    assert!(!stale.exists());
+    assert!(!stale_png.exists());
+    assert!(fs::read_to_string(&log_path)
+        .unwrap()
+        .contains("Removed 2 leftover partial download(s)"));
+}
+
+#[test]
+fn test_corrupt_downloads_are_rejected_and_counted_as_failed() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+
+    // An HTML error page served with an image content type
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let page = format!(
+            "<!DOCTYPE html><html><body>{}</body></html>",
+            "x".repeat(80_000)
+        );
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            read_request_head(&stream);
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                page.len()
+            );
+            let _ = stream.write_all(header.as_bytes());
+            let _ = stream.write_all(page.as_bytes());
+        }
+    });
+
+    let log_path = format!("{}/download.log", save_dir);
+    let result = download_natgeo_photo_of_the_day(
+        &format!("{}/error.jpg", base_url),
+        save_dir,
+        "Error_Page",
+        &log_path,
+    );
+    assert!(matches!(result, Err(PhotoError::CorruptImage(_))));
+    assert!(!temp_dir.path().join("Error_Page.jpg").exists());
+    assert!(!temp_dir.path().join("Error_Page.jpg.part").exists());
+    assert!(fs::read_to_string(&log_path)
+        .unwrap()
+        .contains("Rejected download"));
+
+    let collection = PhotoCollection {
+        name: "Best of October".to_string(),
+        photos: vec![PhotoInfo {
+            image_url: format!("{}/01-best-pod-october-18.jpg", base_url),
+            title: "Photo 1".to_string(),
+            caption: None,
+            photographer: None,
+        }],
+    };
+    let collection_dir = temp_dir.path().join("collection");
+    let result = download_collection_into(
+        &collection,
+        collection_dir.to_str().unwrap(),
+        &CollectionDownloadOptions::default(),
+        &CancelFlag::new(),
+        None,
+    )
+    .unwrap();
+    assert_eq!((result.downloaded, result.failed), (0, 1));
+}
+
+#[test]
+fn test_download_converts_webp_to_jpeg_when_requested() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+
+    let mut webp = Vec::new();
+    image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+        32,
+        24,
+        image::Rgb([10, 120, 200]),
+    ))
+    .write_to(
+        &mut std::io::Cursor::new(&mut webp),
+        image::ImageFormat::WebP,
+    )
+    .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/photo.webp", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        read_request_head(&stream);
+        let header = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: image/webp\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            webp.len()
+        );
+        let _ = stream.write_all(header.as_bytes());
+        let _ = stream.write_all(&webp);
+    });
+
+    let options = DownloadOptions {
+        convert_to: Some(ConvertFormat::Jpeg),
+        quality: 80,
+        ..DownloadOptions::default()
+    };
+    let log_path = format!("{}/download.log", save_dir);
+    let outcome =
+        download_natgeo_photo_of_the_day_with_options(&url, save_dir, "Reef", &log_path, &options)
+            .unwrap();
+
+    let jpg = temp_dir.path().join("Reef.jpg");
+    assert_eq!(outcome, DownloadOutcome::Downloaded(jpg.clone()));
+    assert!(!temp_dir.path().join("Reef.webp").exists());
+    assert_eq!(
+        image::ImageReader::open(&jpg)
+            .unwrap()
+            .with_guessed_format()
+            .unwrap()
+            .format(),
+        Some(image::ImageFormat::Jpeg)
+    );
+}
+
+/// Serve `/photo.jpg` (a full-size JPEG) and `/thumb.jpg` (a tiny one), recording
+/// each request as `"METHOD /path"`. Without `with_length`, responses omit
+/// `Content-Length` and the body runs until the connection closes.
+fn serve_photo_and_thumbnail(with_length: bool) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&requests);
+
+    let mut thumbnail = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new(&mut thumbnail)
+        .encode(
+            &noise_pixels(40, 30),
+            40,
+            30,
+            image::ExtendedColorType::Rgb8,
+        )
+        .unwrap();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            let request = read_request_head(&stream);
+            log.lock().unwrap().push(request.clone());
+
+            let body = if request.ends_with("/thumb.jpg") {
+                &thumbnail[..]
+            } else {
+                fixture_jpeg()
+            };
+            let length = if with_length {
+                format!("Content-Length: {}\r\n", body.len())
+            } else {
+                String::new()
+            };
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: image/jpeg\r\n{}Connection: close\r\n\r\n",
+                length
+            );
+            let _ = stream.write_all(header.as_bytes());
+            if !request.starts_with("HEAD") {
+                let _ = stream.write_all(body);
+            }
+        }
+    });
+
+    (base_url, requests)
+}
+
+fn photo_and_thumbnail_collection(base_url: &str) -> PhotoCollection {
+    PhotoCollection {
+        name: "Best of October".to_string(),
+        photos: ["photo", "thumb"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| PhotoInfo {
+                image_url: format!("{}/{}.jpg", base_url, name),
+                title: format!("Photo {}", i + 1),
+                caption: None,
+                photographer: None,
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn test_collection_skips_thumbnails_by_content_length() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let (base_url, requests) = serve_photo_and_thumbnail(true);
+    let collection = photo_and_thumbnail_collection(&base_url);
+
+    let result = download_collection_into(
+        &collection,
+        save_dir,
+        &CollectionDownloadOptions::default(),
+        &CancelFlag::new(),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!((result.downloaded, result.skipped), (1, 1));
+    // The thumbnail is never fetched
+    assert_eq!(
+        *requests.lock().unwrap(),
+        ["HEAD /photo.jpg", "GET /photo.jpg", "HEAD /thumb.jpg"]
+    );
+    assert!(!temp_dir.path().join("Photo_2.jpg").exists());
+    let log = fs::read_to_string(temp_dir.path().join("collection.log")).unwrap();
+    assert!(log.contains("Skipped Photo_2 (too small"));
+
+    // The threshold is configurable
+    let options = CollectionDownloadOptions {
+        min_photo_size: 1,
+        ..CollectionDownloadOptions::default()
+    };
+    let result =
+        download_collection_into(&collection, save_dir, &options, &CancelFlag::new(), None)
+            .unwrap();
+    assert_eq!((result.downloaded, result.skipped), (1, 1));
+    assert!(temp_dir.path().join("Photo_2.jpg").exists());
+}
+
+#[test]
+fn test_collection_keeps_different_photos_with_the_same_title() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let (base_url, _) = serve_photo_and_thumbnail(true);
+    let mut collection = photo_and_thumbnail_collection(&base_url);
+    for photo in &mut collection.photos {
+        photo.title = "Same title".to_string();
+    }
+    let options = CollectionDownloadOptions {
+        min_photo_size: 1,
+        ..CollectionDownloadOptions::default()
+    };
+
+    let result =
+        download_collection_into(&collection, save_dir, &options, &CancelFlag::new(), None)
+            .unwrap();
+    assert_eq!((result.downloaded, result.skipped), (2, 0));
+    let first = fs::read(temp_dir.path().join("Same_title.jpg")).unwrap();
+    let second = fs::read(temp_dir.path().join("Same_title_2.jpg")).unwrap();
+    assert_eq!(first, fixture_jpeg());
+    assert_ne!(first, second);
+    let metadata = read_metadata(&temp_dir.path().join("Same_title_2.json")).unwrap();
+    assert_eq!(metadata.url, format!("{}/thumb.jpg", base_url));
+
+    // Both are recognized on the next run
+    let result =
+        download_collection_into(&collection, save_dir, &options, &CancelFlag::new(), None)
+            .unwrap();
+    assert_eq!((result.downloaded, result.skipped), (0, 2));
+    assert!(!temp_dir.path().join("Same_title_3.jpg").exists());
+}
+
+#[test]
+fn test_collection_checks_size_after_download_without_content_length() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let (base_url, requests) = serve_photo_and_thumbnail(false);
+    let collection = photo_and_thumbnail_collection(&base_url);
+
+    let result = download_collection_into(
+        &collection,
+        save_dir,
+        &CollectionDownloadOptions::default(),
+        &CancelFlag::new(),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!((result.downloaded, result.skipped), (1, 1));
+    assert!(requests
+        .lock()
+        .unwrap()
+        .contains(&"GET /thumb.jpg".to_string()));
+    assert!(temp_dir.path().join("Photo_1.jpg").exists());
+    assert!(!temp_dir.path().join("Photo_2.jpg").exists());
+    let log = fs::read_to_string(temp_dir.path().join("collection.log")).unwrap();
+    assert!(log.contains("Removed Photo_2 (too small"));
+}
+
+#[test]
+fn test_collection_progress_reports_each_photo() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let (base_url, _) = serve_photo_and_thumbnail(true);
+    let collection = photo_and_thumbnail_collection(&base_url);
+
+    let finished = RefCell::new(Vec::new());
+    let started = RefCell::new(Vec::new());
+    let last_transfer = RefCell::new(None);
+    let progress = |event: CollectionProgress| match event {
+        CollectionProgress::Started {
+            title,
+            index,
+            total,
+        } => {
+            started.borrow_mut().push((title.to_string(), index, total));
+        }
+        CollectionProgress::Transfer {
+            bytes, total_bytes, ..
+        } => *last_transfer.borrow_mut() = Some((bytes, total_bytes)),
+        CollectionProgress::Finished {
+            completed,
+            total,
+            downloaded,
+            skipped,
+            failed,
+            ..
+        } => finished
+            .borrow_mut()
+            .push((completed, total, downloaded, skipped, failed)),
+    };
+
+    download_collection_into(
+        &collection,
+        save_dir,
+        &CollectionDownloadOptions::default(),
+        &CancelFlag::new(),
+        Some(&progress),
+    )
+    .unwrap();
+
+    assert_eq!(
+        *started.borrow(),
+        [("Photo 1".to_string(), 0, 2), ("Photo 2".to_string(), 1, 2)]
+    );
+    // Once per photo: the full-size photo is downloaded, the thumbnail skipped
+    assert_eq!(*finished.borrow(), [(1, 2, 1, 0, 0), (2, 2, 1, 1, 0)]);
+    let size = fixture_jpeg().len() as u64;
+    assert_eq!(*last_transfer.borrow(), Some((size, Some(size))));
+}
+
+/// Answer requests with each status in `statuses` in turn, then serve the fixture JPEG.
+/// 429 responses carry `Retry-After: 0`.
+fn serve_statuses(statuses: &[u16]) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/photo.jpg", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&requests);
+    let statuses = statuses.to_vec();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            read_request_head(&stream);
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+
+            if let Some(&status) = statuses.get(n) {
+                let retry_after = if status == 429 {
+                    "Retry-After: 0\r\n"
+                } else {
+                    ""
+                };
+                let response = format!(
+                    "HTTP/1.1 {} Error\r\n{}Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    status, retry_after
+                );
+                let _ = stream.write_all(response.as_bytes());
+            } else {
+                let body = fixture_jpeg();
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(header.as_bytes());
+                let _ = stream.write_all(body);
+            }
+        }
+    });
+
+    (url, requests)
+}
+
+fn fast_retry_options(retries: u32) -> DownloadOptions {
+    DownloadOptions {
+        http: HttpOptions {
+            retries,
+            retry_base_delay: Duration::from_millis(10),
+            ..HttpOptions::default()
+        },
+        ..DownloadOptions::default()
+    }
+}
+
+#[test]
+fn test_download_retries_server_errors_then_succeeds() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let (url, requests) = serve_statuses(&[503, 500]);
+    let log_path = format!("{}/download.log", save_dir);
+
+    let outcome = download_natgeo_photo_of_the_day_with_options(
+        &url,
+        save_dir,
+        "Retried",
+        &log_path,
+        &fast_retry_options(2),
+    )
+    .unwrap();
+
+    assert_eq!(
+        outcome,
+        DownloadOutcome::Downloaded(temp_dir.path().join("Retried.jpg"))
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    let log = fs::read_to_string(&log_path).unwrap();
+    assert!(log.contains("after HTTP 503 Service Unavailable (retry 1 of 2)"));
+    assert!(log.contains("after HTTP 500 Internal Server Error (retry 2 of 2)"));
+}
+
+#[test]
+fn test_download_honors_retry_after_on_429() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let (url, requests) = serve_statuses(&[429]);
+    let log_path = format!("{}/download.log", save_dir);
+
+    // A long base delay proves the Retry-After: 0 header was used instead
+    let options = DownloadOptions {
+        http: HttpOptions {
+            retries: 1,
+            retry_base_delay: Duration::from_mins(1),
+            ..HttpOptions::default()
+        },
+        ..DownloadOptions::default()
+    };
+    let started = std::time::Instant::now();
+    download_natgeo_photo_of_the_day_with_options(&url, save_dir, "Limited", &log_path, &options)
+        .unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert!(fs::read_to_string(&log_path).unwrap().contains("Retrying"));
+}
+
+#[test]
+fn test_download_gives_up_without_retrying_client_errors() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let log_path = format!("{}/download.log", save_dir);
+
+    // 404 is never retried
+    let (url, requests) = serve_statuses(&[404]);
+    let result = download_natgeo_photo_of_the_day_with_options(
+        &url,
+        save_dir,
+        "Missing",
+        &log_path,
+        &fast_retry_options(3),
+    );
+    match result {
+        Err(
+            ref e @ PhotoError::HttpStatus {
+                status: 404,
+                url: ref failed,
+            },
+        ) => {
+            assert_eq!(*failed, url);
+            assert!(e.is_permanent());
+        }
+        other => panic!("expected HTTP 404, got {other:?}"),
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    // Retries run out on persistent server errors
+    let (url, requests) = serve_statuses(&[503, 503, 503]);
+    let result = download_natgeo_photo_of_the_day_with_options(
+        &url,
+        save_dir,
+        "Unavailable",
+        &log_path,
+        &fast_retry_options(1),
+    );
+    assert!(matches!(
+        result,
+        Err(PhotoError::HttpStatus { status: 503, .. })
+    ));
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert!(!fs::read_to_string(&log_path).unwrap().contains("retry 2"));
+}
+
+/// Accept connections but only answer after `delay`, counting requests
+fn serve_slowly(delay: Duration) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/slow.jpg", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&requests);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            counter.fetch_add(1, Ordering::SeqCst);
+            std::thread::spawn(move || {
+                read_request_head(&stream);
+                std::thread::sleep(delay);
+                let _ = stream.write_all(
+                    b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                );
+            });
+        }
+    });
+
+    (url, requests)
+}
+
+#[test]
+fn test_download_times_out_on_stalled_server() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let log_path = format!("{}/download.log", save_dir);
+    let (url, requests) = serve_slowly(Duration::from_secs(5));
+
+    let options = DownloadOptions {
+        http: HttpOptions {
+            retries: 1,
+            retry_base_delay: Duration::from_millis(10),
+            timeout: Duration::from_millis(300),
+            ..HttpOptions::default()
+        },
+        ..DownloadOptions::default()
+    };
+    let started = std::time::Instant::now();
+    let result = download_natgeo_photo_of_the_day_with_options(
+        &url, save_dir, "Stalled", &log_path, &options,
+    );
+
+    assert!(matches!(result, Err(PhotoError::Timeout(_))), "{result:?}");
+    assert!(started.elapsed() < Duration::from_secs(4));
+    // Timeouts are retried, and the log says why
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert!(fs::read_to_string(&log_path)
+        .unwrap()
+        .contains("after Request timed out"));
+    assert!(!temp_dir.path().join("Stalled.jpg").exists());
+}
+
+#[test]
+fn test_download_goes_through_configured_proxy() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let log_path = format!("{}/download.log", save_dir);
+    // The photo server doubles as a forward proxy: it answers whatever it's asked for
+    let (proxy_url, requests) = serve_photo_and_thumbnail(true);
+
+    let options = DownloadOptions {
+        http: HttpOptions {
+            proxy: Some(proxy_url),
+            ..HttpOptions::default()
+        },
+        ..DownloadOptions::default()
+    };
+    let outcome = download_natgeo_photo_of_the_day_with_options(
+        "http://photos.invalid/reef.jpg",
+        save_dir,
+        "Reef",
+        &log_path,
+        &options,
+    )
+    .unwrap();
+
+    assert_eq!(
+        outcome,
+        DownloadOutcome::Downloaded(temp_dir.path().join("Reef.jpg"))
+    );
+    assert_eq!(
+        *requests.lock().unwrap(),
+        ["GET http://photos.invalid/reef.jpg"]
+    );
+}
+
+#[test]
+fn test_invalid_proxy_is_reported() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let options = DownloadOptions {
+        http: HttpOptions {
+            proxy: Some("http://bad host:80".to_string()),
+            ..HttpOptions::default()
+        },
+        ..DownloadOptions::default()
+    };
+
+    let result = download_natgeo_photo_of_the_day_with_options(
+        "http://photos.invalid/reef.jpg",
+        save_dir,
+        "Reef",
+        &format!("{}/download.log", save_dir),
+        &options,
+    );
+
+    assert!(
+        matches!(result, Err(PhotoError::InvalidProxy(_))),
+        "{result:?}"
+    );
+}
+
+/// Requests seen by a keep-alive server: connections accepted, and each request's head lines
+#[derive(Default)]
+struct KeepAliveLog {
+    connections: AtomicUsize,
+    requests: Mutex<Vec<Vec<String>>>,
+}
+
+impl KeepAliveLog {
+    /// Value of `name` in each recorded request, in order
+    fn header(&self, name: &str) -> Vec<Option<String>> {
+        let prefix = format!("{}:", name.to_lowercase());
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|lines| {
+                lines.iter().find_map(|line| {
+                    line.to_lowercase()
+                        .starts_with(&prefix)
+                        .then(|| line[prefix.len()..].trim().to_string())
+                })
+            })
+            .collect()
+    }
+}
+
+/// Serve a collection page at `/collection` and the fixture JPEG everywhere else,
+/// keeping connections open between requests like a real server
+fn serve_keep_alive() -> (String, Arc<KeepAliveLog>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let log = Arc::new(KeepAliveLog::default());
+    let server_log = Arc::clone(&log);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            server_log.connections.fetch_add(1, Ordering::SeqCst);
+            let log = Arc::clone(&server_log);
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut lines = Vec::new();
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).is_ok_and(|n| n > 0) && line != "\r\n" {
+                        lines.push(line.trim_end().to_string());
+                        line.clear();
+                    }
+                    let Some(request_line) = lines.first().cloned() else {
+                        break;
+                    };
+                    log.requests.lock().unwrap().push(lines);
+
+                    let (content_type, body): (&str, &[u8]) =
+                        if request_line.contains("/collection") {
+                            (
+                                "text/html",
+                                br#"<meta property="og:title" content="Best of October">
+<img src="https://i.natgeofe.com/n/abc/01-best-pod-october-18.jpg">"#,
+                            )
+                        } else {
+                            ("image/jpeg", fixture_jpeg())
+                        };
+                    let header = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                        content_type,
+                        body.len()
+                    );
+                    let _ = stream.write_all(header.as_bytes());
+                    if !request_line.starts_with("HEAD") {
+                        let _ = stream.write_all(body);
+                    }
+                }
+            });
+        }
+    });
+
+    (base_url, log)
+}
+
+#[test]
+fn test_client_sends_browser_headers_for_pages_and_photos() {
+    let temp_dir = TempDir::new().unwrap();
+    let (base_url, log) = serve_keep_alive();
+    let client = NatGeoClient::new(HttpOptions::default()).unwrap();
+
+    let collection = client
+        .collection(&format!("{}/collection", base_url))
+        .unwrap();
+    client
+        .download(
+            &format!("{}/photo.jpg", base_url),
+            &temp_dir.path().join("Reef"),
+        )
+        .unwrap();
+
+    assert_eq!(collection.name, "Best of October");
+    let user_agents = log.header("User-Agent");
+    assert_eq!(user_agents.len(), 2);
+    assert!(user_agents.iter().all(|agent| agent
+        .as_deref()
+        .is_some_and(|a| a.starts_with("Mozilla/5.0"))));
+    assert_eq!(
+        log.header("Accept"),
+        [
+            Some(
+                "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8"
+                    .to_string()
+            ),
+            Some("image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8".to_string()),
+        ]
+    );
+    assert_eq!(
+        log.header("Referer"),
+        [
+            Some("https://www.nationalgeographic.com/".to_string()),
+            None
+        ]
+    );
+    assert_eq!(
+        log.header("Accept-Language"),
+        [Some("en-US,en;q=0.9".to_string()), None]
+    );
+    assert!(temp_dir.path().join("Reef.jpg").exists());
+    assert!(temp_dir.path().join("Reef.log").exists());
+}
+
+#[test]
+fn test_collection_download_reuses_one_connection() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let (base_url, log) = serve_keep_alive();
+    let collection = PhotoCollection {
+        name: "Reused".to_string(),
+        photos: (1..=3)
+            .map(|n| PhotoInfo {
+                image_url: format!("{}/{:02}-best-pod.jpg", base_url, n),
+                title: format!("{:02}-best-pod", n),
+                caption: None,
+                photographer: None,
+            })
+            .collect(),
+    };
+
+    let result = download_collection_into(
+        &collection,
+        save_dir,
+        &CollectionDownloadOptions::default(),
+        &CancelFlag::new(),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(result.downloaded, 3);
+    // A HEAD and a GET per photo, all over the same connection
+    assert_eq!(log.requests.lock().unwrap().len(), 6);
+    assert_eq!(log.connections.load(Ordering::SeqCst), 1);
+}
+
+const PHOTO_OF_THE_DAY_PAGE: &str = include_str!("fixtures/photo-of-the-day.html");
+const PHOTO_OF_THE_DAY_SRCSET_PAGE: &str = include_str!("fixtures/photo-of-the-day-srcset.html");
+const PHOTO_OF_THE_DAY_JSON_LD_PAGE: &str = include_str!("fixtures/photo-of-the-day-jsonld.html");
+const PHOTO_OF_THE_DAY_NEXT_DATA_PAGE: &str =
+    include_str!("fixtures/photo-of-the-day-next-data.html");
+const COLLECTION_PAGE: &str = include_str!("fixtures/best-photos-october-2018.html");
+const WILDLIFE_GALLERY_PAGE: &str = include_str!("fixtures/wildlife-gallery.html");
+const DUPLICATES_PAGE: &str = include_str!("fixtures/best-photos-march-2019.html");
+
+/// Serve `body` as an HTML page with `status` for every request on a local port
+fn serve_html(status: u16, body: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            read_request_head(&stream);
+            let response = format!(
+                "HTTP/1.1 {} Fixture\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+
+    base_url
+}
+
+#[test]
+fn test_photo_of_the_day_scraped_from_fixture_page() {
+    let base_url = serve_html(200, PHOTO_OF_THE_DAY_PAGE);
+
+    let info = get_photo_of_the_day_from(&format!("{}/photo-of-the-day", base_url)).unwrap();
+
+    assert_eq!(
+        info,
+        PhotoInfo {
+            image_url: "https://i.natgeofe.com/n/4f5aa2f0-6b7c-4d4f-8a3e-2c1a9e8d7b6f/mossy-forest-waterfall.jpg"
+                .to_string(),
+            title: "Mossy Forest Waterfall".to_string(),
+            caption: Some(
+                "A waterfall drops through a moss-covered forest in Oregon's Columbia River Gorge."
+                    .to_string()
+            ),
+            photographer: Some("Photograph by Jane Q. Doe".to_string()),
+        }
+    );
+}
+
+#[test]
+fn test_photo_of_the_day_prefers_widest_srcset_variant() {
+    let base_url = serve_html(200, PHOTO_OF_THE_DAY_SRCSET_PAGE);
+
+    let info = get_photo_of_the_day_from(&base_url).unwrap();
+
+    // og:image is a 1200px crop; the page's picture element offers the full photo at 4096px
+    assert_eq!(
+        info.image_url,
+        "https://i.natgeofe.com/n/b2c3d4e5-0000-4000-8000-1234567890ab/aurora-over-fjord.jpg?w=4096&h=2731"
+    );
+    assert_eq!(info.title, "Aurora Over the Fjord");
+}
+
+#[test]
+fn test_photo_of_the_day_read_from_json_ld() {
+    let base_url = serve_html(200, PHOTO_OF_THE_DAY_JSON_LD_PAGE);
+
+    let info = get_photo_of_the_day_from(&base_url).unwrap();
+
+    assert_eq!(
+        info,
+        PhotoInfo {
+            image_url: "https://i.natgeofe.com/n/c3d4e5f6-1111-4222-8333-abcdefabcdef/desert-dunes.jpg"
+                .to_string(),
+            title: "Desert Dunes at Sunrise".to_string(),
+            caption: Some(
+                "Morning light rakes across the dunes of Sossusvlei in Namibia's Namib-Naukluft National Park."
+                    .to_string()
+            ),
+            photographer: Some("Photograph by Ana Lima".to_string()),
+        }
+    );
+}
+
+#[test]
+fn test_photo_of_the_day_read_from_next_data() {
+    let base_url = serve_html(200, PHOTO_OF_THE_DAY_NEXT_DATA_PAGE);
+
+    let info = get_photo_of_the_day_from(&base_url).unwrap();
+
+    assert_eq!(
+        info.image_url,
+        "https://i.natgeofe.com/n/d4e5f6a7-2222-4333-8444-0123456789ab/glacier-cave.jpg"
+    );
+    assert_eq!(info.title, "Glacier Cave Blues");
+    assert_eq!(
+        info.caption.as_deref(),
+        Some("Meltwater carves a blue tunnel through the Breiðamerkurjökull glacier in Iceland.")
+    );
+    assert_eq!(
+        info.photographer.as_deref(),
+        Some("Photograph by Erik Sund")
+    );
+}
+
+#[test]
+fn test_broken_structured_data_falls_back_to_meta_tags_and_is_logged() {
+    let temp_dir = TempDir::new().unwrap();
+    let log_path = temp_dir.path().join("error.log");
+    let base_url = serve_html(
+        200,
+        r#"<meta property="og:image" content="https://example.com/photos/canyon.jpg">
+<meta property="og:title" content="Canyon at Noon">
+<script type="application/ld+json">{"@type": "ImageObject", "contentUrl": </script>"#,
+    );
+
+    let info = NatGeoClient::new(HttpOptions::default())
+        .unwrap()
+        .with_log(log_path.to_str().unwrap())
+        .photo_of_the_day_from(&base_url)
+        .unwrap();
+
+    assert_eq!(info.image_url, "https://example.com/photos/canyon.jpg");
+    assert_eq!(info.title, "Canyon at Noon");
+    let log = fs::read_to_string(&log_path).unwrap();
+    assert!(
+        log.contains("Ignoring structured data on photo page: JSON parsing error"),
+        "{log}"
+    );
+}
+
+#[test]
+fn test_photo_of_the_day_reports_error_status() {
+    let base_url = serve_html(404, "<html><body>Not Found</body></html>");
+
+    let result = get_photo_of_the_day_from(&base_url);
+
+    match result {
+        Err(PhotoError::HttpStatus { status, url }) => {
+            assert_eq!(status, 404);
+            assert_eq!(url, base_url);
+        }
+        other => panic!("expected an HTTP error, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_collection_reports_server_error_status() {
+    let base_url = serve_html(500, "<html><body>Oops</body></html>");
+    let http = HttpOptions {
+        retries: 0,
+        ..HttpOptions::default()
+    };
+
+    let result = get_collection_photos_with(&base_url, &http, &CollectionFilter::default());
+
+    match result {
+        Err(e @ PhotoError::HttpStatus { status: 500, .. }) => {
+            assert!(!e.is_permanent());
+            assert_eq!(e.to_string(), format!("HTTP 500 from {base_url}"));
+        }
+        other => panic!("expected an HTTP error, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_collection_scraped_from_fixture_page() {
+    let base_url = serve_html(200, COLLECTION_PAGE);
+
+    let collection =
+        get_collection_photos(&format!("{}/best-photos-october-2018", base_url)).unwrap();
+
+    assert_eq!(collection.name, "Best Photos of the Day: October 2018");
+    // Article order, even though the page's head mentions the second photo first
+    let photos: Vec<(&str, &str)> = collection
+        .photos
+        .iter()
+        .map(|photo| (photo.title.as_str(), photo.image_url.as_str()))
+        .collect();
+    assert_eq!(
+        photos,
+        [
+            (
+                "01-best-pod-october-18",
+                "https://i.natgeofe.com/n/0c1d2e3f-1111-2222-3333-444455556666/01-best-pod-october-18.jpg?w=2880&h=1920"
+            ),
+            (
+                "02-best-pod-october-18",
+                "https://i.natgeofe.com/n/7a8b9c0d-aaaa-bbbb-cccc-ddddeeeeffff/02-best-pod-october-18.jpg"
+            ),
+            (
+                "03_best-pod-october-18",
+                "https://i.natgeofe.com/n/5e6f7a8b-1234-5678-9abc-def012345678/03_best-pod-october-18.JPG"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_collection_captions_from_fixture_page_name_the_downloads() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let page_url = serve_html(200, COLLECTION_PAGE);
+    let (image_base, _requests) = serve_fixture_images(|_| {});
+
+    let mut collection = get_collection_photos(&page_url).unwrap();
+    let captions: Vec<Option<&str>> = collection
+        .photos
+        .iter()
+        .map(|photo| photo.caption.as_deref())
+        .collect();
+    assert_eq!(
+        captions,
+        [
+            Some("Fishermen cast nets at dawn on Inle Lake, Myanmar. Photograph by Mya Thet"),
+            Some("A snowy owl lifts off over a frozen field."),
+            Some("Lightning over the Grand Canyon."),
+        ]
+    );
+
+    // Serve the scraped photos locally
+    for photo in &mut collection.photos {
+        photo.image_url = photo
+            .image_url
+            .replace("https://i.natgeofe.com", &image_base);
+    }
+    let result = download_collection_into(
+        &collection,
+        save_dir,
+        &CollectionDownloadOptions::default(),
+        &CancelFlag::new(),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(result.downloaded, 3);
+    let mut photos: Vec<String> = fs::read_dir(save_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| {
+            std::path::Path::new(name)
+                .extension()
+                .is_some_and(|ext| ext == "jpg")
+        })
+        .collect();
+    photos.sort();
+    assert_eq!(
+        photos,
+        [
+            "01-Fishermen_cast_nets_at_dawn_on_Inle_Lake,_Myanmar._Photograph_by_Mya_Thet.jpg",
+            "02-A_snowy_owl_lifts_off_over_a_frozen_field.jpg",
+            "03-Lightning_over_the_Grand_Canyon.jpg",
+        ]
+    );
+}
+
+#[test]
+fn test_collection_skips_photos_saved_under_their_old_name() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let (base_url, requests) = serve_fixture_images(|_| {});
+    fs::write(temp_dir.path().join("01-best-pod.jpg"), fixture_jpeg()).unwrap();
+    let collection = PhotoCollection {
+        name: "Renamed".to_string(),
+        photos: vec![PhotoInfo {
+            image_url: format!("{}/01-best-pod.jpg", base_url),
+            title: "01-best-pod".to_string(),
+            caption: Some("Reef at dusk".to_string()),
+            photographer: None,
+        }],
+    };
+
+    let result = download_collection_into(
+        &collection,
+        save_dir,
+        &CollectionDownloadOptions::default(),
+        &CancelFlag::new(),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!((result.downloaded, result.skipped), (0, 1));
+    assert_eq!(requests.load(Ordering::SeqCst), 0);
+    assert!(!temp_dir.path().join("01-Reef_at_dusk.jpg").exists());
+}
+
+#[test]
+fn test_gallery_without_best_pod_slugs_suggests_no_filter() {
+    let base_url = serve_html(200, WILDLIFE_GALLERY_PAGE);
+
+    match get_collection_photos(&base_url) {
+        Err(PhotoError::NoPhotos(message)) => {
+            assert!(message.contains("3 other image(s)"), "{message}");
+            assert!(message.contains("--no-filter"), "{message}");
+        }
+        other => panic!("expected NoPhotos, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_gallery_filter_pattern_and_no_filter() {
+    let base_url = serve_html(200, WILDLIFE_GALLERY_PAGE);
+    let titles = |filter: &CollectionFilter| -> Vec<String> {
+        get_collection_photos_with(&base_url, &HttpOptions::default(), filter)
+            .unwrap()
+            .photos
+            .into_iter()
+            .map(|photo| photo.title)
+            .collect()
+    };
+
+    assert_eq!(
+        titles(&CollectionFilter::None),
+        [
+            "yir-wildlife-2024-01",
+            "yir-wildlife-2024-02",
+            "NationalGeographic_2841733"
+        ]
+    );
+    assert_eq!(
+        titles(&CollectionFilter::Pattern("YIR_wildlife".to_string())),
+        ["yir-wildlife-2024-01", "yir-wildlife-2024-02"]
+    );
+}
+
+#[test]
+fn test_collection_downloads_each_photo_once_despite_duplicate_urls() {
+    let temp_dir = TempDir::new().unwrap();
+    let save_dir = temp_dir.path().to_str().unwrap();
+    let page_url = serve_html(200, DUPLICATES_PAGE);
+    let (image_base, requests) = serve_fixture_images(|_| {});
+
+    let mut collection = get_collection_photos(&page_url).unwrap();
+    let titles: Vec<&str> = collection
+        .photos
+        .iter()
+        .map(|photo| photo.title.as_str())
+        .collect();
+    // Six URLs for three photos; the declared 2400w copy of the first wins
+    assert_eq!(
+        titles,
+        [
+            "best-pod-march-19-wild-horses-2x",
+            "02-best-pod-march-19",
+            "03-best-pod-march-19"
+        ]
+    );
+
+    for photo in &mut collection.photos {
+        photo.image_url = photo
+            .image_url
+            .replace("https://i.natgeofe.com", &image_base);
+    }
+    let result = download_collection_into(
+        &collection,
+        save_dir,
+        &CollectionDownloadOptions::default(),
+        &CancelFlag::new(),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(result.downloaded, 3);
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    let photos = fs::read_dir(save_dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "jpg")
+        })
+        .count();
+    assert_eq!(photos, 3);
+}
+
+#[test]
+fn test_rerun_in_new_date_dir_is_skipped_by_content_hash() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("photos");
+    let first_day = root.join("2025-12-24");
+    let rerun_day = root.join("2026-12-24");
+    fs::create_dir_all(&first_day).unwrap();
+    fs::create_dir_all(&rerun_day).unwrap();
+    let (base_url, _) = serve_fixture_images(|_| {});
+    let url = format!("{}/photo.jpg", base_url);
+
+    let manifest = ManifestConfig {
+        path: temp_dir.path().join("data").join("manifest.json"),
+        root,
+    };
+    let options = DownloadOptions {
+        manifest: Some(manifest.clone()),
+        ..DownloadOptions::default()
+    };
+    let download = |day: &std::path::Path, title: &str| {
+        let save_dir = day.to_str().unwrap();
+        let log_path = format!("{}/{}.log", save_dir, title);
+        download_natgeo_photo_of_the_day_with_options(&url, save_dir, title, &log_path, &options)
+            .unwrap()
+    };
+
+    // The first download creates the manifest
+    let original = first_day.join("Winter Solstice.jpg");
+    assert_eq!(
+        download(&first_day, "Winter Solstice"),
+        DownloadOutcome::Downloaded(original.clone())
+    );
+    let recorded: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest.path).unwrap()).unwrap();
+    let entries = recorded["photos"].as_object().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries.values().next().unwrap(),
+        "2025-12-24/Winter Solstice.jpg"
+    );
+
+    // A year later the same bytes come back under a new title
+    assert_eq!(
+        download(&rerun_day, "Holiday Rerun"),
+        DownloadOutcome::Duplicate(original.clone())
+    );
+    assert!(!rerun_day.join("Holiday Rerun.jpg").exists());
+    assert!(!rerun_day.join("Holiday Rerun.jpg.part").exists());
+    let log = fs::read_to_string(rerun_day.join("Holiday Rerun.log")).unwrap();
+    assert!(log.contains(original.to_str().unwrap()));
+
+    // Once the original is gone the hash no longer blocks the download
+    fs::remove_file(&original).unwrap();
+    assert_eq!(
+        download(&rerun_day, "Holiday Rerun"),
+        DownloadOutcome::Downloaded(rerun_day.join("Holiday Rerun.jpg"))
+    );
+}
+
+#[test]
+fn test_verify_fix_redownloads_from_metadata_url() {
+    let temp_dir = TempDir::new().unwrap();
+    let day = temp_dir.path().join("2026-02-01");
+    fs::create_dir_all(&day).unwrap();
+    let (base_url, requests) = serve_fixture_images(|_| {});
+
+    let photo = day.join("Reef.jpg");
+    fs::write(&photo, &fixture_jpeg()[..100]).unwrap();
+    let info = PhotoInfo {
+        image_url: format!("{}/reef.jpg", base_url),
+        title: "Reef".to_string(),
+        caption: Some("A coral reef".to_string()),
+        photographer: None,
+    };
+    write_metadata(
+        &metadata_path_for(&photo),
+        &PhotoMetadata::for_download(&info, &photo).unwrap(),
+    )
+    .unwrap();
+
+    let report = verify_photos_with(temp_dir.path(), 1).unwrap();
+    assert_eq!(report.issues.len(), 1);
+    let client = NatGeoClient::new(HttpOptions::default()).unwrap();
+    assert_eq!(
+        repair_photo(&report.issues[0], &client),
+        Repair::Redownloaded(photo.clone())
+    );
+
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    assert_eq!(fs::read(&photo).unwrap(), fixture_jpeg());
+    let metadata = read_metadata(&metadata_path_for(&photo)).unwrap();
+    assert_eq!(metadata.file_size, fixture_jpeg().len() as u64);
+    assert_eq!(metadata.caption.as_deref(), Some("A coral reef"));
+    assert!(verify_photos_with(temp_dir.path(), 1)
+        .unwrap()
+        .issues
+        .is_empty());
+}
+
+/// A web archive on a local port. Requests for a day in `redirects` are sent to
+/// the snapshot timestamp it maps to, snapshots serve the photo of the day
+/// fixture, and anything else is a 404.
+fn serve_archive(redirects: &'static [(&'static str, &'static str)]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let origin = base_url.clone();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            let request = read_request_head(&stream);
+            let timestamp = request
+                .split_once("/web/")
+                .and_then(|(_, rest)| rest.split_once('/'))
+                .map_or("", |(timestamp, _)| timestamp);
+
+            let response = if let Some((_, snapshot)) =
+                redirects.iter().find(|(day, _)| *day == timestamp)
+            {
+                format!(
+                    "HTTP/1.1 302 Found\r\nLocation: {}/web/{}/https://www.nationalgeographic.com/photo-of-the-day\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    origin, snapshot
+                )
+            } else if timestamp.len() == 14 {
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    PHOTO_OF_THE_DAY_PAGE.len(),
+                    PHOTO_OF_THE_DAY_PAGE
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+
+    format!("{}/web/", base_url)
+}
+
+#[test]
+fn test_photo_for_past_date_from_archive() {
+    let archive = serve_archive(&[
+        ("20241103", "20241103083000"),
+        ("20241104", "20241101120000"),
+    ]);
+    let client = NatGeoClient::new(HttpOptions::default()).unwrap();
+    let day = |d| NaiveDate::from_ymd_opt(2024, 11, d).unwrap();
+
+    let info = client.photo_for_date_from(&archive, day(3)).unwrap();
+    assert_eq!(
+        info,
+        get_photo_of_the_day_from(&format!("{}20241103083000/x", archive)).unwrap()
+    );
+
+    // The nearest snapshot is from another day, so that day's photo isn't used
+    assert!(matches!(
+        client.photo_for_date_from(&archive, day(4)),
+        Err(PhotoError::NoPhotos(_))
+    ));
+    // Nothing archived at all
+    assert!(matches!(
+        client.photo_for_date_from(&archive, day(5)),
+        Err(PhotoError::NoPhotos(_))
+    ));
+}
+
+#[test]
+fn test_photo_for_future_date_is_rejected() {
+    let client = NatGeoClient::new(HttpOptions::default()).unwrap();
+    let tomorrow = chrono::Local::now().date_naive() + chrono::Days::new(1);
+    let err = client.photo_for_date(tomorrow).unwrap_err();
+    assert!(matches!(err, PhotoError::InvalidDate(_)));
+    assert!(err.to_string().contains("in the future"));
+}
+
+#[test]
+fn test_config_file_sets_the_photo_directory() {
+    let home = TempDir::new().unwrap();
+    let library = home.path().join("Wallpapers");
+    fs::create_dir_all(library.join("2026-02-01")).unwrap();
+    fs::write(library.join("2026-02-01/Foggy_Morning.jpg"), fixture_jpeg()).unwrap();
+
+    let config_dir = home.path().join(".config/natgeo-wallpapers");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::write(
+        config_dir.join("config.toml"),
+        "[paths]\nphotos = \"~/Wallpapers\"\n[wallpaper]\nsparkle = true\n",
+    )
+    .unwrap();
+
+    // The unknown key only warns
+    let (stdout, success) = run_binary(home.path(), &["list", "--json"]);
+    assert!(success);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json.as_array().unwrap().len(), 1);
+    assert_eq!(json[0]["filename"], "Foggy_Morning.jpg");
+
+    let (stdout, success) = run_binary(home.path(), &["config", "show"]);
+    assert!(success);
+    assert!(stdout.contains(&format!("photos = \"{}/\"", library.display())));
+    assert!(stdout.contains("mode = \"monitors\""));
+}
+
+#[test]
+fn test_config_init_writes_a_default_file_once() {
+    let home = TempDir::new().unwrap();
+    let config_file = home.path().join(".config/natgeo-wallpapers/config.toml");
+
+    let (_, success) = run_binary(home.path(), &["config", "init"]);
+    assert!(success);
+    let written = fs::read_to_string(&config_file).unwrap();
+    assert!(written.contains("[wallpaper]"));
+
+    // A second init refuses to overwrite edits unless forced
+    fs::write(&config_file, "[wallpaper]\nrandom = true\n").unwrap();
+    let (_, success) = run_binary(home.path(), &["config", "init"]);
+    assert!(!success);
+    assert!(fs::read_to_string(&config_file)
+        .unwrap()
+        .contains("random = true"));
+    let (_, success) = run_binary(home.path(), &["config", "init", "--force"]);
+    assert!(success);
+    assert_eq!(fs::read_to_string(&config_file).unwrap(), written);
+}
+
+#[test]
+fn test_broken_config_file_is_an_error() {
+    let home = TempDir::new().unwrap();
+    let config_dir = home.path().join(".config/natgeo-wallpapers");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::write(config_dir.join("config.toml"), "[paths\n").unwrap();
+
+    let (_, success) = run_binary(home.path(), &["list"]);
+    assert!(!success);
+    // `config init --force` still works, to start over
+    let (_, success) = run_binary(home.path(), &["config", "init", "--force"]);
+    assert!(success);
+    let (_, success) = run_binary(home.path(), &["config", "show"]);
+    assert!(success);
+}
+
+#[test]
+fn test_photo_dir_environment_variable_overrides_config() {
+    let home = TempDir::new().unwrap();
+    let library = home.path().join("elsewhere");
+    fs::create_dir_all(library.join("2026-02-01")).unwrap();
+    fs::write(library.join("2026-02-01/Foggy_Morning.jpg"), fixture_jpeg()).unwrap();
+
+    let config_dir = home.path().join(".config/natgeo-wallpapers");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::write(
+        config_dir.join("config.toml"),
+        "[paths]\nphotos = \"~/Wallpapers\"\n",
+    )
+    .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_natgeo-wallpapers"))
+        .args(["list", "--json"])
+        .env("HOME", home.path())
+        .env_remove("XDG_CONFIG_HOME")
+        .env("NATGEO_WALLPAPERS_PHOTO_DIR", &library)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json.as_array().unwrap().len(), 1);
+}